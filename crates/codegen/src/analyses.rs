@@ -0,0 +1,201 @@
+//! This module contains [`FunctionAnalyses`], which computes the analyses of a function on demand
+//! and caches them until the function changes.
+use std::fmt;
+
+use sonatina_ir::{ControlFlowGraph, Function};
+
+use crate::{
+    domtree::DomTree, liveness::Liveness, loop_analysis::LoopTree, post_domtree::PostDomTree,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Analysis {
+    Cfg,
+    DomTree,
+    PostDomTree,
+    LoopTree,
+    Liveness,
+}
+
+impl Analysis {
+    /// Returns the analyses this analysis is computed from.
+    pub fn deps(self) -> &'static [Analysis] {
+        match self {
+            Self::Cfg | Self::PostDomTree => &[],
+            Self::DomTree => &[Self::Cfg],
+            Self::LoopTree => &[Self::Cfg, Self::DomTree],
+            Self::Liveness => &[Self::Cfg],
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Cfg => "cfg",
+            Self::DomTree => "domtree",
+            Self::PostDomTree => "post-domtree",
+            Self::LoopTree => "loop-tree",
+            Self::Liveness => "liveness",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The analyses of a function.
+///
+/// An analysis is computed the first time it's [requested](Self::compute) and kept until
+/// [invalidated](Self::invalidate). The analyses don't track which function they were computed
+/// for, so they must be invalidated whenever the function changes or another function is analyzed.
+/// The buffers of the analyses are reused across functions.
+#[derive(Debug, Default)]
+pub struct FunctionAnalyses {
+    cfg: ControlFlowGraph,
+    domtree: DomTree,
+    post_domtree: PostDomTree,
+    lpt: LoopTree,
+    liveness: Liveness,
+    valid: [bool; 5],
+}
+
+impl FunctionAnalyses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Computes `analysis` of `func` and the analyses it depends on, unless they are cached.
+    pub fn compute(&mut self, func: &Function, analysis: Analysis) -> &mut Self {
+        if self.is_valid(analysis) {
+            return self;
+        }
+        for &dep in analysis.deps() {
+            self.compute(func, dep);
+        }
+
+        match analysis {
+            Analysis::Cfg => self.cfg.compute(func),
+            Analysis::DomTree => self.domtree.compute(&self.cfg),
+            Analysis::PostDomTree => self.post_domtree.compute(func),
+            Analysis::LoopTree => self.lpt.compute(&self.cfg, &self.domtree),
+            Analysis::Liveness => self.liveness.compute(func, &self.cfg),
+        }
+        self.valid[analysis.index()] = true;
+        self
+    }
+
+    /// Returns `true` if `analysis` is cached.
+    pub fn is_valid(&self, analysis: Analysis) -> bool {
+        self.valid[analysis.index()]
+    }
+
+    /// Drops all cached analyses.
+    pub fn invalidate(&mut self) {
+        self.valid = Default::default();
+    }
+
+    /// Returns the CFG. Panics if it's not [computed](Self::compute).
+    pub fn cfg(&self) -> &ControlFlowGraph {
+        self.assert_valid(Analysis::Cfg);
+        &self.cfg
+    }
+
+    /// Returns the dominator tree. Panics if it's not [computed](Self::compute).
+    pub fn domtree(&self) -> &DomTree {
+        self.assert_valid(Analysis::DomTree);
+        &self.domtree
+    }
+
+    /// Returns the post dominator tree. Panics if it's not [computed](Self::compute).
+    pub fn post_domtree(&self) -> &PostDomTree {
+        self.assert_valid(Analysis::PostDomTree);
+        &self.post_domtree
+    }
+
+    /// Returns the loop tree. Panics if it's not [computed](Self::compute).
+    pub fn loop_tree(&self) -> &LoopTree {
+        self.assert_valid(Analysis::LoopTree);
+        &self.lpt
+    }
+
+    /// Returns the liveness. Panics if it's not [computed](Self::compute).
+    pub fn liveness(&self) -> &Liveness {
+        self.assert_valid(Analysis::Liveness);
+        &self.liveness
+    }
+
+    /// Computes the CFG, and returns it for a pass that keeps it up to date while changing the
+    /// function.
+    pub fn cfg_mut(&mut self, func: &Function) -> &mut ControlFlowGraph {
+        self.compute(func, Analysis::Cfg);
+        &mut self.cfg
+    }
+
+    /// Computes the CFG and the dominator tree, and returns them for a pass that keeps them up to
+    /// date while changing the function.
+    pub fn cfg_and_domtree_mut(
+        &mut self,
+        func: &Function,
+    ) -> (&mut ControlFlowGraph, &mut DomTree) {
+        self.compute(func, Analysis::DomTree);
+        (&mut self.cfg, &mut self.domtree)
+    }
+
+    /// Computes the CFG and the loop tree, and returns them for a pass that keeps them up to date
+    /// while changing the function.
+    pub fn cfg_and_loop_tree_mut(
+        &mut self,
+        func: &Function,
+    ) -> (&mut ControlFlowGraph, &mut LoopTree) {
+        self.compute(func, Analysis::LoopTree);
+        (&mut self.cfg, &mut self.lpt)
+    }
+
+    fn assert_valid(&self, analysis: Analysis) {
+        assert!(self.is_valid(analysis), "`{analysis}` is not computed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn compute_lazily() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        builder.br(arg, b1, b2);
+        builder.switch_to_block(b1);
+        builder.jump(b2);
+        builder.switch_to_block(b2);
+        builder.ret(None);
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+
+        let mut analyses = FunctionAnalyses::new();
+        analyses.compute(func, Analysis::LoopTree);
+        assert!(analyses.is_valid(Analysis::Cfg));
+        assert!(analyses.is_valid(Analysis::DomTree));
+        assert!(!analyses.is_valid(Analysis::Liveness));
+        assert_eq!(analyses.domtree().idom_of(b2), Some(b0));
+        assert_eq!(analyses.loop_tree().loop_num(), 0);
+
+        analyses.invalidate();
+        assert!(!analyses.is_valid(Analysis::Cfg));
+        analyses.compute(func, Analysis::Liveness);
+        assert!(analyses.liveness().is_live_in(arg, b0));
+    }
+}
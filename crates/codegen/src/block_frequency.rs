@@ -0,0 +1,184 @@
+//! This module contains an analysis that estimates how often each block runs per run of its
+//! function, from the [weights](sonatina_ir::BranchWeights) of branches.
+//!
+//! The entry block runs once, and every other block runs as often as the edges into it are taken.
+//! A branch takes each of its edges in proportion to the weight of the edge, or equally often if
+//! it has no weights. Frequencies in loops are found by iterating these equations until they
+//! settle, so the header of a loop whose back edge is taken with probability `p` runs about
+//! `1 / (1 - p)` times per entry into the loop.
+//!
+//! Blocks that are only reached through edges of weight zero never run. Since guessed
+//! frequencies say little, optimizations only act on [hot](BlockFrequency::is_hot) and
+//! [cold](BlockFrequency::is_cold) blocks of functions that have branch weights.
+use cranelift_entity::SecondaryMap;
+use smallvec::SmallVec;
+
+use sonatina_ir::{Block, ControlFlowGraph, Function};
+
+/// The maximum number of times the equations are iterated.
+const MAX_ITERATIONS: usize = 256;
+
+/// The frequencies have settled once none changes by more than this fraction in an iteration.
+const TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Default)]
+pub struct BlockFrequency {
+    freqs: SecondaryMap<Block, f64>,
+    has_weights: bool,
+}
+
+impl BlockFrequency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compute(&mut self, func: &Function, cfg: &ControlFlowGraph) {
+        self.clear();
+        let Some(entry) = func.layout.entry_block() else {
+            return;
+        };
+        self.has_weights = func.layout.iter_block().any(|block| {
+            func.layout
+                .last_insn_of(block)
+                .is_some_and(|insn| func.dfg.branch_weights(insn).is_some())
+        });
+
+        let mut rpo: Vec<_> = cfg.post_order().collect();
+        rpo.reverse();
+        let mut in_edges: SecondaryMap<Block, Vec<(Block, f64)>> = SecondaryMap::default();
+        for &block in &rpo {
+            for (succ, prob) in edge_probabilities(func, block) {
+                in_edges[succ].push((block, prob));
+            }
+        }
+
+        // Visiting blocks in RPO lets frequencies flow through acyclic regions in one iteration.
+        for _ in 0..MAX_ITERATIONS {
+            let mut settled = true;
+            for &block in &rpo {
+                let inflow: f64 = in_edges[block]
+                    .iter()
+                    .map(|&(pred, prob)| self.freqs[pred] * prob)
+                    .sum();
+                let freq = if block == entry { 1.0 + inflow } else { inflow };
+                if (freq - self.freqs[block]).abs() > TOLERANCE * freq.max(1.0) {
+                    settled = false;
+                }
+                self.freqs[block] = freq;
+            }
+
+            if settled {
+                break;
+            }
+        }
+    }
+
+    /// Returns the estimated number of times `block` runs per run of the function. Unreachable
+    /// blocks never run.
+    pub fn freq(&self, block: Block) -> f64 {
+        self.freqs[block]
+    }
+
+    /// Returns `true` if the function has branch weights.
+    pub fn has_weights(&self) -> bool {
+        self.has_weights
+    }
+
+    /// Returns `true` if the branch weights say that `block` never runs.
+    pub fn is_cold(&self, block: Block) -> bool {
+        self.has_weights && self.freqs[block] == 0.0
+    }
+
+    /// Returns `true` if the branch weights say that `block` runs more often than the entry block,
+    /// e.g., in a loop.
+    pub fn is_hot(&self, block: Block) -> bool {
+        self.has_weights && self.freqs[block] > 1.0 + TOLERANCE
+    }
+
+    pub fn clear(&mut self) {
+        self.freqs.clear();
+        self.has_weights = false;
+    }
+}
+
+/// Returns the successors of `block` with the probability that its branch goes to each.
+fn edge_probabilities(func: &Function, block: Block) -> SmallVec<[(Block, f64); 4]> {
+    let mut probs: SmallVec<[(Block, f64); 4]> = SmallVec::new();
+    let Some(branch) = func.layout.last_insn_of(block) else {
+        return probs;
+    };
+
+    let dests: SmallVec<[Block; 4]> = func.dfg.analyze_branch(branch).iter_dests().collect();
+    let weights = func.dfg.branch_weights(branch);
+    for (idx, &dest) in dests.iter().enumerate() {
+        let prob = weights
+            .and_then(|weights| weights.probability(idx))
+            .unwrap_or(1.0 / dests.len() as f64);
+        match probs.iter_mut().find(|(succ, _)| *succ == dest) {
+            Some((_, total)) => *total += prob,
+            None => probs.push((dest, prob)),
+        }
+    }
+    probs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn loop_and_cold_path() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let b4 = builder.append_block();
+        let (c0, c1) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(c0, b1, b3);
+        builder.set_branch_weights(&[1, 0]);
+
+        builder.switch_to_block(b1);
+        builder.jump(b2);
+
+        builder.switch_to_block(b2);
+        builder.br(c1, b2, b4);
+        builder.set_branch_weights(&[9, 1]);
+
+        builder.switch_to_block(b3);
+        builder.jump(b4);
+
+        builder.switch_to_block(b4);
+        builder.ret(None);
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut freq = BlockFrequency::new();
+        freq.compute(func, &cfg);
+
+        assert_close(freq.freq(b0), 1.0);
+        assert_close(freq.freq(b1), 1.0);
+        // The loop exits with probability 1/10.
+        assert_close(freq.freq(b2), 10.0);
+        assert_close(freq.freq(b4), 1.0);
+        assert!(freq.is_hot(b2));
+        assert!(!freq.is_hot(b4));
+        assert!(freq.is_cold(b3));
+        assert!(!freq.is_cold(b1));
+    }
+}
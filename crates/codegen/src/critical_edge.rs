@@ -37,6 +37,7 @@ impl CriticalEdgeSplitter {
         for edge in edges {
             self.split_edge(edge, func, cfg);
         }
+        func.debug_check_phis(cfg);
     }
 
     pub fn clear(&mut self) {
@@ -74,21 +75,7 @@ impl CriticalEdgeSplitter {
         func.dfg
             .rewrite_branch_dest(insn, original_dest, inserted_dest);
         self.modify_cfg(cfg, source_block, original_dest, inserted_dest);
-        self.modify_phi_blocks(func, original_dest, inserted_dest);
-    }
-
-    fn modify_phi_blocks(&self, func: &mut Function, original_dest: Block, inserted_dest: Block) {
-        for insn in func.layout.iter_insn(original_dest) {
-            if !func.dfg.is_phi(insn) {
-                continue;
-            }
-
-            for block in func.dfg.phi_blocks_mut(insn) {
-                if *block == original_dest {
-                    *block = inserted_dest;
-                }
-            }
-        }
+        func.rename_phi_blocks(original_dest, source_block, inserted_dest);
     }
 
     fn modify_cfg(
@@ -297,6 +284,57 @@ mod tests {
         assert_eq!(cfg, cfg_split);
     }
 
+    #[test]
+    fn critical_edge_phi_from_branch() {
+        let mut builder = test_func_builder(&[Type::I1], Type::I8);
+
+        let a = builder.append_block();
+        let b = builder.append_block();
+        let c = builder.append_block();
+
+        builder.switch_to_block(a);
+        let cond = builder.args()[0];
+        builder.br(cond, c, b);
+
+        builder.switch_to_block(b);
+        builder.jump(c);
+
+        builder.switch_to_block(c);
+        let v1 = builder.make_imm_value(1i8);
+        let v2 = builder.make_imm_value(2i8);
+        let phi_value = builder.phi(Type::I8, &[(v1, a), (v2, b)]);
+        builder.ret(Some(phi_value));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::default();
+        cfg.compute(func);
+        CriticalEdgeSplitter::new().run(func, &mut cfg);
+
+        // The arg from `a` now flows through the inserted block.
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1) -> i8 {
+    block0:
+        br v0 block3 block1;
+
+    block1:
+        jump block2;
+
+    block2:
+        v3.i8 = phi (1.i8 block3) (2.i8 block1);
+        return v3;
+
+    block3:
+        jump block2;
+
+}
+"
+        );
+    }
+
     #[test]
     fn critical_edge_br_table() {
         let mut builder = test_func_builder(&[], Type::Void);
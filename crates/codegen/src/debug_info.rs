@@ -0,0 +1,433 @@
+//! This module contains the debug info sidecar emitted along with bytecode.
+//!
+//! The sidecar maps bytecode offsets back to the source locations attached to instructions, and
+//! describes where source-level variables live, so that external debuggers can provide
+//! source-level stepping. It's serialized as JSON in the following format.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "files": ["main.fe"],
+//!   "functions": [{ "name": "foo", "id": "foo#1f2e3d4c5b6a7988", "start": 0, "end": 42 }],
+//!   "lines": [{ "offset": 3, "file": 0, "start": 10, "end": 18, "inlined_at": 0 }],
+//!   "inlined_at": [{
+//!     "callee": "bar", "call_site": { "file": 0, "start": 40, "end": 46 }, "parent": null
+//!   }],
+//!   "variables": [{
+//!     "function": "foo", "name": "x", "start": 3, "end": 42,
+//!     "location": { "kind": "stack_slot", "index": 1 }
+//!   }]
+//! }
+//! ```
+//!
+//! `file` is an index into `files`, and `inlined_at` and `parent` are indices into `inlined_at`,
+//! or `null`. An `inlined_at` entry is the call site that `callee` was inlined at; following
+//! `parent` gives the rest of the inlined call stack. Offsets are byte offsets in the emitted
+//! code, and `end` offsets are exclusive.
+//!
+//! The `id` of a function is its symbol followed by a hash of its IR, see [`function_id`]. Unlike
+//! offsets, it doesn't change when code is moved around, so tools can recognize a function across
+//! builds, and tell apart functions with the same symbol from different modules.
+//!
+//! The same locations can also be emitted as a Solidity source map, see [`encode_source_map`],
+//! which existing explorers, debuggers and coverage tools understand.
+use std::io;
+
+use sonatina_ir::{
+    ir_writer::FuncWriter,
+    json::{delim, JsonStr},
+    module::{FuncRef, ModuleCtx},
+    source_loc::{InlinedAt, InlinedAtData},
+    Function, Insn, SourceLoc,
+};
+use tiny_keccak::{Hasher, Keccak};
+
+pub const DEBUG_INFO_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub functions: Vec<FunctionRange>,
+    pub lines: Vec<LineEntry>,
+    /// Indexed by [`InlinedAt`].
+    pub inlined_at: Vec<InlinedAtData>,
+    pub variables: Vec<VariableEntry>,
+}
+
+/// The range of code that a function is emitted to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionRange {
+    pub name: String,
+    /// The stable identifier of the function, see [`function_id`].
+    pub id: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Code from `offset` up to the next entry originates from `loc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    pub offset: u32,
+    pub loc: SourceLoc,
+}
+
+/// A source-level variable that lives at `location` while the code in `start..end` runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableEntry {
+    pub function: String,
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+    pub location: VariableLocation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableLocation {
+    /// The `index`-th slot of the function frame.
+    StackSlot(u32),
+    /// A fixed offset in memory.
+    Memory(u32),
+}
+
+impl DebugInfo {
+    pub fn write_json(&self, mut w: impl io::Write) -> io::Result<()> {
+        write!(w, "{{\"version\":{DEBUG_INFO_VERSION},\"files\":[")?;
+        for (i, file) in self.files.iter().enumerate() {
+            write!(w, "{}{}", delim(i), JsonStr(file))?;
+        }
+
+        write!(w, "],\"functions\":[")?;
+        for (i, func) in self.functions.iter().enumerate() {
+            write!(
+                w,
+                "{}{{\"name\":{},\"id\":{},\"start\":{},\"end\":{}}}",
+                delim(i),
+                JsonStr(&func.name),
+                JsonStr(&func.id),
+                func.start,
+                func.end
+            )?;
+        }
+
+        write!(w, "],\"lines\":[")?;
+        for (i, line) in self.lines.iter().enumerate() {
+            write!(
+                w,
+                "{}{{\"offset\":{},{},\"inlined_at\":{}}}",
+                delim(i),
+                line.offset,
+                JsonLoc(&line.loc),
+                JsonIndex(line.loc.inlined_at),
+            )?;
+        }
+
+        write!(w, "],\"inlined_at\":[")?;
+        for (i, data) in self.inlined_at.iter().enumerate() {
+            write!(
+                w,
+                "{}{{\"callee\":{},\"call_site\":",
+                delim(i),
+                JsonStr(&data.callee)
+            )?;
+            match &data.call_site {
+                Some(loc) => write!(w, "{{{}}}", JsonLoc(loc))?,
+                None => write!(w, "null")?,
+            }
+            write!(w, ",\"parent\":{}}}", JsonIndex(data.parent))?;
+        }
+
+        write!(w, "],\"variables\":[")?;
+        for (i, var) in self.variables.iter().enumerate() {
+            let (kind, index) = match var.location {
+                VariableLocation::StackSlot(index) => ("stack_slot", index),
+                VariableLocation::Memory(offset) => ("memory", offset),
+            };
+            write!(
+                w,
+                "{}{{\"function\":{},\"name\":{},\"start\":{},\"end\":{},\
+                 \"location\":{{\"kind\":\"{kind}\",\"index\":{index}}}}}",
+                delim(i),
+                JsonStr(&var.function),
+                JsonStr(&var.name),
+                var.start,
+                var.end,
+            )?;
+        }
+
+        write!(w, "]}}")
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut buf = Vec::new();
+        self.write_json(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+/// Collects debug info while code is emitted.
+///
+/// An emitter calls [`Self::begin_function`] and [`Self::end_function`] around each function, and
+/// [`Self::record_insn`] with the offset of the code emitted for each instruction.
+#[derive(Debug, Default)]
+pub struct DebugInfoBuilder {
+    info: DebugInfo,
+    current_func: Option<(String, String, u32)>,
+}
+
+impl DebugInfoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin_function(&mut self, func_ref: FuncRef, func: &Function, offset: u32) {
+        debug_assert!(self.current_func.is_none());
+        self.current_func = Some((
+            func.sig.name().to_string(),
+            function_id(func_ref, func),
+            offset,
+        ));
+    }
+
+    pub fn end_function(&mut self, offset: u32) {
+        let (name, id, start) = self.current_func.take().unwrap();
+        self.info.functions.push(FunctionRange {
+            name,
+            id,
+            start,
+            end: offset,
+        });
+    }
+
+    /// Record that the code for `insn` starts at `offset`.
+    /// Insns without a source location, or with the same location as the previous entry, don't
+    /// add a new entry.
+    pub fn record_insn(&mut self, func: &Function, insn: Insn, offset: u32) {
+        let Some(loc) = func.dfg.insn_loc(insn) else {
+            return;
+        };
+
+        match self.info.lines.last() {
+            Some(last) if last.loc == loc => {}
+            Some(last) if last.offset == offset => {
+                // The previous insn emitted no code.
+                self.info.lines.last_mut().unwrap().loc = loc;
+            }
+            _ => self.info.lines.push(LineEntry { offset, loc }),
+        }
+    }
+
+    pub fn add_variable(&mut self, var: VariableEntry) {
+        self.info.variables.push(var);
+    }
+
+    pub fn finish(mut self, ctx: &ModuleCtx) -> DebugInfo {
+        debug_assert!(self.current_func.is_none());
+        ctx.with_source_file_store(|s| {
+            self.info.files = s.all_files().map(|(_, path)| path.to_string()).collect();
+            self.info.inlined_at = s.all_inlined_ats().map(|(_, data)| data.clone()).collect();
+        });
+        self.info
+    }
+}
+
+/// The source location of an instruction of the emitted code, i.e., an element of a source map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    pub loc: Option<SourceLoc>,
+    pub jump: JumpKind,
+}
+
+/// How a jump instruction transfers control, as a source map tells it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JumpKind {
+    /// A jump into a function, i.e., a call.
+    Into,
+    /// A jump out of a function, i.e., a return.
+    OutOf,
+    /// Any other instruction.
+    #[default]
+    Regular,
+}
+
+/// Encodes `entries`, one for each instruction in order, as a Solidity source map, i.e.,
+/// `s:l:f:j:m` elements separated by `;`. `s` and `l` are the start and the length of the
+/// location in bytes, `f` is the index of the file, or `-1` for an instruction without location,
+/// `j` is `i`, `o` or `-` by [`JumpKind`], and `m`, the modifier depth, is always 0.
+///
+/// As in the format, a field equal to the one of the previous element is left empty, and empty
+/// trailing fields are omitted along with their `:`.
+pub fn encode_source_map(entries: &[SourceMapEntry]) -> String {
+    let mut map = String::new();
+    let mut prev: Option<[String; 5]> = None;
+    for (i, entry) in entries.iter().enumerate() {
+        let (start, len, file) = match entry.loc {
+            Some(loc) => (
+                loc.start.to_string(),
+                (loc.end - loc.start).to_string(),
+                loc.file.as_u32().to_string(),
+            ),
+            None => ("-1".to_string(), "-1".to_string(), "-1".to_string()),
+        };
+        let jump = match entry.jump {
+            JumpKind::Into => "i",
+            JumpKind::OutOf => "o",
+            JumpKind::Regular => "-",
+        };
+        let fields = [start, len, file, jump.to_string(), "0".to_string()];
+
+        let mut written: Vec<&str> = fields
+            .iter()
+            .enumerate()
+            .map(|(j, field)| match &prev {
+                Some(prev) if prev[j] == *field => "",
+                _ => field.as_str(),
+            })
+            .collect();
+        while written.last() == Some(&"") {
+            written.pop();
+        }
+
+        if i != 0 {
+            map.push(';');
+        }
+        map.push_str(&written.join(":"));
+        prev = Some(fields);
+    }
+    map
+}
+
+/// Returns the stable identifier of `func`, i.e., its symbol followed by `#` and the first 8
+/// bytes of the keccak256 hash of its IR in hex, e.g., `foo#1f2e3d4c5b6a7988`.
+///
+/// The hash covers the signature and the body, so the identifier only changes when the code of
+/// the function does, e.g., when another function is inlined into it.
+pub fn function_id(func_ref: FuncRef, func: &Function) -> String {
+    let mut ir = Vec::new();
+    FuncWriter::new(func_ref, func, None)
+        .write(&mut ir)
+        .unwrap();
+
+    let mut hash = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(&ir);
+    hasher.finalize(&mut hash);
+
+    let mut id = format!("{}#", func.sig.name());
+    for byte in &hash[..8] {
+        id.push_str(&format!("{byte:02x}"));
+    }
+    id
+}
+
+/// Writes the `file`, `start` and `end` members of a location.
+struct JsonLoc<'a>(&'a SourceLoc);
+
+impl std::fmt::Display for JsonLoc<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "\"file\":{},\"start\":{},\"end\":{}",
+            self.0.file.as_u32(),
+            self.0.start,
+            self.0.end
+        )
+    }
+}
+
+/// Writes an inlined-at index, or `null`.
+struct JsonIndex(Option<InlinedAt>);
+
+impl std::fmt::Display for JsonIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            Some(inlined_at) => write!(f, "{}", inlined_at.as_u32()),
+            None => write!(f, "null"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn sidecar_json() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let file = builder
+            .module_builder
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("dir/\"main\".fe"));
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        builder.set_loc(Some(SourceLoc::new(file, 10, 15)));
+        let v1 = builder.add(arg, arg);
+        let v2 = builder.mul(v1, arg);
+        builder.set_loc(Some(SourceLoc::new(file, 20, 30)));
+        builder.ret(Some(v2));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+
+        let mut builder = DebugInfoBuilder::new();
+        builder.begin_function(func_ref, func, 0);
+        let mut offset = 0;
+        for insn in func.layout.iter_insn(func.layout.entry_block().unwrap()) {
+            builder.record_insn(func, insn, offset);
+            offset += 2;
+        }
+        builder.end_function(offset);
+        builder.add_variable(VariableEntry {
+            function: func.sig.name().to_string(),
+            name: "x".to_string(),
+            start: 0,
+            end: offset,
+            location: VariableLocation::StackSlot(0),
+        });
+
+        let info = builder.finish(&module.ctx);
+        assert_eq!(info.lines.len(), 2);
+        let id = function_id(func_ref, func);
+        assert!(id.starts_with("test_func#") && id.len() == "test_func#".len() + 16);
+        assert_eq!(
+            info.to_json(),
+            format!(
+                "{{\"version\":1,\"files\":[\"dir/\\\"main\\\".fe\"],\
+                 \"functions\":[{{\"name\":\"test_func\",\"id\":\"{id}\",\"start\":0,\"end\":6}}],\
+                 \"lines\":[{{\"offset\":0,\"file\":0,\"start\":10,\"end\":15,\"inlined_at\":null}},\
+                 {{\"offset\":4,\"file\":0,\"start\":20,\"end\":30,\"inlined_at\":null}}],\
+                 \"inlined_at\":[],\
+                 \"variables\":[{{\"function\":\"test_func\",\"name\":\"x\",\"start\":0,\"end\":6,\
+                 \"location\":{{\"kind\":\"stack_slot\",\"index\":0}}}}]}}"
+            )
+        );
+    }
+
+    #[test]
+    fn source_map() {
+        let builder = test_func_builder(&[], Type::Void);
+        let file = builder
+            .module_builder
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("main.fe"));
+        let loc = |start, end| Some(SourceLoc::new(file, start, end));
+        let entry = |loc, jump| SourceMapEntry { loc, jump };
+
+        let entries = [
+            entry(loc(10, 15), JumpKind::Regular),
+            entry(loc(10, 15), JumpKind::Regular),
+            entry(loc(10, 20), JumpKind::Into),
+            entry(loc(30, 40), JumpKind::Into),
+            entry(None, JumpKind::OutOf),
+        ];
+        assert_eq!(
+            encode_source_map(&entries),
+            "10:5:0:-:0;;:10::i;30;-1:-1:-1:o"
+        );
+    }
+}
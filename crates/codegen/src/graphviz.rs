@@ -0,0 +1,34 @@
+//! This module fills [`RenderOptions`] with the results of the analyses of this crate, so that
+//! they can be drawn over the CFG.
+use sonatina_ir::{
+    graphviz::{LoopCluster, RenderOptions},
+    Function,
+};
+
+use crate::{domtree::DomTree, loop_analysis::LoopTree};
+
+/// Adds an edge from the immediate dominator of each reachable block.
+pub fn add_domtree(options: &mut RenderOptions, domtree: &DomTree) {
+    for &block in domtree.rpo() {
+        if let Some(idom) = domtree.idom_of(block) {
+            options.dom_edge(block, idom);
+        }
+    }
+}
+
+/// Adds a cluster for each loop in `lpt`.
+pub fn add_loops(options: &mut RenderOptions, func: &Function, lpt: &LoopTree) {
+    for lp in lpt.loops() {
+        let blocks = func
+            .layout
+            .iter_block()
+            .filter(|block| lpt.loop_of_block(*block) == Some(lp))
+            .collect();
+        let depth = std::iter::successors(lpt.parent_loop(lp), |lp| lpt.parent_loop(*lp)).count();
+        options.loop_cluster(LoopCluster {
+            header: lpt.loop_header(lp),
+            blocks,
+            depth,
+        });
+    }
+}
@@ -0,0 +1,283 @@
+//! This module contains the Solidity ABI of the external functions of a contract, along with the
+//! selectors and event topics derived from it, so that frontends compute the same values as the
+//! dispatcher does.
+//!
+//! Integers are exposed as unsigned integers of the same width, and `i1` as `bool`. Arrays are
+//! exposed as static arrays, structs as tuples, and `bytes` as `bytes`. A pointer argument is
+//! exposed as the type it points to, since aggregates are passed by reference. The dispatcher only
+//! decodes integers yet.
+use std::fmt::Write;
+
+use sonatina_ir::{
+    json::{delim, JsonStr},
+    module::ModuleCtx,
+    types::{CompoundTypeData, TypeStore},
+    CallConv, Function, Signature, Type,
+};
+use tiny_keccak::{Hasher, Keccak};
+
+/// The selector of `Panic(uint256)`, the error that a `trap` reverts with.
+pub const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Returns the ABI type name of `ty`, or `None` if `ty` can't be passed to external functions.
+pub fn abi_type_name(ctx: &ModuleCtx, ty: Type) -> Option<String> {
+    ctx.with_ty_store(|store| type_name(store, passed_type(store, ty)))
+}
+
+/// Returns the canonical signature of `sig`, e.g., `transfer(uint256,uint64)`, or `None` if
+/// `sig` has a type without an ABI representation.
+pub fn canonical_signature(ctx: &ModuleCtx, sig: &Signature) -> Option<String> {
+    let args = sig
+        .args()
+        .iter()
+        .map(|ty| abi_type_name(ctx, *ty))
+        .collect::<Option<Vec<_>>>()?;
+    if sig.ret_ty() != Type::Void {
+        abi_type_name(ctx, sig.ret_ty())?;
+    }
+
+    Some(format!("{}({})", sig.name(), args.join(",")))
+}
+
+/// Returns the selector of the function `sig`, or `None` if `sig` has a type without an ABI
+/// representation.
+pub fn function_selector(ctx: &ModuleCtx, sig: &Signature) -> Option<[u8; 4]> {
+    canonical_signature(ctx, sig).map(|canonical_sig| selector(&canonical_sig))
+}
+
+/// Returns the topic of an event whose fields are the arguments of `sig`, or `None` if `sig` has
+/// a type without an ABI representation.
+pub fn event_topic(ctx: &ModuleCtx, sig: &Signature) -> Option<[u8; 32]> {
+    canonical_signature(ctx, sig).map(|canonical_sig| topic(&canonical_sig))
+}
+
+/// Returns the first 4 bytes of the keccak256 hash of a canonical signature.
+pub fn selector(canonical_sig: &str) -> [u8; 4] {
+    let hash = keccak256(canonical_sig.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Returns the keccak256 hash of a canonical event signature, which is the first topic of the
+/// logs of the event.
+pub fn topic(canonical_sig: &str) -> [u8; 32] {
+    keccak256(canonical_sig.as_bytes())
+}
+
+fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(bytes);
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// Returns the type of the value an argument of type `ty` passes, i.e., the type a pointer points
+/// to.
+fn passed_type(store: &TypeStore, ty: Type) -> Type {
+    match ty {
+        Type::Compound(compound) => match store.resolve_compound(compound) {
+            CompoundTypeData::Ptr(pointee) => *pointee,
+            _ => ty,
+        },
+        _ => ty,
+    }
+}
+
+/// Returns the ABI type name of `ty` as a value, i.e., pointers have none.
+fn type_name(store: &TypeStore, ty: Type) -> Option<String> {
+    match ty {
+        Type::I1 => Some("bool".to_string()),
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128 | Type::I256 => {
+            Some(format!("uint{}", super::layout::bit_width(ty)))
+        }
+        Type::Compound(compound) => match store.resolve_compound(compound) {
+            CompoundTypeData::Array { elem, len } => {
+                Some(format!("{}[{len}]", type_name(store, *elem)?))
+            }
+            CompoundTypeData::Struct(def) => {
+                let fields = def
+                    .fields
+                    .iter()
+                    .map(|field| type_name(store, *field))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(format!("({})", fields.join(",")))
+            }
+            CompoundTypeData::Bytes => Some("bytes".to_string()),
+            CompoundTypeData::Ptr(_) => None,
+        },
+        Type::Void => None,
+    }
+}
+
+/// Returns the ABI JSON describing `funcs`, with the state mutability of each function taken from
+/// its attributes. Structs are described as tuples with their fields as components. A function
+/// with the [`CallConv::Fallback`] convention is described as the fallback of the contract.
+///
+/// # Panics
+/// Panics if a signature has a type without an ABI representation.
+pub fn abi_json<'a>(ctx: &ModuleCtx, funcs: impl IntoIterator<Item = &'a Function>) -> String {
+    let mut json = String::from("[");
+    for (i, func) in funcs.into_iter().enumerate() {
+        json.push_str(delim(i));
+
+        let sig = &func.sig;
+        if sig.call_conv() == CallConv::Fallback {
+            write!(
+                json,
+                "{{\"type\":\"fallback\",\"stateMutability\":\"{}\"}}",
+                func.sig.attrs().mutability
+            )
+            .unwrap();
+            continue;
+        }
+
+        let params = |tys: &[Type]| {
+            tys.iter()
+                .map(|ty| ctx.with_ty_store(|store| param_json(store, passed_type(store, *ty))))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let outputs = if sig.ret_ty() == Type::Void {
+            String::new()
+        } else {
+            params(&[sig.ret_ty()])
+        };
+
+        write!(
+            json,
+            "{{\"type\":\"function\",\"name\":{},\"inputs\":[{}],\"outputs\":[{}],\
+             \"stateMutability\":\"{}\"}}",
+            JsonStr(sig.name()),
+            params(sig.args()),
+            outputs,
+            func.sig.attrs().mutability
+        )
+        .unwrap();
+    }
+    json.push(']');
+    json
+}
+
+/// Returns the JSON of a parameter of type `ty`. A struct, or an array of them, is a `tuple`
+/// whose components are its fields.
+fn param_json(store: &TypeStore, mut ty: Type) -> String {
+    // Array dimensions are written innermost first, e.g., `uint8[4][2]`.
+    let mut dims = String::new();
+    while let Some((elem, len)) = store.array_def(ty) {
+        dims = format!("[{len}]{dims}");
+        ty = elem;
+    }
+
+    match store.struct_def(ty) {
+        Some(def) => {
+            let components: Vec<_> = def
+                .fields
+                .iter()
+                .map(|field| param_json(store, *field))
+                .collect();
+            format!(
+                "{{\"name\":\"\",\"type\":\"tuple{dims}\",\"components\":[{}]}}",
+                components.join(",")
+            )
+        }
+        None => {
+            let name = type_name(store, ty).expect("type has no ABI representation");
+            format!("{{\"name\":\"\",\"type\":\"{name}{dims}\"}}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::build_test_isa, Linkage, StateMutability};
+
+    #[test]
+    fn selector_and_json() {
+        let ctx = ModuleCtx::new(build_test_isa());
+        assert_eq!(
+            selector("transfer(address,uint256)"),
+            [0xa9, 0x05, 0x9c, 0xbb]
+        );
+        assert_eq!(selector("Panic(uint256)"), PANIC_SELECTOR);
+
+        let sig = Signature::new("add", Linkage::Public, &[Type::I32, Type::I1], Type::I256);
+        assert_eq!(
+            canonical_signature(&ctx, &sig).as_deref(),
+            Some("add(uint32,bool)")
+        );
+        let mut func = Function::new(&ctx, sig);
+        assert_eq!(
+            abi_json(&ctx, [&func]),
+            "[{\"type\":\"function\",\"name\":\"add\",\
+             \"inputs\":[{\"name\":\"\",\"type\":\"uint32\"},{\"name\":\"\",\"type\":\"bool\"}],\
+             \"outputs\":[{\"name\":\"\",\"type\":\"uint256\"}],\
+             \"stateMutability\":\"nonpayable\"}]"
+        );
+
+        // Structs are tuples, and the state mutability is the attribute of the function.
+        let point = ctx.with_ty_store_mut(|s| {
+            let point = s.make_struct("point", &[Type::I64, Type::I1], false);
+            let points = s.make_array(point, 2);
+            s.make_ptr(points)
+        });
+        let sig = Signature::new("norm", Linkage::Public, &[point], Type::I64);
+        func = Function::new(&ctx, sig);
+        func.sig.attrs_mut().mutability = StateMutability::Pure;
+        assert_eq!(
+            abi_json(&ctx, [&func]),
+            "[{\"type\":\"function\",\"name\":\"norm\",\
+             \"inputs\":[{\"name\":\"\",\"type\":\"tuple[2]\",\"components\":[\
+             {\"name\":\"\",\"type\":\"uint64\"},{\"name\":\"\",\"type\":\"bool\"}]}],\
+             \"outputs\":[{\"name\":\"\",\"type\":\"uint64\"}],\
+             \"stateMutability\":\"pure\"}]"
+        );
+    }
+
+    #[test]
+    fn compound_types() {
+        let ctx = ModuleCtx::new(build_test_isa());
+        let (array, point, ptr, nested_ptr) = ctx.with_ty_store_mut(|s| {
+            let array = s.make_array(Type::I8, 4);
+            let point = s.make_struct("point", &[Type::I64, array], false);
+            let ptr = s.make_ptr(point);
+            let nested_ptr = s.make_struct("node", &[ptr], false);
+            (array, point, ptr, nested_ptr)
+        });
+        assert_eq!(abi_type_name(&ctx, array).as_deref(), Some("uint8[4]"));
+        assert_eq!(
+            abi_type_name(&ctx, point).as_deref(),
+            Some("(uint64,uint8[4])")
+        );
+        assert_eq!(
+            abi_type_name(&ctx, ptr).as_deref(),
+            Some("(uint64,uint8[4])")
+        );
+        assert_eq!(abi_type_name(&ctx, nested_ptr), None);
+
+        let sig = Signature::new("move", Linkage::Public, &[ptr, Type::I1], Type::Void);
+        assert_eq!(
+            canonical_signature(&ctx, &sig).as_deref(),
+            Some("move((uint64,uint8[4]),bool)")
+        );
+        assert_eq!(
+            function_selector(&ctx, &sig),
+            Some(selector("move((uint64,uint8[4]),bool)"))
+        );
+    }
+
+    #[test]
+    fn event_topics() {
+        assert_eq!(
+            topic("Transfer(address,address,uint256)")[..4],
+            [0xdd, 0xf2, 0x52, 0xad]
+        );
+
+        let ctx = ModuleCtx::new(build_test_isa());
+        let sig = Signature::new("Log", Linkage::Private, &[Type::I256], Type::Void);
+        let topic = event_topic(&ctx, &sig).unwrap();
+        assert_eq!(topic, super::topic("Log(uint256)"));
+        assert_eq!(topic[..4], selector("Log(uint256)"));
+    }
+}
@@ -0,0 +1,393 @@
+//! This module contains the symbolic assembly that IR is lowered to, and the assembler that
+//! resolves labels and encodes the assembly into bytecode.
+//!
+//! The width of the `PUSH` of a label depends on the offset of the label, which in turn depends
+//! on the widths of the pushes before it. The assembler starts with the narrowest pushes and
+//! widens those whose target doesn't fit until no push needs widening. Pushes only ever grow, so
+//! offsets only grow, too, and this converges on the narrowest widths that fit.
+//!
+//! Labels and external symbols are encoded as [relocations](crate::reloc): the assembler resolves
+//! the labels, and leaves the external symbols in [`Assembled::relocs`] to be resolved by
+//! [`Assembled::link`].
+use cranelift_entity::{entity_impl, SecondaryMap};
+use sonatina_ir::{module::FuncRef, Insn, U256};
+
+use crate::reloc::{self, Reloc, RelocError, RelocKind};
+
+use super::opcode::OpCode;
+
+/// An opaque reference to an offset in the assembled code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(u32);
+entity_impl!(Label);
+
+/// The size of the offset of a label in data, e.g., an entry of a jump table.
+pub const LABEL_DATA_SIZE: usize = 4;
+
+/// A symbol whose value isn't known until the code is linked or deployed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExternalSymbol {
+    /// The address of a library.
+    Library(String),
+    /// An immutable, which the deploy code writes into the runtime code.
+    Immutable(String),
+}
+
+impl ExternalSymbol {
+    /// Returns the size of the value of the symbol.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Library(_) => 20,
+            Self::Immutable(_) => 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmItem {
+    Op(OpCode),
+
+    /// Push an immediate with the narrowest `PUSH`, which is `PUSH0` for zero if the assembly
+    /// [may use it](Assembly::with_push0).
+    Push(U256),
+
+    /// Push the offset of a label with the narrowest `PUSH`.
+    PushLabel(Label),
+
+    /// Push the value of an external symbol with a `PUSH` as wide as the symbol. The immediate is
+    /// zero until the symbol is linked.
+    PushExternal(ExternalSymbol),
+
+    /// Bind a label to a `JUMPDEST`.
+    JumpDest(Label),
+
+    /// Bind a label to the current offset without emitting code, e.g., to refer to a data
+    /// section.
+    Mark(Label),
+
+    /// Raw bytes that are never executed.
+    Data(Vec<u8>),
+
+    /// The offset of a label as `LABEL_DATA_SIZE` big-endian bytes that are never executed, e.g.,
+    /// an entry of a jump table.
+    LabelData(Label),
+
+    /// Marks the start of the code of a function. Emits no code.
+    FuncStart(FuncRef),
+
+    /// Marks the end of the code of a function. Emits no code.
+    FuncEnd(FuncRef),
+
+    /// Marks the start of the code lowered from an insn. Emits no code.
+    InsnStart(FuncRef, Insn),
+}
+
+impl AsmItem {
+    /// Returns the size of the encoded item, where the immediate of a `PushLabel` takes
+    /// `label_size` bytes.
+    fn size(&self, push0: bool, label_size: usize) -> usize {
+        match self {
+            Self::Op(op) => 1 + op.immediate_size(),
+            Self::Push(imm) => 1 + push_size(*imm, push0),
+            Self::PushLabel(_) => 1 + label_size,
+            Self::PushExternal(symbol) => 1 + symbol.size(),
+            Self::LabelData(_) => LABEL_DATA_SIZE,
+            Self::JumpDest(_) => 1,
+            Self::Data(data) => data.len(),
+            Self::Mark(_) | Self::FuncStart(_) | Self::FuncEnd(_) | Self::InsnStart(..) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    items: Vec<AsmItem>,
+    label_num: u32,
+    push0: bool,
+}
+
+impl Assembly {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether zero is pushed with `PUSH0`, which is only available since Shanghai.
+    pub fn with_push0(mut self, push0: bool) -> Self {
+        self.push0 = push0;
+        self
+    }
+
+    pub fn make_label(&mut self) -> Label {
+        let label = Label::from_u32(self.label_num);
+        self.label_num += 1;
+        label
+    }
+
+    pub fn push_item(&mut self, item: AsmItem) {
+        self.items.push(item);
+    }
+
+    pub fn op(&mut self, op: OpCode) {
+        debug_assert_eq!(op.immediate_size(), 0);
+        self.push_item(AsmItem::Op(op));
+    }
+
+    pub fn push(&mut self, imm: impl Into<U256>) {
+        self.push_item(AsmItem::Push(imm.into()));
+    }
+
+    pub fn push_label(&mut self, label: Label) {
+        self.push_item(AsmItem::PushLabel(label));
+    }
+
+    pub fn jump_dest(&mut self, label: Label) {
+        self.push_item(AsmItem::JumpDest(label));
+    }
+
+    pub fn items(&self) -> &[AsmItem] {
+        &self.items
+    }
+
+    /// Resolves labels and encodes the assembly. External symbols are left as relocations.
+    ///
+    /// # Panics
+    /// Panics if a referenced label is never bound.
+    pub fn assemble(&self) -> Assembled {
+        let narrowest = if self.push0 { 0 } else { 1 };
+        let mut label_sizes = vec![narrowest; self.items.len()];
+        let (offsets, label_offsets, size) = loop {
+            let (offsets, label_offsets, size) = self.layout(&label_sizes);
+            let mut relaxed = true;
+            for (item, label_size) in self.items.iter().zip(&mut label_sizes) {
+                if let AsmItem::PushLabel(label) = item {
+                    let target = resolve(&label_offsets, *label);
+                    let needed = push_size(target.into(), self.push0);
+                    if needed > *label_size {
+                        *label_size = needed;
+                        relaxed = false;
+                    }
+                }
+            }
+            if relaxed {
+                break (offsets, label_offsets, size);
+            }
+        };
+
+        let mut code = Vec::with_capacity(size);
+        let mut label_relocs = Vec::new();
+        let mut relocs = Vec::new();
+        for (item, &label_size) in self.items.iter().zip(&label_sizes) {
+            match item {
+                AsmItem::Op(op) => code.push(op.0),
+                AsmItem::Push(imm) => {
+                    let size = push_size(*imm, self.push0);
+                    code.push(push_op(size).0);
+                    code.extend_from_slice(&be_bytes(*imm)[32 - size..]);
+                }
+                AsmItem::PushLabel(label) => {
+                    code.push(push_op(label_size).0);
+                    let kind = RelocKind::BigEndian(label_size);
+                    label_relocs.push(Reloc::new(*label, kind, code.len() as u32));
+                    code.resize(code.len() + label_size, 0);
+                }
+                AsmItem::PushExternal(symbol) => {
+                    let size = symbol.size();
+                    code.push(OpCode::push(size).0);
+                    let kind = RelocKind::BigEndian(size);
+                    relocs.push(Reloc::new(symbol.clone(), kind, code.len() as u32));
+                    code.resize(code.len() + size, 0);
+                }
+                AsmItem::LabelData(label) => {
+                    let kind = RelocKind::BigEndian(LABEL_DATA_SIZE);
+                    label_relocs.push(Reloc::new(*label, kind, code.len() as u32));
+                    code.resize(code.len() + LABEL_DATA_SIZE, 0);
+                }
+                AsmItem::JumpDest(_) => code.push(OpCode::JUMPDEST.0),
+                AsmItem::Data(data) => code.extend_from_slice(data),
+                AsmItem::Mark(_)
+                | AsmItem::FuncStart(_)
+                | AsmItem::FuncEnd(_)
+                | AsmItem::InsnStart(..) => {}
+            }
+        }
+
+        // The pushes are as wide as their targets, and a label in data is never farther than
+        // `u32::MAX`, so resolving labels can't fail.
+        reloc::apply(&mut code, label_relocs, |label| {
+            Some(resolve(&label_offsets, *label).into())
+        })
+        .unwrap();
+
+        Assembled {
+            code,
+            offsets,
+            relocs,
+        }
+    }
+
+    /// Returns the offset of each item and each label, and the size of the code, if the
+    /// immediate of the `PushLabel` at each index takes the corresponding bytes in `label_sizes`.
+    fn layout(&self, label_sizes: &[usize]) -> (Vec<u32>, SecondaryMap<Label, Option<u32>>, usize) {
+        let mut offsets = Vec::with_capacity(self.items.len());
+        let mut label_offsets: SecondaryMap<Label, Option<u32>> = SecondaryMap::new();
+        let mut offset = 0;
+        for (item, &label_size) in self.items.iter().zip(label_sizes) {
+            offsets.push(offset as u32);
+            if let AsmItem::JumpDest(label) | AsmItem::Mark(label) = item {
+                debug_assert!(label_offsets[*label].is_none(), "{label:?} is bound twice");
+                label_offsets[*label] = Some(offset as u32);
+            }
+            offset += item.size(self.push0, label_size);
+        }
+        (offsets, label_offsets, offset)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembled {
+    pub code: Vec<u8>,
+    /// The offset in `code` of each item of the assembly.
+    pub offsets: Vec<u32>,
+    /// The references to external symbols that are yet to be linked.
+    pub relocs: Vec<Reloc<ExternalSymbol>>,
+}
+
+impl Assembled {
+    /// Patches the value of each external symbol that `resolve` knows into the code. The
+    /// relocations of the other symbols are kept.
+    pub fn link(
+        &mut self,
+        resolve: impl FnMut(&ExternalSymbol) -> Option<U256>,
+    ) -> Result<(), RelocError<ExternalSymbol>> {
+        let relocs = std::mem::take(&mut self.relocs);
+        self.relocs = reloc::apply(&mut self.code, relocs, resolve)?;
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes needed to push `imm`, which is zero for `PUSH0`.
+pub(super) fn push_size(imm: U256, push0: bool) -> usize {
+    let size = imm.bits().div_ceil(8);
+    if push0 {
+        size
+    } else {
+        size.max(1)
+    }
+}
+
+/// Returns the `PUSH` with an immediate of `size` bytes.
+fn push_op(size: usize) -> OpCode {
+    if size == 0 {
+        OpCode::PUSH0
+    } else {
+        OpCode::push(size)
+    }
+}
+
+fn resolve(label_offsets: &SecondaryMap<Label, Option<u32>>, label: Label) -> u32 {
+    label_offsets[label].unwrap_or_else(|| panic!("{label:?} is not bound"))
+}
+
+pub(super) fn be_bytes(imm: U256) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    imm.to_big_endian(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_labels() {
+        let mut asm = Assembly::new();
+        let dest = asm.make_label();
+        let data = asm.make_label();
+
+        asm.push(0x1234u64);
+        asm.push_label(dest);
+        asm.op(OpCode::JUMP);
+        asm.jump_dest(dest);
+        asm.push_label(data);
+        asm.op(OpCode::STOP);
+        asm.push_item(AsmItem::Mark(data));
+        asm.push_item(AsmItem::Data(vec![0xaa]));
+        asm.push_item(AsmItem::LabelData(dest));
+
+        let assembled = asm.assemble();
+        assert_eq!(
+            assembled.code,
+            vec![
+                0x61, 0x12, 0x34, // PUSH2 0x1234
+                0x60, 0x06, // PUSH1 6
+                0x56, // JUMP
+                0x5b, // JUMPDEST
+                0x60, 0x0a, // PUSH1 10
+                0x00, // STOP
+                0xaa, 0x00, 0x00, 0x00, 0x06,
+            ]
+        );
+        assert_eq!(assembled.offsets, vec![0, 3, 5, 6, 7, 9, 10, 10, 11]);
+    }
+
+    #[test]
+    fn assemble_push0() {
+        let mut asm = Assembly::new().with_push0(true);
+        asm.push(0u64);
+        asm.push(0xffu64);
+        asm.push(U256::MAX);
+        let assembled = asm.assemble();
+        assert_eq!(&assembled.code[..3], &[0x5f, 0x60, 0xff]);
+        assert_eq!(assembled.code[3], 0x7f);
+        assert_eq!(assembled.code.len(), 3 + 33);
+
+        let mut asm = Assembly::new();
+        asm.push(0u64);
+        assert_eq!(asm.assemble().code, vec![0x60, 0x00]);
+    }
+
+    #[test]
+    fn relax_label_pushes() {
+        // With `PUSH1`s, the destination is at 0x100, so the first push needs widening, which
+        // moves the destination to 0x101.
+        let mut asm = Assembly::new();
+        let dest = asm.make_label();
+        asm.push_label(dest);
+        asm.op(OpCode::JUMP);
+        asm.push_item(AsmItem::Data(vec![0; 0xfd]));
+        asm.jump_dest(dest);
+        asm.push_label(dest);
+        asm.op(OpCode::JUMP);
+
+        let assembled = asm.assemble();
+        assert_eq!(&assembled.code[..4], &[0x61, 0x01, 0x01, 0x56]);
+        assert_eq!(assembled.code[0x101], OpCode::JUMPDEST.0);
+        assert_eq!(&assembled.code[0x102..], &[0x61, 0x01, 0x01, 0x56]);
+    }
+
+    #[test]
+    fn link_external_symbols() {
+        let mut asm = Assembly::new();
+        let lib = ExternalSymbol::Library("math".to_string());
+        let imm = ExternalSymbol::Immutable("owner".to_string());
+        asm.push_item(AsmItem::PushExternal(lib.clone()));
+        asm.push_item(AsmItem::PushExternal(imm.clone()));
+
+        let mut assembled = asm.assemble();
+        assert_eq!(assembled.code.len(), 21 + 33);
+        assert_eq!(
+            assembled.relocs,
+            vec![
+                Reloc::new(lib.clone(), RelocKind::BigEndian(20), 1),
+                Reloc::new(imm.clone(), RelocKind::BigEndian(32), 22),
+            ]
+        );
+
+        assembled
+            .link(|symbol| (*symbol == lib).then_some(U256::from(0xabcdu64)))
+            .unwrap();
+        assert_eq!(&assembled.code[19..21], &[0xab, 0xcd]);
+        assert_eq!(assembled.relocs.len(), 1);
+        assert_eq!(assembled.relocs[0].symbol, imm);
+    }
+}
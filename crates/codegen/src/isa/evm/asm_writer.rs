@@ -0,0 +1,113 @@
+//! This module contains the writer of the textual form of EVM assembly, which shows what each
+//! insn is lowered to, e.g.,
+//!
+//! ```text
+//! ; func public %add
+//! L1:
+//!     JUMPDEST
+//!     ; v2.i32 = add v0 v1;
+//!     PUSH1 0x00
+//!     MLOAD
+//!     ...
+//!     PUSH2 L7
+//!     JUMPI
+//! ```
+//!
+//! Pushes are written with the width they are assembled to, and labels by name rather than by
+//! offset, so that the text doesn't change when unrelated code moves.
+use std::io;
+
+use sonatina_ir::{ir_writer::FuncWriter, Module};
+
+use super::{
+    asm::{AsmItem, Assembled, Assembly, ExternalSymbol},
+    opcode::OpCode,
+};
+
+pub struct AsmWriter<'a> {
+    module: &'a Module,
+    asm: &'a Assembly,
+    assembled: &'a Assembled,
+}
+
+impl<'a> AsmWriter<'a> {
+    /// `assembled` must be the result of assembling `asm`.
+    pub fn new(module: &'a Module, asm: &'a Assembly, assembled: &'a Assembled) -> Self {
+        Self {
+            module,
+            asm,
+            assembled,
+        }
+    }
+
+    pub fn write(&self, mut w: impl io::Write) -> io::Result<()> {
+        let items = self.asm.items();
+        for (idx, item) in items.iter().enumerate() {
+            let offset = self.assembled.offsets[idx] as usize;
+            let end = self
+                .assembled
+                .offsets
+                .get(idx + 1)
+                .map_or(self.assembled.code.len(), |end| *end as usize);
+            let bytes = &self.assembled.code[offset..end];
+
+            match item {
+                AsmItem::Op(op) => writeln!(w, "    {op}")?,
+                AsmItem::Push(_) => {
+                    write!(w, "    {}", OpCode(bytes[0]))?;
+                    if bytes.len() > 1 {
+                        write!(w, " 0x")?;
+                        write_hex(&mut w, &bytes[1..])?;
+                    }
+                    writeln!(w)?;
+                }
+                AsmItem::PushLabel(label) => {
+                    writeln!(w, "    {} L{}", OpCode(bytes[0]), label.as_u32())?
+                }
+                AsmItem::PushExternal(symbol) => {
+                    let op = OpCode(bytes[0]);
+                    match symbol {
+                        ExternalSymbol::Library(name) => writeln!(w, "    {op} library({name})")?,
+                        ExternalSymbol::Immutable(name) => {
+                            writeln!(w, "    {op} immutable({name})")?
+                        }
+                    }
+                }
+                AsmItem::JumpDest(label) => writeln!(w, "L{}:\n    JUMPDEST", label.as_u32())?,
+                AsmItem::Mark(label) => writeln!(w, "L{}:", label.as_u32())?,
+                AsmItem::Data(_) => {
+                    write!(w, "    .data 0x")?;
+                    write_hex(&mut w, bytes)?;
+                    writeln!(w)?;
+                }
+                AsmItem::LabelData(label) => writeln!(w, "    .label L{}", label.as_u32())?,
+                AsmItem::FuncStart(func_ref) => {
+                    let sig = &self.module.funcs[*func_ref].sig;
+                    writeln!(w, "\n; func {} %{}", sig.linkage(), sig.name())?;
+                }
+                AsmItem::FuncEnd(_) => {}
+                AsmItem::InsnStart(func_ref, insn) => {
+                    let func = &self.module.funcs[*func_ref];
+                    write!(w, "    ; ")?;
+                    FuncWriter::new(*func_ref, func, None).write_insn(*insn, &mut w)?;
+                    writeln!(w)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn dump_string(&self) -> io::Result<String> {
+        let mut s = Vec::new();
+        self.write(&mut s)?;
+        Ok(String::from_utf8(s).unwrap())
+    }
+}
+
+fn write_hex(mut w: impl io::Write, bytes: &[u8]) -> io::Result<()> {
+    for byte in bytes {
+        write!(w, "{byte:02x}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! This module contains the memory layout of types on the EVM.
+//!
+//! Scalars are stored big-endian in their byte size, pointers take a whole word, and aggregates
+//! are laid out without padding.
+use sonatina_ir::{
+    global_variable::ConstantValue,
+    module::ModuleCtx,
+    types::{CompoundTypeData, BYTES_PTR_FIELD},
+    Type, U256,
+};
+
+pub const WORD_SIZE: usize = 32;
+
+/// Returns the number of bits of a value of `ty` on the operand stack.
+pub fn bit_width(ty: Type) -> usize {
+    match ty {
+        Type::I1 => 1,
+        Type::I8 => 8,
+        Type::I16 => 16,
+        Type::I32 => 32,
+        Type::I64 => 64,
+        Type::I128 => 128,
+        Type::I256 | Type::Compound(_) => 256,
+        Type::Void => 0,
+    }
+}
+
+/// Returns the number of bytes a value of `ty` occupies in memory.
+pub fn size_of(ctx: &ModuleCtx, ty: Type) -> usize {
+    match ty {
+        Type::I1 | Type::I8 => 1,
+        Type::I16 => 2,
+        Type::I32 => 4,
+        Type::I64 => 8,
+        Type::I128 => 16,
+        Type::I256 => WORD_SIZE,
+        Type::Void => 0,
+        Type::Compound(cmpd) => {
+            let data = ctx.with_ty_store(|s| s.resolve_compound(cmpd).clone());
+            match data {
+                CompoundTypeData::Ptr(_) => WORD_SIZE,
+                CompoundTypeData::Array { elem, len } => len * size_of(ctx, elem),
+                CompoundTypeData::Struct(data) => {
+                    data.fields.iter().map(|field| size_of(ctx, *field)).sum()
+                }
+                CompoundTypeData::Bytes => 2 * WORD_SIZE,
+            }
+        }
+    }
+}
+
+/// Returns the offset of the `idx`-th field of an aggregate `ty`, along with the field type.
+///
+/// # Panics
+/// Panics if `ty` is not an aggregate, or `idx` is out of range of a struct.
+pub fn field_offset(ctx: &ModuleCtx, ty: Type, idx: usize) -> (usize, Type) {
+    let Type::Compound(cmpd) = ty else {
+        panic!("scalar types have no fields");
+    };
+
+    let data = ctx.with_ty_store(|s| s.resolve_compound(cmpd).clone());
+    match data {
+        CompoundTypeData::Array { elem, .. } => (idx * size_of(ctx, elem), elem),
+        CompoundTypeData::Struct(data) => {
+            let offset = data.fields[..idx]
+                .iter()
+                .map(|field| size_of(ctx, *field))
+                .sum();
+            (offset, data.fields[idx])
+        }
+        CompoundTypeData::Bytes => {
+            let field_ty = ctx.with_ty_store(|s| s.bytes_field(idx));
+            let offset = if idx == BYTES_PTR_FIELD { 0 } else { WORD_SIZE };
+            (offset, field_ty)
+        }
+        CompoundTypeData::Ptr(_) => panic!("pointers have no fields"),
+    }
+}
+
+/// Appends the memory image of `value` of `ty` to `buf`.
+pub fn serialize_const(ctx: &ModuleCtx, ty: Type, value: &ConstantValue, buf: &mut Vec<u8>) {
+    match value {
+        ConstantValue::Immediate(imm) => {
+            let layout = ctx.isa.type_layout();
+            buf.extend(layout.to_bytes(imm.as_i256().to_u256(), ty));
+        }
+        ConstantValue::Array(elems) | ConstantValue::Struct(elems) => {
+            for (idx, elem) in elems.iter().enumerate() {
+                let (_, elem_ty) = field_offset(ctx, ty, idx);
+                serialize_const(ctx, elem_ty, elem, buf);
+            }
+        }
+    }
+}
+
+/// Returns the mask of the low `bits` bits.
+pub fn low_mask(bits: usize) -> U256 {
+    if bits >= 256 {
+        U256::MAX
+    } else {
+        (U256::one() << bits) - U256::one()
+    }
+}
+
+/// Returns a word of `lane` repeated in each `lane_bits`-bit lane, e.g., `0x5555..55` for `0x5` in
+/// 4-bit lanes.
+pub fn splat(lane: U256, lane_bits: usize) -> U256 {
+    (0..256 / lane_bits).fold(U256::zero(), |acc, idx| acc | (lane << (idx * lane_bits)))
+}
@@ -0,0 +1,1133 @@
+//! This module contains the lowering of functions to EVM assembly.
+//!
+//! Every argument and insn result lives in a word-sized slot of the frame of its function, so the
+//! operand stack only holds return addresses and the temporaries of the insn being lowered.
+//! A frame is laid out as follows.
+//!
+//! ```text
+//! fp + 0x00 | the frame pointer of the caller
+//! fp + 0x20 | value slots
+//! ...       | alloca regions
+//! ```
+//!
+//! A caller pushes the return address and then the arguments, and jumps to the callee. The callee
+//! pops the arguments into its frame, and returns by jumping to the return address with the
+//! result on top of the stack.
+//!
+//! Values are kept zero-extended to the width of their type; signed operations sign-extend their
+//! operands first.
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use sonatina_ir::{
+    insn::{BinaryOp, CastOp, MemOp, OverflowOp, UnaryOp},
+    module::FuncRef,
+    Block, DataLocationKind, Function, GlobalVariable, Insn, InsnData, Module, Type, Value,
+    ValueData, U256,
+};
+use sonatina_triple::Feature;
+
+use crate::optim::tail_call;
+
+use super::{
+    abi,
+    asm::{AsmItem, Assembly, Label},
+    intrinsic_opcode,
+    layout::{self, WORD_SIZE},
+    opcode::OpCode,
+    EvmCodegenError, FP_ADDR, SP_ADDR,
+};
+
+/// The address of the identity precompile, which returns its input.
+const IDENTITY_ADDR: usize = 0x04;
+
+/// The maximum number of words or slots that a memory intrinsic with a constant length is unrolled
+/// into.
+const UNROLL_LIMIT: usize = 4;
+
+/// Labels and addresses shared by all functions in a module.
+pub(super) struct ModuleSymbols {
+    pub func_labels: FxHashMap<FuncRef, Label>,
+    pub global_addrs: FxHashMap<GlobalVariable, usize>,
+    /// Addresses of the constants in the constant pool.
+    pub const_addrs: FxHashMap<U256, usize>,
+}
+
+pub(super) struct FuncLowering<'a> {
+    module: &'a Module,
+    func_ref: FuncRef,
+    func: &'a Function,
+    symbols: &'a ModuleSymbols,
+    asm: &'a mut Assembly,
+
+    block_labels: FxHashMap<Block, Label>,
+    /// Offsets of value slots from the frame pointer.
+    slots: FxHashMap<Value, usize>,
+    /// Offsets of alloca regions from the frame pointer.
+    allocas: FxHashMap<Insn, usize>,
+    frame_size: usize,
+    /// Whether the frame must outlive tail calls, since an alloca address may be used by them.
+    has_escaping_alloca: bool,
+    /// Jump destinations that copy phi arguments on an edge, i.e., `(label, from, to)`.
+    edge_blocks: Vec<(Label, Block, Block)>,
+}
+
+impl<'a> FuncLowering<'a> {
+    pub(super) fn new(
+        module: &'a Module,
+        func_ref: FuncRef,
+        symbols: &'a ModuleSymbols,
+        asm: &'a mut Assembly,
+    ) -> Self {
+        let func = &module.funcs[func_ref];
+        let mut lowering = Self {
+            module,
+            func_ref,
+            func,
+            symbols,
+            asm,
+            block_labels: FxHashMap::default(),
+            slots: FxHashMap::default(),
+            allocas: FxHashMap::default(),
+            frame_size: 0,
+            has_escaping_alloca: tail_call::has_escaping_alloca(func),
+            edge_blocks: Vec::new(),
+        };
+        lowering.layout_frame();
+        lowering
+    }
+
+    pub(super) fn lower(mut self) -> Result<(), EvmCodegenError> {
+        let func = self.func;
+        for block in func.layout.iter_block() {
+            let label = self.asm.make_label();
+            self.block_labels.insert(block, label);
+        }
+
+        self.asm.push_item(AsmItem::FuncStart(self.func_ref));
+        self.asm.jump_dest(self.symbols.func_labels[&self.func_ref]);
+        self.prologue();
+
+        for block in func.layout.iter_block() {
+            self.asm.jump_dest(self.block_labels[&block]);
+
+            let mut insns = func.layout.iter_insn(block);
+            while let Some(insn) = insns.next() {
+                self.asm.push_item(AsmItem::InsnStart(self.func_ref, insn));
+                if self.lower_insn(insn)? {
+                    // The following return is folded into the tail call.
+                    insns.next();
+                }
+            }
+
+            for (label, from, to) in std::mem::take(&mut self.edge_blocks) {
+                self.asm.jump_dest(label);
+                self.copy_phi_args(from, to);
+                self.jump_to(to);
+            }
+        }
+
+        self.asm.push_item(AsmItem::FuncEnd(self.func_ref));
+        Ok(())
+    }
+
+    fn layout_frame(&mut self) {
+        let func = self.func;
+        let mut offset = WORD_SIZE;
+        let mut add_slot = |slots: &mut FxHashMap<Value, usize>, value| {
+            slots.insert(value, offset);
+            offset += WORD_SIZE;
+        };
+
+        for &arg in &func.arg_values {
+            add_slot(&mut self.slots, arg);
+        }
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                for &result in func.dfg.insn_results(insn) {
+                    if func.dfg.value_ty(result) != Type::Void {
+                        add_slot(&mut self.slots, result);
+                    }
+                }
+            }
+        }
+
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                if let InsnData::Alloca { ty } = func.dfg.insn_data(insn) {
+                    self.allocas.insert(insn, offset);
+                    let size = layout::size_of(&self.module.ctx, *ty);
+                    offset += size.div_ceil(WORD_SIZE) * WORD_SIZE;
+                }
+            }
+        }
+
+        self.frame_size = offset;
+    }
+
+    /// Allocates the frame on top of the stack pointer, and pops arguments into their slots.
+    fn prologue(&mut self) {
+        self.push(SP_ADDR);
+        self.op(OpCode::MLOAD);
+        self.push(FP_ADDR);
+        self.op(OpCode::MLOAD);
+        self.op(OpCode::dup(2));
+        self.op(OpCode::MSTORE);
+        self.op(OpCode::dup(1));
+        self.push(FP_ADDR);
+        self.op(OpCode::MSTORE);
+        self.push(self.frame_size);
+        self.op(OpCode::ADD);
+        self.push(SP_ADDR);
+        self.op(OpCode::MSTORE);
+
+        let func = self.func;
+        for &arg in func.arg_values.iter().rev() {
+            self.store_top(arg);
+        }
+    }
+
+    /// Frees the frame and restores the frame pointer of the caller.
+    fn epilogue(&mut self) {
+        self.push(FP_ADDR);
+        self.op(OpCode::MLOAD);
+        self.op(OpCode::dup(1));
+        self.push(SP_ADDR);
+        self.op(OpCode::MSTORE);
+        self.op(OpCode::MLOAD);
+        self.push(FP_ADDR);
+        self.op(OpCode::MSTORE);
+    }
+
+    /// Lowers `insn`, and returns `true` if the following insn is also covered.
+    fn lower_insn(&mut self, insn: Insn) -> Result<bool, EvmCodegenError> {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let block = func.layout.insn_block(insn);
+
+        match dfg.insn_data(insn) {
+            InsnData::Unary { code, args } => {
+                let ty = dfg.value_ty(args[0]);
+                self.load(args[0]);
+                match code {
+                    UnaryOp::Not => self.op(OpCode::NOT),
+                    UnaryOp::Neg => {
+                        self.push(0);
+                        self.op(OpCode::SUB);
+                    }
+                    UnaryOp::Clz => self.clz(ty),
+                    UnaryOp::Ctz => self.ctz(ty),
+                    UnaryOp::Popcount => self.popcount(),
+                    UnaryOp::Bswap => self.bswap(ty),
+                }
+                self.mask(ty);
+            }
+
+            InsnData::Binary { code, args } => self.lower_binary(*code, *args),
+
+            InsnData::OverflowBinary { code, args } => {
+                self.lower_overflow_binary(insn, *code, *args)
+            }
+
+            InsnData::Cast { code, args, ty } => {
+                let from = dfg.value_ty(args[0]);
+                self.load(args[0]);
+                match code {
+                    CastOp::Sext => {
+                        self.sign_extend(from);
+                        self.mask(*ty);
+                    }
+                    CastOp::Trunc => self.mask(*ty),
+                    CastOp::Zext | CastOp::BitCast => {}
+                }
+            }
+
+            InsnData::Load { args, loc } => {
+                self.load(args[0]);
+                match loc {
+                    DataLocationKind::Memory => {
+                        let ty = dfg.insn_result_ty(insn).unwrap();
+                        let size = layout::size_of(&self.module.ctx, ty);
+                        self.op(OpCode::MLOAD);
+                        if size < WORD_SIZE {
+                            self.push((WORD_SIZE - size) * 8);
+                            self.op(OpCode::SHR);
+                        }
+                    }
+                    DataLocationKind::Storage => self.op(OpCode::SLOAD),
+                }
+            }
+
+            InsnData::Store { args, loc } => {
+                let [addr, data] = *args;
+                match loc {
+                    DataLocationKind::Memory => {
+                        let ty = dfg.value_ty(data);
+                        self.store_memory(addr, data, layout::size_of(&self.module.ctx, ty));
+                    }
+                    DataLocationKind::Storage => {
+                        self.load(data);
+                        self.load(addr);
+                        self.op(OpCode::SSTORE);
+                    }
+                }
+            }
+
+            InsnData::Mem { code, args, loc } => self.lower_mem(*code, *args, *loc),
+
+            InsnData::Call {
+                func: callee,
+                args,
+                ret_ty,
+                is_tail,
+            } => {
+                // Calls to external functions jump to their stubs.
+                let callee_label = self.symbols.func_labels[callee];
+                let call_conv = self.module.funcs[*callee].sig.call_conv();
+
+                if *is_tail
+                    && call_conv.allows_tail_call()
+                    && !self.has_escaping_alloca
+                    && self.is_followed_by_return(insn)
+                {
+                    // Reuse the return address of the current function, and let the callee
+                    // allocate its frame in place of the current one. This is only sound if
+                    // nothing the callee can reach points into the current frame.
+                    for &arg in args {
+                        self.load(arg);
+                    }
+                    self.epilogue();
+                    self.asm.push_label(callee_label);
+                    self.op(OpCode::JUMP);
+                    return Ok(true);
+                }
+
+                let ret_label = self.asm.make_label();
+                self.asm.push_label(ret_label);
+                for &arg in args {
+                    self.load(arg);
+                }
+                self.asm.push_label(callee_label);
+                self.op(OpCode::JUMP);
+                self.asm.jump_dest(ret_label);
+                if *ret_ty == Type::Void {
+                    return Ok(false);
+                }
+            }
+
+            InsnData::Intrinsic {
+                intrinsic, args, ..
+            } => {
+                let name = self.module.ctx.isa.with_intrinsics(|intrinsics| {
+                    intrinsics.intrinsic_data(*intrinsic).name.clone()
+                });
+                let Some(opcode) = intrinsic_opcode(&name) else {
+                    return Err(EvmCodegenError::UnsupportedIntrinsic {
+                        func: self.func.sig.name().to_string(),
+                        intrinsic: name,
+                    });
+                };
+                for &arg in args.iter().rev() {
+                    self.load(arg);
+                }
+                self.op(opcode);
+            }
+
+            InsnData::Jump { dests } => {
+                self.copy_phi_args(block, dests[0]);
+                self.jump_unless_next(block, dests[0]);
+            }
+
+            InsnData::Branch { args, dests } => {
+                let [then, else_] = *dests;
+                self.load(args[0]);
+                // If `then` follows, the condition is inverted so that `then` is reached by
+                // falling through, unless `else` needs an edge block to copy phi arguments,
+                // which would come in between.
+                let next = func.layout.next_block_of(block);
+                if next == Some(then) && then != else_ && !self.has_phis(else_) {
+                    self.op(OpCode::ISZERO);
+                    self.asm.push_label(self.block_labels[&else_]);
+                    self.op(OpCode::JUMPI);
+                    self.copy_phi_args(block, then);
+                    self.jump_unless_next(block, then);
+                } else {
+                    let label = self.edge_label(block, then);
+                    self.asm.push_label(label);
+                    self.op(OpCode::JUMPI);
+                    self.copy_phi_args(block, else_);
+                    self.jump_unless_next(block, else_);
+                }
+            }
+
+            InsnData::BrTable {
+                args,
+                default,
+                table,
+            } => {
+                for (value, dest) in args[1..].iter().zip(table) {
+                    self.load(*value);
+                    self.load(args[0]);
+                    self.op(OpCode::EQ);
+                    let label = self.edge_label(block, *dest);
+                    self.asm.push_label(label);
+                    self.op(OpCode::JUMPI);
+                }
+
+                match default {
+                    Some(dest) => {
+                        self.copy_phi_args(block, *dest);
+                        self.jump_unless_next(block, *dest);
+                    }
+                    None => self.op(OpCode::INVALID),
+                }
+            }
+
+            InsnData::Alloca { .. } => {
+                let offset = self.allocas[&insn];
+                self.push_frame_addr(offset);
+            }
+
+            InsnData::Return { args } => {
+                if let Some(arg) = *args {
+                    self.load(arg);
+                }
+                self.epilogue();
+                if args.is_some() {
+                    self.op(OpCode::swap(1));
+                }
+                self.op(OpCode::JUMP);
+            }
+
+            InsnData::Revert { selector, args } => {
+                for &arg in args.iter().rev() {
+                    self.load(arg);
+                }
+                self.revert(*selector, args.len());
+            }
+
+            InsnData::Trap { reason } => {
+                self.push(reason.panic_code());
+                self.revert(abi::PANIC_SELECTOR, 1);
+            }
+
+            InsnData::Unreachable => self.op(OpCode::INVALID),
+
+            InsnData::Gep { args } => self.lower_gep(args),
+
+            // Phi arguments are copied on edges.
+            InsnData::Phi { .. } => return Ok(false),
+        }
+
+        // Results other than the last one are stored while the insn is lowered.
+        if let Some(&result) = dfg.insn_results(insn).last() {
+            if dfg.value_ty(result) != Type::Void {
+                self.store_top(result);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reverts with `selector` followed by the `arg_num` words on top of the stack, the first one
+    /// on top. The data is written from address zero, which overwrites the frame pointer, so the
+    /// args must be on the stack already.
+    fn revert(&mut self, selector: [u8; 4], arg_num: usize) {
+        self.push(u32::from_be_bytes(selector));
+        self.push(224);
+        self.op(OpCode::SHL);
+        self.push(0);
+        self.op(OpCode::MSTORE);
+        for idx in 0..arg_num {
+            self.push(4 + idx * WORD_SIZE);
+            self.op(OpCode::MSTORE);
+        }
+        self.push(4 + arg_num * WORD_SIZE);
+        self.push(0);
+        self.op(OpCode::REVERT);
+    }
+
+    fn lower_binary(&mut self, code: BinaryOp, args: [Value; 2]) {
+        let [lhs, rhs] = args;
+        let ty = self.func.dfg.value_ty(lhs);
+
+        let (op, negate) = match code {
+            BinaryOp::Shl | BinaryOp::Shr => {
+                self.load(lhs);
+                self.load(rhs);
+                let op = if code == BinaryOp::Shl {
+                    OpCode::SHL
+                } else {
+                    OpCode::SHR
+                };
+                self.op(op);
+                self.mask(ty);
+                return;
+            }
+
+            BinaryOp::Rotl | BinaryOp::Rotr => {
+                self.rotate(code == BinaryOp::Rotl, lhs, rhs, ty);
+                return;
+            }
+
+            BinaryOp::Sar => {
+                self.load(lhs);
+                self.sign_extend(ty);
+                self.load(rhs);
+                self.op(OpCode::SAR);
+                self.mask(ty);
+                return;
+            }
+
+            BinaryOp::Sdiv | BinaryOp::Slt | BinaryOp::Sgt | BinaryOp::Sle | BinaryOp::Sge => {
+                self.load(rhs);
+                self.sign_extend(ty);
+                self.load(lhs);
+                self.sign_extend(ty);
+                match code {
+                    BinaryOp::Sdiv => {
+                        self.op(OpCode::SDIV);
+                        self.mask(ty);
+                    }
+                    BinaryOp::Slt => self.op(OpCode::SLT),
+                    BinaryOp::Sgt => self.op(OpCode::SGT),
+                    BinaryOp::Sle => {
+                        self.op(OpCode::SGT);
+                        self.op(OpCode::ISZERO);
+                    }
+                    _ => {
+                        self.op(OpCode::SLT);
+                        self.op(OpCode::ISZERO);
+                    }
+                }
+                return;
+            }
+
+            BinaryOp::Add => (OpCode::ADD, false),
+            BinaryOp::Sub => (OpCode::SUB, false),
+            BinaryOp::Mul => (OpCode::MUL, false),
+            BinaryOp::Udiv => (OpCode::DIV, false),
+            BinaryOp::Lt => (OpCode::LT, false),
+            BinaryOp::Gt => (OpCode::GT, false),
+            BinaryOp::Le => (OpCode::GT, true),
+            BinaryOp::Ge => (OpCode::LT, true),
+            BinaryOp::Eq => (OpCode::EQ, false),
+            BinaryOp::Ne => (OpCode::EQ, true),
+            BinaryOp::And => (OpCode::AND, false),
+            BinaryOp::Or => (OpCode::OR, false),
+            BinaryOp::Xor => (OpCode::XOR, false),
+        };
+
+        // The first operand of EVM binary opcodes is the top of the stack.
+        self.load(rhs);
+        self.load(lhs);
+        self.op(op);
+        if negate {
+            self.op(OpCode::ISZERO);
+        }
+        if matches!(code, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul) {
+            self.mask(ty);
+        }
+    }
+
+    /// Stores the wrapped result computed by the unchecked op, and leaves the overflow flag on top
+    /// of the stack. The flag is computed from the operands and the stored result.
+    fn lower_overflow_binary(&mut self, insn: Insn, code: OverflowOp, args: [Value; 2]) {
+        let [lhs, rhs] = args;
+        let ty = self.func.dfg.value_ty(lhs);
+        let bits = layout::bit_width(ty);
+        let result = self.func.dfg.insn_results(insn)[0];
+
+        self.lower_binary(code.unchecked(), args);
+        self.store_top(result);
+
+        match code {
+            // The sum wrapped if it's less than an operand.
+            OverflowOp::Uaddo => {
+                self.load(lhs);
+                self.load(result);
+                self.op(OpCode::LT);
+            }
+
+            OverflowOp::Usubo => {
+                self.load(rhs);
+                self.load(lhs);
+                self.op(OpCode::LT);
+            }
+
+            // The sign bit of `(res ^ lhs) & (res ^ rhs)` for additions, and of
+            // `(lhs ^ rhs) & (lhs ^ res)` for subtractions.
+            OverflowOp::Saddo | OverflowOp::Ssubo => {
+                self.load(result);
+                self.load(lhs);
+                self.op(OpCode::XOR);
+                if code == OverflowOp::Saddo {
+                    self.load(result);
+                } else {
+                    self.load(lhs);
+                }
+                self.load(rhs);
+                self.op(OpCode::XOR);
+                self.op(OpCode::AND);
+                self.push(bits - 1);
+                self.op(OpCode::SHR);
+            }
+
+            // The product wrapped if dividing it by a non-zero `lhs` doesn't give `rhs` back.
+            OverflowOp::Umulo | OverflowOp::Smulo => {
+                let signed = code == OverflowOp::Smulo;
+                for value in [rhs, lhs, result] {
+                    self.load(value);
+                    if signed {
+                        self.sign_extend(ty);
+                    }
+                }
+                self.op(if signed { OpCode::SDIV } else { OpCode::DIV });
+                self.op(OpCode::EQ);
+                self.op(OpCode::ISZERO);
+                self.load(lhs);
+                self.op(OpCode::ISZERO);
+                self.op(OpCode::ISZERO);
+                self.op(OpCode::AND);
+
+                // `SDIV` can't tell `-1 * MIN` from `MIN`.
+                if signed {
+                    self.load(lhs);
+                    self.push(layout::low_mask(bits));
+                    self.op(OpCode::EQ);
+                    self.load(rhs);
+                    self.push(U256::one() << (bits - 1));
+                    self.op(OpCode::EQ);
+                    self.op(OpCode::AND);
+                    self.op(OpCode::OR);
+                }
+            }
+        }
+    }
+
+    fn lower_gep(&mut self, args: &[Value]) {
+        let (module, func) = (self.module, self.func);
+        let ctx = &module.ctx;
+        let dfg = &func.dfg;
+
+        let base_ty = dfg.value_ty(args[0]);
+        let mut ty = ctx.with_ty_store(|s| s.deref(base_ty)).unwrap();
+        let mut offset = 0;
+
+        self.load(args[0]);
+        for &idx in &args[1..] {
+            match dfg.value_data(idx) {
+                ValueData::Immediate { imm, .. } => {
+                    let (field_offset, field_ty) = layout::field_offset(ctx, ty, imm.as_usize());
+                    offset += field_offset;
+                    ty = field_ty;
+                }
+
+                _ => {
+                    // Only arrays can be indexed dynamically.
+                    let (elem_size, elem_ty) = layout::field_offset(ctx, ty, 1);
+                    self.load(idx);
+                    self.push(elem_size);
+                    self.op(OpCode::MUL);
+                    self.op(OpCode::ADD);
+                    ty = elem_ty;
+                }
+            }
+        }
+
+        if offset != 0 {
+            self.push(offset);
+            self.op(OpCode::ADD);
+        }
+    }
+
+    fn store_memory(&mut self, addr: Value, data: Value, size: usize) {
+        match size {
+            WORD_SIZE => {
+                self.load(data);
+                self.load(addr);
+                self.op(OpCode::MSTORE);
+            }
+
+            1 => {
+                self.load(data);
+                self.load(addr);
+                self.op(OpCode::MSTORE8);
+            }
+
+            _ => {
+                // Merge the data into the word at `addr` to keep the following bytes intact.
+                let shift = (WORD_SIZE - size) * 8;
+                self.load(addr);
+                self.op(OpCode::MLOAD);
+                self.push(!(layout::low_mask(size * 8) << shift));
+                self.op(OpCode::AND);
+                self.load(data);
+                self.push(shift);
+                self.op(OpCode::SHL);
+                self.op(OpCode::OR);
+                self.load(addr);
+                self.op(OpCode::MSTORE);
+            }
+        }
+    }
+
+    /// Lowers a memory intrinsic, whose `args` are `[dst, src, len]`, or `[dst, byte, len]` for
+    /// `memset`. Short constant lengths are unrolled into word or slot accesses.
+    fn lower_mem(&mut self, code: MemOp, args: [Value; 3], loc: DataLocationKind) {
+        let [dst, src, len] = args;
+        let unroll_limit = match loc {
+            DataLocationKind::Memory => UNROLL_LIMIT * WORD_SIZE,
+            DataLocationKind::Storage => UNROLL_LIMIT,
+        };
+        let const_len = self
+            .func
+            .dfg
+            .value_imm(len)
+            .map(|imm| imm.as_unsigned())
+            .filter(|len| *len <= U256::from(unroll_limit))
+            .map(|len| len.as_usize());
+
+        match (code, loc) {
+            (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Memory) => {
+                if let Some(len) = const_len {
+                    self.copy_memory_unrolled(dst, src, len);
+                } else if self.module.ctx.isa.triple().has_feature(Feature::Mcopy) {
+                    self.load(len);
+                    self.load(src);
+                    self.load(dst);
+                    self.op(OpCode::MCOPY);
+                } else {
+                    // The identity precompile reads its whole input before it writes the output,
+                    // so overlapping ranges are copied correctly.
+                    self.load(len);
+                    self.load(dst);
+                    self.load(len);
+                    self.load(src);
+                    self.push(IDENTITY_ADDR);
+                    self.op(OpCode::GAS);
+                    self.op(OpCode::STATICCALL);
+                    self.op(OpCode::POP);
+                }
+            }
+
+            (MemOp::Memset, DataLocationKind::Memory) => match const_len {
+                Some(len) => {
+                    for offset in (0..len).step_by(WORD_SIZE) {
+                        self.splat_byte(src);
+                        self.store_memory_word(dst, offset, len - offset);
+                    }
+                }
+                None => self.counted_loop(len, false, |this| {
+                    this.load(src);
+                    this.op(OpCode::dup(2));
+                    this.load(dst);
+                    this.op(OpCode::ADD);
+                    this.op(OpCode::MSTORE8);
+                }),
+            },
+
+            (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Storage) => match const_len {
+                Some(len) => {
+                    // All slots are loaded before any is stored, so overlapping ranges are copied
+                    // correctly.
+                    for idx in 0..len {
+                        self.push_addr(src, idx);
+                        self.op(OpCode::SLOAD);
+                    }
+                    for idx in (0..len).rev() {
+                        self.push_addr(dst, idx);
+                        self.op(OpCode::SSTORE);
+                    }
+                }
+                None if code == MemOp::Memcpy => self.copy_storage(dst, src, len, false),
+                None => {
+                    // Copy backward if `dst` is above `src`, so that no slot is overwritten
+                    // before it's read.
+                    let backward = self.asm.make_label();
+                    let done = self.asm.make_label();
+                    self.load(src);
+                    self.load(dst);
+                    self.op(OpCode::GT);
+                    self.asm.push_label(backward);
+                    self.op(OpCode::JUMPI);
+                    self.copy_storage(dst, src, len, false);
+                    self.asm.push_label(done);
+                    self.op(OpCode::JUMP);
+                    self.asm.jump_dest(backward);
+                    self.copy_storage(dst, src, len, true);
+                    self.asm.jump_dest(done);
+                }
+            },
+
+            (MemOp::Memset, DataLocationKind::Storage) => match const_len {
+                Some(len) => {
+                    for idx in 0..len {
+                        self.splat_byte(src);
+                        self.push_addr(dst, idx);
+                        self.op(OpCode::SSTORE);
+                    }
+                }
+                None => self.counted_loop(len, false, |this| {
+                    this.splat_byte(src);
+                    this.load(dst);
+                    this.op(OpCode::dup(3));
+                    this.op(OpCode::ADD);
+                    this.op(OpCode::SSTORE);
+                }),
+            },
+        }
+    }
+
+    /// Copies `len` bytes of memory from `src` to `dst` word by word. All words are loaded before
+    /// any is stored, so overlapping ranges are copied correctly.
+    fn copy_memory_unrolled(&mut self, dst: Value, src: Value, len: usize) {
+        let offsets: SmallVec<[usize; UNROLL_LIMIT]> = (0..len).step_by(WORD_SIZE).collect();
+        for &offset in &offsets {
+            self.push_addr(src, offset);
+            self.op(OpCode::MLOAD);
+        }
+        for &offset in offsets.iter().rev() {
+            self.store_memory_word(dst, offset, len - offset);
+        }
+    }
+
+    /// Stores the word on top of the stack to `dst + offset`. If fewer than a word of `size`
+    /// bytes is left, only the leading bytes are stored, and the following bytes are kept.
+    fn store_memory_word(&mut self, dst: Value, offset: usize, size: usize) {
+        if size < WORD_SIZE {
+            let keep = layout::low_mask((WORD_SIZE - size) * 8);
+            self.push(!keep);
+            self.op(OpCode::AND);
+            self.push_addr(dst, offset);
+            self.op(OpCode::MLOAD);
+            self.push(keep);
+            self.op(OpCode::AND);
+            self.op(OpCode::OR);
+        }
+        self.push_addr(dst, offset);
+        self.op(OpCode::MSTORE);
+    }
+
+    /// Pushes a word whose bytes are all the `i8` `byte`.
+    fn splat_byte(&mut self, byte: Value) {
+        self.load(byte);
+        self.push(layout::splat(U256::one(), 8));
+        self.op(OpCode::MUL);
+    }
+
+    /// Pushes `base + offset`.
+    fn push_addr(&mut self, base: Value, offset: usize) {
+        self.load(base);
+        if offset != 0 {
+            self.push(offset);
+            self.op(OpCode::ADD);
+        }
+    }
+
+    /// Copies `len` storage slots from `src` to `dst`.
+    fn copy_storage(&mut self, dst: Value, src: Value, len: Value, backward: bool) {
+        self.counted_loop(len, backward, |this| {
+            this.load(src);
+            this.op(OpCode::dup(2));
+            this.op(OpCode::ADD);
+            this.op(OpCode::SLOAD);
+            this.load(dst);
+            this.op(OpCode::dup(3));
+            this.op(OpCode::ADD);
+            this.op(OpCode::SSTORE);
+        });
+    }
+
+    /// Emits a loop that runs `body` for each index below `len`, in increasing order or in
+    /// decreasing order if `backward` is set. The index is on top of the stack when `body` starts,
+    /// and `body` must leave the stack as it found it.
+    fn counted_loop(&mut self, len: Value, backward: bool, body: impl FnOnce(&mut Self)) {
+        let head = self.asm.make_label();
+        let end = self.asm.make_label();
+
+        if backward {
+            self.load(len);
+            self.asm.jump_dest(head);
+            self.op(OpCode::dup(1));
+            self.op(OpCode::ISZERO);
+            self.asm.push_label(end);
+            self.op(OpCode::JUMPI);
+            self.push(1);
+            self.op(OpCode::swap(1));
+            self.op(OpCode::SUB);
+            body(self);
+        } else {
+            self.push(0);
+            self.asm.jump_dest(head);
+            self.load(len);
+            self.op(OpCode::dup(2));
+            self.op(OpCode::LT);
+            self.op(OpCode::ISZERO);
+            self.asm.push_label(end);
+            self.op(OpCode::JUMPI);
+            body(self);
+            self.push(1);
+            self.op(OpCode::ADD);
+        }
+        self.asm.push_label(head);
+        self.op(OpCode::JUMP);
+        self.asm.jump_dest(end);
+        self.op(OpCode::POP);
+    }
+
+    /// Copies phi arguments for the edge `from` -> `to` into the phi slots in parallel.
+    fn copy_phi_args(&mut self, from: Block, to: Block) {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let copies: SmallVec<[(Value, Value); 4]> = func
+            .layout
+            .iter_insn(to)
+            .take_while(|insn| dfg.is_phi(*insn))
+            .filter_map(|insn| {
+                let InsnData::Phi { values, blocks, .. } = dfg.insn_data(insn) else {
+                    unreachable!();
+                };
+                let idx = blocks.iter().position(|block| *block == from)?;
+                Some((dfg.insn_result(insn).unwrap(), values[idx]))
+            })
+            .collect();
+
+        for (_, arg) in &copies {
+            self.load(*arg);
+        }
+        for (phi, _) in copies.iter().rev() {
+            self.store_top(*phi);
+        }
+    }
+
+    /// Returns the label to jump to for the edge `from` -> `to`. If `to` has phis, the label
+    /// refers to a jump destination that copies the phi arguments first.
+    fn edge_label(&mut self, from: Block, to: Block) -> Label {
+        if !self.has_phis(to) {
+            return self.block_labels[&to];
+        }
+
+        let label = self.asm.make_label();
+        self.edge_blocks.push((label, from, to));
+        label
+    }
+
+    fn has_phis(&self, block: Block) -> bool {
+        self.func
+            .layout
+            .first_insn_of(block)
+            .is_some_and(|insn| self.func.dfg.is_phi(insn))
+    }
+
+    fn jump_to(&mut self, dest: Block) {
+        self.asm.push_label(self.block_labels[&dest]);
+        self.op(OpCode::JUMP);
+    }
+
+    /// Jumps from the end of `from` to `dest`, unless `dest` follows `from` in the layout and no
+    /// edge block comes in between, in which case the code falls through.
+    fn jump_unless_next(&mut self, from: Block, dest: Block) {
+        if self.edge_blocks.is_empty() && self.func.layout.next_block_of(from) == Some(dest) {
+            return;
+        }
+        self.jump_to(dest);
+    }
+
+    fn is_followed_by_return(&self, call: Insn) -> bool {
+        let Some(next) = self.func.layout.next_insn_of(call) else {
+            return false;
+        };
+        match self.func.dfg.insn_data(next) {
+            InsnData::Return { args } => *args == self.func.dfg.insn_result(call),
+            _ => false,
+        }
+    }
+
+    /// Pushes `value`.
+    fn load(&mut self, value: Value) {
+        let func = self.func;
+        match func.dfg.value_data(value) {
+            ValueData::Immediate { imm, ty } => {
+                let word = imm.as_i256().to_u256() & layout::low_mask(layout::bit_width(*ty));
+                if let Some(&addr) = self.symbols.const_addrs.get(&word) {
+                    self.push(addr);
+                    self.op(OpCode::MLOAD);
+                } else {
+                    self.push(word);
+                }
+            }
+            ValueData::Global { gv, .. } => self.push(self.symbols.global_addrs[gv]),
+            ValueData::Arg { .. } | ValueData::Insn { .. } => {
+                self.push_frame_addr(self.slots[&value]);
+                self.op(OpCode::MLOAD);
+            }
+        }
+    }
+
+    /// Pops the top of the stack into the slot of `value`.
+    fn store_top(&mut self, value: Value) {
+        self.push_frame_addr(self.slots[&value]);
+        self.op(OpCode::MSTORE);
+    }
+
+    fn push_frame_addr(&mut self, offset: usize) {
+        self.push(FP_ADDR);
+        self.op(OpCode::MLOAD);
+        if offset != 0 {
+            self.push(offset);
+            self.op(OpCode::ADD);
+        }
+    }
+
+    /// Sign-extends the top of the stack from the width of `ty` to 256 bits.
+    fn sign_extend(&mut self, ty: Type) {
+        match layout::bit_width(ty) {
+            256 => {}
+            1 => {
+                self.push(0);
+                self.op(OpCode::SUB);
+            }
+            bits => {
+                self.push(bits / 8 - 1);
+                self.op(OpCode::SIGNEXTEND);
+            }
+        }
+    }
+
+    /// Rotates `lhs` by `rhs` modulo the width of `ty` with two shifts:
+    /// `(x << a) | (x >> (bits - a))` for a left rotate. A shift by `bits` leaves nothing of a
+    /// masked value, so rotating by zero needs no special case.
+    fn rotate(&mut self, left: bool, lhs: Value, rhs: Value, ty: Type) {
+        let bits = layout::bit_width(ty);
+        let (op, rev_op) = if left {
+            (OpCode::SHL, OpCode::SHR)
+        } else {
+            (OpCode::SHR, OpCode::SHL)
+        };
+        self.load(rhs);
+        self.push(bits - 1);
+        self.op(OpCode::AND);
+        self.load(lhs);
+        self.op(OpCode::dup(1));
+        self.op(OpCode::dup(3));
+        self.op(op);
+        // `[x << a, x, a]`, with `a` on top.
+        self.op(OpCode::swap(2));
+        self.push(bits);
+        self.op(OpCode::SUB);
+        self.op(rev_op);
+        self.op(OpCode::OR);
+        self.mask(ty);
+    }
+
+    /// Counts the leading zeros of the top of the stack in the width of `ty`.
+    fn clz(&mut self, ty: Type) {
+        // Smearing the highest one to the right leaves a zero for each leading zero.
+        let bits = layout::bit_width(ty);
+        let mut shift = 1;
+        while shift < bits {
+            self.op(OpCode::dup(1));
+            self.push(shift);
+            self.op(OpCode::SHR);
+            self.op(OpCode::OR);
+            shift *= 2;
+        }
+        self.popcount();
+        self.push(bits);
+        self.op(OpCode::SUB);
+    }
+
+    /// Counts the trailing zeros of the top of the stack in the width of `ty`.
+    fn ctz(&mut self, ty: Type) {
+        // `(x & -x) - 1` has a one for each trailing zero, and all ones if `x` is zero.
+        self.op(OpCode::dup(1));
+        self.push(0);
+        self.op(OpCode::SUB);
+        self.op(OpCode::AND);
+        self.push(1);
+        self.op(OpCode::swap(1));
+        self.op(OpCode::SUB);
+        self.mask(ty);
+        self.popcount();
+    }
+
+    /// Counts the ones of the top of the stack by summing up lanes of increasing width, which
+    /// takes a fixed number of steps since the EVM has no such opcode.
+    fn popcount(&mut self) {
+        // `x - ((x >> 1) & 0x55..55)` holds the count of each 2-bit lane.
+        self.op(OpCode::dup(1));
+        self.push(1);
+        self.op(OpCode::SHR);
+        self.push(layout::splat(U256::from(0x5u8), 4));
+        self.op(OpCode::AND);
+        self.op(OpCode::swap(1));
+        self.op(OpCode::SUB);
+
+        // `(x & 0x33..33) + ((x >> 2) & 0x33..33)` for 4-bit lanes.
+        self.op(OpCode::dup(1));
+        self.push(2);
+        self.op(OpCode::SHR);
+        self.push(layout::splat(U256::from(0x3u8), 4));
+        self.op(OpCode::AND);
+        self.op(OpCode::swap(1));
+        self.push(layout::splat(U256::from(0x3u8), 4));
+        self.op(OpCode::AND);
+        self.op(OpCode::ADD);
+
+        // `(x + (x >> n)) & mask` for 8-bit and 16-bit lanes. The count of 256 ones doesn't fit in
+        // 8 bits.
+        for lane_bits in [8, 16] {
+            self.op(OpCode::dup(1));
+            self.push(lane_bits / 2);
+            self.op(OpCode::SHR);
+            self.op(OpCode::ADD);
+            self.push(layout::splat(layout::low_mask(lane_bits / 2), lane_bits));
+            self.op(OpCode::AND);
+        }
+
+        // Multiplying by `0x0001..0001` sums up the lanes into the top one.
+        self.push(layout::splat(U256::one(), 16));
+        self.op(OpCode::MUL);
+        self.push(240);
+        self.op(OpCode::SHR);
+    }
+
+    /// Reverses the bytes of the top of the stack in the width of `ty`.
+    fn bswap(&mut self, ty: Type) {
+        // Swap adjacent lanes of 8 bits, then of 16 bits, and so on:
+        // `((x >> n) & mask) | ((x & mask) << n)`.
+        let bits = layout::bit_width(ty);
+        let mut shift = 8;
+        while shift < bits {
+            let mask = layout::splat(layout::low_mask(shift), shift * 2) & layout::low_mask(bits);
+            self.op(OpCode::dup(1));
+            self.push(shift);
+            self.op(OpCode::SHR);
+            self.push(mask);
+            self.op(OpCode::AND);
+            self.op(OpCode::swap(1));
+            self.push(mask);
+            self.op(OpCode::AND);
+            self.push(shift);
+            self.op(OpCode::SHL);
+            self.op(OpCode::OR);
+            shift *= 2;
+        }
+    }
+
+    /// Truncates the top of the stack to the width of `ty`.
+    fn mask(&mut self, ty: Type) {
+        let bits = layout::bit_width(ty);
+        if bits < 256 {
+            self.push(layout::low_mask(bits));
+            self.op(OpCode::AND);
+        }
+    }
+
+    fn push(&mut self, imm: impl Into<U256>) {
+        self.asm.push(imm);
+    }
+
+    fn op(&mut self, op: OpCode) {
+        self.asm.op(op);
+    }
+}
@@ -0,0 +1,221 @@
+//! This module contains the metadata that can be appended to runtime code, in the format solc
+//! uses, so that tools which look for it, e.g., source verifiers, find it.
+//!
+//! The metadata is a CBOR map from text keys to byte or text strings, followed by its length as
+//! two big-endian bytes, e.g., `{"ipfs": h'1220...', "sonatina": "0.0.3-alpha"}`. The default
+//! payload only names the compiler and its version; builds that must be reproducible across
+//! compiler versions can drop or replace entries, or not append metadata at all.
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+/// The largest file whose IPFS hash is computed, i.e., the size of a single IPFS chunk.
+const IPFS_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    entries: Vec<(String, MetadataValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataValue {
+    Bytes(Vec<u8>),
+    Text(String),
+}
+
+impl Default for Metadata {
+    /// Returns the metadata naming the compiler and its version.
+    fn default() -> Self {
+        Self::empty().with_entry(
+            "sonatina",
+            MetadataValue::Text(env!("CARGO_PKG_VERSION").into()),
+        )
+    }
+}
+
+impl Metadata {
+    /// Returns metadata without entries.
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the entry of `key` to `value`. A new entry is added after the existing ones.
+    pub fn with_entry(mut self, key: &str, value: MetadataValue) -> Self {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+        self
+    }
+
+    /// Removes the entry of `key`, if any.
+    pub fn without_entry(mut self, key: &str) -> Self {
+        self.entries.retain(|(k, _)| k != key);
+        self
+    }
+
+    /// Sets the `ipfs` entry to the hash of `input`, see [`ipfs_hash`]. The entry is put first,
+    /// as solc does. Inputs larger than an IPFS chunk leave the metadata unchanged.
+    pub fn with_ipfs_hash_of(mut self, input: &[u8]) -> Self {
+        if let Some(hash) = ipfs_hash(input) {
+            self = self.without_entry("ipfs");
+            self.entries
+                .insert(0, ("ipfs".to_string(), MetadataValue::Bytes(hash)));
+        }
+        self
+    }
+
+    pub fn entries(&self) -> &[(String, MetadataValue)] {
+        &self.entries
+    }
+
+    /// Returns the CBOR encoding of the metadata followed by its length. Fails if the encoding is
+    /// too long for its length to fit in two bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, MetadataTooLargeError> {
+        let mut cbor = Vec::new();
+        write_head(&mut cbor, MAJOR_MAP, self.entries.len());
+        for (key, value) in &self.entries {
+            write_head(&mut cbor, MAJOR_TEXT, key.len());
+            cbor.extend_from_slice(key.as_bytes());
+            match value {
+                MetadataValue::Bytes(bytes) => {
+                    write_head(&mut cbor, MAJOR_BYTES, bytes.len());
+                    cbor.extend_from_slice(bytes);
+                }
+                MetadataValue::Text(text) => {
+                    write_head(&mut cbor, MAJOR_TEXT, text.len());
+                    cbor.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+
+        let len = u16::try_from(cbor.len()).map_err(|_| MetadataTooLargeError(cbor.len()))?;
+        cbor.extend_from_slice(&len.to_be_bytes());
+        Ok(cbor)
+    }
+}
+
+/// The CBOR encoding of metadata is longer than [`u16::MAX`] bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataTooLargeError(pub usize);
+
+impl fmt::Display for MetadataTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "metadata of {} bytes exceeds the limit of {} bytes",
+            self.0,
+            u16::MAX
+        )
+    }
+}
+
+impl std::error::Error for MetadataTooLargeError {}
+
+/// Returns the IPFS hash of a file with `content`, i.e., the sha2-256 multihash of the file as a
+/// single UnixFS node, or `None` if the file doesn't fit in a single chunk.
+pub fn ipfs_hash(content: &[u8]) -> Option<Vec<u8>> {
+    if content.len() > IPFS_CHUNK_SIZE {
+        return None;
+    }
+
+    // The UnixFS `Data` message: the type `File`, the content and the file size.
+    let mut unixfs = vec![0x08, 0x02];
+    if !content.is_empty() {
+        unixfs.push(0x12);
+        write_varint(&mut unixfs, content.len());
+        unixfs.extend_from_slice(content);
+    }
+    unixfs.push(0x18);
+    write_varint(&mut unixfs, content.len());
+
+    // The `PBNode` that only has the `Data` field.
+    let mut node = vec![0x0a];
+    write_varint(&mut node, unixfs.len());
+    node.extend_from_slice(&unixfs);
+
+    let mut hash = vec![0x12, 0x20];
+    hash.extend_from_slice(&Sha256::digest(node));
+    Some(hash)
+}
+
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_MAP: u8 = 5;
+
+/// Writes the head of a CBOR data item of `major` type with the argument `arg`.
+fn write_head(buf: &mut Vec<u8>, major: u8, arg: usize) {
+    let major = major << 5;
+    match arg {
+        0..=23 => buf.push(major | arg as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, arg as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(arg as u16).to_be_bytes());
+        }
+        _ => match u32::try_from(arg) {
+            Ok(arg) => {
+                buf.push(major | 26);
+                buf.extend_from_slice(&arg.to_be_bytes());
+            }
+            Err(_) => {
+                buf.push(major | 27);
+                buf.extend_from_slice(&(arg as u64).to_be_bytes());
+            }
+        },
+    }
+}
+
+/// Writes `value` as a protobuf varint.
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let metadata = Metadata::empty()
+            .with_entry("solc", MetadataValue::Bytes(vec![0, 8, 26]))
+            .with_ipfs_hash_of(b"");
+        let encoded = metadata.encode().unwrap();
+
+        let mut expected = vec![0xa2, 0x64, b'i', b'p', b'f', b's', 0x58, 0x22];
+        expected.extend_from_slice(&ipfs_hash(b"").unwrap());
+        expected.extend_from_slice(&[0x64, b's', b'o', b'l', b'c', 0x43, 0, 8, 26]);
+        expected.extend_from_slice(&[0x00, 0x33]);
+        assert_eq!(encoded, expected);
+
+        let default = Metadata::default().encode().unwrap();
+        assert_eq!(default[0], 0xa1);
+        assert_eq!(
+            default.len() - 2,
+            u16::from_be_bytes([default[default.len() - 2], default[default.len() - 1]]) as usize
+        );
+    }
+
+    #[test]
+    fn reject_oversized() {
+        let metadata = Metadata::empty().with_entry("big", MetadataValue::Bytes(vec![0; 0x10000]));
+        // The map, the key and the head of the value take 1 + 4 + 5 bytes.
+        assert_eq!(metadata.encode(), Err(MetadataTooLargeError(0x10000 + 10)));
+
+        let metadata = Metadata::empty().with_entry("big", MetadataValue::Bytes(vec![0; 0xff00]));
+        assert_eq!(metadata.encode().unwrap().len(), 0xff00 + 8 + 2);
+    }
+
+    #[test]
+    fn ipfs_hash_of_empty_file() {
+        // The CIDv0 `QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH`.
+        let hash = ipfs_hash(b"").unwrap();
+        assert_eq!(hash[..6], [0x12, 0x20, 0xbf, 0xcc, 0xda, 0x78][..],);
+    }
+}
@@ -0,0 +1,1521 @@
+//! This module contains the EVM backend, which compiles a module into a contract.
+//!
+//! Functions with the [`SolidityExternal`](CallConv::SolidityExternal) calling convention, which
+//! public functions have by default, are the external functions of the contract, dispatched by the
+//! selector of their [canonical signatures](abi::canonical_signature). A selector that matches
+//! none of them runs the [`Fallback`](CallConv::Fallback) function if there is one, and reverts
+//! otherwise. Memory doesn't outlive a call, so static initializers run at the start of every
+//! call, before dispatching. A contract with few external functions compares the selector with
+//! each of them in turn; a larger one looks up a bucket of candidates in a jump table first, see
+//! [`crate::switch_cluster`].
+//!
+//! A module that defines [contracts](sonatina_ir::contract) is compiled into one artifact per
+//! contract by [`compile_contracts`], with the entries of each contract as its external functions.
+//!
+//! Memory is laid out as follows.
+//!
+//! ```text
+//! 0x00 | the frame pointer
+//! 0x20 | the stack pointer, i.e., the end of the innermost frame
+//! 0x40 | global variables, initialized from the data section of the runtime code
+//! ...  | the constant pool, initialized along with global variables
+//! ...  | frames
+//! ```
+//!
+//! External functions are library functions: a call to one goes through a stub that ABI-encodes
+//! the arguments and `DELEGATECALL`s the [library](ExternalSymbol::Library) named after the
+//! function, whose address is patched in by [`ContractArtifact::link`]. An external function with
+//! the [`Contract`](CallConv::Contract) convention is called by `CALL` instead, so it runs on the
+//! storage of its own contract, whose address is linked the same way.
+//!
+//! Runtime code that exceeds [`verify::MAX_CODE_SIZE`] fails to compile, unless
+//! [`CompileOptions::split_code`] is set, in which case cold functions are moved to a companion
+//! contract, see [`split`].
+//!
+//! The triple's features control the encoding of immediates: with
+//! [`Push0`](sonatina_triple::Feature::Push0), zero is pushed with `PUSH0`, and with
+//! [`ConstPool`](sonatina_triple::Feature::ConstPool), large constants that are used often enough
+//! are loaded from the constant pool with `MLOAD` instead of being pushed at every use. With
+//! [`Mcopy`](sonatina_triple::Feature::Mcopy), memory is copied with `MCOPY` instead of a call to
+//! the identity precompile.
+use std::fmt;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use sonatina_ir::{
+    module::FuncRef, static_init::InitError, CallConv, GlobalVariable, InsnData, Module, Signature,
+    Type, ValueData, U256,
+};
+use sonatina_triple::{EvmVersion, Feature, Version};
+
+use crate::{
+    debug_info::{encode_source_map, DebugInfo, DebugInfoBuilder, JumpKind, SourceMapEntry},
+    reloc::{self, Reloc, RelocError},
+    switch_cluster::{self, CaseClusters},
+};
+
+use self::{
+    asm::{push_size, AsmItem, Assembled, Assembly, ExternalSymbol, LABEL_DATA_SIZE},
+    asm_writer::AsmWriter,
+    layout::WORD_SIZE,
+    lower::{FuncLowering, ModuleSymbols},
+    metadata::{Metadata, MetadataTooLargeError},
+    opcode::OpCode,
+    split::CodeSplit,
+    stack_depth::{StackDepth, StackWarning},
+    verify::{VerifyError, MAX_CODE_SIZE},
+};
+
+pub mod abi;
+pub mod asm;
+pub mod asm_writer;
+pub mod layout;
+mod lower;
+pub mod metadata;
+pub mod opcode;
+pub mod split;
+pub mod stack_depth;
+pub mod verify;
+pub mod yul;
+
+pub const FP_ADDR: usize = 0x00;
+pub const SP_ADDR: usize = 0x20;
+pub const GLOBAL_BASE: usize = 0x40;
+
+/// The average number of selectors in a bucket of the dispatch table.
+const DISPATCH_BUCKET_LEN: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct ContractArtifact {
+    /// The code that deploys `runtime`.
+    pub deploy: Vec<u8>,
+    pub runtime: Vec<u8>,
+    /// `runtime` as textual assembly, see [`asm_writer`].
+    pub asm: String,
+    pub abi: String,
+    /// Maps offsets in `runtime` to source locations.
+    pub debug_info: DebugInfo,
+    /// The same locations as `debug_info` as a Solidity source map of `runtime`.
+    pub source_map: String,
+    /// Call chains that may overflow the operand stack at runtime.
+    pub stack_warnings: Vec<StackWarning>,
+    /// References in `runtime` to external symbols, which are yet to be linked.
+    pub relocs: Vec<Reloc<ExternalSymbol>>,
+    /// The functions moved to a companion contract, if the runtime code was split.
+    pub split: Option<Box<CodeSplit>>,
+}
+
+impl ContractArtifact {
+    /// Patches the value of each external symbol that `resolve` knows into the runtime code,
+    /// along with the copy of it in the deploy code. The relocations of the other symbols are
+    /// kept.
+    pub fn link(
+        &mut self,
+        resolve: impl FnMut(&ExternalSymbol) -> Option<U256>,
+    ) -> Result<(), RelocError<ExternalSymbol>> {
+        let relocs = std::mem::take(&mut self.relocs);
+        self.relocs = reloc::apply(&mut self.runtime, relocs, resolve)?;
+        let start = self.deploy.len() - self.runtime.len();
+        self.deploy[start..].copy_from_slice(&self.runtime);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvmCodegenError {
+    /// The module targets an ISA other than the EVM.
+    UnsupportedTarget(String),
+
+    /// The EVM version lacks opcodes the backend relies on, e.g., `SHL` and `SHR`.
+    UnsupportedVersion(EvmVersion),
+
+    /// A function calls an external function that can't be called from the output, e.g., because
+    /// its arguments or result have no ABI representation, or because Yul can't link it.
+    ExternalCall { caller: String, callee: String },
+
+    /// A function uses an intrinsic that has no opcode, e.g., one registered by a frontend.
+    UnsupportedIntrinsic { func: String, intrinsic: String },
+
+    /// A public function has a type that the dispatcher can't decode, i.e., other than an
+    /// integer.
+    NonAbiSignature(String),
+
+    /// A fallback takes arguments or returns a value, or isn't the only fallback of the contract.
+    InvalidFallback(String),
+
+    /// An entry of a contract is not defined in the module.
+    InvalidContractEntry { contract: String, func: String },
+
+    /// Static initializers depend on each other in a cycle.
+    StaticInitCycle(Vec<String>),
+
+    /// A static initializer is not defined in the module, takes arguments or returns a value.
+    InvalidStaticInit(String),
+
+    /// The emitted code fails verification, see [`verify`].
+    InvalidBytecode(Vec<VerifyError>),
+
+    /// The metadata to append is too long to encode, see [`Metadata::encode`].
+    MetadataTooLarge(MetadataTooLargeError),
+}
+
+impl fmt::Display for EvmCodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedTarget(triple) => {
+                write!(f, "target `{triple}` is not an EVM target")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "EVM version `{version}` is not supported")
+            }
+            Self::ExternalCall { caller, callee } => {
+                write!(f, "`{caller}` calls external function `{callee}`")
+            }
+            Self::UnsupportedIntrinsic { func, intrinsic } => {
+                write!(
+                    f,
+                    "`{func}` uses intrinsic `{intrinsic}`, which has no opcode"
+                )
+            }
+            Self::NonAbiSignature(func) => write!(
+                f,
+                "public function `{func}` has a type the dispatcher can't decode"
+            ),
+            Self::InvalidFallback(func) => write!(
+                f,
+                "fallback `{func}` must be the only one, and take and return nothing"
+            ),
+            Self::InvalidContractEntry { contract, func } => write!(
+                f,
+                "entry `{func}` of contract `{contract}` must be defined in the module"
+            ),
+            Self::StaticInitCycle(cycle) => write!(
+                f,
+                "static initializers depend on each other: `{}`",
+                cycle.join("` -> `")
+            ),
+            Self::InvalidStaticInit(func) => write!(
+                f,
+                "static initializer `{func}` must be defined in the module, take no arguments and \
+                 return nothing"
+            ),
+            Self::InvalidBytecode(errors) => {
+                write!(f, "emitted code is invalid")?;
+                for err in errors {
+                    write!(f, "\n  {err}")?;
+                }
+                Ok(())
+            }
+            Self::MetadataTooLarge(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for EvmCodegenError {}
+
+/// Options of the compilation that don't depend on the target.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// The metadata appended to the runtime code. None is appended by default, so that the code
+    /// only depends on the module.
+    pub metadata: Option<Metadata>,
+    /// Whether runtime code that exceeds [`verify::MAX_CODE_SIZE`] is split into the contract and
+    /// a companion contract, see [`split`].
+    pub split_code: bool,
+}
+
+/// Compiles all functions in `module` into a contract.
+pub fn compile(module: &Module) -> Result<ContractArtifact, EvmCodegenError> {
+    compile_with(module, &CompileOptions::default())
+}
+
+/// Compiles all functions in `module` into a contract with `options`.
+pub fn compile_with(
+    module: &Module,
+    options: &CompileOptions,
+) -> Result<ContractArtifact, EvmCodegenError> {
+    let _span = tracing::info_span!("evm_compile").entered();
+    check_version(module)?;
+    let funcs = defined_funcs(module);
+    let entries = external_entries(module, &funcs)?;
+    let fallback = fallback_func(module, &funcs)?;
+    compile_funcs(module, &funcs, &entries, fallback, options)
+}
+
+/// Compiles each contract defined in `module` into its own artifact, which only contains the
+/// functions reachable from the entries of the contract and the static initializers. Returns the
+/// artifacts along with the names of the contracts, in the order the contracts are defined.
+pub fn compile_contracts(
+    module: &Module,
+) -> Result<Vec<(String, ContractArtifact)>, EvmCodegenError> {
+    compile_contracts_with(module, &CompileOptions::default())
+}
+
+/// Compiles each contract defined in `module` into its own artifact with `options`, see
+/// [`compile_contracts`].
+pub fn compile_contracts_with(
+    module: &Module,
+    options: &CompileOptions,
+) -> Result<Vec<(String, ContractArtifact)>, EvmCodegenError> {
+    check_version(module)?;
+    let defined = defined_funcs(module);
+    let inits = static_inits(module, &defined)?;
+
+    let mut artifacts = Vec::new();
+    for contract in module.contracts.iter() {
+        let _span = tracing::info_span!("evm_compile", contract = contract.name.as_str()).entered();
+        if let Some(entry) = contract.entries.iter().find(|f| !defined.contains(f)) {
+            return Err(EvmCodegenError::InvalidContractEntry {
+                contract: contract.name.clone(),
+                func: module.funcs[*entry].sig.name().to_string(),
+            });
+        }
+
+        let roots: Vec<_> = contract.entries.iter().chain(&inits).copied().collect();
+        let funcs = reachable_funcs(module, &roots, &FxHashMap::default());
+        let fallback = fallback_func(module, &contract.entries)?;
+        let selected: Vec<_> = contract
+            .entries
+            .iter()
+            .copied()
+            .filter(|entry| Some(*entry) != fallback)
+            .collect();
+        let entries = entry_selectors(module, &selected)?;
+        artifacts.push((
+            contract.name.clone(),
+            compile_funcs(module, &funcs, &entries, fallback, options)?,
+        ));
+    }
+    Ok(artifacts)
+}
+
+fn check_version(module: &Module) -> Result<(), EvmCodegenError> {
+    let triple = module.ctx.isa.triple();
+    let Version::EvmVersion(version) = triple.version else {
+        return Err(EvmCodegenError::UnsupportedTarget(triple.to_string()));
+    };
+    if matches!(
+        version,
+        EvmVersion::Frontier | EvmVersion::Homestead | EvmVersion::Byzantium
+    ) {
+        return Err(EvmCodegenError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+/// Compiles `funcs` into a contract whose external functions are `entries`, and which calls
+/// `fallback`, if any, for other selectors.
+fn compile_funcs(
+    module: &Module,
+    funcs: &[FuncRef],
+    entries: &[(FuncRef, [u8; 4])],
+    fallback: Option<FuncRef>,
+    options: &CompileOptions,
+) -> Result<ContractArtifact, EvmCodegenError> {
+    let inits = static_inits(module, funcs)?;
+    let dispatched = Dispatched { entries, fallback };
+    let mut runtime = emit_runtime(module, funcs, &dispatched, &inits, &FxHashMap::default())?;
+    let mut split = None;
+    if options.split_code && runtime.assembled.code.len() > MAX_CODE_SIZE {
+        (runtime, split) = split::split(module, funcs, &dispatched, &inits, runtime, options)?;
+    }
+
+    let Runtime {
+        asm,
+        assembled: mut runtime,
+        symbols,
+        stack_warnings,
+    } = runtime;
+    let mut debug_info = DebugInfoBuilder::new();
+    for (item, &offset) in asm.items().iter().zip(&runtime.offsets) {
+        match item {
+            AsmItem::FuncStart(func_ref) => {
+                debug_info.begin_function(*func_ref, &module.funcs[*func_ref], offset)
+            }
+            AsmItem::FuncEnd(_) => debug_info.end_function(offset),
+            AsmItem::InsnStart(func_ref, insn) => {
+                debug_info.record_insn(&module.funcs[*func_ref], *insn, offset)
+            }
+            _ => {}
+        }
+    }
+
+    let asm_text = AsmWriter::new(module, &asm, &runtime)
+        .dump_string()
+        .unwrap();
+    let source_map = encode_source_map(&source_map_entries(module, &asm, &symbols));
+    if let Some(metadata) = &options.metadata {
+        let metadata = metadata
+            .encode()
+            .map_err(EvmCodegenError::MetadataTooLarge)?;
+        runtime.code.extend_from_slice(&metadata);
+    }
+    verify_runtime(&asm, &runtime.code, &runtime.offsets)?;
+    let push0 = module.ctx.isa.triple().has_feature(Feature::Push0);
+    Ok(ContractArtifact {
+        deploy: deploy_code(&runtime.code, push0),
+        asm: asm_text,
+        source_map,
+        abi: abi::abi_json(
+            &module.ctx,
+            dispatched.funcs().map(|func_ref| &module.funcs[func_ref]),
+        ),
+        runtime: runtime.code,
+        relocs: runtime.relocs,
+        debug_info: debug_info.finish(&module.ctx),
+        stack_warnings,
+        split: split.map(Box::new),
+    })
+}
+
+/// The functions the dispatcher of a contract calls.
+#[derive(Clone, Copy)]
+struct Dispatched<'a> {
+    /// The external functions along with their selectors.
+    entries: &'a [(FuncRef, [u8; 4])],
+    /// The function that is called for selectors of no external function.
+    fallback: Option<FuncRef>,
+}
+
+impl Dispatched<'_> {
+    /// Returns the external functions followed by the fallback.
+    fn funcs(&self) -> impl Iterator<Item = FuncRef> + '_ {
+        self.entries
+            .iter()
+            .map(|(func_ref, _)| *func_ref)
+            .chain(self.fallback)
+    }
+}
+
+/// The assembled runtime code of a contract, before it's finished into an artifact.
+struct Runtime {
+    asm: Assembly,
+    assembled: Assembled,
+    symbols: ModuleSymbols,
+    stack_warnings: Vec<StackWarning>,
+}
+
+/// Lowers `funcs` and the dispatcher of `dispatched` and assembles them. The functions in
+/// `delegated` are replaced with stubs that call the function with the given selector in the
+/// companion contract of a split.
+fn emit_runtime(
+    module: &Module,
+    funcs: &[FuncRef],
+    dispatched: &Dispatched,
+    inits: &[FuncRef],
+    delegated: &FxHashMap<FuncRef, [u8; 4]>,
+) -> Result<Runtime, EvmCodegenError> {
+    let triple = module.ctx.isa.triple();
+    let push0 = triple.has_feature(Feature::Push0);
+
+    let mut asm = Assembly::new().with_push0(push0);
+    let (global_addrs, mut global_data) = layout_globals(module, GLOBAL_BASE);
+    let const_addrs = if triple.has_feature(Feature::ConstPool) {
+        let lowered: Vec<_> = funcs
+            .iter()
+            .copied()
+            .filter(|func_ref| !delegated.contains_key(func_ref))
+            .collect();
+        layout_const_pool(module, &lowered, &mut global_data)
+    } else {
+        FxHashMap::default()
+    };
+    let externals = external_callees(module, funcs, delegated)?;
+    let symbols = ModuleSymbols {
+        func_labels: funcs
+            .iter()
+            .chain(externals.iter().map(|(func_ref, _)| func_ref))
+            .map(|func_ref| (*func_ref, asm.make_label()))
+            .collect(),
+        global_addrs,
+        const_addrs,
+    };
+
+    let data_label = asm.make_label();
+    let frame_base = GLOBAL_BASE + global_data.len().div_ceil(WORD_SIZE) * WORD_SIZE;
+    let dispatch_table = emit_dispatcher(
+        &mut asm,
+        module,
+        &symbols,
+        dispatched,
+        inits,
+        frame_base,
+        data_label,
+        &global_data,
+    );
+    // Cold functions are laid out after the others so that the hot code stays together.
+    let mut layout = funcs.to_vec();
+    layout.sort_by_key(|func_ref| module.funcs[*func_ref].sig.attrs().cold);
+    for func_ref in layout {
+        if let Some(&selector) = delegated.get(&func_ref) {
+            let label = symbols.func_labels[&func_ref];
+            let companion = ExternalSymbol::Library(split::COMPANION_SYMBOL.to_string());
+            let call = OpCode::DELEGATECALL;
+            split::emit_call_stub(&mut asm, module, label, func_ref, selector, companion, call);
+            continue;
+        }
+        let func = &module.funcs[func_ref];
+        let _span = tracing::debug_span!("lower", func = func.sig.name()).entered();
+        FuncLowering::new(module, func_ref, &symbols, &mut asm).lower()?;
+    }
+    for (func_ref, selector) in externals {
+        let label = symbols.func_labels[&func_ref];
+        let sig = &module.funcs[func_ref].sig;
+        let target = ExternalSymbol::Library(sig.name().to_string());
+        let call = if sig.call_conv() == CallConv::Contract {
+            OpCode::CALL
+        } else {
+            OpCode::DELEGATECALL
+        };
+        split::emit_call_stub(&mut asm, module, label, func_ref, selector, target, call);
+    }
+
+    let roots: Vec<_> = dispatched.funcs().chain(inits.iter().copied()).collect();
+    let stack_warnings =
+        StackDepth::analyze(module, &asm, &symbols.func_labels).warnings(module, &roots);
+    for warning in &stack_warnings {
+        tracing::warn!("{warning}");
+    }
+    asm.push_item(AsmItem::Mark(data_label));
+    asm.push_item(AsmItem::Data(global_data));
+    for item in dispatch_table {
+        asm.push_item(item);
+    }
+
+    let assembled = asm.assemble();
+    tracing::debug!(
+        asm_items = asm.items().len(),
+        runtime_size = assembled.code.len(),
+        "assembled runtime code"
+    );
+    Ok(Runtime {
+        asm,
+        assembled,
+        symbols,
+        stack_warnings,
+    })
+}
+
+/// Verifies the runtime code assembled from `asm`. The code runs from its start, and from each
+/// bucket of the dispatch table with the selector on the stack.
+fn verify_runtime(asm: &Assembly, code: &[u8], offsets: &[u32]) -> Result<(), EvmCodegenError> {
+    let items = asm.items();
+    let mut code_len = code.len();
+    let mut label_offsets = FxHashMap::default();
+    for (item, &offset) in items.iter().zip(offsets) {
+        match item {
+            AsmItem::JumpDest(label) => {
+                label_offsets.insert(*label, offset as usize);
+            }
+            AsmItem::Data(_) => code_len = code_len.min(offset as usize),
+            _ => {}
+        }
+    }
+
+    let mut roots = vec![(0, 0)];
+    for item in items {
+        if let AsmItem::LabelData(label) = item {
+            roots.push((label_offsets[label], 1));
+        }
+    }
+
+    let errors = verify::verify(code, code_len, &roots);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(EvmCodegenError::InvalidBytecode(errors))
+    }
+}
+
+/// Returns the source map entry of each instruction in `asm`. A `JUMP` to a function is a jump
+/// into it, and any other `JUMP` whose target isn't pushed right before it in a function is a
+/// return.
+fn source_map_entries(
+    module: &Module,
+    asm: &Assembly,
+    symbols: &ModuleSymbols,
+) -> Vec<SourceMapEntry> {
+    let func_labels: FxHashSet<_> = symbols.func_labels.values().copied().collect();
+    let mut entries = Vec::new();
+    let mut loc = None;
+    let mut in_func = false;
+    let mut prev: Option<&AsmItem> = None;
+    for item in asm.items() {
+        let jump = match item {
+            AsmItem::FuncStart(_) | AsmItem::FuncEnd(_) => {
+                in_func = matches!(item, AsmItem::FuncStart(_));
+                loc = None;
+                continue;
+            }
+            AsmItem::InsnStart(func_ref, insn) => {
+                loc = module.funcs[*func_ref].dfg.insn_loc(*insn);
+                continue;
+            }
+            AsmItem::Mark(_) | AsmItem::Data(_) | AsmItem::LabelData(_) => continue,
+
+            AsmItem::Op(op) if *op == OpCode::JUMP => match prev {
+                Some(AsmItem::PushLabel(label)) if func_labels.contains(label) => JumpKind::Into,
+                Some(AsmItem::PushLabel(_)) => JumpKind::Regular,
+                _ if in_func => JumpKind::OutOf,
+                _ => JumpKind::Regular,
+            },
+            AsmItem::Op(_)
+            | AsmItem::Push(_)
+            | AsmItem::PushLabel(_)
+            | AsmItem::PushExternal(_)
+            | AsmItem::JumpDest(_) => JumpKind::Regular,
+        };
+        entries.push(SourceMapEntry { loc, jump });
+        prev = Some(item);
+    }
+    entries
+}
+
+/// Returns the functions that are defined in `module`.
+fn defined_funcs(module: &Module) -> Vec<FuncRef> {
+    module
+        .iter_functions()
+        .filter(|func_ref| module.funcs[*func_ref].layout.entry_block().is_some())
+        .collect()
+}
+
+/// Returns the functions in `funcs` along with the functions they call, directly or indirectly,
+/// in the order they are declared. The callees of functions in `delegated` aren't followed, since
+/// they run in the companion contract of a split.
+fn reachable_funcs(
+    module: &Module,
+    funcs: &[FuncRef],
+    delegated: &FxHashMap<FuncRef, [u8; 4]>,
+) -> Vec<FuncRef> {
+    let mut reachable = FxHashSet::default();
+    let mut worklist = funcs.to_vec();
+    while let Some(func_ref) = worklist.pop() {
+        if !reachable.insert(func_ref) || delegated.contains_key(&func_ref) {
+            continue;
+        }
+        let func = &module.funcs[func_ref];
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                if let InsnData::Call { func: callee, .. } = func.dfg.insn_data(insn) {
+                    worklist.push(*callee);
+                }
+            }
+        }
+    }
+
+    defined_funcs(module)
+        .into_iter()
+        .filter(|func_ref| reachable.contains(func_ref))
+        .collect()
+}
+
+/// Returns the external functions that `funcs` call, along with their selectors, in the order
+/// they are declared. The functions in `delegated` aren't lowered, so their calls are ignored.
+fn external_callees(
+    module: &Module,
+    funcs: &[FuncRef],
+    delegated: &FxHashMap<FuncRef, [u8; 4]>,
+) -> Result<Vec<(FuncRef, [u8; 4])>, EvmCodegenError> {
+    let mut callees = FxHashMap::default();
+    for &func_ref in funcs {
+        if delegated.contains_key(&func_ref) {
+            continue;
+        }
+        let func = &module.funcs[func_ref];
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                let InsnData::Call { func: callee, .. } = func.dfg.insn_data(insn) else {
+                    continue;
+                };
+                if !module.is_external(*callee) || callees.contains_key(callee) {
+                    continue;
+                }
+                let sig = &module.funcs[*callee].sig;
+                let selector = abi::function_selector(&module.ctx, sig)
+                    .filter(|_| decodes_signature(sig))
+                    .ok_or_else(|| EvmCodegenError::ExternalCall {
+                        caller: func.sig.name().to_string(),
+                        callee: sig.name().to_string(),
+                    })?;
+                callees.insert(*callee, selector);
+            }
+        }
+    }
+
+    let mut callees: Vec<_> = callees.into_iter().collect();
+    callees.sort_unstable_by_key(|(func_ref, _)| *func_ref);
+    Ok(callees)
+}
+
+/// Returns the functions in `funcs` with the [`CallConv::SolidityExternal`] convention along
+/// with their selectors.
+fn external_entries(
+    module: &Module,
+    funcs: &[FuncRef],
+) -> Result<Vec<(FuncRef, [u8; 4])>, EvmCodegenError> {
+    let external: Vec<_> = funcs
+        .iter()
+        .copied()
+        .filter(|func_ref| module.funcs[*func_ref].sig.call_conv() == CallConv::SolidityExternal)
+        .collect();
+    entry_selectors(module, &external)
+}
+
+/// Returns the function in `funcs` with the [`CallConv::Fallback`] convention, if any. There must
+/// be at most one, which takes no arguments and returns nothing.
+fn fallback_func(module: &Module, funcs: &[FuncRef]) -> Result<Option<FuncRef>, EvmCodegenError> {
+    let mut fallbacks = funcs
+        .iter()
+        .copied()
+        .filter(|func_ref| module.funcs[*func_ref].sig.call_conv() == CallConv::Fallback);
+    let Some(fallback) = fallbacks.next() else {
+        return Ok(None);
+    };
+
+    let sig = &module.funcs[fallback].sig;
+    if !sig.args().is_empty() || sig.ret_ty() != Type::Void {
+        return Err(EvmCodegenError::InvalidFallback(sig.name().to_string()));
+    }
+    if let Some(other) = fallbacks.next() {
+        let other = module.funcs[other].sig.name().to_string();
+        return Err(EvmCodegenError::InvalidFallback(other));
+    }
+    Ok(Some(fallback))
+}
+
+/// Returns `entries` along with their selectors.
+fn entry_selectors(
+    module: &Module,
+    entries: &[FuncRef],
+) -> Result<Vec<(FuncRef, [u8; 4])>, EvmCodegenError> {
+    let mut selectors = Vec::with_capacity(entries.len());
+    for &func_ref in entries {
+        let sig = &module.funcs[func_ref].sig;
+        let selector = abi::function_selector(&module.ctx, sig)
+            .filter(|_| decodes_signature(sig))
+            .ok_or_else(|| EvmCodegenError::NonAbiSignature(sig.name().to_string()))?;
+        selectors.push((func_ref, selector));
+    }
+    Ok(selectors)
+}
+
+/// Returns whether the dispatcher can decode the arguments of `sig` and encode its result, i.e.,
+/// they are integers.
+fn decodes_signature(sig: &Signature) -> bool {
+    sig.args().iter().all(Type::is_integral)
+        && (sig.ret_ty() == Type::Void || sig.ret_ty().is_integral())
+}
+
+/// Returns the static initializers of `module` in the order they run. They must be in `funcs`.
+fn static_inits(module: &Module, funcs: &[FuncRef]) -> Result<Vec<FuncRef>, EvmCodegenError> {
+    let name = |func_ref: FuncRef| module.funcs[func_ref].sig.name().to_string();
+    let inits = module.static_init_order().map_err(|err| match err {
+        InitError::Cycle(cycle) => {
+            EvmCodegenError::StaticInitCycle(cycle.into_iter().map(name).collect())
+        }
+        InitError::InvalidSignature(func_ref) => EvmCodegenError::InvalidStaticInit(name(func_ref)),
+    })?;
+    if let Some(&init) = inits.iter().find(|init| !funcs.contains(init)) {
+        return Err(EvmCodegenError::InvalidStaticInit(name(init)));
+    }
+    Ok(inits)
+}
+
+/// Assigns addresses from `base` on to global variables, and returns them along with the initial
+/// image of the global region.
+fn layout_globals(module: &Module, base: usize) -> (FxHashMap<GlobalVariable, usize>, Vec<u8>) {
+    let ctx = &module.ctx;
+    let gvs: Vec<_> = ctx.with_gv_store(|s| {
+        s.all_gvs()
+            .map(|gv| (gv, s.ty(gv), s.init_data(gv).cloned()))
+            .collect()
+    });
+
+    let mut addrs = FxHashMap::default();
+    let mut data = Vec::new();
+    for (gv, ty, init) in gvs {
+        // Each global starts at a word boundary.
+        data.resize(data.len().div_ceil(WORD_SIZE) * WORD_SIZE, 0);
+        addrs.insert(gv, base + data.len());
+
+        let size = layout::size_of(ctx, ty);
+        match init {
+            Some(init) => layout::serialize_const(ctx, ty, &init, &mut data),
+            None => data.resize(data.len() + size, 0),
+        }
+    }
+
+    (addrs, data)
+}
+
+/// Appends the constant pool to the initial image of the global region in `data`, and returns
+/// the address of each pooled constant.
+///
+/// A constant is pooled if loading it at each use, i.e., `PUSH2 addr MLOAD`, together with the
+/// word it takes in the data section is smaller than pushing it at each use.
+fn layout_const_pool(
+    module: &Module,
+    funcs: &[FuncRef],
+    data: &mut Vec<u8>,
+) -> FxHashMap<U256, usize> {
+    const LOAD_SIZE: usize = 4;
+
+    let mut uses = FxHashMap::<U256, usize>::default();
+    for &func_ref in funcs {
+        let func = &module.funcs[func_ref];
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                let insn_data = func.dfg.insn_data(insn);
+                // The immediate indices of a gep are folded into its offset.
+                if matches!(insn_data, InsnData::Gep { .. }) {
+                    continue;
+                }
+                for &arg in insn_data.args() {
+                    if let ValueData::Immediate { imm, ty } = func.dfg.value_data(arg) {
+                        let word =
+                            imm.as_i256().to_u256() & layout::low_mask(layout::bit_width(*ty));
+                        *uses.entry(word).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut pooled: Vec<_> = uses
+        .into_iter()
+        .filter(|(imm, use_num)| {
+            let push_len = 1 + push_size(*imm, false);
+            use_num * push_len > use_num * LOAD_SIZE + WORD_SIZE
+        })
+        .map(|(imm, _)| imm)
+        .collect();
+    pooled.sort_unstable();
+
+    data.resize(data.len().div_ceil(WORD_SIZE) * WORD_SIZE, 0);
+    let mut addrs = FxHashMap::default();
+    for imm in pooled {
+        addrs.insert(imm, GLOBAL_BASE + data.len());
+        data.extend_from_slice(&asm::be_bytes(imm));
+    }
+    addrs
+}
+
+/// Emits the entry of the runtime code, which initializes memory, runs the static initializers
+/// in `inits` and dispatches the call to the external function matching the selector in calldata,
+/// or to the fallback if none matches.
+#[allow(clippy::too_many_arguments)]
+fn emit_dispatcher(
+    asm: &mut Assembly,
+    module: &Module,
+    symbols: &ModuleSymbols,
+    dispatched: &Dispatched,
+    inits: &[FuncRef],
+    frame_base: usize,
+    data_label: asm::Label,
+    global_data: &[u8],
+) -> Vec<AsmItem> {
+    asm.push(frame_base);
+    asm.push(SP_ADDR);
+    asm.op(OpCode::MSTORE);
+
+    if !global_data.is_empty() {
+        asm.push(global_data.len());
+        asm.push_label(data_label);
+        asm.push(GLOBAL_BASE);
+        asm.op(OpCode::CODECOPY);
+    }
+
+    for init in inits {
+        let ret_label = asm.make_label();
+        asm.push_label(ret_label);
+        asm.push_label(symbols.func_labels[init]);
+        asm.op(OpCode::JUMP);
+        asm.jump_dest(ret_label);
+    }
+
+    asm.push(0);
+    asm.op(OpCode::CALLDATALOAD);
+    asm.push(224);
+    asm.op(OpCode::SHR);
+
+    let entries = dispatched.entries;
+    let stubs: Vec<_> = entries.iter().map(|_| asm.make_label()).collect();
+    let costs = module.ctx.isa.cost_table();
+    let linear = switch_cluster::prefers_linear_search(costs, entries.len(), DISPATCH_BUCKET_LEN);
+    let dispatch_table = if linear {
+        for ((_, selector), stub) in entries.iter().zip(&stubs) {
+            emit_selector_test(asm, *selector, *stub);
+        }
+        vec![]
+    } else {
+        let no_match = asm.make_label();
+        let table = emit_dispatch_table(asm, entries, &stubs, frame_base, no_match);
+        asm.jump_dest(no_match);
+        table
+    };
+
+    // The selector is still on the stack.
+    match dispatched.fallback {
+        Some(fallback) => {
+            asm.op(OpCode::POP);
+            let ret_label = asm.make_label();
+            asm.push_label(ret_label);
+            asm.push_label(symbols.func_labels[&fallback]);
+            asm.op(OpCode::JUMP);
+            asm.jump_dest(ret_label);
+            asm.op(OpCode::STOP);
+        }
+        None => {
+            asm.push(0);
+            asm.op(OpCode::dup(1));
+            asm.op(OpCode::REVERT);
+        }
+    }
+
+    for ((func_ref, _), stub) in entries.iter().zip(stubs) {
+        let sig = &module.funcs[*func_ref].sig;
+        asm.jump_dest(stub);
+        asm.op(OpCode::POP);
+
+        let ret_label = asm.make_label();
+        asm.push_label(ret_label);
+        for (idx, ty) in sig.args().iter().enumerate() {
+            asm.push(4 + idx * WORD_SIZE);
+            asm.op(OpCode::CALLDATALOAD);
+            let bits = layout::bit_width(*ty);
+            if bits < 256 {
+                asm.push(layout::low_mask(bits));
+                asm.op(OpCode::AND);
+            }
+        }
+        asm.push_label(symbols.func_labels[func_ref]);
+        asm.op(OpCode::JUMP);
+
+        asm.jump_dest(ret_label);
+        if sig.ret_ty() == Type::Void {
+            asm.op(OpCode::STOP);
+        } else {
+            asm.push(0);
+            asm.op(OpCode::MSTORE);
+            asm.push(WORD_SIZE);
+            asm.push(0);
+            asm.op(OpCode::RETURN);
+        }
+    }
+
+    dispatch_table
+}
+
+/// Jumps to `stub` if the selector on top of the stack is `selector`.
+fn emit_selector_test(asm: &mut Assembly, selector: [u8; 4], stub: asm::Label) {
+    asm.op(OpCode::dup(1));
+    asm.push(u32::from_be_bytes(selector));
+    asm.op(OpCode::EQ);
+    asm.push_label(stub);
+    asm.op(OpCode::JUMPI);
+}
+
+/// Emits the lookup of the selector on top of the stack in a table of buckets of selectors, and
+/// returns the table, which must be placed after all code. Each bucket compares the selector with
+/// its selectors in turn. Selectors in no bucket jump to `no_match` with the selector on the
+/// stack.
+fn emit_dispatch_table(
+    asm: &mut Assembly,
+    entries: &[(FuncRef, [u8; 4])],
+    stubs: &[asm::Label],
+    frame_base: usize,
+    no_match: asm::Label,
+) -> Vec<AsmItem> {
+    let selectors: Vec<_> = entries
+        .iter()
+        .map(|(_, selector)| u32::from_be_bytes(*selector) as u64)
+        .collect();
+    let buckets = CaseClusters::analyze(&selectors, 32).buckets(DISPATCH_BUCKET_LEN);
+    let table = asm.make_label();
+
+    // Compute the index of the bucket.
+    asm.op(OpCode::dup(1));
+    if buckets.shift != 0 {
+        asm.push(buckets.shift);
+        asm.op(OpCode::SHR);
+    }
+    if buckets.base != 0 {
+        asm.push(buckets.base);
+        asm.op(OpCode::swap(1));
+        asm.op(OpCode::SUB);
+    }
+    let max_index = (u32::MAX as u64 >> buckets.shift).wrapping_sub(buckets.base);
+    let out_of_range = asm.make_label();
+    let checks_range = buckets.base != 0 || max_index >= buckets.buckets.len() as u64;
+    if checks_range {
+        asm.op(OpCode::dup(1));
+        asm.push(buckets.buckets.len() - 1);
+        asm.op(OpCode::LT);
+        asm.push_label(out_of_range);
+        asm.op(OpCode::JUMPI);
+    }
+
+    // Load the table entry, which is the offset of the bucket, and jump to it. The first frame
+    // isn't set up yet, so its memory serves as scratch space.
+    asm.push(LABEL_DATA_SIZE);
+    asm.op(OpCode::MUL);
+    asm.push_label(table);
+    asm.op(OpCode::ADD);
+    asm.push(LABEL_DATA_SIZE);
+    asm.op(OpCode::swap(1));
+    asm.push(frame_base);
+    asm.op(OpCode::CODECOPY);
+    asm.push(frame_base);
+    asm.op(OpCode::MLOAD);
+    asm.push(256 - LABEL_DATA_SIZE * 8);
+    asm.op(OpCode::SHR);
+    asm.op(OpCode::JUMP);
+
+    let mut table_items = vec![AsmItem::Mark(table)];
+    for bucket in &buckets.buckets {
+        if bucket.is_empty() {
+            table_items.push(AsmItem::LabelData(no_match));
+            continue;
+        }
+
+        let label = asm.make_label();
+        table_items.push(AsmItem::LabelData(label));
+        asm.jump_dest(label);
+        for &key in bucket {
+            let idx = selectors
+                .iter()
+                .position(|selector| *selector == key)
+                .unwrap();
+            emit_selector_test(asm, entries[idx].1, stubs[idx]);
+        }
+        asm.push_label(no_match);
+        asm.op(OpCode::JUMP);
+    }
+
+    // An index out of range is still on the stack, so it's popped to reach `no_match` with the
+    // same stack as the buckets do.
+    if checks_range {
+        asm.jump_dest(out_of_range);
+        asm.op(OpCode::POP);
+        asm.push_label(no_match);
+        asm.op(OpCode::JUMP);
+    }
+    table_items
+}
+
+/// Returns the code that copies `runtime` to memory and returns it.
+fn deploy_code(runtime: &[u8], push0: bool) -> Vec<u8> {
+    let mut asm = Assembly::new().with_push0(push0);
+    let runtime_label = asm.make_label();
+
+    asm.push(runtime.len());
+    asm.op(OpCode::dup(1));
+    asm.push_label(runtime_label);
+    asm.push(0);
+    asm.op(OpCode::CODECOPY);
+    asm.push(0);
+    asm.op(OpCode::RETURN);
+    asm.push_item(AsmItem::Mark(runtime_label));
+    asm.push_item(AsmItem::Data(runtime.to_vec()));
+
+    asm.assemble().code
+}
+
+/// Returns the opcode that implements the intrinsic `name`, see the intrinsics registered by
+/// [`sonatina_ir::isa::evm_eth`]. The operands of the opcode are the args of the intrinsic, with the
+/// first one on top of the stack.
+pub(crate) fn intrinsic_opcode(name: &str) -> Option<OpCode> {
+    Some(match name {
+        "number" => OpCode::NUMBER,
+        "timestamp" => OpCode::TIMESTAMP,
+        "coinbase" => OpCode::COINBASE,
+        "gaslimit" => OpCode::GASLIMIT,
+        "chainid" => OpCode::CHAINID,
+        "basefee" => OpCode::BASEFEE,
+        "balance" => OpCode::BALANCE,
+        "keccak256" => OpCode::KECCAK256,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        insn::TrapReason,
+        isa::IsaBuilder,
+        module::ModuleCtx,
+        Linkage,
+    };
+    use sonatina_triple::TargetTriple;
+
+    /// Walks the code and returns the offsets of `JUMPDEST`s and the targets of `PUSH`es
+    /// followed by a jump.
+    fn jump_dests_and_targets(code: &[u8]) -> (Vec<usize>, Vec<usize>) {
+        let (mut dests, mut targets) = (vec![], vec![]);
+        let mut pc = 0;
+        while pc < code.len() {
+            let op = OpCode(code[pc]);
+            let next = pc + 1 + op.immediate_size();
+            if op == OpCode::JUMPDEST {
+                dests.push(pc);
+            } else if (1..=4).contains(&op.immediate_size())
+                && matches!(code.get(next), Some(&byte) if byte == OpCode::JUMP.0 || byte == OpCode::JUMPI.0)
+            {
+                let target = code[pc + 1..next]
+                    .iter()
+                    .fold(0, |target, byte| target << 8 | *byte as usize);
+                targets.push(target);
+            }
+            pc = next;
+        }
+        (dests, targets)
+    }
+
+    #[test]
+    fn compile_loop() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let n = builder.args()[0];
+        let zero = builder.make_imm_value(0i32);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        let acc = builder.phi(Type::I32, &[(zero, b0)]);
+        let i = builder.phi(Type::I32, &[(zero, b0)]);
+        let acc_next = builder.add(acc, i);
+        let one = builder.make_imm_value(1i32);
+        let i_next = builder.add(i, one);
+        builder.append_phi_arg(acc, acc_next, b1);
+        builder.append_phi_arg(i, i_next, b1);
+        let cond = builder.lt(i_next, n);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(acc_next));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module).unwrap();
+
+        let (dests, targets) = jump_dests_and_targets(&artifact.runtime);
+        assert!(!targets.is_empty());
+        for target in targets {
+            assert!(dests.contains(&target), "{target} is not a `JUMPDEST`");
+        }
+
+        // The deploy code ends with the runtime code.
+        assert!(artifact.deploy.ends_with(&artifact.runtime));
+        assert!(artifact.asm.contains("\n; func public %test_func\n"));
+        assert!(artifact.asm.contains(" = add v2 v3;\n"));
+        // Without source locations, the source map only tells the calls and returns apart.
+        assert!(artifact.source_map.starts_with("-1:-1:-1:-:0;"));
+        assert!(artifact.source_map.contains(";:::o"));
+        assert_eq!(artifact.debug_info.functions.len(), 1);
+        assert!(artifact.debug_info.functions[0]
+            .id
+            .starts_with("test_func#"));
+        assert_eq!(
+            artifact.abi,
+            "[{\"type\":\"function\",\"name\":\"test_func\",\
+             \"inputs\":[{\"name\":\"\",\"type\":\"uint32\"}],\
+             \"outputs\":[{\"name\":\"\",\"type\":\"uint32\"}],\
+             \"stateMutability\":\"nonpayable\"}]"
+        );
+    }
+
+    #[test]
+    fn compile_contracts_with_shared_code() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let double = mb.declare_function(Signature::new(
+            "double",
+            Linkage::Private,
+            &[Type::I32],
+            Type::I32,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(double);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let v = builder.add(arg, arg);
+        builder.ret(Some(v));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let mut entries = vec![];
+        for (name, calls_double) in [("a", true), ("b", false)] {
+            let sig = Signature::new(name, Linkage::Public, &[Type::I32], Type::I32);
+            let func_ref = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(func_ref);
+            let b0 = builder.append_block();
+            builder.switch_to_block(b0);
+            let mut v = builder.args()[0];
+            if calls_double {
+                v = builder.call(double, &[v]).unwrap();
+            }
+            builder.ret(Some(v));
+            builder.seal_all();
+            mb = builder.finish();
+            entries.push(func_ref);
+        }
+        mb.define_contract("A", &entries[..1]);
+        mb.define_contract("B", &entries[1..]);
+
+        let artifacts = compile_contracts(&mb.build()).unwrap();
+        let names: Vec<_> = artifacts
+            .iter()
+            .map(|(name, artifact)| {
+                let funcs: Vec<_> = artifact
+                    .debug_info
+                    .functions
+                    .iter()
+                    .map(|func| func.name.as_str())
+                    .collect();
+                (name.as_str(), funcs)
+            })
+            .collect();
+        assert_eq!(names, vec![("A", vec!["double", "a"]), ("B", vec!["b"])]);
+        assert!(artifacts[1].1.abi.contains("\"name\":\"b\""));
+        assert!(!artifacts[1].1.abi.contains("\"name\":\"a\""));
+    }
+
+    #[test]
+    fn compile_dispatch_table() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let mut selectors = vec![];
+        for i in 0..12 {
+            let name = format!("get{i}");
+            let sig = Signature::new(&name, Linkage::Public, &[Type::I32], Type::I32);
+            selectors.push(u32::from_be_bytes(abi::selector(&format!("{name}(uint32)"))) as u64);
+
+            let func_ref = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(func_ref);
+            let b0 = builder.append_block();
+            builder.switch_to_block(b0);
+            let arg = builder.args()[0];
+            builder.ret(Some(arg));
+            builder.seal_all();
+            mb = builder.finish();
+        }
+
+        let artifact = compile(&mb.build()).unwrap();
+        let buckets = CaseClusters::analyze(&selectors, 32).buckets(DISPATCH_BUCKET_LEN);
+        let table_start = artifact.runtime.len() - buckets.buckets.len() * LABEL_DATA_SIZE;
+        let (dests, targets) = jump_dests_and_targets(&artifact.runtime[..table_start]);
+        for target in targets {
+            assert!(dests.contains(&target), "{target} is not a `JUMPDEST`");
+        }
+        for entry in artifact.runtime[table_start..].chunks(LABEL_DATA_SIZE) {
+            let target = u32::from_be_bytes(entry.try_into().unwrap()) as usize;
+            assert!(dests.contains(&target), "{target} is not a `JUMPDEST`");
+        }
+    }
+
+    #[test]
+    fn compile_with_features() {
+        let triple = TargetTriple::parse("evm-ethereum-shanghai+const-pool").unwrap();
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(IsaBuilder::new(triple).build()));
+        let sig = Signature::new("mix", Linkage::Public, &[Type::I256], Type::I256);
+        let func_ref = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(func_ref);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let big = U256::MAX - U256::from(0x1234);
+        let mut v = builder.args()[0];
+        for _ in 0..3 {
+            let imm = builder.make_imm_value(big);
+            v = builder.xor(v, imm);
+        }
+        builder.ret(Some(v));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let artifact = compile(&mb.build()).unwrap();
+        let code = &artifact.runtime;
+        let word = asm::be_bytes(big);
+        // The constant is only in the pool, which ends the data section.
+        assert_eq!(code.windows(WORD_SIZE).filter(|w| *w == word).count(), 1);
+        assert!(code.ends_with(&word));
+        let (dests, targets) = jump_dests_and_targets(code);
+        for target in targets {
+            assert!(dests.contains(&target), "{target} is not a `JUMPDEST`");
+        }
+        // The deploy code returns the runtime code with `PUSH0 RETURN`.
+        let deploy_len = artifact.deploy.len() - code.len();
+        assert!(artifact.deploy[..deploy_len].ends_with(&[OpCode::PUSH0.0, OpCode::RETURN.0]));
+    }
+
+    #[test]
+    fn compile_with_metadata() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        builder.ret(Some(arg));
+        builder.seal_all();
+        let module = builder.finish().build();
+
+        let metadata = Metadata::default().with_ipfs_hash_of(b"func");
+        let options = CompileOptions {
+            metadata: Some(metadata.clone()),
+            ..Default::default()
+        };
+        let plain = compile(&module).unwrap();
+        let artifact = compile_with(&module, &options).unwrap();
+        assert_eq!(
+            artifact.runtime,
+            [plain.runtime.as_slice(), &metadata.encode().unwrap()].concat()
+        );
+        assert!(artifact.deploy.ends_with(&artifact.runtime));
+    }
+
+    #[test]
+    fn compile_external_call() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let ext = mb.declare_external_function("ext", &[Type::I32], Type::I32);
+        let sig = Signature::new("entry", Linkage::Public, &[Type::I32], Type::I32);
+        let entry = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(entry);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let v = builder.call(ext, &[arg]).unwrap();
+        let v = builder.call(ext, &[v]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        let module = builder.finish().build();
+
+        // Both calls share a stub, which calls the library named after the function.
+        let mut artifact = compile(&module).unwrap();
+        let symbol = ExternalSymbol::Library("ext".to_string());
+        assert_eq!(artifact.relocs.len(), 1);
+        assert_eq!(artifact.relocs[0].symbol, symbol);
+        assert!(artifact.asm.contains("DELEGATECALL"));
+        assert!(!artifact.abi.contains("\"name\":\"ext\""));
+
+        let addr = U256::from(0xc0ffee);
+        artifact.link(|s| (*s == symbol).then_some(addr)).unwrap();
+        assert!(artifact.relocs.is_empty());
+    }
+
+    #[test]
+    fn compile_revert_and_trap() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I256], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let (cond, arg) = (builder.args()[0], builder.args()[1]);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.revert([0xca, 0xfe, 0xba, 0xbe], &[arg]);
+
+        builder.switch_to_block(b2);
+        builder.trap(TrapReason::OutOfBounds);
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module).unwrap();
+        assert!(artifact.asm.contains("revert 0xcafebabe v1;\n"));
+        assert!(artifact.asm.contains("PUSH4 0xcafebabe\n"));
+        assert!(artifact.asm.contains("PUSH4 0x4e487b71\n"));
+        assert!(artifact.asm.contains("PUSH1 0x32\n"));
+        // The selector and a word are returned in both cases.
+        assert_eq!(
+            artifact
+                .asm
+                .matches("PUSH1 0x24\n    PUSH1 0x00\n    REVERT\n")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn compile_unreachable() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let cond = builder.args()[0];
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.ret(None);
+
+        builder.switch_to_block(b2);
+        builder.unreachable();
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module).unwrap();
+        assert!(artifact.asm.contains("    ; unreachable;\n    INVALID\n"));
+    }
+
+    #[test]
+    fn compile_fall_through() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let cond = builder.args()[0];
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.jump(b2);
+
+        builder.switch_to_block(b2);
+        builder.ret(None);
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module).unwrap();
+        // `br` jumps to `block2` unless the condition holds, and both `block0` and `block1` fall
+        // through to the next block.
+        assert!(artifact.asm.contains("    ISZERO\n"));
+        assert!(artifact.asm.contains("    JUMPI\nL"));
+        assert!(artifact.asm.contains("    ; jump block2;\nL"));
+    }
+
+    #[test]
+    fn compile_with_call_convs() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let sig = Signature::new("balance_of", Linkage::External, &[Type::I256], Type::I256)
+            .with_call_conv(CallConv::Contract);
+        let balance_of = mb.declare_function(sig);
+
+        // A public function that isn't an external function of the contract.
+        let sig = Signature::new("helper", Linkage::Public, &[Type::I256], Type::I256)
+            .with_call_conv(CallConv::Internal);
+        let helper = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(helper);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let v = builder.call(balance_of, &[arg]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let sig = Signature::new("entry", Linkage::Public, &[Type::I256], Type::I256);
+        let entry = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(entry);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let v = builder.call(helper, &[arg]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let sig = Signature::new("fallback", Linkage::Private, &[], Type::Void)
+            .with_call_conv(CallConv::Fallback);
+        let fallback = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(fallback);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        builder.ret(None);
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let artifact = compile(&module).unwrap();
+        assert!(artifact.abi.contains("\"name\":\"entry\""));
+        assert!(!artifact.abi.contains("\"name\":\"helper\""));
+        assert!(artifact
+            .abi
+            .contains("{\"type\":\"fallback\",\"stateMutability\":\"nonpayable\"}"));
+        // The contract is called by `CALL`, so it keeps its own storage.
+        assert!(!artifact.asm.contains("DELEGATECALL"));
+        assert!(artifact.asm.contains("CALL"));
+        assert_eq!(
+            artifact.relocs[0].symbol,
+            ExternalSymbol::Library("balance_of".to_string())
+        );
+
+        // A fallback takes no arguments.
+        let sig = Signature::new("fallback", Linkage::Private, &[Type::I32], Type::Void)
+            .with_call_conv(CallConv::Fallback);
+        module.funcs[fallback].sig = sig;
+        assert_eq!(
+            compile(&module).unwrap_err(),
+            EvmCodegenError::InvalidFallback("fallback".to_string())
+        );
+    }
+
+    #[test]
+    fn compile_with_split() {
+        // Two functions that together exceed the size limit, one of which is called twice.
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let mut bigs = vec![];
+        for name in ["big0", "big1"] {
+            let sig = Signature::new(name, Linkage::Private, &[Type::I32], Type::I32);
+            let func_ref = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(func_ref);
+            let b0 = builder.append_block();
+            builder.switch_to_block(b0);
+            let mut v = builder.args()[0];
+            for _ in 0..500 {
+                v = builder.add(v, v);
+            }
+            builder.ret(Some(v));
+            builder.seal_all();
+            mb = builder.finish();
+            bigs.push(func_ref);
+        }
+
+        let sig = Signature::new("entry", Linkage::Public, &[Type::I32], Type::I32);
+        let entry = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(entry);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let mut v = builder.args()[0];
+        for callee in [bigs[0], bigs[0], bigs[1]] {
+            v = builder.call(callee, &[v]).unwrap();
+        }
+        builder.ret(Some(v));
+        builder.seal_all();
+        let module = builder.finish().build();
+
+        let Err(EvmCodegenError::InvalidBytecode(errors)) = compile(&module) else {
+            panic!("the runtime code must be too large");
+        };
+        assert!(matches!(errors[..], [VerifyError::CodeTooLarge(_)]));
+
+        let options = CompileOptions {
+            split_code: true,
+            ..Default::default()
+        };
+        let mut artifact = compile_with(&module, &options).unwrap();
+        assert!(artifact.runtime.len() <= MAX_CODE_SIZE);
+        let code_split = artifact.split.take().unwrap();
+        let moved: Vec<_> = code_split.moved.iter().map(|d| d.func.as_str()).collect();
+        assert_eq!(moved, vec!["big1"]);
+        assert!(code_split.original_size > MAX_CODE_SIZE);
+        assert!(code_split.companion.abi.contains("\"name\":\"big1\""));
+        assert!(code_split.companion.runtime.len() <= MAX_CODE_SIZE);
+
+        let symbol = ExternalSymbol::Library(split::COMPANION_SYMBOL.to_string());
+        assert_eq!(artifact.relocs.len(), 1);
+        assert_eq!(artifact.relocs[0].symbol, symbol);
+        let offset = artifact.relocs[0].offset as usize;
+        let addr = U256::from(0xc0ffee);
+        artifact.link(|s| (*s == symbol).then_some(addr)).unwrap();
+        assert!(artifact.relocs.is_empty());
+        assert!(artifact.deploy.ends_with(&artifact.runtime));
+        assert_eq!(
+            &artifact.runtime[offset + 17..offset + 20],
+            &[0xc0, 0xff, 0xee]
+        );
+    }
+}
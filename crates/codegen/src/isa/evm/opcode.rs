@@ -0,0 +1,188 @@
+use std::fmt;
+
+/// An EVM opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OpCode(pub u8);
+
+macro_rules! define_opcodes {
+    ($($name:ident = $byte:literal, $mnemonic:literal, $pops:literal, $pushes:literal;)*) => {
+        impl OpCode {
+            $(pub const $name: Self = Self($byte);)*
+        }
+
+        /// Returns the mnemonic and the stack effect of opcodes without a numbered family.
+        fn fixed_info(byte: u8) -> Option<(&'static str, u8, u8)> {
+            match byte {
+                $($byte => Some(($mnemonic, $pops, $pushes)),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+define_opcodes! {
+    STOP = 0x00, "STOP", 0, 0;
+    ADD = 0x01, "ADD", 2, 1;
+    MUL = 0x02, "MUL", 2, 1;
+    SUB = 0x03, "SUB", 2, 1;
+    DIV = 0x04, "DIV", 2, 1;
+    SDIV = 0x05, "SDIV", 2, 1;
+    MOD = 0x06, "MOD", 2, 1;
+    SMOD = 0x07, "SMOD", 2, 1;
+    ADDMOD = 0x08, "ADDMOD", 3, 1;
+    MULMOD = 0x09, "MULMOD", 3, 1;
+    EXP = 0x0a, "EXP", 2, 1;
+    SIGNEXTEND = 0x0b, "SIGNEXTEND", 2, 1;
+    LT = 0x10, "LT", 2, 1;
+    GT = 0x11, "GT", 2, 1;
+    SLT = 0x12, "SLT", 2, 1;
+    SGT = 0x13, "SGT", 2, 1;
+    EQ = 0x14, "EQ", 2, 1;
+    ISZERO = 0x15, "ISZERO", 1, 1;
+    AND = 0x16, "AND", 2, 1;
+    OR = 0x17, "OR", 2, 1;
+    XOR = 0x18, "XOR", 2, 1;
+    NOT = 0x19, "NOT", 1, 1;
+    BYTE = 0x1a, "BYTE", 2, 1;
+    SHL = 0x1b, "SHL", 2, 1;
+    SHR = 0x1c, "SHR", 2, 1;
+    SAR = 0x1d, "SAR", 2, 1;
+    KECCAK256 = 0x20, "KECCAK256", 2, 1;
+    ADDRESS = 0x30, "ADDRESS", 0, 1;
+    BALANCE = 0x31, "BALANCE", 1, 1;
+    ORIGIN = 0x32, "ORIGIN", 0, 1;
+    CALLER = 0x33, "CALLER", 0, 1;
+    CALLVALUE = 0x34, "CALLVALUE", 0, 1;
+    CALLDATALOAD = 0x35, "CALLDATALOAD", 1, 1;
+    CALLDATASIZE = 0x36, "CALLDATASIZE", 0, 1;
+    CALLDATACOPY = 0x37, "CALLDATACOPY", 3, 0;
+    CODESIZE = 0x38, "CODESIZE", 0, 1;
+    CODECOPY = 0x39, "CODECOPY", 3, 0;
+    GASPRICE = 0x3a, "GASPRICE", 0, 1;
+    EXTCODESIZE = 0x3b, "EXTCODESIZE", 1, 1;
+    EXTCODECOPY = 0x3c, "EXTCODECOPY", 4, 0;
+    RETURNDATASIZE = 0x3d, "RETURNDATASIZE", 0, 1;
+    RETURNDATACOPY = 0x3e, "RETURNDATACOPY", 3, 0;
+    EXTCODEHASH = 0x3f, "EXTCODEHASH", 1, 1;
+    BLOCKHASH = 0x40, "BLOCKHASH", 1, 1;
+    COINBASE = 0x41, "COINBASE", 0, 1;
+    TIMESTAMP = 0x42, "TIMESTAMP", 0, 1;
+    NUMBER = 0x43, "NUMBER", 0, 1;
+    DIFFICULTY = 0x44, "DIFFICULTY", 0, 1;
+    GASLIMIT = 0x45, "GASLIMIT", 0, 1;
+    CHAINID = 0x46, "CHAINID", 0, 1;
+    SELFBALANCE = 0x47, "SELFBALANCE", 0, 1;
+    BASEFEE = 0x48, "BASEFEE", 0, 1;
+    POP = 0x50, "POP", 1, 0;
+    MLOAD = 0x51, "MLOAD", 1, 1;
+    MSTORE = 0x52, "MSTORE", 2, 0;
+    MSTORE8 = 0x53, "MSTORE8", 2, 0;
+    SLOAD = 0x54, "SLOAD", 1, 1;
+    SSTORE = 0x55, "SSTORE", 2, 0;
+    JUMP = 0x56, "JUMP", 1, 0;
+    JUMPI = 0x57, "JUMPI", 2, 0;
+    PC = 0x58, "PC", 0, 1;
+    MSIZE = 0x59, "MSIZE", 0, 1;
+    GAS = 0x5a, "GAS", 0, 1;
+    JUMPDEST = 0x5b, "JUMPDEST", 0, 0;
+    MCOPY = 0x5e, "MCOPY", 3, 0;
+    PUSH0 = 0x5f, "PUSH0", 0, 1;
+    CREATE = 0xf0, "CREATE", 3, 1;
+    CALL = 0xf1, "CALL", 7, 1;
+    CALLCODE = 0xf2, "CALLCODE", 7, 1;
+    RETURN = 0xf3, "RETURN", 2, 0;
+    DELEGATECALL = 0xf4, "DELEGATECALL", 6, 1;
+    CREATE2 = 0xf5, "CREATE2", 4, 1;
+    STATICCALL = 0xfa, "STATICCALL", 6, 1;
+    REVERT = 0xfd, "REVERT", 2, 0;
+    INVALID = 0xfe, "INVALID", 0, 0;
+    SELFDESTRUCT = 0xff, "SELFDESTRUCT", 1, 0;
+}
+
+impl OpCode {
+    /// Returns `PUSH<n>`, which pushes the following `n` bytes of immediate.
+    pub fn push(n: usize) -> Self {
+        debug_assert!((1..=32).contains(&n));
+        Self(0x5f + n as u8)
+    }
+
+    /// Returns `DUP<n>`, which duplicates the `n`-th stack item.
+    pub fn dup(n: usize) -> Self {
+        debug_assert!((1..=16).contains(&n));
+        Self(0x7f + n as u8)
+    }
+
+    /// Returns `SWAP<n>`, which swaps the top of the stack and the `n + 1`-th stack item.
+    pub fn swap(n: usize) -> Self {
+        debug_assert!((1..=16).contains(&n));
+        Self(0x8f + n as u8)
+    }
+
+    /// Returns `LOG<n>`, which emits a log with `n` topics.
+    pub fn log(n: usize) -> Self {
+        debug_assert!(n <= 4);
+        Self(0xa0 + n as u8)
+    }
+
+    /// Returns the number of immediate bytes following the opcode.
+    pub fn immediate_size(self) -> usize {
+        match self.0 {
+            0x60..=0x7f => (self.0 - 0x5f) as usize,
+            _ => 0,
+        }
+    }
+
+    /// Returns the number of stack items consumed and produced by the opcode, or `None` if the
+    /// opcode is undefined.
+    pub fn stack_effect(self) -> Option<(usize, usize)> {
+        let (pops, pushes) = match self.0 {
+            0x60..=0x7f => (0, 1),
+            0x80..=0x8f => {
+                let n = (self.0 - 0x7f) as usize;
+                (n, n + 1)
+            }
+            0x90..=0x9f => {
+                let n = (self.0 - 0x8f) as usize;
+                (n + 1, n + 1)
+            }
+            0xa0..=0xa4 => ((self.0 - 0xa0) as usize + 2, 0),
+            byte => {
+                let (_, pops, pushes) = fixed_info(byte)?;
+                (pops as usize, pushes as usize)
+            }
+        };
+        Some((pops, pushes))
+    }
+
+    /// Returns `true` if the execution doesn't continue to the next opcode.
+    pub fn is_terminator(self) -> bool {
+        matches!(
+            self,
+            Self::STOP
+                | Self::JUMP
+                | Self::RETURN
+                | Self::REVERT
+                | Self::INVALID
+                | Self::SELFDESTRUCT
+        )
+    }
+
+    pub fn is_defined(self) -> bool {
+        self.stack_effect().is_some()
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            0x60..=0x7f => write!(f, "PUSH{}", self.0 - 0x5f),
+            0x80..=0x8f => write!(f, "DUP{}", self.0 - 0x7f),
+            0x90..=0x9f => write!(f, "SWAP{}", self.0 - 0x8f),
+            0xa0..=0xa4 => write!(f, "LOG{}", self.0 - 0xa0),
+            byte => match fixed_info(byte) {
+                Some((mnemonic, ..)) => f.write_str(mnemonic),
+                None => write!(f, "UNKNOWN(0x{byte:02x})"),
+            },
+        }
+    }
+}
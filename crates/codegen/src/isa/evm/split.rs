@@ -0,0 +1,295 @@
+//! This module contains the splitting of a contract whose runtime code exceeds
+//! [`MAX_CODE_SIZE`](super::verify::MAX_CODE_SIZE) into the contract and a companion contract.
+//!
+//! Cold internal functions are moved to the companion, whose external functions they become. The
+//! contract keeps a stub in place of each moved function, which encodes the arguments as
+//! calldata, calls the companion by `DELEGATECALL` so that the function runs on the storage of the
+//! contract, and returns the result as if the function had run in place.
+//!
+//! Memory isn't shared with the companion, so only functions whose arguments and result have an
+//! ABI representation, and which don't access global variables, directly or through their
+//! callees, can be moved. Callees of a moved function are compiled into the companion as well,
+//! and are only kept in the contract if the contract still calls them.
+//!
+//! Functions are moved coldest first, and among equally cold ones largest first, until the code
+//! fits. A function is as hot as its call sites are, where a call site in a loop counts
+//! [`LOOP_WEIGHT`] times as much as one outside of it. Functions marked `cold` are taken as the
+//! coldest regardless of their call sites.
+use std::cmp::Reverse;
+
+use rustc_hash::FxHashMap;
+use sonatina_ir::{module::FuncRef, ControlFlowGraph, InsnData, Module, Type, ValueData, U256};
+
+use crate::{domtree::DomTree, loop_analysis::LoopTree};
+
+use super::{
+    abi,
+    asm::{AsmItem, Assembly, ExternalSymbol, Label},
+    decodes_signature,
+    layout::WORD_SIZE,
+    opcode::OpCode,
+    reachable_funcs,
+    verify::MAX_CODE_SIZE,
+    CompileOptions, ContractArtifact, Dispatched, EvmCodegenError, Runtime, SP_ADDR,
+};
+
+/// The library symbol of the address of the companion contract, which must be linked once the
+/// companion is deployed.
+pub const COMPANION_SYMBOL: &str = "companion";
+
+/// The weight of a call site per loop it's nested in.
+const LOOP_WEIGHT: u64 = 8;
+
+#[derive(Debug, Clone)]
+pub struct CodeSplit {
+    /// The contract the moved functions are compiled into.
+    pub companion: ContractArtifact,
+    /// The moved functions in the order they were chosen.
+    pub moved: Vec<SplitDecision>,
+    /// The size of the runtime code before splitting.
+    pub original_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitDecision {
+    pub func: String,
+    /// The size of the code of the function before splitting.
+    pub size: usize,
+    /// The sum of the weights of the call sites of the function.
+    pub heat: u64,
+}
+
+/// Moves functions out of `runtime`, which is compiled from `funcs`, until it fits. Returns the
+/// runtime code of what is left along with the split, or `runtime` itself if no function can be
+/// moved.
+pub(super) fn split(
+    module: &Module,
+    funcs: &[FuncRef],
+    dispatched: &Dispatched,
+    inits: &[FuncRef],
+    mut runtime: Runtime,
+    options: &CompileOptions,
+) -> Result<(Runtime, Option<CodeSplit>), EvmCodegenError> {
+    let original_size = runtime.assembled.code.len();
+    let sizes = func_sizes(&runtime);
+    let heats = heats(module, funcs);
+    let roots: Vec<_> = dispatched.funcs().chain(inits.iter().copied()).collect();
+
+    let mut candidates: Vec<_> = funcs
+        .iter()
+        .copied()
+        .filter(|func_ref| !roots.contains(func_ref) && is_movable(module, *func_ref))
+        .collect();
+    candidates.sort_by_key(|func_ref| (heats[func_ref], Reverse(sizes[func_ref])));
+
+    let mut moved = Vec::new();
+    let mut delegated = FxHashMap::default();
+    for func_ref in candidates {
+        if runtime.assembled.code.len() <= MAX_CODE_SIZE {
+            break;
+        }
+        // Functions that are only called by moved functions are already gone.
+        if !runtime.symbols.func_labels.contains_key(&func_ref) {
+            continue;
+        }
+
+        let sig = &module.funcs[func_ref].sig;
+        let selector = abi::function_selector(&module.ctx, sig).unwrap();
+        delegated.insert(func_ref, selector);
+        let kept = reachable_funcs(module, &roots, &delegated);
+        runtime = super::emit_runtime(module, &kept, dispatched, inits, &delegated)?;
+
+        let decision = SplitDecision {
+            func: sig.name().to_string(),
+            size: sizes[&func_ref],
+            heat: heats[&func_ref],
+        };
+        tracing::info!(
+            func = decision.func.as_str(),
+            size = decision.size,
+            heat = decision.heat,
+            runtime_size = runtime.assembled.code.len(),
+            "moved function to the companion contract"
+        );
+        moved.push((func_ref, selector, decision));
+    }
+
+    if moved.is_empty() {
+        return Ok((runtime, None));
+    }
+
+    let companion_entries: Vec<_> = moved
+        .iter()
+        .map(|(func_ref, selector, _)| (*func_ref, *selector))
+        .collect();
+    let companion_roots: Vec<_> = companion_entries
+        .iter()
+        .map(|(func_ref, _)| *func_ref)
+        .chain(inits.iter().copied())
+        .collect();
+    let companion_funcs = reachable_funcs(module, &companion_roots, &FxHashMap::default());
+    let companion_options = CompileOptions {
+        split_code: false,
+        ..options.clone()
+    };
+    let companion = super::compile_funcs(
+        module,
+        &companion_funcs,
+        &companion_entries,
+        None,
+        &companion_options,
+    )?;
+
+    let split = CodeSplit {
+        companion,
+        moved: moved.into_iter().map(|(_, _, decision)| decision).collect(),
+        original_size,
+    };
+    Ok((runtime, Some(split)))
+}
+
+/// Emits the stub in place of a moved function, which follows the calling convention of
+/// functions and calls the function with `selector` at the address `target`, e.g., the companion,
+/// by `call`, which is either `DELEGATECALL` or `CALL`.
+pub(super) fn emit_call_stub(
+    asm: &mut Assembly,
+    module: &Module,
+    label: Label,
+    func_ref: FuncRef,
+    selector: [u8; 4],
+    target: ExternalSymbol,
+    call: OpCode,
+) {
+    let sig = &module.funcs[func_ref].sig;
+    let args_size = 4 + sig.args().len() * WORD_SIZE;
+    let ret_size = if sig.ret_ty() == Type::Void {
+        0
+    } else {
+        WORD_SIZE
+    };
+
+    // Encode the calldata on top of the innermost frame, with the return address and the
+    // arguments on the stack.
+    asm.jump_dest(label);
+    asm.push(SP_ADDR);
+    asm.op(OpCode::MLOAD);
+    asm.push(U256::from(u32::from_be_bytes(selector)) << 224);
+    asm.op(OpCode::dup(2));
+    asm.op(OpCode::MSTORE);
+    for idx in (0..sig.args().len()).rev() {
+        asm.op(OpCode::swap(1));
+        asm.op(OpCode::dup(2));
+        asm.push(4 + idx * WORD_SIZE);
+        asm.op(OpCode::ADD);
+        asm.op(OpCode::MSTORE);
+    }
+
+    // The result overwrites the calldata.
+    asm.push(ret_size);
+    asm.op(OpCode::dup(2));
+    asm.push(args_size);
+    asm.op(OpCode::dup(4));
+    if call == OpCode::CALL {
+        // No ether is sent along.
+        asm.push(0);
+    }
+    asm.push_item(AsmItem::PushExternal(target));
+    asm.op(OpCode::GAS);
+    asm.op(call);
+
+    // Bubble up a revert of the callee.
+    let ok = asm.make_label();
+    asm.push_label(ok);
+    asm.op(OpCode::JUMPI);
+    asm.op(OpCode::RETURNDATASIZE);
+    asm.push(0);
+    asm.op(OpCode::dup(1));
+    asm.op(OpCode::RETURNDATACOPY);
+    asm.op(OpCode::RETURNDATASIZE);
+    asm.push(0);
+    asm.op(OpCode::REVERT);
+
+    asm.jump_dest(ok);
+    if ret_size == 0 {
+        asm.op(OpCode::POP);
+    } else {
+        asm.op(OpCode::MLOAD);
+        asm.op(OpCode::swap(1));
+    }
+    asm.op(OpCode::JUMP);
+}
+
+/// Returns whether `func_ref` can run in the companion, i.e., the dispatcher of the companion can
+/// decode its arguments, and neither it nor its callees access global variables.
+fn is_movable(module: &Module, func_ref: FuncRef) -> bool {
+    if !decodes_signature(&module.funcs[func_ref].sig) {
+        return false;
+    }
+
+    reachable_funcs(module, &[func_ref], &FxHashMap::default())
+        .into_iter()
+        .all(|func_ref| {
+            let func = &module.funcs[func_ref];
+            func.layout.iter_block().all(|block| {
+                func.layout.iter_insn(block).all(|insn| {
+                    func.dfg
+                        .insn_data(insn)
+                        .args()
+                        .iter()
+                        .all(|arg| !matches!(func.dfg.value_data(*arg), ValueData::Global { .. }))
+                })
+            })
+        })
+}
+
+/// Returns the size of the code of each function in `runtime`.
+fn func_sizes(runtime: &Runtime) -> FxHashMap<FuncRef, usize> {
+    let mut sizes = FxHashMap::default();
+    let mut start = 0;
+    for (item, &offset) in runtime.asm.items().iter().zip(&runtime.assembled.offsets) {
+        match item {
+            AsmItem::FuncStart(_) => start = offset as usize,
+            AsmItem::FuncEnd(func_ref) => {
+                sizes.insert(*func_ref, offset as usize - start);
+            }
+            _ => {}
+        }
+    }
+    sizes
+}
+
+/// Returns the heat of each function in `funcs`, see the module documentation.
+fn heats(module: &Module, funcs: &[FuncRef]) -> FxHashMap<FuncRef, u64> {
+    let mut heats: FxHashMap<_, _> = funcs.iter().map(|func_ref| (*func_ref, 0u64)).collect();
+    let mut cfg = ControlFlowGraph::new();
+    let mut domtree = DomTree::new();
+    let mut lpt = LoopTree::new();
+    for &func_ref in funcs {
+        let func = &module.funcs[func_ref];
+        cfg.compute(func);
+        domtree.compute(&cfg);
+        lpt.compute(&cfg, &domtree);
+
+        for block in func.layout.iter_block() {
+            let mut depth = 0;
+            let mut lp = lpt.loop_of_block(block);
+            while let Some(parent) = lp {
+                depth += 1;
+                lp = lpt.parent_loop(parent);
+            }
+            let weight = LOOP_WEIGHT.saturating_pow(depth);
+
+            for insn in func.layout.iter_insn(block) {
+                if let InsnData::Call { func: callee, .. } = func.dfg.insn_data(insn) {
+                    if module.funcs[*callee].sig.attrs().cold {
+                        continue;
+                    }
+                    if let Some(heat) = heats.get_mut(callee) {
+                        *heat = heat.saturating_add(weight);
+                    }
+                }
+            }
+        }
+    }
+    heats
+}
@@ -0,0 +1,439 @@
+//! This module contains a static analysis of the depth of the operand stack of lowered code.
+//!
+//! Values live in frames in memory, so the operand stack of a function only holds the temporaries
+//! of the insn being lowered and the return addresses and arguments of calls. The analysis
+//! simulates the assembly of each function from its entry, tracking which stack items are labels
+//! so that calls, returns and jumps can be told apart, and records the maximum depth reached in
+//! each block. Depths are relative to the depth before the caller pushes the return address.
+//!
+//! A call holds the stack of the caller while the callee runs, so the depth adds up along call
+//! chains. The EVM aborts when the stack exceeds [`STACK_LIMIT`] items, which a long call chain or
+//! any recursion can reach at runtime.
+use std::fmt;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use sonatina_ir::{module::FuncRef, Block, Module, Type};
+
+use super::{
+    asm::{AsmItem, Assembly, Label},
+    opcode::OpCode,
+};
+
+/// The maximum number of items on the operand stack of the EVM.
+pub const STACK_LIMIT: usize = 1024;
+
+#[derive(Debug, Clone, Default)]
+pub struct FuncStackDepth {
+    /// The maximum depth reached in each block.
+    pub blocks: FxHashMap<Block, usize>,
+    /// The callees along with the depth of the stack under their return address.
+    pub calls: Vec<(FuncRef, usize)>,
+}
+
+impl FuncStackDepth {
+    /// Returns the maximum depth reached by the function itself, excluding its callees.
+    pub fn max_depth(&self) -> usize {
+        self.blocks.values().copied().max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackWarning {
+    /// Functions call each other in a cycle, so the depth grows with the depth of the recursion.
+    Recursion(Vec<String>),
+
+    /// The depth exceeds [`STACK_LIMIT`] on a call chain from an entry.
+    Overflow { chain: Vec<String>, depth: usize },
+}
+
+impl fmt::Display for StackWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Recursion(cycle) => write!(
+                f,
+                "recursive calls may overflow the stack: `{}`",
+                cycle.join("` -> `")
+            ),
+            Self::Overflow { chain, depth } => write!(
+                f,
+                "the stack may reach {depth} items, exceeding the limit of {STACK_LIMIT}: `{}`",
+                chain.join("` -> `")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StackDepth {
+    funcs: FxHashMap<FuncRef, FuncStackDepth>,
+}
+
+impl StackDepth {
+    /// Simulates the functions lowered to `asm`, whose entries are bound to `func_labels`.
+    pub fn analyze(
+        module: &Module,
+        asm: &Assembly,
+        func_labels: &FxHashMap<FuncRef, Label>,
+    ) -> Self {
+        let items = asm.items();
+        let mut label_idx = FxHashMap::default();
+        for (idx, item) in items.iter().enumerate() {
+            if let AsmItem::JumpDest(label) = item {
+                label_idx.insert(*label, idx);
+            }
+        }
+        let label_funcs: FxHashMap<_, _> = func_labels
+            .iter()
+            .map(|(func_ref, label)| (*label, *func_ref))
+            .collect();
+
+        let mut analysis = Self::default();
+        for (idx, item) in items.iter().enumerate() {
+            if let AsmItem::FuncStart(func_ref) = item {
+                let simulator = Simulator {
+                    module,
+                    items,
+                    label_idx: &label_idx,
+                    label_funcs: &label_funcs,
+                };
+                let depth = simulator.simulate(*func_ref, idx);
+                analysis.funcs.insert(*func_ref, depth);
+            }
+        }
+        analysis
+    }
+
+    pub fn func(&self, func_ref: FuncRef) -> Option<&FuncStackDepth> {
+        self.funcs.get(&func_ref)
+    }
+
+    /// Returns the maximum depth while `func_ref` runs, including its callees, or `None` if it
+    /// reaches a recursive call.
+    pub fn max_depth_of(&self, func_ref: FuncRef) -> Option<usize> {
+        self.deepest_chain(
+            func_ref,
+            &mut FxHashMap::default(),
+            &mut FxHashSet::default(),
+        )
+        .map(|(depth, _)| depth)
+    }
+
+    /// Returns the warnings about the call chains from `roots`, which are called on an empty
+    /// stack.
+    pub fn warnings(&self, module: &Module, roots: &[FuncRef]) -> Vec<StackWarning> {
+        let name = |func_ref: &FuncRef| module.funcs[*func_ref].sig.name().to_string();
+        let mut warnings: Vec<_> = self
+            .cycles(roots)
+            .into_iter()
+            .map(|cycle| StackWarning::Recursion(cycle.iter().map(name).collect()))
+            .collect();
+
+        let mut memo = FxHashMap::default();
+        for &root in roots {
+            let Some((depth, _)) = self.deepest_chain(root, &mut memo, &mut FxHashSet::default())
+            else {
+                continue;
+            };
+            if depth <= STACK_LIMIT {
+                continue;
+            }
+
+            let mut chain = vec![root];
+            while let Some(Some((_, Some(next)))) = memo.get(chain.last().unwrap()) {
+                chain.push(*next);
+            }
+            warnings.push(StackWarning::Overflow {
+                chain: chain.iter().map(name).collect(),
+                depth,
+            });
+        }
+        warnings
+    }
+
+    /// Returns the maximum depth while `func_ref` runs along with the callee on the deepest call
+    /// chain, or `None` if it reaches a recursive call. Results are memoized in `memo`.
+    fn deepest_chain(
+        &self,
+        func_ref: FuncRef,
+        memo: &mut FxHashMap<FuncRef, Option<(usize, Option<FuncRef>)>>,
+        on_path: &mut FxHashSet<FuncRef>,
+    ) -> Option<(usize, Option<FuncRef>)> {
+        if let Some(result) = memo.get(&func_ref) {
+            return *result;
+        }
+        // External functions aren't lowered, and don't use the stack of the caller.
+        let Some(depth) = self.funcs.get(&func_ref) else {
+            return Some((0, None));
+        };
+        if !on_path.insert(func_ref) {
+            return None;
+        }
+
+        let mut result = Some((depth.max_depth(), None));
+        for &(callee, held) in &depth.calls {
+            match self.deepest_chain(callee, memo, on_path) {
+                Some((callee_depth, _)) => {
+                    if let Some((max, next)) = &mut result {
+                        if held + callee_depth > *max {
+                            *max = held + callee_depth;
+                            *next = Some(callee);
+                        }
+                    }
+                }
+                None => result = None,
+            }
+        }
+
+        on_path.remove(&func_ref);
+        memo.insert(func_ref, result);
+        result
+    }
+
+    /// Returns the cycles in the call graph that are reachable from `roots`, each once.
+    fn cycles(&self, roots: &[FuncRef]) -> Vec<Vec<FuncRef>> {
+        let mut cycles = Vec::new();
+        let mut seen_cycles = FxHashSet::default();
+        let mut visited = FxHashSet::default();
+        for &root in roots {
+            let mut path = Vec::new();
+            self.find_cycles(root, &mut path, &mut visited, &mut cycles, &mut seen_cycles);
+        }
+        cycles
+    }
+
+    fn find_cycles(
+        &self,
+        func_ref: FuncRef,
+        path: &mut Vec<FuncRef>,
+        visited: &mut FxHashSet<FuncRef>,
+        cycles: &mut Vec<Vec<FuncRef>>,
+        seen_cycles: &mut FxHashSet<Vec<FuncRef>>,
+    ) {
+        if let Some(start) = path.iter().position(|f| *f == func_ref) {
+            let cycle = path[start..].to_vec();
+            let mut key = cycle.clone();
+            key.sort_unstable();
+            if seen_cycles.insert(key) {
+                cycles.push(cycle);
+            }
+            return;
+        }
+        if !visited.insert(func_ref) {
+            return;
+        }
+        let Some(depth) = self.funcs.get(&func_ref) else {
+            return;
+        };
+
+        path.push(func_ref);
+        for &(callee, _) in &depth.calls {
+            self.find_cycles(callee, path, visited, cycles, seen_cycles);
+        }
+        path.pop();
+    }
+}
+
+struct Simulator<'a> {
+    module: &'a Module,
+    items: &'a [AsmItem],
+    label_idx: &'a FxHashMap<Label, usize>,
+    label_funcs: &'a FxHashMap<Label, FuncRef>,
+}
+
+/// A stack item, which is either a known label or an unknown word.
+type Item = Option<Label>;
+
+impl Simulator<'_> {
+    /// Simulates the function whose code starts at `start`.
+    fn simulate(&self, func_ref: FuncRef, start: usize) -> FuncStackDepth {
+        let func = &self.module.funcs[func_ref];
+        let mut depth = FuncStackDepth::default();
+
+        // The caller pushes the return address and then the arguments.
+        let entry_stack = vec![None; 1 + func.arg_values.len()];
+        let mut worklist = vec![(start, entry_stack, func.layout.entry_block())];
+        let mut visited = FxHashSet::default();
+
+        while let Some((seg_start, mut stack, mut block)) = worklist.pop() {
+            for (idx, item) in self.items.iter().enumerate().skip(seg_start) {
+                match item {
+                    AsmItem::Op(op) if *op == OpCode::JUMP => {
+                        record(&mut depth, &stack, block);
+                        let target = stack.pop().flatten();
+                        let Some(label) = target else {
+                            // A return to the caller.
+                            break;
+                        };
+
+                        let Some(&callee) = self.label_funcs.get(&label) else {
+                            self.enqueue(&mut worklist, &mut visited, label, stack, block);
+                            break;
+                        };
+                        let arg_num = self.module.funcs[callee].arg_values.len();
+                        let held = stack.len().saturating_sub(1 + arg_num);
+                        depth.calls.push((callee, held));
+
+                        // A tail call reuses the return address of the caller, which is unknown.
+                        if let Some(&Some(ret_label)) = stack.get(held) {
+                            stack.truncate(held);
+                            if self.module.funcs[callee].sig.ret_ty() != Type::Void {
+                                stack.push(None);
+                            }
+                            self.enqueue(&mut worklist, &mut visited, ret_label, stack, block);
+                        }
+                        break;
+                    }
+
+                    AsmItem::Op(op) if *op == OpCode::JUMPI => {
+                        record(&mut depth, &stack, block);
+                        let target = stack.pop().flatten();
+                        stack.pop();
+                        if let Some(label) = target {
+                            let branch_stack = stack.clone();
+                            self.enqueue(&mut worklist, &mut visited, label, branch_stack, block);
+                        }
+                    }
+
+                    AsmItem::Op(op) if op.is_terminator() => break,
+
+                    AsmItem::Op(op) => match op.0 {
+                        // `DUP<n>`
+                        0x80..=0x8f => {
+                            let n = (op.0 - 0x7f) as usize;
+                            let item = stack.len().checked_sub(n).and_then(|i| stack[i]);
+                            stack.push(item);
+                        }
+                        // `SWAP<n>`
+                        0x90..=0x9f => {
+                            let n = (op.0 - 0x8f) as usize;
+                            if stack.len() > n {
+                                let top = stack.len() - 1;
+                                stack.swap(top, top - n);
+                            }
+                        }
+                        _ => {
+                            let (pops, pushes) = op.stack_effect().unwrap_or((0, 0));
+                            stack.truncate(stack.len().saturating_sub(pops));
+                            stack.extend(std::iter::repeat_n(None, pushes));
+                        }
+                    },
+
+                    AsmItem::Push(_) | AsmItem::PushExternal(_) => stack.push(None),
+
+                    AsmItem::PushLabel(label) => stack.push(Some(*label)),
+
+                    AsmItem::JumpDest(_) => {
+                        // Code that falls through to a label simulates it unless it's done.
+                        if idx != seg_start && !visited.insert(idx) {
+                            break;
+                        }
+                    }
+
+                    AsmItem::InsnStart(_, insn) => block = Some(func.layout.insn_block(*insn)),
+
+                    AsmItem::FuncEnd(_) => break,
+
+                    AsmItem::Mark(_)
+                    | AsmItem::Data(_)
+                    | AsmItem::LabelData(_)
+                    | AsmItem::FuncStart(_) => {}
+                }
+                record(&mut depth, &stack, block);
+            }
+        }
+
+        depth
+    }
+
+    /// Schedules the simulation of the code at `label` with `stack`, unless it's scheduled already.
+    fn enqueue(
+        &self,
+        worklist: &mut Vec<(usize, Vec<Item>, Option<Block>)>,
+        visited: &mut FxHashSet<usize>,
+        label: Label,
+        stack: Vec<Item>,
+        block: Option<Block>,
+    ) {
+        if let Some(&idx) = self.label_idx.get(&label) {
+            if visited.insert(idx) {
+                worklist.push((idx, stack, block));
+            }
+        }
+    }
+}
+
+/// Records the depth of `stack` in `block`.
+fn record(depth: &mut FuncStackDepth, stack: &[Item], block: Option<Block>) {
+    if let Some(block) = block {
+        let max = depth.blocks.entry(block).or_default();
+        *max = (*max).max(stack.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        Linkage, Signature,
+    };
+
+    use crate::isa::evm::compile;
+
+    #[test]
+    fn warn_recursion() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let leaf = mb.declare_function(Signature::new(
+            "leaf",
+            Linkage::Private,
+            &[Type::I32],
+            Type::I32,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(leaf);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        builder.ret(Some(arg));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let countdown = mb.declare_function(Signature::new(
+            "countdown",
+            Linkage::Public,
+            &[Type::I32],
+            Type::I32,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(countdown);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        builder.switch_to_block(b0);
+        let n = builder.args()[0];
+        let zero = builder.make_imm_value(0i32);
+        let is_zero = builder.eq(n, zero);
+        builder.br(is_zero, b1, b2);
+        builder.switch_to_block(b1);
+        let v = builder.call(leaf, &[n]).unwrap();
+        builder.ret(Some(v));
+        builder.switch_to_block(b2);
+        let one = builder.make_imm_value(1i32);
+        let next = builder.sub(n, one);
+        let v = builder.call(countdown, &[next]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module).unwrap();
+        assert_eq!(
+            artifact.stack_warnings,
+            [StackWarning::Recursion(vec!["countdown".to_string()])]
+        );
+        assert_eq!(
+            artifact.stack_warnings[0].to_string(),
+            "recursive calls may overflow the stack: `countdown`"
+        );
+    }
+}
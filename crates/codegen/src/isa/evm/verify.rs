@@ -0,0 +1,304 @@
+//! This module contains a checker of emitted bytecode, which catches bugs of the emitter before
+//! the code is deployed.
+//!
+//! Besides the size of the code and the encoding of pushes, the checker simulates the code from
+//! its roots, tracking which stack items are constants pushed by the code. A jump to a constant
+//! must land on a `JUMPDEST`, and a stack item must never be popped from an empty stack. Calls and
+//! returns are followed through the return addresses on the stack, so a function is simulated
+//! once for each distinct stack it's called with.
+//!
+//! The stack must also be balanced: a `JUMPDEST` that is reached twice with the same constants on
+//! the stack, e.g., by a loop, must be reached with the same depth, or the stack would grow or
+//! shrink with each iteration.
+//!
+//! A jump to a computed target, e.g., through a jump table, isn't followed; the code it may land
+//! on is given as a root instead.
+use std::fmt;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::{opcode::OpCode, stack_depth::STACK_LIMIT};
+
+/// The maximum size of runtime code, see EIP-170.
+pub const MAX_CODE_SIZE: usize = 0x6000;
+
+/// The maximum number of states the simulation visits before it gives up on the rest of the code.
+const STATE_LIMIT: usize = 1 << 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The code exceeds [`MAX_CODE_SIZE`].
+    CodeTooLarge(usize),
+
+    /// The immediate of the `PUSH` at `offset` extends past the end of the code.
+    TruncatedPush { offset: usize },
+
+    /// The opcode at `offset` is undefined.
+    UndefinedOpcode { offset: usize, opcode: u8 },
+
+    /// The jump at `offset` lands on `target`, which isn't a `JUMPDEST`.
+    InvalidJumpTarget { offset: usize, target: usize },
+
+    /// The insn at `offset` pops more items than the stack holds.
+    StackUnderflow { offset: usize },
+
+    /// The `JUMPDEST` at `offset` is reached with different stack depths.
+    UnbalancedStack {
+        offset: usize,
+        depths: (usize, usize),
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CodeTooLarge(size) => write!(
+                f,
+                "code size {size} exceeds the limit of {MAX_CODE_SIZE} bytes"
+            ),
+            Self::TruncatedPush { offset } => {
+                write!(f, "`PUSH` at {offset:#x} is truncated")
+            }
+            Self::UndefinedOpcode { offset, opcode } => {
+                write!(f, "undefined opcode {opcode:#04x} at {offset:#x}")
+            }
+            Self::InvalidJumpTarget { offset, target } => write!(
+                f,
+                "jump at {offset:#x} targets {target:#x}, which is not a `JUMPDEST`"
+            ),
+            Self::StackUnderflow { offset } => write!(f, "stack underflow at {offset:#x}"),
+            Self::UnbalancedStack { offset, depths } => write!(
+                f,
+                "`JUMPDEST` at {offset:#x} is reached with stack depths {} and {}",
+                depths.0, depths.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// A stack item, which is either a constant the code pushed, or an unknown word.
+type Item = Option<usize>;
+
+/// Checks `code`, whose instructions end at `code_len` and are followed by data. Each root is the
+/// offset of code that may run along with the depth of the stack when it does, which is unknown.
+/// Returns the errors in the order they are found.
+pub fn verify(code: &[u8], code_len: usize, roots: &[(usize, usize)]) -> Vec<VerifyError> {
+    let mut errors = Vec::new();
+    if code.len() > MAX_CODE_SIZE {
+        errors.push(VerifyError::CodeTooLarge(code.len()));
+    }
+
+    let mut jump_dests = FxHashSet::default();
+    let mut offset = 0;
+    while offset < code_len {
+        let op = OpCode(code[offset]);
+        let next = offset + 1 + op.immediate_size();
+        if op == OpCode::JUMPDEST {
+            jump_dests.insert(offset);
+        } else if next > code_len {
+            errors.push(VerifyError::TruncatedPush { offset });
+        }
+        offset = next;
+    }
+
+    let mut checker = Checker {
+        code: &code[..code_len],
+        jump_dests: &jump_dests,
+        errors,
+        depths: FxHashMap::default(),
+        visited: FxHashSet::default(),
+        worklist: Vec::new(),
+    };
+    for &(offset, depth) in roots {
+        checker.enqueue(offset, vec![None; depth]);
+    }
+    checker.run();
+    checker.errors
+}
+
+struct Checker<'a> {
+    code: &'a [u8],
+    jump_dests: &'a FxHashSet<usize>,
+    errors: Vec<VerifyError>,
+    /// The depth each `JUMPDEST` is reached with, keyed by the constants on the stack.
+    depths: FxHashMap<(usize, Vec<usize>), usize>,
+    visited: FxHashSet<(usize, Vec<Item>)>,
+    worklist: Vec<(usize, Vec<Item>)>,
+}
+
+impl Checker<'_> {
+    fn run(&mut self) {
+        while let Some((start, stack)) = self.worklist.pop() {
+            if self.visited.len() >= STATE_LIMIT {
+                return;
+            }
+            self.simulate(start, stack);
+        }
+    }
+
+    /// Simulates the code from `start` to the end of its block.
+    fn simulate(&mut self, start: usize, mut stack: Vec<Item>) {
+        let mut offset = start;
+        while let Some(&byte) = self.code.get(offset) {
+            let op = OpCode(byte);
+            let next = offset + 1 + op.immediate_size();
+            let Some((pops, pushes)) = op.stack_effect() else {
+                self.errors.push(VerifyError::UndefinedOpcode {
+                    offset,
+                    opcode: byte,
+                });
+                return;
+            };
+            if stack.len() < pops {
+                self.errors.push(VerifyError::StackUnderflow { offset });
+                return;
+            }
+
+            match byte {
+                // `PUSH<n>`
+                0x5f..=0x7f => {
+                    let imm = self.code.get(offset + 1..next).unwrap_or_default();
+                    // Constants too large to be offsets are as good as unknown.
+                    let value = imm
+                        .iter()
+                        .try_fold(0u64, |value, byte| {
+                            (value <= u32::MAX as u64).then_some(value << 8 | *byte as u64)
+                        })
+                        .filter(|value| *value <= u32::MAX as u64);
+                    stack.push(value.map(|value| value as usize));
+                }
+                // `DUP<n>`
+                0x80..=0x8f => stack.push(stack[stack.len() - pops]),
+                // `SWAP<n>`
+                0x90..=0x9f => {
+                    let top = stack.len() - 1;
+                    stack.swap(top, top + 1 - pops);
+                }
+                _ if op == OpCode::JUMP || op == OpCode::JUMPI => {
+                    let target = stack.pop().unwrap();
+                    if op == OpCode::JUMPI {
+                        stack.pop();
+                    }
+                    match target {
+                        Some(target) if !self.jump_dests.contains(&target) => {
+                            self.errors
+                                .push(VerifyError::InvalidJumpTarget { offset, target });
+                            return;
+                        }
+                        Some(target) => self.enqueue(target, stack.clone()),
+                        // A computed target, whose code is a root.
+                        None => {}
+                    }
+                    if op == OpCode::JUMP {
+                        return;
+                    }
+                }
+                _ if op.is_terminator() => return,
+                _ => {
+                    stack.truncate(stack.len() - pops);
+                    stack.extend(std::iter::repeat_n(None, pushes));
+                }
+            }
+
+            if stack.len() > STACK_LIMIT {
+                // Recursion; the stack depth analysis warns about it.
+                return;
+            }
+            offset = next;
+            if self.jump_dests.contains(&offset) {
+                // Fall through into the next block.
+                self.enqueue(offset, stack);
+                return;
+            }
+        }
+    }
+
+    /// Queues the code at `target` to be simulated with `stack`.
+    fn enqueue(&mut self, target: usize, stack: Vec<Item>) {
+        let constants: Vec<usize> = stack.iter().flatten().copied().collect();
+        match self.depths.get(&(target, constants.clone())) {
+            Some(&depth) if depth != stack.len() => {
+                self.errors.push(VerifyError::UnbalancedStack {
+                    offset: target,
+                    depths: (depth, stack.len()),
+                });
+                return;
+            }
+            Some(_) => {}
+            None => {
+                self.depths.insert((target, constants), stack.len());
+            }
+        }
+
+        if self.visited.insert((target, stack.clone())) {
+            self.worklist.push((target, stack));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_jumps() {
+        // A loop that keeps the stack balanced.
+        let code = [
+            0x60, 0x03, // PUSH1 3
+            0x5b, // JUMPDEST
+            0x60, 0x00, // PUSH1 0
+            0x60, 0x02, // PUSH1 2
+            0x57, // JUMPI
+            0x00, // STOP
+        ];
+        assert_eq!(verify(&code, code.len(), &[(0, 0)]), vec![]);
+
+        // A jump into the immediate of a push.
+        let code = [0x60, 0x5b, 0x60, 0x01, 0x56];
+        assert_eq!(
+            verify(&code, code.len(), &[(0, 0)]),
+            vec![VerifyError::InvalidJumpTarget {
+                offset: 4,
+                target: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn verify_stack() {
+        // A loop that leaves an item on the stack in each iteration.
+        let code = [
+            0x5b, // JUMPDEST
+            0x30, // ADDRESS
+            0x60, 0x00, // PUSH1 0
+            0x56, // JUMP
+        ];
+        assert_eq!(
+            verify(&code, code.len(), &[(0, 0)]),
+            vec![VerifyError::UnbalancedStack {
+                offset: 0,
+                depths: (0, 1)
+            }]
+        );
+
+        let code = [0x01, 0x00];
+        assert_eq!(
+            verify(&code, code.len(), &[(0, 1)]),
+            vec![VerifyError::StackUnderflow { offset: 0 }]
+        );
+    }
+
+    #[test]
+    fn verify_encoding() {
+        // The `PUSH2` runs into the data.
+        let code = [0x00, 0x61, 0x01, 0xaa];
+        let errors = verify(&code, 3, &[(0, 0)]);
+        assert_eq!(errors, vec![VerifyError::TruncatedPush { offset: 1 }]);
+
+        let code = vec![0x00; MAX_CODE_SIZE + 1];
+        let errors = verify(&code, 1, &[(0, 0)]);
+        assert_eq!(errors, vec![VerifyError::CodeTooLarge(MAX_CODE_SIZE + 1)]);
+    }
+}
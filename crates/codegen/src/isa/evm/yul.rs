@@ -0,0 +1,854 @@
+//! This module contains a code generator that emits Yul source instead of bytecode, so that a
+//! module can go through solc's Yul pipeline, and the EVM backend can be tested differentially
+//! against solc.
+//!
+//! The emitted object has the same interface as the contract [compiled](super::compile) from the
+//! module, i.e., the same dispatch of external functions and static initializers. Values are kept
+//! zero-extended to the width of their type as in the EVM backend.
+//!
+//! Yul has no jumps, so a function with more than one block becomes a loop that switches on the
+//! index of the current block. A branch assigns the phi arguments of its destination, sets the
+//! index and continues the loop.
+//!
+//! Memory follows the conventions of solc: `0x40` holds the free memory pointer and global
+//! variables start at `0x80`. A function allocates its alloca regions from the free memory pointer
+//! on entry, and frees them on return.
+use std::fmt::{self, Write};
+
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use sonatina_ir::{
+    insn::{BinaryOp, CastOp, MemOp, OverflowOp, UnaryOp},
+    module::FuncRef,
+    Block, DataLocationKind, Function, GlobalVariable, Insn, InsnData, Module, Type, Value,
+    ValueData, U256,
+};
+use sonatina_triple::Feature;
+
+use super::{
+    abi, defined_funcs, external_entries, fallback_func, intrinsic_opcode,
+    layout::{self, WORD_SIZE},
+    layout_globals, static_inits, EvmCodegenError,
+};
+
+/// The address of the free memory pointer.
+const FREE_PTR_ADDR: usize = 0x40;
+
+/// The address of the first global variable.
+const YUL_GLOBAL_BASE: usize = 0x80;
+
+/// Emits a Yul object named `name` that deploys the contract compiled from `module`.
+pub fn emit_yul(module: &Module, name: &str) -> Result<String, EvmCodegenError> {
+    let funcs = defined_funcs(module);
+    let entries = external_entries(module, &funcs)?;
+    let fallback = fallback_func(module, &funcs)?;
+    let inits = static_inits(module, &funcs)?;
+    let (global_addrs, global_data) = layout_globals(module, YUL_GLOBAL_BASE);
+    let heap_base = YUL_GLOBAL_BASE + global_data.len().div_ceil(WORD_SIZE) * WORD_SIZE;
+
+    let mut w = YulWriter::default();
+    let runtime = format!("{name}_deployed");
+    w.open(format_args!("object \"{name}\""));
+    w.open("code");
+    w.line(format_args!(
+        "datacopy(0, dataoffset(\"{runtime}\"), datasize(\"{runtime}\"))"
+    ));
+    w.line(format_args!("return(0, datasize(\"{runtime}\"))"));
+    w.close();
+
+    w.open(format_args!("object \"{runtime}\""));
+    w.open("code");
+    w.line(format_args!("mstore({FREE_PTR_ADDR:#x}, {heap_base:#x})"));
+    // Memory is zeroed, so only words with set bits are initialized.
+    for (idx, chunk) in global_data.chunks(WORD_SIZE).enumerate() {
+        if chunk.iter().all(|byte| *byte == 0) {
+            continue;
+        }
+        let mut word = [0; WORD_SIZE];
+        word[..chunk.len()].copy_from_slice(chunk);
+        w.line(format_args!(
+            "mstore({:#x}, {})",
+            YUL_GLOBAL_BASE + idx * WORD_SIZE,
+            hex(U256::from_big_endian(&word))
+        ));
+    }
+    for init in &inits {
+        w.line(format_args!("{}()", func_name(module, *init)));
+    }
+
+    let no_match = match fallback {
+        Some(fallback) => format!("{}()", func_name(module, fallback)),
+        None => "revert(0, 0)".to_string(),
+    };
+    if entries.is_empty() {
+        w.line(no_match);
+    } else {
+        w.line("switch shr(224, calldataload(0))");
+        for (func_ref, selector) in &entries {
+            let sig = &module.funcs[*func_ref].sig;
+            w.open(format_args!("case {:#010x}", u32::from_be_bytes(*selector)));
+            let args: Vec<_> = sig
+                .args()
+                .iter()
+                .enumerate()
+                .map(|(idx, ty)| mask(format!("calldataload({})", 4 + idx * WORD_SIZE), *ty))
+                .collect();
+            let call = format!("{}({})", func_name(module, *func_ref), args.join(", "));
+            if sig.ret_ty() == Type::Void {
+                w.line(call);
+                w.line("stop()");
+            } else {
+                w.line(format_args!("mstore(0, {call})"));
+                w.line(format_args!("return(0, {WORD_SIZE})"));
+            }
+            w.close();
+        }
+        w.open("default");
+        w.line(no_match);
+        w.close();
+    }
+
+    for &func_ref in &funcs {
+        FuncEmitter::new(module, func_ref, &global_addrs).emit(&mut w)?;
+    }
+
+    w.close();
+    w.close();
+    w.close();
+    Ok(w.buf)
+}
+
+struct FuncEmitter<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    global_addrs: &'a FxHashMap<GlobalVariable, usize>,
+
+    /// The indices of blocks in the dispatch loop.
+    block_indices: FxHashMap<Block, usize>,
+    /// Offsets of alloca regions from `_fp`.
+    allocas: FxHashMap<Insn, usize>,
+    alloca_size: usize,
+}
+
+impl<'a> FuncEmitter<'a> {
+    fn new(
+        module: &'a Module,
+        func_ref: FuncRef,
+        global_addrs: &'a FxHashMap<GlobalVariable, usize>,
+    ) -> Self {
+        let func = &module.funcs[func_ref];
+        let block_indices = func
+            .layout
+            .iter_block()
+            .enumerate()
+            .map(|(idx, block)| (block, idx))
+            .collect();
+
+        let mut allocas = FxHashMap::default();
+        let mut alloca_size = 0;
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                if let InsnData::Alloca { ty } = func.dfg.insn_data(insn) {
+                    allocas.insert(insn, alloca_size);
+                    let size = layout::size_of(&module.ctx, *ty);
+                    alloca_size += size.div_ceil(WORD_SIZE) * WORD_SIZE;
+                }
+            }
+        }
+
+        Self {
+            module,
+            func,
+            global_addrs,
+            block_indices,
+            allocas,
+            alloca_size,
+        }
+    }
+
+    fn emit(&self, w: &mut YulWriter) -> Result<(), EvmCodegenError> {
+        let func = self.func;
+        let args: Vec<_> = func.arg_values.iter().map(|arg| var(*arg)).collect();
+        let ret = if func.sig.ret_ty() == Type::Void {
+            ""
+        } else {
+            " -> ret"
+        };
+        w.open(format_args!(
+            "function {}({}){ret}",
+            sanitize(func.sig.name()),
+            args.join(", ")
+        ));
+        if self.alloca_size != 0 {
+            w.line(format_args!("let _fp := mload({FREE_PTR_ADDR:#x})"));
+            w.line(format_args!(
+                "mstore({FREE_PTR_ADDR:#x}, add(_fp, {}))",
+                self.alloca_size
+            ));
+        }
+
+        if self.block_indices.len() == 1 {
+            let entry = func.layout.entry_block().unwrap();
+            for insn in func.layout.iter_insn(entry) {
+                self.emit_insn(w, insn, true)?;
+            }
+            w.close();
+            return Ok(());
+        }
+
+        // Values are assigned in blocks that don't dominate each other syntactically, so they
+        // are declared up front.
+        let results: Vec<_> = func
+            .layout
+            .iter_block()
+            .flat_map(|block| func.layout.iter_insn(block))
+            .flat_map(|insn| func.dfg.insn_results(insn))
+            .filter(|result| func.dfg.value_ty(**result) != Type::Void)
+            .map(|result| var(*result))
+            .collect();
+        if !results.is_empty() {
+            w.line(format_args!("let {}", results.join(", ")));
+        }
+        w.line("let _block := 0");
+        w.open("for {} 1 {}");
+        w.line("switch _block");
+        for block in func.layout.iter_block() {
+            w.open(format_args!("case {}", self.block_indices[&block]));
+            for insn in func.layout.iter_insn(block) {
+                self.emit_insn(w, insn, false)?;
+            }
+            w.close();
+        }
+        w.close();
+        w.close();
+        Ok(())
+    }
+
+    /// Emits `insn`. If `declare` is `true`, its result is declared rather than assigned.
+    fn emit_insn(
+        &self,
+        w: &mut YulWriter,
+        insn: Insn,
+        declare: bool,
+    ) -> Result<(), EvmCodegenError> {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let block = func.layout.insn_block(insn);
+
+        let expr = match dfg.insn_data(insn) {
+            InsnData::Unary { code, args } => {
+                let ty = dfg.value_ty(args[0]);
+                let arg = self.value(args[0]);
+                match code {
+                    UnaryOp::Not => mask(format!("not({arg})"), ty),
+                    UnaryOp::Neg => mask(format!("sub(0, {arg})"), ty),
+                    UnaryOp::Clz | UnaryOp::Ctz | UnaryOp::Popcount | UnaryOp::Bswap => {
+                        // The expansions use intermediate values more than once, so they are
+                        // emitted as a sequence of assignments to the result.
+                        let result = var(dfg.insn_result(insn).unwrap());
+                        let decl = if declare { "let " } else { "" };
+                        let (init, steps) = bit_manipulation(*code, ty, arg, &result);
+                        w.line(format_args!("{decl}{result} := {init}"));
+                        for step in steps {
+                            w.line(format_args!("{result} := {step}"));
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+
+            InsnData::Binary { code, args } => self.binary(*code, *args),
+
+            InsnData::OverflowBinary { code, args } => {
+                let results = dfg.insn_results(insn);
+                let decl = if declare { "let " } else { "" };
+                let wrapped = self.binary(code.unchecked(), *args);
+                w.line(format_args!("{decl}{} := {wrapped}", var(results[0])));
+                let flag = self.overflow_flag(*code, *args, results[0]);
+                w.line(format_args!("{decl}{} := {flag}", var(results[1])));
+                return Ok(());
+            }
+
+            InsnData::Cast { code, args, ty } => {
+                let from = dfg.value_ty(args[0]);
+                let arg = self.value(args[0]);
+                match code {
+                    CastOp::Sext => mask(sign_extend(arg, from), *ty),
+                    CastOp::Trunc => mask(arg, *ty),
+                    CastOp::Zext | CastOp::BitCast => arg,
+                }
+            }
+
+            InsnData::Load { args, loc } => {
+                let addr = self.value(args[0]);
+                match loc {
+                    DataLocationKind::Memory => {
+                        let ty = dfg.insn_result_ty(insn).unwrap();
+                        let size = layout::size_of(&self.module.ctx, ty);
+                        if size < WORD_SIZE {
+                            format!("shr({}, mload({addr}))", (WORD_SIZE - size) * 8)
+                        } else {
+                            format!("mload({addr})")
+                        }
+                    }
+                    DataLocationKind::Storage => format!("sload({addr})"),
+                }
+            }
+
+            InsnData::Store { args, loc } => {
+                let (addr, data) = (self.value(args[0]), self.value(args[1]));
+                match loc {
+                    DataLocationKind::Memory => {
+                        let ty = dfg.value_ty(args[1]);
+                        match layout::size_of(&self.module.ctx, ty) {
+                            WORD_SIZE => w.line(format_args!("mstore({addr}, {data})")),
+                            1 => w.line(format_args!("mstore8({addr}, {data})")),
+                            size => {
+                                // Merge the data into the word at `addr` to keep the following
+                                // bytes intact.
+                                let shift = (WORD_SIZE - size) * 8;
+                                let keep = !(layout::low_mask(size * 8) << shift);
+                                w.line(format_args!(
+                                    "mstore({addr}, or(and(mload({addr}), {}), shl({shift}, \
+                                     {data})))",
+                                    hex(keep)
+                                ));
+                            }
+                        }
+                    }
+                    DataLocationKind::Storage => w.line(format_args!("sstore({addr}, {data})")),
+                }
+                return Ok(());
+            }
+
+            InsnData::Mem { code, args, loc } => {
+                self.mem(w, *code, *args, *loc);
+                return Ok(());
+            }
+
+            InsnData::Call {
+                func: callee, args, ..
+            } => {
+                if self.module.is_external(*callee) {
+                    return Err(EvmCodegenError::ExternalCall {
+                        caller: func.sig.name().to_string(),
+                        callee: self.module.funcs[*callee].sig.name().to_string(),
+                    });
+                }
+                let args: Vec<_> = args.iter().map(|arg| self.value(*arg)).collect();
+                format!("{}({})", func_name(self.module, *callee), args.join(", "))
+            }
+
+            // The intrinsics that have an opcode are named like the Yul builtins.
+            InsnData::Intrinsic {
+                intrinsic, args, ..
+            } => {
+                let name = self.module.ctx.isa.with_intrinsics(|intrinsics| {
+                    intrinsics.intrinsic_data(*intrinsic).name.clone()
+                });
+                if intrinsic_opcode(&name).is_none() {
+                    return Err(EvmCodegenError::UnsupportedIntrinsic {
+                        func: func.sig.name().to_string(),
+                        intrinsic: name,
+                    });
+                }
+                let args: Vec<_> = args.iter().map(|arg| self.value(*arg)).collect();
+                format!("{name}({})", args.join(", "))
+            }
+
+            InsnData::Jump { dests } => {
+                self.emit_edge(w, block, dests[0]);
+                w.line("continue");
+                return Ok(());
+            }
+
+            InsnData::Branch { args, dests } => {
+                w.line(format_args!("switch {}", self.value(args[0])));
+                w.open("case 0");
+                self.emit_edge(w, block, dests[1]);
+                w.close();
+                w.open("default");
+                self.emit_edge(w, block, dests[0]);
+                w.close();
+                w.line("continue");
+                return Ok(());
+            }
+
+            InsnData::BrTable {
+                args,
+                default,
+                table,
+            } => {
+                w.line(format_args!("switch {}", self.value(args[0])));
+                // Cases must be distinct, and the first matching one is taken.
+                let mut keys = SmallVec::<[String; 8]>::new();
+                for (value, dest) in args[1..].iter().zip(table) {
+                    let key = self.value(*value);
+                    if keys.contains(&key) {
+                        continue;
+                    }
+                    w.open(format_args!("case {key}"));
+                    self.emit_edge(w, block, *dest);
+                    w.close();
+                    keys.push(key);
+                }
+                w.open("default");
+                match default {
+                    Some(dest) => self.emit_edge(w, block, *dest),
+                    None => w.line("invalid()"),
+                }
+                w.close();
+                w.line("continue");
+                return Ok(());
+            }
+
+            InsnData::Alloca { .. } => format!("add(_fp, {})", self.allocas[&insn]),
+
+            InsnData::Return { args } => {
+                if let Some(arg) = args {
+                    w.line(format_args!("ret := {}", self.value(*arg)));
+                }
+                if self.alloca_size != 0 {
+                    w.line(format_args!("mstore({FREE_PTR_ADDR:#x}, _fp)"));
+                }
+                w.line("leave");
+                return Ok(());
+            }
+
+            InsnData::Revert { selector, args } => {
+                let args: Vec<_> = args.iter().map(|arg| self.value(*arg)).collect();
+                emit_revert(w, *selector, &args);
+                return Ok(());
+            }
+
+            InsnData::Trap { reason } => {
+                let code = reason.panic_code().to_string();
+                emit_revert(w, abi::PANIC_SELECTOR, &[code]);
+                return Ok(());
+            }
+
+            InsnData::Unreachable => {
+                w.line("invalid()");
+                return Ok(());
+            }
+
+            InsnData::Gep { args } => self.gep(args),
+
+            // Phi arguments are assigned on edges.
+            InsnData::Phi { .. } => return Ok(()),
+        };
+
+        match self.result(insn) {
+            Some(result) if declare => w.line(format_args!("let {} := {expr}", var(result))),
+            Some(result) => w.line(format_args!("{} := {expr}", var(result))),
+            None => w.line(expr),
+        }
+        Ok(())
+    }
+
+    /// Emits a memory intrinsic. Copies within memory use `mcopy` if the target has it, and the
+    /// identity precompile otherwise; the other intrinsics are loops over bytes or slots.
+    fn mem(&self, w: &mut YulWriter, code: MemOp, args: [Value; 3], loc: DataLocationKind) {
+        let [dst, src, len] = args.map(|arg| self.value(arg));
+        let forward = format!("for {{ let _i := 0 }} lt(_i, {len}) {{ _i := add(_i, 1) }}");
+        match (code, loc) {
+            (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Memory) => {
+                if self.module.ctx.isa.triple().has_feature(Feature::Mcopy) {
+                    w.line(format_args!("mcopy({dst}, {src}, {len})"));
+                } else {
+                    w.line(format_args!(
+                        "pop(staticcall(gas(), 4, {src}, {len}, {dst}, {len}))"
+                    ));
+                }
+            }
+
+            (MemOp::Memset, DataLocationKind::Memory) => {
+                w.open(forward);
+                w.line(format_args!("mstore8(add({dst}, _i), {src})"));
+                w.close();
+            }
+
+            (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Storage) => {
+                let copy = format!("sstore(add({dst}, _i), sload(add({src}, _i)))");
+                if code == MemOp::Memcpy {
+                    w.open(forward);
+                    w.line(&copy);
+                    w.close();
+                    return;
+                }
+
+                // Copy backward if `dst` is above `src`, so that no slot is overwritten before
+                // it's read.
+                w.line(format_args!("switch gt({dst}, {src})"));
+                w.open("case 0");
+                w.open(forward);
+                w.line(&copy);
+                w.close();
+                w.close();
+                w.open("default");
+                w.open(format_args!("for {{ let _i := {len} }} _i {{}}"));
+                w.line("_i := sub(_i, 1)");
+                w.line(&copy);
+                w.close();
+                w.close();
+            }
+
+            (MemOp::Memset, DataLocationKind::Storage) => {
+                let word = hex(layout::splat(U256::one(), 8));
+                w.open(forward);
+                w.line(format_args!("sstore(add({dst}, _i), mul({src}, {word}))"));
+                w.close();
+            }
+        }
+    }
+
+    fn binary(&self, code: BinaryOp, args: [Value; 2]) -> String {
+        let ty = self.func.dfg.value_ty(args[0]);
+        let (lhs, rhs) = (self.value(args[0]), self.value(args[1]));
+        let slhs = || sign_extend(lhs.clone(), ty);
+        let srhs = || sign_extend(rhs.clone(), ty);
+
+        match code {
+            BinaryOp::Add => mask(format!("add({lhs}, {rhs})"), ty),
+            BinaryOp::Sub => mask(format!("sub({lhs}, {rhs})"), ty),
+            BinaryOp::Mul => mask(format!("mul({lhs}, {rhs})"), ty),
+            BinaryOp::Udiv => format!("div({lhs}, {rhs})"),
+            BinaryOp::Sdiv => mask(format!("sdiv({}, {})", slhs(), srhs()), ty),
+            BinaryOp::Lt => format!("lt({lhs}, {rhs})"),
+            BinaryOp::Gt => format!("gt({lhs}, {rhs})"),
+            BinaryOp::Slt => format!("slt({}, {})", slhs(), srhs()),
+            BinaryOp::Sgt => format!("sgt({}, {})", slhs(), srhs()),
+            BinaryOp::Le => format!("iszero(gt({lhs}, {rhs}))"),
+            BinaryOp::Ge => format!("iszero(lt({lhs}, {rhs}))"),
+            BinaryOp::Sle => format!("iszero(sgt({}, {}))", slhs(), srhs()),
+            BinaryOp::Sge => format!("iszero(slt({}, {}))", slhs(), srhs()),
+            BinaryOp::Eq => format!("eq({lhs}, {rhs})"),
+            BinaryOp::Ne => format!("iszero(eq({lhs}, {rhs}))"),
+            BinaryOp::And => format!("and({lhs}, {rhs})"),
+            BinaryOp::Or => format!("or({lhs}, {rhs})"),
+            BinaryOp::Xor => format!("xor({lhs}, {rhs})"),
+            // Yul takes the shift amount first.
+            BinaryOp::Shl => mask(format!("shl({rhs}, {lhs})"), ty),
+            BinaryOp::Shr => format!("shr({rhs}, {lhs})"),
+            BinaryOp::Sar => mask(format!("sar({rhs}, {})", slhs()), ty),
+            BinaryOp::Rotl | BinaryOp::Rotr => {
+                let bits = layout::bit_width(ty);
+                let amount = format!("and({rhs}, {})", bits - 1);
+                let (op, rev_op) = if code == BinaryOp::Rotl {
+                    ("shl", "shr")
+                } else {
+                    ("shr", "shl")
+                };
+                mask(
+                    format!("or({op}({amount}, {lhs}), {rev_op}(sub({bits}, {amount}), {lhs}))"),
+                    ty,
+                )
+            }
+        }
+    }
+
+    /// Returns the overflow flag of `code`, which is computed from the operands and the wrapped
+    /// `result`.
+    fn overflow_flag(&self, code: OverflowOp, args: [Value; 2], result: Value) -> String {
+        let ty = self.func.dfg.value_ty(args[0]);
+        let bits = layout::bit_width(ty);
+        let (lhs, rhs, res) = (self.value(args[0]), self.value(args[1]), var(result));
+
+        match code {
+            OverflowOp::Uaddo => format!("lt({res}, {lhs})"),
+            OverflowOp::Usubo => format!("lt({lhs}, {rhs})"),
+            OverflowOp::Saddo => format!(
+                "shr({}, and(xor({res}, {lhs}), xor({res}, {rhs})))",
+                bits - 1
+            ),
+            OverflowOp::Ssubo => format!(
+                "shr({}, and(xor({lhs}, {rhs}), xor({lhs}, {res})))",
+                bits - 1
+            ),
+            OverflowOp::Umulo => {
+                format!("and(iszero(iszero({lhs})), iszero(eq(div({res}, {lhs}), {rhs})))")
+            }
+            // `sdiv` can't tell `-1 * MIN` from `MIN`.
+            OverflowOp::Smulo => format!(
+                "or(and(iszero(iszero({lhs})), iszero(eq(sdiv({}, {}), {}))), and(eq({lhs}, {}), \
+                 eq({rhs}, {})))",
+                sign_extend(res.clone(), ty),
+                sign_extend(lhs.clone(), ty),
+                sign_extend(rhs.clone(), ty),
+                hex(layout::low_mask(bits)),
+                hex(U256::one() << (bits - 1)),
+            ),
+        }
+    }
+
+    fn gep(&self, args: &[Value]) -> String {
+        let ctx = &self.module.ctx;
+        let dfg = &self.func.dfg;
+
+        let base_ty = dfg.value_ty(args[0]);
+        let mut ty = ctx.with_ty_store(|s| s.deref(base_ty)).unwrap();
+        let mut offset = 0;
+        let mut addr = self.value(args[0]);
+        for &idx in &args[1..] {
+            match dfg.value_data(idx) {
+                ValueData::Immediate { imm, .. } => {
+                    let (field_offset, field_ty) = layout::field_offset(ctx, ty, imm.as_usize());
+                    offset += field_offset;
+                    ty = field_ty;
+                }
+
+                _ => {
+                    // Only arrays can be indexed dynamically.
+                    let (elem_size, elem_ty) = layout::field_offset(ctx, ty, 1);
+                    addr = format!("add({addr}, mul({}, {elem_size}))", self.value(idx));
+                    ty = elem_ty;
+                }
+            }
+        }
+
+        if offset == 0 {
+            addr
+        } else {
+            format!("add({addr}, {offset})")
+        }
+    }
+
+    /// Emits the transfer from `from` to `to`, which assigns the phi arguments of the edge in
+    /// parallel.
+    fn emit_edge(&self, w: &mut YulWriter, from: Block, to: Block) {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let copies: SmallVec<[(Value, Value); 4]> = func
+            .layout
+            .iter_insn(to)
+            .take_while(|insn| dfg.is_phi(*insn))
+            .filter_map(|insn| {
+                let InsnData::Phi { values, blocks, .. } = dfg.insn_data(insn) else {
+                    unreachable!();
+                };
+                let idx = blocks.iter().position(|block| *block == from)?;
+                Some((dfg.insn_result(insn).unwrap(), values[idx]))
+            })
+            .collect();
+
+        if let [(phi, arg)] = copies.as_slice() {
+            w.line(format_args!("{} := {}", var(*phi), self.value(*arg)));
+        } else {
+            for (idx, (_, arg)) in copies.iter().enumerate() {
+                w.line(format_args!("let _t{idx} := {}", self.value(*arg)));
+            }
+            for (idx, (phi, _)) in copies.iter().enumerate() {
+                w.line(format_args!("{} := _t{idx}", var(*phi)));
+            }
+        }
+        w.line(format_args!("_block := {}", self.block_indices[&to]));
+    }
+
+    /// Returns the result of `insn` if it has a value.
+    fn result(&self, insn: Insn) -> Option<Value> {
+        let dfg = &self.func.dfg;
+        dfg.insn_result(insn)
+            .filter(|result| dfg.value_ty(*result) != Type::Void)
+    }
+
+    /// Returns the expression of `value`.
+    fn value(&self, value: Value) -> String {
+        match self.func.dfg.value_data(value) {
+            ValueData::Immediate { imm, ty } => {
+                let word = imm.as_i256().to_u256() & layout::low_mask(layout::bit_width(*ty));
+                word.to_string()
+            }
+            ValueData::Global { gv, .. } => format!("{:#x}", self.global_addrs[gv]),
+            ValueData::Arg { .. } | ValueData::Insn { .. } => var(value),
+        }
+    }
+}
+
+/// Writes Yul source with nested blocks indented.
+#[derive(Default)]
+struct YulWriter {
+    buf: String,
+    indent: usize,
+}
+
+impl YulWriter {
+    fn line(&mut self, line: impl fmt::Display) {
+        writeln!(self.buf, "{:indent$}{line}", "", indent = self.indent * 4).unwrap();
+    }
+
+    /// Writes `header` followed by an opening brace, and indents the following lines.
+    fn open(&mut self, header: impl fmt::Display) {
+        self.line(format_args!("{header} {{"));
+        self.indent += 1;
+    }
+
+    fn close(&mut self) {
+        self.indent -= 1;
+        self.line("}");
+    }
+}
+
+fn var(value: Value) -> String {
+    format!("v{}", value.as_u32())
+}
+
+fn func_name(module: &Module, func_ref: FuncRef) -> String {
+    sanitize(module.funcs[func_ref].sig.name())
+}
+
+/// Returns the Yul identifier of a function, which is prefixed so that it doesn't collide with
+/// builtins or variables.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("fun_{name}")
+}
+
+/// Sign-extends `expr` from the width of `ty` to 256 bits.
+fn sign_extend(expr: String, ty: Type) -> String {
+    match layout::bit_width(ty) {
+        256 => expr,
+        1 => format!("sub(0, {expr})"),
+        bits => format!("signextend({}, {expr})", bits / 8 - 1),
+    }
+}
+
+/// Returns the initial value of the result of a bit manipulation, and the steps that update the
+/// result, which is `x`, in turn. See the EVM backend for how they work.
+fn bit_manipulation(code: UnaryOp, ty: Type, arg: String, x: &str) -> (String, Vec<String>) {
+    let bits = layout::bit_width(ty);
+    let popcount = || {
+        let m = |lane: usize, lane_bits| hex(layout::splat(U256::from(lane), lane_bits));
+        vec![
+            format!("sub({x}, and(shr(1, {x}), {}))", m(0x5, 4)),
+            format!(
+                "add(and({x}, {}), and(shr(2, {x}), {}))",
+                m(0x3, 4),
+                m(0x3, 4)
+            ),
+            format!("and(add({x}, shr(4, {x})), {})", m(0xf, 8)),
+            format!("and(add({x}, shr(8, {x})), {})", m(0xff, 16)),
+            format!("shr(240, mul({x}, {}))", m(0x1, 16)),
+        ]
+    };
+    let shifts = |first: usize| {
+        std::iter::successors(Some(first), |shift| Some(shift * 2)).take_while(move |s| *s < bits)
+    };
+
+    match code {
+        UnaryOp::Clz => {
+            let mut steps: Vec<_> = shifts(1)
+                .map(|shift| format!("or({x}, shr({shift}, {x}))"))
+                .collect();
+            steps.extend(popcount());
+            steps.push(format!("sub({bits}, {x})"));
+            (arg, steps)
+        }
+        UnaryOp::Ctz => (
+            mask(format!("sub(and({arg}, sub(0, {arg})), 1)"), ty),
+            popcount(),
+        ),
+        UnaryOp::Popcount => (arg, popcount()),
+        UnaryOp::Bswap => {
+            let steps = shifts(8)
+                .map(|shift| {
+                    let m =
+                        layout::splat(layout::low_mask(shift), shift * 2) & layout::low_mask(bits);
+                    let m = hex(m);
+                    format!("or(and(shr({shift}, {x}), {m}), shl({shift}, and({x}, {m})))")
+                })
+                .collect();
+            (arg, steps)
+        }
+        UnaryOp::Not | UnaryOp::Neg => unreachable!(),
+    }
+}
+
+/// Truncates `expr` to the width of `ty`.
+fn mask(expr: String, ty: Type) -> String {
+    let bits = layout::bit_width(ty);
+    if bits < 256 {
+        format!("and({expr}, {})", hex(layout::low_mask(bits)))
+    } else {
+        expr
+    }
+}
+
+/// Writes a revert with `selector` followed by `args` as words.
+fn emit_revert(w: &mut YulWriter, selector: [u8; 4], args: &[String]) {
+    let selector = u32::from_be_bytes(selector);
+    w.line(format_args!("mstore(0, shl(224, {selector:#010x}))"));
+    for (idx, arg) in args.iter().enumerate() {
+        w.line(format_args!("mstore({}, {arg})", 4 + idx * WORD_SIZE));
+    }
+    w.line(format_args!("revert(0, {})", 4 + args.len() * WORD_SIZE));
+}
+
+fn hex(word: U256) -> String {
+    format!("0x{word:x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::builder::test_util::*;
+
+    #[test]
+    fn emit_loop() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let n = builder.args()[0];
+        let zero = builder.make_imm_value(0i32);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        let acc = builder.phi(Type::I32, &[(zero, b0)]);
+        let i = builder.phi(Type::I32, &[(zero, b0)]);
+        let acc_next = builder.add(acc, i);
+        let one = builder.make_imm_value(1i32);
+        let i_next = builder.add(i, one);
+        builder.append_phi_arg(acc, acc_next, b1);
+        builder.append_phi_arg(i, i_next, b1);
+        let cond = builder.lt(i_next, n);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(acc_next));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let yul = emit_yul(&module, "Test").unwrap();
+
+        assert!(yul.starts_with("object \"Test\" {\n"));
+        assert!(yul.contains("object \"Test_deployed\" {\n"));
+        let selector = u32::from_be_bytes(super::super::abi::selector("test_func(uint32)"));
+        assert!(yul.contains(&format!("case {selector:#010x} {{")));
+        assert!(yul.contains("mstore(0, fun_test_func(and(calldataload(4), 0xffffffff)))"));
+        assert!(yul.contains("function fun_test_func(v0) -> ret {"));
+
+        // Both phis take their arguments from the back edge in parallel.
+        let (acc, i) = (var(acc), var(i));
+        let (acc_next, i_next) = (var(acc_next), var(i_next));
+        assert!(yul.contains(&format!("{acc_next} := and(add({acc}, {i}), 0xffffffff)")));
+        assert!(yul.contains(&format!("let _t0 := {acc_next}")));
+        assert!(yul.contains(&format!("let _t1 := {i_next}")));
+        assert!(yul.contains(&format!("{i} := _t1")));
+        assert!(yul.contains(&format!("ret := {acc_next}")));
+
+        let opened = yul.matches('{').count();
+        assert_eq!(opened, yul.matches('}').count());
+    }
+}
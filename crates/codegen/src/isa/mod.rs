@@ -0,0 +1,3 @@
+//! This module contains code generators for target ISAs.
+pub mod evm;
+pub mod riscv;
@@ -0,0 +1,89 @@
+//! This module contains the emission of statically linked ELF32 executables.
+//!
+//! The executable only has program headers: one loadable segment for the code and, if there are
+//! global variables, one for their initial image. Section headers are optional for loaders, so
+//! they are omitted.
+
+const EHDR_SIZE: u16 = 52;
+const PHDR_SIZE: u16 = 32;
+const PAGE_SIZE: u32 = 0x1000;
+
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+/// A loadable segment of an executable.
+pub struct Segment<'a> {
+    pub vaddr: u32,
+    pub bytes: &'a [u8],
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// Returns an executable that loads `segments` and starts at `entry`. Segments must start at
+/// page-aligned addresses.
+pub fn write_executable(entry: u32, segments: &[Segment]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    // The identification: ELFCLASS32, ELFDATA2LSB, EV_CURRENT and the System V ABI.
+    buf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    buf.resize(16, 0);
+    put_u16(&mut buf, ET_EXEC);
+    put_u16(&mut buf, EM_RISCV);
+    put_u32(&mut buf, 1);
+    put_u32(&mut buf, entry);
+    put_u32(&mut buf, EHDR_SIZE as u32);
+    // There are no section headers.
+    put_u32(&mut buf, 0);
+    put_u32(&mut buf, 0);
+    put_u16(&mut buf, EHDR_SIZE);
+    put_u16(&mut buf, PHDR_SIZE);
+    put_u16(&mut buf, segments.len() as u16);
+    put_u16(&mut buf, 0);
+    put_u16(&mut buf, 0);
+    put_u16(&mut buf, 0);
+
+    // Each segment is placed at a page boundary of the file, as its address is in memory.
+    let mut offset = PAGE_SIZE;
+    let mut offsets = Vec::with_capacity(segments.len());
+    for segment in segments {
+        debug_assert_eq!(segment.vaddr % PAGE_SIZE, 0);
+        let size = segment.bytes.len() as u32;
+        let mut flags = PF_R;
+        if segment.writable {
+            flags |= PF_W;
+        }
+        if segment.executable {
+            flags |= PF_X;
+        }
+
+        put_u32(&mut buf, PT_LOAD);
+        put_u32(&mut buf, offset);
+        put_u32(&mut buf, segment.vaddr);
+        put_u32(&mut buf, segment.vaddr);
+        put_u32(&mut buf, size);
+        put_u32(&mut buf, size);
+        put_u32(&mut buf, flags);
+        put_u32(&mut buf, PAGE_SIZE);
+
+        offsets.push(offset);
+        offset += size.next_multiple_of(PAGE_SIZE);
+    }
+
+    for (segment, offset) in segments.iter().zip(offsets) {
+        buf.resize(offset as usize, 0);
+        buf.extend_from_slice(segment.bytes);
+    }
+    buf
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,500 @@
+//! This module contains the encoding of RV32IM insns, and the assembler that resolves labels and
+//! encodes a function into machine code.
+use cranelift_entity::entity_impl;
+use rustc_hash::FxHashMap;
+use sonatina_ir::module::FuncRef;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub u8);
+
+impl Reg {
+    pub const ZERO: Self = Self(0);
+    pub const RA: Self = Self(1);
+    pub const SP: Self = Self(2);
+    pub const T0: Self = Self(5);
+    pub const T1: Self = Self(6);
+    pub const T2: Self = Self(7);
+    pub const A0: Self = Self(10);
+    pub const A7: Self = Self(17);
+
+    /// Returns the `idx`-th argument register.
+    pub fn arg(idx: usize) -> Self {
+        debug_assert!(idx < ARG_REG_NUM);
+        Self(10 + idx as u8)
+    }
+}
+
+/// The number of registers arguments are passed in, i.e., `a0`-`a7`.
+pub const ARG_REG_NUM: usize = 8;
+
+/// The callee-saved registers `s1`-`s11`, which values are allocated to. Values in them survive
+/// calls, so the temporaries only hold operands of the insn being lowered.
+pub const ALLOCATABLE_REGS: [Reg; 11] = [
+    Reg(9),
+    Reg(18),
+    Reg(19),
+    Reg(20),
+    Reg(21),
+    Reg(22),
+    Reg(23),
+    Reg(24),
+    Reg(25),
+    Reg(26),
+    Reg(27),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Div,
+    Divu,
+}
+
+impl AluOp {
+    /// Returns `funct7` and `funct3`.
+    fn functs(self) -> (u32, u32) {
+        match self {
+            Self::Add => (0x00, 0),
+            Self::Sub => (0x20, 0),
+            Self::Sll => (0x00, 1),
+            Self::Slt => (0x00, 2),
+            Self::Sltu => (0x00, 3),
+            Self::Xor => (0x00, 4),
+            Self::Srl => (0x00, 5),
+            Self::Sra => (0x20, 5),
+            Self::Or => (0x00, 6),
+            Self::And => (0x00, 7),
+            Self::Mul => (0x01, 0),
+            Self::Div => (0x01, 4),
+            Self::Divu => (0x01, 5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluImmOp {
+    Addi,
+    Sltiu,
+    Xori,
+    Andi,
+    Slli,
+    Srli,
+    Srai,
+}
+
+/// The width of a memory access. Loads zero-extend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    pub fn from_size(size: usize) -> Self {
+        match size {
+            1 => Self::Byte,
+            2 => Self::Half,
+            4 => Self::Word,
+            _ => unreachable!("no {size}-byte access on RV32"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inst {
+    Alu {
+        op: AluOp,
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    AluImm {
+        op: AluImmOp,
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    /// Loads `imm << 12` into `rd`.
+    Lui {
+        rd: Reg,
+        imm: u32,
+    },
+    Load {
+        width: Width,
+        rd: Reg,
+        base: Reg,
+        offset: i32,
+    },
+    Store {
+        width: Width,
+        src: Reg,
+        base: Reg,
+        offset: i32,
+    },
+    /// Branches by `offset` bytes if `rs1 == rs2`, or `rs1 != rs2` if `ne` is set.
+    Branch {
+        ne: bool,
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Jal {
+        rd: Reg,
+        offset: i32,
+    },
+    Jalr {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Ecall,
+    Ebreak,
+}
+
+impl Inst {
+    pub fn encode(self) -> u32 {
+        match self {
+            Self::Alu { op, rd, rs1, rs2 } => {
+                let (funct7, funct3) = op.functs();
+                funct7 << 25 | reg(rs2) << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | 0x33
+            }
+
+            Self::AluImm { op, rd, rs1, imm } => {
+                let (funct3, imm) = match op {
+                    AluImmOp::Addi => (0, imm),
+                    AluImmOp::Sltiu => (3, imm),
+                    AluImmOp::Xori => (4, imm),
+                    AluImmOp::Andi => (7, imm),
+                    AluImmOp::Slli => (1, imm & 0x1f),
+                    AluImmOp::Srli => (5, imm & 0x1f),
+                    AluImmOp::Srai => (5, 0x400 | (imm & 0x1f)),
+                };
+                i_type(imm, rs1, funct3, rd, 0x13)
+            }
+
+            Self::Lui { rd, imm } => (imm & 0xf_ffff) << 12 | reg(rd) << 7 | 0x37,
+
+            Self::Load {
+                width,
+                rd,
+                base,
+                offset,
+            } => {
+                let funct3 = match width {
+                    Width::Byte => 4,
+                    Width::Half => 5,
+                    Width::Word => 2,
+                };
+                i_type(offset, base, funct3, rd, 0x03)
+            }
+
+            Self::Store {
+                width,
+                src,
+                base,
+                offset,
+            } => {
+                let funct3 = match width {
+                    Width::Byte => 0,
+                    Width::Half => 1,
+                    Width::Word => 2,
+                };
+                let imm = offset as u32;
+                (imm >> 5 & 0x7f) << 25
+                    | reg(src) << 20
+                    | reg(base) << 15
+                    | funct3 << 12
+                    | (imm & 0x1f) << 7
+                    | 0x23
+            }
+
+            Self::Branch {
+                ne,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                debug_assert!(offset % 2 == 0 && (-4096..4096).contains(&offset));
+                let imm = offset as u32;
+                (imm >> 12 & 1) << 31
+                    | (imm >> 5 & 0x3f) << 25
+                    | reg(rs2) << 20
+                    | reg(rs1) << 15
+                    | u32::from(ne) << 12
+                    | (imm >> 1 & 0xf) << 8
+                    | (imm >> 11 & 1) << 7
+                    | 0x63
+            }
+
+            Self::Jal { rd, offset } => {
+                debug_assert!(offset % 2 == 0 && (-(1 << 20)..1 << 20).contains(&offset));
+                let imm = offset as u32;
+                (imm >> 20 & 1) << 31
+                    | (imm >> 1 & 0x3ff) << 21
+                    | (imm >> 11 & 1) << 20
+                    | (imm >> 12 & 0xff) << 12
+                    | reg(rd) << 7
+                    | 0x6f
+            }
+
+            Self::Jalr { rd, rs1, offset } => i_type(offset, rs1, 0, rd, 0x67),
+
+            Self::Ecall => 0x0000_0073,
+            Self::Ebreak => 0x0010_0073,
+        }
+    }
+}
+
+fn reg(reg: Reg) -> u32 {
+    reg.0 as u32
+}
+
+fn i_type(imm: i32, rs1: Reg, funct3: u32, rd: Reg, opcode: u32) -> u32 {
+    debug_assert!((-2048..2048).contains(&imm));
+    (imm as u32 & 0xfff) << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | opcode
+}
+
+/// Returns `true` if `imm` fits in the 12-bit signed immediate of I- and S-type insns.
+pub fn fits_imm12(imm: i64) -> bool {
+    (-2048..2048).contains(&imm)
+}
+
+/// An opaque reference to an offset in the assembled code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label(u32);
+entity_impl!(Label);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmItem {
+    Inst(Inst),
+
+    /// A `jal` to a label.
+    Jal {
+        rd: Reg,
+        label: Label,
+    },
+
+    /// Binds a label to the current offset.
+    Bind(Label),
+
+    /// Marks the start of the code of a function. Emits no code.
+    FuncStart(FuncRef),
+}
+
+#[derive(Debug, Default)]
+pub struct Assembly {
+    items: Vec<AsmItem>,
+    label_num: u32,
+}
+
+pub struct Assembled {
+    pub code: Vec<u8>,
+    /// The offsets of the functions in `code`.
+    pub func_offsets: Vec<(FuncRef, u32)>,
+}
+
+impl Assembly {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn make_label(&mut self) -> Label {
+        let label = Label(self.label_num);
+        self.label_num += 1;
+        label
+    }
+
+    pub fn push_item(&mut self, item: AsmItem) {
+        self.items.push(item);
+    }
+
+    pub fn inst(&mut self, inst: Inst) {
+        self.items.push(AsmItem::Inst(inst));
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        self.items.push(AsmItem::Bind(label));
+    }
+
+    /// Jumps to `label`, linking the return address in `rd`.
+    pub fn jal(&mut self, rd: Reg, label: Label) {
+        self.items.push(AsmItem::Jal { rd, label });
+    }
+
+    /// Loads the 32-bit `value` into `rd`.
+    pub fn li(&mut self, rd: Reg, value: u32) {
+        if fits_imm12(value as i32 as i64) {
+            self.inst(Inst::AluImm {
+                op: AluImmOp::Addi,
+                rd,
+                rs1: Reg::ZERO,
+                imm: value as i32,
+            });
+            return;
+        }
+
+        // `addi` sign-extends its immediate, so round the upper part up if the lower is negative.
+        let hi = value.wrapping_add(0x800) >> 12;
+        let lo = value.wrapping_sub(hi << 12) as i32;
+        self.inst(Inst::Lui { rd, imm: hi });
+        if lo != 0 {
+            self.inst(Inst::AluImm {
+                op: AluImmOp::Addi,
+                rd,
+                rs1: rd,
+                imm: lo,
+            });
+        }
+    }
+
+    pub fn mv(&mut self, rd: Reg, rs: Reg) {
+        if rd != rs {
+            self.inst(Inst::AluImm {
+                op: AluImmOp::Addi,
+                rd,
+                rs1: rs,
+                imm: 0,
+            });
+        }
+    }
+
+    /// Resolves labels and encodes the assembly. Jumps are relative, so the code can be placed at
+    /// any address.
+    pub fn assemble(&self) -> Assembled {
+        let mut offsets = FxHashMap::default();
+        let mut offset = 0;
+        for item in &self.items {
+            match item {
+                AsmItem::Bind(label) => {
+                    offsets.insert(*label, offset);
+                }
+                AsmItem::Inst(_) | AsmItem::Jal { .. } => offset += 4,
+                AsmItem::FuncStart(_) => {}
+            }
+        }
+
+        let mut code = Vec::with_capacity(offset as usize);
+        let mut func_offsets = Vec::new();
+        for item in &self.items {
+            let pc = code.len() as i32;
+            let inst = match item {
+                AsmItem::Inst(inst) => *inst,
+                AsmItem::Jal { rd, label } => Inst::Jal {
+                    rd: *rd,
+                    offset: offsets[label] - pc,
+                },
+                AsmItem::FuncStart(func_ref) => {
+                    func_offsets.push((*func_ref, pc as u32));
+                    continue;
+                }
+                AsmItem::Bind(_) => continue,
+            };
+            code.extend_from_slice(&inst.encode().to_le_bytes());
+        }
+
+        Assembled { code, func_offsets }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let cases = [
+            (
+                Inst::Alu {
+                    op: AluOp::Add,
+                    rd: Reg::A0,
+                    rs1: Reg::A0,
+                    rs2: Reg::arg(1),
+                },
+                0x00b50533,
+            ),
+            (
+                Inst::Alu {
+                    op: AluOp::Divu,
+                    rd: Reg::T0,
+                    rs1: Reg::T1,
+                    rs2: Reg::T2,
+                },
+                0x027352b3,
+            ),
+            (
+                Inst::AluImm {
+                    op: AluImmOp::Addi,
+                    rd: Reg::SP,
+                    rs1: Reg::SP,
+                    imm: -16,
+                },
+                0xff010113,
+            ),
+            (
+                Inst::AluImm {
+                    op: AluImmOp::Srai,
+                    rd: Reg::T0,
+                    rs1: Reg::T0,
+                    imm: 24,
+                },
+                0x4182d293,
+            ),
+            (
+                Inst::Store {
+                    width: Width::Word,
+                    src: Reg::RA,
+                    base: Reg::SP,
+                    offset: 12,
+                },
+                0x00112623,
+            ),
+            (
+                Inst::Load {
+                    width: Width::Half,
+                    rd: Reg::A0,
+                    base: Reg::SP,
+                    offset: -2,
+                },
+                0xffe15503,
+            ),
+            (
+                Inst::Branch {
+                    ne: false,
+                    rs1: Reg::T0,
+                    rs2: Reg::ZERO,
+                    offset: 8,
+                },
+                0x00028463,
+            ),
+            (
+                Inst::Jal {
+                    rd: Reg::RA,
+                    offset: -8,
+                },
+                0xff9ff0ef,
+            ),
+            (
+                Inst::Jalr {
+                    rd: Reg::ZERO,
+                    rs1: Reg::RA,
+                    offset: 0,
+                },
+                0x00008067,
+            ),
+        ];
+        for (inst, expected) in cases {
+            assert_eq!(inst.encode(), expected, "{inst:?}");
+        }
+    }
+}
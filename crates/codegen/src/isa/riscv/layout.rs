@@ -0,0 +1,159 @@
+//! This module contains the memory layout of types on RV32.
+//!
+//! Scalars are stored little-endian in their byte size and aligned to it, pointers take 4 bytes,
+//! and fields of aggregates are padded to their alignment, as in the C ABI.
+use sonatina_ir::{
+    global_variable::ConstantValue,
+    module::ModuleCtx,
+    types::{CompoundTypeData, BYTES_LEN_FIELD, BYTES_PTR_FIELD},
+    Type,
+};
+
+pub const POINTER_SIZE: usize = 4;
+
+/// Returns the number of bits of a value of `ty` in a register, or `None` if it doesn't fit in
+/// one.
+pub fn bit_width(ty: Type) -> Option<u32> {
+    match ty {
+        Type::I1 => Some(1),
+        Type::I8 => Some(8),
+        Type::I16 => Some(16),
+        Type::I32 | Type::Compound(_) => Some(32),
+        Type::I64 | Type::I128 | Type::I256 | Type::Void => None,
+    }
+}
+
+/// Returns the number of bytes a value of `ty` occupies in memory, including the padding at the
+/// end of an aggregate.
+pub fn size_of(ctx: &ModuleCtx, ty: Type) -> usize {
+    match ty {
+        Type::I1 | Type::I8 => 1,
+        Type::I16 => 2,
+        Type::I32 => 4,
+        Type::I64 => 8,
+        Type::I128 => 16,
+        Type::I256 => 32,
+        Type::Void => 0,
+        Type::Compound(cmpd) => {
+            let data = ctx.with_ty_store(|s| s.resolve_compound(cmpd).clone());
+            match data {
+                CompoundTypeData::Ptr(_) => POINTER_SIZE,
+                CompoundTypeData::Array { elem, len } => len * size_of(ctx, elem),
+                CompoundTypeData::Struct(data) => {
+                    let end = match data.fields.last() {
+                        Some(last) => {
+                            field_offset(ctx, ty, data.fields.len() - 1).0 + size_of(ctx, *last)
+                        }
+                        None => 0,
+                    };
+                    end.next_multiple_of(align_of(ctx, ty))
+                }
+                // The length is an `i256` after the pointer.
+                CompoundTypeData::Bytes => {
+                    let (offset, len_ty) = field_offset(ctx, ty, BYTES_LEN_FIELD);
+                    (offset + size_of(ctx, len_ty)).next_multiple_of(POINTER_SIZE)
+                }
+            }
+        }
+    }
+}
+
+/// Returns the alignment of `ty` in bytes.
+pub fn align_of(ctx: &ModuleCtx, ty: Type) -> usize {
+    match ty {
+        Type::Compound(cmpd) => {
+            let data = ctx.with_ty_store(|s| s.resolve_compound(cmpd).clone());
+            match data {
+                CompoundTypeData::Ptr(_) | CompoundTypeData::Bytes => POINTER_SIZE,
+                CompoundTypeData::Array { elem, .. } => align_of(ctx, elem),
+                CompoundTypeData::Struct(data) if data.packed => 1,
+                CompoundTypeData::Struct(data) => data
+                    .fields
+                    .iter()
+                    .map(|field| align_of(ctx, *field))
+                    .max()
+                    .unwrap_or(1),
+            }
+        }
+        // Wider integers only live in memory, where they are split into words.
+        _ => size_of(ctx, ty).clamp(1, POINTER_SIZE),
+    }
+}
+
+/// Returns the offset of the `idx`-th field of an aggregate `ty`, along with the field type.
+///
+/// # Panics
+/// Panics if `ty` is not an aggregate, or `idx` is out of range of a struct.
+pub fn field_offset(ctx: &ModuleCtx, ty: Type, idx: usize) -> (usize, Type) {
+    let Type::Compound(cmpd) = ty else {
+        panic!("scalar types have no fields");
+    };
+
+    let data = ctx.with_ty_store(|s| s.resolve_compound(cmpd).clone());
+    match data {
+        CompoundTypeData::Array { elem, .. } => (idx * size_of(ctx, elem), elem),
+        CompoundTypeData::Struct(data) => {
+            let mut offset = 0usize;
+            for (i, &field) in data.fields.iter().enumerate() {
+                if !data.packed {
+                    offset = offset.next_multiple_of(align_of(ctx, field));
+                }
+                if i == idx {
+                    break;
+                }
+                offset += size_of(ctx, field);
+            }
+            (offset, data.fields[idx])
+        }
+        CompoundTypeData::Bytes => {
+            let field_ty = ctx.with_ty_store(|s| s.bytes_field(idx));
+            let offset = if idx == BYTES_PTR_FIELD {
+                0
+            } else {
+                POINTER_SIZE
+            };
+            (offset, field_ty)
+        }
+        CompoundTypeData::Ptr(_) => panic!("pointers have no fields"),
+    }
+}
+
+/// Appends the memory image of `value` of `ty` to `buf`, which starts at an address aligned for
+/// `ty`.
+pub fn serialize_const(ctx: &ModuleCtx, ty: Type, value: &ConstantValue, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    match value {
+        ConstantValue::Immediate(imm) => {
+            let layout = ctx.isa.type_layout();
+            buf.extend(layout.to_bytes(imm.as_i256().to_u256(), ty));
+        }
+        ConstantValue::Array(elems) | ConstantValue::Struct(elems) => {
+            for (idx, elem) in elems.iter().enumerate() {
+                let (offset, elem_ty) = field_offset(ctx, ty, idx);
+                buf.resize(start + offset, 0);
+                serialize_const(ctx, elem_ty, elem, buf);
+            }
+        }
+    }
+    buf.resize(start + size_of(ctx, ty), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::isa::IsaBuilder;
+    use sonatina_triple::TargetTriple;
+
+    #[test]
+    fn bytes_covers_its_fields() {
+        let triple = TargetTriple::parse("riscv32-zkvm-rv32im").unwrap();
+        let ctx = ModuleCtx::new(IsaBuilder::new(triple).build());
+        let bytes = ctx.with_ty_store_mut(|s| s.make_bytes());
+
+        let (offset, len_ty) = field_offset(&ctx, bytes, BYTES_LEN_FIELD);
+        assert_eq!((offset, len_ty), (POINTER_SIZE, Type::I256));
+        assert_eq!(size_of(&ctx, bytes), POINTER_SIZE + 32);
+        assert_eq!(align_of(&ctx, bytes), POINTER_SIZE);
+    }
+}
@@ -0,0 +1,972 @@
+//! This module contains the lowering of functions to RV32IM.
+//!
+//! Values live in the registers or spill slots [`RegAlloc`] assigns them, and operands that are
+//! spilled or constant are materialized in the temporaries `t0`-`t2`. A frame is laid out as
+//! follows, and its size is a multiple of 16 as the calling convention requires.
+//!
+//! ```text
+//! sp + 0 | spill slots
+//! ...    | scratch slots for copying phi arguments in parallel
+//! ...    | alloca regions
+//! ...    | saved registers and the return address
+//! ```
+//!
+//! Arguments are passed in `a0`-`a7` and the result is returned in `a0`. Values are kept
+//! zero-extended to the width of their type; signed operations sign-extend their operands first.
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+use sonatina_ir::{
+    insn::{BinaryOp, CastOp, MemOp, OverflowOp, UnaryOp},
+    module::FuncRef,
+    Block, DataLocationKind, Function, GlobalVariable, Insn, InsnData, Module, Type, Value,
+    ValueData,
+};
+
+use super::{
+    inst::{fits_imm12, AluImmOp, AluOp, AsmItem, Assembly, Inst, Label, Reg, Width, ARG_REG_NUM},
+    layout,
+    regalloc::{Loc, RegAlloc},
+    RiscvCodegenError,
+};
+
+/// Labels and addresses shared by all functions in a module.
+pub(super) struct ModuleSymbols {
+    pub func_labels: FxHashMap<FuncRef, Label>,
+    pub global_addrs: FxHashMap<GlobalVariable, u32>,
+}
+
+pub(super) struct FuncLowering<'a> {
+    module: &'a Module,
+    func_ref: FuncRef,
+    func: &'a Function,
+    symbols: &'a ModuleSymbols,
+    asm: &'a mut Assembly,
+    regs: RegAlloc,
+
+    block_labels: FxHashMap<Block, Label>,
+    /// The offset of the phi scratch slots from the stack pointer.
+    scratch_offset: usize,
+    /// Offsets of alloca regions from the stack pointer.
+    allocas: FxHashMap<Insn, usize>,
+    /// Offsets of saved registers from the stack pointer.
+    saved_regs: Vec<(Reg, usize)>,
+    frame_size: usize,
+    /// Jump destinations that copy phi arguments on an edge, i.e., `(label, from, to)`.
+    edge_blocks: Vec<(Label, Block, Block)>,
+}
+
+impl<'a> FuncLowering<'a> {
+    pub(super) fn new(
+        module: &'a Module,
+        func_ref: FuncRef,
+        symbols: &'a ModuleSymbols,
+        asm: &'a mut Assembly,
+    ) -> Result<Self, RiscvCodegenError> {
+        let func = &module.funcs[func_ref];
+        check_types(func)?;
+        let mut lowering = Self {
+            module,
+            func_ref,
+            func,
+            symbols,
+            asm,
+            regs: RegAlloc::new(func),
+            block_labels: FxHashMap::default(),
+            scratch_offset: 0,
+            allocas: FxHashMap::default(),
+            saved_regs: Vec::new(),
+            frame_size: 0,
+            edge_blocks: Vec::new(),
+        };
+        lowering.layout_frame()?;
+        Ok(lowering)
+    }
+
+    pub(super) fn lower(mut self) -> Result<(), RiscvCodegenError> {
+        let func = self.func;
+        for block in func.layout.iter_block() {
+            let label = self.asm.make_label();
+            self.block_labels.insert(block, label);
+        }
+
+        self.asm.push_item(AsmItem::FuncStart(self.func_ref));
+        self.asm.bind(self.symbols.func_labels[&self.func_ref]);
+        self.prologue();
+
+        for block in func.layout.iter_block() {
+            self.asm.bind(self.block_labels[&block]);
+            for insn in func.layout.iter_insn(block) {
+                self.lower_insn(insn)?;
+            }
+
+            for (label, from, to) in std::mem::take(&mut self.edge_blocks) {
+                self.asm.bind(label);
+                self.copy_phi_args(from, to);
+                self.jump_to(to);
+            }
+        }
+        Ok(())
+    }
+
+    fn layout_frame(&mut self) -> Result<(), RiscvCodegenError> {
+        let func = self.func;
+        let mut offset = self.regs.spill_slot_num() * 4;
+
+        self.scratch_offset = offset;
+        let max_phis = func
+            .layout
+            .iter_block()
+            .map(|block| {
+                func.layout
+                    .iter_insn(block)
+                    .take_while(|insn| func.dfg.is_phi(*insn))
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        offset += max_phis * 4;
+
+        let ctx = &self.module.ctx;
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                if let InsnData::Alloca { ty } = func.dfg.insn_data(insn) {
+                    offset = offset.next_multiple_of(layout::align_of(ctx, *ty).max(4));
+                    self.allocas.insert(insn, offset);
+                    offset += layout::size_of(ctx, *ty);
+                }
+            }
+        }
+
+        offset = offset.next_multiple_of(4);
+        for reg in self.regs.used_regs().into_iter().chain([Reg::RA]) {
+            self.saved_regs.push((reg, offset));
+            offset += 4;
+        }
+
+        self.frame_size = offset.next_multiple_of(16);
+        // Offsets from the stack pointer must fit in the immediate of loads and stores.
+        if !fits_imm12(self.frame_size as i64) {
+            return Err(RiscvCodegenError::FrameTooLarge(
+                func.sig.name().to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Allocates the frame, saves registers and moves arguments to their locations.
+    fn prologue(&mut self) {
+        self.asm.inst(Inst::AluImm {
+            op: AluImmOp::Addi,
+            rd: Reg::SP,
+            rs1: Reg::SP,
+            imm: -(self.frame_size as i32),
+        });
+        for (reg, offset) in self.saved_regs.clone() {
+            self.store_word(reg, offset);
+        }
+
+        let func = self.func;
+        for (idx, &arg) in func.arg_values.iter().enumerate() {
+            self.define(arg, Reg::arg(idx));
+        }
+    }
+
+    /// Restores saved registers and frees the frame.
+    fn epilogue(&mut self) {
+        for (reg, offset) in self.saved_regs.clone() {
+            self.load_word(reg, offset);
+        }
+        self.asm.inst(Inst::AluImm {
+            op: AluImmOp::Addi,
+            rd: Reg::SP,
+            rs1: Reg::SP,
+            imm: self.frame_size as i32,
+        });
+    }
+
+    fn lower_insn(&mut self, insn: Insn) -> Result<(), RiscvCodegenError> {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let block = func.layout.insn_block(insn);
+        let result = dfg.insn_result(insn);
+
+        match dfg.insn_data(insn) {
+            InsnData::Unary { code, args } => {
+                let bits = self.bits(args[0]);
+                let src = self.use_value(args[0], Reg::T1);
+                let dst = self.def_reg(result.unwrap());
+                match code {
+                    UnaryOp::Not => self.alu_imm(AluImmOp::Xori, dst, src, -1),
+                    UnaryOp::Neg => self.alu(AluOp::Sub, dst, Reg::ZERO, src),
+                    UnaryOp::Clz | UnaryOp::Ctz | UnaryOp::Popcount | UnaryOp::Bswap => {
+                        self.asm.mv(Reg::T0, src);
+                        self.lower_bit_manipulation(*code, bits);
+                        self.asm.mv(dst, Reg::T0);
+                    }
+                }
+                self.mask(dst, bits);
+                self.define(result.unwrap(), dst);
+            }
+
+            InsnData::Binary { code, args } => self.lower_binary(*code, *args, result.unwrap()),
+
+            InsnData::OverflowBinary { code, args } => {
+                let results = dfg.insn_results(insn);
+                self.lower_overflow_binary(*code, *args, [results[0], results[1]]);
+            }
+
+            InsnData::Cast { code, args, ty } => {
+                let result = result.unwrap();
+                let from = self.bits(args[0]);
+                let to = layout::bit_width(*ty).unwrap();
+                let dst = self.def_reg(result);
+                match code {
+                    CastOp::Sext => {
+                        let src = self.use_copy(args[0], Reg::T1);
+                        self.sign_extend(src, from);
+                        self.mask(src, to);
+                        self.asm.mv(dst, src);
+                    }
+                    CastOp::Trunc => {
+                        let src = self.use_value(args[0], Reg::T1);
+                        self.asm.mv(dst, src);
+                        self.mask(dst, to);
+                    }
+                    CastOp::Zext | CastOp::BitCast => {
+                        let src = self.use_value(args[0], Reg::T1);
+                        self.asm.mv(dst, src);
+                    }
+                }
+                self.define(result, dst);
+            }
+
+            InsnData::Load { args, loc } => {
+                self.check_memory(*loc)?;
+                let result = result.unwrap();
+                let size = layout::size_of(&self.module.ctx, dfg.value_ty(result));
+                let addr = self.use_value(args[0], Reg::T1);
+                let dst = self.def_reg(result);
+                self.asm.inst(Inst::Load {
+                    width: Width::from_size(size),
+                    rd: dst,
+                    base: addr,
+                    offset: 0,
+                });
+                self.define(result, dst);
+            }
+
+            InsnData::Store { args, loc } => {
+                self.check_memory(*loc)?;
+                let [addr, data] = *args;
+                let size = layout::size_of(&self.module.ctx, dfg.value_ty(data));
+                let addr = self.use_value(addr, Reg::T1);
+                let data = self.use_value(data, Reg::T2);
+                self.asm.inst(Inst::Store {
+                    width: Width::from_size(size),
+                    src: data,
+                    base: addr,
+                    offset: 0,
+                });
+            }
+
+            InsnData::Mem { code, args, loc } => {
+                self.check_memory(*loc)?;
+                self.lower_mem(*code, *args);
+            }
+
+            InsnData::Intrinsic { intrinsic, .. } => {
+                return Err(RiscvCodegenError::UnsupportedIntrinsic {
+                    func: func.sig.name().to_string(),
+                    intrinsic: self.module.ctx.isa.with_intrinsics(|intrinsics| {
+                        intrinsics.intrinsic_data(*intrinsic).name.clone()
+                    }),
+                });
+            }
+
+            InsnData::Call {
+                func: callee, args, ..
+            } => {
+                let Some(&callee_label) = self.symbols.func_labels.get(callee) else {
+                    return Err(RiscvCodegenError::ExternalCall {
+                        caller: func.sig.name().to_string(),
+                        callee: self.module.funcs[*callee].sig.name().to_string(),
+                    });
+                };
+                if args.len() > ARG_REG_NUM {
+                    return Err(RiscvCodegenError::TooManyArgs(
+                        self.module.funcs[*callee].sig.name().to_string(),
+                    ));
+                }
+
+                // Values live in callee-saved registers or the frame, so writing the argument
+                // registers doesn't clobber any of them.
+                for (idx, &arg) in args.iter().enumerate() {
+                    let reg = self.use_value(arg, Reg::arg(idx));
+                    self.asm.mv(Reg::arg(idx), reg);
+                }
+                self.asm.jal(Reg::RA, callee_label);
+                if let Some(result) = result.filter(|r| dfg.value_ty(*r) != Type::Void) {
+                    self.define(result, Reg::A0);
+                }
+            }
+
+            InsnData::Jump { dests } => {
+                self.copy_phi_args(block, dests[0]);
+                self.jump_to(dests[0]);
+            }
+
+            InsnData::Branch { args, dests } => {
+                let cond = self.use_value(args[0], Reg::T1);
+                let then = self.edge_label(block, dests[0]);
+                self.jump_if(cond, Reg::ZERO, true, then);
+                self.copy_phi_args(block, dests[1]);
+                self.jump_to(dests[1]);
+            }
+
+            InsnData::BrTable {
+                args,
+                default,
+                table,
+            } => {
+                for (value, dest) in args[1..].iter().zip(table) {
+                    let cond = self.use_value(args[0], Reg::T1);
+                    let key = self.use_value(*value, Reg::T2);
+                    let label = self.edge_label(block, *dest);
+                    self.jump_if(cond, key, false, label);
+                }
+
+                match default {
+                    Some(dest) => {
+                        self.copy_phi_args(block, *dest);
+                        self.jump_to(*dest);
+                    }
+                    None => self.asm.inst(Inst::Ebreak),
+                }
+            }
+
+            InsnData::Alloca { .. } => {
+                let result = result.unwrap();
+                let dst = self.def_reg(result);
+                self.alu_imm(AluImmOp::Addi, dst, Reg::SP, self.allocas[&insn] as i32);
+                self.define(result, dst);
+            }
+
+            InsnData::Return { args } => {
+                if let Some(arg) = *args {
+                    let reg = self.use_value(arg, Reg::A0);
+                    self.asm.mv(Reg::A0, reg);
+                }
+                self.epilogue();
+                self.asm.inst(Inst::Jalr {
+                    rd: Reg::ZERO,
+                    rs1: Reg::RA,
+                    offset: 0,
+                });
+            }
+
+            // zkVMs have no revert data, so all of these halt the program.
+            InsnData::Revert { .. } | InsnData::Trap { .. } | InsnData::Unreachable => {
+                self.asm.inst(Inst::Ebreak)
+            }
+
+            InsnData::Gep { args } => self.lower_gep(args, result.unwrap()),
+
+            // Phi arguments are copied on edges.
+            InsnData::Phi { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    /// Lowers the wrapped result with [`Self::lower_binary`], and then computes the overflow flag
+    /// from the operands and the wrapped result, which are reloaded from their locations.
+    fn lower_overflow_binary(&mut self, code: OverflowOp, args: [Value; 2], results: [Value; 2]) {
+        let [lhs, rhs] = args;
+        let [wrapped, flag] = results;
+        let bits = self.bits(lhs);
+        self.lower_binary(code.unchecked(), args, wrapped);
+
+        let res = self.use_copy(wrapped, Reg::T0);
+        let lhs_reg = self.use_copy(lhs, Reg::T1);
+        let rhs_reg = self.use_copy(rhs, Reg::T2);
+        if code.is_signed() {
+            for reg in [res, lhs_reg, rhs_reg] {
+                self.sign_extend(reg, bits);
+            }
+        }
+
+        match code {
+            // The wrapped sum is less than an operand iff the addition carries.
+            OverflowOp::Uaddo => self.alu(AluOp::Sltu, res, res, lhs_reg),
+            OverflowOp::Usubo => self.alu(AluOp::Sltu, res, lhs_reg, rhs_reg),
+
+            // Signed addition overflows iff the sign of the result differs from the signs of both
+            // operands, and subtraction iff the operands differ in sign and the result has the sign
+            // of `rhs`.
+            OverflowOp::Saddo => {
+                self.alu(AluOp::Xor, lhs_reg, res, lhs_reg);
+                self.alu(AluOp::Xor, rhs_reg, res, rhs_reg);
+                self.alu(AluOp::And, res, lhs_reg, rhs_reg);
+                self.alu_imm(AluImmOp::Srli, res, res, 31);
+            }
+            OverflowOp::Ssubo => {
+                self.alu(AluOp::Xor, rhs_reg, lhs_reg, rhs_reg);
+                self.alu(AluOp::Xor, lhs_reg, lhs_reg, res);
+                self.alu(AluOp::And, res, lhs_reg, rhs_reg);
+                self.alu_imm(AluImmOp::Srli, res, res, 31);
+            }
+
+            // Multiplication overflows iff `lhs != 0 && res / lhs != rhs`.
+            OverflowOp::Umulo | OverflowOp::Smulo => {
+                let div = if code == OverflowOp::Umulo {
+                    AluOp::Divu
+                } else {
+                    AluOp::Div
+                };
+                self.alu(div, res, res, lhs_reg);
+                self.alu(AluOp::Xor, res, res, rhs_reg);
+                self.alu(AluOp::Sltu, res, Reg::ZERO, res);
+                self.alu(AluOp::Sltu, lhs_reg, Reg::ZERO, lhs_reg);
+                self.alu(AluOp::And, res, res, lhs_reg);
+
+                if code == OverflowOp::Smulo {
+                    // `MIN / -1` is `MIN` in RV32, so `-1 * MIN` passes the check above. The
+                    // product of two negative operands is positive, so it also overflows iff the
+                    // result is negative.
+                    let lhs_reg = self.use_copy(lhs, Reg::T1);
+                    let rhs_reg = self.use_copy(rhs, Reg::T2);
+                    self.sign_extend(lhs_reg, bits);
+                    self.sign_extend(rhs_reg, bits);
+                    self.alu(AluOp::And, lhs_reg, lhs_reg, rhs_reg);
+                    let wrapped = self.use_copy(wrapped, Reg::T2);
+                    self.sign_extend(wrapped, bits);
+                    self.alu(AluOp::And, lhs_reg, lhs_reg, wrapped);
+                    self.alu_imm(AluImmOp::Srli, lhs_reg, lhs_reg, 31);
+                    self.alu(AluOp::Or, res, res, lhs_reg);
+                }
+            }
+        }
+        self.define(flag, res);
+    }
+
+    /// Computes `code` of the `bits`-bit value in `t0` into `t0`. RV32IM has no bit manipulation
+    /// insns, so they are expanded into shifts and masks.
+    fn lower_bit_manipulation(&mut self, code: UnaryOp, bits: u32) {
+        let x = Reg::T0;
+        match code {
+            // Smearing the highest one to the right leaves a zero for each leading zero.
+            UnaryOp::Clz => {
+                let mut shift = 1;
+                while shift < bits {
+                    self.alu_imm(AluImmOp::Srli, Reg::T1, x, shift as i32);
+                    self.alu(AluOp::Or, x, x, Reg::T1);
+                    shift *= 2;
+                }
+                self.popcount();
+                self.asm.li(Reg::T1, bits);
+                self.alu(AluOp::Sub, x, Reg::T1, x);
+            }
+
+            // `(x & -x) - 1` has a one for each trailing zero, and all ones if `x` is zero.
+            UnaryOp::Ctz => {
+                self.alu(AluOp::Sub, Reg::T1, Reg::ZERO, x);
+                self.alu(AluOp::And, x, x, Reg::T1);
+                self.alu_imm(AluImmOp::Addi, x, x, -1);
+                self.mask(x, bits);
+                self.popcount();
+            }
+
+            UnaryOp::Popcount => self.popcount(),
+
+            // Swap adjacent bytes, and then adjacent halves: `((x >> n) & mask) | ((x & mask) << n)`.
+            UnaryOp::Bswap => {
+                let mut shift = 8;
+                while shift < bits {
+                    let mask = (0..bits)
+                        .step_by(shift as usize * 2)
+                        .fold(0, |mask, idx| mask | (low_mask(shift) << idx));
+                    self.alu_imm(AluImmOp::Srli, Reg::T1, x, shift as i32);
+                    self.asm.li(Reg::T2, mask);
+                    self.alu(AluOp::And, Reg::T1, Reg::T1, Reg::T2);
+                    self.alu(AluOp::And, x, x, Reg::T2);
+                    self.alu_imm(AluImmOp::Slli, x, x, shift as i32);
+                    self.alu(AluOp::Or, x, x, Reg::T1);
+                    shift *= 2;
+                }
+            }
+
+            UnaryOp::Not | UnaryOp::Neg => unreachable!(),
+        }
+    }
+
+    /// Counts the ones in `t0` into `t0` by summing up the counts of 2-bit, 4-bit and 8-bit lanes.
+    fn popcount(&mut self) {
+        let x = Reg::T0;
+        self.alu_imm(AluImmOp::Srli, Reg::T1, x, 1);
+        self.asm.li(Reg::T2, 0x5555_5555);
+        self.alu(AluOp::And, Reg::T1, Reg::T1, Reg::T2);
+        self.alu(AluOp::Sub, x, x, Reg::T1);
+
+        self.alu_imm(AluImmOp::Srli, Reg::T1, x, 2);
+        self.asm.li(Reg::T2, 0x3333_3333);
+        self.alu(AluOp::And, Reg::T1, Reg::T1, Reg::T2);
+        self.alu(AluOp::And, x, x, Reg::T2);
+        self.alu(AluOp::Add, x, x, Reg::T1);
+
+        self.alu_imm(AluImmOp::Srli, Reg::T1, x, 4);
+        self.alu(AluOp::Add, x, x, Reg::T1);
+        self.asm.li(Reg::T2, 0x0f0f_0f0f);
+        self.alu(AluOp::And, x, x, Reg::T2);
+
+        // Multiplying by `0x01010101` sums up the bytes into the top one.
+        self.asm.li(Reg::T2, 0x0101_0101);
+        self.alu(AluOp::Mul, x, x, Reg::T2);
+        self.alu_imm(AluImmOp::Srli, x, x, 24);
+    }
+
+    fn lower_binary(&mut self, code: BinaryOp, args: [Value; 2], result: Value) {
+        let [lhs, rhs] = args;
+        let bits = self.bits(lhs);
+        let dst = self.def_reg(result);
+
+        let (lhs, rhs) = match code {
+            BinaryOp::Sdiv
+            | BinaryOp::Sar
+            | BinaryOp::Slt
+            | BinaryOp::Sgt
+            | BinaryOp::Sle
+            | BinaryOp::Sge => {
+                let lhs = self.use_copy(lhs, Reg::T1);
+                self.sign_extend(lhs, bits);
+                let rhs = if code == BinaryOp::Sar {
+                    self.use_copy(rhs, Reg::T2)
+                } else {
+                    let rhs = self.use_copy(rhs, Reg::T2);
+                    self.sign_extend(rhs, bits);
+                    rhs
+                };
+                (lhs, rhs)
+            }
+            // The amount is copied, since the guard of the shift overwrites it.
+            BinaryOp::Shl | BinaryOp::Shr => {
+                (self.use_value(lhs, Reg::T1), self.use_copy(rhs, Reg::T2))
+            }
+            _ => (self.use_value(lhs, Reg::T1), self.use_value(rhs, Reg::T2)),
+        };
+
+        match code {
+            BinaryOp::Add => self.alu(AluOp::Add, dst, lhs, rhs),
+            BinaryOp::Sub => self.alu(AluOp::Sub, dst, lhs, rhs),
+            BinaryOp::Mul => self.alu(AluOp::Mul, dst, lhs, rhs),
+            BinaryOp::Udiv => self.alu(AluOp::Divu, dst, lhs, rhs),
+            BinaryOp::Sdiv => self.alu(AluOp::Div, dst, lhs, rhs),
+            BinaryOp::And => self.alu(AluOp::And, dst, lhs, rhs),
+            BinaryOp::Or => self.alu(AluOp::Or, dst, lhs, rhs),
+            BinaryOp::Xor => self.alu(AluOp::Xor, dst, lhs, rhs),
+            // `SLL`, `SRL` and `SRA` only use the low 5 bits of the amount, so shifts by 32 or more
+            // are handled apart. Shifts by the width or more of narrower values are left for the
+            // mask of the result.
+            BinaryOp::Shl | BinaryOp::Shr => {
+                let op = if code == BinaryOp::Shl {
+                    AluOp::Sll
+                } else {
+                    AluOp::Srl
+                };
+                self.alu(op, dst, lhs, rhs);
+                // Clear the result if the amount is 32 or more.
+                self.alu_imm(AluImmOp::Sltiu, rhs, rhs, 32);
+                self.alu(AluOp::Sub, rhs, Reg::ZERO, rhs);
+                self.alu(AluOp::And, dst, dst, rhs);
+            }
+            BinaryOp::Sar => {
+                // An amount of 32 or more is set to all ones, which shifts by 31 and fills the
+                // result with the sign. `dst` is neither operand, which are both copies.
+                self.alu_imm(AluImmOp::Sltiu, dst, rhs, 32);
+                self.alu_imm(AluImmOp::Addi, dst, dst, -1);
+                self.alu(AluOp::Or, rhs, rhs, dst);
+                self.alu(AluOp::Sra, dst, lhs, rhs);
+            }
+            BinaryOp::Rotl | BinaryOp::Rotr => {
+                self.rotate(code == BinaryOp::Rotl, dst, lhs, rhs, bits)
+            }
+
+            BinaryOp::Lt => self.alu(AluOp::Sltu, dst, lhs, rhs),
+            BinaryOp::Gt => self.alu(AluOp::Sltu, dst, rhs, lhs),
+            BinaryOp::Slt => self.alu(AluOp::Slt, dst, lhs, rhs),
+            BinaryOp::Sgt => self.alu(AluOp::Slt, dst, rhs, lhs),
+            BinaryOp::Le | BinaryOp::Ge | BinaryOp::Sle | BinaryOp::Sge => {
+                // `a <= b` is `!(b < a)`, and `a >= b` is `!(a < b)`.
+                let op = if matches!(code, BinaryOp::Le | BinaryOp::Ge) {
+                    AluOp::Sltu
+                } else {
+                    AluOp::Slt
+                };
+                if matches!(code, BinaryOp::Le | BinaryOp::Sle) {
+                    self.alu(op, dst, rhs, lhs);
+                } else {
+                    self.alu(op, dst, lhs, rhs);
+                }
+                self.alu_imm(AluImmOp::Xori, dst, dst, 1);
+            }
+
+            BinaryOp::Eq => {
+                self.alu(AluOp::Xor, dst, lhs, rhs);
+                self.alu_imm(AluImmOp::Sltiu, dst, dst, 1);
+            }
+            BinaryOp::Ne => {
+                self.alu(AluOp::Xor, dst, lhs, rhs);
+                self.alu(AluOp::Sltu, dst, Reg::ZERO, dst);
+            }
+        }
+
+        if matches!(
+            code,
+            BinaryOp::Add
+                | BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Sdiv
+                | BinaryOp::Shl
+                | BinaryOp::Sar
+                | BinaryOp::Rotl
+                | BinaryOp::Rotr
+        ) {
+            self.mask(dst, bits);
+        }
+        self.define(result, dst);
+    }
+
+    /// Rotates `lhs` by `rhs` modulo `bits` into `dst` with two shifts, since RV32IM has no rotate
+    /// insns. The bits shifted past the width are left for the mask of the result.
+    fn rotate(&mut self, left: bool, dst: Reg, lhs: Reg, rhs: Reg, bits: u32) {
+        let (op, rev_op) = if left {
+            (AluOp::Sll, AluOp::Srl)
+        } else {
+            (AluOp::Srl, AluOp::Sll)
+        };
+        self.alu_imm(AluImmOp::Andi, Reg::T0, rhs, bits as i32 - 1);
+        self.alu(op, Reg::T2, lhs, Reg::T0);
+        // A shift by `bits` leaves nothing of a `bits`-bit value, and `SLL` and `SRL` only use
+        // the low 5 bits of the amount, so rotating by zero ORs `lhs` with itself or with zero.
+        self.alu(AluOp::Sub, Reg::T0, Reg::ZERO, Reg::T0);
+        self.alu_imm(AluImmOp::Addi, Reg::T0, Reg::T0, bits as i32);
+        self.alu(rev_op, Reg::T0, lhs, Reg::T0);
+        self.alu(AluOp::Or, dst, Reg::T2, Reg::T0);
+    }
+
+    /// Lowers a memory intrinsic to a loop over bytes with `t0` as the destination, `t1` as the
+    /// source or the fill byte, and `t2` as the number of bytes left.
+    fn lower_mem(&mut self, code: MemOp, args: [Value; 3]) {
+        let (dst, src, len) = (Reg::T0, Reg::T1, Reg::T2);
+        self.use_copy(args[0], dst);
+        self.use_copy(args[1], src);
+        self.use_copy(args[2], len);
+
+        let head = self.asm.make_label();
+        let end = self.asm.make_label();
+        if code == MemOp::Memmove {
+            // Copy backward from the ends if `dst` is above `src`, so that no byte is overwritten
+            // before it's read.
+            let forward = self.asm.make_label();
+            self.alu(AluOp::Sltu, Reg::A0, src, dst);
+            self.jump_if(Reg::A0, Reg::ZERO, false, forward);
+            self.alu(AluOp::Add, dst, dst, len);
+            self.alu(AluOp::Add, src, src, len);
+            let back_head = self.asm.make_label();
+            self.asm.bind(back_head);
+            self.jump_if(len, Reg::ZERO, false, end);
+            self.alu_imm(AluImmOp::Addi, dst, dst, -1);
+            self.alu_imm(AluImmOp::Addi, src, src, -1);
+            self.copy_byte(dst, src);
+            self.alu_imm(AluImmOp::Addi, len, len, -1);
+            self.asm.jal(Reg::ZERO, back_head);
+            self.asm.bind(forward);
+        }
+
+        self.asm.bind(head);
+        self.jump_if(len, Reg::ZERO, false, end);
+        match code {
+            MemOp::Memcpy | MemOp::Memmove => {
+                self.copy_byte(dst, src);
+                self.alu_imm(AluImmOp::Addi, src, src, 1);
+            }
+            MemOp::Memset => self.asm.inst(Inst::Store {
+                width: Width::Byte,
+                src,
+                base: dst,
+                offset: 0,
+            }),
+        }
+        self.alu_imm(AluImmOp::Addi, dst, dst, 1);
+        self.alu_imm(AluImmOp::Addi, len, len, -1);
+        self.asm.jal(Reg::ZERO, head);
+        self.asm.bind(end);
+    }
+
+    /// Copies the byte at `src` to `dst` through `a0`, which is free since values don't live in
+    /// argument registers across insns.
+    fn copy_byte(&mut self, dst: Reg, src: Reg) {
+        self.asm.inst(Inst::Load {
+            width: Width::Byte,
+            rd: Reg::A0,
+            base: src,
+            offset: 0,
+        });
+        self.asm.inst(Inst::Store {
+            width: Width::Byte,
+            src: Reg::A0,
+            base: dst,
+            offset: 0,
+        });
+    }
+
+    fn lower_gep(&mut self, args: &[Value], result: Value) {
+        let (module, func) = (self.module, self.func);
+        let ctx = &module.ctx;
+        let dfg = &func.dfg;
+
+        let base_ty = dfg.value_ty(args[0]);
+        let mut ty = ctx.with_ty_store(|s| s.deref(base_ty)).unwrap();
+        let mut offset = 0;
+
+        let addr = self.use_copy(args[0], Reg::T1);
+        for &idx in &args[1..] {
+            match dfg.value_data(idx) {
+                ValueData::Immediate { imm, .. } => {
+                    let (field_offset, field_ty) = layout::field_offset(ctx, ty, imm.as_usize());
+                    offset += field_offset;
+                    ty = field_ty;
+                }
+
+                _ => {
+                    // Only arrays can be indexed dynamically.
+                    let (elem_size, elem_ty) = layout::field_offset(ctx, ty, 1);
+                    let idx = self.use_copy(idx, Reg::T2);
+                    self.asm.li(Reg::T0, elem_size as u32);
+                    self.alu(AluOp::Mul, idx, idx, Reg::T0);
+                    self.alu(AluOp::Add, addr, addr, idx);
+                    ty = elem_ty;
+                }
+            }
+        }
+
+        if offset != 0 {
+            self.asm.li(Reg::T0, offset as u32);
+            self.alu(AluOp::Add, addr, addr, Reg::T0);
+        }
+        self.define(result, addr);
+    }
+
+    /// Copies phi arguments for the edge `from` -> `to` into the phis in parallel, through the
+    /// scratch slots if there is more than one.
+    fn copy_phi_args(&mut self, from: Block, to: Block) {
+        let func = self.func;
+        let dfg = &func.dfg;
+        let copies: SmallVec<[(Value, Value); 4]> = func
+            .layout
+            .iter_insn(to)
+            .take_while(|insn| dfg.is_phi(*insn))
+            .filter_map(|insn| {
+                let InsnData::Phi { values, blocks, .. } = dfg.insn_data(insn) else {
+                    unreachable!();
+                };
+                let idx = blocks.iter().position(|block| *block == from)?;
+                Some((dfg.insn_result(insn).unwrap(), values[idx]))
+            })
+            .collect();
+
+        if let [(phi, arg)] = copies.as_slice() {
+            let src = self.use_value(*arg, Reg::T1);
+            self.define(*phi, src);
+            return;
+        }
+
+        for (idx, (_, arg)) in copies.iter().enumerate() {
+            let src = self.use_value(*arg, Reg::T1);
+            self.store_word(src, self.scratch_offset + idx * 4);
+        }
+        for (idx, (phi, _)) in copies.iter().enumerate() {
+            let dst = self.def_reg(*phi);
+            self.load_word(dst, self.scratch_offset + idx * 4);
+            self.define(*phi, dst);
+        }
+    }
+
+    /// Returns the label to jump to for the edge `from` -> `to`. If `to` has phis, the label
+    /// refers to code that copies the phi arguments first.
+    fn edge_label(&mut self, from: Block, to: Block) -> Label {
+        let has_phi = self
+            .func
+            .layout
+            .first_insn_of(to)
+            .is_some_and(|insn| self.func.dfg.is_phi(insn));
+        if !has_phi {
+            return self.block_labels[&to];
+        }
+
+        let label = self.asm.make_label();
+        self.edge_blocks.push((label, from, to));
+        label
+    }
+
+    fn jump_to(&mut self, dest: Block) {
+        self.asm.jal(Reg::ZERO, self.block_labels[&dest]);
+    }
+
+    /// Jumps to `label` if `rs1 != rs2`, or `rs1 == rs2` if `ne` is not set. The branch skips a
+    /// `jal` with the opposite condition, since `jal` reaches much further than a branch.
+    fn jump_if(&mut self, rs1: Reg, rs2: Reg, ne: bool, label: Label) {
+        self.asm.inst(Inst::Branch {
+            ne: !ne,
+            rs1,
+            rs2,
+            offset: 8,
+        });
+        self.asm.jal(Reg::ZERO, label);
+    }
+
+    /// Returns the register holding `value`, materializing it in `scratch` unless it lives in a
+    /// register.
+    fn use_value(&mut self, value: Value, scratch: Reg) -> Reg {
+        let func = self.func;
+        match func.dfg.value_data(value) {
+            ValueData::Immediate { imm, ty } => {
+                let bits = layout::bit_width(*ty).unwrap();
+                let word = imm.as_i256().to_u256().low_u32();
+                self.asm.li(scratch, word & low_mask(bits));
+                scratch
+            }
+            ValueData::Global { gv, .. } => {
+                self.asm.li(scratch, self.symbols.global_addrs[gv]);
+                scratch
+            }
+            ValueData::Arg { .. } | ValueData::Insn { .. } => match self.regs.loc(value) {
+                Loc::Reg(reg) => reg,
+                Loc::Stack(slot) => {
+                    self.load_word(scratch, slot * 4);
+                    scratch
+                }
+            },
+        }
+    }
+
+    /// Materializes `value` in `scratch`, which can then be modified.
+    fn use_copy(&mut self, value: Value, scratch: Reg) -> Reg {
+        let reg = self.use_value(value, scratch);
+        self.asm.mv(scratch, reg);
+        scratch
+    }
+
+    /// Returns the register to compute `value` in, which is passed to [`Self::define`] after.
+    fn def_reg(&self, value: Value) -> Reg {
+        match self.regs.loc(value) {
+            Loc::Reg(reg) => reg,
+            Loc::Stack(_) => Reg::T0,
+        }
+    }
+
+    /// Moves `value` computed in `reg` to its location.
+    fn define(&mut self, value: Value, reg: Reg) {
+        match self.regs.loc(value) {
+            Loc::Reg(dst) => self.asm.mv(dst, reg),
+            Loc::Stack(slot) => self.store_word(reg, slot * 4),
+        }
+    }
+
+    fn check_memory(&self, loc: DataLocationKind) -> Result<(), RiscvCodegenError> {
+        match loc {
+            DataLocationKind::Memory => Ok(()),
+            DataLocationKind::Storage => Err(RiscvCodegenError::StorageAccess(
+                self.func.sig.name().to_string(),
+            )),
+        }
+    }
+
+    fn bits(&self, value: Value) -> u32 {
+        layout::bit_width(self.func.dfg.value_ty(value)).unwrap()
+    }
+
+    /// Sign-extends `reg` from `bits` to 32 bits.
+    fn sign_extend(&mut self, reg: Reg, bits: u32) {
+        match bits {
+            32 => {}
+            1 => self.alu(AluOp::Sub, reg, Reg::ZERO, reg),
+            _ => {
+                self.alu_imm(AluImmOp::Slli, reg, reg, 32 - bits as i32);
+                self.alu_imm(AluImmOp::Srai, reg, reg, 32 - bits as i32);
+            }
+        }
+    }
+
+    /// Truncates `reg` to `bits`.
+    fn mask(&mut self, reg: Reg, bits: u32) {
+        match bits {
+            32 => {}
+            1..=11 => self.alu_imm(AluImmOp::Andi, reg, reg, low_mask(bits) as i32),
+            _ => {
+                self.alu_imm(AluImmOp::Slli, reg, reg, 32 - bits as i32);
+                self.alu_imm(AluImmOp::Srli, reg, reg, 32 - bits as i32);
+            }
+        }
+    }
+
+    fn load_word(&mut self, rd: Reg, offset: usize) {
+        self.asm.inst(Inst::Load {
+            width: Width::Word,
+            rd,
+            base: Reg::SP,
+            offset: offset as i32,
+        });
+    }
+
+    fn store_word(&mut self, src: Reg, offset: usize) {
+        self.asm.inst(Inst::Store {
+            width: Width::Word,
+            src,
+            base: Reg::SP,
+            offset: offset as i32,
+        });
+    }
+
+    fn alu(&mut self, op: AluOp, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.asm.inst(Inst::Alu { op, rd, rs1, rs2 });
+    }
+
+    fn alu_imm(&mut self, op: AluImmOp, rd: Reg, rs1: Reg, imm: i32) {
+        self.asm.inst(Inst::AluImm { op, rd, rs1, imm });
+    }
+}
+
+/// Checks that every value of `func` fits in a register, and that it takes few enough arguments
+/// to pass them in registers.
+fn check_types(func: &Function) -> Result<(), RiscvCodegenError> {
+    let name = || func.sig.name().to_string();
+    if func.arg_values.len() > ARG_REG_NUM {
+        return Err(RiscvCodegenError::TooManyArgs(name()));
+    }
+
+    let ret_ty = func.sig.ret_ty();
+    if ret_ty != Type::Void && layout::bit_width(ret_ty).is_none() {
+        return Err(RiscvCodegenError::UnsupportedType(name()));
+    }
+    for block in func.layout.iter_block() {
+        for insn in func.layout.iter_insn(block) {
+            let dfg = &func.dfg;
+            let results = dfg.insn_results(insn);
+            let values = dfg.insn_args(insn).iter().chain(results);
+            for &value in values {
+                // Constant field indices are folded into the offset of a `gep`.
+                let is_const_index =
+                    matches!(dfg.insn_data(insn), InsnData::Gep { .. }) && dfg.is_imm(value);
+                let ty = dfg.value_ty(value);
+                if !is_const_index && ty != Type::Void && layout::bit_width(ty).is_none() {
+                    return Err(RiscvCodegenError::UnsupportedType(name()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn low_mask(bits: u32) -> u32 {
+    u32::MAX >> (32 - bits)
+}
@@ -0,0 +1,534 @@
+//! This module contains the RISC-V backend, which compiles a module into a statically linked
+//! RV32IM executable for zkVMs.
+//!
+//! The executable runs the static initializers and then the entry function, and exits with the
+//! result of the entry in `a0` through the `exit` syscall, i.e., `ecall` with `a7 = 93`, which
+//! zkVMs use to halt. Memory is laid out as follows.
+//!
+//! ```text
+//! 0x0001_0000 | global variables, initialized from the data segment
+//! ...         | code, starting at the next page boundary
+//! ...         | the stack, growing down from 0x0020_0000
+//! ```
+//!
+//! Only values that fit in a register, i.e., of at most 32 bits and pointers, are supported, and
+//! memory accesses are to RAM only since there is no storage.
+use std::fmt;
+
+use rustc_hash::FxHashMap;
+use sonatina_ir::{module::FuncRef, static_init::InitError, GlobalVariable, Module, Type};
+use sonatina_triple::Architecture;
+
+use self::{
+    elf::Segment,
+    inst::{Assembly, Inst, Reg},
+    lower::{FuncLowering, ModuleSymbols},
+};
+
+pub mod elf;
+pub mod inst;
+pub mod layout;
+mod lower;
+pub mod regalloc;
+
+pub const DATA_BASE: u32 = 0x0001_0000;
+pub const STACK_TOP: u32 = 0x0020_0000;
+
+/// The number of the `exit` syscall.
+const SYS_EXIT: u32 = 93;
+
+const PAGE_SIZE: u32 = 0x1000;
+
+#[derive(Debug, Clone)]
+pub struct RiscvArtifact {
+    /// The executable in the ELF format.
+    pub elf: Vec<u8>,
+    /// The machine code, which is loaded at `text_base`.
+    pub text: Vec<u8>,
+    /// The initial image of global variables, which is loaded at `data_base`.
+    pub data: Vec<u8>,
+    pub text_base: u32,
+    pub data_base: u32,
+    /// The addresses of `_start` and the functions in the module.
+    pub symbols: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiscvCodegenError {
+    /// The module targets an ISA other than RISC-V.
+    UnsupportedTarget(String),
+
+    /// The entry is not defined in the module or takes arguments.
+    InvalidEntry(String),
+
+    /// A function has a value that doesn't fit in a register.
+    UnsupportedType(String),
+
+    /// A function takes more arguments than there are argument registers.
+    TooManyArgs(String),
+
+    /// There is no storage to load from or store to on RISC-V.
+    StorageAccess(String),
+
+    /// The frame of a function is out of reach of stack-pointer-relative loads and stores.
+    FrameTooLarge(String),
+
+    /// Calls to external functions need to be resolved by linking first.
+    ExternalCall { caller: String, callee: String },
+
+    /// The target has no intrinsics to lower.
+    UnsupportedIntrinsic { func: String, intrinsic: String },
+
+    /// Static initializers depend on each other in a cycle.
+    StaticInitCycle(Vec<String>),
+
+    /// A static initializer is not defined in the module, takes arguments or returns a value.
+    InvalidStaticInit(String),
+}
+
+impl fmt::Display for RiscvCodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedTarget(triple) => {
+                write!(f, "target `{triple}` is not a RISC-V target")
+            }
+            Self::InvalidEntry(func) => write!(
+                f,
+                "entry `{func}` must be defined in the module and take no arguments"
+            ),
+            Self::UnsupportedType(func) => write!(
+                f,
+                "`{func}` has a value wider than 32 bits, which RV32 doesn't support"
+            ),
+            Self::TooManyArgs(func) => write!(
+                f,
+                "`{func}` takes more than {} arguments",
+                inst::ARG_REG_NUM
+            ),
+            Self::StorageAccess(func) => {
+                write!(f, "`{func}` accesses storage, which RISC-V doesn't have")
+            }
+            Self::FrameTooLarge(func) => write!(f, "the frame of `{func}` is too large"),
+            Self::ExternalCall { caller, callee } => {
+                write!(f, "`{caller}` calls external function `{callee}`")
+            }
+            Self::UnsupportedIntrinsic { func, intrinsic } => {
+                write!(
+                    f,
+                    "`{func}` uses intrinsic `{intrinsic}`, which RISC-V doesn't have"
+                )
+            }
+            Self::StaticInitCycle(cycle) => write!(
+                f,
+                "static initializers depend on each other: `{}`",
+                cycle.join("` -> `")
+            ),
+            Self::InvalidStaticInit(func) => write!(
+                f,
+                "static initializer `{func}` must be defined in the module, take no arguments and \
+                 return nothing"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RiscvCodegenError {}
+
+/// Compiles all functions in `module` into an executable that runs `entry`.
+pub fn compile(module: &Module, entry: FuncRef) -> Result<RiscvArtifact, RiscvCodegenError> {
+    let _span = tracing::info_span!("riscv_compile").entered();
+    let triple = module.ctx.isa.triple();
+    if triple.architecture != Architecture::Riscv32 {
+        return Err(RiscvCodegenError::UnsupportedTarget(triple.to_string()));
+    }
+
+    let name = |func_ref: FuncRef| module.funcs[func_ref].sig.name().to_string();
+    let funcs: Vec<_> = module
+        .iter_functions()
+        .filter(|func_ref| module.funcs[*func_ref].layout.entry_block().is_some())
+        .collect();
+    if !funcs.contains(&entry) || !module.funcs[entry].sig.args().is_empty() {
+        return Err(RiscvCodegenError::InvalidEntry(name(entry)));
+    }
+
+    let inits = module.static_init_order().map_err(|err| match err {
+        InitError::Cycle(cycle) => {
+            RiscvCodegenError::StaticInitCycle(cycle.into_iter().map(name).collect())
+        }
+        InitError::InvalidSignature(func_ref) => {
+            RiscvCodegenError::InvalidStaticInit(name(func_ref))
+        }
+    })?;
+    if let Some(&init) = inits.iter().find(|init| !funcs.contains(init)) {
+        return Err(RiscvCodegenError::InvalidStaticInit(name(init)));
+    }
+
+    let (global_addrs, data) = layout_globals(module);
+    let text_base = DATA_BASE + (data.len() as u32).next_multiple_of(PAGE_SIZE);
+
+    let mut asm = Assembly::new();
+    let symbols = ModuleSymbols {
+        func_labels: funcs
+            .iter()
+            .map(|func_ref| (*func_ref, asm.make_label()))
+            .collect(),
+        global_addrs,
+    };
+
+    emit_start(&mut asm, module, &symbols, &inits, entry);
+    for &func_ref in &funcs {
+        let _span =
+            tracing::debug_span!("lower", func = module.funcs[func_ref].sig.name()).entered();
+        FuncLowering::new(module, func_ref, &symbols, &mut asm)?.lower()?;
+    }
+
+    let assembled = asm.assemble();
+    let mut segments = Vec::new();
+    if !data.is_empty() {
+        segments.push(Segment {
+            vaddr: DATA_BASE,
+            bytes: &data,
+            writable: true,
+            executable: false,
+        });
+    }
+    segments.push(Segment {
+        vaddr: text_base,
+        bytes: &assembled.code,
+        writable: false,
+        executable: true,
+    });
+    let elf = elf::write_executable(text_base, &segments);
+
+    let symbols = std::iter::once(("_start".to_string(), text_base))
+        .chain(
+            assembled
+                .func_offsets
+                .iter()
+                .map(|(func_ref, offset)| (name(*func_ref), text_base + offset)),
+        )
+        .collect();
+    Ok(RiscvArtifact {
+        elf,
+        text: assembled.code,
+        data,
+        text_base,
+        data_base: DATA_BASE,
+        symbols,
+    })
+}
+
+/// Assigns addresses from [`DATA_BASE`] on to global variables, and returns them along with the
+/// initial image of the global region.
+fn layout_globals(module: &Module) -> (FxHashMap<GlobalVariable, u32>, Vec<u8>) {
+    let ctx = &module.ctx;
+    let gvs: Vec<_> = ctx.with_gv_store(|s| {
+        s.all_gvs()
+            .map(|gv| (gv, s.ty(gv), s.init_data(gv).cloned()))
+            .collect()
+    });
+
+    let mut addrs = FxHashMap::default();
+    let mut data = Vec::new();
+    for (gv, ty, init) in gvs {
+        data.resize(data.len().next_multiple_of(layout::align_of(ctx, ty)), 0);
+        addrs.insert(gv, DATA_BASE + data.len() as u32);
+
+        let size = layout::size_of(ctx, ty);
+        match init {
+            Some(init) => layout::serialize_const(ctx, ty, &init, &mut data),
+            None => data.resize(data.len() + size, 0),
+        }
+    }
+
+    (addrs, data)
+}
+
+/// Emits `_start`, which sets up the stack, runs the static initializers in `inits` and `entry`,
+/// and exits with the result of `entry`.
+fn emit_start(
+    asm: &mut Assembly,
+    module: &Module,
+    symbols: &ModuleSymbols,
+    inits: &[FuncRef],
+    entry: FuncRef,
+) {
+    asm.li(Reg::SP, STACK_TOP);
+    for init in inits {
+        asm.jal(Reg::RA, symbols.func_labels[init]);
+    }
+    asm.jal(Reg::RA, symbols.func_labels[&entry]);
+    if module.funcs[entry].sig.ret_ty() == Type::Void {
+        asm.li(Reg::A0, 0);
+    }
+
+    asm.li(Reg::A7, SYS_EXIT);
+    asm.inst(Inst::Ecall);
+    // The syscall doesn't return.
+    asm.inst(Inst::Ebreak);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::ModuleBuilder, func_cursor::InsnInserter, isa::IsaBuilder, module::ModuleCtx,
+        Linkage, Signature,
+    };
+    use sonatina_triple::TargetTriple;
+
+    /// Loads `elf` and runs it until the `exit` syscall, returning the exit code.
+    fn run(elf: &[u8]) -> u32 {
+        let u16_at = |buf: &[u8], at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+        let u32_at =
+            |buf: &[u8], at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+
+        assert_eq!(&elf[..4], b"\x7fELF");
+        assert_eq!(u16_at(elf, 18), 243);
+        let mut mem = vec![0u8; STACK_TOP as usize];
+        let (phoff, phnum) = (u32_at(elf, 28) as usize, u16_at(elf, 44) as usize);
+        for phdr in (0..phnum).map(|i| phoff + i * 32) {
+            let (offset, vaddr, size) = (
+                u32_at(elf, phdr + 4) as usize,
+                u32_at(elf, phdr + 8) as usize,
+                u32_at(elf, phdr + 16) as usize,
+            );
+            mem[vaddr..vaddr + size].copy_from_slice(&elf[offset..offset + size]);
+        }
+
+        let mut regs = [0u32; 32];
+        let mut pc = u32_at(elf, 24);
+        for _ in 0..100_000 {
+            let inst = u32_at(&mem, pc as usize);
+            let rd = (inst >> 7 & 31) as usize;
+            let rs1 = regs[(inst >> 15 & 31) as usize];
+            let rs2 = regs[(inst >> 20 & 31) as usize];
+            let funct3 = inst >> 12 & 7;
+            let imm_i = (inst as i32 >> 20) as u32;
+            let mut next = pc.wrapping_add(4);
+            let value = match inst & 0x7f {
+                0x33 => match (inst >> 25, funct3) {
+                    (0, 0) => rs1.wrapping_add(rs2),
+                    (0x20, 0) => rs1.wrapping_sub(rs2),
+                    (0, 1) => rs1 << (rs2 & 31),
+                    (0, 2) => ((rs1 as i32) < (rs2 as i32)) as u32,
+                    (0, 3) => (rs1 < rs2) as u32,
+                    (0, 4) => rs1 ^ rs2,
+                    (0, 5) => rs1 >> (rs2 & 31),
+                    (0x20, 5) => (rs1 as i32 >> (rs2 & 31)) as u32,
+                    (0, 6) => rs1 | rs2,
+                    (0, 7) => rs1 & rs2,
+                    (1, 0) => rs1.wrapping_mul(rs2),
+                    (1, 4) => (rs1 as i32).checked_div(rs2 as i32).unwrap_or(-1) as u32,
+                    (1, 5) => rs1.checked_div(rs2).unwrap_or(u32::MAX),
+                    _ => panic!("unknown insn {inst:#010x}"),
+                },
+                0x13 => match funct3 {
+                    0 => rs1.wrapping_add(imm_i),
+                    3 => (rs1 < imm_i) as u32,
+                    4 => rs1 ^ imm_i,
+                    7 => rs1 & imm_i,
+                    1 => rs1 << (imm_i & 31),
+                    5 if inst >> 30 & 1 == 1 => (rs1 as i32 >> (imm_i & 31)) as u32,
+                    5 => rs1 >> (imm_i & 31),
+                    _ => panic!("unknown insn {inst:#010x}"),
+                },
+                0x37 => inst & 0xffff_f000,
+                0x03 => {
+                    let addr = rs1.wrapping_add(imm_i) as usize;
+                    match funct3 {
+                        2 => u32_at(&mem, addr),
+                        4 => mem[addr] as u32,
+                        5 => u16_at(&mem, addr) as u32,
+                        _ => panic!("unknown insn {inst:#010x}"),
+                    }
+                }
+                0x23 => {
+                    let imm = ((inst as i32 >> 25) << 5) as u32 | (inst >> 7 & 31);
+                    let addr = rs1.wrapping_add(imm) as usize;
+                    let size = 1 << funct3;
+                    mem[addr..addr + size].copy_from_slice(&rs2.to_le_bytes()[..size]);
+                    pc = next;
+                    continue;
+                }
+                0x63 => {
+                    let imm = ((inst as i32 >> 31) << 12) as u32
+                        | (inst & 0x80) << 4
+                        | (inst >> 20 & 0x7e0)
+                        | (inst >> 7 & 0x1e);
+                    if (rs1 == rs2) == (funct3 == 0) {
+                        next = pc.wrapping_add(imm);
+                    }
+                    pc = next;
+                    continue;
+                }
+                0x6f => {
+                    let imm = ((inst as i32 >> 31) << 20) as u32
+                        | (inst & 0xff000)
+                        | (inst >> 9 & 0x800)
+                        | (inst >> 20 & 0x7fe);
+                    next = pc.wrapping_add(imm);
+                    pc.wrapping_add(4)
+                }
+                0x67 => {
+                    next = rs1.wrapping_add(imm_i) & !1;
+                    pc.wrapping_add(4)
+                }
+                0x73 if inst == 0x73 => {
+                    assert_eq!(regs[17], SYS_EXIT);
+                    return regs[10];
+                }
+                _ => panic!("unknown insn {inst:#010x}"),
+            };
+            if rd != 0 {
+                regs[rd] = value;
+            }
+            pc = next;
+        }
+        panic!("the program didn't exit");
+    }
+
+    fn riscv_builder() -> ModuleBuilder {
+        let triple = TargetTriple::parse("riscv32-zkvm-rv32im").unwrap();
+        ModuleBuilder::new(ModuleCtx::new(IsaBuilder::new(triple).build()))
+    }
+
+    #[test]
+    fn compile_and_run() {
+        let mut mb = riscv_builder();
+        let sum_to = mb.declare_function(Signature::new(
+            "sum_to",
+            Linkage::Private,
+            &[Type::I32],
+            Type::I32,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(sum_to);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let n = builder.args()[0];
+        let zero = builder.make_imm_value(0i32);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        let acc = builder.phi(Type::I32, &[(zero, b0)]);
+        let i = builder.phi(Type::I32, &[(zero, b0)]);
+        let acc_next = builder.add(acc, i);
+        let one = builder.make_imm_value(1i32);
+        let i_next = builder.add(i, one);
+        builder.append_phi_arg(acc, acc_next, b1);
+        builder.append_phi_arg(i, i_next, b1);
+        let cond = builder.lt(i_next, n);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(acc_next));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let main = mb.declare_function(Signature::new("main", Linkage::Public, &[], Type::I32));
+        let mut builder = mb.build_function::<InsnInserter>(main);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let n = builder.make_imm_value(30i32);
+        let sum = builder.call(sum_to, &[n]).unwrap();
+        // The sum is 435, which wraps to 179 in 8 bits.
+        let byte = builder.trunc(sum, Type::I8);
+        let ret = builder.zext(byte, Type::I32);
+        builder.ret(Some(ret));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module, main).unwrap();
+        assert_eq!(artifact.text_base, DATA_BASE);
+        assert_eq!(
+            artifact
+                .symbols
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            ["_start", "sum_to", "main"]
+        );
+        assert_eq!(run(&artifact.elf), 179);
+    }
+
+    #[test]
+    fn shift_by_32_or_more() {
+        let mut mb = riscv_builder();
+        let shifts = mb.declare_function(Signature::new(
+            "shifts",
+            Linkage::Private,
+            &[Type::I32, Type::I32],
+            Type::I32,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(shifts);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (x, amount) = (builder.args()[0], builder.args()[1]);
+        let shl = builder.shl(x, amount);
+        let shr = builder.shr(x, amount);
+        let sar = builder.sar(x, amount);
+        let ret = builder.or(shl, shr);
+        let ret = builder.or(ret, sar);
+        builder.ret(Some(ret));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let shl8 = mb.declare_function(Signature::new(
+            "shl8",
+            Linkage::Private,
+            &[Type::I8, Type::I8],
+            Type::I8,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(shl8);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (x, amount) = (builder.args()[0], builder.args()[1]);
+        let ret = builder.shl(x, amount);
+        builder.ret(Some(ret));
+        builder.seal_all();
+        mb = builder.finish();
+
+        let main = mb.declare_function(Signature::new("main", Linkage::Public, &[], Type::I32));
+        let mut builder = mb.build_function::<InsnInserter>(main);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        // `shl` and `shr` by 33 are 0, and `sar` fills the result with the sign of `x`, which is
+        // positive.
+        let x = builder.make_imm_value(0x4000_0001i32);
+        let amount = builder.make_imm_value(33i32);
+        let positive = builder.call(shifts, &[x, amount]).unwrap();
+        let x = builder.make_imm_value(i32::MIN + 1);
+        let amount = builder.make_imm_value(32i32);
+        let negative = builder.call(shifts, &[x, amount]).unwrap();
+        let x = builder.make_imm_value(5i8);
+        let amount = builder.make_imm_value(32i8);
+        let byte = builder.call(shl8, &[x, amount]).unwrap();
+        let byte = builder.zext(byte, Type::I32);
+        // `0 + -1 + 0` if all shifts are in range.
+        let ret = builder.add(positive, negative);
+        let ret = builder.add(ret, byte);
+        builder.ret(Some(ret));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let artifact = compile(&module, main).unwrap();
+        assert_eq!(run(&artifact.elf), u32::MAX);
+    }
+
+    #[test]
+    fn reject_evm_target() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(
+            sonatina_ir::builder::test_util::build_test_isa(),
+        ));
+        let main = mb.declare_function(Signature::new("main", Linkage::Public, &[], Type::Void));
+        let module = mb.build();
+        assert!(matches!(
+            compile(&module, main),
+            Err(RiscvCodegenError::UnsupportedTarget(_))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! This module contains a linear scan register allocator.
+//!
+//! Insns are numbered in layout order, and each value gets a single live interval from its
+//! definition to its last use, extended over the blocks it is live in. Intervals are then visited
+//! in order of their start, and a value whose interval overlaps those of all free registers is
+//! spilled to a stack slot; when registers run out, the value whose interval ends last is the one
+//! that's spilled.
+//!
+//! Phi arguments are copied at the end of the predecessor, so the interval of a phi covers the
+//! end of each of its predecessors, too.
+use rustc_hash::FxHashMap;
+use sonatina_ir::{Block, ControlFlowGraph, Function, InsnData, Type, Value, ValueData};
+
+use crate::liveness::Liveness;
+
+use super::inst::{Reg, ALLOCATABLE_REGS};
+
+/// Where a value lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loc {
+    Reg(Reg),
+    /// The index of a word-sized spill slot in the frame.
+    Stack(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct RegAlloc {
+    locs: FxHashMap<Value, Loc>,
+    spill_slot_num: usize,
+}
+
+impl RegAlloc {
+    pub fn new(func: &Function) -> Self {
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut liveness = Liveness::new();
+        liveness.compute(func, &cfg);
+
+        let mut intervals = live_intervals(func, &liveness);
+        intervals.sort_unstable_by_key(|(value, start, _)| (*start, *value));
+
+        let mut alloc = Self::default();
+        let mut free: Vec<_> = ALLOCATABLE_REGS.iter().rev().copied().collect();
+        // Intervals that hold a register, i.e., `(end, value, reg)`.
+        let mut active: Vec<(usize, Value, Reg)> = Vec::new();
+        for (value, start, end) in intervals {
+            active.retain(|&(active_end, _, reg)| {
+                let expired = active_end < start;
+                if expired {
+                    free.push(reg);
+                }
+                !expired
+            });
+
+            if let Some(reg) = free.pop() {
+                active.push((end, value, reg));
+                alloc.locs.insert(value, Loc::Reg(reg));
+                continue;
+            }
+
+            let (furthest, &(furthest_end, spilled, reg)) = active
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (end, ..))| *end)
+                .unwrap();
+            if furthest_end > end {
+                active[furthest] = (end, value, reg);
+                alloc.locs.insert(value, Loc::Reg(reg));
+                alloc.spill(spilled);
+            } else {
+                alloc.spill(value);
+            }
+        }
+
+        alloc
+    }
+
+    /// Returns where `value` lives. Only arguments and insn results have a location.
+    pub fn loc(&self, value: Value) -> Loc {
+        self.locs[&value]
+    }
+
+    /// Returns the registers that hold values, which the function needs to save.
+    pub fn used_regs(&self) -> Vec<Reg> {
+        let mut regs: Vec<_> = self
+            .locs
+            .values()
+            .filter_map(|loc| match loc {
+                Loc::Reg(reg) => Some(*reg),
+                Loc::Stack(_) => None,
+            })
+            .collect();
+        regs.sort_unstable();
+        regs.dedup();
+        regs
+    }
+
+    pub fn spill_slot_num(&self) -> usize {
+        self.spill_slot_num
+    }
+
+    fn spill(&mut self, value: Value) {
+        self.locs.insert(value, Loc::Stack(self.spill_slot_num));
+        self.spill_slot_num += 1;
+    }
+}
+
+/// Returns the live interval of each argument and insn result as `(value, start, end)`.
+fn live_intervals(func: &Function, liveness: &Liveness) -> Vec<(Value, usize, usize)> {
+    let mut ranges = FxHashMap::<Value, (usize, usize)>::default();
+    let mut extend = |value: Value, pos: usize| {
+        let range = ranges.entry(value).or_insert((pos, pos));
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+
+    // Arguments are defined before the first insn, at position 0.
+    for &arg in &func.arg_values {
+        extend(arg, 0);
+    }
+
+    let mut block_ranges = FxHashMap::<Block, (usize, usize)>::default();
+    let mut pos = 1;
+    for block in func.layout.iter_block() {
+        let first = pos;
+        pos += func.layout.iter_insn(block).count();
+        block_ranges.insert(block, (first, pos - 1));
+    }
+
+    let is_tracked = |value: Value| {
+        matches!(
+            func.dfg.value_data(value),
+            ValueData::Insn { .. } | ValueData::Arg { .. }
+        )
+    };
+    for block in func.layout.iter_block() {
+        let (first, last) = block_ranges[&block];
+        for (pos, insn) in (first..).zip(func.layout.iter_insn(block)) {
+            let mut results = func
+                .dfg
+                .insn_results(insn)
+                .iter()
+                .copied()
+                .filter(|result| func.dfg.value_ty(*result) != Type::Void);
+
+            if let InsnData::Phi { values, blocks, .. } = func.dfg.insn_data(insn) {
+                let result = results.next().unwrap();
+                extend(result, first);
+                for (&value, pred) in values.iter().zip(blocks) {
+                    let pred_last = block_ranges[pred].1;
+                    extend(result, pred_last);
+                    if is_tracked(value) {
+                        extend(value, pred_last);
+                    }
+                }
+                continue;
+            }
+
+            for result in results {
+                extend(result, pos);
+            }
+            for &arg in func.dfg.insn_args(insn) {
+                if is_tracked(arg) {
+                    extend(arg, pos);
+                }
+            }
+        }
+
+        for &value in liveness.live_in(block) {
+            extend(value, first);
+        }
+        for &value in liveness.live_out(block) {
+            extend(value, last);
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(value, (start, end))| (value, start, end))
+        .collect()
+}
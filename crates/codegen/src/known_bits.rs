@@ -0,0 +1,315 @@
+//! This module contains a known-bits analysis, which computes the bits of each value that are the
+//! same in every execution, e.g., the upper bits of a `zext` result or of an `and` with a mask.
+//!
+//! A value is seen as an unsigned integer of the width of its type, so the bits above the width
+//! are known to be zero. The known bits also give the unsigned range of a value: it's at least the
+//! bits that are known to be one, and at most the bits that aren't known to be zero.
+//!
+//! Blocks are visited once in reverse post order. A phi with an argument that isn't visited yet,
+//! i.e., one that comes in through a back edge, has no known bits.
+use cranelift_entity::SecondaryMap;
+
+use sonatina_ir::{
+    insn::{BinaryOp, CastOp, UnaryOp},
+    ControlFlowGraph, Function, Insn, InsnData, Type, Value, ValueData, U256,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownBits {
+    /// The bits that are known to be zero.
+    pub zero: U256,
+    /// The bits that are known to be one.
+    pub one: U256,
+}
+
+impl KnownBits {
+    /// Returns the known bits of a `bits`-bit value that nothing else is known about.
+    pub fn unknown(bits: usize) -> Self {
+        Self {
+            zero: !low_mask(bits),
+            one: U256::zero(),
+        }
+    }
+
+    pub fn constant(val: U256) -> Self {
+        Self {
+            zero: !val,
+            one: val,
+        }
+    }
+
+    /// Returns the smallest unsigned value with these bits.
+    pub fn umin(self) -> U256 {
+        self.one
+    }
+
+    /// Returns the largest unsigned value with these bits.
+    pub fn umax(self) -> U256 {
+        !self.zero
+    }
+
+    /// Returns `true` if the sign bit of a `bits`-bit value is known to be zero.
+    pub fn is_non_negative(self, bits: usize) -> bool {
+        self.zero.bit(bits - 1)
+    }
+
+    /// Returns `true` if the sign bit of a `bits`-bit value is known to be one.
+    pub fn is_negative(self, bits: usize) -> bool {
+        self.one.bit(bits - 1)
+    }
+
+    /// Returns the bits known in both `self` and `other`, e.g., the known bits of a phi.
+    pub fn intersect(self, other: Self) -> Self {
+        Self {
+            zero: self.zero & other.zero,
+            one: self.one & other.one,
+        }
+    }
+
+    fn is_constant(self) -> bool {
+        self.zero | self.one == U256::MAX
+    }
+
+    /// Returns the number of low bits that are known to be zero.
+    fn trailing_zeros(self) -> usize {
+        self.umax().trailing_zeros() as usize
+    }
+
+    fn with_trailing_zeros(self, num: usize) -> Self {
+        Self {
+            zero: self.zero | low_mask(num),
+            one: self.one & !low_mask(num),
+        }
+    }
+
+    /// Returns the known bits of a `bits`-bit value that is at most `max`.
+    fn at_most(max: U256, bits: usize) -> Self {
+        Self::unknown(bits.min(max.bits()))
+    }
+
+    fn trunc(self, bits: usize) -> Self {
+        Self {
+            zero: self.zero | !low_mask(bits),
+            one: self.one & low_mask(bits),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct KnownBitsAnalysis {
+    bits: SecondaryMap<Value, Option<KnownBits>>,
+}
+
+impl KnownBitsAnalysis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compute(&mut self, func: &Function, cfg: &ControlFlowGraph) {
+        self.clear();
+
+        let mut rpo: Vec<_> = cfg.post_order().collect();
+        rpo.reverse();
+        for block in rpo {
+            for insn in func.layout.iter_insn(block) {
+                let results = func.dfg.insn_results(insn);
+                let Some(&result) = results.first() else {
+                    continue;
+                };
+                self.bits[result] = Some(self.transfer(func, insn, result));
+
+                // The other results are overflow flags.
+                for &result in &results[1..] {
+                    self.bits[result] = Some(KnownBits::unknown(1));
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+
+    /// Returns the known bits of `value`.
+    pub fn known_bits(&self, func: &Function, value: Value) -> KnownBits {
+        self.try_known_bits(func, value)
+            .unwrap_or_else(|| KnownBits::unknown(bit_width(func.dfg.value_ty(value))))
+    }
+
+    /// Returns the known bits of `value`, or `None` if it's the result of an insn that isn't
+    /// analyzed yet.
+    fn try_known_bits(&self, func: &Function, value: Value) -> Option<KnownBits> {
+        match func.dfg.value_data(value) {
+            ValueData::Immediate { imm, .. } => Some(KnownBits::constant(imm.as_unsigned())),
+            ValueData::Insn { .. } => self.bits[value],
+            ValueData::Arg { ty, .. } | ValueData::Global { ty, .. } => {
+                Some(KnownBits::unknown(bit_width(*ty)))
+            }
+        }
+    }
+
+    fn transfer(&self, func: &Function, insn: Insn, result: Value) -> KnownBits {
+        let bits = bit_width(func.dfg.value_ty(result));
+        let arg = |idx: usize| self.known_bits(func, func.dfg.insn_arg(insn, idx));
+
+        match func.dfg.insn_data(insn) {
+            InsnData::Unary {
+                code: UnaryOp::Not, ..
+            } => {
+                let arg = arg(0);
+                KnownBits {
+                    zero: arg.one | !low_mask(bits),
+                    one: arg.zero & low_mask(bits),
+                }
+            }
+
+            // A count of bits is at most the width.
+            InsnData::Unary {
+                code: UnaryOp::Clz | UnaryOp::Ctz | UnaryOp::Popcount,
+                ..
+            } => KnownBits::at_most(U256::from(bits), bits),
+
+            InsnData::Binary { code, .. } => binary(*code, arg(0), arg(1), bits),
+            InsnData::OverflowBinary { code, .. } => binary(code.unchecked(), arg(0), arg(1), bits),
+
+            InsnData::Cast { code, args, .. } => {
+                let from = bit_width(func.dfg.value_ty(args[0]));
+                match code {
+                    CastOp::Zext => arg(0),
+                    CastOp::Sext if arg(0).is_non_negative(from) => arg(0),
+                    CastOp::Trunc => arg(0).trunc(bits),
+                    CastOp::Sext | CastOp::BitCast => KnownBits::unknown(bits),
+                }
+            }
+
+            InsnData::Phi { values, .. } => values
+                .iter()
+                .map(|value| self.try_known_bits(func, *value))
+                .reduce(|lhs, rhs| Some(lhs?.intersect(rhs?)))
+                .flatten()
+                .unwrap_or_else(|| KnownBits::unknown(bits)),
+
+            _ => KnownBits::unknown(bits),
+        }
+    }
+}
+
+fn binary(code: BinaryOp, lhs: KnownBits, rhs: KnownBits, bits: usize) -> KnownBits {
+    match code {
+        BinaryOp::And => KnownBits {
+            zero: lhs.zero | rhs.zero,
+            one: lhs.one & rhs.one,
+        },
+        BinaryOp::Or => KnownBits {
+            zero: lhs.zero & rhs.zero,
+            one: lhs.one | rhs.one,
+        },
+        BinaryOp::Xor => KnownBits {
+            zero: (lhs.zero & rhs.zero) | (lhs.one & rhs.one),
+            one: (lhs.zero & rhs.one) | (lhs.one & rhs.zero),
+        },
+
+        // Wrapping keeps the low bits, so they are known even if the result overflows.
+        BinaryOp::Add | BinaryOp::Sub => {
+            let low = lhs.trailing_zeros().min(rhs.trailing_zeros());
+            let result = match lhs.umax().checked_add(rhs.umax()) {
+                Some(max) if code == BinaryOp::Add => KnownBits::at_most(max, bits),
+                _ => KnownBits::unknown(bits),
+            };
+            result.with_trailing_zeros(low.min(bits))
+        }
+        BinaryOp::Mul => {
+            let low = lhs.trailing_zeros().saturating_add(rhs.trailing_zeros());
+            let result = match lhs.umax().checked_mul(rhs.umax()) {
+                Some(max) => KnownBits::at_most(max, bits),
+                None => KnownBits::unknown(bits),
+            };
+            result.with_trailing_zeros(low.min(bits))
+        }
+        BinaryOp::Udiv => KnownBits::at_most(lhs.umax(), bits),
+
+        BinaryOp::Shl | BinaryOp::Shr | BinaryOp::Sar if rhs.is_constant() => {
+            if rhs.one >= U256::from(bits) {
+                return KnownBits::unknown(bits);
+            }
+            let amount = rhs.one.as_usize();
+            let shr = KnownBits {
+                zero: (lhs.zero >> amount) | !(U256::MAX >> amount),
+                one: lhs.one >> amount,
+            };
+            match code {
+                BinaryOp::Shl => KnownBits {
+                    zero: (lhs.zero << amount) | low_mask(amount) | !low_mask(bits),
+                    one: (lhs.one << amount) & low_mask(bits),
+                },
+                BinaryOp::Shr => shr,
+                // An arithmetic shift of a non-negative value is a logical shift.
+                _ if lhs.is_non_negative(bits) => shr,
+                _ => KnownBits::unknown(bits),
+            }
+        }
+
+        _ => KnownBits::unknown(bits),
+    }
+}
+
+/// Returns the width of values of `ty`. Values that aren't integers are treated as 256-bit
+/// integers.
+pub(crate) fn bit_width(ty: Type) -> usize {
+    match ty {
+        Type::I1 => 1,
+        Type::I8 => 8,
+        Type::I16 => 16,
+        Type::I32 => 32,
+        Type::I64 => 64,
+        Type::I128 => 128,
+        _ => 256,
+    }
+}
+
+pub(crate) fn low_mask(bits: usize) -> U256 {
+    if bits >= 256 {
+        U256::MAX
+    } else {
+        (U256::one() << bits) - U256::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::builder::test_util::*;
+
+    #[test]
+    fn masked_and_extended_values() {
+        let mut builder = test_func_builder(&[Type::I8, Type::I32], Type::Void);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (x, y) = (builder.args()[0], builder.args()[1]);
+        let v2 = builder.zext(x, Type::I32);
+        let mask = builder.make_imm_value(0xf0i32);
+        let v3 = builder.and(y, mask);
+        let v4 = builder.add(v2, v3);
+        let two = builder.make_imm_value(2i32);
+        let v5 = builder.shl(v3, two);
+        builder.ret(None);
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut analysis = KnownBitsAnalysis::new();
+        analysis.compute(func, &cfg);
+
+        assert_eq!(analysis.known_bits(func, v2).umax(), U256::from(0xff));
+        let v3 = analysis.known_bits(func, v3);
+        assert_eq!(v3.umax(), U256::from(0xf0));
+        assert_eq!(v3.zero, !U256::from(0xf0));
+        assert_eq!(analysis.known_bits(func, v4).umax(), U256::from(0x1ff));
+        assert_eq!(analysis.known_bits(func, v5).umax(), U256::from(0x3c0));
+        assert_eq!(analysis.known_bits(func, y).umax(), U256::from(u32::MAX));
+    }
+}
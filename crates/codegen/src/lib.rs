@@ -2,8 +2,22 @@
 // See <https://github.com/rust-lang/rust-clippy/issues/7512> and <https://github.com/rust-lang/rust-clippy/issues/7336>
 #![allow(clippy::needless_collect)]
 
+pub mod analyses;
+pub mod block_frequency;
 pub mod critical_edge;
+pub mod debug_info;
 pub mod domtree;
+pub mod graphviz;
+pub mod isa;
+pub mod known_bits;
+pub mod liveness;
 pub mod loop_analysis;
 pub mod optim;
+pub mod pass_manager;
 pub mod post_domtree;
+pub mod reloc;
+pub mod revert_analysis;
+pub mod single_exit;
+pub mod stats;
+pub mod switch_cluster;
+pub mod thunk;
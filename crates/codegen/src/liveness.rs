@@ -0,0 +1,161 @@
+//! This module contains a liveness analysis, which computes the values live on entry to and on
+//! exit from each block.
+//!
+//! Only values defined by insns and function arguments are tracked; immediates and global
+//! variables are never live. A phi uses its argument at the end of the corresponding predecessor,
+//! so the argument is live on exit from the predecessor, but not on entry to the block of the phi.
+use std::collections::BTreeSet;
+
+use cranelift_entity::SecondaryMap;
+
+use sonatina_ir::{Block, ControlFlowGraph, Function, InsnData, Value, ValueData};
+
+#[derive(Debug, Default)]
+pub struct Liveness {
+    live_in: SecondaryMap<Block, BTreeSet<Value>>,
+    live_out: SecondaryMap<Block, BTreeSet<Value>>,
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compute(&mut self, func: &Function, cfg: &ControlFlowGraph) {
+        self.clear();
+
+        let mut uses = SecondaryMap::<Block, BTreeSet<Value>>::default();
+        let mut defs = SecondaryMap::<Block, BTreeSet<Value>>::default();
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                if !func.dfg.is_phi(insn) {
+                    for &arg in func.dfg.insn_args(insn) {
+                        if is_tracked(func, arg) && !defs[block].contains(&arg) {
+                            uses[block].insert(arg);
+                        }
+                    }
+                }
+                defs[block].extend(func.dfg.insn_results(insn).iter().copied());
+            }
+        }
+
+        // Visiting blocks in post order makes successors mostly precede their predecessors.
+        let post_order: Vec<_> = cfg.post_order().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &post_order {
+                let mut live_out = BTreeSet::new();
+                for &succ in cfg.succs_of(block) {
+                    live_out.extend(self.live_in[succ].iter().copied());
+                    live_out.extend(phi_args(func, succ, block));
+                }
+
+                let mut live_in = uses[block].clone();
+                live_in.extend(live_out.difference(&defs[block]).copied());
+                if live_in != self.live_in[block] {
+                    self.live_in[block] = live_in;
+                    changed = true;
+                }
+                self.live_out[block] = live_out;
+            }
+        }
+    }
+
+    /// Returns the values live on entry to `block`.
+    pub fn live_in(&self, block: Block) -> &BTreeSet<Value> {
+        &self.live_in[block]
+    }
+
+    /// Returns the values live on exit from `block`.
+    pub fn live_out(&self, block: Block) -> &BTreeSet<Value> {
+        &self.live_out[block]
+    }
+
+    pub fn is_live_in(&self, value: Value, block: Block) -> bool {
+        self.live_in[block].contains(&value)
+    }
+
+    pub fn is_live_out(&self, value: Value, block: Block) -> bool {
+        self.live_out[block].contains(&value)
+    }
+
+    pub fn clear(&mut self) {
+        self.live_in.clear();
+        self.live_out.clear();
+    }
+}
+
+fn is_tracked(func: &Function, value: Value) -> bool {
+    matches!(
+        func.dfg.value_data(value),
+        ValueData::Insn { .. } | ValueData::Arg { .. }
+    )
+}
+
+/// Returns the arguments phis in `block` take from `pred`.
+fn phi_args(func: &Function, block: Block, pred: Block) -> impl Iterator<Item = Value> + '_ {
+    func.layout
+        .iter_insn(block)
+        .map_while(move |insn| match func.dfg.insn_data(insn) {
+            InsnData::Phi { values, blocks, .. } => Some(
+                values
+                    .iter()
+                    .zip(blocks)
+                    .filter(|(_, block)| **block == pred)
+                    .map(|(value, _)| *value)
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        })
+        .flatten()
+        .filter(|value| is_tracked(func, *value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn loop_liveness() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let n = builder.args()[0];
+        let zero = builder.make_imm_value(0i32);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        let i = builder.phi(Type::I32, &[(zero, b0)]);
+        let one = builder.make_imm_value(1i32);
+        let i_next = builder.add(i, one);
+        builder.append_phi_arg(i, i_next, b1);
+        let cond = builder.lt(i_next, n);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(i_next));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut liveness = Liveness::new();
+        liveness.compute(func, &cfg);
+
+        assert_eq!(liveness.live_in(b0), &BTreeSet::from([n]));
+        assert_eq!(liveness.live_out(b0), &BTreeSet::from([n]));
+        // `i_next` flows into the phi along the back edge, but the phi itself is defined in `b1`.
+        assert_eq!(liveness.live_in(b1), &BTreeSet::from([n]));
+        assert_eq!(liveness.live_out(b1), &BTreeSet::from([n, i_next]));
+        assert_eq!(liveness.live_in(b2), &BTreeSet::from([i_next]));
+        assert!(!liveness.is_live_in(i, b1));
+    }
+}
@@ -124,6 +124,12 @@ impl LoopTree {
             }
 
             while let Some(block) = worklist.pop() {
+                // A block that isn't dominated by the header is unreachable from the entry block,
+                // so it never belongs to the loop.
+                if !domtree.dominates(cur_lp_header, block) {
+                    continue;
+                }
+
                 match self.block_to_loop[block].expand() {
                     Some(lp_of_block) => {
                         let outermost_parent = self.outermost_parent(lp_of_block);
@@ -135,8 +141,10 @@ impl LoopTree {
                             self.loops[cur_lp].children.push(outermost_parent);
                             self.loops[outermost_parent].parent = cur_lp.into();
 
-                            let lp_header_of_block = self.loop_header(lp_of_block);
-                            worklist.extend(cfg.preds_of(lp_header_of_block));
+                            // Continue from the header of the whole nest, since the blocks
+                            // leading into the inner headers are already in the nest.
+                            let header_of_nest = self.loop_header(outermost_parent);
+                            worklist.extend(cfg.preds_of(header_of_nest));
                         }
                     }
 
@@ -57,12 +57,21 @@ impl AdceSolver {
 
         for block in func.layout.iter_block() {
             for insn in func.layout.iter_insn(block) {
-                if func.dfg.has_side_effect(insn) {
+                if func.has_side_effect(insn) {
                     self.mark_insn(func, insn);
                 }
             }
         }
 
+        // The entry block is kept even if nothing in it is live, since the function begins there.
+        if let Some(last_insn) = func
+            .layout
+            .entry_block()
+            .and_then(|entry| func.layout.last_insn_of(entry))
+        {
+            self.mark_insn(func, last_insn);
+        }
+
         while let Some(insn) = self.worklist.pop() {
             self.mark_by_insn(func, insn, &pdf_set);
         }
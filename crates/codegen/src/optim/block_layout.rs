@@ -0,0 +1,197 @@
+//! This module contains a solver that lays out blocks so that hot edges fall through.
+//!
+//! The EVM backend omits the jump to the block that follows in the layout, and lowers a `br`
+//! whose `then` destination follows with its condition inverted, so an edge between adjacent
+//! blocks costs no taken `JUMP`. Blocks are laid out in chains: starting from the entry block, each
+//! block is followed by its hottest successor that isn't laid out yet, by the
+//! [weights](sonatina_ir::BranchWeights) of its branch. A block without such a successor is
+//! followed by the first remaining block in the original layout, with cold blocks, i.e., those
+//! only reached by edges of weight zero, left for the end.
+//!
+//! Functions without branch weights keep their layout, since there is no profile to improve on
+//! the order the frontend chose.
+use rustc_hash::{FxHashMap, FxHashSet};
+use sonatina_ir::{Block, ControlFlowGraph, Function};
+
+#[derive(Debug, Default)]
+pub struct BlockLayoutSolver {
+    moved_block_num: usize,
+}
+
+impl BlockLayoutSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.moved_block_num = 0;
+    }
+
+    /// Returns the number of blocks moved since the last [`Self::clear`].
+    pub fn moved_block_num(&self) -> usize {
+        self.moved_block_num
+    }
+
+    pub fn run(&mut self, func: &mut Function, cfg: &ControlFlowGraph) {
+        let has_weights = func.layout.iter_block().any(|block| {
+            func.layout
+                .last_insn_of(block)
+                .is_some_and(|insn| func.dfg.branch_weights(insn).is_some())
+        });
+        let Some(entry) = func.layout.entry_block().filter(|_| has_weights) else {
+            return;
+        };
+
+        let original: Vec<_> = func.layout.iter_block().collect();
+        let positions: FxHashMap<_, _> = original
+            .iter()
+            .enumerate()
+            .map(|(pos, &block)| (block, pos))
+            .collect();
+        let cold: FxHashSet<_> = original
+            .iter()
+            .copied()
+            .filter(|&block| is_cold(func, cfg, block))
+            .collect();
+
+        let mut order = Vec::with_capacity(original.len());
+        let mut placed = FxHashSet::default();
+        let mut next = Some(entry);
+        while let Some(block) = next {
+            order.push(block);
+            placed.insert(block);
+            next = hottest_succ(func, cfg, block, &placed, &positions).or_else(|| {
+                let mut remaining = original.iter().filter(|b| !placed.contains(*b));
+                remaining
+                    .clone()
+                    .find(|b| !cold.contains(*b))
+                    .or_else(|| remaining.next())
+                    .copied()
+            });
+        }
+
+        for pair in order.windows(2) {
+            let [prev, block] = [pair[0], pair[1]];
+            if func.layout.next_block_of(prev) != Some(block) {
+                func.layout.move_block_after(block, prev);
+                self.moved_block_num += 1;
+            }
+        }
+    }
+}
+
+/// Returns the successor of `block` with the heaviest edge that isn't placed yet. Edges of a
+/// branch without weights weigh the same, and ties go to the successor that comes first in the
+/// original layout. An edge of weight zero never falls through.
+fn hottest_succ(
+    func: &Function,
+    cfg: &ControlFlowGraph,
+    block: Block,
+    placed: &FxHashSet<Block>,
+    positions: &FxHashMap<Block, usize>,
+) -> Option<Block> {
+    let branch = func.layout.last_insn_of(block)?;
+    cfg.succs_of(block)
+        .copied()
+        .filter(|succ| !placed.contains(succ))
+        .map(|succ| (succ, func.dfg.edge_weight(branch, succ).unwrap_or(1)))
+        .filter(|&(_, weight)| weight != 0)
+        .max_by(|(a, a_weight), (b, b_weight)| {
+            a_weight
+                .cmp(b_weight)
+                .then_with(|| positions[b].cmp(&positions[a]))
+        })
+        .map(|(succ, _)| succ)
+}
+
+/// Returns `true` if every edge into `block` has weight zero.
+fn is_cold(func: &Function, cfg: &ControlFlowGraph, block: Block) -> bool {
+    let mut preds = cfg.preds_of(block).peekable();
+    preds.peek().is_some()
+        && preds.all(|&pred| {
+            let branch = func.layout.last_insn_of(pred).unwrap();
+            func.dfg.edge_weight(branch, block) == Some(0)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, insn::TrapReason, Type};
+
+    #[test]
+    fn fall_through_hot_edges() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I1], Type::I8);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let b4 = builder.append_block();
+        let (c0, c1) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(c0, b1, b2);
+        builder.set_branch_weights(&[1, 99]);
+
+        builder.switch_to_block(b1);
+        let one = builder.make_imm_value(1i8);
+        builder.ret(Some(one));
+
+        builder.switch_to_block(b2);
+        builder.br(c1, b3, b4);
+        builder.set_branch_weights(&[0, 10]);
+
+        builder.switch_to_block(b3);
+        builder.trap(TrapReason::Assert);
+
+        builder.switch_to_block(b4);
+        let two = builder.make_imm_value(2i8);
+        builder.ret(Some(two));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut solver = BlockLayoutSolver::new();
+        solver.run(func, &cfg);
+
+        // The cold `b3` goes last, after the less likely `b1`.
+        assert_eq!(
+            func.layout.iter_block().collect::<Vec<_>>(),
+            [b0, b2, b4, b1, b3]
+        );
+        assert_eq!(solver.moved_block_num(), 2);
+    }
+
+    #[test]
+    fn keep_layout_without_weights() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        let cond = builder.args()[0];
+        builder.switch_to_block(b0);
+        builder.br(cond, b2, b1);
+
+        builder.switch_to_block(b1);
+        builder.ret(None);
+
+        builder.switch_to_block(b2);
+        builder.ret(None);
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut solver = BlockLayoutSolver::new();
+        solver.run(func, &cfg);
+        assert_eq!(solver.moved_block_num(), 0);
+        assert_eq!(func.layout.iter_block().collect::<Vec<_>>(), [b0, b1, b2]);
+    }
+}
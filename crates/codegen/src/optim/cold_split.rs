@@ -0,0 +1,356 @@
+//! This module contains a splitter that moves cold regions of functions into helper functions.
+//!
+//! A region is a block along with the blocks it dominates, and is cold if its entry block
+//! [must revert](crate::revert_analysis::RevertAnalysis::must_revert), or never runs by the
+//! [frequencies](crate::block_frequency::BlockFrequency) of the branch weights. A cold region is
+//! split out if nothing leaves it but `return`s and reverts, and its entry block has no phis: the
+//! entry block of the region then calls a private helper that contains the region, and returns
+//! its result. The values the region uses from the rest of the function are passed as arguments.
+//!
+//! Helpers are marked `cold` and never inlined, so the backend lays them out after the hot code.
+//! Regions smaller than [`ColdSplitter::new`]'s `min_size` are left in place, since the call
+//! would cost about as much code as it moves away.
+use rustc_hash::FxHashSet;
+
+use sonatina_ir::{
+    cloner::FunctionCloner,
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    module::FuncRef,
+    Block, ControlFlowGraph, FuncAttrs, Function, InlineHint, InsnData, Linkage, Module, Signature,
+    Type, Value, ValueData,
+};
+
+use crate::{block_frequency::BlockFrequency, domtree::DomTree, revert_analysis::RevertAnalysis};
+
+#[derive(Debug)]
+pub struct ColdSplitter {
+    /// The minimum size in bytes of a region that is split out.
+    min_size: u64,
+    split_num: usize,
+    helper_num: usize,
+}
+
+impl Default for ColdSplitter {
+    fn default() -> Self {
+        Self::new(32)
+    }
+}
+
+impl ColdSplitter {
+    pub fn new(min_size: u64) -> Self {
+        Self {
+            min_size,
+            split_num: 0,
+            helper_num: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.split_num = 0;
+    }
+
+    /// Returns the number of regions split out since the last [`Self::clear`].
+    pub fn split_num(&self) -> usize {
+        self.split_num
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        for func_ref in module.iter_functions() {
+            let func = &module.funcs[func_ref];
+            let attrs = func.sig.attrs();
+            if attrs.optnone || attrs.cold || func.layout.entry_block().is_none() {
+                continue;
+            }
+
+            for region in self.cold_regions(module, func) {
+                self.split(module, func_ref, &region);
+                self.split_num += 1;
+            }
+        }
+    }
+
+    /// Returns the disjoint cold regions of `func` that can be split out, each with its entry
+    /// block first and the rest in layout order.
+    fn cold_regions(&self, module: &Module, func: &Function) -> Vec<Vec<Block>> {
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+        let mut revert = RevertAnalysis::new();
+        revert.compute(func, &cfg);
+        let mut freq = BlockFrequency::new();
+        freq.compute(func, &cfg);
+
+        let entry = func.layout.entry_block();
+        let costs = module.ctx.isa.cost_table();
+        let mut regions = Vec::new();
+        let mut in_region = FxHashSet::default();
+
+        // A dominator comes before the blocks it dominates in RPO, so the largest region is found
+        // first.
+        for &block in domtree.rpo() {
+            if Some(block) == entry
+                || in_region.contains(&block)
+                || func.phis_of(block).next().is_some()
+                || !(revert.must_revert(block) || freq.is_cold(block))
+            {
+                continue;
+            }
+
+            let region: Vec<_> = func
+                .layout
+                .iter_block()
+                .filter(|&other| domtree.is_reachable(other) && domtree.dominates(block, other))
+                .collect();
+            let is_closed = region
+                .iter()
+                .all(|&block| cfg.succs_of(block).all(|succ| region.contains(succ)));
+            let size: u64 = region
+                .iter()
+                .flat_map(|&block| func.layout.iter_insn(block))
+                .map(|insn| costs.insn_size(func.dfg.insn_data(insn)))
+                .sum();
+            if !is_closed || size < self.min_size {
+                continue;
+            }
+
+            // Keep the entry block first, wherever it's laid out.
+            let mut ordered = vec![block];
+            ordered.extend(region.iter().copied().filter(|&other| other != block));
+            in_region.extend(region);
+            regions.push(ordered);
+        }
+
+        regions
+    }
+
+    fn split(&mut self, module: &mut Module, func_ref: FuncRef, region: &[Block]) {
+        let func = &module.funcs[func_ref];
+        let inputs = region_inputs(func, region);
+        let returns = region.iter().any(|&block| {
+            func.layout
+                .last_insn_of(block)
+                .is_some_and(|insn| func.dfg.is_return(insn))
+        });
+
+        let param_tys: Vec<_> = inputs
+            .iter()
+            .map(|&input| func.dfg.value_ty(input))
+            .collect();
+        let ret_ty = if returns {
+            func.sig.ret_ty()
+        } else {
+            Type::Void
+        };
+        let attrs = FuncAttrs {
+            inline: InlineHint::Never,
+            cold: true,
+            mutability: func.sig.attrs().mutability,
+            ..FuncAttrs::default()
+        };
+        let name = self.helper_name(module);
+        let sig = Signature::new(&name, Linkage::Private, &param_tys, ret_ty).with_attrs(attrs);
+
+        let mut helper = Function::new(&module.ctx, sig.clone());
+        let mut cloner = FunctionCloner::new(func);
+        for (&input, &arg) in inputs.iter().zip(&helper.arg_values) {
+            cloner.map_value(input, arg);
+        }
+        cloner.clone_blocks_into(&mut helper, region, None);
+        let helper = module.funcs.push(helper);
+
+        let func = &mut module.funcs[func_ref];
+        let entry = region[0];
+        let loc = func
+            .layout
+            .first_insn_of(entry)
+            .and_then(|insn| func.dfg.insn_loc(insn));
+        for &block in &region[1..] {
+            InsnInserter::at_location(CursorLocation::BlockTop(block)).remove_block(func);
+        }
+        let insns: Vec<_> = func.layout.iter_insn(entry).collect();
+        for insn in insns {
+            InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+        }
+
+        func.callees.insert(helper, sig);
+        let call = func.dfg.make_insn(InsnData::Call {
+            func: helper,
+            args: inputs.into_iter().collect(),
+            ret_ty,
+            is_tail: false,
+        });
+        func.layout.append_insn(call, entry);
+        func.dfg.set_insn_loc(call, loc);
+        // A void call has no result to attach.
+        let result = (ret_ty != Type::Void)
+            .then(|| func.dfg.make_result(call))
+            .flatten()
+            .map(|data| {
+                let result = func.dfg.make_value(data);
+                func.dfg.attach_result(call, result);
+                result
+            });
+
+        let exit = if returns {
+            InsnData::Return { args: result }
+        } else {
+            InsnData::Unreachable
+        };
+        let exit = func.dfg.make_insn(exit);
+        func.layout.append_insn(exit, entry);
+        func.dfg.set_insn_loc(exit, loc);
+    }
+
+    /// Returns a helper name that doesn't collide with functions in the module.
+    fn helper_name(&mut self, module: &Module) -> String {
+        loop {
+            let name = format!("__cold_{}", self.helper_num);
+            self.helper_num += 1;
+            if module.funcs.values().all(|func| func.sig.name() != name) {
+                return name;
+            }
+        }
+    }
+}
+
+/// Returns the arguments and results of insns outside of `region` that `region` uses, in the
+/// order of their first use.
+fn region_inputs(func: &Function, region: &[Block]) -> Vec<Value> {
+    let mut inputs = Vec::new();
+    for &block in region {
+        for insn in func.layout.iter_insn(block) {
+            for &arg in func.dfg.insn_data(insn).args() {
+                let is_input = match func.dfg.value_data(arg) {
+                    ValueData::Arg { .. } => true,
+                    ValueData::Insn { insn, .. } => {
+                        !region.contains(&func.layout.insn_block(*insn))
+                    }
+                    ValueData::Immediate { .. } | ValueData::Global { .. } => false,
+                };
+                if is_input && !inputs.contains(&arg) {
+                    inputs.push(arg);
+                }
+            }
+        }
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::builder::test_util::*;
+
+    #[test]
+    fn split_revert_path() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let (cond, x) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        let y = builder.add(x, x);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.ret(Some(y));
+
+        builder.switch_to_block(b2);
+        let mut v = y;
+        for _ in 0..4 {
+            v = builder.mul(v, x);
+        }
+        builder.revert([0xca, 0xfe, 0xba, 0xbe], &[v]);
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let mut splitter = ColdSplitter::new(1);
+        splitter.run(&mut module);
+        assert_eq!(splitter.split_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32) -> i32 {
+    block0:
+        v2.i32 = add v1 v1;
+        br v0 block1 block2;
+
+    block1:
+        return v2;
+
+    block2:
+        call %__cold_0 v2 v1;
+        unreachable;
+
+}
+"
+        );
+
+        let helper = module.iter_functions().last().unwrap();
+        assert!(module.funcs[helper].sig.attrs().cold);
+        assert_eq!(
+            dump_func(&module, helper),
+            "func private %__cold_0(v0.i32, v1.i32) -> void inline(never) cold {
+    block0:
+        v2.i32 = mul v0 v1;
+        v3.i32 = mul v2 v1;
+        v4.i32 = mul v3 v1;
+        v5.i32 = mul v4 v1;
+        revert 0xcafebabe v5;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn split_never_run_path() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let (cond, x) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(cond, b1, b2);
+        builder.set_branch_weights(&[1, 0]);
+
+        builder.switch_to_block(b1);
+        builder.ret(Some(x));
+
+        builder.switch_to_block(b2);
+        let mut v = x;
+        for _ in 0..4 {
+            v = builder.mul(v, x);
+        }
+        builder.ret(Some(v));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let mut splitter = ColdSplitter::new(1);
+        splitter.run(&mut module);
+        assert_eq!(splitter.split_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32) -> i32 {
+    block0:
+        br v0 block1 block2;
+
+    block1:
+        return v1;
+
+    block2:
+        v6.i32 = call %__cold_0 v1;
+        return v6;
+
+}
+"
+        );
+    }
+}
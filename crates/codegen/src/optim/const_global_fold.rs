@@ -0,0 +1,280 @@
+//! This module contains a solver for folding loads from constant global variables.
+//!
+//! A memory load whose address is a constant global, or a `gep` over a constant global with only
+//! immediate indices, is replaced with the immediate found in the initializer of the global.
+//! Once all functions in the module are folded, private constant globals that are no longer
+//! referenced are removed from the module.
+use rustc_hash::FxHashSet;
+use smallvec::SmallVec;
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    global_variable::ConstantValue,
+    DataLocationKind, Function, GlobalVariable, Immediate, Insn, InsnData, Linkage, Module, Value,
+    ValueData,
+};
+
+#[derive(Debug, Default)]
+pub struct ConstGlobalFoldSolver {
+    folded_num: usize,
+}
+
+impl ConstGlobalFoldSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.folded_num = 0;
+    }
+
+    /// Returns the number of loads folded since the last [`Self::clear`].
+    pub fn folded_num(&self) -> usize {
+        self.folded_num
+    }
+
+    /// Fold loads in all functions in the module, then remove private constant globals that
+    /// become unreferenced.
+    pub fn run(&mut self, module: &mut Module) {
+        let func_refs: Vec<_> = module.iter_functions().collect();
+        for func_ref in func_refs {
+            let func = &mut module.funcs[func_ref];
+            if !func.sig.attrs().optnone {
+                self.run_on_func(func);
+            }
+        }
+
+        self.remove_unreferenced_gvs(module);
+    }
+
+    /// Fold loads in a single function.
+    /// Globals are never removed by this method.
+    pub fn run_on_func(&mut self, func: &mut Function) {
+        let mut inserter = InsnInserter::at_location(CursorLocation::NoWhere);
+        inserter.set_to_entry(func);
+
+        loop {
+            match inserter.loc() {
+                CursorLocation::At(insn) => {
+                    let Some(imm) = self.fold_load(func, insn) else {
+                        inserter.proceed(func);
+                        continue;
+                    };
+
+                    let result = func.dfg.insn_result(insn).unwrap();
+                    let imm_value = func.dfg.make_imm_value(imm);
+                    func.dfg.change_to_alias(result, imm_value);
+
+                    let addr = func.dfg.insn_arg(insn, 0);
+                    inserter.remove_insn(func);
+                    self.remove_dead_gep(func, addr);
+                    self.folded_num += 1;
+                }
+
+                CursorLocation::BlockTop(_) | CursorLocation::BlockBottom(_) => {
+                    inserter.proceed(func);
+                }
+
+                CursorLocation::NoWhere => break,
+            }
+        }
+    }
+
+    /// Returns the immediate loaded by `insn` if the loaded address is statically known to point
+    /// to an immediate in a constant global initializer.
+    fn fold_load(&self, func: &Function, insn: Insn) -> Option<Immediate> {
+        let InsnData::Load {
+            args: [addr],
+            loc: DataLocationKind::Memory,
+        } = func.dfg.insn_data(insn)
+        else {
+            return None;
+        };
+
+        let (gv, indices) = self.resolve_addr(func, *addr)?;
+        let result_ty = func.dfg.insn_result_ty(insn)?;
+
+        func.dfg.ctx.with_gv_store(|s| {
+            if !s.is_const(gv) {
+                return None;
+            }
+
+            let mut data = s.init_data(gv)?;
+            for idx in indices {
+                data = match data {
+                    ConstantValue::Array(elems) | ConstantValue::Struct(elems) => elems.get(idx)?,
+                    ConstantValue::Immediate(_) => return None,
+                };
+            }
+
+            match data {
+                ConstantValue::Immediate(imm) if imm.ty() == result_ty => Some(*imm),
+                _ => None,
+            }
+        })
+    }
+
+    /// Decompose `addr` into a global variable and constant indices into its initializer.
+    fn resolve_addr(
+        &self,
+        func: &Function,
+        addr: Value,
+    ) -> Option<(GlobalVariable, SmallVec<[usize; 4]>)> {
+        match func.dfg.value_data(addr) {
+            ValueData::Global { gv, .. } => Some((*gv, SmallVec::new())),
+
+            ValueData::Insn { insn, .. } => {
+                let InsnData::Gep { args } = func.dfg.insn_data(*insn) else {
+                    return None;
+                };
+
+                let gv = func.dfg.value_gv(args[0])?;
+                let mut indices = SmallVec::new();
+                for &idx in &args[1..] {
+                    let ValueData::Immediate { imm, .. } = func.dfg.value_data(idx) else {
+                        return None;
+                    };
+                    if imm.is_negative() {
+                        return None;
+                    }
+                    indices.push(imm.as_usize());
+                }
+                Some((gv, indices))
+            }
+
+            _ => None,
+        }
+    }
+
+    fn remove_dead_gep(&self, func: &mut Function, addr: Value) {
+        let Some(insn) = func.dfg.value_insn(addr) else {
+            return;
+        };
+
+        if matches!(func.dfg.insn_data(insn), InsnData::Gep { .. }) && func.dfg.users_num(addr) == 0
+        {
+            InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+        }
+    }
+
+    fn remove_unreferenced_gvs(&self, module: &Module) {
+        let mut referenced = FxHashSet::default();
+        for func in module.funcs.values() {
+            for (value, data) in func.dfg.values.iter() {
+                if let ValueData::Global { gv, .. } = data {
+                    if func.dfg.users_num(value) > 0 {
+                        referenced.insert(*gv);
+                    }
+                }
+            }
+        }
+
+        module.ctx.with_gv_store_mut(|s| {
+            let dead: Vec<_> = s
+                .all_gvs()
+                .filter(|gv| {
+                    s.is_const(*gv)
+                        && s.gv_data(*gv).linkage == Linkage::Private
+                        && !referenced.contains(gv)
+                })
+                .collect();
+
+            for gv in dead {
+                s.remove_gv(gv);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, GlobalVariableData, Type};
+
+    #[test]
+    fn fold_array_of_struct() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let s_ty = builder.declare_struct_type("Entry", &[Type::I8, Type::I32], false);
+        let arr_ty = builder.declare_array_type(s_ty, 2);
+
+        let entry = |tag: i8, val: i32| {
+            ConstantValue::make_struct(vec![
+                ConstantValue::make_imm(tag),
+                ConstantValue::make_imm(val),
+            ])
+        };
+        let gv = builder
+            .module_builder
+            .make_global(GlobalVariableData::constant(
+                "TABLE".to_string(),
+                arr_ty,
+                Linkage::Private,
+                ConstantValue::make_array(vec![entry(1, 10), entry(2, 20)]),
+            ));
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let base = builder.make_global_value(gv);
+        let v1 = builder.make_imm_value(1i32);
+        let ptr = builder.gep(&[base, v1, v1]).unwrap();
+        let val = builder.memory_load(ptr);
+        builder.ret(Some(val));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let mut solver = ConstGlobalFoldSolver::new();
+        solver.run(&mut module);
+        assert_eq!(solver.folded_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func() -> i32 {
+    block0:
+        return 20.i32;
+
+}
+"
+        );
+        assert!(module
+            .ctx
+            .with_gv_store(|s| s.gv_by_symbol("TABLE").is_none()));
+    }
+
+    #[test]
+    fn keep_non_const_index() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let arr_ty = builder.declare_array_type(Type::I32, 2);
+        let gv = builder
+            .module_builder
+            .make_global(GlobalVariableData::constant(
+                "TABLE".to_string(),
+                arr_ty,
+                Linkage::Private,
+                ConstantValue::make_array(vec![
+                    ConstantValue::make_imm(1i32),
+                    ConstantValue::make_imm(2i32),
+                ]),
+            ));
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let idx = builder.args()[0];
+        let base = builder.make_global_value(gv);
+        let ptr = builder.gep(&[base, idx]).unwrap();
+        let val = builder.memory_load(ptr);
+        builder.ret(Some(val));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let mut solver = ConstGlobalFoldSolver::new();
+        solver.run(&mut module);
+
+        assert_eq!(solver.folded_num(), 0);
+        assert!(module
+            .ctx
+            .with_gv_store(|s| s.gv_by_symbol("TABLE").is_some()));
+    }
+}
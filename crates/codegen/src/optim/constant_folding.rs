@@ -1,41 +1,41 @@
+//! This module contains constant folding of insns whose arguments are immediates.
+//!
+//! Pointers are folded by the addresses they hold, never by the contents of what they point to.
+//! The null pointer is the pointer with address `0`, e.g., `bitcast 0.i256 to *i8`; an integer
+//! cast to a pointer is zero-extended to 256 bits. No `alloca` or global variable is placed at
+//! address `0`, so a pointer to an object never compares equal to null. A `gep` with only zero
+//! indices holds the same address as its base, so such a `gep` of null is null again.
 use sonatina_ir::{
     insn::{BinaryOp, CastOp, UnaryOp},
-    DataFlowGraph, Immediate, InsnData,
+    DataFlowGraph, Immediate, InsnData, Type, Value, ValueData, I256,
 };
 
 pub(super) fn fold_constant(dfg: &DataFlowGraph, insn_data: &InsnData) -> Option<Immediate> {
+    if insn_data
+        .args()
+        .first()
+        .is_some_and(|arg| is_ptr(dfg, *arg))
+    {
+        return fold_ptr(dfg, insn_data);
+    }
+
     match insn_data {
         InsnData::Unary { code, args } => {
             let arg = dfg.value_imm(args[0])?;
             Some(match *code {
                 UnaryOp::Not => !arg,
                 UnaryOp::Neg => -arg,
+                UnaryOp::Clz => arg.clz(),
+                UnaryOp::Ctz => arg.ctz(),
+                UnaryOp::Popcount => arg.popcount(),
+                UnaryOp::Bswap => arg.bswap(),
             })
         }
 
         InsnData::Binary { code, args } => {
             let lhs = dfg.value_imm(args[0])?;
             let rhs = dfg.value_imm(args[1])?;
-            Some(match *code {
-                BinaryOp::Add => lhs + rhs,
-                BinaryOp::Sub => lhs - rhs,
-                BinaryOp::Mul => lhs * rhs,
-                BinaryOp::Udiv => lhs.udiv(rhs),
-                BinaryOp::Sdiv => lhs.sdiv(rhs),
-                BinaryOp::Lt => lhs.lt(rhs),
-                BinaryOp::Gt => lhs.gt(rhs),
-                BinaryOp::Slt => lhs.slt(rhs),
-                BinaryOp::Sgt => lhs.sgt(rhs),
-                BinaryOp::Le => lhs.le(rhs),
-                BinaryOp::Ge => lhs.ge(rhs),
-                BinaryOp::Sle => lhs.sle(rhs),
-                BinaryOp::Sge => lhs.sge(rhs),
-                BinaryOp::Eq => lhs.imm_eq(rhs),
-                BinaryOp::Ne => lhs.imm_ne(rhs),
-                BinaryOp::And => lhs & rhs,
-                BinaryOp::Or => lhs | rhs,
-                BinaryOp::Xor => lhs ^ rhs,
-            })
+            Some(fold_binary(*code, lhs, rhs))
         }
 
         InsnData::Cast { code, args, ty } => {
@@ -44,19 +44,148 @@ pub(super) fn fold_constant(dfg: &DataFlowGraph, insn_data: &InsnData) -> Option
                 CastOp::Sext => arg.sext(*ty),
                 CastOp::Zext => arg.zext(*ty),
                 CastOp::Trunc => arg.trunc(*ty),
+                CastOp::BitCast if arg.ty() == *ty => arg,
                 CastOp::BitCast => return None,
             })
         }
 
-        InsnData::Load { .. }
+        // Only the wrapped result could be folded into a single immediate.
+        InsnData::OverflowBinary { .. }
+        | InsnData::Load { .. }
         | InsnData::Jump { .. }
         | InsnData::Branch { .. }
         | InsnData::BrTable { .. }
         | InsnData::Store { .. }
+        | InsnData::Mem { .. }
         | InsnData::Call { .. }
+        | InsnData::Intrinsic { .. }
         | InsnData::Alloca { .. }
         | InsnData::Gep { .. }
         | InsnData::Return { .. }
+        | InsnData::Revert { .. }
+        | InsnData::Trap { .. }
+        | InsnData::Unreachable
         | InsnData::Phi { .. } => None,
     }
 }
+
+/// Returns `true` if `value` is a pointer.
+pub(super) fn is_ptr(dfg: &DataFlowGraph, value: Value) -> bool {
+    let ty = dfg.value_ty(value);
+    dfg.ctx.with_ty_store(|s| s.is_ptr(ty))
+}
+
+fn fold_binary(code: BinaryOp, lhs: Immediate, rhs: Immediate) -> Immediate {
+    match code {
+        BinaryOp::Add => lhs + rhs,
+        BinaryOp::Sub => lhs - rhs,
+        BinaryOp::Mul => lhs * rhs,
+        BinaryOp::Udiv => lhs.udiv(rhs),
+        BinaryOp::Sdiv => lhs.sdiv(rhs),
+        BinaryOp::Lt => lhs.lt(rhs),
+        BinaryOp::Gt => lhs.gt(rhs),
+        BinaryOp::Slt => lhs.slt(rhs),
+        BinaryOp::Sgt => lhs.sgt(rhs),
+        BinaryOp::Le => lhs.le(rhs),
+        BinaryOp::Ge => lhs.ge(rhs),
+        BinaryOp::Sle => lhs.sle(rhs),
+        BinaryOp::Sge => lhs.sge(rhs),
+        BinaryOp::Eq => lhs.imm_eq(rhs),
+        BinaryOp::Ne => lhs.imm_ne(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs << rhs,
+        BinaryOp::Shr => lhs >> rhs,
+        BinaryOp::Sar => lhs.sar(rhs),
+        BinaryOp::Rotl => lhs.rotl(rhs),
+        BinaryOp::Rotr => lhs.rotr(rhs),
+    }
+}
+
+/// The address a pointer is known to hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PtrAddr {
+    /// A constant address, e.g., null.
+    Const(I256),
+    /// The address of an `alloca` or a global variable, which is never null.
+    Object,
+}
+
+/// Folds an insn whose first argument is a pointer. Only comparisons and casts to integers are
+/// folded, because other insns on pointers produce pointers, which have no immediates.
+fn fold_ptr(dfg: &DataFlowGraph, insn_data: &InsnData) -> Option<Immediate> {
+    match insn_data {
+        InsnData::Binary { code, args } if code.is_cmp() => {
+            match (ptr_addr(dfg, args[0])?, ptr_addr(dfg, args[1])?) {
+                (PtrAddr::Const(lhs), PtrAddr::Const(rhs)) => Some(fold_binary(
+                    *code,
+                    Immediate::I256(lhs),
+                    Immediate::I256(rhs),
+                )),
+                (PtrAddr::Const(addr), PtrAddr::Object)
+                | (PtrAddr::Object, PtrAddr::Const(addr))
+                    if addr.is_zero() =>
+                {
+                    match code {
+                        BinaryOp::Eq => Some(false.into()),
+                        BinaryOp::Ne => Some(true.into()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        }
+
+        InsnData::Cast {
+            code: CastOp::BitCast,
+            args,
+            ty,
+        } if ty.is_integral() => match ptr_addr(dfg, args[0])? {
+            PtrAddr::Const(addr) => Some(Immediate::from_i256(addr, *ty)),
+            PtrAddr::Object => None,
+        },
+
+        _ => None,
+    }
+}
+
+/// Returns the address `ptr` is known to hold.
+fn ptr_addr(dfg: &DataFlowGraph, ptr: Value) -> Option<PtrAddr> {
+    let insn = match dfg.value_data(ptr) {
+        ValueData::Global { .. } => return Some(PtrAddr::Object),
+        ValueData::Insn { insn, .. } => *insn,
+        ValueData::Arg { .. } | ValueData::Immediate { .. } => return None,
+    };
+
+    match dfg.insn_data(insn) {
+        InsnData::Alloca { .. } => Some(PtrAddr::Object),
+
+        InsnData::Cast {
+            code: CastOp::BitCast,
+            args,
+            ..
+        } => {
+            if is_ptr(dfg, args[0]) {
+                return ptr_addr(dfg, args[0]);
+            }
+            let imm = dfg.value_imm(args[0])?;
+            let addr = if imm.ty() == Type::I256 {
+                imm
+            } else {
+                imm.zext(Type::I256)
+            };
+            Some(PtrAddr::Const(addr.as_i256()))
+        }
+
+        InsnData::Gep { args }
+            if args[1..]
+                .iter()
+                .all(|idx| dfg.value_imm(*idx).is_some_and(Immediate::is_zero)) =>
+        {
+            ptr_addr(dfg, args[0])
+        }
+
+        _ => None,
+    }
+}
@@ -0,0 +1,211 @@
+//! This module contains a solver for dominator-scoped common subexpression elimination.
+//!
+//! The solver walks the dominator tree in preorder and hash-conses pure instructions. An
+//! instruction that is identical to an instruction in a dominating position is replaced with the
+//! result of the dominating one. Entries are popped from the table when the walk leaves the
+//! dominator subtree that defines them, so the table only holds available expressions.
+//!
+//! This is much cheaper than [`super::gvn`] but only finds syntactically identical
+//! instructions; it doesn't see through phis nor infer values from branch conditions.
+
+use rustc_hash::FxHashMap;
+
+use crate::domtree::{DomTree, DominatorTreeTraversable};
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    Block, Function, Insn, InsnData, Value,
+};
+
+#[derive(Debug, Default)]
+pub struct CseSolver {
+    /// Available expressions keyed by their canonicalized insn data.
+    table: FxHashMap<InsnData, Value>,
+    /// Keys inserted to `table` in insertion order, used to pop scopes.
+    undo_log: Vec<InsnData>,
+    eliminated_num: usize,
+}
+
+impl CseSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.undo_log.clear();
+        self.eliminated_num = 0;
+    }
+
+    /// Returns the number of insns eliminated since the last [`Self::clear`].
+    pub fn eliminated_num(&self) -> usize {
+        self.eliminated_num
+    }
+
+    /// Run CSE on the function.
+    /// The control flow graph is never modified, so `domtree` stays valid after the run.
+    pub fn run(&mut self, func: &mut Function, domtree: &DomTree) {
+        let Some(&entry) = domtree.rpo().first() else {
+            return;
+        };
+
+        let mut domtree_traversable = DominatorTreeTraversable::default();
+        domtree_traversable.compute(domtree);
+
+        let mut stack = vec![Scope::Enter(entry)];
+        while let Some(scope) = stack.pop() {
+            match scope {
+                Scope::Enter(block) => {
+                    stack.push(Scope::Leave(self.undo_log.len()));
+                    self.visit_block(func, block);
+                    stack.extend(
+                        domtree_traversable
+                            .children_of(block)
+                            .iter()
+                            .rev()
+                            .map(|child| Scope::Enter(*child)),
+                    );
+                }
+
+                Scope::Leave(undo_len) => {
+                    for key in self.undo_log.drain(undo_len..) {
+                        self.table.remove(&key);
+                    }
+                }
+            }
+        }
+
+        self.table.clear();
+    }
+
+    fn visit_block(&mut self, func: &mut Function, block: Block) {
+        let mut inserter = InsnInserter::at_location(CursorLocation::BlockTop(block));
+        inserter.proceed(func);
+
+        while let CursorLocation::At(insn) = inserter.loc() {
+            if !self.is_eligible(func, insn) {
+                inserter.proceed(func);
+                continue;
+            }
+
+            let key = canonicalize(func.dfg.insn_data(insn));
+            let result = func.dfg.insn_result(insn).unwrap();
+            match self.table.get(&key) {
+                Some(&leader) => {
+                    func.dfg.change_to_alias(result, leader);
+                    inserter.remove_insn(func);
+                    self.eliminated_num += 1;
+                }
+
+                None => {
+                    self.table.insert(key.clone(), result);
+                    self.undo_log.push(key);
+                    inserter.proceed(func);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the insn is pure and produces a single value, so that it can be replaced with
+    /// an identical insn that dominates it.
+    fn is_eligible(&self, func: &Function, insn: Insn) -> bool {
+        !(func.dfg.has_side_effect(insn) || func.dfg.is_branch(insn) || func.dfg.is_phi(insn))
+            && func.dfg.insn_results(insn).len() == 1
+    }
+}
+
+enum Scope {
+    /// Visit the block, then its children in the dominator tree.
+    Enter(Block),
+    /// Pop table entries inserted after the undo log had the given length.
+    Leave(usize),
+}
+
+/// Orders operands of commutative insns so that `a op b` and `b op a` hash equally.
+pub(super) fn canonicalize(insn_data: &InsnData) -> InsnData {
+    match insn_data {
+        InsnData::Binary { code, args } if code.is_commutative() && args[1] < args[0] => {
+            InsnData::Binary {
+                code: *code,
+                args: [args[1], args[0]],
+            }
+        }
+        _ => insn_data.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, ControlFlowGraph, Type};
+
+    #[test]
+    fn eliminate_in_dominated_blocks_only() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32, Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let (cond, x, y) = (builder.args()[0], builder.args()[1], builder.args()[2]);
+        let v3 = builder.add(x, y);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        let v4 = builder.add(y, x);
+        let v5 = builder.mul(v3, v4);
+        builder.jump(b3);
+
+        builder.switch_to_block(b2);
+        let v6 = builder.mul(x, y);
+        builder.jump(b3);
+
+        builder.switch_to_block(b3);
+        let v7 = builder.phi(Type::I32, &[(v5, b1), (v6, b2)]);
+        let v8 = builder.mul(x, y);
+        let v9 = builder.add(v7, v8);
+        builder.ret(Some(v9));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+
+        let mut solver = CseSolver::new();
+        solver.run(func, &domtree);
+        assert_eq!(solver.eliminated_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32, v2.i32) -> i32 {
+    block0:
+        v3.i32 = add v1 v2;
+        br v0 block1 block2;
+
+    block1:
+        v5.i32 = mul v3 v3;
+        jump block3;
+
+    block2:
+        v6.i32 = mul v1 v2;
+        jump block3;
+
+    block3:
+        v7.i32 = phi (v5 block1) (v6 block2);
+        v8.i32 = mul v1 v2;
+        v9.i32 = add v7 v8;
+        return v9;
+
+}
+"
+        );
+    }
+}
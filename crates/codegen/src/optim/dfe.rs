@@ -0,0 +1,125 @@
+//! This module contains dead function elimination, which removes private functions that are no
+//! longer reachable, e.g., callees the inliner has inlined into all of their callers.
+//!
+//! A function is reachable if it's called by a reachable function, or it's a root: a function that
+//! isn't private, a static initializer, an entry of a contract or an `optnone` function.
+
+use rustc_hash::FxHashSet;
+use sonatina_ir::{call_sites::CallSiteIndex, module::FuncRef, Function, Linkage, Module};
+
+#[derive(Debug, Default)]
+pub struct DeadFuncElim {
+    removed_num: usize,
+}
+
+impl DeadFuncElim {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.removed_num = 0;
+    }
+
+    /// Returns the number of functions removed since the last [`Self::clear`].
+    pub fn removed_num(&self) -> usize {
+        self.removed_num
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        let mut worklist: Vec<_> = module
+            .iter_functions()
+            .filter(|func_ref| {
+                let sig = &module.funcs[*func_ref].sig;
+                sig.linkage() != Linkage::Private || sig.attrs().optnone
+            })
+            .chain(module.static_inits.iter().map(|init| init.func))
+            .chain(
+                module
+                    .contracts
+                    .iter()
+                    .flat_map(|contract| contract.entries.iter().copied()),
+            )
+            .collect();
+
+        let mut call_sites = CallSiteIndex::new();
+        call_sites.compute(module);
+        let mut reachable = FxHashSet::default();
+        while let Some(func_ref) = worklist.pop() {
+            if reachable.insert(func_ref) {
+                let callees = call_sites.calls_in(func_ref).iter();
+                worklist.extend(callees.map(|(_, callee)| *callee));
+            }
+        }
+
+        let dead: Vec<FuncRef> = module
+            .iter_functions()
+            .filter(|func_ref| !reachable.contains(func_ref))
+            .collect();
+
+        // Dead functions may still call each other, so their bodies are dropped before they are
+        // removed.
+        for &func_ref in &dead {
+            let sig = module.funcs[func_ref].sig.clone();
+            module.replace_function(func_ref, Function::new(&module.ctx, sig));
+        }
+        for func_ref in dead {
+            module
+                .remove_function(func_ref)
+                .expect("only dead functions call dead functions");
+            self.removed_num += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        Signature, Type,
+    };
+
+    use crate::optim::inliner::{InlineThreshold, Inliner};
+
+    #[test]
+    fn remove_inlined_callee() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+
+        let sig = Signature::new("callee", Linkage::Private, &[Type::I32], Type::I32);
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let v = builder.add(x, x);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("test_func", Linkage::Public, &[Type::I32], Type::I32);
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let v = builder.call(callee, &[x]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        // The callee is still called.
+        let mut dfe = DeadFuncElim::new();
+        dfe.run(&mut module);
+        assert_eq!(dfe.removed_num(), 0);
+
+        Inliner::new(InlineThreshold::default()).run(&mut module);
+        dfe.run(&mut module);
+        assert_eq!(dfe.removed_num(), 1);
+        assert!(module.is_removed(callee));
+        assert_eq!(module.iter_functions().collect::<Vec<_>>(), vec![caller]);
+    }
+}
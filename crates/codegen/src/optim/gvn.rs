@@ -107,7 +107,7 @@ impl GvnSolver {
             rank += 1;
 
             for insn in func.layout.iter_insn(block) {
-                if let Some(insn_result) = func.dfg.insn_result(insn) {
+                for &insn_result in func.dfg.insn_results(insn) {
                     self.values[insn_result].rank = rank;
                     rank += 1;
                 }
@@ -330,10 +330,16 @@ impl GvnSolver {
 
         // If insn has a side effect, create new class if the value still belongs to
         // `INITIAL_CLASS`.
-        if func.dfg.has_side_effect(insn) {
+        // Insns with more than one result are treated the same way, since only their first result
+        // could be replaced. The other results get classes of their own, like arguments.
+        if func.dfg.has_side_effect(insn) || func.dfg.insn_results(insn).len() > 1 {
             if self.value_class(insn_result) == INITIAL_CLASS {
                 let class = self.make_class(gvn_insn, None);
                 self.assign_class(insn_result, class);
+                for &result in &func.dfg.insn_results(insn)[1..] {
+                    let class = self.make_class(GvnInsn::Value(result), None);
+                    self.assign_class(result, class);
+                }
                 return true;
             } else {
                 return false;
@@ -627,15 +633,21 @@ impl GvnSolver {
                 InsnData::cast(code, arg, ty)
             }
 
-            InsnData::Store { .. }
+            InsnData::OverflowBinary { .. }
+            | InsnData::Store { .. }
+            | InsnData::Mem { .. }
             | InsnData::Load { .. }
             | InsnData::Call { .. }
+            | InsnData::Intrinsic { .. }
             | InsnData::Jump { .. }
             | InsnData::Branch { .. }
             | InsnData::BrTable { .. }
             | InsnData::Alloca { .. }
             | InsnData::Gep { .. }
-            | InsnData::Return { .. } => insn_data.clone(),
+            | InsnData::Return { .. }
+            | InsnData::Revert { .. }
+            | InsnData::Trap { .. }
+            | InsnData::Unreachable => insn_data.clone(),
 
             InsnData::Phi { values, blocks, ty } => {
                 let edges = &self.blocks[block].in_edges;
@@ -1562,8 +1574,7 @@ impl<'a> RedundantCodeRemover<'a> {
                 // Insert new phi insn to top of the phi_insn block.
                 inserter.set_location(CursorLocation::BlockTop(phi_insn.block));
                 let insn = inserter.insert_insn_data(func, phi);
-                let result = inserter.make_result(func, insn).unwrap();
-                inserter.attach_result(func, insn, result);
+                let result = inserter.make_and_attach_result(func, insn).unwrap();
 
                 // Restore the inserter loc.
                 inserter.set_location(current_inserter_loc);
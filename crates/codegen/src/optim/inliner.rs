@@ -0,0 +1,615 @@
+//! This module contains a function inliner.
+//!
+//! Whether a call site is inlined is decided by comparing the cost of the callee, i.e., the size
+//! of its code estimated by the cost table of the target ISA, against a threshold that depends on
+//! the call site context. See [`InlineThreshold`] for the bonuses a call site can get. The
+//! [inline hint](InlineHint) of the callee overrides the decision: `inline(always)` callees are
+//! inlined regardless of their cost, and `inline(never)` ones are never inlined.
+//!
+//! If the caller has branch weights, e.g., from a profile, the [frequency](BlockFrequency) of the
+//! block of the call site also counts: hot call sites get a bonus, and call sites that never run
+//! get no bonus at all, since inlining them only grows the code.
+//!
+//! Inlined instructions keep their source locations, with the call site appended to their
+//! inlined-at chain, so that debuggers and profilers can attribute them to the callee.
+
+use smallvec::SmallVec;
+
+use sonatina_ir::{
+    call_sites::CallSiteIndex,
+    cloner::FunctionCloner,
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    isa::IsaSpecificCostTable,
+    module::FuncRef,
+    Block, ControlFlowGraph, Function, Immediate, InlineHint, Insn, InsnData, Module, Value,
+};
+
+use super::{adce::AdceSolver, sccp::SccpSolver};
+use crate::block_frequency::BlockFrequency;
+
+/// Parameters to compute the inline threshold of a call site, in bytes of code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineThreshold {
+    /// The threshold every call site gets.
+    pub base: usize,
+    /// Added for each immediate argument of the call site.
+    pub const_arg_bonus: usize,
+    /// Added if the call site is the only one that calls the callee in the module.
+    pub single_call_site_bonus: usize,
+    /// Added if the branch weights of the caller say the call site runs more often than the
+    /// caller's entry, e.g., in a loop.
+    pub hot_call_site_bonus: usize,
+    /// If `true`, the body of a callee called with immediate arguments is speculatively folded
+    /// by SCCP with the arguments, and the cost of the folded body is used as the cost of the
+    /// callee.
+    pub speculative_folding: bool,
+}
+
+impl Default for InlineThreshold {
+    fn default() -> Self {
+        Self {
+            base: 320,
+            const_arg_bonus: 80,
+            single_call_site_bonus: 640,
+            hot_call_site_bonus: 160,
+            speculative_folding: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Inliner {
+    threshold: InlineThreshold,
+    call_sites: CallSiteIndex,
+    inlined_num: usize,
+}
+
+impl Inliner {
+    pub fn new(threshold: InlineThreshold) -> Self {
+        Self {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.call_sites.clear();
+        self.inlined_num = 0;
+    }
+
+    /// Returns the number of inlined call sites since the last [`Self::clear`].
+    pub fn inlined_num(&self) -> usize {
+        self.inlined_num
+    }
+
+    /// Inline call sites in all functions in the module.
+    /// Call sites that are exposed by inlining are not inlined in the same run.
+    pub fn run(&mut self, module: &mut Module) {
+        self.call_sites.compute(module);
+
+        let callers: Vec<_> = module.iter_functions().collect();
+        let mut cfg = ControlFlowGraph::new();
+        let mut freq = BlockFrequency::new();
+        for caller in callers {
+            cfg.compute(&module.funcs[caller]);
+            freq.compute(&module.funcs[caller], &cfg);
+            for (call, callee) in self.call_sites.calls_in(caller).to_vec() {
+                if !self.is_inlinable(module, caller, callee) {
+                    continue;
+                }
+
+                let caller_func = &module.funcs[caller];
+                let callee_func = &module.funcs[callee];
+                if callee_func.sig.attrs().inline != InlineHint::Always {
+                    let costs = module.ctx.isa.cost_table();
+                    let cost = self.callee_cost(costs, caller_func, call, callee_func);
+                    if cost > self.threshold_of(caller_func, call, callee, &freq) {
+                        continue;
+                    }
+                }
+
+                let callee_func = module.funcs[callee].clone();
+                inline_call(&mut module.funcs[caller], call, &callee_func);
+                self.inlined_num += 1;
+                self.call_sites.update(module, caller);
+                cfg.compute(&module.funcs[caller]);
+                freq.compute(&module.funcs[caller], &cfg);
+            }
+        }
+    }
+
+    fn is_inlinable(&self, module: &Module, caller: FuncRef, callee: FuncRef) -> bool {
+        let callee_func = &module.funcs[callee];
+        // Inlining either side of a call into or from an `optnone` function would expose its
+        // body to optimizations.
+        caller != callee
+            && !module.funcs[caller].sig.attrs().optnone
+            && !callee_func.sig.attrs().optnone
+            && callee_func.sig.attrs().inline != InlineHint::Never
+            && !module.is_external(callee)
+            && callee_func.layout.entry_block().is_some()
+            && callee_func
+                .layout
+                .iter_block()
+                .filter_map(|block| callee_func.layout.last_insn_of(block))
+                .any(|insn| callee_func.dfg.is_return(insn))
+    }
+
+    /// Returns the inline threshold of the call site.
+    fn threshold_of(
+        &self,
+        caller: &Function,
+        call: Insn,
+        callee: FuncRef,
+        freq: &BlockFrequency,
+    ) -> usize {
+        let block = caller.layout.insn_block(call);
+        let mut threshold = self.threshold.base;
+        if freq.is_cold(block) {
+            return threshold;
+        }
+        if freq.is_hot(block) {
+            threshold += self.threshold.hot_call_site_bonus;
+        }
+        threshold += self.threshold.const_arg_bonus * const_args(caller, call).len();
+        if self.call_sites.call_sites_num(callee) == 1 {
+            threshold += self.threshold.single_call_site_bonus;
+        }
+        threshold
+    }
+
+    /// Returns the cost of inlining `callee` at the call site.
+    fn callee_cost(
+        &self,
+        costs: &dyn IsaSpecificCostTable,
+        caller: &Function,
+        call: Insn,
+        callee: &Function,
+    ) -> usize {
+        let const_args = const_args(caller, call);
+        if !self.threshold.speculative_folding || const_args.is_empty() {
+            return code_size(costs, callee);
+        }
+
+        // Fold a snapshot of the callee with the immediate arguments.
+        let mut snapshot = callee.clone();
+        for (idx, imm) in const_args {
+            let arg = snapshot.arg_values[idx];
+            let imm = snapshot.dfg.make_imm_value(imm);
+            snapshot.dfg.change_to_alias(arg, imm);
+        }
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(&snapshot);
+        SccpSolver::new().run(&mut snapshot, &mut cfg);
+        AdceSolver::new().run(&mut snapshot);
+        code_size(costs, &snapshot)
+    }
+}
+
+/// Inline `callee` at `call` in `caller`.
+/// The call block is split right after the call, and the body of the callee is placed between
+/// the two halves.
+fn inline_call(caller: &mut Function, call: Insn, callee: &Function) {
+    let call_block = caller.layout.insn_block(call);
+    let call_site = caller.dfg.insn_loc(call);
+    let cont = split_block_after(caller, call);
+
+    // Lay out the body of the callee.
+    let mut cloner = FunctionCloner::new(callee);
+    for (arg, call_arg) in callee.arg_values.iter().zip(caller.dfg.insn_args(call)) {
+        cloner.map_value(*arg, *call_arg);
+    }
+    cloner.clone_into(caller, Some(call_block));
+
+    let mut returns: SmallVec<[(Value, Block); 4]> = SmallVec::new();
+    for block in callee.layout.iter_block() {
+        for insn in callee.layout.iter_insn(block) {
+            let new_insn = cloner.insn(insn).unwrap();
+            if let Some(loc) = caller.dfg.insn_loc(new_insn) {
+                let loc = caller.dfg.ctx.with_source_file_store_mut(|s| {
+                    s.inline_loc(loc, callee.sig.name(), call_site)
+                });
+                caller.dfg.set_insn_loc(new_insn, Some(loc));
+            }
+
+            match caller.dfg.insn_data(new_insn).clone() {
+                InsnData::Return { args } => {
+                    if let Some(value) = args {
+                        returns.push((value, caller.layout.insn_block(new_insn)));
+                    }
+                    caller.dfg.replace_insn(new_insn, InsnData::jump(cont));
+                }
+                // Returns of the callee are turned into jumps, so the call is no longer in tail
+                // position.
+                InsnData::Call {
+                    func, args, ret_ty, ..
+                } => {
+                    let data = InsnData::Call {
+                        func,
+                        args,
+                        ret_ty,
+                        is_tail: false,
+                    };
+                    caller.dfg.replace_insn(new_insn, data);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Replace the call with a jump to the inlined entry, and merge returned values.
+    let call_result = caller.dfg.insn_result(call);
+    InsnInserter::at_location(CursorLocation::At(call)).remove_insn(caller);
+    let callee_entry = cloner.block(callee.layout.entry_block().unwrap()).unwrap();
+    let jump = caller.dfg.make_insn(InsnData::jump(callee_entry));
+    caller.dfg.set_insn_loc(jump, call_site);
+    caller.layout.append_insn(jump, call_block);
+
+    let Some(call_result) = call_result else {
+        return;
+    };
+    let ret_value = match returns.as_slice() {
+        [(value, _)] => *value,
+        _ => {
+            let mut inserter = InsnInserter::at_location(CursorLocation::BlockTop(cont));
+            let phi = inserter.insert_insn_data(
+                caller,
+                InsnData::Phi {
+                    values: returns.iter().map(|(value, _)| *value).collect(),
+                    blocks: returns.iter().map(|(_, block)| *block).collect(),
+                    ty: caller.dfg.value_ty(call_result),
+                },
+            );
+            let phi_result = inserter.make_result(caller, phi).unwrap();
+            inserter.attach_result(caller, phi, phi_result);
+            phi_result
+        }
+    };
+    caller.dfg.change_to_alias(call_result, ret_value);
+}
+
+/// Move insns after `insn` to a new block inserted right after the block of `insn`, and returns
+/// the new block.
+fn split_block_after(func: &mut Function, insn: Insn) -> Block {
+    let block = func.layout.insn_block(insn);
+    let new_block = func.dfg.make_block();
+    func.layout.insert_block_after(new_block, block);
+
+    let mut next_insn = func.layout.next_insn_of(insn);
+    while let Some(insn) = next_insn {
+        next_insn = func.layout.next_insn_of(insn);
+        func.layout.remove_insn(insn);
+        func.layout.append_insn(insn, new_block);
+    }
+
+    // Successors now receive control from the new block.
+    let Some(term) = func.layout.last_insn_of(new_block) else {
+        return new_block;
+    };
+    let dests: SmallVec<[Block; 4]> = func.dfg.analyze_branch(term).iter_dests().collect();
+    for dest in dests {
+        func.rename_phi_blocks(dest, block, new_block);
+    }
+
+    new_block
+}
+
+/// Returns call sites in the function in layout order.
+pub(super) fn call_sites_in(func: &Function) -> Vec<(Insn, FuncRef)> {
+    func.layout
+        .iter_block()
+        .flat_map(|block| func.layout.iter_insn(block))
+        .filter_map(|insn| match func.dfg.insn_data(insn) {
+            InsnData::Call { func, .. } => Some((insn, *func)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns immediate arguments of the call with their indices.
+fn const_args(func: &Function, call: Insn) -> SmallVec<[(usize, Immediate); 4]> {
+    func.dfg
+        .insn_args(call)
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, arg)| Some((idx, func.dfg.value_imm(*arg)?)))
+        .collect()
+}
+
+/// Returns the estimated size of the code of `func` in bytes.
+pub(super) fn code_size(costs: &dyn IsaSpecificCostTable, func: &Function) -> usize {
+    func.layout
+        .iter_block()
+        .flat_map(|block| func.layout.iter_insn(block))
+        .map(|insn| costs.insn_size(func.dfg.insn_data(insn)) as usize)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        insn::BinaryOp,
+        module::ModuleCtx,
+        source_loc::InlinedAtData,
+        Linkage, Signature, SourceLoc, Type,
+    };
+
+    /// Build a module with `%callee(v0.i1, v1.i32) -> i32`, which returns either `v1 + 1` or
+    /// `v1 * v1` depending on `v0`, and `%test_func(v0.i32, v1.i1) -> i32`, which calls it with
+    /// `cond` or `v1` if `cond` is `None`.
+    fn build_module(cond: Option<bool>) -> (Module, FuncRef) {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+
+        let sig = Signature::new(
+            "callee",
+            Linkage::Private,
+            &[Type::I1, Type::I32],
+            Type::I32,
+        );
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (c, x) = (builder.args()[0], builder.args()[1]);
+        builder.br(c, b1, b2);
+        builder.switch_to_block(b1);
+        let one = builder.make_imm_value(1i32);
+        let v = builder.add(x, one);
+        builder.ret(Some(v));
+        builder.switch_to_block(b2);
+        let v = builder.mul(x, x);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new(
+            "test_func",
+            Linkage::Public,
+            &[Type::I32, Type::I1],
+            Type::I32,
+        );
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let c = match cond {
+            Some(cond) => builder.make_imm_value(cond),
+            None => builder.args()[1],
+        };
+        let v = builder.call(callee, &[c, x]).unwrap();
+        let v = builder.sub(v, x);
+        builder.ret(Some(v));
+        builder.seal_all();
+
+        (builder.finish().build(), caller)
+    }
+
+    #[test]
+    fn inline_with_phi() {
+        let (mut module, caller) = build_module(Some(true));
+
+        let mut inliner = Inliner::new(InlineThreshold::default());
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, caller),
+            "func public %test_func(v0.i32, v1.i1) -> i32 {
+    block0:
+        jump block2;
+
+    block2:
+        br 1.i1 block3 block4;
+
+    block3:
+        v5.i32 = add v0 1.i32;
+        jump block1;
+
+    block4:
+        v6.i32 = mul v0 v0;
+        jump block1;
+
+    block1:
+        v8.i32 = phi (v5 block3) (v6 block4);
+        v4.i32 = sub v8 v0;
+        return v4;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn context_sensitive_threshold() {
+        let (module, _) = build_module(None);
+        let callee = module
+            .iter_functions()
+            .find(|func_ref| module.funcs[*func_ref].sig.name() == "callee")
+            .unwrap();
+        let callee_size = code_size(module.ctx.isa.cost_table(), &module.funcs[callee]);
+        let threshold = InlineThreshold {
+            base: callee_size - 1,
+            const_arg_bonus: 0,
+            single_call_site_bonus: 0,
+            hot_call_site_bonus: 0,
+            speculative_folding: true,
+        };
+
+        // The callee collapses to a `mul` and a `return` once the condition is known.
+        let (mut module, _) = build_module(Some(false));
+        let mut inliner = Inliner::new(threshold);
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 1);
+
+        let (mut module, _) = build_module(None);
+        let mut inliner = Inliner::new(threshold);
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 0);
+
+        let (mut module, _) = build_module(None);
+        let mut inliner = Inliner::new(InlineThreshold {
+            single_call_site_bonus: 1,
+            ..threshold
+        });
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 1);
+    }
+
+    #[test]
+    fn skip_cold_call_sites() {
+        let build = |weights: &[u32]| {
+            let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+            let sig = Signature::new("callee", Linkage::Private, &[Type::I32], Type::I32);
+            let callee = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(callee);
+            let b0 = builder.append_block();
+            builder.switch_to_block(b0);
+            let x = builder.args()[0];
+            let one = builder.make_imm_value(1i32);
+            let v = builder.add(x, one);
+            builder.ret(Some(v));
+            builder.seal_all();
+            let mut mb = builder.finish();
+
+            let sig = Signature::new(
+                "test_func",
+                Linkage::Public,
+                &[Type::I32, Type::I1],
+                Type::I32,
+            );
+            let caller = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(caller);
+            let b0 = builder.append_block();
+            let b1 = builder.append_block();
+            let b2 = builder.append_block();
+            let (x, c) = (builder.args()[0], builder.args()[1]);
+            builder.switch_to_block(b0);
+            builder.br(c, b1, b2);
+            builder.set_branch_weights(weights);
+            builder.switch_to_block(b1);
+            let v = builder.call(callee, &[x]).unwrap();
+            builder.ret(Some(v));
+            builder.switch_to_block(b2);
+            builder.ret(Some(x));
+            builder.seal_all();
+            builder.finish().build()
+        };
+        let threshold = InlineThreshold {
+            base: 0,
+            const_arg_bonus: 0,
+            single_call_site_bonus: 1000,
+            hot_call_site_bonus: 0,
+            speculative_folding: false,
+        };
+
+        // The call site never runs, so it gets no bonus.
+        let mut module = build(&[0, 1]);
+        let mut inliner = Inliner::new(threshold);
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 0);
+
+        let mut module = build(&[1, 0]);
+        let mut inliner = Inliner::new(threshold);
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 1);
+    }
+
+    #[test]
+    fn respect_inline_hints() {
+        let find_callee = |module: &Module| {
+            module
+                .iter_functions()
+                .find(|func_ref| module.funcs[*func_ref].sig.name() == "callee")
+                .unwrap()
+        };
+        let never_inline = InlineThreshold {
+            base: 0,
+            const_arg_bonus: 0,
+            single_call_site_bonus: 0,
+            hot_call_site_bonus: 0,
+            speculative_folding: false,
+        };
+
+        let (mut module, _) = build_module(None);
+        let callee = find_callee(&module);
+        module.funcs[callee].sig.attrs_mut().inline = InlineHint::Always;
+        let mut inliner = Inliner::new(never_inline);
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 1);
+
+        let (mut module, _) = build_module(Some(true));
+        let callee = find_callee(&module);
+        module.funcs[callee].sig.attrs_mut().inline = InlineHint::Never;
+        let mut inliner = Inliner::new(InlineThreshold::default());
+        inliner.run(&mut module);
+        assert_eq!(inliner.inlined_num(), 0);
+    }
+
+    #[test]
+    fn record_inlined_at() {
+        let (mut module, caller) = build_module(Some(true));
+        let callee = module
+            .iter_functions()
+            .find(|func_ref| module.funcs[*func_ref].sig.name() == "callee")
+            .unwrap();
+
+        let file = module
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("main.fe"));
+        let callee_func = &mut module.funcs[callee];
+        let insns: Vec<_> = callee_func
+            .layout
+            .iter_block()
+            .flat_map(|block| callee_func.layout.iter_insn(block))
+            .collect();
+        for (i, insn) in insns.into_iter().enumerate() {
+            let loc = SourceLoc::new(file, i as u32, i as u32 + 1);
+            callee_func.dfg.set_insn_loc(insn, Some(loc));
+        }
+        let call_loc = SourceLoc::new(file, 100, 110);
+        let caller_func = &mut module.funcs[caller];
+        let (call, _) = call_sites_in(caller_func)[0];
+        caller_func.dfg.set_insn_loc(call, Some(call_loc));
+
+        Inliner::new(InlineThreshold::default()).run(&mut module);
+
+        // The `add` insn is the second insn of the callee.
+        let caller_func = &module.funcs[caller];
+        let add = caller_func
+            .layout
+            .iter_block()
+            .flat_map(|block| caller_func.layout.iter_insn(block))
+            .find(|insn| {
+                matches!(
+                    caller_func.dfg.insn_data(*insn),
+                    InsnData::Binary {
+                        code: BinaryOp::Add,
+                        ..
+                    }
+                )
+            })
+            .unwrap();
+        let loc = caller_func.dfg.insn_loc(add).unwrap();
+        assert_eq!((loc.start, loc.end), (1, 2));
+
+        let inlined_at = module
+            .ctx
+            .with_source_file_store(|s| s.inlined_at_data(loc.inlined_at.unwrap()).clone());
+        assert_eq!(
+            inlined_at,
+            InlinedAtData {
+                callee: "callee".to_string(),
+                call_site: Some(call_loc),
+                parent: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! This module contains a solver for instruction combining.
+//!
+//! Unlike [`super::insn_simplify`], which only rewrites an instruction to an existing value or a
+//! simpler instruction found by pattern matching, this pass reassociates chains of arithmetic
+//! and logic instructions so that their immediate operands can be folded together.
+//! Rewrites are driven by [`RULES`] and applied until a fixpoint is reached.
+//!
+//! `shl` by an immediate is a multiplication by a power of two, so it is reassociated with other
+//! shifts and multiplications by immediates. It is kept as a shift otherwise, since shifts are
+//! cheaper than multiplications on every target.
+//!
+//! Rotates written as a pair of shifts, e.g., in hash functions, are combined into `rotl`.
+
+use std::collections::VecDeque;
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    insn::{BinaryOp, UnaryOp},
+    DataFlowGraph, Function, Immediate, Insn, InsnData, Value, I256, U256,
+};
+
+use super::constant_folding::fold_constant;
+use crate::known_bits::bit_width;
+
+/// A rewrite rule. Returns `None` if the rule doesn't match the instruction.
+type Rule = fn(&mut DataFlowGraph, Insn) -> Option<Rewrite>;
+
+/// Rewrite rules tried in order. The first matching rule wins.
+const RULES: &[Rule] = &[
+    fold_imm,
+    identity,
+    absorb,
+    canonicalize_commutative,
+    double_negation,
+    neg_of_sub,
+    add_of_neg,
+    or_of_shifts_to_rotl,
+    sub_imm_to_add,
+    reassociate_shl,
+    reassociate_imm,
+];
+
+#[derive(Debug)]
+pub struct InsnCombineSolver {
+    worklist: VecDeque<Insn>,
+    rewritten_num: usize,
+}
+
+impl InsnCombineSolver {
+    pub fn new() -> Self {
+        Self {
+            worklist: VecDeque::default(),
+            rewritten_num: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.worklist.clear();
+        self.rewritten_num = 0;
+    }
+
+    /// Returns the number of rewrites applied since the last [`Self::clear`].
+    pub fn rewritten_num(&self) -> usize {
+        self.rewritten_num
+    }
+
+    pub fn run(&mut self, func: &mut Function) {
+        for block in func.layout.iter_block() {
+            self.worklist.extend(func.layout.iter_insn(block));
+        }
+
+        while let Some(insn) = self.worklist.pop_front() {
+            if !func.layout.is_insn_inserted(insn) {
+                continue;
+            }
+
+            let Some(rewrite) = RULES.iter().find_map(|rule| rule(&mut func.dfg, insn)) else {
+                continue;
+            };
+            self.apply(func, insn, rewrite);
+        }
+    }
+
+    fn apply(&mut self, func: &mut Function, insn: Insn, rewrite: Rewrite) {
+        self.rewritten_num += 1;
+
+        let result = func.dfg.insn_result(insn).unwrap();
+        self.worklist.extend(func.dfg.users(result).copied());
+        let old_args: Vec<_> = func.dfg.insn_args(insn).to_vec();
+
+        match rewrite {
+            Rewrite::Value(value) => {
+                func.dfg.change_to_alias(result, value);
+                InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+            }
+
+            Rewrite::Insn(data) => {
+                func.dfg.replace_insn(insn, data);
+                self.worklist.push_back(insn);
+            }
+        }
+
+        for arg in old_args {
+            self.remove_if_dead(func, arg);
+        }
+    }
+
+    /// Remove the instruction defining `value` if the rewrite made it unused.
+    fn remove_if_dead(&mut self, func: &mut Function, value: Value) {
+        let mut dead = vec![value];
+        while let Some(value) = dead.pop() {
+            let Some(insn) = func.dfg.value_insn(value) else {
+                continue;
+            };
+
+            if func.dfg.users_num(value) != 0
+                || !func.layout.is_insn_inserted(insn)
+                || func.dfg.has_side_effect(insn)
+                || func.dfg.may_trap(insn)
+                || func.dfg.is_phi(insn)
+            {
+                continue;
+            }
+
+            dead.extend_from_slice(func.dfg.insn_args(insn));
+            InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+        }
+    }
+}
+
+impl Default for InsnCombineSolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Rewrite {
+    /// Replace all uses of the result with the value and remove the instruction.
+    Value(Value),
+    /// Replace the instruction in place, keeping its result value.
+    Insn(InsnData),
+}
+
+fn binary(dfg: &DataFlowGraph, insn: Insn) -> Option<(BinaryOp, Value, Value)> {
+    match dfg.insn_data(insn) {
+        InsnData::Binary { code, args } => Some((*code, args[0], args[1])),
+        _ => None,
+    }
+}
+
+fn unary(dfg: &DataFlowGraph, insn: Insn) -> Option<(UnaryOp, Value)> {
+    match dfg.insn_data(insn) {
+        InsnData::Unary { code, args } => Some((*code, args[0])),
+        _ => None,
+    }
+}
+
+fn value_binary(dfg: &DataFlowGraph, value: Value) -> Option<(BinaryOp, Value, Value)> {
+    binary(dfg, dfg.value_insn(value)?)
+}
+
+fn value_unary(dfg: &DataFlowGraph, value: Value) -> Option<(UnaryOp, Value)> {
+    unary(dfg, dfg.value_insn(value)?)
+}
+
+fn make_binary(code: BinaryOp, lhs: Value, rhs: Value) -> Rewrite {
+    Rewrite::Insn(InsnData::Binary {
+        code,
+        args: [lhs, rhs],
+    })
+}
+
+/// `c1 op c2 => c3`.
+fn fold_imm(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    if let Some((BinaryOp::Udiv | BinaryOp::Sdiv, _, rhs)) = binary(dfg, insn) {
+        if dfg.value_imm(rhs)?.is_zero() {
+            return None;
+        }
+    }
+
+    let imm = fold_constant(&*dfg, dfg.insn_data(insn))?;
+    Some(Rewrite::Value(dfg.make_imm_value(imm)))
+}
+
+/// `x + 0`, `x - 0`, `x | 0`, `x ^ 0`, `x << 0`, `rotl x 0`, `x * 1`, `x / 1`, `x & -1 => x`.
+fn identity(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, lhs, rhs) = binary(dfg, insn)?;
+    let rhs = dfg.value_imm(rhs)?;
+
+    let is_identity = match code {
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Or
+        | BinaryOp::Xor
+        | BinaryOp::Shl
+        | BinaryOp::Shr
+        | BinaryOp::Sar
+        | BinaryOp::Rotl
+        | BinaryOp::Rotr => rhs.is_zero(),
+        BinaryOp::Mul | BinaryOp::Udiv | BinaryOp::Sdiv => rhs.is_one(),
+        BinaryOp::And => rhs.is_all_one(),
+        _ => false,
+    };
+
+    is_identity.then_some(Rewrite::Value(lhs))
+}
+
+/// `x * 0`, `x & 0 => 0` and `x | -1 => -1`.
+fn absorb(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, _, rhs) = binary(dfg, insn)?;
+    let rhs_imm = dfg.value_imm(rhs)?;
+
+    let is_absorbing = match code {
+        BinaryOp::Mul | BinaryOp::And => rhs_imm.is_zero(),
+        BinaryOp::Or => rhs_imm.is_all_one(),
+        _ => false,
+    };
+
+    is_absorbing.then_some(Rewrite::Value(rhs))
+}
+
+/// `c op x => x op c` if `op` is commutative.
+fn canonicalize_commutative(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, lhs, rhs) = binary(dfg, insn)?;
+    if code.is_commutative() && dfg.is_imm(lhs) && !dfg.is_imm(rhs) {
+        Some(make_binary(code, rhs, lhs))
+    } else {
+        None
+    }
+}
+
+/// `-(-x) => x` and `!(!x) => x`.
+fn double_negation(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, arg) = unary(dfg, insn)?;
+    let (inner_code, inner_arg) = value_unary(dfg, arg)?;
+    (code == inner_code).then_some(Rewrite::Value(inner_arg))
+}
+
+/// `-(x - y) => y - x`.
+fn neg_of_sub(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (UnaryOp::Neg, arg) = unary(dfg, insn)? else {
+        return None;
+    };
+    let (BinaryOp::Sub, x, y) = value_binary(dfg, arg)? else {
+        return None;
+    };
+    Some(make_binary(BinaryOp::Sub, y, x))
+}
+
+/// `x + (-y) => x - y`.
+fn add_of_neg(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (BinaryOp::Add, lhs, rhs) = binary(dfg, insn)? else {
+        return None;
+    };
+
+    if let Some((UnaryOp::Neg, y)) = value_unary(dfg, rhs) {
+        Some(make_binary(BinaryOp::Sub, lhs, y))
+    } else if let Some((UnaryOp::Neg, y)) = value_unary(dfg, lhs) {
+        Some(make_binary(BinaryOp::Sub, rhs, y))
+    } else {
+        None
+    }
+}
+
+/// `(x << c) | (x >> (n - c)) => rotl x c`, where `n` is the bit width of `x`.
+fn or_of_shifts_to_rotl(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (BinaryOp::Or, lhs, rhs) = binary(dfg, insn)? else {
+        return None;
+    };
+    let ty = dfg.value_ty(lhs);
+
+    let (x, amount) = [(lhs, rhs), (rhs, lhs)]
+        .into_iter()
+        .find_map(|(lhs, rhs)| {
+            let (x, shl_amount) = value_shl_imm(dfg, lhs)?;
+            let (y, shr_amount) = value_shr_imm(dfg, rhs)?;
+            (x == y && shl_amount + shr_amount == bit_width(ty)).then_some((x, shl_amount))
+        })?;
+
+    let amount = Immediate::from_i256(I256::from_u256(U256::from(amount)), ty);
+    let amount = dfg.make_imm_value(amount);
+    Some(make_binary(BinaryOp::Rotl, x, amount))
+}
+
+/// Returns `x` and `c` if `value` is `x << c`, or `x * (1 << c)`.
+fn value_shl_imm(dfg: &DataFlowGraph, value: Value) -> Option<(Value, usize)> {
+    match value_binary(dfg, value)? {
+        (BinaryOp::Shl, x, amount) => Some((x, shift_amount(dfg, amount)?)),
+        (BinaryOp::Mul, x, factor) => {
+            let factor = dfg.value_imm(factor)?;
+            if factor.is_zero() || !factor.is_power_of_two() {
+                return None;
+            }
+            Some((x, factor.as_unsigned().trailing_zeros() as usize))
+        }
+        _ => None,
+    }
+}
+
+/// Returns `x` and `c` if `value` is `x >> c`.
+fn value_shr_imm(dfg: &DataFlowGraph, value: Value) -> Option<(Value, usize)> {
+    let (BinaryOp::Shr, x, amount) = value_binary(dfg, value)? else {
+        return None;
+    };
+    Some((x, shift_amount(dfg, amount)?))
+}
+
+/// Returns the immediate shift amount `amount` if it's less than the bit width.
+fn shift_amount(dfg: &DataFlowGraph, amount: Value) -> Option<usize> {
+    let amount = dfg.value_imm(amount)?;
+    let bits = amount.bit_width();
+    let amount = amount.as_unsigned();
+    (amount < U256::from(bits)).then(|| amount.as_usize())
+}
+
+/// `x - c => x + (-c)`, so that the immediate can be reassociated.
+fn sub_imm_to_add(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (BinaryOp::Sub, lhs, rhs) = binary(dfg, insn)? else {
+        return None;
+    };
+    if dfg.is_imm(lhs) {
+        return None;
+    }
+
+    let neg = -dfg.value_imm(rhs)?;
+    let neg = dfg.make_imm_value(neg);
+    Some(make_binary(BinaryOp::Add, lhs, neg))
+}
+
+/// Reassociates shifts left by immediates, which are multiplications by powers of two:
+/// - `(x << c1) << c2 => x << (c1 + c2)`, which is `0` if the amount reaches the width of `x`.
+/// - `(x * c1) << c2 => x * (c1 << c2)`.
+/// - `(x << c1) * c2 => x * (c2 << c1)`.
+fn reassociate_shl(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, lhs, rhs) = binary(dfg, insn)?;
+    let c2 = dfg.value_imm(rhs)?;
+    let (inner_code, x, c1) = value_binary(dfg, lhs)?;
+    let c1 = dfg.value_imm(c1)?;
+
+    match (inner_code, code) {
+        (BinaryOp::Shl, BinaryOp::Shl) => {
+            let bits = U256::from(bit_width(dfg.value_ty(x)));
+            let (c1, c2_amount) = (c1.as_unsigned(), c2.as_unsigned());
+            if c1 >= bits || c2_amount >= bits {
+                return None;
+            }
+
+            if c1 + c2_amount >= bits {
+                let zero = dfg.make_imm_value(Immediate::zero(dfg.value_ty(x)));
+                return Some(Rewrite::Value(zero));
+            }
+            let amount = Immediate::from_i256(I256::from_u256(c1 + c2_amount), c2.ty());
+            let amount = dfg.make_imm_value(amount);
+            Some(make_binary(BinaryOp::Shl, x, amount))
+        }
+        (BinaryOp::Mul, BinaryOp::Shl) => {
+            let factor = dfg.make_imm_value(c1 << c2);
+            Some(make_binary(BinaryOp::Mul, x, factor))
+        }
+        (BinaryOp::Shl, BinaryOp::Mul) => {
+            let factor = dfg.make_imm_value(c2 << c1);
+            Some(make_binary(BinaryOp::Mul, x, factor))
+        }
+        _ => None,
+    }
+}
+
+/// `(x op c1) op c2 => x op (c1 op c2)` for associative and commutative `op`.
+fn reassociate_imm(dfg: &mut DataFlowGraph, insn: Insn) -> Option<Rewrite> {
+    let (code, lhs, rhs) = binary(dfg, insn)?;
+    if !code.is_commutative() {
+        return None;
+    }
+    let c2 = dfg.value_imm(rhs)?;
+
+    let (inner_code, x, c1) = value_binary(dfg, lhs)?;
+    if inner_code != code {
+        return None;
+    }
+    let c1 = dfg.value_imm(c1)?;
+
+    let folded = match code {
+        BinaryOp::Add => c1 + c2,
+        BinaryOp::Mul => c1 * c2,
+        BinaryOp::And => c1 & c2,
+        BinaryOp::Or => c1 | c2,
+        BinaryOp::Xor => c1 ^ c2,
+        _ => return None,
+    };
+    let folded = dfg.make_imm_value(folded);
+    Some(make_binary(code, x, folded))
+}
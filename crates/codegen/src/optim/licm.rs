@@ -55,8 +55,8 @@ impl LicmSolver {
             for insn in func.layout.iter_insn(block) {
                 if self.is_invariant(func, &loop_var, insn) {
                     self.invariants.push(insn);
-                } else if let Some(result) = func.dfg.insn_result(insn) {
-                    loop_var.insert(result);
+                } else {
+                    loop_var.extend(func.dfg.insn_results(insn));
                 }
             }
         }
@@ -185,8 +185,7 @@ impl LicmSolver {
                     let mut inserter =
                         InsnInserter::at_location(CursorLocation::BlockTop(new_preheader));
                     let new_phi_insn = inserter.insert_insn_data(func, phi_insn_data.clone());
-                    let result = inserter.make_result(func, new_phi_insn).unwrap();
-                    inserter.attach_result(func, new_phi_insn, result);
+                    let result = inserter.make_and_attach_result(func, new_phi_insn).unwrap();
 
                     // Add phi_insn_data to `inserted_phis` for reusing.
                     inserted_phis.insert(phi_insn_data, result);
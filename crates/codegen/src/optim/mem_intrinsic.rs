@@ -0,0 +1,190 @@
+//! This module contains a solver that simplifies memory intrinsics, i.e., `memcpy`, `memmove`
+//! and `memset`.
+//!
+//! An intrinsic with a zero length, and a copy whose destination is its source, are removed.
+//! Within a block, a load from the destination of the latest intrinsic is forwarded as long as no
+//! insn with side effects comes in between:
+//! - A load after a `memcpy` reads from the source instead, which leaves the copy dead if nothing
+//!   else reads the destination. A `memmove` isn't forwarded, since it may overwrite its source.
+//! - A load of an integer after a `memset` with an immediate byte is replaced with the byte
+//!   repeated over the width of the integer.
+//!
+//! The load must be covered by the length of the intrinsic, i.e., a memory length of at least the
+//! size of the loaded type, or a storage length of at least one slot.
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    insn::MemOp,
+    DataLocationKind, Function, Immediate, Insn, InsnData, Type, Value, I256, U256,
+};
+
+#[derive(Debug, Default)]
+pub struct MemIntrinsicSolver {
+    removed_num: usize,
+    forwarded_num: usize,
+}
+
+impl MemIntrinsicSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.removed_num = 0;
+        self.forwarded_num = 0;
+    }
+
+    /// Returns the number of intrinsics removed since the last [`Self::clear`].
+    pub fn removed_num(&self) -> usize {
+        self.removed_num
+    }
+
+    /// Returns the number of loads forwarded since the last [`Self::clear`].
+    pub fn forwarded_num(&self) -> usize {
+        self.forwarded_num
+    }
+
+    pub fn run(&mut self, func: &mut Function) {
+        let blocks: Vec<_> = func.layout.iter_block().collect();
+        for block in blocks {
+            let insns: Vec<_> = func.layout.iter_insn(block).collect();
+            let mut latest = None;
+            for insn in insns {
+                match *func.dfg.insn_data(insn) {
+                    InsnData::Mem { code, args, .. } => {
+                        let [dst, src, len] = args;
+                        let is_copy = matches!(code, MemOp::Memcpy | MemOp::Memmove);
+                        if const_len(func, len) == Some(U256::zero()) || (is_copy && dst == src) {
+                            InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+                            self.removed_num += 1;
+                            latest = None;
+                        } else {
+                            latest = Some(insn);
+                        }
+                    }
+                    InsnData::Load { .. } => {
+                        if let Some(mem) = latest {
+                            self.forward_load(func, mem, insn);
+                        }
+                    }
+                    _ if func.dfg.has_side_effect(insn) => latest = None,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Forwards `load` if it reads the destination of the intrinsic `mem`.
+    fn forward_load(&mut self, func: &mut Function, mem: Insn, load: Insn) {
+        let InsnData::Mem {
+            code,
+            args: [dst, src, len],
+            loc,
+        } = *func.dfg.insn_data(mem)
+        else {
+            unreachable!();
+        };
+        let InsnData::Load {
+            args: [addr],
+            loc: load_loc,
+        } = *func.dfg.insn_data(load)
+        else {
+            return;
+        };
+        let ty = func.dfg.insn_result_ty(load).unwrap();
+        if addr != dst || load_loc != loc || !ty.is_integral() {
+            return;
+        }
+
+        let Some(len) = const_len(func, len) else {
+            return;
+        };
+        let covered = match loc {
+            DataLocationKind::Memory => {
+                len >= U256::from(func.dfg.ctx.isa.type_layout().scalar_size(ty))
+            }
+            DataLocationKind::Storage => !len.is_zero(),
+        };
+        if !covered {
+            return;
+        }
+
+        match code {
+            MemOp::Memcpy => {
+                func.dfg.replace_insn_arg(load, src, 0);
+            }
+            MemOp::Memset => {
+                let Some(byte) = func.dfg.value_imm(src) else {
+                    return;
+                };
+                let result = func.dfg.insn_result(load).unwrap();
+                let splat = func.dfg.make_imm_value(splat_byte(byte, ty));
+                func.dfg.change_to_alias(result, splat);
+                InsnInserter::at_location(CursorLocation::At(load)).remove_insn(func);
+            }
+            MemOp::Memmove => return,
+        }
+        self.forwarded_num += 1;
+    }
+}
+
+fn const_len(func: &Function, len: Value) -> Option<U256> {
+    func.dfg.value_imm(len).map(Immediate::as_unsigned)
+}
+
+/// Returns `byte` repeated over the width of `ty`.
+fn splat_byte(byte: Immediate, ty: Type) -> Immediate {
+    let byte = byte.as_unsigned() & U256::from(0xffu8);
+    let word = (0..32).fold(U256::zero(), |word, _| word << 8 | byte);
+    Immediate::from_i256(I256::from_u256(word), ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::builder::test_util::*;
+
+    #[test]
+    fn forward_and_remove() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let dst = builder.alloca(Type::I32);
+        let src = builder.alloca(Type::I32);
+        let zero = builder.make_imm_value(I256::zero());
+        let len = builder.make_imm_value(I256::from(4u8));
+        let byte = builder.make_imm_value(1i8);
+        builder.memmove(DataLocationKind::Memory, dst, src, zero);
+        builder.memcpy(DataLocationKind::Memory, dst, src, len);
+        let v2 = builder.memory_load(dst);
+        builder.memset(DataLocationKind::Memory, src, byte, len);
+        let v3 = builder.memory_load(src);
+        let v4 = builder.add(v2, v3);
+        builder.ret(Some(v4));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut solver = MemIntrinsicSolver::new();
+        solver.run(func);
+        assert_eq!(solver.removed_num(), 1);
+        assert_eq!(solver.forwarded_num(), 2);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func() -> i32 {
+    block0:
+        v0.*i32 = alloca i32;
+        v1.*i32 = alloca i32;
+        memcpy @memory v0 v1 4.i256;
+        v5.i32 = load @memory v1;
+        memset @memory v1 1.i8 4.i256;
+        v7.i32 = add v5 16843009.i32;
+        return v7;
+
+}
+"
+        );
+    }
+}
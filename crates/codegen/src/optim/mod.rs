@@ -1,8 +1,24 @@
 pub mod adce;
+pub mod block_layout;
+pub mod cold_split;
+pub mod const_global_fold;
+pub mod cse;
+pub mod dfe;
 pub mod gvn;
+pub mod inliner;
+pub mod insn_combine;
 pub mod insn_simplify;
 pub mod licm;
+pub mod mem_intrinsic;
+pub mod outliner;
+pub mod overflow_check_elim;
+pub mod pre;
 pub mod sccp;
+pub mod simplify_cfg;
+pub mod sink;
+pub mod specializer;
+pub mod strip;
+pub mod tail_call;
 
 mod constant_folding;
 mod simplify_impl;
@@ -0,0 +1,526 @@
+//! This module contains a function outliner to reduce code size.
+//!
+//! The outliner finds sequences of consecutive instructions that are repeated across the module,
+//! extracts each of them into a private helper function, and replaces every occurrence with a
+//! call to the helper. This is the opposite of inlining, and trades the overhead of calls for
+//! smaller code, which matters to stay under the size limit of deployed EVM code. Sizes are
+//! estimated by the cost table of the target ISA.
+//!
+//! Two sequences are identical if they only differ in the values they use from outside of them;
+//! those values become the arguments of the helper. A sequence can define at most one value that
+//! is used outside of it, which becomes the return value of the helper.
+//!
+//! The most profitable sequence is outlined first, then the module is rescanned until no sequence
+//! reduces the size of the code.
+//!
+//! Sequences in blocks that the branch weights say are [hot](BlockFrequency::is_hot), e.g., in a
+//! loop, are left in place, since every run of an occurrence would pay for the call.
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use smallvec::{smallvec, SmallVec};
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    isa::IsaSpecificCostTable,
+    module::FuncRef,
+    ControlFlowGraph, Function, Immediate, Insn, InsnData, Linkage, Module, Signature, Type, Value,
+    ValueData,
+};
+
+use crate::block_frequency::BlockFrequency;
+
+/// Sequences longer than this are not considered to bound the cost of the scan.
+const MAX_SEQ_LEN: usize = 64;
+
+#[derive(Debug)]
+pub struct Outliner {
+    /// The minimum number of insns in an outlined sequence.
+    min_len: usize,
+    outlined_num: usize,
+    helper_num: usize,
+}
+
+impl Default for Outliner {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl Outliner {
+    pub fn new(min_len: usize) -> Self {
+        debug_assert!(min_len > 0);
+        Self {
+            min_len,
+            outlined_num: 0,
+            helper_num: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.outlined_num = 0;
+    }
+
+    /// Returns the number of sequences replaced with calls since the last [`Self::clear`].
+    pub fn outlined_num(&self) -> usize {
+        self.outlined_num
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        while let Some((key, occurrences)) = self.best_candidate(module) {
+            let (helper, sig) = self.make_helper(module, &key, &occurrences[0]);
+
+            // Outputs of replaced occurrences may be inputs of later ones.
+            let mut renamed = FxHashMap::default();
+            for occurrence in occurrences {
+                let func = &mut module.funcs[occurrence.func];
+                replace_with_call(func, helper, &sig, occurrence, &mut renamed);
+                self.outlined_num += 1;
+            }
+        }
+    }
+
+    /// Returns the sequence that reduces the size of the code the most, along with its disjoint
+    /// occurrences.
+    fn best_candidate(&self, module: &Module) -> Option<(SeqKey, Vec<Occurrence>)> {
+        // Candidates are kept in the order of discovery so that ties are broken deterministically.
+        let mut candidates: Vec<(SeqKey, Vec<Occurrence>)> = Vec::new();
+        let mut candidate_idx: FxHashMap<SeqKey, usize> = FxHashMap::default();
+
+        let mut cfg = ControlFlowGraph::new();
+        let mut freq = BlockFrequency::new();
+        for func_ref in module.iter_functions() {
+            let func = &module.funcs[func_ref];
+            if func.sig.attrs().optnone {
+                continue;
+            }
+            cfg.compute(func);
+            freq.compute(func, &cfg);
+            for block in func.layout.iter_block() {
+                if freq.is_hot(block) {
+                    continue;
+                }
+                let insns: Vec<_> = func.layout.iter_insn(block).collect();
+                for run in insns.split(|insn| !is_outlinable(func, *insn)) {
+                    for start in 0..run.len() {
+                        let max_end = run.len().min(start + MAX_SEQ_LEN);
+                        for end in start + self.min_len..=max_end {
+                            let Some((key, occurrence)) =
+                                make_key(func, func_ref, &run[start..end])
+                            else {
+                                continue;
+                            };
+
+                            let idx = *candidate_idx.entry(key.clone()).or_insert_with(|| {
+                                candidates.push((key, Vec::new()));
+                                candidates.len() - 1
+                            });
+                            candidates[idx].1.push(occurrence);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut best = None;
+        let mut best_benefit = 0;
+        for (key, occurrences) in candidates {
+            let occurrences = select_disjoint(occurrences);
+            let benefit = benefit(module.ctx.isa.cost_table(), &key, occurrences.len());
+            if benefit > best_benefit {
+                best_benefit = benefit;
+                best = Some((key, occurrences));
+            }
+        }
+
+        best
+    }
+
+    fn make_helper(
+        &mut self,
+        module: &mut Module,
+        key: &SeqKey,
+        occurrence: &Occurrence,
+    ) -> (FuncRef, Signature) {
+        let origin = &module.funcs[occurrence.func];
+        let ret_ty = match occurrence.output {
+            Some(output) => origin.dfg.value_ty(output),
+            None => Type::Void,
+        };
+        let name = self.helper_name(module);
+        let sig = Signature::new(&name, Linkage::Private, &key.param_tys, ret_ty);
+
+        let mut func = Function::new(&module.ctx, sig.clone());
+        for (data, _) in &key.insns {
+            if let InsnData::Call { func: callee, .. } = data {
+                func.callees.insert(*callee, origin.callees[callee].clone());
+            }
+        }
+
+        let block = func.dfg.make_block();
+        func.layout.append_block(block);
+        let mut results: Vec<Option<Value>> = Vec::with_capacity(key.insns.len());
+        for (data, operands) in &key.insns {
+            let mut data = data.clone();
+            for (arg, operand) in data.args_mut().iter_mut().zip(operands) {
+                *arg = match operand {
+                    Operand::Local(idx) => results[*idx].unwrap(),
+                    Operand::Param(idx) => func.arg_values[*idx],
+                    Operand::Imm(imm) => func.dfg.make_imm_value(*imm),
+                };
+            }
+
+            let insn = func.dfg.make_insn(data);
+            func.layout.append_insn(insn, block);
+            let result = func
+                .dfg
+                .make_result(insn)
+                .map(|data| func.dfg.make_value(data));
+            if let Some(result) = result {
+                func.dfg.attach_result(insn, result);
+            }
+            results.push(result);
+        }
+
+        let ret = func.dfg.make_insn(InsnData::Return {
+            args: key.output.map(|idx| results[idx].unwrap()),
+        });
+        func.layout.append_insn(ret, block);
+
+        (module.funcs.push(func), sig)
+    }
+
+    /// Returns a helper name that doesn't collide with functions in the module.
+    fn helper_name(&mut self, module: &Module) -> String {
+        loop {
+            let name = format!("__outlined_{}", self.helper_num);
+            self.helper_num += 1;
+            if module.funcs.values().all(|func| func.sig.name() != name) {
+                return name;
+            }
+        }
+    }
+}
+
+/// An operand of an insn in a sequence.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    /// The result of the insn at the index in the sequence.
+    Local(usize),
+    /// The argument of the helper at the index.
+    Param(usize),
+    Imm(Immediate),
+}
+
+/// A sequence of insns up to renaming of the values used from outside of it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SeqKey {
+    /// Insn data with dummy args, and the operands that replace them.
+    insns: Vec<(InsnData, SmallVec<[Operand; 4]>)>,
+    param_tys: Vec<Type>,
+    /// The index of the insn whose result is used outside of the sequence.
+    output: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Occurrence {
+    func: FuncRef,
+    insns: Vec<Insn>,
+    /// The values passed as arguments to the helper.
+    inputs: Vec<Value>,
+    output: Option<Value>,
+}
+
+fn is_outlinable(func: &Function, insn: Insn) -> bool {
+    matches!(
+        func.dfg.insn_data(insn),
+        InsnData::Unary { .. }
+            | InsnData::Binary { .. }
+            | InsnData::Cast { .. }
+            | InsnData::Load { .. }
+            | InsnData::Store { .. }
+            | InsnData::Call { .. }
+            | InsnData::Intrinsic { .. }
+            | InsnData::Gep { .. }
+    )
+}
+
+/// Returns the key of `seq`, or `None` if more than one value defined in `seq` is used outside
+/// of it.
+fn make_key(func: &Function, func_ref: FuncRef, seq: &[Insn]) -> Option<(SeqKey, Occurrence)> {
+    let mut locals = FxHashMap::default();
+    let mut inputs = Vec::new();
+    let mut insns = Vec::with_capacity(seq.len());
+
+    for (idx, &insn) in seq.iter().enumerate() {
+        let mut data = func.dfg.insn_data(insn).clone();
+        if let InsnData::Call { is_tail, .. } = &mut data {
+            *is_tail = false;
+        }
+
+        let mut operands = SmallVec::new();
+        for arg in data.args_mut() {
+            let operand = if let Some(&idx) = locals.get(&*arg) {
+                Operand::Local(idx)
+            } else if let ValueData::Immediate { imm, .. } = func.dfg.value_data(*arg) {
+                Operand::Imm(*imm)
+            } else {
+                let idx = match inputs.iter().position(|input| *input == *arg) {
+                    Some(idx) => idx,
+                    None => {
+                        inputs.push(*arg);
+                        inputs.len() - 1
+                    }
+                };
+                Operand::Param(idx)
+            };
+            operands.push(operand);
+            *arg = Value::from_u32(0);
+        }
+
+        if let Some(result) = func.dfg.insn_result(insn) {
+            locals.insert(result, idx);
+        }
+        insns.push((data, operands));
+    }
+
+    let mut outputs = locals
+        .iter()
+        .filter(|(result, _)| func.dfg.users(**result).any(|user| !seq.contains(user)));
+    let output = outputs.next().map(|(&result, &idx)| (result, idx));
+    if outputs.next().is_some() {
+        return None;
+    }
+
+    let key = SeqKey {
+        insns,
+        param_tys: inputs
+            .iter()
+            .map(|input| func.dfg.value_ty(*input))
+            .collect(),
+        output: output.map(|(_, idx)| idx),
+    };
+    let occurrence = Occurrence {
+        func: func_ref,
+        insns: seq.to_vec(),
+        inputs,
+        output: output.map(|(result, _)| result),
+    };
+    Some((key, occurrence))
+}
+
+/// Drops occurrences that overlap with a preceding one. Insns are numbered per function, so
+/// they're told apart by their function as well.
+fn select_disjoint(occurrences: Vec<Occurrence>) -> Vec<Occurrence> {
+    let mut used = FxHashSet::default();
+    occurrences
+        .into_iter()
+        .filter(|occurrence| {
+            let func = occurrence.func;
+            if occurrence
+                .insns
+                .iter()
+                .any(|insn| used.contains(&(func, *insn)))
+            {
+                false
+            } else {
+                used.extend(occurrence.insns.iter().map(|insn| (func, *insn)));
+                true
+            }
+        })
+        .collect()
+}
+
+/// Returns the number of bytes saved by outlining the sequence of `key` occurring `num` times.
+/// Each occurrence is replaced with a call, and the helper has an extra return.
+fn benefit(costs: &dyn IsaSpecificCostTable, key: &SeqKey, num: usize) -> u64 {
+    if num < 2 {
+        return 0;
+    }
+
+    // Only the shape of the call and the return matters to their sizes.
+    let dummy = Value::from_u32(0);
+    let call = InsnData::Call {
+        func: FuncRef::from_u32(0),
+        args: smallvec![dummy; key.param_tys.len()],
+        ret_ty: Type::Void,
+        is_tail: false,
+    };
+    let ret = InsnData::Return {
+        args: key.output.map(|_| dummy),
+    };
+
+    let num = num as u64;
+    let seq_size: u64 = key
+        .insns
+        .iter()
+        .map(|(data, _)| costs.insn_size(data))
+        .sum();
+    (num * seq_size).saturating_sub(num * costs.insn_size(&call) + seq_size + costs.insn_size(&ret))
+}
+
+fn replace_with_call(
+    func: &mut Function,
+    helper: FuncRef,
+    sig: &Signature,
+    occurrence: Occurrence,
+    renamed: &mut FxHashMap<Value, Value>,
+) {
+    func.callees.insert(helper, sig.clone());
+
+    let args = occurrence
+        .inputs
+        .iter()
+        .map(|input| renamed.get(input).copied().unwrap_or(*input))
+        .collect();
+    let call = func.dfg.make_insn(InsnData::Call {
+        func: helper,
+        args,
+        ret_ty: sig.ret_ty(),
+        is_tail: false,
+    });
+    func.layout.insert_insn_before(call, occurrence.insns[0]);
+    let result = func.dfg.make_result(call).unwrap();
+    let result = func.dfg.make_value(result);
+    func.dfg.attach_result(call, result);
+
+    if let Some(output) = occurrence.output {
+        func.dfg.change_to_alias(output, result);
+        renamed.insert(output, result);
+    }
+
+    for &insn in occurrence.insns.iter().rev() {
+        InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        module::ModuleCtx,
+    };
+
+    #[test]
+    fn outline_across_functions() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+
+        let mut func_refs = Vec::new();
+        for (name, swap) in [("f", false), ("g", true)] {
+            let sig = Signature::new(name, Linkage::Public, &[Type::I32, Type::I32], Type::I32);
+            let func_ref = mb.declare_function(sig);
+            let mut builder = mb.build_function::<InsnInserter>(func_ref);
+
+            let b0 = builder.append_block();
+            builder.switch_to_block(b0);
+            let (mut x, mut y) = (builder.args()[0], builder.args()[1]);
+            if swap {
+                std::mem::swap(&mut x, &mut y);
+            }
+            let v = builder.add(x, y);
+            let v = builder.mul(v, x);
+            let one = builder.make_imm_value(1i32);
+            let v = builder.sub(v, one);
+            let v = builder.xor(v, y);
+            builder.ret(Some(v));
+            builder.seal_all();
+
+            mb = builder.finish();
+            func_refs.push(func_ref);
+        }
+        let mut module = mb.build();
+
+        let mut outliner = Outliner::new(2);
+        outliner.run(&mut module);
+        assert_eq!(outliner.outlined_num(), 2);
+
+        assert_eq!(
+            dump_func(&module, func_refs[0]),
+            "func public %f(v0.i32, v1.i32) -> i32 {
+    block0:
+        v7.i32 = call %__outlined_0 v0 v1;
+        return v7;
+
+}
+"
+        );
+        assert_eq!(
+            dump_func(&module, func_refs[1]),
+            "func public %g(v0.i32, v1.i32) -> i32 {
+    block0:
+        v7.i32 = call %__outlined_0 v1 v0;
+        return v7;
+
+}
+"
+        );
+
+        let helper = module.iter_functions().last().unwrap();
+        assert_eq!(
+            dump_func(&module, helper),
+            "func private %__outlined_0(v0.i32, v1.i32) -> i32 {
+    block0:
+        v2.i32 = add v0 v1;
+        v3.i32 = mul v2 v0;
+        v5.i32 = sub v3 1.i32;
+        v6.i32 = xor v5 v1;
+        return v6;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn keep_hot_sequences() {
+        let build = |weights: Option<&[u32]>| {
+            let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+            for name in ["f", "g"] {
+                let sig = Signature::new(
+                    name,
+                    Linkage::Public,
+                    &[Type::I32, Type::I32, Type::I1],
+                    Type::I32,
+                );
+                let func_ref = mb.declare_function(sig);
+                let mut builder = mb.build_function::<InsnInserter>(func_ref);
+
+                let b0 = builder.append_block();
+                let b1 = builder.append_block();
+                let b2 = builder.append_block();
+                let (x, y, c) = (builder.args()[0], builder.args()[1], builder.args()[2]);
+                builder.switch_to_block(b0);
+                builder.jump(b1);
+
+                builder.switch_to_block(b1);
+                let v = builder.add(x, y);
+                let v = builder.mul(v, x);
+                let one = builder.make_imm_value(1i32);
+                let v = builder.sub(v, one);
+                let v = builder.xor(v, y);
+                builder.br(c, b1, b2);
+                if let Some(weights) = weights {
+                    builder.set_branch_weights(weights);
+                }
+
+                builder.switch_to_block(b2);
+                builder.ret(Some(v));
+                builder.seal_all();
+                mb = builder.finish();
+            }
+            mb.build()
+        };
+
+        let mut module = build(None);
+        let mut outliner = Outliner::new(2);
+        outliner.run(&mut module);
+        assert_eq!(outliner.outlined_num(), 2);
+
+        // The loop runs 10 times per call.
+        let mut module = build(Some(&[9, 1]));
+        let mut outliner = Outliner::new(2);
+        outliner.run(&mut module);
+        assert_eq!(outliner.outlined_num(), 0);
+    }
+}
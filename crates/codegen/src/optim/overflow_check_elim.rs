@@ -0,0 +1,149 @@
+//! This module contains overflow check elimination, which rewrites checked arithmetic that can't
+//! overflow into unchecked arithmetic, and replaces its overflow flag with `false`.
+//!
+//! Frontends with checked arithmetic, e.g., Fe, check every `+`, `-` and `*` and revert if the
+//! flag is set. Operands are often bounded, e.g., zero-extended from a narrower type or masked, and
+//! the [known bits](crate::known_bits) of the operands prove that such checks never fail. SCCP then
+//! folds the branches on the flags, and ADCE removes the revert paths.
+
+use sonatina_ir::{insn::OverflowOp, ControlFlowGraph, Function, Immediate, Insn, InsnData, U256};
+
+use crate::known_bits::{bit_width, low_mask, KnownBits, KnownBitsAnalysis};
+
+#[derive(Debug, Default)]
+pub struct OverflowCheckElimSolver {
+    known_bits: KnownBitsAnalysis,
+    eliminated_num: usize,
+}
+
+impl OverflowCheckElimSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.known_bits.clear();
+        self.eliminated_num = 0;
+    }
+
+    /// Returns the number of overflow checks eliminated since the last [`Self::clear`].
+    pub fn eliminated_num(&self) -> usize {
+        self.eliminated_num
+    }
+
+    pub fn run(&mut self, func: &mut Function, cfg: &ControlFlowGraph) {
+        self.known_bits.compute(func, cfg);
+
+        let checks: Vec<_> = func
+            .layout
+            .iter_block()
+            .flat_map(|block| func.layout.iter_insn(block))
+            .filter(|insn| self.cannot_overflow(func, *insn))
+            .collect();
+        for insn in checks {
+            eliminate(func, insn);
+            self.eliminated_num += 1;
+        }
+    }
+
+    /// Returns `true` if `insn` is checked arithmetic that can't overflow.
+    fn cannot_overflow(&self, func: &Function, insn: Insn) -> bool {
+        let InsnData::OverflowBinary { code, args } = func.dfg.insn_data(insn) else {
+            return false;
+        };
+        let bits = bit_width(func.dfg.value_ty(args[0]));
+        let lhs = self.known_bits.known_bits(func, args[0]);
+        let rhs = self.known_bits.known_bits(func, args[1]);
+
+        let umax = low_mask(bits);
+        let smax = low_mask(bits - 1);
+        let fits = |max: Option<U256>, limit: U256| max.is_some_and(|max| max <= limit);
+        let non_negative = |kb: KnownBits| kb.is_non_negative(bits);
+        match code {
+            OverflowOp::Uaddo => fits(lhs.umax().checked_add(rhs.umax()), umax),
+            OverflowOp::Usubo => lhs.umin() >= rhs.umax(),
+            OverflowOp::Umulo => fits(lhs.umax().checked_mul(rhs.umax()), umax),
+
+            // Operands with different signs can't overflow an addition, and operands with the
+            // same sign can't overflow a subtraction.
+            OverflowOp::Saddo => {
+                (non_negative(lhs)
+                    && non_negative(rhs)
+                    && fits(lhs.umax().checked_add(rhs.umax()), smax))
+                    || (non_negative(lhs) && rhs.is_negative(bits))
+                    || (lhs.is_negative(bits) && non_negative(rhs))
+            }
+            OverflowOp::Ssubo => {
+                (non_negative(lhs) && non_negative(rhs))
+                    || (lhs.is_negative(bits) && rhs.is_negative(bits))
+            }
+            OverflowOp::Smulo => {
+                non_negative(lhs)
+                    && non_negative(rhs)
+                    && fits(lhs.umax().checked_mul(rhs.umax()), smax)
+            }
+        }
+    }
+}
+
+/// Rewrites `insn` into unchecked arithmetic, and replaces the uses of its flag with `false`.
+fn eliminate(func: &mut Function, insn: Insn) {
+    let InsnData::OverflowBinary { code, args } = *func.dfg.insn_data(insn) else {
+        unreachable!();
+    };
+    let &[result, flag] = func.dfg.insn_results(insn) else {
+        unreachable!("checked arithmetic has two results");
+    };
+
+    let no_overflow = func.dfg.make_imm_value(Immediate::I1(false));
+    func.dfg.change_to_alias(flag, no_overflow);
+    func.dfg.detach_results(insn);
+    func.dfg.attach_result(insn, result);
+    func.dfg
+        .replace_insn(insn, InsnData::binary(code.unchecked(), args[0], args[1]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn eliminate_bounded_checks() {
+        let mut builder = test_func_builder(&[Type::I8, Type::I32], Type::I1);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (x, y) = (builder.args()[0], builder.args()[1]);
+        let x = builder.zext(x, Type::I32);
+        // `x + x` is at most `0x1fe`, but `sum + y` may overflow.
+        let (sum, overflow0) = builder.uaddo(x, x);
+        let (_, overflow1) = builder.uaddo(sum, y);
+        let overflow = builder.or(overflow0, overflow1);
+        builder.ret(Some(overflow));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut solver = OverflowCheckElimSolver::new();
+        solver.run(func, &cfg);
+        assert_eq!(solver.eliminated_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i8, v1.i32) -> i1 {
+    block0:
+        v2.i32 = zext v0;
+        v3.i32 = add v2 v2;
+        v5.i32, v6.i1 = uaddo v3 v1;
+        v7.i1 = or 0.i1 v6;
+        return v7;
+
+}
+"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! This module contains a solver for partial redundancy elimination.
+//!
+//! An expression in a merge block is partially redundant if it's already available at the end of
+//! some, but not all, of the predecessors. The solver inserts the expression at the end of the
+//! predecessors where it's missing, then replaces the original expression with a phi that merges
+//! the values flowing from each predecessor. Operands defined by phis in the merge block are
+//! translated to their incoming values on each edge.
+//!
+//! Insertion only happens on edges whose source has a single successor, so no path executes more
+//! instructions than before. Critical edges should be split beforehand to expose more
+//! opportunities, see [`crate::critical_edge::CriticalEdgeSplitter`].
+//!
+//! Only pure and non-trapping instructions are considered, loads are never moved.
+
+use smallvec::SmallVec;
+
+use crate::domtree::DomTree;
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    Block, ControlFlowGraph, Function, Insn, InsnData, Value,
+};
+
+use super::cse::canonicalize;
+
+#[derive(Debug, Default)]
+pub struct PreSolver {
+    inserted_num: usize,
+    eliminated_num: usize,
+}
+
+impl PreSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.inserted_num = 0;
+        self.eliminated_num = 0;
+    }
+
+    /// Returns the number of insns inserted into predecessors since the last [`Self::clear`].
+    pub fn inserted_num(&self) -> usize {
+        self.inserted_num
+    }
+
+    /// Returns the number of insns replaced with phis since the last [`Self::clear`].
+    pub fn eliminated_num(&self) -> usize {
+        self.eliminated_num
+    }
+
+    /// Run PRE on the function.
+    /// The control flow graph is never modified, so `cfg` and `domtree` stay valid after the run.
+    pub fn run(&mut self, func: &mut Function, cfg: &ControlFlowGraph, domtree: &DomTree) {
+        for &block in domtree.rpo() {
+            let preds: SmallVec<[Block; 4]> = cfg.preds_of(block).copied().collect();
+            if !self.is_merge_candidate(cfg, domtree, block, &preds) {
+                continue;
+            }
+
+            let insns: SmallVec<[Insn; 16]> = func.layout.iter_insn(block).collect();
+            for insn in insns {
+                self.eliminate(func, domtree, block, &preds, insn);
+            }
+        }
+    }
+
+    /// Returns `true` if it's safe to insert insns at the end of all predecessors of `block`.
+    fn is_merge_candidate(
+        &self,
+        cfg: &ControlFlowGraph,
+        domtree: &DomTree,
+        block: Block,
+        preds: &[Block],
+    ) -> bool {
+        preds.len() >= 2
+            && preds.iter().all(|&pred| {
+                cfg.succ_num_of(pred) == 1
+                    && is_reachable(domtree, pred)
+                    // Back edges are not handled, the expression would be found in `block` itself.
+                    && !domtree.dominates(block, pred)
+            })
+    }
+
+    fn eliminate(
+        &mut self,
+        func: &mut Function,
+        domtree: &DomTree,
+        block: Block,
+        preds: &[Block],
+        insn: Insn,
+    ) {
+        if !is_eligible(func, insn) {
+            return;
+        }
+
+        let mut translated: SmallVec<[InsnData; 4]> = SmallVec::new();
+        for &pred in preds {
+            let mut data = func.dfg.insn_data(insn).clone();
+            for arg in data.args_mut() {
+                let Some(value) = translate(func, block, *arg, pred) else {
+                    return;
+                };
+                *arg = value;
+            }
+            translated.push(canonicalize(&data));
+        }
+
+        let available: SmallVec<[Option<Value>; 4]> = preds
+            .iter()
+            .zip(&translated)
+            .map(|(&pred, data)| find_available(func, domtree, pred, data))
+            .collect();
+        if available.iter().all(Option::is_none) {
+            return;
+        }
+
+        let mut values = SmallVec::new();
+        for ((&pred, data), available) in preds.iter().zip(translated).zip(available) {
+            let value = match available {
+                Some(value) => value,
+                None => self.insert_at_end(func, pred, data),
+            };
+            values.push(value);
+        }
+
+        let result = func.dfg.insn_result(insn).unwrap();
+        let ty = func.dfg.value_ty(result);
+        let mut inserter = InsnInserter::at_location(CursorLocation::BlockTop(block));
+        let phi = inserter.insert_insn_data(
+            func,
+            InsnData::Phi {
+                values,
+                blocks: preds.iter().copied().collect(),
+                ty,
+            },
+        );
+        let phi_result = inserter.make_and_attach_result(func, phi).unwrap();
+
+        InsnInserter::at_location(CursorLocation::At(insn)).replace_with_value(func, phi_result);
+        self.eliminated_num += 1;
+    }
+
+    /// Insert `data` right before the terminator of `block`, and returns its result.
+    fn insert_at_end(&mut self, func: &mut Function, block: Block, data: InsnData) -> Value {
+        let term = func.layout.last_insn_of(block).unwrap();
+        let mut inserter = InsnInserter::at_location(CursorLocation::At(term));
+        let insn = inserter.insert_insn_data_before(func, data);
+        let result = inserter.make_and_attach_result(func, insn).unwrap();
+        self.inserted_num += 1;
+        result
+    }
+}
+
+/// Returns `true` if the insn can be freely inserted into a path that already executes it.
+fn is_eligible(func: &Function, insn: Insn) -> bool {
+    !(func.dfg.has_side_effect(insn)
+        || func.dfg.may_trap(insn)
+        || func.dfg.is_branch(insn)
+        || func.dfg.is_phi(insn))
+        && func.dfg.insn_results(insn).len() == 1
+}
+
+/// Translate `value` used in `block` to the value flowing from `pred`.
+/// Returns `None` if `value` is defined by a non-phi insn in `block`.
+fn translate(func: &Function, block: Block, value: Value, pred: Block) -> Option<Value> {
+    let Some(insn) = func.dfg.value_insn(value) else {
+        return Some(value);
+    };
+    if func.layout.insn_block(insn) != block {
+        return Some(value);
+    }
+
+    match func.dfg.insn_data(insn) {
+        InsnData::Phi { values, blocks, .. } => {
+            let idx = blocks.iter().position(|block| *block == pred)?;
+            Some(values[idx])
+        }
+        _ => None,
+    }
+}
+
+/// Returns the result of an insn equivalent to `data` that is available at the end of `block`.
+fn find_available(
+    func: &Function,
+    domtree: &DomTree,
+    block: Block,
+    data: &InsnData,
+) -> Option<Value> {
+    let mut runner = Some(block);
+    while let Some(block) = runner {
+        for insn in func.layout.iter_insn(block) {
+            if is_eligible(func, insn) && canonicalize(func.dfg.insn_data(insn)) == *data {
+                return func.dfg.insn_result(insn);
+            }
+        }
+        runner = domtree.idom_of(block);
+    }
+
+    None
+}
+
+fn is_reachable(domtree: &DomTree, block: Block) -> bool {
+    domtree.rpo().first() == Some(&block) || domtree.is_reachable(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn insert_into_missing_pred() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32, Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let (cond, x, y) = (builder.args()[0], builder.args()[1], builder.args()[2]);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.add(x, y);
+        builder.jump(b3);
+
+        builder.switch_to_block(b2);
+        builder.jump(b3);
+
+        builder.switch_to_block(b3);
+        let v4 = builder.add(y, x);
+        builder.ret(Some(v4));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+
+        let mut solver = PreSolver::new();
+        solver.run(func, &cfg, &domtree);
+        assert_eq!(solver.inserted_num(), 1);
+        assert_eq!(solver.eliminated_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32, v2.i32) -> i32 {
+    block0:
+        br v0 block1 block2;
+
+    block1:
+        v3.i32 = add v1 v2;
+        jump block3;
+
+    block2:
+        v5.i32 = add v1 v2;
+        jump block3;
+
+    block3:
+        v6.i32 = phi (v3 block1) (v5 block2);
+        return v6;
+
+}
+"
+        );
+    }
+}
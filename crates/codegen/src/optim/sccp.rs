@@ -14,6 +14,8 @@ use sonatina_ir::{
     Block, ControlFlowGraph, Function, Immediate, Insn, Type, Value,
 };
 
+use super::constant_folding;
+
 #[derive(Debug)]
 pub struct SccpSolver {
     lattice: SecondaryMap<Value, LatticeCell>,
@@ -128,11 +130,7 @@ impl SccpSolver {
             .reachable_blocks
             .contains(&func.layout.insn_block(insn)));
 
-        for &arg in func.dfg.insn_args(insn) {
-            if let Some(imm) = func.dfg.value_imm(arg) {
-                self.set_lattice_cell(arg, LatticeCell::Const(imm));
-            }
-        }
+        self.set_imm_args(func, insn);
 
         let block = func.layout.insn_block(insn);
 
@@ -164,18 +162,29 @@ impl SccpSolver {
 
     fn eval_insn(&mut self, func: &Function, insn: Insn) {
         debug_assert!(!func.dfg.is_phi(insn));
-        for &arg in func.dfg.insn_args(insn) {
-            if let Some(imm) = func.dfg.value_imm(arg) {
-                self.set_lattice_cell(arg, LatticeCell::Const(imm));
-            }
-        }
+        self.set_imm_args(func, insn);
 
         let cell = match func.dfg.insn_data(insn) {
+            // Pointers have no constant in the lattice, so only comparisons on them and casts back
+            // to integers are folded, by the addresses they hold.
+            insn_data @ (InsnData::Binary { .. } | InsnData::Cast { .. })
+                if constant_folding::is_ptr(&func.dfg, insn_data.args()[0]) =>
+            {
+                match constant_folding::fold_constant(&func.dfg, insn_data) {
+                    Some(imm) => LatticeCell::Const(imm),
+                    None => LatticeCell::Top,
+                }
+            }
+
             InsnData::Unary { code, args } => {
                 let arg_cell = self.lattice[args[0]];
                 match *code {
                     UnaryOp::Not => arg_cell.not(),
                     UnaryOp::Neg => arg_cell.neg(),
+                    UnaryOp::Clz => arg_cell.apply_unop(Immediate::clz),
+                    UnaryOp::Ctz => arg_cell.apply_unop(Immediate::ctz),
+                    UnaryOp::Popcount => arg_cell.apply_unop(Immediate::popcount),
+                    UnaryOp::Bswap => arg_cell.apply_unop(Immediate::bswap),
                 }
             }
 
@@ -201,6 +210,26 @@ impl SccpSolver {
                     BinaryOp::And => lhs.and(rhs),
                     BinaryOp::Or => lhs.or(rhs),
                     BinaryOp::Xor => lhs.xor(rhs),
+                    BinaryOp::Shl => lhs.shl(rhs),
+                    BinaryOp::Shr => lhs.shr(rhs),
+                    BinaryOp::Sar => lhs.sar(rhs),
+                    BinaryOp::Rotl => lhs.rotl(rhs),
+                    BinaryOp::Rotr => lhs.rotr(rhs),
+                }
+            }
+
+            InsnData::OverflowBinary { code, args } => {
+                // The overflow flag isn't folded, only the wrapped result is.
+                let flag = func.dfg.insn_results(insn)[1];
+                self.set_lattice_cell(flag, LatticeCell::Top);
+
+                let lhs = self.lattice[args[0]];
+                let rhs = self.lattice[args[1]];
+                match code.unchecked() {
+                    BinaryOp::Add => lhs.add(rhs),
+                    BinaryOp::Sub => lhs.sub(rhs),
+                    BinaryOp::Mul => lhs.mul(rhs),
+                    _ => unreachable!(),
                 }
             }
 
@@ -210,7 +239,10 @@ impl SccpSolver {
                     CastOp::Sext => arg_cell.sext(*ty),
                     CastOp::Zext => arg_cell.zext(*ty),
                     CastOp::Trunc => arg_cell.trunc(*ty),
-                    CastOp::BitCast => LatticeCell::Top,
+                    CastOp::BitCast => match arg_cell {
+                        LatticeCell::Const(imm) if imm.ty() == *ty => arg_cell,
+                        _ => LatticeCell::Top,
+                    },
                 }
             }
 
@@ -218,6 +250,10 @@ impl SccpSolver {
 
             InsnData::Call { .. } => LatticeCell::Top,
 
+            // An intrinsic has no result if it returns nothing.
+            InsnData::Intrinsic { .. } if func.dfg.insn_result(insn).is_none() => return,
+            InsnData::Intrinsic { .. } => LatticeCell::Top,
+
             InsnData::Jump { dests, .. } => {
                 self.flow_work.push(FlowEdge::new(insn, dests[0]));
                 return;
@@ -297,7 +333,12 @@ impl SccpSolver {
 
             InsnData::Alloca { .. } | InsnData::Gep { .. } => LatticeCell::Top,
 
-            InsnData::Store { .. } | InsnData::Return { .. } => {
+            InsnData::Store { .. }
+            | InsnData::Mem { .. }
+            | InsnData::Return { .. }
+            | InsnData::Revert { .. }
+            | InsnData::Trap { .. }
+            | InsnData::Unreachable => {
                 // No insn result. Do nothing.
                 return;
             }
@@ -368,9 +409,14 @@ impl SccpSolver {
 
         match self.lattice[insn_result].to_imm() {
             Some(imm) => {
-                InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
                 let new_value = func.dfg.make_imm_value(imm);
-                func.dfg.change_to_alias(insn_result, new_value);
+                if func.dfg.insn_results(insn).len() > 1 {
+                    // The insn is kept for its other results.
+                    func.dfg.change_to_alias(insn_result, new_value);
+                } else {
+                    InsnInserter::at_location(CursorLocation::At(insn))
+                        .replace_with_value(func, new_value);
+                }
             }
             None => {
                 if func.dfg.is_phi(insn) {
@@ -383,18 +429,19 @@ impl SccpSolver {
     fn try_fold_phi(&self, func: &mut Function, insn: Insn) {
         debug_assert!(func.dfg.is_phi(insn));
 
+        // An arg from a reachable block may still flow through an unreachable edge, e.g., from a
+        // `br` with a constant condition.
+        let phi_block = func.layout.insn_block(insn);
         let mut blocks = func.dfg.phi_blocks(insn).to_vec();
-        blocks.retain(|block| !self.reachable_blocks.contains(block));
+        blocks.retain(|&block| !self.is_reachable(func, block, phi_block));
         for block in blocks {
             func.dfg.remove_phi_arg(insn, block);
         }
 
         // Remove phi function if it has just one argument.
         if func.dfg.insn_args_num(insn) == 1 {
-            let phi_value = func.dfg.insn_result(insn).unwrap();
-            func.dfg
-                .change_to_alias(phi_value, func.dfg.insn_arg(insn, 0));
-            InsnInserter::at_location(CursorLocation::At(insn)).remove_insn(func);
+            let arg = func.dfg.insn_arg(insn, 0);
+            InsnInserter::at_location(CursorLocation::At(insn)).replace_with_value(func, arg);
         }
     }
 
@@ -416,6 +463,19 @@ impl SccpSolver {
         false
     }
 
+    /// Sets the lattice cells of immediate args of `insn`. A pointer to a constant global has the
+    /// initializer as its immediate, but it holds the address of the global, which is unknown.
+    fn set_imm_args(&mut self, func: &Function, insn: Insn) {
+        for &arg in func.dfg.insn_args(insn) {
+            if constant_folding::is_ptr(&func.dfg, arg) {
+                continue;
+            }
+            if let Some(imm) = func.dfg.value_imm(arg) {
+                self.set_lattice_cell(arg, LatticeCell::Const(imm));
+            }
+        }
+    }
+
     fn set_lattice_cell(&mut self, value: Value, cell: LatticeCell) {
         let old_cell = &self.lattice[value];
         if old_cell != &cell {
@@ -599,6 +659,26 @@ impl LatticeCell {
         self.apply_binop(rhs, ops::BitXor::bitxor)
     }
 
+    fn shl(self, rhs: Self) -> Self {
+        self.apply_binop(rhs, ops::Shl::shl)
+    }
+
+    fn shr(self, rhs: Self) -> Self {
+        self.apply_binop(rhs, ops::Shr::shr)
+    }
+
+    fn sar(self, rhs: Self) -> Self {
+        self.apply_binop(rhs, Immediate::sar)
+    }
+
+    fn rotl(self, rhs: Self) -> Self {
+        self.apply_binop(rhs, Immediate::rotl)
+    }
+
+    fn rotr(self, rhs: Self) -> Self {
+        self.apply_binop(rhs, Immediate::rotr)
+    }
+
     fn sext(self, ty: Type) -> Self {
         self.apply_unop(|val| Immediate::sext(val, ty))
     }
@@ -0,0 +1,161 @@
+//! This module contains a solver that simplifies the CFG around `unreachable`.
+//!
+//! A block that ends in `unreachable` is dead if none of its other insns may leave the block, i.e.,
+//! trap or not return; reaching it would mean reaching the `unreachable`. Each edge into a dead
+//! block is removed from the branch of its predecessor, and the dead block is removed:
+//! - A `br` to a dead block becomes a `jump` to its other destination, and a `br_table` drops the
+//!   cases that go to it.
+//! - A `jump` to a dead block becomes `unreachable` itself, so that the predecessor may be dead in
+//!   turn.
+//!
+//! The entry block is never removed, so a function whose entry block is dead keeps it.
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    Block, ControlFlowGraph, Function, InsnData,
+};
+
+#[derive(Debug, Default)]
+pub struct SimplifyCfgSolver {
+    worklist: Vec<Block>,
+    removed_block_num: usize,
+    removed_edge_num: usize,
+}
+
+impl SimplifyCfgSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.worklist.clear();
+        self.removed_block_num = 0;
+        self.removed_edge_num = 0;
+    }
+
+    /// Returns the number of dead blocks removed since the last [`Self::clear`].
+    pub fn removed_block_num(&self) -> usize {
+        self.removed_block_num
+    }
+
+    /// Returns the number of edges into dead blocks removed since the last [`Self::clear`].
+    pub fn removed_edge_num(&self) -> usize {
+        self.removed_edge_num
+    }
+
+    pub fn run(&mut self, func: &mut Function, cfg: &mut ControlFlowGraph) {
+        self.worklist.clear();
+        let entry = func.layout.entry_block();
+        self.worklist.extend(
+            func.layout
+                .iter_block()
+                .filter(|&block| Some(block) != entry && is_dead(func, block)),
+        );
+
+        while let Some(block) = self.worklist.pop() {
+            if !func.layout.is_block_inserted(block) {
+                continue;
+            }
+
+            let preds: Vec<_> = cfg.preds_of(block).copied().collect();
+            for pred in preds {
+                self.remove_edge(func, pred, block);
+                cfg.remove_edge(pred, block);
+                if Some(pred) != entry && is_dead(func, pred) {
+                    self.worklist.push(pred);
+                }
+            }
+
+            InsnInserter::at_location(CursorLocation::BlockTop(block)).remove_block(func);
+            self.removed_block_num += 1;
+        }
+
+        cfg.compute(func);
+        func.debug_check_phis(cfg);
+    }
+
+    /// Removes every destination of the branch of `pred` that is `dest`.
+    fn remove_edge(&mut self, func: &mut Function, pred: Block, dest: Block) {
+        let branch = func.layout.last_insn_of(pred).unwrap();
+        while func
+            .dfg
+            .analyze_branch(branch)
+            .iter_dests()
+            .any(|block| block == dest)
+        {
+            if matches!(func.dfg.insn_data(branch), InsnData::Jump { .. }) {
+                func.dfg.replace_insn(branch, InsnData::Unreachable);
+            } else {
+                func.dfg.remove_branch_dest(branch, dest);
+            }
+        }
+        self.removed_edge_num += 1;
+    }
+}
+
+/// Returns `true` if `block` ends in `unreachable` and none of its other insns may leave it.
+fn is_dead(func: &Function, block: Block) -> bool {
+    let Some(last) = func.layout.last_insn_of(block) else {
+        return false;
+    };
+    matches!(func.dfg.insn_data(last), InsnData::Unreachable)
+        && func
+            .layout
+            .iter_insn(block)
+            .all(|insn| insn == last || !func.dfg.may_trap(insn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn propagate_unreachable() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let (cond, x) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.ret(Some(x));
+
+        builder.switch_to_block(b2);
+        let one = builder.make_imm_value(1i32);
+        builder.add(x, one);
+        builder.jump(b3);
+
+        builder.switch_to_block(b3);
+        builder.unreachable();
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut solver = SimplifyCfgSolver::new();
+        solver.run(func, &mut cfg);
+        assert_eq!(solver.removed_block_num(), 2);
+        assert_eq!(solver.removed_edge_num(), 2);
+        assert_eq!(cfg.succs_of(b0).copied().collect::<Vec<_>>(), [b1]);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32) -> i32 {
+    block0:
+        jump block1;
+
+    block1:
+        return v1;
+
+}
+"
+        );
+    }
+}
@@ -5,9 +5,9 @@ use smallvec::SmallVec;
 use cranelift_entity::{entity_impl, PrimaryMap, SecondaryMap};
 
 use sonatina_ir::{
-    insn::{BinaryOp, CastOp, DataLocationKind, UnaryOp},
+    insn::{BinaryOp, CastOp, DataLocationKind, MemOp, OverflowOp, TrapReason, UnaryOp},
     module::FuncRef,
-    Block, DataFlowGraph, Immediate, Insn, InsnData, Type, Value,
+    Block, DataFlowGraph, Immediate, Insn, InsnData, Intrinsic, Type, Value,
 };
 
 #[allow(clippy::all)]
@@ -83,6 +83,7 @@ fn try_swap_arg(ctx: &mut SimplifyContext, expr: Expr) -> Option<Expr> {
 type Unit = ();
 type ArgArray1 = [ExprValue; 1];
 type ArgArray2 = [ExprValue; 2];
+type ArgArray3 = [ExprValue; 3];
 type BlockArray1 = [Block; 1];
 type BlockArray2 = [Block; 2];
 
@@ -109,6 +110,12 @@ pub enum ExprData {
         args: ArgArray2,
     },
 
+    /// Binary instructions with an overflow check.
+    OverflowBinary {
+        code: OverflowOp,
+        args: ArgArray2,
+    },
+
     /// Cast operations.
     Cast {
         code: CastOp,
@@ -127,12 +134,26 @@ pub enum ExprData {
         args: ArgArray2,
         loc: DataLocationKind,
     },
+
+    /// Copy or fill a range of memory or storage.
+    Mem {
+        code: MemOp,
+        args: ArgArray3,
+        loc: DataLocationKind,
+    },
+
     Call {
         func: FuncRef,
         args: ArgList,
         ret_ty: Type,
     },
 
+    Intrinsic {
+        intrinsic: Intrinsic,
+        args: ArgList,
+        ret_ty: Type,
+    },
+
     /// Unconditional jump operations.
     Jump {
         dests: BlockArray1,
@@ -160,6 +181,17 @@ pub enum ExprData {
         args: Option<Value>,
     },
 
+    Revert {
+        selector: [u8; 4],
+        args: ArgList,
+    },
+
+    Trap {
+        reason: TrapReason,
+    },
+
+    Unreachable,
+
     Gep {
         args: ArgList,
     },
@@ -185,6 +217,11 @@ impl ExprData {
                 args: [args[0].into(), args[1].into()],
             },
 
+            InsnData::OverflowBinary { code, args } => Self::OverflowBinary {
+                code: *code,
+                args: [args[0].into(), args[1].into()],
+            },
+
             InsnData::Cast { code, args, ty } => Self::Cast {
                 code: *code,
                 args: [args[0].into()],
@@ -201,12 +238,30 @@ impl ExprData {
                 loc: *loc,
             },
 
-            InsnData::Call { func, args, ret_ty } => Self::Call {
+            InsnData::Mem { code, args, loc } => Self::Mem {
+                code: *code,
+                args: [args[0].into(), args[1].into(), args[2].into()],
+                loc: *loc,
+            },
+
+            InsnData::Call {
+                func, args, ret_ty, ..
+            } => Self::Call {
                 func: *func,
                 args: args.iter().copied().map(Into::into).collect(),
                 ret_ty: *ret_ty,
             },
 
+            InsnData::Intrinsic {
+                intrinsic,
+                args,
+                ret_ty,
+            } => Self::Intrinsic {
+                intrinsic: *intrinsic,
+                args: args.iter().copied().map(Into::into).collect(),
+                ret_ty: *ret_ty,
+            },
+
             InsnData::Jump { dests } => Self::Jump { dests: *dests },
 
             InsnData::Branch { args, dests } => Self::Branch {
@@ -232,6 +287,15 @@ impl ExprData {
 
             InsnData::Return { args } => Self::Return { args: *args },
 
+            InsnData::Revert { selector, args } => Self::Revert {
+                selector: *selector,
+                args: args.iter().copied().map(Into::into).collect(),
+            },
+
+            InsnData::Trap { reason } => Self::Trap { reason: *reason },
+
+            InsnData::Unreachable => Self::Unreachable,
+
             InsnData::Phi { values, blocks, ty } => Self::Phi {
                 values: values.iter().copied().map(Into::into).collect(),
                 blocks: blocks.clone(),
@@ -252,6 +316,11 @@ impl ExprData {
                 args: [args[0].as_value()?, args[1].as_value()?],
             },
 
+            Self::OverflowBinary { code, args } => InsnData::OverflowBinary {
+                code: *code,
+                args: [args[0].as_value()?, args[1].as_value()?],
+            },
+
             Self::Cast { code, args, ty } => InsnData::Cast {
                 code: *code,
                 args: [args[0].as_value()?],
@@ -268,6 +337,16 @@ impl ExprData {
                 loc: *loc,
             },
 
+            Self::Mem { code, args, loc } => InsnData::Mem {
+                code: *code,
+                args: [
+                    args[0].as_value()?,
+                    args[1].as_value()?,
+                    args[2].as_value()?,
+                ],
+                loc: *loc,
+            },
+
             Self::Call { func, args, ret_ty } => InsnData::Call {
                 func: *func,
                 args: args
@@ -275,6 +354,20 @@ impl ExprData {
                     .map(|val| val.as_value())
                     .collect::<Option<_>>()?,
                 ret_ty: *ret_ty,
+                is_tail: false,
+            },
+
+            Self::Intrinsic {
+                intrinsic,
+                args,
+                ret_ty,
+            } => InsnData::Intrinsic {
+                intrinsic: *intrinsic,
+                args: args
+                    .iter()
+                    .map(|val| val.as_value())
+                    .collect::<Option<_>>()?,
+                ret_ty: *ret_ty,
             },
 
             Self::Jump { dests } => InsnData::Jump { dests: *dests },
@@ -308,6 +401,18 @@ impl ExprData {
 
             Self::Return { args } => InsnData::Return { args: *args },
 
+            Self::Revert { selector, args } => InsnData::Revert {
+                selector: *selector,
+                args: args
+                    .iter()
+                    .map(|val| val.as_value())
+                    .collect::<Option<_>>()?,
+            },
+
+            Self::Trap { reason } => InsnData::Trap { reason: *reason },
+
+            Self::Unreachable => InsnData::Unreachable,
+
             Self::Phi { values, blocks, ty } => InsnData::Phi {
                 values: values
                     .iter()
@@ -0,0 +1,189 @@
+//! This module contains a solver for code sinking.
+//!
+//! Side-effect free instructions are moved down to the nearest common dominator of their uses,
+//! e.g., into the branch arm that actually needs the result. This removes the computation from
+//! paths that don't use it and shortens live ranges.
+//! An instruction is never sunk into a loop that doesn't contain its original block.
+
+use smallvec::SmallVec;
+
+use crate::{domtree::DomTree, loop_analysis::LoopTree};
+
+use sonatina_ir::{Block, Function, Insn, InsnData};
+
+#[derive(Debug, Default)]
+pub struct SinkSolver {
+    sunk_num: usize,
+}
+
+impl SinkSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.sunk_num = 0;
+    }
+
+    /// Returns the number of insns sunk since the last [`Self::clear`].
+    pub fn sunk_num(&self) -> usize {
+        self.sunk_num
+    }
+
+    /// Run code sinking on the function.
+    /// The control flow graph is never modified, so `domtree` and `lpt` stay valid after the run.
+    pub fn run(&mut self, func: &mut Function, domtree: &DomTree, lpt: &LoopTree) {
+        // Visit users before their operands so that a chain of insns sinks together.
+        for &block in domtree.rpo().iter().rev() {
+            let mut next_insn = func.layout.last_insn_of(block);
+            while let Some(insn) = next_insn {
+                next_insn = func.layout.prev_insn_of(insn);
+                self.sink_insn(func, domtree, lpt, insn);
+            }
+        }
+    }
+
+    fn sink_insn(&mut self, func: &mut Function, domtree: &DomTree, lpt: &LoopTree, insn: Insn) {
+        if !self.is_sinkable(func, insn) {
+            return;
+        }
+
+        let def_block = func.layout.insn_block(insn);
+        let Some(mut target) = self.use_dominator(func, domtree, insn) else {
+            return;
+        };
+        if !domtree.strictly_dominates(def_block, target) {
+            return;
+        }
+
+        // Back off to the outermost block that isn't in a loop excluding `def_block`.
+        while let Some(lp) = lpt.loop_of_block(target) {
+            if lpt.is_in_loop(def_block, lp) {
+                break;
+            }
+            target = domtree.idom_of(target).unwrap();
+        }
+        if target == def_block {
+            return;
+        }
+
+        let result = func.dfg.insn_result(insn).unwrap();
+        let before = func
+            .layout
+            .iter_insn(target)
+            .find(|&user| {
+                !func.dfg.is_phi(user)
+                    && (func.dfg.insn_args(user).contains(&result)
+                        || func.layout.last_insn_of(target) == Some(user))
+            })
+            .unwrap();
+
+        func.layout.remove_insn(insn);
+        func.layout.insert_insn_before(insn, before);
+        self.sunk_num += 1;
+    }
+
+    fn is_sinkable(&self, func: &Function, insn: Insn) -> bool {
+        !(func.dfg.has_side_effect(insn)
+            || func.dfg.may_trap(insn)
+            || func.dfg.is_branch(insn)
+            || func.dfg.is_phi(insn))
+            && func.dfg.insn_results(insn).len() == 1
+    }
+
+    /// Returns the nearest common dominator of the blocks where the result of `insn` is used.
+    /// A use in a phi is regarded as a use at the end of the corresponding predecessor.
+    fn use_dominator(&self, func: &Function, domtree: &DomTree, insn: Insn) -> Option<Block> {
+        let result = func.dfg.insn_result(insn)?;
+
+        let mut use_blocks: SmallVec<[Block; 8]> = SmallVec::new();
+        for &user in func.dfg.users(result) {
+            match func.dfg.insn_data(user) {
+                InsnData::Phi { values, blocks, .. } => use_blocks.extend(
+                    values
+                        .iter()
+                        .zip(blocks)
+                        .filter(|(value, _)| **value == result)
+                        .map(|(_, block)| *block),
+                ),
+                _ => use_blocks.push(func.layout.insn_block(user)),
+            }
+        }
+
+        let mut use_blocks = use_blocks.into_iter();
+        let first = use_blocks.next()?;
+        use_blocks.try_fold(first, |acc, block| common_dominator(domtree, acc, block))
+    }
+}
+
+/// Returns the nearest block that dominates both `lhs` and `rhs`, or `None` if either of them is
+/// unreachable.
+fn common_dominator(domtree: &DomTree, lhs: Block, rhs: Block) -> Option<Block> {
+    let mut runner = lhs;
+    while !domtree.dominates(runner, rhs) {
+        runner = domtree.idom_of(runner)?;
+    }
+    Some(runner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, ControlFlowGraph, Type};
+
+    #[test]
+    fn sink_into_branch_arm() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32, Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let (cond, x, y) = (builder.args()[0], builder.args()[1], builder.args()[2]);
+        let v3 = builder.add(x, y);
+        let v4 = builder.mul(v3, x);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.ret(Some(v4));
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(x));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+        let mut lpt = LoopTree::new();
+        lpt.compute(&cfg, &domtree);
+
+        let mut solver = SinkSolver::new();
+        solver.run(func, &domtree, &lpt);
+        assert_eq!(solver.sunk_num(), 2);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32, v2.i32) -> i32 {
+    block0:
+        br v0 block1 block2;
+
+    block1:
+        v3.i32 = add v1 v2;
+        v4.i32 = mul v3 v1;
+        return v4;
+
+    block2:
+        return v1;
+
+}
+"
+        );
+    }
+}
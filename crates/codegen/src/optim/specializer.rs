@@ -0,0 +1,294 @@
+//! This module contains a function specializer.
+//!
+//! A call site that passes immediates to a small private function is redirected to a copy of the
+//! function specialized for the immediates, i.e., a copy that takes only the other arguments and
+//! uses the immediates in their place. The copy is folded by SCCP and cleaned up by ADCE right
+//! away, so that it's smaller than the original when the immediates decide branches.
+//!
+//! Call sites that pass the same immediates to the same function share a copy. Unlike inlining,
+//! specialization doesn't grow the callers, so it also pays off for functions called from many
+//! places.
+
+use rustc_hash::FxHashMap;
+
+use sonatina_ir::{
+    cloner::FunctionCloner, module::FuncRef, ControlFlowGraph, Immediate, Insn, InsnData, Linkage,
+    Module, Signature,
+};
+
+use super::{
+    adce::AdceSolver,
+    inliner::{call_sites_in, code_size},
+    sccp::SccpSolver,
+};
+
+/// The immediates passed to a function, or `None` for arguments that aren't immediates.
+type SpecKey = (FuncRef, Vec<Option<Immediate>>);
+
+#[derive(Debug)]
+pub struct Specializer {
+    /// The maximum estimated code size in bytes of a function that is specialized.
+    max_size: usize,
+    specialized: FxHashMap<SpecKey, (FuncRef, Signature)>,
+    specialized_num: usize,
+}
+
+impl Default for Specializer {
+    fn default() -> Self {
+        Self::new(256)
+    }
+}
+
+impl Specializer {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            specialized: FxHashMap::default(),
+            specialized_num: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.specialized.clear();
+        self.specialized_num = 0;
+    }
+
+    /// Returns the number of call sites redirected to specialized functions since the last
+    /// [`Self::clear`].
+    pub fn specialized_num(&self) -> usize {
+        self.specialized_num
+    }
+
+    /// Specializes functions for the call sites in all functions in the module. Call sites in the
+    /// specialized functions are not specialized in the same run.
+    pub fn run(&mut self, module: &mut Module) {
+        self.specialized.clear();
+
+        let callers: Vec<_> = module.iter_functions().collect();
+        for caller in callers {
+            if module.funcs[caller].sig.attrs().optnone {
+                continue;
+            }
+
+            for (call, callee) in call_sites_in(&module.funcs[caller]) {
+                if !self.is_specializable(module, caller, callee) {
+                    continue;
+                }
+                let Some(key) = spec_key(module, caller, call, callee) else {
+                    continue;
+                };
+
+                let (spec, sig) = match self.specialized.get(&key) {
+                    Some(specialized) => specialized.clone(),
+                    None => {
+                        let specialized = specialize(module, &key);
+                        self.specialized.insert(key.clone(), specialized.clone());
+                        specialized
+                    }
+                };
+                redirect(module, caller, call, &key, spec, &sig);
+                self.specialized_num += 1;
+            }
+        }
+    }
+
+    fn is_specializable(&self, module: &Module, caller: FuncRef, callee: FuncRef) -> bool {
+        let callee_func = &module.funcs[callee];
+        caller != callee
+            && callee_func.sig.linkage() == Linkage::Private
+            && !callee_func.sig.attrs().optnone
+            && callee_func.layout.entry_block().is_some()
+            && code_size(module.ctx.isa.cost_table(), callee_func) <= self.max_size
+    }
+}
+
+/// Returns the key of the call site, or `None` if it passes no immediates.
+fn spec_key(module: &Module, caller: FuncRef, call: Insn, callee: FuncRef) -> Option<SpecKey> {
+    let dfg = &module.funcs[caller].dfg;
+    let imms: Vec<_> = dfg
+        .insn_args(call)
+        .iter()
+        .map(|arg| dfg.value_imm(*arg))
+        .collect();
+    imms.iter().any(Option::is_some).then_some((callee, imms))
+}
+
+/// Adds a copy of the function of `key` specialized for the immediates of `key` to the module.
+fn specialize(module: &mut Module, (callee, imms): &SpecKey) -> (FuncRef, Signature) {
+    let callee_func = &module.funcs[*callee];
+    let arg_tys: Vec<_> = callee_func
+        .sig
+        .args()
+        .iter()
+        .zip(imms)
+        .filter(|(_, imm)| imm.is_none())
+        .map(|(ty, _)| *ty)
+        .collect();
+    let name = spec_name(module, callee_func.sig.name());
+    let sig = Signature::new(&name, Linkage::Private, &arg_tys, callee_func.sig.ret_ty());
+
+    let mut cloner = FunctionCloner::new(callee_func);
+    for (arg, imm) in callee_func.arg_values.iter().zip(imms) {
+        if let Some(imm) = imm {
+            cloner.map_imm(*arg, *imm);
+        }
+    }
+    let mut func = cloner.clone_with_sig(sig);
+    let sig = func.sig.clone();
+
+    let mut cfg = ControlFlowGraph::new();
+    cfg.compute(&func);
+    SccpSolver::new().run(&mut func, &mut cfg);
+    AdceSolver::new().run(&mut func);
+
+    (module.funcs.push(func), sig)
+}
+
+/// Returns a name for a specialization of `callee` that doesn't collide with functions in the
+/// module.
+fn spec_name(module: &Module, callee: &str) -> String {
+    (0..)
+        .map(|idx| format!("{callee}__spec_{idx}"))
+        .find(|name| {
+            module
+                .funcs
+                .values()
+                .all(|func| func.sig.name() != name.as_str())
+        })
+        .unwrap()
+}
+
+/// Redirects `call` to `spec`, passing only the arguments that aren't immediates.
+fn redirect(
+    module: &mut Module,
+    caller: FuncRef,
+    call: Insn,
+    (_, imms): &SpecKey,
+    spec: FuncRef,
+    sig: &Signature,
+) {
+    let func = &mut module.funcs[caller];
+    let InsnData::Call {
+        args,
+        ret_ty,
+        is_tail,
+        ..
+    } = func.dfg.insn_data(call).clone()
+    else {
+        unreachable!("call sites are calls");
+    };
+
+    let args = args
+        .into_iter()
+        .zip(imms)
+        .filter(|(_, imm)| imm.is_none())
+        .map(|(arg, _)| arg)
+        .collect();
+    func.dfg.replace_insn(
+        call,
+        InsnData::Call {
+            func: spec,
+            args,
+            ret_ty,
+            is_tail,
+        },
+    );
+    func.callees.insert(spec, sig.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        Type,
+    };
+
+    /// Build a module with `%callee(v0.i1, v1.i32) -> i32`, which returns either `v1 + 1` or
+    /// `v1 * v1` depending on `v0`, and `%test_func(v0.i32)`, which calls it twice with `true` and
+    /// once with `false`.
+    fn build_module() -> (Module, FuncRef, FuncRef) {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+
+        let sig = Signature::new(
+            "callee",
+            Linkage::Private,
+            &[Type::I1, Type::I32],
+            Type::I32,
+        );
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (c, x) = (builder.args()[0], builder.args()[1]);
+        builder.br(c, b1, b2);
+        builder.switch_to_block(b1);
+        let one = builder.make_imm_value(1i32);
+        let v = builder.add(x, one);
+        builder.ret(Some(v));
+        builder.switch_to_block(b2);
+        let v = builder.mul(x, x);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("test_func", Linkage::Public, &[Type::I32], Type::I32);
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let t = builder.make_imm_value(true);
+        let f = builder.make_imm_value(false);
+        let v0 = builder.call(callee, &[t, x]).unwrap();
+        let v1 = builder.call(callee, &[f, v0]).unwrap();
+        let v2 = builder.call(callee, &[t, v1]).unwrap();
+        builder.ret(Some(v2));
+        builder.seal_all();
+
+        (builder.finish().build(), caller, callee)
+    }
+
+    #[test]
+    fn specialize_on_imm_args() {
+        let (mut module, caller, callee) = build_module();
+
+        let mut specializer = Specializer::default();
+        specializer.run(&mut module);
+        assert_eq!(specializer.specialized_num(), 3);
+
+        // Calls with the same immediates share a specialization.
+        let calls = call_sites_in(&module.funcs[caller]);
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].1, calls[2].1);
+        assert_ne!(calls[0].1, calls[1].1);
+        assert!(calls.iter().all(|(_, func)| *func != callee));
+        assert_eq!(module.funcs.len(), 4);
+
+        let spec = &module.funcs[calls[0].1];
+        assert_eq!(spec.sig.name(), "callee__spec_0");
+        assert_eq!(spec.sig.args(), &[Type::I32]);
+        // The branch on the immediate is folded, and the dead arm is removed.
+        let dumped = dump_func(&module, calls[0].1);
+        assert!(!dumped.contains("br "));
+        assert!(!dumped.contains("mul"));
+        assert!(dump_func(&module, calls[1].1).contains("mul v0 v0"));
+    }
+
+    #[test]
+    fn skip_large_callee() {
+        let (mut module, caller, callee) = build_module();
+
+        let mut specializer = Specializer::new(0);
+        specializer.run(&mut module);
+        assert_eq!(specializer.specialized_num(), 0);
+        assert!(call_sites_in(&module.funcs[caller])
+            .iter()
+            .all(|(_, func)| *func == callee));
+    }
+}
@@ -0,0 +1,125 @@
+//! This module contains a pass that strips debug information from a module.
+//!
+//! The source locations of all insns are removed along with the source files and inlined call
+//! sites they refer to, which leaves the minimal module for a release build. Running the pipeline
+//! after stripping also checks that no pass depends on debug information for correctness.
+//!
+//! Function, global and struct names are symbols that are part of the semantics of the module, so
+//! they are kept. Value names only exist in the debug info of the parser, which the caller drops.
+//! Functions with the `optnone` attribute keep their locations, and so do the source files.
+
+use sonatina_ir::Module;
+
+#[derive(Debug, Default)]
+pub struct StripSolver {
+    stripped_num: usize,
+}
+
+impl StripSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.stripped_num = 0;
+    }
+
+    /// Returns the number of source locations removed since the last [`Self::clear`].
+    pub fn stripped_num(&self) -> usize {
+        self.stripped_num
+    }
+
+    pub fn run(&mut self, module: &mut Module) {
+        let mut has_kept_locs = false;
+        for func in module.funcs.values_mut() {
+            if func.sig.attrs().optnone {
+                has_kept_locs |= func
+                    .layout
+                    .iter_block()
+                    .flat_map(|block| func.layout.iter_insn(block))
+                    .any(|insn| func.dfg.insn_loc(insn).is_some());
+                continue;
+            }
+
+            for block in func.layout.iter_block() {
+                for insn in func.layout.iter_insn(block) {
+                    if func.dfg.insn_loc(insn).is_some() {
+                        func.dfg.set_insn_loc(insn, None);
+                        self.stripped_num += 1;
+                    }
+                }
+            }
+        }
+
+        if !has_kept_locs {
+            module.ctx.with_source_file_store_mut(|s| s.clear());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, SourceLoc, Type};
+
+    #[test]
+    fn strip_locs() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let file = builder
+            .module_builder
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("main.fe"));
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        builder.set_loc(Some(SourceLoc::new(file, 3, 8)));
+        let arg = builder.args()[0];
+        let v = builder.add(arg, arg);
+        builder.set_loc(None);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let mut solver = StripSolver::new();
+        solver.run(&mut module);
+        assert_eq!(solver.stripped_num(), 1);
+
+        let func = &module.funcs[module.iter_functions().next().unwrap()];
+        let insn = func.dfg.value_insn(v).unwrap();
+        assert_eq!(func.dfg.insn_loc(insn), None);
+        assert_eq!(
+            module.ctx.with_source_file_store(|s| s.all_files().count()),
+            0
+        );
+    }
+
+    #[test]
+    fn keep_optnone_locs() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        builder.func.sig.attrs_mut().optnone = true;
+        let file = builder
+            .module_builder
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("main.fe"));
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        builder.set_loc(Some(SourceLoc::new(file, 3, 8)));
+        let arg = builder.args()[0];
+        let v = builder.add(arg, arg);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let mut solver = StripSolver::new();
+        solver.run(&mut module);
+        assert_eq!(solver.stripped_num(), 0);
+
+        let func = &module.funcs[module.iter_functions().next().unwrap()];
+        let insn = func.dfg.value_insn(v).unwrap();
+        assert_eq!(func.dfg.insn_loc(insn), Some(SourceLoc::new(file, 3, 8)));
+        assert_eq!(
+            module.ctx.with_source_file_store(|s| s.all_files().count()),
+            1
+        );
+    }
+}
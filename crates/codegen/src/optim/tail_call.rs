@@ -0,0 +1,210 @@
+//! This module contains a solver that marks calls in tail position.
+//!
+//! A call is in tail position if it's immediately followed by a return of its result. The backend
+//! lowers a marked call as a jump that reuses the frame of the caller, which saves gas and bounds
+//! the stack depth of tail-recursive functions.
+//!
+//! Other passes may insert insns between a call and a return, so the solver should run right
+//! before code generation. Calls that are no longer in tail position are unmarked, and calls whose
+//! callee has a calling convention that doesn't allow tail calls are never marked.
+//!
+//! Calls in a function with an alloca whose address may escape are never marked either: the
+//! callee would overwrite the frame that the address points into.
+
+use sonatina_ir::{Function, Insn, InsnData, Value};
+
+#[derive(Debug, Default)]
+pub struct TailCallSolver {
+    marked_num: usize,
+}
+
+impl TailCallSolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.marked_num = 0;
+    }
+
+    /// Returns the number of calls marked as tail calls since the last [`Self::clear`].
+    pub fn marked_num(&self) -> usize {
+        self.marked_num
+    }
+
+    pub fn run(&mut self, func: &mut Function) {
+        let has_escaping_alloca = has_escaping_alloca(func);
+        let insns: Vec<_> = func
+            .layout
+            .iter_block()
+            .flat_map(|block| func.layout.iter_insn(block))
+            .collect();
+
+        for insn in insns {
+            let InsnData::Call {
+                func: callee,
+                args,
+                ret_ty,
+                is_tail,
+            } = func.dfg.insn_data(insn)
+            else {
+                continue;
+            };
+
+            let in_tail_position = !has_escaping_alloca
+                && is_in_tail_position(func, insn)
+                && func.callees[callee].call_conv().allows_tail_call();
+            if *is_tail == in_tail_position {
+                continue;
+            }
+
+            let data = InsnData::Call {
+                func: *callee,
+                args: args.clone(),
+                ret_ty: *ret_ty,
+                is_tail: in_tail_position,
+            };
+            func.dfg.replace_insn(insn, data);
+            if in_tail_position {
+                self.marked_num += 1;
+            }
+        }
+    }
+}
+
+/// Returns `true` if the address of an alloca of `func` may be used after a call reuses the frame,
+/// i.e., if it's used other than to access memory, directly or through a `gep`.
+pub(crate) fn has_escaping_alloca(func: &Function) -> bool {
+    func.layout
+        .iter_block()
+        .flat_map(|block| func.layout.iter_insn(block))
+        .filter(|&insn| matches!(func.dfg.insn_data(insn), InsnData::Alloca { .. }))
+        .any(|alloca| may_escape(func, func.dfg.insn_result(alloca).unwrap()))
+}
+
+fn may_escape(func: &Function, addr: Value) -> bool {
+    func.dfg
+        .users(addr)
+        .any(|&user| match func.dfg.insn_data(user) {
+            InsnData::Load { .. } | InsnData::Mem { .. } => false,
+            InsnData::Store { args, .. } => args[1] == addr,
+            InsnData::Gep { args } => {
+                args[1..].contains(&addr) || may_escape(func, func.dfg.insn_result(user).unwrap())
+            }
+            _ => true,
+        })
+}
+
+/// Returns `true` if `call` is immediately followed by a return of its result.
+fn is_in_tail_position(func: &Function, call: Insn) -> bool {
+    let Some(next) = func.layout.next_insn_of(call) else {
+        return false;
+    };
+
+    match func.dfg.insn_data(next) {
+        InsnData::Return { args } => *args == func.dfg.insn_result(call),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        DataLocationKind, Linkage, Signature, Type,
+    };
+
+    #[test]
+    fn mark_tail_calls() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let sig = Signature::new(
+            "test_func",
+            Linkage::Public,
+            &[Type::I1, Type::I32],
+            Type::I32,
+        );
+        let func_ref = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(func_ref);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let (c, x) = (builder.args()[0], builder.args()[1]);
+        builder.br(c, b1, b2);
+
+        builder.switch_to_block(b1);
+        let v2 = builder.call(func_ref, &[c, x]).unwrap();
+        builder.ret(Some(v2));
+
+        builder.switch_to_block(b2);
+        let v3 = builder.call(func_ref, &[c, x]).unwrap();
+        let v4 = builder.add(v3, x);
+        builder.ret(Some(v4));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func = &mut module.funcs[func_ref];
+
+        let mut solver = TailCallSolver::new();
+        solver.run(func);
+        assert_eq!(solver.marked_num(), 1);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1, v1.i32) -> i32 {
+    block0:
+        br v0 block1 block2;
+
+    block1:
+        v2.i32 = tail call %test_func v0 v1;
+        return v2;
+
+    block2:
+        v3.i32 = call %test_func v0 v1;
+        v4.i32 = add v3 v1;
+        return v4;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn keep_frame_with_escaping_alloca() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let ptr_ty = mb.ptr_type(Type::I32);
+        let read = mb.declare_function(Signature::new(
+            "read",
+            Linkage::Private,
+            &[ptr_ty],
+            Type::I32,
+        ));
+        let sig = Signature::new("test_func", Linkage::Public, &[Type::I32], Type::I32);
+        let func_ref = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(func_ref);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let ptr = builder.alloca(Type::I32);
+        builder.store(DataLocationKind::Memory, ptr, x);
+        let v = builder.call(read, &[ptr]).unwrap();
+        builder.ret(Some(v));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func = &mut module.funcs[func_ref];
+        assert!(has_escaping_alloca(func));
+
+        // The callee would reuse the frame that `ptr` points into.
+        let mut solver = TailCallSolver::new();
+        solver.run(func);
+        assert_eq!(solver.marked_num(), 0);
+    }
+}
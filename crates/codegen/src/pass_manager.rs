@@ -0,0 +1,869 @@
+//! This module contains a pass manager that runs a pipeline of optimization passes on a module.
+//!
+//! A pipeline is written as a comma separated list of pass names, e.g., `inline,sccp,adce`.
+//! Function passes run on every function that has a body, and module passes run once on the whole
+//! module. A function pass gets the analyses it requires from [`FunctionAnalyses`], which are
+//! dropped after the pass, since it may change the function in any way.
+//! Functions with the `optnone` attribute are left untouched by all passes.
+//!
+//! When [statistics](PassManager::enable_stats) are enabled, the pass manager measures the time
+//! each pass takes and how many insns and blocks it adds or removes in net, along with the
+//! counters the pass reports itself, e.g., the number of call sites it inlined.
+//!
+//! To find the pass that causes a miscompilation, a [bisect limit](PassManager::set_bisect_limit)
+//! stops applying passes after the given number of invocations. A function pass is invoked once
+//! per function, and a module pass once per module.
+//!
+//! With more than one [thread](PassManager::set_threads), a function pass runs on functions
+//! concurrently, after [freezing](sonatina_ir::module::ModuleCtx::freeze_types) the type store.
+//! Module passes always run on the calling thread.
+//!
+//! To diagnose a regression visually, the [CFGs can be dumped](PassManager::dump_cfg_after) as
+//! graphviz files after selected passes.
+//!
+//! Each pass runs in a `pass` [tracing] span, and each function a function pass runs on in a nested
+//! `func` span. The insn and block counts around each pass are emitted as `DEBUG` events.
+use std::{
+    fmt, fs,
+    io::{self, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Instant,
+};
+
+use smallvec::{smallvec, SmallVec};
+use sonatina_ir::{Function, Module};
+use tracing::Level;
+
+use crate::{
+    analyses::{Analysis, FunctionAnalyses},
+    optim::{
+        adce::AdceSolver,
+        block_layout::BlockLayoutSolver,
+        cold_split::ColdSplitter,
+        const_global_fold::ConstGlobalFoldSolver,
+        cse::CseSolver,
+        dfe::DeadFuncElim,
+        gvn::GvnSolver,
+        inliner::{InlineThreshold, Inliner},
+        insn_combine::InsnCombineSolver,
+        insn_simplify::InsnSimplifySolver,
+        licm::LicmSolver,
+        mem_intrinsic::MemIntrinsicSolver,
+        outliner::Outliner,
+        overflow_check_elim::OverflowCheckElimSolver,
+        pre::PreSolver,
+        sccp::SccpSolver,
+        simplify_cfg::SimplifyCfgSolver,
+        sink::SinkSolver,
+        specializer::Specializer,
+        strip::StripSolver,
+        tail_call::TailCallSolver,
+    },
+    stats::Statistics,
+};
+
+/// The named counters a pass reports, e.g., `("calls-inlined", 2)`.
+type Counters = SmallVec<[(&'static str, usize); 2]>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    Sccp,
+    SimplifyCfg,
+    Adce,
+    InsnSimplify,
+    InsnCombine,
+    Cse,
+    Gvn,
+    Licm,
+    Pre,
+    Sink,
+    OverflowCheckElim,
+    MemIntrinsic,
+    TailCall,
+    ConstGlobalFold,
+    Inline,
+    Outline,
+    ColdSplit,
+    Specialize,
+    Strip,
+    Dfe,
+    BlockLayout,
+}
+
+impl Pass {
+    pub const ALL: &'static [Pass] = &[
+        Pass::Sccp,
+        Pass::SimplifyCfg,
+        Pass::Adce,
+        Pass::InsnSimplify,
+        Pass::InsnCombine,
+        Pass::Cse,
+        Pass::Gvn,
+        Pass::Licm,
+        Pass::Pre,
+        Pass::Sink,
+        Pass::OverflowCheckElim,
+        Pass::MemIntrinsic,
+        Pass::TailCall,
+        Pass::ConstGlobalFold,
+        Pass::Inline,
+        Pass::Outline,
+        Pass::ColdSplit,
+        Pass::Specialize,
+        Pass::Strip,
+        Pass::Dfe,
+        Pass::BlockLayout,
+    ];
+
+    /// Returns the name of the pass used in pipeline strings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sccp => "sccp",
+            Self::SimplifyCfg => "simplify-cfg",
+            Self::Adce => "adce",
+            Self::InsnSimplify => "insn-simplify",
+            Self::InsnCombine => "insn-combine",
+            Self::Cse => "cse",
+            Self::Gvn => "gvn",
+            Self::Licm => "licm",
+            Self::Pre => "pre",
+            Self::Sink => "sink",
+            Self::OverflowCheckElim => "overflow-check-elim",
+            Self::MemIntrinsic => "mem-intrinsic",
+            Self::TailCall => "tail-call",
+            Self::ConstGlobalFold => "const-global-fold",
+            Self::Inline => "inline",
+            Self::Outline => "outline",
+            Self::ColdSplit => "cold-split",
+            Self::Specialize => "specialize",
+            Self::Strip => "strip",
+            Self::Dfe => "dfe",
+            Self::BlockLayout => "block-layout",
+        }
+    }
+
+    /// Returns `true` if the pass runs on the whole module rather than on each function.
+    pub fn is_module_pass(self) -> bool {
+        matches!(
+            self,
+            Self::ConstGlobalFold
+                | Self::Inline
+                | Self::Outline
+                | Self::ColdSplit
+                | Self::Specialize
+                | Self::Strip
+                | Self::Dfe
+        )
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = UnknownPassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|pass| pass.name() == s)
+            .ok_or_else(|| UnknownPassError(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPassError(pub String);
+
+impl fmt::Display for UnknownPassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown pass `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPassError {}
+
+/// An invocation of a pass, recorded while bisecting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassInvocation {
+    /// The 1-based index of the invocation.
+    pub index: usize,
+    pub pass: Pass,
+    /// The function the pass is invoked on, or `None` for a module pass.
+    pub func: Option<String>,
+    /// `true` if the invocation is beyond the bisect limit and the pass didn't run.
+    pub skipped: bool,
+}
+
+impl fmt::Display for PassInvocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Self {
+            index,
+            pass,
+            func,
+            skipped,
+        } = self;
+        let action = if *skipped { "NOT running" } else { "running" };
+        write!(f, "BISECT: {action} pass ({index}) {pass} on ")?;
+        match func {
+            Some(func) => write!(f, "function `{func}`"),
+            None => write!(f, "module"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+    analyses: FunctionAnalyses,
+    threads: NonZeroUsize,
+    stats: Option<Statistics>,
+    bisect_limit: Option<usize>,
+    invocations: Vec<PassInvocation>,
+    cfg_dump: Option<CfgDump>,
+}
+
+/// The passes after which CFGs are dumped, and the directory they are dumped to.
+#[derive(Debug)]
+struct CfgDump {
+    passes: Vec<Pass>,
+    dir: PathBuf,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self {
+            passes: Vec::new(),
+            analyses: FunctionAnalyses::new(),
+            threads: NonZeroUsize::MIN,
+            stats: None,
+            bisect_limit: None,
+            invocations: Vec::new(),
+            cfg_dump: None,
+        }
+    }
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a pass manager with the pipeline used when no pipeline is specified.
+    pub fn default_pipeline() -> Self {
+        let mut pm = Self::new();
+        for pass in [
+            Pass::Inline,
+            Pass::ConstGlobalFold,
+            Pass::OverflowCheckElim,
+            Pass::MemIntrinsic,
+            Pass::Sccp,
+            Pass::SimplifyCfg,
+            Pass::InsnSimplify,
+            Pass::InsnCombine,
+            Pass::Adce,
+            Pass::Gvn,
+            Pass::Licm,
+            Pass::Sink,
+            Pass::Adce,
+            Pass::TailCall,
+            Pass::BlockLayout,
+        ] {
+            pm.add_pass(pass);
+        }
+        pm
+    }
+
+    /// Parses a comma separated list of pass names, e.g., `sccp,adce`.
+    pub fn parse_pipeline(pipeline: &str) -> Result<Self, UnknownPassError> {
+        let mut pm = Self::new();
+        for name in pipeline.split(',').map(str::trim) {
+            if !name.is_empty() {
+                pm.add_pass(name.parse()?);
+            }
+        }
+        Ok(pm)
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) -> &mut Self {
+        self.passes.push(pass);
+        self
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    /// Sets the number of threads function passes run on. Defaults to 1.
+    pub fn set_threads(&mut self, threads: NonZeroUsize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Collects statistics in subsequent runs.
+    pub fn enable_stats(&mut self) -> &mut Self {
+        self.stats.get_or_insert_with(Statistics::new);
+        self
+    }
+
+    /// Returns the statistics collected so far, or `None` if they are not enabled.
+    pub fn stats(&self) -> Option<&Statistics> {
+        self.stats.as_ref()
+    }
+
+    /// Runs only the first `limit` pass invocations, and records all invocations.
+    pub fn set_bisect_limit(&mut self, limit: usize) -> &mut Self {
+        self.bisect_limit = Some(limit);
+        self
+    }
+
+    /// Returns the pass invocations recorded while bisecting.
+    pub fn invocations(&self) -> &[PassInvocation] {
+        &self.invocations
+    }
+
+    /// Writes the CFG of each function with a body to `dir` in the graphviz format after each run
+    /// of the `passes` in subsequent runs. A file is named after the 1-based position of the pass
+    /// in the pipeline, the pass, and the function, e.g., `03-sccp-main.dot`.
+    pub fn dump_cfg_after(&mut self, passes: &[Pass], dir: impl Into<PathBuf>) -> &mut Self {
+        self.cfg_dump = Some(CfgDump {
+            passes: passes.to_vec(),
+            dir: dir.into(),
+        });
+        self
+    }
+
+    /// Run all passes in the pipeline in order.
+    pub fn run(&mut self, module: &mut Module) {
+        for (idx, pass) in self.passes.clone().into_iter().enumerate() {
+            self.run_and_measure(pass, module);
+
+            let dump = self.cfg_dump.as_ref();
+            if let Some(dump) = dump.filter(|dump| dump.passes.contains(&pass)) {
+                if let Err(err) = dump_cfgs(module, &dump.dir, idx + 1, pass) {
+                    tracing::warn!(%err, dir = %dump.dir.display(), "failed to dump CFGs");
+                }
+            }
+        }
+    }
+
+    fn run_and_measure(&mut self, pass: Pass, module: &mut Module) {
+        let _span = tracing::info_span!("pass", pass = pass.name()).entered();
+        if self.stats.is_none() && !tracing::enabled!(Level::DEBUG) {
+            self.run_pass(pass, module);
+            return;
+        }
+
+        let (insns_before, blocks_before) = count_insns_and_blocks(module);
+        let start = Instant::now();
+        let counters = self.run_pass(pass, module);
+        let time = start.elapsed();
+        let (insns_after, blocks_after) = count_insns_and_blocks(module);
+        tracing::debug!(
+            insns_before,
+            insns_after,
+            blocks_before,
+            blocks_after,
+            time_us = time.as_micros() as u64,
+            "pass finished"
+        );
+
+        let Some(stats) = &mut self.stats else {
+            return;
+        };
+        stats.record_run(pass, time);
+        for (counter, n) in [
+            ("insns-added", insns_after.saturating_sub(insns_before)),
+            ("insns-removed", insns_before.saturating_sub(insns_after)),
+            ("blocks-added", blocks_after.saturating_sub(blocks_before)),
+            ("blocks-removed", blocks_before.saturating_sub(blocks_after)),
+        ]
+        .into_iter()
+        .chain(counters.into_iter().map(|(counter, n)| (counter, n as u64)))
+        {
+            if n != 0 {
+                stats.bump(pass, counter, n);
+            }
+        }
+    }
+
+    /// Runs `pass` and returns the counters it reports, summed over the functions it ran on.
+    fn run_pass(&mut self, pass: Pass, module: &mut Module) -> Counters {
+        if !pass.is_module_pass() {
+            return self.run_func_pass_on_all(pass, module);
+        }
+
+        if self.should_run(pass, None) {
+            run_module_pass(pass, module)
+        } else {
+            Counters::new()
+        }
+    }
+
+    fn run_func_pass_on_all(&mut self, pass: Pass, module: &mut Module) -> Counters {
+        // Decide which functions the pass runs on in order, so that bisecting is deterministic.
+        let mut funcs = Vec::new();
+        for func in module.funcs.values_mut() {
+            if func.layout.entry_block().is_some()
+                && !func.sig.attrs().optnone
+                && self.should_run(pass, Some(func.sig.name()))
+            {
+                funcs.push(func);
+            }
+        }
+
+        let mut counters = Counters::new();
+        let threads = self.threads.get().min(funcs.len());
+        if threads <= 1 {
+            for func in funcs {
+                add_counters(&mut counters, run_func_pass(pass, func, &mut self.analyses));
+            }
+            return counters;
+        }
+
+        // Functions mostly look up existing types, so they don't need to contend for a lock.
+        module.ctx.freeze_types();
+        let span = tracing::Span::current();
+        let chunk_size = funcs.len().div_ceil(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = funcs
+                .chunks_mut(chunk_size)
+                .map(|chunk| {
+                    let span = span.clone();
+                    scope.spawn(move || {
+                        let _span = span.entered();
+                        let mut analyses = FunctionAnalyses::new();
+                        let mut counters = Counters::new();
+                        for func in chunk {
+                            add_counters(&mut counters, run_func_pass(pass, func, &mut analyses));
+                        }
+                        counters
+                    })
+                })
+                .collect();
+            for handle in handles {
+                add_counters(&mut counters, handle.join().unwrap());
+            }
+        });
+        counters
+    }
+
+    /// Records an invocation of `pass` if bisecting, and returns `false` if the invocation is
+    /// beyond the bisect limit.
+    fn should_run(&mut self, pass: Pass, func: Option<&str>) -> bool {
+        let Some(limit) = self.bisect_limit else {
+            return true;
+        };
+
+        let index = self.invocations.len() + 1;
+        let skipped = index > limit;
+        self.invocations.push(PassInvocation {
+            index,
+            pass,
+            func: func.map(str::to_string),
+            skipped,
+        });
+        !skipped
+    }
+}
+
+/// Writes the CFG of each function with a body in `module` to `dir`.
+fn dump_cfgs(module: &Module, dir: &Path, position: usize, pass: Pass) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for func in module.funcs.values() {
+        if func.layout.entry_block().is_none() {
+            continue;
+        }
+        let path = dir.join(format!("{position:02}-{pass}-{}.dot", func.sig.name()));
+        let mut file = BufWriter::new(fs::File::create(path)?);
+        sonatina_ir::render_to(func, &mut file)?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// Runs the function pass `pass` on `func`, computing the analyses it requires, and returns the
+/// counters it reports.
+fn run_func_pass(pass: Pass, func: &mut Function, analyses: &mut FunctionAnalyses) -> Counters {
+    let _span = tracing::debug_span!("func", func = func.sig.name()).entered();
+    let counters = match pass {
+        Pass::Sccp => {
+            let cfg = analyses.cfg_mut(func);
+            SccpSolver::new().run(func, cfg);
+            Counters::new()
+        }
+        Pass::SimplifyCfg => {
+            let cfg = analyses.cfg_mut(func);
+            let mut solver = SimplifyCfgSolver::new();
+            solver.run(func, cfg);
+            smallvec![
+                ("dead-blocks-removed", solver.removed_block_num()),
+                ("dead-edges-removed", solver.removed_edge_num()),
+            ]
+        }
+        Pass::Adce => {
+            AdceSolver::new().run(func);
+            Counters::new()
+        }
+        Pass::InsnSimplify => {
+            InsnSimplifySolver::new().run(func);
+            Counters::new()
+        }
+        Pass::InsnCombine => {
+            let mut solver = InsnCombineSolver::new();
+            solver.run(func);
+            smallvec![("rewrites", solver.rewritten_num())]
+        }
+        Pass::TailCall => {
+            let mut solver = TailCallSolver::new();
+            solver.run(func);
+            smallvec![("tail-calls", solver.marked_num())]
+        }
+        Pass::OverflowCheckElim => {
+            analyses.compute(func, Analysis::Cfg);
+            let mut solver = OverflowCheckElimSolver::new();
+            solver.run(func, analyses.cfg());
+            smallvec![("checks-eliminated", solver.eliminated_num())]
+        }
+        Pass::MemIntrinsic => {
+            let mut solver = MemIntrinsicSolver::new();
+            solver.run(func);
+            smallvec![
+                ("intrinsics-removed", solver.removed_num()),
+                ("loads-forwarded", solver.forwarded_num()),
+            ]
+        }
+        Pass::Cse => {
+            analyses.compute(func, Analysis::DomTree);
+            let mut solver = CseSolver::new();
+            solver.run(func, analyses.domtree());
+            smallvec![("insns-eliminated", solver.eliminated_num())]
+        }
+        Pass::Gvn => {
+            let (cfg, domtree) = analyses.cfg_and_domtree_mut(func);
+            GvnSolver::new().run(func, cfg, domtree);
+            Counters::new()
+        }
+        Pass::Pre => {
+            analyses.compute(func, Analysis::DomTree);
+            let mut solver = PreSolver::new();
+            solver.run(func, analyses.cfg(), analyses.domtree());
+            smallvec![
+                ("insns-inserted", solver.inserted_num()),
+                ("insns-eliminated", solver.eliminated_num()),
+            ]
+        }
+        Pass::Licm => {
+            let (cfg, lpt) = analyses.cfg_and_loop_tree_mut(func);
+            LicmSolver::new().run(func, cfg, lpt);
+            Counters::new()
+        }
+        Pass::Sink => {
+            analyses.compute(func, Analysis::LoopTree);
+            let mut solver = SinkSolver::new();
+            solver.run(func, analyses.domtree(), analyses.loop_tree());
+            smallvec![("insns-sunk", solver.sunk_num())]
+        }
+        Pass::BlockLayout => {
+            analyses.compute(func, Analysis::Cfg);
+            let mut solver = BlockLayoutSolver::new();
+            solver.run(func, analyses.cfg());
+            smallvec![("blocks-moved", solver.moved_block_num())]
+        }
+        Pass::ConstGlobalFold
+        | Pass::Inline
+        | Pass::Outline
+        | Pass::ColdSplit
+        | Pass::Specialize
+        | Pass::Strip
+        | Pass::Dfe => {
+            unreachable!()
+        }
+    };
+    analyses.invalidate();
+    counters
+}
+
+/// Runs the module pass `pass` on `module`, and returns the counters it reports.
+fn run_module_pass(pass: Pass, module: &mut Module) -> Counters {
+    match pass {
+        Pass::ConstGlobalFold => {
+            let mut solver = ConstGlobalFoldSolver::new();
+            solver.run(module);
+            smallvec![("loads-folded", solver.folded_num())]
+        }
+        Pass::Inline => {
+            let mut inliner = Inliner::new(InlineThreshold::default());
+            inliner.run(module);
+            smallvec![("calls-inlined", inliner.inlined_num())]
+        }
+        Pass::Outline => {
+            let mut outliner = Outliner::default();
+            outliner.run(module);
+            smallvec![("sequences-outlined", outliner.outlined_num())]
+        }
+        Pass::ColdSplit => {
+            let mut splitter = ColdSplitter::default();
+            splitter.run(module);
+            smallvec![("regions-split", splitter.split_num())]
+        }
+        Pass::Specialize => {
+            let mut specializer = Specializer::default();
+            specializer.run(module);
+            smallvec![("calls-specialized", specializer.specialized_num())]
+        }
+        Pass::Strip => {
+            let mut solver = StripSolver::new();
+            solver.run(module);
+            smallvec![("locs-stripped", solver.stripped_num())]
+        }
+        Pass::Dfe => {
+            let mut solver = DeadFuncElim::new();
+            solver.run(module);
+            smallvec![("funcs-removed", solver.removed_num())]
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Adds each counter of `counters` to the counter of the same name in `total`.
+fn add_counters(total: &mut Counters, counters: Counters) {
+    for (counter, n) in counters {
+        match total.iter_mut().find(|(name, _)| *name == counter) {
+            Some((_, sum)) => *sum += n,
+            None => total.push((counter, n)),
+        }
+    }
+}
+
+/// Returns the number of insns and blocks in all functions of `module`.
+fn count_insns_and_blocks(module: &Module) -> (u64, u64) {
+    let (mut insns, mut blocks) = (0, 0);
+    for func_ref in module.iter_functions() {
+        let layout = &module.funcs[func_ref].layout;
+        for block in layout.iter_block() {
+            blocks += 1;
+            insns += layout.iter_insn(block).count() as u64;
+        }
+    }
+    (insns, blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        Linkage, Signature, Type,
+    };
+
+    #[test]
+    fn parse_pipeline() {
+        let pm = PassManager::parse_pipeline("inline, sccp,insn-simplify,adce,").unwrap();
+        assert_eq!(
+            pm.passes(),
+            &[Pass::Inline, Pass::Sccp, Pass::InsnSimplify, Pass::Adce]
+        );
+
+        assert_eq!(
+            PassManager::parse_pipeline("sccp,dce").unwrap_err(),
+            UnknownPassError("dce".to_string())
+        );
+    }
+
+    #[test]
+    fn run_pipeline() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v0 = builder.make_imm_value(1i32);
+        let v1 = builder.make_imm_value(2i32);
+        let v2 = builder.add(v0, v1);
+        let v3 = builder.mul(v2, v2);
+        builder.ret(Some(v3));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let mut pm = PassManager::parse_pipeline("sccp,adce").unwrap();
+        pm.enable_stats().run(&mut module);
+
+        let func_ref = module.iter_functions().next().unwrap();
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func() -> i32 {
+    block0:
+        return 9.i32;
+
+}
+"
+        );
+
+        let stats = pm.stats().unwrap();
+        assert_eq!(stats.passes().len(), 2);
+        assert_eq!(stats.get(Pass::Sccp).unwrap().runs, 1);
+        let insns_removed: u64 = stats
+            .passes()
+            .iter()
+            .filter_map(|stats| stats.counters.get("insns-removed"))
+            .sum();
+        assert_eq!(insns_removed, 2);
+    }
+
+    #[test]
+    fn report_pass_counters() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+
+        let sig = Signature::new("callee", Linkage::Private, &[Type::I32], Type::I32);
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let v = builder.add(x, x);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("test_func", Linkage::Public, &[Type::I32], Type::I32);
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let v = builder.call(callee, &[x]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let mut pm = PassManager::parse_pipeline("inline,dfe").unwrap();
+        pm.enable_stats().run(&mut module);
+
+        let stats = pm.stats().unwrap();
+        assert_eq!(
+            stats.get(Pass::Inline).unwrap().counters["calls-inlined"],
+            1
+        );
+        assert_eq!(stats.get(Pass::Dfe).unwrap().counters["funcs-removed"], 1);
+    }
+
+    #[test]
+    fn run_parallel() {
+        let build_module = || {
+            let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+            for i in 0..5 {
+                let sig =
+                    Signature::new(&format!("f{i}"), Linkage::Public, &[Type::I32], Type::I32);
+                let func_ref = mb.declare_function(sig);
+                let mut builder = mb.build_function::<InsnInserter>(func_ref);
+                let b0 = builder.append_block();
+                builder.switch_to_block(b0);
+                let arg = builder.args()[0];
+                let v0 = builder.make_imm_value(i);
+                let v1 = builder.make_imm_value(2i32);
+                let v2 = builder.add(v0, v1);
+                let v3 = builder.mul(v2, arg);
+                builder.ret(Some(v3));
+                builder.seal_all();
+                mb = builder.finish();
+            }
+            mb.build()
+        };
+
+        let mut serial = build_module();
+        PassManager::default_pipeline().run(&mut serial);
+        let mut parallel = build_module();
+        PassManager::default_pipeline()
+            .set_threads(NonZeroUsize::new(3).unwrap())
+            .run(&mut parallel);
+
+        for func_ref in serial.iter_functions() {
+            assert_eq!(dump_func(&serial, func_ref), dump_func(&parallel, func_ref));
+        }
+    }
+
+    #[test]
+    fn skip_optnone() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v0 = builder.make_imm_value(1i32);
+        let v1 = builder.make_imm_value(2i32);
+        let v2 = builder.add(v0, v1);
+        builder.ret(Some(v2));
+        builder.seal_all();
+        builder.func.sig.attrs_mut().optnone = true;
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let before = dump_func(&module, func_ref);
+        assert!(before.contains("-> i32 optnone {"));
+
+        PassManager::default_pipeline().run(&mut module);
+        assert_eq!(dump_func(&module, func_ref), before);
+    }
+
+    #[test]
+    fn dump_cfg_after() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v0 = builder.make_imm_value(1i32);
+        let v1 = builder.make_imm_value(2i32);
+        let v2 = builder.add(v0, v1);
+        builder.ret(Some(v2));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let dir = std::env::temp_dir().join(format!("sonatina-cfg-dump-{}", std::process::id()));
+        let mut pm = PassManager::parse_pipeline("sccp,adce,sccp").unwrap();
+        pm.dump_cfg_after(&[Pass::Sccp], &dir).run(&mut module);
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["01-sccp-test_func.dot", "03-sccp-test_func.dot"]);
+
+        let dot = fs::read_to_string(dir.join("03-sccp-test_func.dot")).unwrap();
+        assert!(dot.starts_with("digraph test_func {"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bisect() {
+        let mut builder = test_func_builder(&[], Type::I32);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v0 = builder.make_imm_value(1i32);
+        let v1 = builder.make_imm_value(2i32);
+        let v2 = builder.add(v0, v1);
+        builder.ret(Some(v2));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let before = count_insns_and_blocks(&module);
+        let mut pm = PassManager::parse_pipeline("sccp,adce,inline").unwrap();
+        pm.set_bisect_limit(0).run(&mut module);
+        assert_eq!(count_insns_and_blocks(&module), before);
+
+        let invocations: Vec<_> = pm.invocations().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            invocations,
+            [
+                "BISECT: NOT running pass (1) sccp on function `test_func`",
+                "BISECT: NOT running pass (2) adce on function `test_func`",
+                "BISECT: NOT running pass (3) inline on module",
+            ]
+        );
+    }
+}
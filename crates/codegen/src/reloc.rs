@@ -0,0 +1,141 @@
+//! This module contains relocations, i.e., the places in emitted code that refer to the value of
+//! a symbol that isn't known when the code is encoded.
+//!
+//! An emitter writes a placeholder for each such reference and records a [`Reloc`]. Symbols the
+//! emitter knows, e.g., jump targets, are resolved right away by [`apply`]; the others, e.g., the
+//! addresses of libraries, are left to whoever knows them later, through the same function.
+use std::fmt;
+
+use sonatina_ir::U256;
+
+/// A reference to `symbol` at `offset` in the code, whose value is the value of the symbol plus
+/// `addend`, encoded as `kind` describes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reloc<S> {
+    pub symbol: S,
+    pub kind: RelocKind,
+    pub offset: u32,
+    pub addend: i64,
+}
+
+impl<S> Reloc<S> {
+    pub fn new(symbol: S, kind: RelocKind, offset: u32) -> Self {
+        Self {
+            symbol,
+            kind,
+            offset,
+            addend: 0,
+        }
+    }
+
+    pub fn with_addend(mut self, addend: i64) -> Self {
+        self.addend = addend;
+        self
+    }
+}
+
+/// How the value of a relocation is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocKind {
+    /// The value as big-endian bytes of the given width, e.g., the immediate of an EVM `PUSH`.
+    BigEndian(usize),
+    /// The value as little-endian bytes of the given width.
+    LittleEndian(usize),
+}
+
+impl RelocKind {
+    /// Returns the number of bytes the value takes.
+    pub fn size(self) -> usize {
+        match self {
+            Self::BigEndian(size) | Self::LittleEndian(size) => size,
+        }
+    }
+
+    /// Writes `value` into `bytes`, which must be `self.size()` bytes long, and returns `false`
+    /// if it doesn't fit.
+    fn write(self, value: U256, bytes: &mut [u8]) -> bool {
+        let size = self.size();
+        if value.bits() > size * 8 {
+            return false;
+        }
+
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            let pos = match self {
+                Self::BigEndian(_) => size - 1 - idx,
+                Self::LittleEndian(_) => idx,
+            };
+            *byte = value.byte(pos);
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocError<S> {
+    /// The value of the symbol plus the addend doesn't fit the width of the relocation, or is
+    /// negative.
+    Overflow { symbol: S, offset: u32, value: U256 },
+    /// The relocation lies outside the code.
+    OutOfBounds { symbol: S, offset: u32 },
+}
+
+impl<S: fmt::Debug> fmt::Display for RelocError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Overflow {
+                symbol,
+                offset,
+                value,
+            } => write!(
+                f,
+                "value `{value:#x}` of {symbol:?} doesn't fit the relocation at `{offset:#x}`"
+            ),
+            Self::OutOfBounds { symbol, offset } => {
+                write!(
+                    f,
+                    "relocation of {symbol:?} at `{offset:#x}` is out of bounds"
+                )
+            }
+        }
+    }
+}
+
+impl<S: fmt::Debug> std::error::Error for RelocError<S> {}
+
+/// Patches each relocation in `relocs` whose symbol `resolve` knows the value of into `code`, and
+/// returns the relocations that remain unresolved.
+pub fn apply<S>(
+    code: &mut [u8],
+    relocs: Vec<Reloc<S>>,
+    mut resolve: impl FnMut(&S) -> Option<U256>,
+) -> Result<Vec<Reloc<S>>, RelocError<S>> {
+    let mut unresolved = Vec::new();
+    for reloc in relocs {
+        let Some(value) = resolve(&reloc.symbol) else {
+            unresolved.push(reloc);
+            continue;
+        };
+
+        let start = reloc.offset as usize;
+        let Some(bytes) = code.get_mut(start..start + reloc.kind.size()) else {
+            return Err(RelocError::OutOfBounds {
+                symbol: reloc.symbol,
+                offset: reloc.offset,
+            });
+        };
+        let addend = U256::from(reloc.addend.unsigned_abs());
+        let patched = if reloc.addend < 0 {
+            value.checked_sub(addend)
+        } else {
+            value.checked_add(addend)
+        };
+        if !patched.is_some_and(|value| reloc.kind.write(value, bytes)) {
+            return Err(RelocError::Overflow {
+                symbol: reloc.symbol,
+                offset: reloc.offset,
+                value,
+            });
+        }
+    }
+    Ok(unresolved)
+}
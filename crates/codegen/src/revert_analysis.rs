@@ -0,0 +1,143 @@
+//! This module contains an analysis that classifies blocks by whether execution starting from them
+//! can revert.
+//!
+//! A block reverts by itself if its terminator has no successors and is not a `return`, e.g., a
+//! `revert`, a `trap` or a `br_table` without destinations, or if it contains a call, since the
+//! callee may revert.
+//! Paths that never leave a loop are considered to revert, because on the EVM they run out of gas.
+use cranelift_entity::SecondaryMap;
+
+use sonatina_ir::{Block, ControlFlowGraph, Function, InsnData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RevertReachability {
+    /// All paths from the block revert.
+    MustRevert,
+    /// No path from the block reverts.
+    NoRevert,
+    /// Some paths from the block revert.
+    MayRevert,
+}
+
+#[derive(Debug, Default)]
+pub struct RevertAnalysis {
+    may_return: SecondaryMap<Block, bool>,
+    may_revert: SecondaryMap<Block, bool>,
+}
+
+impl RevertAnalysis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compute(&mut self, func: &Function, cfg: &ControlFlowGraph) {
+        self.clear();
+
+        let return_blocks = func.layout.iter_block().filter(|&block| {
+            func.layout
+                .last_insn_of(block)
+                .is_some_and(|insn| func.dfg.is_return(insn))
+        });
+        propagate(cfg, &mut self.may_return, return_blocks.collect());
+
+        // Blocks that can't return either revert explicitly or never terminate.
+        let revert_blocks = func.layout.iter_block().filter(|&block| {
+            !self.may_return[block]
+                || func
+                    .layout
+                    .iter_insn(block)
+                    .any(|insn| matches!(func.dfg.insn_data(insn), InsnData::Call { .. }))
+        });
+        propagate(cfg, &mut self.may_revert, revert_blocks.collect());
+    }
+
+    pub fn reachability(&self, block: Block) -> RevertReachability {
+        if !self.may_return[block] {
+            RevertReachability::MustRevert
+        } else if self.may_revert[block] {
+            RevertReachability::MayRevert
+        } else {
+            RevertReachability::NoRevert
+        }
+    }
+
+    /// Returns `true` if all paths from the `block` revert, i.e., effects of the block are never
+    /// observable.
+    pub fn must_revert(&self, block: Block) -> bool {
+        self.reachability(block) == RevertReachability::MustRevert
+    }
+
+    /// Returns `true` if no path from the `block` reverts.
+    pub fn no_revert(&self, block: Block) -> bool {
+        self.reachability(block) == RevertReachability::NoRevert
+    }
+
+    pub fn clear(&mut self) {
+        self.may_return.clear();
+        self.may_revert.clear();
+    }
+}
+
+/// Marks all blocks that reach one of the `worklist` blocks.
+fn propagate(
+    cfg: &ControlFlowGraph,
+    marks: &mut SecondaryMap<Block, bool>,
+    mut worklist: Vec<Block>,
+) {
+    while let Some(block) = worklist.pop() {
+        if marks[block] {
+            continue;
+        }
+        marks[block] = true;
+        worklist.extend(cfg.preds_of(block).filter(|pred| !marks[**pred]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{builder::test_util::*, Type};
+
+    #[test]
+    fn revert_reachability() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let b4 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        builder.br(arg, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.ret(None);
+
+        builder.switch_to_block(b2);
+        builder.br(arg, b3, b4);
+
+        builder.switch_to_block(b3);
+        builder.br_table(arg, None, &[]);
+
+        builder.switch_to_block(b4);
+        builder.jump(b4);
+
+        builder.seal_all();
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        let mut analysis = RevertAnalysis::new();
+        analysis.compute(func, &cfg);
+
+        assert_eq!(analysis.reachability(b0), RevertReachability::MayRevert);
+        assert!(analysis.no_revert(b1));
+        assert!(analysis.must_revert(b2));
+        assert!(analysis.must_revert(b3));
+        assert!(analysis.must_revert(b4));
+    }
+}
@@ -0,0 +1,265 @@
+//! This module contains a transform that merges all return sites of a function into a single
+//! exit block, and its inverse.
+//!
+//! In the single-exit form, every `return` is replaced with a jump to a newly created exit block,
+//! and returned values are merged by a phi in the exit block.
+
+use smallvec::SmallVec;
+
+use sonatina_ir::{
+    func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    Block, ControlFlowGraph, Function, Insn, InsnData, Type, Value,
+};
+
+#[derive(Debug, Default)]
+pub struct SingleExitNormalizer {
+    returns: Vec<Insn>,
+}
+
+impl SingleExitNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.returns.clear();
+    }
+
+    /// Merge all `return` insns of the function into a single exit block.
+    /// Returns the exit block, or `None` if the function has no `return`.
+    ///
+    /// `cfg` is updated to reflect the new exit block.
+    pub fn run(&mut self, func: &mut Function, cfg: &mut ControlFlowGraph) -> Option<Block> {
+        self.clear();
+
+        for block in func.layout.iter_block() {
+            if let Some(last_insn) = func.layout.last_insn_of(block) {
+                if func.dfg.is_return(last_insn) {
+                    self.returns.push(last_insn);
+                }
+            }
+        }
+
+        match self.returns.len() {
+            0 => return None,
+            1 => return Some(func.layout.insn_block(self.returns[0])),
+            _ => {}
+        }
+
+        let exit = func.dfg.make_block();
+        let mut inserter = InsnInserter::at_location(CursorLocation::NoWhere);
+        inserter.append_block(func, exit);
+
+        let mut incomings: SmallVec<[(Value, Block); 8]> = SmallVec::new();
+        for &ret in &self.returns {
+            let block = func.layout.insn_block(ret);
+            if let InsnData::Return { args: Some(arg) } = func.dfg.insn_data(ret) {
+                incomings.push((*arg, block));
+            }
+
+            func.dfg.replace_insn(ret, InsnData::jump(exit));
+            cfg.add_edge(block, exit);
+        }
+
+        inserter.set_location(CursorLocation::BlockBottom(exit));
+        let ret_value = self.merge_returned_values(func, &mut inserter, &incomings);
+        inserter.insert_insn_data(func, InsnData::Return { args: ret_value });
+
+        // Blocks that end with a `revert` or a `trap` stay exits.
+        cfg.exits.retain(|block| {
+            func.layout
+                .last_insn_of(*block)
+                .is_some_and(|insn| func.dfg.is_exit(insn))
+        });
+        cfg.exits.push(exit);
+        Some(exit)
+    }
+
+    /// The inverse of [`Self::run`].
+    /// Duplicates the `return` of each exit block that consists only of phis and a `return` into
+    /// its predecessors that unconditionally jump to it. Exit blocks that lose all their
+    /// predecessors are removed.
+    ///
+    /// `cfg` is updated accordingly.
+    pub fn split(&mut self, func: &mut Function, cfg: &mut ControlFlowGraph) {
+        let exits: SmallVec<[Block; 8]> = cfg.exits.clone();
+        for exit in exits {
+            if Some(exit) == func.layout.entry_block() || !self.is_splittable(func, exit) {
+                continue;
+            }
+
+            let preds: SmallVec<[Block; 8]> = cfg.preds_of(exit).copied().collect();
+            for pred in preds {
+                self.duplicate_return(func, cfg, exit, pred);
+            }
+
+            if cfg.pred_num_of(exit) == 0 {
+                InsnInserter::at_location(CursorLocation::BlockTop(exit)).remove_block(func);
+                cfg.exits.retain(|block| *block != exit);
+            }
+        }
+    }
+
+    fn merge_returned_values(
+        &self,
+        func: &mut Function,
+        inserter: &mut InsnInserter,
+        incomings: &[(Value, Block)],
+    ) -> Option<Value> {
+        let (first, _) = *incomings.first()?;
+        if incomings.iter().all(|(value, _)| *value == first) {
+            return Some(first);
+        }
+
+        let ty = func.sig.ret_ty();
+        debug_assert_ne!(ty, Type::Void);
+        let phi = InsnData::Phi {
+            values: incomings.iter().map(|(value, _)| *value).collect(),
+            blocks: incomings.iter().map(|(_, block)| *block).collect(),
+            ty,
+        };
+
+        let insn = inserter.insert_insn_data(func, phi);
+        let result = inserter.make_result(func, insn).unwrap();
+        inserter.attach_result(func, insn, result);
+        Some(result)
+    }
+
+    /// Returns `true` if `exit` consists only of phis whose results are used only by the
+    /// `return` at the end of the block.
+    fn is_splittable(&self, func: &Function, exit: Block) -> bool {
+        let Some(ret) = func.layout.last_insn_of(exit) else {
+            return false;
+        };
+        if !func.dfg.is_return(ret) {
+            return false;
+        }
+
+        func.layout
+            .iter_insn(exit)
+            .filter(|insn| *insn != ret)
+            .all(|insn| {
+                func.dfg.is_phi(insn)
+                    && func
+                        .dfg
+                        .insn_result(insn)
+                        .is_none_or(|result| func.dfg.users(result).all(|user| *user == ret))
+            })
+    }
+
+    fn duplicate_return(
+        &self,
+        func: &mut Function,
+        cfg: &mut ControlFlowGraph,
+        exit: Block,
+        pred: Block,
+    ) {
+        let Some(jump) = func.layout.last_insn_of(pred) else {
+            return;
+        };
+        if !matches!(func.dfg.insn_data(jump), InsnData::Jump { .. }) {
+            return;
+        }
+
+        let ret = func.layout.last_insn_of(exit).unwrap();
+        let ret_value = match func.dfg.insn_data(ret) {
+            InsnData::Return { args: Some(arg) } => {
+                Some(self.incoming_value(func, exit, *arg, pred))
+            }
+            _ => None,
+        };
+        func.dfg
+            .replace_insn(jump, InsnData::Return { args: ret_value });
+
+        func.remove_phi_args(exit, pred);
+
+        cfg.remove_edge(pred, exit);
+        cfg.exits.push(pred);
+    }
+
+    /// Returns the value that `value` takes when `exit` is entered from `pred`.
+    fn incoming_value(&self, func: &Function, exit: Block, value: Value, pred: Block) -> Value {
+        let Some(insn) = func.dfg.value_insn(value) else {
+            return value;
+        };
+        if func.layout.insn_block(insn) != exit {
+            return value;
+        }
+
+        match func.dfg.insn_data(insn) {
+            InsnData::Phi { values, blocks, .. } => {
+                let idx = blocks.iter().position(|block| *block == pred).unwrap();
+                values[idx]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::builder::test_util::*;
+
+    #[test]
+    fn merge_and_split_returns() {
+        let mut builder = test_func_builder(&[Type::I1], Type::I8);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let cond = builder.args()[0];
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        let v1 = builder.make_imm_value(1i8);
+        builder.ret(Some(v1));
+
+        builder.switch_to_block(b2);
+        let v2 = builder.make_imm_value(2i8);
+        builder.ret(Some(v2));
+
+        builder.seal_all();
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let original = dump_func(&module, func_ref);
+
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::default();
+        cfg.compute(func);
+        let mut normalizer = SingleExitNormalizer::new();
+        let exit = normalizer.run(func, &mut cfg).unwrap();
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i1) -> i8 {
+    block0:
+        br v0 block1 block2;
+
+    block1:
+        jump block3;
+
+    block2:
+        jump block3;
+
+    block3:
+        v3.i8 = phi (1.i8 block1) (2.i8 block2);
+        return v3;
+
+}
+"
+        );
+        assert_eq!(cfg.exits.as_slice(), &[exit]);
+
+        let func = &mut module.funcs[func_ref];
+        let mut cfg_recomputed = ControlFlowGraph::default();
+        cfg_recomputed.compute(func);
+        assert_eq!(cfg, cfg_recomputed);
+
+        normalizer.split(func, &mut cfg);
+        assert_eq!(dump_func(&module, func_ref), original);
+    }
+}
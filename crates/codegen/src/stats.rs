@@ -0,0 +1,167 @@
+//! This module contains statistics collected while a pass pipeline runs.
+//!
+//! Each pass has named counters, e.g., `insns-removed`, and the accumulated wall-clock time it
+//! took. Statistics are printed either as a human readable table or as JSON.
+use std::{collections::BTreeMap, io, time::Duration};
+
+use sonatina_ir::json::{delim, JsonStr};
+
+use crate::pass_manager::Pass;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    pub pass: Pass,
+    /// The number of times the pass appears in the pipeline.
+    pub runs: usize,
+    pub time: Duration,
+    pub counters: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Stats of each pass, in the order the passes first ran.
+    passes: Vec<PassStats>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `pass` ran once and took `time`.
+    pub fn record_run(&mut self, pass: Pass, time: Duration) {
+        let stats = self.pass_stats_mut(pass);
+        stats.runs += 1;
+        stats.time += time;
+    }
+
+    /// Adds `n` to the `counter` of `pass`.
+    pub fn bump(&mut self, pass: Pass, counter: &'static str, n: u64) {
+        *self
+            .pass_stats_mut(pass)
+            .counters
+            .entry(counter)
+            .or_default() += n;
+    }
+
+    pub fn passes(&self) -> &[PassStats] {
+        &self.passes
+    }
+
+    pub fn get(&self, pass: Pass) -> Option<&PassStats> {
+        self.passes.iter().find(|stats| stats.pass == pass)
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.passes.iter().map(|stats| stats.time).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.passes.clear();
+    }
+
+    pub fn write_table(&self, mut w: impl io::Write) -> io::Result<()> {
+        writeln!(
+            w,
+            "{:<20}{:>6}{:>12}  counters",
+            "pass", "runs", "time (ms)"
+        )?;
+        for stats in &self.passes {
+            write!(
+                w,
+                "{:<20}{:>6}{:>12.3}",
+                stats.pass.name(),
+                stats.runs,
+                as_millis(stats.time)
+            )?;
+            for (i, (name, value)) in stats.counters.iter().enumerate() {
+                let sep = if i == 0 { "  " } else { " " };
+                write!(w, "{sep}{name}={value}")?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w, "{:<26}{:>12.3}", "total", as_millis(self.total_time()))
+    }
+
+    pub fn write_json(&self, mut w: impl io::Write) -> io::Result<()> {
+        write!(w, "{{\"passes\":[")?;
+        for (i, stats) in self.passes.iter().enumerate() {
+            write!(
+                w,
+                "{}{{\"pass\":{},\"runs\":{},\"time_us\":{},\"counters\":{{",
+                delim(i),
+                JsonStr(stats.pass.name()),
+                stats.runs,
+                stats.time.as_micros()
+            )?;
+            for (j, (name, value)) in stats.counters.iter().enumerate() {
+                write!(w, "{}{}:{value}", delim(j), JsonStr(name))?;
+            }
+            write!(w, "}}}}")?;
+        }
+        write!(w, "],\"total_time_us\":{}}}", self.total_time().as_micros())
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut buf = Vec::new();
+        self.write_json(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn pass_stats_mut(&mut self, pass: Pass) -> &mut PassStats {
+        match self.passes.iter().position(|stats| stats.pass == pass) {
+            Some(idx) => &mut self.passes[idx],
+            None => {
+                self.passes.push(PassStats {
+                    pass,
+                    runs: 0,
+                    time: Duration::ZERO,
+                    counters: BTreeMap::new(),
+                });
+                self.passes.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+fn as_millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_stats() {
+        let mut stats = Statistics::new();
+        stats.record_run(Pass::Sccp, Duration::from_micros(1500));
+        stats.bump(Pass::Sccp, "insns-removed", 2);
+        stats.record_run(Pass::Adce, Duration::from_micros(500));
+        stats.record_run(Pass::Sccp, Duration::from_micros(500));
+        stats.bump(Pass::Sccp, "insns-removed", 1);
+
+        let sccp = stats.get(Pass::Sccp).unwrap();
+        assert_eq!(sccp.runs, 2);
+        assert_eq!(sccp.counters["insns-removed"], 3);
+        assert_eq!(
+            stats.to_json(),
+            "{\"passes\":[\
+             {\"pass\":\"sccp\",\"runs\":2,\"time_us\":2000,\"counters\":{\"insns-removed\":3}},\
+             {\"pass\":\"adce\",\"runs\":1,\"time_us\":500,\"counters\":{}}\
+             ],\"total_time_us\":2500}"
+        );
+
+        let mut table = Vec::new();
+        stats.write_table(&mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "\
+pass                  runs   time (ms)  counters
+sccp                     2       2.000  insns-removed=3
+adce                     1       0.500
+total                            2.500
+"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! This module contains the case clustering of switches, i.e., `br_table` insns and the selector
+//! dispatch of contracts.
+//!
+//! Testing a switch with one comparison per case costs gas linear in the number of cases, which
+//! dominates the cost of calling a contract with hundreds of external functions. The clustering
+//! tells the lowering which cases can be found with a jump table instead: keys that are dense in a
+//! range are looked up by their offset in the range, and keys that are spread out, like function
+//! selectors, are split into buckets by the bits right below the high bits all keys have in common.
+use smallvec::smallvec;
+use sonatina_ir::{isa::IsaSpecificCostTable, Block, DataFlowGraph, Insn, InsnData, Value};
+
+/// The minimum percentage of the keys in its range that a range cluster has.
+const MIN_RANGE_DENSITY: u128 = 40;
+
+/// The minimum number of keys of a range cluster; fewer keys are cheaper to compare.
+const MIN_RANGE_KEYS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseClusters {
+    /// The width of the keys in bits.
+    bits: u32,
+    /// The keys in ascending order, without duplicates.
+    keys: Vec<u64>,
+    clusters: Vec<Cluster>,
+    common_high_bits: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cluster {
+    /// A key that is tested by comparison.
+    Single(u64),
+    /// Keys in `lo..=hi` that are dense enough to be looked up in a jump table indexed by
+    /// `key - lo`.
+    Range { lo: u64, hi: u64, keys: Vec<u64> },
+}
+
+/// A jump table whose entry for a key is the bucket at `(key >> shift) - base`. A bucket holds the
+/// keys that share the entry, which still need to be compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Buckets {
+    pub shift: u32,
+    pub base: u64,
+    pub buckets: Vec<Vec<u64>>,
+}
+
+impl CaseClusters {
+    /// Clusters the `keys` of a switch on a `bits`-wide value.
+    pub fn analyze(keys: &[u64], bits: u32) -> Self {
+        debug_assert!(bits <= 256);
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut clusters = Vec::new();
+        let mut start = 0;
+        while start < keys.len() {
+            // Density isn't monotonic in the end of the range, so take the furthest dense end.
+            let end = (start..keys.len())
+                .rev()
+                .find(|&end| is_dense(&keys[start..=end]))
+                .unwrap_or(start);
+            if end - start + 1 >= MIN_RANGE_KEYS {
+                clusters.push(Cluster::Range {
+                    lo: keys[start],
+                    hi: keys[end],
+                    keys: keys[start..=end].to_vec(),
+                });
+                start = end + 1;
+            } else {
+                clusters.push(Cluster::Single(keys[start]));
+                start += 1;
+            }
+        }
+
+        let common_high_bits = match (keys.first(), keys.last()) {
+            (Some(min), Some(max)) => {
+                let common = (min ^ max).leading_zeros();
+                if bits <= 64 {
+                    common - (64 - bits)
+                } else {
+                    common + (bits - 64)
+                }
+            }
+            _ => bits,
+        };
+
+        Self {
+            bits,
+            keys,
+            clusters,
+            common_high_bits,
+        }
+    }
+
+    /// Clusters the cases of a `br_table`, or returns `None` if `insn` is not a `br_table` or has
+    /// a case that doesn't fit in 64 bits.
+    pub fn from_br_table(dfg: &DataFlowGraph, insn: Insn) -> Option<Self> {
+        let InsnData::BrTable { args, .. } = dfg.insn_data(insn) else {
+            return None;
+        };
+
+        let mut bits = 0;
+        let mut keys = Vec::with_capacity(args.len() - 1);
+        for &arg in &args[1..] {
+            let imm = dfg.value_imm(arg)?;
+            let key = imm.as_unsigned();
+            if key.bits() > 64 {
+                return None;
+            }
+            bits = imm.bit_width() as u32;
+            keys.push(key.as_u64());
+        }
+        Some(Self::analyze(&keys, bits))
+    }
+
+    /// Returns the keys in ascending order.
+    pub fn keys(&self) -> &[u64] {
+        &self.keys
+    }
+
+    /// Returns the clusters in ascending order of their keys.
+    pub fn clusters(&self) -> &[Cluster] {
+        &self.clusters
+    }
+
+    /// Returns the number of high bits that all keys have in common.
+    pub fn common_high_bits(&self) -> u32 {
+        self.common_high_bits
+    }
+
+    /// Returns `true` if the keys form a single range cluster.
+    pub fn is_dense(&self) -> bool {
+        matches!(self.clusters.as_slice(), [Cluster::Range { .. }])
+    }
+
+    /// Splits the keys into buckets of about `bucket_len` keys on average.
+    ///
+    /// Dense keys get one bucket per key in their range. Otherwise, the buckets are indexed by the
+    /// bits below the common high bits, which spreads keys that look random, like selectors,
+    /// evenly.
+    pub fn buckets(&self, bucket_len: usize) -> Buckets {
+        debug_assert!(bucket_len > 0);
+        let (shift, base, len) = match self.clusters.as_slice() {
+            [Cluster::Range { lo, hi, .. }] => (0, *lo, (hi - lo) as usize + 1),
+            _ => {
+                let free_bits = self.bits - self.common_high_bits;
+                let bucket_num = self.keys.len().div_ceil(bucket_len).max(1);
+                let index_bits = bucket_num
+                    .next_power_of_two()
+                    .trailing_zeros()
+                    .min(free_bits);
+                let shift = free_bits - index_bits;
+                let base = self
+                    .keys
+                    .first()
+                    .map_or(0, |key| shr(*key, shift + index_bits) << index_bits);
+                (shift, base, 1 << index_bits)
+            }
+        };
+
+        let mut buckets = vec![Vec::new(); len];
+        for &key in &self.keys {
+            buckets[(shr(key, shift) - base) as usize].push(key);
+        }
+        Buckets {
+            shift,
+            base,
+            buckets,
+        }
+    }
+}
+
+/// Returns `true` if comparing a switch with `key_num` keys in turn is cheaper on average than
+/// looking up a jump table of buckets of `bucket_len` keys, which are compared after.
+pub fn prefers_linear_search(
+    costs: &dyn IsaSpecificCostTable,
+    key_num: usize,
+    bucket_len: usize,
+) -> bool {
+    // Only the shape of a single case matters to its cost.
+    let case = InsnData::BrTable {
+        args: smallvec![Value::from_u32(0); 2],
+        default: None,
+        table: smallvec![Block::from_u32(0)],
+    };
+    let case_cost = costs.insn_cost(&case);
+
+    // On average, half of the keys are compared before finding the matching one.
+    key_num as u64 * case_cost <= 2 * costs.jump_table_cost() + bucket_len as u64 * case_cost
+}
+
+/// Shifts right, giving `0` if all bits are shifted out.
+fn shr(key: u64, amount: u32) -> u64 {
+    key.checked_shr(amount).unwrap_or(0)
+}
+
+fn is_dense(keys: &[u64]) -> bool {
+    let span = (keys[keys.len() - 1] - keys[0]) as u128 + 1;
+    keys.len() as u128 * 100 >= span * MIN_RANGE_DENSITY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cluster_keys() {
+        let clusters = CaseClusters::analyze(&[100, 3, 1, 2, 4, 6, 1000, 4], 32);
+        assert_eq!(clusters.keys(), &[1, 2, 3, 4, 6, 100, 1000]);
+        assert_eq!(
+            clusters.clusters(),
+            &[
+                Cluster::Range {
+                    lo: 1,
+                    hi: 6,
+                    keys: vec![1, 2, 3, 4, 6]
+                },
+                Cluster::Single(100),
+                Cluster::Single(1000),
+            ]
+        );
+        assert_eq!(clusters.common_high_bits(), 22);
+
+        let dense = CaseClusters::analyze(&[10, 11, 13, 14], 8);
+        assert!(dense.is_dense());
+        let buckets = dense.buckets(2);
+        assert_eq!((buckets.shift, buckets.base), (0, 10));
+        assert_eq!(
+            buckets.buckets,
+            vec![vec![10], vec![11], vec![], vec![13], vec![14]]
+        );
+    }
+
+    #[test]
+    fn selector_buckets() {
+        let selectors = [
+            0xa9059cbb, 0x095ea7b3, 0x23b872dd, 0x70a08231, 0x18160ddd, 0xdd62ed3e,
+        ];
+        let clusters = CaseClusters::analyze(&selectors, 32);
+        assert_eq!(clusters.common_high_bits(), 0);
+        assert!(!clusters.is_dense());
+
+        let buckets = clusters.buckets(2);
+        assert_eq!((buckets.shift, buckets.base), (30, 0));
+        assert_eq!(
+            buckets.buckets,
+            vec![
+                vec![0x095ea7b3, 0x18160ddd, 0x23b872dd],
+                vec![0x70a08231],
+                vec![0xa9059cbb],
+                vec![0xdd62ed3e],
+            ]
+        );
+    }
+
+    #[test]
+    fn linear_search_threshold() {
+        let isa = sonatina_ir::builder::test_util::build_test_isa();
+        let costs = isa.cost_table();
+        assert!(prefers_linear_search(costs, 8, 2));
+        // A contract with many external functions is worth the overhead of a jump table.
+        assert!(!prefers_linear_search(costs, 12, 2));
+    }
+}
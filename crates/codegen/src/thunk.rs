@@ -0,0 +1,308 @@
+//! This module contains the generation of thunks, i.e., small functions that adapt calls with one
+//! signature to a function with another.
+//!
+//! A thunk takes the arguments its callers pass, reorders, drops or widens them to the arguments
+//! of its target, and tail calls the target. This allows changing the signature of a function,
+//! e.g., to remove a dead argument or to specialize it for a constant, and to redirect call sites
+//! to the thunk one at a time instead of rewriting every caller at once.
+
+use std::fmt;
+
+use sonatina_ir::{
+    insn::CastOp, module::FuncRef, Block, Function, Immediate, Insn, InsnData, Linkage, Module,
+    Signature, Type, Value,
+};
+
+/// How a thunk computes an argument of its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgSource {
+    /// The argument of the thunk at the index, which has the same type.
+    Arg(usize),
+    /// The argument of the thunk at the index, sign extended to the wider type.
+    Sext(usize),
+    /// The argument of the thunk at the index, zero extended to the wider type.
+    Zext(usize),
+    /// A constant for an argument the thunk doesn't take.
+    Imm(Immediate),
+}
+
+#[derive(Debug, Default)]
+pub struct ThunkBuilder {
+    thunk_num: usize,
+}
+
+impl ThunkBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a private function that takes `args`, computes the arguments of `target` from them as
+    /// described by `sources`, and returns the result of calling `target`.
+    pub fn make_thunk(
+        &mut self,
+        module: &mut Module,
+        target: FuncRef,
+        args: &[Type],
+        sources: &[ArgSource],
+    ) -> Result<FuncRef, ThunkError> {
+        let target_sig = module.funcs[target].sig.clone();
+        validate_sources(&target_sig, args, sources)?;
+
+        let name = self.thunk_name(module, target_sig.name());
+        let sig = Signature::new(&name, Linkage::Private, args, target_sig.ret_ty());
+        let mut func = Function::new(&module.ctx, sig);
+        func.callees.insert(target, target_sig.clone());
+
+        let block = func.dfg.make_block();
+        func.layout.append_block(block);
+        let mut call_args = Vec::with_capacity(sources.len());
+        for (source, &ty) in sources.iter().zip(target_sig.args()) {
+            let arg = match *source {
+                ArgSource::Arg(idx) => func.arg_values[idx],
+                ArgSource::Sext(idx) => {
+                    let arg = func.arg_values[idx];
+                    append_cast(&mut func, block, CastOp::Sext, arg, ty)
+                }
+                ArgSource::Zext(idx) => {
+                    let arg = func.arg_values[idx];
+                    append_cast(&mut func, block, CastOp::Zext, arg, ty)
+                }
+                ArgSource::Imm(imm) => func.dfg.make_imm_value(imm),
+            };
+            call_args.push(arg);
+        }
+
+        let call = func.dfg.make_insn(InsnData::Call {
+            func: target,
+            args: call_args.into(),
+            ret_ty: target_sig.ret_ty(),
+            is_tail: true,
+        });
+        func.layout.append_insn(call, block);
+        let result = func
+            .dfg
+            .make_result(call)
+            .map(|data| func.dfg.make_value(data));
+        if let Some(result) = result {
+            func.dfg.attach_result(call, result);
+        }
+        let ret = func.dfg.make_insn(InsnData::Return { args: result });
+        func.layout.append_insn(ret, block);
+
+        Ok(module.funcs.push(func))
+    }
+
+    /// Returns a thunk name that doesn't collide with functions in the module.
+    fn thunk_name(&mut self, module: &Module, target: &str) -> String {
+        loop {
+            let name = format!("__thunk_{target}_{}", self.thunk_num);
+            self.thunk_num += 1;
+            if module.funcs.values().all(|func| func.sig.name() != name) {
+                return name;
+            }
+        }
+    }
+}
+
+/// Redirects the calls at `sites`, given as the caller and the call insn, to `thunk`. The calls
+/// must pass the arguments the thunk takes.
+pub fn redirect_calls(
+    module: &mut Module,
+    sites: &[(FuncRef, Insn)],
+    thunk: FuncRef,
+) -> Result<(), ThunkError> {
+    let sig = module.funcs[thunk].sig.clone();
+    for &(caller, insn) in sites {
+        let func = &module.funcs[caller];
+        let InsnData::Call { args, ret_ty, .. } = func.dfg.insn_data(insn) else {
+            return Err(ThunkError::NotCall(caller, insn));
+        };
+        let arg_tys = args.iter().map(|arg| func.dfg.value_ty(*arg));
+        if !arg_tys.eq(sig.args().iter().copied()) || *ret_ty != sig.ret_ty() {
+            return Err(ThunkError::SignatureMismatch(caller, insn));
+        }
+    }
+
+    for &(caller, insn) in sites {
+        let func = &mut module.funcs[caller];
+        func.callees.insert(thunk, sig.clone());
+        let InsnData::Call {
+            args,
+            ret_ty,
+            is_tail,
+            ..
+        } = func.dfg.insn_data(insn).clone()
+        else {
+            unreachable!();
+        };
+        func.dfg.replace_insn(
+            insn,
+            InsnData::Call {
+                func: thunk,
+                args,
+                ret_ty,
+                is_tail,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn validate_sources(
+    target_sig: &Signature,
+    args: &[Type],
+    sources: &[ArgSource],
+) -> Result<(), ThunkError> {
+    if sources.len() != target_sig.args().len() {
+        return Err(ThunkError::ArgNum {
+            expected: target_sig.args().len(),
+            found: sources.len(),
+        });
+    }
+
+    for (target_arg, (source, &ty)) in sources.iter().zip(target_sig.args()).enumerate() {
+        let valid = match *source {
+            ArgSource::Arg(idx) => args.get(idx) == Some(&ty),
+            ArgSource::Sext(idx) | ArgSource::Zext(idx) => args
+                .get(idx)
+                .is_some_and(|arg| arg.is_integral() && ty.is_integral() && *arg < ty),
+            ArgSource::Imm(imm) => imm.ty() == ty,
+        };
+        if !valid {
+            return Err(ThunkError::InvalidSource(target_arg));
+        }
+    }
+    Ok(())
+}
+
+fn append_cast(func: &mut Function, block: Block, code: CastOp, arg: Value, ty: Type) -> Value {
+    let insn = func.dfg.make_insn(InsnData::cast(code, arg, ty));
+    func.layout.append_insn(insn, block);
+    let result = func.dfg.make_result(insn).unwrap();
+    let result = func.dfg.make_value(result);
+    func.dfg.attach_result(insn, result);
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThunkError {
+    /// The number of argument sources differs from the number of arguments of the target.
+    ArgNum { expected: usize, found: usize },
+    /// The source of the target argument at the index refers to a missing argument of the thunk,
+    /// or doesn't produce a value of the argument type.
+    InvalidSource(usize),
+    /// The insn of the caller is not a call.
+    NotCall(FuncRef, Insn),
+    /// The call passes arguments or expects a result the thunk doesn't match.
+    SignatureMismatch(FuncRef, Insn),
+}
+
+impl fmt::Display for ThunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ArgNum { expected, found } => write!(
+                f,
+                "thunk target takes {expected} arguments, but {found} sources are given"
+            ),
+            Self::InvalidSource(idx) => {
+                write!(f, "invalid source for argument {idx} of the thunk target")
+            }
+            Self::NotCall(func, insn) => write!(f, "{insn:?} in {func:?} is not a call"),
+            Self::SignatureMismatch(func, insn) => {
+                write!(
+                    f,
+                    "call {insn:?} in {func:?} doesn't match the thunk signature"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ThunkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sonatina_ir::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+    };
+
+    #[test]
+    fn thunk_drops_and_widens_args() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let target = mb.declare_function(Signature::new(
+            "g",
+            Linkage::Public,
+            &[Type::I64, Type::I32, Type::I32],
+            Type::I64,
+        ));
+        let old = mb.declare_function(Signature::new(
+            "old",
+            Linkage::Public,
+            &[Type::I32, Type::I8, Type::I1],
+            Type::I64,
+        ));
+
+        let caller = mb.declare_function(Signature::new(
+            "f",
+            Linkage::Public,
+            &[Type::I32, Type::I8],
+            Type::I64,
+        ));
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (x, y) = (builder.args()[0], builder.args()[1]);
+        let flag = builder.make_imm_value(true);
+        let v = builder.call(old, &[x, y, flag]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let mut thunks = ThunkBuilder::new();
+        let args = [Type::I32, Type::I8, Type::I1];
+        assert_eq!(
+            thunks.make_thunk(&mut module, target, &args, &[ArgSource::Sext(0)]),
+            Err(ThunkError::ArgNum {
+                expected: 3,
+                found: 1
+            })
+        );
+        let sources = [
+            ArgSource::Zext(1),
+            ArgSource::Arg(0),
+            ArgSource::Imm(7i32.into()),
+        ];
+        let thunk = thunks
+            .make_thunk(&mut module, target, &args, &sources)
+            .unwrap();
+
+        let call = module.funcs[caller].dfg.value_insn(v).unwrap();
+        redirect_calls(&mut module, &[(caller, call)], thunk).unwrap();
+
+        assert_eq!(
+            dump_func(&module, thunk),
+            "func private %__thunk_g_0(v0.i32, v1.i8, v2.i1) -> i64 {
+    block0:
+        v3.i64 = zext v1;
+        v5.i64 = tail call %g v3 v0 7.i32;
+        return v5;
+
+}
+"
+        );
+        assert_eq!(
+            dump_func(&module, caller),
+            "func public %f(v0.i32, v1.i8) -> i64 {
+    block0:
+        v3.i64 = call %__thunk_g_0 v0 v1 1.i1;
+        return v3;
+
+}
+"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Property tests that compare `ControlFlowGraph`, `DomTree` and `LoopTree` against naive
+//! reference implementations on randomly generated CFGs.
+use std::collections::{BTreeMap, BTreeSet};
+
+use petgraph::{algo::dominators::simple_fast, graph::NodeIndex, Graph};
+use proptest::prelude::*;
+
+use sonatina_codegen::{domtree::DomTree, loop_analysis::LoopTree};
+use sonatina_ir::{builder::test_util::test_func_builder, Block, ControlFlowGraph, Function, Type};
+
+/// A terminator of a generated block. Destinations are indices into the block list.
+#[derive(Debug, Clone)]
+enum Term {
+    Ret,
+    Jump(usize),
+    Br(usize, usize),
+}
+
+impl Term {
+    fn succs(&self) -> Vec<usize> {
+        match *self {
+            Term::Ret => vec![],
+            Term::Jump(dest) => vec![dest],
+            Term::Br(then, else_) => vec![then, else_],
+        }
+    }
+}
+
+fn term(block_num: usize) -> impl Strategy<Value = Term> {
+    prop_oneof![
+        1 => Just(Term::Ret),
+        2 => (0..block_num).prop_map(Term::Jump),
+        3 => (0..block_num, 0..block_num).prop_map(|(then, else_)| Term::Br(then, else_)),
+    ]
+}
+
+fn cfg_shape() -> impl Strategy<Value = Vec<Term>> {
+    (1usize..16).prop_flat_map(|block_num| prop::collection::vec(term(block_num), block_num))
+}
+
+struct Case {
+    shape: Vec<Term>,
+    func: Function,
+    blocks: Vec<Block>,
+}
+
+impl Case {
+    fn new(shape: Vec<Term>) -> Self {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+        let blocks: Vec<_> = shape.iter().map(|_| builder.append_block()).collect();
+        let cond = builder.args()[0];
+
+        for (term, &block) in shape.iter().zip(&blocks) {
+            builder.switch_to_block(block);
+            match *term {
+                Term::Ret => builder.ret(None),
+                Term::Jump(dest) => builder.jump(blocks[dest]),
+                Term::Br(then, else_) => builder.br(cond, blocks[then], blocks[else_]),
+            }
+        }
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = module.funcs[func_ref].clone();
+        Self {
+            shape,
+            func,
+            blocks,
+        }
+    }
+
+    fn block_num(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn idx(&self, block: Block) -> usize {
+        self.blocks.iter().position(|b| *b == block).unwrap()
+    }
+
+    fn preds(&self, block: usize) -> BTreeSet<usize> {
+        (0..self.block_num())
+            .filter(|&pred| self.shape[pred].succs().contains(&block))
+            .collect()
+    }
+
+    /// Returns blocks reachable from the entry when `removed` is deleted from the graph.
+    fn reachable_without(&self, removed: Option<usize>) -> Vec<bool> {
+        let mut reached = vec![false; self.block_num()];
+        if removed == Some(0) {
+            return reached;
+        }
+
+        reached[0] = true;
+        let mut stack = vec![0];
+        while let Some(block) = stack.pop() {
+            for succ in self.shape[block].succs() {
+                if Some(succ) != removed && !reached[succ] {
+                    reached[succ] = true;
+                    stack.push(succ);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Immediate dominators computed by `petgraph`.
+    fn reference_idoms(&self) -> Vec<Option<usize>> {
+        let mut graph = Graph::<(), ()>::new();
+        let nodes: Vec<NodeIndex> = (0..self.block_num()).map(|_| graph.add_node(())).collect();
+        for (block, term) in self.shape.iter().enumerate() {
+            for succ in term.succs() {
+                graph.add_edge(nodes[block], nodes[succ], ());
+            }
+        }
+
+        let doms = simple_fast(&graph, nodes[0]);
+        nodes
+            .iter()
+            .map(|&node| doms.immediate_dominator(node).map(|idom| idom.index()))
+            .collect()
+    }
+
+    /// Natural loops keyed by their header. Back edges sharing a header form a single loop.
+    fn reference_loops(&self) -> BTreeMap<usize, BTreeSet<usize>> {
+        let idoms = self.reference_idoms();
+        let reachable = self.reachable_without(None);
+        let dominates = |dom: usize, mut block: usize| loop {
+            if dom == block {
+                return true;
+            }
+            match idoms[block] {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        };
+
+        let mut loops: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for latch in (0..self.block_num()).filter(|b| reachable[*b]) {
+            for header in self.shape[latch].succs() {
+                if !dominates(header, latch) {
+                    continue;
+                }
+
+                let body = loops.entry(header).or_default();
+                body.insert(header);
+                let mut stack = vec![latch];
+                while let Some(block) = stack.pop() {
+                    if body.insert(block) {
+                        stack.extend(self.preds(block).into_iter().filter(|b| reachable[*b]));
+                    }
+                }
+            }
+        }
+
+        loops
+    }
+
+    fn cfg(&self) -> ControlFlowGraph {
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(&self.func);
+        cfg
+    }
+}
+
+proptest! {
+    #[test]
+    fn cfg_edges(shape in cfg_shape()) {
+        let case = Case::new(shape);
+        let cfg = case.cfg();
+
+        for (idx, &block) in case.blocks.iter().enumerate() {
+            let succs: BTreeSet<_> = cfg.succs_of(block).map(|b| case.idx(*b)).collect();
+            let expected: BTreeSet<_> = case.shape[idx].succs().into_iter().collect();
+            prop_assert_eq!(succs, expected);
+
+            let preds: BTreeSet<_> = cfg.preds_of(block).map(|b| case.idx(*b)).collect();
+            prop_assert_eq!(preds, case.preds(idx));
+        }
+
+        let exits: BTreeSet<_> = cfg.exits.iter().map(|b| case.idx(*b)).collect();
+        let expected: BTreeSet<_> = (0..case.block_num())
+            .filter(|b| matches!(case.shape[*b], Term::Ret))
+            .collect();
+        prop_assert_eq!(exits, expected);
+    }
+
+    #[test]
+    fn post_order(shape in cfg_shape()) {
+        let case = Case::new(shape);
+        let cfg = case.cfg();
+        let reachable = case.reachable_without(None);
+
+        let po: Vec<_> = cfg.post_order().map(|b| case.idx(b)).collect();
+        let po_set: BTreeSet<_> = po.iter().copied().collect();
+        let expected: BTreeSet<_> = (0..case.block_num()).filter(|b| reachable[*b]).collect();
+        prop_assert_eq!(po.len(), po_set.len(), "post order visits a block twice");
+        prop_assert_eq!(po_set, expected);
+        prop_assert_eq!(po.last().copied(), Some(0));
+
+        // In RPO, every block except the entry has its DFS parent placed before it.
+        let rpo: Vec<_> = po.into_iter().rev().collect();
+        for (pos, &block) in rpo.iter().enumerate().skip(1) {
+            let preds = case.preds(block);
+            prop_assert!(rpo[..pos].iter().any(|b| preds.contains(b)));
+        }
+    }
+
+    #[test]
+    fn dominators(shape in cfg_shape()) {
+        let case = Case::new(shape);
+        let cfg = case.cfg();
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+
+        let reachable = case.reachable_without(None);
+        let ref_idoms = case.reference_idoms();
+        for (idx, &block) in case.blocks.iter().enumerate() {
+            let idom = domtree.idom_of(block).map(|b| case.idx(b));
+            prop_assert_eq!(idom, ref_idoms[idx], "idom of block{} differs", idx);
+        }
+
+        // `a` dominates `b` iff `b` becomes unreachable once `a` is removed.
+        for a in (0..case.block_num()).filter(|b| reachable[*b]) {
+            let reachable_without_a = case.reachable_without(Some(a));
+            for b in (0..case.block_num()).filter(|b| reachable[*b]) {
+                let expected = a == b || !reachable_without_a[b];
+                prop_assert_eq!(
+                    domtree.dominates(case.blocks[a], case.blocks[b]),
+                    expected,
+                    "dominates(block{}, block{})", a, b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn loop_forest(shape in cfg_shape()) {
+        let case = Case::new(shape);
+        let cfg = case.cfg();
+        let mut domtree = DomTree::new();
+        domtree.compute(&cfg);
+        let mut lpt = LoopTree::new();
+        lpt.compute(&cfg, &domtree);
+
+        let reachable = case.reachable_without(None);
+        let ref_loops = case.reference_loops();
+
+        let headers: BTreeSet<_> = lpt.loops().map(|lp| case.idx(lpt.loop_header(lp))).collect();
+        let ref_headers: BTreeSet<_> = ref_loops.keys().copied().collect();
+        prop_assert_eq!(headers, ref_headers);
+
+        for lp in lpt.loops() {
+            let header = case.idx(lpt.loop_header(lp));
+            let body = &ref_loops[&header];
+            for block in (0..case.block_num()).filter(|b| reachable[*b]) {
+                prop_assert_eq!(
+                    lpt.is_in_loop(case.blocks[block], lp),
+                    body.contains(&block),
+                    "membership of block{} in loop headed by block{}", block, header
+                );
+            }
+
+            // The parent is the smallest loop strictly enclosing this one.
+            let ref_parent = ref_loops
+                .iter()
+                .filter(|(h, b)| **h != header && b.is_superset(body))
+                .min_by_key(|(_, b)| b.len())
+                .map(|(h, _)| *h);
+            let parent = lpt.parent_loop(lp).map(|p| case.idx(lpt.loop_header(p)));
+            prop_assert_eq!(parent, ref_parent);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! `sonatina-compile` reads a module in the textual IR format, optimizes it, and compiles it into
+//! an EVM contract.
+//!
+//! ```text
+//! sonatina-compile token.sntn -o out
+//! ```
+//!
+//! writes the following artifacts to `out`.
+//! * `token.bin`: the deploy code in hex.
+//! * `token.bin-runtime`: the runtime code in hex.
+//! * `token.evm-asm`: the runtime code as assembly, annotated with the IR it's lowered from.
+//! * `token.abi.json`: the ABI of the public functions.
+//! * `token.debug.json`: the map from runtime code offsets to source locations.
+//! * `token.srcmap-runtime`: the same locations as a Solidity source map of the runtime code.
+//!
+//! With `--split-code`, runtime code that exceeds the size limit is split, and the companion
+//! contract is written to `token.companion.bin`, `token.companion.bin-runtime` and
+//! `token.companion.abi.json`. The address of the companion is left as zero in the code of the
+//! contract, to be linked once the companion is deployed.
+
+use std::{fmt::Write, fs, num::NonZeroUsize, path::Path, process};
+
+use sonatina_codegen::{
+    isa::evm,
+    pass_manager::{Pass, PassManager},
+};
+
+const USAGE: &str = "\
+Usage: sonatina-compile [OPTIONS] <INPUT>
+
+Options:
+  -p, --passes <PASSES>  Comma separated list of passes to run [default: the default pipeline]
+  -j, --jobs <N>         Number of threads function passes run on [default: 1]
+  -o, --out-dir <DIR>    Write the artifacts to DIR [default: .]
+      --metadata         Append the compiler version and the IPFS hash of the input to the
+                         runtime code as CBOR, as solc does
+      --split-code       Move cold functions to a companion contract if the runtime code exceeds
+                         the size limit
+      --list-passes      Print the available passes
+  -h, --help             Print this message";
+
+struct Args {
+    input: String,
+    passes: Option<String>,
+    jobs: NonZeroUsize,
+    out_dir: String,
+    metadata: bool,
+    split_code: bool,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut input = None;
+        let mut passes = None;
+        let mut jobs = NonZeroUsize::MIN;
+        let mut out_dir = ".".to_string();
+        let mut metadata = false;
+        let mut split_code = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{name}`"))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "--list-passes" => {
+                    for pass in Pass::ALL {
+                        println!("{pass}");
+                    }
+                    process::exit(0);
+                }
+                "-p" | "--passes" => passes = Some(value(&arg)?),
+                "-j" | "--jobs" => {
+                    let n = value(&arg)?;
+                    jobs = n
+                        .parse()
+                        .map_err(|_| format!("invalid number of jobs `{n}`"))?;
+                }
+                "-o" | "--out-dir" => out_dir = value(&arg)?,
+                "--metadata" => metadata = true,
+                "--split-code" => split_code = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ if input.is_none() => input = Some(arg),
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+
+        Ok(Self {
+            input: input.ok_or("no input file is given")?,
+            passes,
+            jobs,
+            out_dir,
+            metadata,
+            split_code,
+        })
+    }
+}
+
+fn main() {
+    let args = Args::parse().unwrap_or_else(|err| {
+        eprintln!("error: {err}\n\n{USAGE}");
+        process::exit(2);
+    });
+
+    if let Err(err) = run(&args) {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let mut pass_manager = match &args.passes {
+        Some(passes) => PassManager::parse_pipeline(passes).map_err(|err| err.to_string())?,
+        None => PassManager::default_pipeline(),
+    };
+    pass_manager.set_threads(args.jobs);
+
+    let input = fs::read_to_string(&args.input)
+        .map_err(|err| format!("failed to read `{}`: {err}", args.input))?;
+    let mut parsed = match sonatina_parser::parse_module(&input) {
+        Ok(parsed) => parsed,
+        Err(errs) => {
+            for err in &errs {
+                eprintln!("{}", err.print_to_string(&args.input, &input, true));
+            }
+            return Err(format!("failed to parse `{}`", args.input));
+        }
+    };
+
+    pass_manager.run(&mut parsed.module);
+    let options = evm::CompileOptions {
+        metadata: args
+            .metadata
+            .then(|| evm::metadata::Metadata::default().with_ipfs_hash_of(input.as_bytes())),
+        split_code: args.split_code,
+    };
+    let artifact = evm::compile_with(&parsed.module, &options).map_err(|err| err.to_string())?;
+    for warning in &artifact.stack_warnings {
+        eprintln!("warning: {warning}");
+    }
+
+    let stem = Path::new(&args.input)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("out");
+    let out_dir = Path::new(&args.out_dir);
+    fs::create_dir_all(out_dir)
+        .map_err(|err| format!("failed to create `{}`: {err}", args.out_dir))?;
+
+    let mut artifacts = vec![
+        ("bin", to_hex(&artifact.deploy)),
+        ("bin-runtime", to_hex(&artifact.runtime)),
+        ("evm-asm", artifact.asm),
+        ("abi.json", artifact.abi),
+        ("debug.json", artifact.debug_info.to_json()),
+        ("srcmap-runtime", artifact.source_map),
+    ];
+    if let Some(split) = artifact.split {
+        for decision in &split.moved {
+            eprintln!(
+                "note: moved `{}` ({} bytes, heat {}) to the companion contract",
+                decision.func, decision.size, decision.heat
+            );
+        }
+        artifacts.extend([
+            ("companion.bin", to_hex(&split.companion.deploy)),
+            ("companion.bin-runtime", to_hex(&split.companion.runtime)),
+            ("companion.abi.json", split.companion.abi),
+        ]);
+    }
+    for (ext, contents) in artifacts {
+        let path = out_dir.join(format!("{stem}.{ext}"));
+        fs::write(&path, contents)
+            .map_err(|err| format!("failed to write `{}`: {err}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn to_hex(code: &[u8]) -> String {
+    code.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").unwrap();
+        hex
+    })
+}
@@ -0,0 +1,310 @@
+//! This module contains a generator of random functions for fuzzing passes, which turns
+//! unstructured bytes, e.g., from a fuzzer, into a well-typed function.
+//!
+//! The body is built from structured statements, i.e., straight-line code, `if`s and loops with a
+//! bounded trip count, so every generated function terminates and every block ends with a
+//! terminator. Values live in variables, which the SSA builder turns into phis where control flow
+//! merges, so every use is dominated by its definition.
+//!
+//! The same bytes always give the same function, so a pass can be checked by generating a
+//! function twice, running the pass on one of them, and comparing the two in the interpreter, see
+//! [`crate::interpret`].
+use arbitrary::{Result, Unstructured};
+use sonatina_ir::{
+    builder::{FunctionBuilder, ModuleBuilder, Variable},
+    func_cursor::InsnInserter,
+    insn::{BinaryOp, CastOp, UnaryOp},
+    isa::IsaBuilder,
+    module::ModuleCtx,
+    DataLocationKind, Immediate, Linkage, Module, Signature, Type, Value, I256, U256,
+};
+use sonatina_triple::TargetTriple;
+
+/// The name of the generated function.
+pub const FUNC_NAME: &str = "fuzz";
+
+const TYPES: [Type; 7] = [
+    Type::I1,
+    Type::I8,
+    Type::I16,
+    Type::I32,
+    Type::I64,
+    Type::I128,
+    Type::I256,
+];
+
+#[derive(Debug, Clone)]
+pub struct GenConfig {
+    /// The maximum number of statements in the body, counting nested ones.
+    pub max_stmts: usize,
+    /// The maximum number of insns in a straight-line statement.
+    pub max_insns: usize,
+    /// The maximum nesting depth of `if`s and loops.
+    pub max_depth: usize,
+    pub max_args: usize,
+    /// The maximum trip count of a loop. No loops are generated if it's zero.
+    pub max_trip_count: u8,
+    /// Whether values are stored to and loaded from memory.
+    pub memory: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        Self {
+            max_stmts: 16,
+            max_insns: 8,
+            max_depth: 3,
+            max_args: 4,
+            max_trip_count: 8,
+            memory: true,
+        }
+    }
+}
+
+/// Generates a module that defines the public function [`FUNC_NAME`].
+pub fn arbitrary_module(u: &mut Unstructured, config: &GenConfig) -> Result<Module> {
+    let triple = TargetTriple::parse("evm-ethereum-london").unwrap();
+    let mut mb = ModuleBuilder::new(ModuleCtx::new(IsaBuilder::new(triple).build()));
+
+    let arg_num = u.int_in_range(0..=config.max_args)?;
+    let args = (0..arg_num)
+        .map(|_| u.choose(&TYPES).copied())
+        .collect::<Result<Vec<_>>>()?;
+    let ret_ty = *u.choose(&TYPES)?;
+    let func_ref = mb.declare_function(Signature::new(FUNC_NAME, Linkage::Public, &args, ret_ty));
+
+    let mut gen = FuncGen {
+        u,
+        config,
+        builder: mb.build_function(func_ref),
+        vars: Vec::new(),
+        slots: Vec::new(),
+        stmt_num: 0,
+    };
+    gen.body(ret_ty)?;
+    Ok(gen.builder.finish().build())
+}
+
+/// Generates arguments for `sig`.
+pub fn arbitrary_args(u: &mut Unstructured, sig: &Signature) -> Result<Vec<Immediate>> {
+    sig.args().iter().map(|ty| arbitrary_imm(u, *ty)).collect()
+}
+
+/// Generates an immediate of `ty`, which is at the edge of the type more often than not.
+pub fn arbitrary_imm(u: &mut Unstructured, ty: Type) -> Result<Immediate> {
+    let val = match u.int_in_range(0..=4u8)? {
+        0 => I256::zero(),
+        1 => I256::one(),
+        2 => I256::all_one(),
+        3 => {
+            let min = Immediate::one(ty)
+                << Immediate::from_i256((Immediate::zero(ty).bit_width() - 1).into(), ty);
+            if u.arbitrary()? {
+                min.as_i256()
+            } else {
+                (!min).as_i256()
+            }
+        }
+        _ => I256::from_u256(U256(u.arbitrary()?)),
+    };
+    Ok(Immediate::from_i256(val, ty))
+}
+
+struct FuncGen<'a, 'b> {
+    u: &'a mut Unstructured<'b>,
+    config: &'a GenConfig,
+    builder: FunctionBuilder<InsnInserter>,
+    /// The variables computations read and write, at least one of each type.
+    vars: Vec<(Variable, Type)>,
+    /// Memory slots, one of each type, if memory is enabled.
+    slots: Vec<(Value, Type)>,
+    stmt_num: usize,
+}
+
+impl FuncGen<'_, '_> {
+    fn body(&mut self, ret_ty: Type) -> Result<()> {
+        let entry = self.builder.append_block();
+        self.builder.switch_to_block(entry);
+        self.builder.seal_block();
+
+        let args = self.builder.args().to_vec();
+        for arg in args {
+            let ty = self.builder.type_of(arg);
+            let var = self.builder.declare_var(ty);
+            self.builder.def_var(var, arg);
+            self.vars.push((var, ty));
+        }
+        for ty in TYPES {
+            let var = self.builder.declare_var(ty);
+            let init = self.imm(ty)?;
+            self.builder.def_var(var, init);
+            self.vars.push((var, ty));
+
+            if self.config.memory {
+                // Slots are initialized, since memory isn't zeroed on every target.
+                let slot = self.builder.alloca(ty);
+                let init = self.imm(ty)?;
+                self.builder.memory_store(slot, init);
+                self.slots.push((slot, ty));
+            }
+        }
+
+        self.stmts(0)?;
+
+        let ret = self.operand(ret_ty)?;
+        self.builder.ret(Some(ret));
+        Ok(())
+    }
+
+    fn stmts(&mut self, depth: usize) -> Result<()> {
+        while self.stmt_num < self.config.max_stmts && self.u.arbitrary()? {
+            self.stmt_num += 1;
+            let nests = depth < self.config.max_depth;
+            match self.u.int_in_range(0..=3u8)? {
+                0 if nests => self.if_stmt(depth)?,
+                1 if nests && self.config.max_trip_count > 0 => self.loop_stmt(depth)?,
+                _ => {
+                    for _ in 0..self.u.int_in_range(1..=self.config.max_insns)? {
+                        self.insn()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn if_stmt(&mut self, depth: usize) -> Result<()> {
+        let cond = self.operand(Type::I1)?;
+        let then = self.builder.append_block();
+        let else_ = self.builder.append_block();
+        let merge = self.builder.append_block();
+        self.builder.br(cond, then, else_);
+
+        for block in [then, else_] {
+            self.builder.switch_to_block(block);
+            self.builder.seal_block();
+            self.stmts(depth + 1)?;
+            self.builder.jump(merge);
+        }
+
+        self.builder.switch_to_block(merge);
+        self.builder.seal_block();
+        Ok(())
+    }
+
+    fn loop_stmt(&mut self, depth: usize) -> Result<()> {
+        let trip_count = self.u.int_in_range(0..=self.config.max_trip_count)?;
+        // The counter isn't one of `vars`, so the body can't change it.
+        let counter = self.builder.declare_var(Type::I8);
+        let zero = self.builder.make_imm_value(0i8);
+        self.builder.def_var(counter, zero);
+
+        let header = self.builder.append_block();
+        let body = self.builder.append_block();
+        let exit = self.builder.append_block();
+        self.builder.jump(header);
+
+        self.builder.switch_to_block(header);
+        let count = self.builder.use_var(counter);
+        let trip_count = self.builder.make_imm_value(trip_count as i8);
+        let cond = self.builder.lt(count, trip_count);
+        self.builder.br(cond, body, exit);
+
+        self.builder.switch_to_block(body);
+        self.builder.seal_block();
+        self.stmts(depth + 1)?;
+        let count = self.builder.use_var(counter);
+        let one = self.builder.make_imm_value(1i8);
+        let next = self.builder.add(count, one);
+        self.builder.def_var(counter, next);
+        self.builder.jump(header);
+
+        self.builder.switch_to_block(header);
+        self.builder.seal_block();
+        self.builder.switch_to_block(exit);
+        self.builder.seal_block();
+        Ok(())
+    }
+
+    /// Generates an insn whose result is assigned to a variable, or a store.
+    fn insn(&mut self) -> Result<()> {
+        let ty = *self.u.choose(&TYPES)?;
+        let kinds = if self.config.memory { 4 } else { 2 };
+        let result = match self.u.int_in_range(0..=kinds)? {
+            0 => {
+                let op = *self.u.choose(&BinaryOp::ALL)?;
+                let lhs = self.operand(ty)?;
+                let rhs = self.operand(ty)?;
+                self.builder.binary_op(op, lhs, rhs)
+            }
+            1 => {
+                let op = *self.u.choose(&UnaryOp::ALL)?;
+                let arg = self.operand(ty)?;
+                self.builder.unary_op(op, arg)
+            }
+            2 => {
+                let to = *self.u.choose(&TYPES)?;
+                let arg = self.operand(ty)?;
+                let op = if ty < to {
+                    if self.u.arbitrary()? {
+                        CastOp::Sext
+                    } else {
+                        CastOp::Zext
+                    }
+                } else if ty > to {
+                    CastOp::Trunc
+                } else {
+                    return Ok(());
+                };
+                self.builder.cast_op(op, arg, to)
+            }
+            3 => {
+                let slot = self.slot(ty);
+                let data = self.operand(ty)?;
+                self.builder.store(DataLocationKind::Memory, slot, data);
+                return Ok(());
+            }
+            _ => {
+                let slot = self.slot(ty);
+                self.builder.load(DataLocationKind::Memory, slot)
+            }
+        };
+
+        let result_ty = self.builder.type_of(result);
+        let var = self.var(result_ty)?;
+        self.builder.def_var(var, result);
+        Ok(())
+    }
+
+    /// Returns an immediate or the value of a variable of `ty`.
+    fn operand(&mut self, ty: Type) -> Result<Value> {
+        if self.u.ratio(1, 4)? {
+            return self.imm(ty);
+        }
+        let var = self.var(ty)?;
+        Ok(self.builder.use_var(var))
+    }
+
+    fn imm(&mut self, ty: Type) -> Result<Value> {
+        let imm = arbitrary_imm(self.u, ty)?;
+        Ok(self.builder.make_imm_value(imm))
+    }
+
+    fn var(&mut self, ty: Type) -> Result<Variable> {
+        let vars: Vec<_> = self
+            .vars
+            .iter()
+            .filter(|(_, var_ty)| *var_ty == ty)
+            .map(|(var, _)| *var)
+            .collect();
+        Ok(*self.u.choose(&vars)?)
+    }
+
+    fn slot(&self, ty: Type) -> Value {
+        self.slots
+            .iter()
+            .find(|(_, slot_ty)| *slot_ty == ty)
+            .map(|(slot, _)| *slot)
+            .unwrap()
+    }
+}
@@ -0,0 +1,306 @@
+//! A harness that tests the EVM backend against the interpreter.
+//!
+//! A module is compiled into a contract whose runtime code is deployed on revm. Each call of an
+//! external function then runs both on revm and in the interpreter, starting from empty storage,
+//! and the two must agree on the result and on the storage the call leaves behind.
+//!
+//! The interpreter defines the semantics of the IR, so inputs it can't execute, e.g., because
+//! they divide by zero, are skipped: the backend is free to do anything on them.
+pub mod gen;
+pub mod validate;
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use revm::{
+    db::{CacheDB, EmptyDB},
+    primitives::{
+        AccountInfo, Address, Bytecode, ExecutionResult, Output, SpecId, TransactTo,
+        U256 as EvmWord,
+    },
+    EVM,
+};
+use sonatina_codegen::isa::evm::{self, abi, EvmCodegenError};
+use sonatina_interpreter::{EvalResult, State};
+use sonatina_ir::{module::FuncRef, CallConv, Immediate, Module, Type, I256, U256};
+use sonatina_triple::{EvmVersion, Version};
+
+/// The number of steps the interpreter runs a call for before giving up on it.
+const FUEL: u64 = 1 << 20;
+
+const GAS_LIMIT: u64 = 30_000_000;
+
+pub struct DiffTest {
+    module: Arc<Module>,
+    runtime: Vec<u8>,
+    spec_id: SpecId,
+}
+
+#[derive(Debug)]
+pub enum DiffTestError {
+    Compile(EvmCodegenError),
+    /// The interpreter doesn't run static initializers, which the contract runs on every call.
+    StaticInits,
+}
+
+impl fmt::Display for DiffTestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Compile(err) => write!(f, "failed to compile the module: {err}"),
+            Self::StaticInits => write!(f, "modules with static initializers are not supported"),
+        }
+    }
+}
+
+impl std::error::Error for DiffTestError {}
+
+/// The effects of a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The returned data, or `None` if the call failed.
+    pub output: Option<Vec<u8>>,
+    /// The non-zero storage slots after the call.
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// A call on which revm and the interpreter disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub func: String,
+    pub args: Vec<Immediate>,
+    pub interpreter: Outcome,
+    pub evm: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` disagrees on (", self.func)?;
+        for (idx, arg) in self.args.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        write!(
+            f,
+            ")\n  interpreter: {:?}\n  evm:         {:?}",
+            self.interpreter, self.evm
+        )
+    }
+}
+
+impl DiffTest {
+    pub fn new(module: Module) -> Result<Self, DiffTestError> {
+        if !module.static_inits.is_empty() {
+            return Err(DiffTestError::StaticInits);
+        }
+        let artifact = evm::compile(&module).map_err(DiffTestError::Compile)?;
+        let Version::EvmVersion(version) = module.ctx.isa.triple().version else {
+            unreachable!("the module compiled for the EVM");
+        };
+
+        Ok(Self {
+            module: Arc::new(module),
+            runtime: artifact.runtime,
+            spec_id: spec_id(version),
+        })
+    }
+
+    /// Returns the external functions of the contract.
+    pub fn external_funcs(&self) -> Vec<FuncRef> {
+        self.module
+            .iter_functions()
+            .filter(|func_ref| {
+                let func = &self.module.funcs[*func_ref];
+                func.sig.call_conv() == CallConv::SolidityExternal
+                    && func.layout.entry_block().is_some()
+            })
+            .collect()
+    }
+
+    /// Calls `func` with `args` on both sides. Returns `Ok(false)` if the interpreter can't execute
+    /// the call, so the outcomes aren't compared.
+    pub fn check(&self, func: FuncRef, args: &[Immediate]) -> Result<bool, Box<Mismatch>> {
+        let Some(interpreter) = interpret(self.module.clone(), func, args) else {
+            return Ok(false);
+        };
+        let evm = self.run_evm(func, args);
+        if interpreter == evm {
+            return Ok(true);
+        }
+
+        Err(Box::new(Mismatch {
+            func: self.module.funcs[func].sig.name().to_string(),
+            args: args.to_vec(),
+            interpreter,
+            evm,
+        }))
+    }
+
+    /// Calls `func` with `cases` sets of arguments generated from `seed`, and returns the number of
+    /// calls that were compared, see [`Self::check`].
+    pub fn check_random(
+        &self,
+        func: FuncRef,
+        cases: usize,
+        seed: u64,
+    ) -> Result<usize, Box<Mismatch>> {
+        let mut rng = SplitMix64(seed);
+        let arg_tys = self.module.funcs[func].sig.args().to_vec();
+        let mut compared = 0;
+        for _ in 0..cases {
+            let args: Vec<_> = arg_tys.iter().map(|ty| rng.imm(*ty)).collect();
+            if self.check(func, &args)? {
+                compared += 1;
+            }
+        }
+        Ok(compared)
+    }
+
+    fn run_evm(&self, func: FuncRef, args: &[Immediate]) -> Outcome {
+        let sig = &self.module.funcs[func].sig;
+        let mut calldata = abi::function_selector(&self.module.ctx, sig)
+            .unwrap()
+            .to_vec();
+        for arg in args {
+            calldata.extend_from_slice(&be_bytes(arg.as_unsigned()));
+        }
+
+        let address = Address::with_last_byte(0xaa);
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            address,
+            AccountInfo {
+                code: Some(Bytecode::new_raw(self.runtime.clone().into())),
+                ..Default::default()
+            },
+        );
+
+        let mut evm = EVM::new();
+        evm.database(db);
+        evm.env.cfg.spec_id = self.spec_id;
+        evm.env.block.gas_limit = EvmWord::from(GAS_LIMIT);
+        evm.env.tx.gas_limit = GAS_LIMIT;
+        evm.env.tx.transact_to = TransactTo::Call(address);
+        evm.env.tx.data = calldata.into();
+
+        let output = match evm
+            .transact_commit()
+            .expect("the transaction must be valid")
+        {
+            ExecutionResult::Success {
+                output: Output::Call(data),
+                ..
+            } => Some(data.to_vec()),
+            _ => None,
+        };
+        let storage = evm.db().unwrap().accounts[&address]
+            .storage
+            .iter()
+            .map(|(key, value)| (from_evm_word(*key), from_evm_word(*value)))
+            .filter(|(_, value)| !value.is_zero())
+            .collect();
+        Outcome { output, storage }
+    }
+}
+
+/// Calls `func` with `args` in the interpreter, starting from empty storage. Returns `None` if
+/// the interpreter can't execute the call, e.g., because it divides by zero or doesn't return.
+pub fn interpret(module: Arc<Module>, func: FuncRef, args: &[Immediate]) -> Option<Outcome> {
+    let mut state = State::with_imm_args(module, func, args);
+    let mut result = None;
+    for _ in 0..FUEL {
+        if let Some(ret) = state.try_step().ok()? {
+            result = Some(ret);
+            break;
+        }
+    }
+
+    let output = match result? {
+        EvalResult::Void => Vec::new(),
+        ret => be_bytes(result_imm(ret).as_unsigned()).to_vec(),
+    };
+    let storage = state
+        .storage()
+        .iter()
+        .map(|(key, value)| (key.to_u256(), value.to_u256()))
+        .filter(|(_, value)| !value.is_zero())
+        .collect();
+    Some(Outcome {
+        output: Some(output),
+        storage,
+    })
+}
+
+fn spec_id(version: EvmVersion) -> SpecId {
+    match version {
+        EvmVersion::Frontier => SpecId::FRONTIER,
+        EvmVersion::Homestead => SpecId::HOMESTEAD,
+        EvmVersion::Byzantium => SpecId::BYZANTIUM,
+        EvmVersion::Constantinople => SpecId::CONSTANTINOPLE,
+        EvmVersion::Istanbul => SpecId::ISTANBUL,
+        EvmVersion::London => SpecId::LONDON,
+        EvmVersion::Shanghai => SpecId::SHANGHAI,
+        EvmVersion::Cancun => SpecId::CANCUN,
+    }
+}
+
+fn result_imm(result: EvalResult) -> Immediate {
+    match result {
+        EvalResult::I1(val) => Immediate::I1(val),
+        EvalResult::I8(val) => Immediate::I8(val),
+        EvalResult::I16(val) => Immediate::I16(val),
+        EvalResult::I32(val) => Immediate::I32(val),
+        EvalResult::I64(val) => Immediate::I64(val),
+        EvalResult::I128(val) => Immediate::I128(val),
+        EvalResult::I256(val) => Immediate::I256(val),
+        EvalResult::Void | EvalResult::Addr(_) => {
+            unreachable!("external functions return integers")
+        }
+    }
+}
+
+fn be_bytes(word: U256) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    word.to_big_endian(&mut bytes);
+    bytes
+}
+
+fn from_evm_word(word: EvmWord) -> U256 {
+    U256::from_big_endian(&word.to_be_bytes::<32>())
+}
+
+/// The generator of arguments, which favors values at the edges of their types.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn imm(&mut self, ty: Type) -> Immediate {
+        let val = match self.next() % 8 {
+            0 => I256::zero(),
+            1 => I256::one(),
+            2 => I256::all_one(),
+            // The minimum and maximum signed values.
+            3 | 4 => {
+                let min = Immediate::one(ty)
+                    << Immediate::from_i256((Immediate::zero(ty).bit_width() - 1).into(), ty);
+                if self.next() % 2 == 0 {
+                    min.as_i256()
+                } else {
+                    (!min).as_i256()
+                }
+            }
+            _ => {
+                let words = [self.next(), self.next(), self.next(), self.next()];
+                I256::from_u256(U256(words))
+            }
+        };
+        Immediate::from_i256(val, ty)
+    }
+}
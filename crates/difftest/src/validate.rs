@@ -0,0 +1,167 @@
+//! This module contains a translation validator, which checks each pass of a pipeline by running
+//! the functions of the module in the interpreter before and after the pass.
+//!
+//! Every function with a body and integral arguments is called on generated arguments, and the
+//! outcome after the pass must be the outcome before it. Calls the interpreter can't execute on
+//! the input of the pass are skipped, since a pass may do anything with them.
+//!
+//! A divergence is reported with minimized arguments, which are found by shrinking one argument at
+//! a time towards zero while the divergence persists.
+//!
+//! The module before a pass is a [deep copy](sonatina_ir::module::ModuleCtx::deep_clone) of it,
+//! so a pass that rewrites the initializers of global variables is checked against the original
+//! ones.
+use std::{fmt, sync::Arc};
+
+use sonatina_codegen::pass_manager::{Pass, PassManager};
+use sonatina_ir::{module::FuncRef, Immediate, Module, Type};
+
+use crate::{interpret, Outcome, SplitMix64};
+
+pub struct TranslationValidator {
+    cases: usize,
+    seed: u64,
+}
+
+/// A call on which a pass changed the outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub pass: Pass,
+    pub func: String,
+    pub args: Vec<Immediate>,
+    pub before: Outcome,
+    /// The outcome after the pass, or `None` if the interpreter can't execute the call anymore.
+    pub after: Option<Outcome>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` changes `{}` on (", self.pass, self.func)?;
+        for (idx, arg) in self.args.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{arg}")?;
+        }
+        write!(
+            f,
+            ")\n  before: {:?}\n  after:  {:?}",
+            self.before, self.after
+        )
+    }
+}
+
+impl TranslationValidator {
+    /// Returns a validator that calls each function with `cases` sets of arguments generated
+    /// from `seed`.
+    pub fn new(cases: usize, seed: u64) -> Self {
+        Self { cases, seed }
+    }
+
+    /// Runs the pipeline of `pm` on `module` one pass at a time, and checks each pass.
+    pub fn run(&self, pm: &PassManager, module: &mut Module) -> Result<(), Box<Divergence>> {
+        for pass in pm.passes() {
+            let before = Arc::new(clone_module(module));
+            PassManager::new().add_pass(*pass).run(module);
+            let after = Arc::new(clone_module(module));
+            self.check(*pass, &before, &after)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `after`, which `pass` made from `before`, computes the same outcomes.
+    pub fn check(
+        &self,
+        pass: Pass,
+        before: &Arc<Module>,
+        after: &Arc<Module>,
+    ) -> Result<(), Box<Divergence>> {
+        let mut rng = SplitMix64(self.seed);
+        for func in before.iter_functions() {
+            if !is_checkable(before, func) || !is_checkable(after, func) {
+                continue;
+            }
+
+            let arg_tys = before.funcs[func].sig.args().to_vec();
+            for _ in 0..self.cases {
+                let args: Vec<_> = arg_tys.iter().map(|ty| rng.imm(*ty)).collect();
+                if diverges(before, after, func, &args) {
+                    let args = minimize(args, |args| diverges(before, after, func, args));
+                    return Err(Box::new(Divergence {
+                        pass,
+                        func: before.funcs[func].sig.name().to_string(),
+                        before: interpret(before.clone(), func, &args).unwrap(),
+                        after: interpret(after.clone(), func, &args),
+                        args,
+                    }));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a copy of `module` that shares no stores with it, so that passes on `module` leave the
+/// copy as it is.
+fn clone_module(module: &Module) -> Module {
+    let ctx = module.ctx.deep_clone();
+    let mut funcs = module.funcs.clone();
+    for func in funcs.values_mut() {
+        func.dfg.ctx = ctx.clone();
+    }
+
+    Module {
+        funcs,
+        ctx,
+        static_inits: module.static_inits.clone(),
+        contracts: module.contracts.clone(),
+        removed_funcs: module.removed_funcs.clone(),
+    }
+}
+
+/// Returns `true` if `func` has a body and takes and returns only integers.
+fn is_checkable(module: &Module, func: FuncRef) -> bool {
+    let Some(func) = module.funcs.get(func) else {
+        return false;
+    };
+    let ret_ty = func.sig.ret_ty();
+    func.layout.entry_block().is_some()
+        && func.sig.args().iter().all(Type::is_integral)
+        && (ret_ty.is_integral() || ret_ty == Type::Void)
+}
+
+fn diverges(before: &Arc<Module>, after: &Arc<Module>, func: FuncRef, args: &[Immediate]) -> bool {
+    match interpret(before.clone(), func, args) {
+        Some(outcome) => interpret(after.clone(), func, args) != Some(outcome),
+        None => false,
+    }
+}
+
+/// Shrinks each argument towards zero as long as `diverges` holds.
+fn minimize(mut args: Vec<Immediate>, diverges: impl Fn(&[Immediate]) -> bool) -> Vec<Immediate> {
+    for idx in 0..args.len() {
+        loop {
+            let arg = args[idx];
+            let ty = arg.ty();
+            let smaller = [
+                Immediate::zero(ty),
+                Immediate::one(ty),
+                arg >> Immediate::one(ty),
+            ]
+            .into_iter()
+            .filter(|candidate| candidate.as_unsigned() < arg.as_unsigned())
+            .find(|candidate| {
+                args[idx] = *candidate;
+                let found = diverges(&args);
+                args[idx] = arg;
+                found
+            });
+
+            match smaller {
+                Some(candidate) => args[idx] = candidate,
+                None => break,
+            }
+        }
+    }
+    args
+}
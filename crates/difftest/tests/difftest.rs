@@ -0,0 +1,34 @@
+//! Runs each external function of each fixture on random inputs, both on revm and in the
+//! interpreter.
+
+use dir_test::{dir_test, Fixture};
+use sonatina_difftest::DiffTest;
+
+const CASES: usize = 64;
+
+#[dir_test(
+    dir: "$CARGO_MANIFEST_DIR/test_files",
+    glob: "*.sntn"
+)]
+fn difftest(fixture: Fixture<&str>) {
+    let module = match sonatina_parser::parse_module(fixture.content()) {
+        Ok(pm) => pm.module,
+        Err(errs) => {
+            for err in errs {
+                eprintln!(
+                    "{}",
+                    err.print_to_string(fixture.path(), fixture.content(), true)
+                );
+            }
+            panic!("parsing failed");
+        }
+    };
+
+    let difftest = DiffTest::new(module).unwrap();
+    for (seed, func_ref) in difftest.external_funcs().into_iter().enumerate() {
+        match difftest.check_random(func_ref, CASES, seed as u64) {
+            Ok(compared) => assert!(compared > 0, "{}: no input was compared", fixture.path()),
+            Err(mismatch) => panic!("{}: {mismatch}", fixture.path()),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Fuzzes the optimization pipeline with random functions, each of which must compute the same
+//! outcome in the interpreter before and after optimization.
+
+use std::sync::Arc;
+
+use arbitrary::Unstructured;
+use sonatina_codegen::{
+    domtree::DomTree,
+    loop_analysis::LoopTree,
+    optim::{
+        adce::AdceSolver, cse::CseSolver, gvn::GvnSolver, insn_simplify::InsnSimplifySolver,
+        licm::LicmSolver, sccp::SccpSolver,
+    },
+};
+use sonatina_difftest::{
+    gen::{arbitrary_args, arbitrary_module, GenConfig},
+    interpret,
+};
+use sonatina_ir::{ControlFlowGraph, Module};
+
+const SEEDS: u64 = 256;
+const BYTES: usize = 4096;
+const CASES: usize = 8;
+
+#[test]
+fn optimize_preserves_semantics() {
+    let config = GenConfig::default();
+    for seed in 0..SEEDS {
+        let bytes = random_bytes(seed);
+        let mut u = Unstructured::new(&bytes);
+        let Ok(module) = arbitrary_module(&mut u, &config) else {
+            continue;
+        };
+        let mut optimized = arbitrary_module(&mut Unstructured::new(&bytes), &config).unwrap();
+        optimize(&mut optimized);
+
+        let func_ref = module.iter_functions().next().unwrap();
+        let sig = module.funcs[func_ref].sig.clone();
+        let (module, optimized) = (Arc::new(module), Arc::new(optimized));
+        for _ in 0..CASES {
+            let Ok(args) = arbitrary_args(&mut u, &sig) else {
+                break;
+            };
+            let Some(expected) = interpret(module.clone(), func_ref, &args) else {
+                continue;
+            };
+            let actual = interpret(optimized.clone(), func_ref, &args);
+            assert_eq!(
+                Some(expected),
+                actual,
+                "seed {seed}: the optimized function disagrees on {args:?}"
+            );
+        }
+    }
+}
+
+fn optimize(module: &mut Module) {
+    let func_refs: Vec<_> = module.iter_functions().collect();
+    for func_ref in func_refs {
+        let func = &mut module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        let mut domtree = DomTree::new();
+        let mut lpt = LoopTree::new();
+
+        cfg.compute(func);
+        SccpSolver::new().run(func, &mut cfg);
+        AdceSolver::new().run(func);
+        InsnSimplifySolver::new().run(func);
+
+        cfg.compute(func);
+        domtree.compute(&cfg);
+        CseSolver::new().run(func, &domtree);
+        GvnSolver::new().run(func, &mut cfg, &mut domtree);
+
+        cfg.compute(func);
+        domtree.compute(&cfg);
+        lpt.compute(&cfg, &domtree);
+        LicmSolver::new().run(func, &mut cfg, &mut lpt);
+    }
+}
+
+/// Returns bytes generated from `seed` by xorshift.
+fn random_bytes(seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..BYTES)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
@@ -0,0 +1,76 @@
+//! Tests of the translation validator.
+
+use std::sync::Arc;
+
+use arbitrary::Unstructured;
+use sonatina_codegen::pass_manager::{Pass, PassManager};
+use sonatina_difftest::{
+    gen::{arbitrary_module, GenConfig},
+    validate::TranslationValidator,
+};
+use sonatina_ir::{Immediate, Module};
+
+const CASES: usize = 16;
+
+#[test]
+fn default_pipeline() {
+    let validator = TranslationValidator::new(CASES, 0);
+    let pm = PassManager::default_pipeline();
+    for seed in 0..64u64 {
+        let bytes: Vec<u8> = (0..2048u64)
+            .map(|i| (seed.wrapping_mul(31) ^ i.wrapping_mul(0x9e37_79b9)) as u8)
+            .collect();
+        let Ok(mut module) =
+            arbitrary_module(&mut Unstructured::new(&bytes), &GenConfig::default())
+        else {
+            continue;
+        };
+        if let Err(divergence) = validator.run(&pm, &mut module) {
+            panic!("seed {seed}: {divergence}");
+        }
+    }
+}
+
+#[test]
+fn minimized_counterexample() {
+    let before = parse(
+        "target = \"evm-ethereum-london\"
+
+func public %clamp(v0.i32) -> i32 {
+    block0:
+        v1.i1 = lt v0 100.i32;
+        br v1 block1 block2;
+
+    block1:
+        return v0;
+
+    block2:
+        return 0.i32;
+}",
+    );
+    // A miscompilation that drops the clamp.
+    let after = parse(
+        "target = \"evm-ethereum-london\"
+
+func public %clamp(v0.i32) -> i32 {
+    block0:
+        return v0;
+}",
+    );
+
+    let validator = TranslationValidator::new(64, 0);
+    let divergence = validator
+        .check(Pass::Sccp, &Arc::new(before), &Arc::new(after))
+        .unwrap_err();
+    assert_eq!(divergence.pass, Pass::Sccp);
+    assert_eq!(divergence.func, "clamp");
+    let [Immediate::I32(arg)] = divergence.args[..] else {
+        panic!("unexpected arguments {:?}", divergence.args);
+    };
+    // Halving the argument any further would make it pass the clamp.
+    assert!((100..200).contains(&(arg as u32)), "{arg} is not minimal");
+}
+
+fn parse(input: &str) -> Module {
+    sonatina_parser::parse_module(input).unwrap().module
+}
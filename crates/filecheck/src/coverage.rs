@@ -0,0 +1,120 @@
+//! This module contains the coverage of the fixtures of each transform, i.e., which insns and
+//! which integer and pointer types appear in the functions a transform is tested on.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+
+use sonatina_ir::{Function, InsnData, Type};
+
+/// The types coverage is reported for.
+const TYPE_NAMES: [&str; 8] = ["i1", "i8", "i16", "i32", "i64", "i128", "i256", "ptr"];
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    /// Coverage of each transform, keyed by the name of its fixture directory.
+    transforms: BTreeMap<String, TransformCoverage>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the insns and types in `func`, an input of the `transform` tests.
+    pub fn record(&mut self, transform: &str, func: &Function) {
+        let cov = self.transforms.entry(transform.to_string()).or_default();
+        for block in func.layout.iter_block() {
+            for insn in func.layout.iter_insn(block) {
+                let insn_data = func.dfg.insn_data(insn);
+                cov.insns.insert(insn_data.mnemonic());
+
+                let results = func.dfg.insn_results(insn);
+                for &value in insn_data.args().iter().chain(results) {
+                    if let Some(name) = type_name(func, func.dfg.value_ty(value)) {
+                        cov.types.insert(name);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the coverage recorded in `other`.
+    pub fn merge(&mut self, other: Coverage) {
+        for (transform, other) in other.transforms {
+            let cov = self.transforms.entry(transform).or_default();
+            cov.insns.extend(other.insns);
+            cov.types.extend(other.types);
+        }
+    }
+
+    /// Returns the transforms whose insn coverage is below `threshold` percent.
+    pub fn below(&self, threshold: f64) -> impl Iterator<Item = &str> {
+        self.transforms
+            .iter()
+            .filter(move |(_, cov)| cov.insn_percent() < threshold)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn write_summary(&self, mut w: impl Write) -> io::Result<()> {
+        let all_insns: Vec<_> = InsnData::all_mnemonics().collect();
+        writeln!(w, "coverage summary:")?;
+        for (name, cov) in &self.transforms {
+            writeln!(
+                w,
+                "  {name}: insns {}/{} ({:.1}%), types {}/{}",
+                cov.insns.len(),
+                all_insns.len(),
+                cov.insn_percent(),
+                cov.types.len(),
+                TYPE_NAMES.len(),
+            )?;
+
+            let missing_insns: Vec<_> = all_insns
+                .iter()
+                .filter(|insn| !cov.insns.contains(*insn))
+                .copied()
+                .collect();
+            if !missing_insns.is_empty() {
+                writeln!(w, "    missing insns: {}", missing_insns.join(", "))?;
+            }
+
+            let missing_types: Vec<_> = TYPE_NAMES
+                .iter()
+                .filter(|ty| !cov.types.contains(*ty))
+                .copied()
+                .collect();
+            if !missing_types.is_empty() {
+                writeln!(w, "    missing types: {}", missing_types.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct TransformCoverage {
+    insns: BTreeSet<&'static str>,
+    types: BTreeSet<&'static str>,
+}
+
+impl TransformCoverage {
+    fn insn_percent(&self) -> f64 {
+        let all = InsnData::all_mnemonics().count();
+        self.insns.len() as f64 * 100.0 / all as f64
+    }
+}
+
+fn type_name(func: &Function, ty: Type) -> Option<&'static str> {
+    Some(match ty {
+        Type::I1 => "i1",
+        Type::I8 => "i8",
+        Type::I16 => "i16",
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::I128 => "i128",
+        Type::I256 => "i256",
+        Type::Compound(_) if func.dfg.ctx.with_ty_store(|s| s.is_ptr(ty)) => "ptr",
+        Type::Compound(_) | Type::Void => return None,
+    })
+}
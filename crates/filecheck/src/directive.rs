@@ -0,0 +1,95 @@
+//! This module translates LLVM style directives into the directives of the `filecheck` crate, so
+//! that fixtures can use either style.
+//!
+//! | LLVM style          | `filecheck`       |
+//! |---------------------|-------------------|
+//! | `CHECK:`            | `check:`          |
+//! | `CHECK-NEXT:`       | `nextln:`         |
+//! | `CHECK-SAME:`       | `sameln:`         |
+//! | `CHECK-NOT:`        | `not:`            |
+//! | `CHECK-DAG:`        | `unordered:`      |
+//! | `[[VAR:regex]]`     | `$(VAR=regex)`    |
+//! | `[[VAR]]`           | `$(VAR)`          |
+//! | `{{regex}}`         | `$(=regex)`       |
+//!
+//! A `$` in the pattern of an LLVM style directive matches itself.
+
+const DIRECTIVES: [(&str, &str); 5] = [
+    ("CHECK:", "check:"),
+    ("CHECK-NEXT:", "nextln:"),
+    ("CHECK-SAME:", "sameln:"),
+    ("CHECK-NOT:", "not:"),
+    ("CHECK-DAG:", "unordered:"),
+];
+
+/// The directives of the `filecheck` crate.
+const NATIVE_DIRECTIVES: [&str; 6] = [
+    "check:",
+    "sameln:",
+    "nextln:",
+    "unordered:",
+    "not:",
+    "regex:",
+];
+
+/// Returns `true` if `comment` is a directive of either style.
+pub fn is_directive(comment: &str) -> bool {
+    let translated = translate(comment);
+    let text = translated.trim_start_matches('#').trim_start();
+    NATIVE_DIRECTIVES
+        .iter()
+        .any(|directive| text.starts_with(directive))
+}
+
+/// Returns the pattern of `comment` if it's a directive that matches a line of the output, i.e.,
+/// `check:` or `nextln:` in either style.
+pub fn line_pattern(comment: &str) -> Option<String> {
+    let translated = translate(comment);
+    let text = translated.trim_start_matches('#').trim_start();
+    ["check:", "nextln:"]
+        .iter()
+        .find_map(|directive| text.strip_prefix(directive))
+        .map(str::to_string)
+}
+
+/// Returns `comment` with an LLVM style directive translated, or `comment` as is if it doesn't
+/// contain one. The leading `#` of the comment is optional.
+pub fn translate(comment: &str) -> String {
+    let trimmed = comment.trim_start_matches('#').trim_start();
+    for (llvm, native) in DIRECTIVES {
+        if let Some(pattern) = trimmed.strip_prefix(llvm) {
+            return format!("{native}{}", translate_pattern(pattern));
+        }
+    }
+    comment.to_string()
+}
+
+fn translate_pattern(mut pattern: &str) -> String {
+    let mut translated = String::with_capacity(pattern.len());
+    while !pattern.is_empty() {
+        if let Some((var, rest)) = split_enclosed(pattern, "[[", "]]") {
+            match var.split_once(':') {
+                Some((name, regex)) => translated.push_str(&format!("$({name}={regex})")),
+                None => translated.push_str(&format!("$({var})")),
+            }
+            pattern = rest;
+        } else if let Some((regex, rest)) = split_enclosed(pattern, "{{", "}}") {
+            translated.push_str(&format!("$(={regex})"));
+            pattern = rest;
+        } else {
+            let c = pattern.chars().next().unwrap();
+            if c == '$' {
+                translated.push('$');
+            }
+            translated.push(c);
+            pattern = &pattern[c.len_utf8()..];
+        }
+    }
+    translated
+}
+
+/// Splits `s` into what's enclosed by `open` and `close` at its start, and the rest.
+fn split_enclosed<'a>(s: &'a str, open: &str, close: &str) -> Option<(&'a str, &'a str)> {
+    let (enclosed, rest) = s.strip_prefix(open)?.split_once(close)?;
+    Some((enclosed, rest))
+}
@@ -1,51 +1,97 @@
-pub mod adce;
-pub mod gvn;
-pub mod insn_simplify;
-pub mod licm;
-pub mod sccp;
+//! This crate runs the fixtures under `fixtures` through a pass pipeline and checks the output of
+//! each function against the directives in its comments.
+//!
+//! A fixture declares its pipeline with a `RUN:` directive in the module comments, e.g.,
+//!
+//! ```text
+//! #! RUN: sccp,adce
+//! ```
+//!
+//! A fixture without one runs the pass its directory is named after, e.g., `insn-simplify` for
+//! the fixtures under `insn_simplify`.
+//!
+//! In [update](FileCheckRunner::enable_update) mode, the directives of each function are rewritten
+//! to match its actual output instead.
+//!
+//! With more than one [thread](FileCheckRunner::set_threads), fixtures are checked concurrently.
+//! Each fixture is isolated from the others, so a pass that panics on one fails only the fixture.
+pub mod coverage;
+pub mod directive;
+mod report;
+mod update;
 
 use std::{
+    collections::BTreeMap,
     fs,
     io::{self, Write},
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
-    time,
+    thread, time,
 };
 
-use sonatina_ir::{ir_writer::FuncWriter, module::FuncRef, Function};
+use sonatina_codegen::pass_manager::PassManager;
+use sonatina_ir::{ir_writer::FuncWriter, module::FuncRef};
 
 use sonatina_parser::{parse_module, ParsedModule};
+
+use coverage::Coverage;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use walkdir::WalkDir;
 
 pub(crate) const FIXTURE_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
 
-pub trait FuncTransform {
-    fn transform(&mut self, func: &mut Function);
-
-    fn test_root(&self) -> PathBuf;
-}
+const RUN_DIRECTIVE: &str = "RUN:";
 
 pub struct FileCheckRunner {
-    transformer: Box<dyn FuncTransform>,
     results: Vec<FileCheckResult>,
+    coverage: Coverage,
     timer: time::Instant,
+    update: bool,
+    updated: Vec<PathBuf>,
+    threads: NonZeroUsize,
 }
 
-impl FileCheckRunner {
-    pub fn new(transformer: impl FuncTransform + 'static) -> Self {
+impl Default for FileCheckRunner {
+    fn default() -> Self {
         Self {
-            transformer: Box::new(transformer),
             results: Vec::new(),
+            coverage: Coverage::new(),
             timer: time::Instant::now(),
+            update: false,
+            updated: Vec::new(),
+            threads: NonZeroUsize::MIN,
         }
     }
+}
+
+impl FileCheckRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rewrites the directives of the fixtures to match the actual output in subsequent runs,
+    /// instead of checking the output against them.
+    pub fn enable_update(&mut self) -> &mut Self {
+        self.update = true;
+        self
+    }
 
-    pub fn attach_transformer(&mut self, transformer: impl FuncTransform + 'static) {
-        self.transformer = Box::new(transformer);
+    /// Returns the fixtures rewritten so far in update mode.
+    pub fn updated(&self) -> &[PathBuf] {
+        &self.updated
     }
 
+    /// Sets the number of threads fixtures are checked on. Defaults to 1.
+    pub fn set_threads(&mut self, threads: NonZeroUsize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Runs all fixtures.
     pub fn run(&mut self) {
-        for ent in WalkDir::new(self.transformer.test_root())
+        let paths: Vec<_> = WalkDir::new(FIXTURE_ROOT)
+            .sort_by_file_name()
             .into_iter()
             .filter_map(|e| match e {
                 Ok(ent) => {
@@ -59,9 +105,41 @@ impl FileCheckRunner {
                 }
                 _ => None,
             })
-        {
-            let mut checker = FileChecker::new(self.transformer.as_mut(), ent.path());
-            self.results.extend(checker.check());
+            .map(|ent| ent.into_path())
+            .collect();
+
+        let update = self.update;
+        let threads = self.threads.get().min(paths.len());
+        let outcomes: Vec<_> = if threads <= 1 {
+            paths.iter().map(|path| check_file(path, update)).collect()
+        } else {
+            let chunk_size = paths.len().div_ceil(threads);
+            thread::scope(|scope| {
+                let handles: Vec<_> = paths
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|path| check_file(path, update))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        };
+
+        // Chunks are joined in order, so the results are in the order of the paths.
+        for (path, outcome) in paths.into_iter().zip(outcomes) {
+            self.results.extend(outcome.results);
+            self.coverage.merge(outcome.coverage);
+            if outcome.updated {
+                self.updated.push(path);
+            }
         }
     }
 
@@ -101,6 +179,51 @@ impl FileCheckRunner {
             elapsed.subsec_millis() / 10,
         )
         .unwrap();
+
+        for path in &self.updated {
+            writeln!(stdout, "updated {}", path.display()).unwrap();
+        }
+    }
+
+    /// Writes the results as JSON, e.g.,
+    ///
+    /// ```json
+    /// {"passed":1,"failed":1,"tests":[
+    ///   {"name":"sccp::simple::f","ok":true},
+    ///   {"name":"sccp::simple::g","ok":false,"error":"...","diff":"..."}
+    /// ]}
+    /// ```
+    pub fn write_json(&self, mut w: impl Write) -> io::Result<()> {
+        let tests: Vec<_> = self
+            .results
+            .iter()
+            .map(|res| {
+                let mut test = serde_json::json!({
+                    "name": res.name(),
+                    "ok": res.is_ok(),
+                });
+                if let Err(err) = &res.result {
+                    test["error"] = err.as_str().into();
+                }
+                if let Some(diff) = &res.diff {
+                    test["diff"] = diff.as_str().into();
+                }
+                test
+            })
+            .collect();
+
+        let failed_num = self.failed_num();
+        let json = serde_json::json!({
+            "passed": self.results.len() - failed_num,
+            "failed": failed_num,
+            "tests": tests,
+        });
+        writeln!(w, "{json}")
+    }
+
+    /// Returns the insns and types exercised by the fixtures of each transform run so far.
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
     }
 
     pub fn failed_num(&self) -> usize {
@@ -112,68 +235,180 @@ impl FileCheckRunner {
     }
 }
 
+/// The results of a fixture.
+struct FileOutcome {
+    results: Vec<FileCheckResult>,
+    coverage: Coverage,
+    updated: bool,
+}
+
+fn check_file(path: &Path, update: bool) -> FileOutcome {
+    let mut coverage = Coverage::new();
+    let mut checker = FileChecker::new(path, &mut coverage, update);
+    let results = match panic::catch_unwind(AssertUnwindSafe(|| checker.check())) {
+        Ok(results) => results,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            vec![FileCheckResult::new(
+                path.to_owned(),
+                Err(format!("panicked: {msg}")),
+            )]
+        }
+    };
+    let updated = checker.updated;
+
+    FileOutcome {
+        results,
+        coverage,
+        updated,
+    }
+}
+
 pub struct FileChecker<'a> {
-    transformer: &'a mut dyn FuncTransform,
     file_path: &'a Path,
+    coverage: &'a mut Coverage,
+    update: bool,
+    updated: bool,
 }
 
 impl<'a> FileChecker<'a> {
-    fn new(transformer: &'a mut dyn FuncTransform, file_path: &'a Path) -> Self {
+    fn new(file_path: &'a Path, coverage: &'a mut Coverage, update: bool) -> Self {
         Self {
-            transformer,
             file_path,
+            coverage,
+            update,
+            updated: false,
         }
     }
 
     fn check(&mut self) -> Vec<FileCheckResult> {
-        let mut parsed_module = match self.parse_file() {
+        let input = fs::read_to_string(self.file_path).unwrap();
+        let mut parsed_module = match self.parse_file(&input) {
             Ok(module) => module,
             Err(msg) => return vec![FileCheckResult::new(self.file_path.to_owned(), Err(msg))],
         };
+        let mut pass_manager = match self.pipeline(&parsed_module.debug.module_comments) {
+            Ok(pass_manager) => pass_manager,
+            Err(msg) => return vec![FileCheckResult::new(self.file_path.to_owned(), Err(msg))],
+        };
 
-        let module = &parsed_module.module;
+        let dir = self.dir_name();
+        for func in parsed_module.module.funcs.values() {
+            self.coverage.record(&dir, func);
+        }
+        pass_manager.run(&mut parsed_module.module);
+
+        if self.update {
+            return self.update_file(&input, &parsed_module);
+        }
 
+        // Declarations have no output to check.
+        let module = &parsed_module.module;
         module
             .iter_functions()
-            .map(|func_ref| self.check_func(&mut parsed_module, func_ref))
+            .filter(|func_ref| module.funcs[*func_ref].layout.entry_block().is_some())
+            .map(|func_ref| self.check_func(&parsed_module, func_ref))
             .collect()
     }
 
-    fn check_func(
-        &mut self,
-        parsed_module: &mut ParsedModule,
-        func_ref: FuncRef,
-    ) -> FileCheckResult {
-        let func = &mut parsed_module.module.funcs[func_ref];
+    fn check_func(&self, parsed_module: &ParsedModule, func_ref: FuncRef) -> FileCheckResult {
+        let func = &parsed_module.module.funcs[func_ref];
         let comments = &parsed_module.debug.func_comments[func_ref];
+        let func_ir = dump_func(parsed_module, func_ref);
 
-        self.transformer.transform(func);
-        let func_ir = FuncWriter::new(func_ref, func, Some(&parsed_module.debug))
-            .dump_string()
-            .unwrap();
+        let result =
+            self.build_checker(comments)
+                .and_then(|checker| match checker.explain(&func_ir, &()) {
+                    Ok((true, _)) => Ok(()),
+                    Ok((false, err)) => Err(err),
+                    Err(err) => Err(format!("{}", err)),
+                });
 
-        let checker = self.build_checker(comments);
+        let mut test_path = self.file_path.to_owned();
+        test_path.push(func.sig.name());
+        let mut res = FileCheckResult::new(test_path, result);
+        if !res.is_ok() {
+            let expected: Vec<_> = comments
+                .iter()
+                .filter_map(|comment| directive::line_pattern(comment))
+                .collect();
+            res.diff = Some(report::diff(&expected, &func_ir));
+        }
+        res
+    }
 
-        let result = match checker.explain(&func_ir, &()) {
-            Ok((true, _)) => Ok(()),
-            Ok((false, err)) => Err(err),
-            Err(err) => Err(format!("{}", err)),
+    /// Rewrites the directives of the functions with a body to match their output.
+    fn update_file(&mut self, input: &str, parsed_module: &ParsedModule) -> Vec<FileCheckResult> {
+        let module = &parsed_module.module;
+        let func_refs: Vec<_> = module
+            .iter_functions()
+            .filter(|func_ref| module.funcs[*func_ref].layout.entry_block().is_some())
+            .collect();
+        let outputs: BTreeMap<_, _> = func_refs
+            .iter()
+            .map(|func_ref| {
+                let name = module.funcs[*func_ref].sig.name().to_string();
+                (name, dump_func(parsed_module, *func_ref))
+            })
+            .collect();
+
+        let rewritten = update::rewrite(input, &outputs);
+        let result = if rewritten == input {
+            Ok(())
+        } else {
+            self.updated = true;
+            fs::write(self.file_path, rewritten).map_err(|err| err.to_string())
         };
 
-        let mut test_path = self.file_path.to_owned();
-        test_path.push(func.sig.name());
-        FileCheckResult::new(test_path, result)
+        func_refs
+            .into_iter()
+            .map(|func_ref| {
+                let mut test_path = self.file_path.to_owned();
+                test_path.push(module.funcs[func_ref].sig.name());
+                FileCheckResult::new(test_path, result.clone())
+            })
+            .collect()
     }
 
-    fn parse_file(&self) -> Result<ParsedModule, String> {
-        let input = fs::read_to_string(self.file_path).unwrap();
+    /// Returns the pipeline declared by the `RUN:` directive in `module_comments`, or the pass the
+    /// directory of the fixture is named after.
+    fn pipeline(&self, module_comments: &[String]) -> Result<PassManager, String> {
+        let pipeline = module_comments
+            .iter()
+            .find_map(|comment| {
+                comment
+                    .trim_start_matches("#!")
+                    .trim()
+                    .strip_prefix(RUN_DIRECTIVE)
+                    .map(str::to_string)
+            })
+            .unwrap_or_else(|| self.dir_name().replace('_', "-"));
+
+        PassManager::parse_pipeline(&pipeline).map_err(|err| format!("invalid pipeline: {err}"))
+    }
 
-        match parse_module(&input) {
+    /// Returns the name of the directory under the fixture root that contains the fixture.
+    fn dir_name(&self) -> String {
+        let path = self.file_path.strip_prefix(FIXTURE_ROOT).unwrap();
+        path.components()
+            .next()
+            .unwrap()
+            .as_os_str()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn parse_file(&self, input: &str) -> Result<ParsedModule, String> {
+        match parse_module(input) {
             Ok(module) => Ok(module),
             Err(errs) => {
                 let mut v = vec![];
                 for e in errs {
-                    e.print(&mut v, self.file_path.to_str().unwrap(), &input, true)
+                    e.print(&mut v, self.file_path.to_str().unwrap(), input, true)
                         .unwrap()
                 }
                 Err(String::from_utf8(v).unwrap())
@@ -181,37 +416,60 @@ impl<'a> FileChecker<'a> {
         }
     }
 
-    fn build_checker(&self, directives: &[String]) -> filecheck::Checker {
+    /// Builds the checker of the directives of a function. Fails if there are none, since the
+    /// function would pass whatever its output.
+    fn build_checker(&self, directives: &[String]) -> Result<filecheck::Checker, String> {
         let mut builder = filecheck::CheckerBuilder::new();
-        for d in directives {
-            if !builder.directive(d).unwrap() && d.contains("nextln") {
-                panic!("not a directive: `{}`", d);
+        let mut directive_num = 0;
+        for d in directives.iter().map(|d| directive::translate(d)) {
+            if builder.directive(&d).map_err(|err| err.to_string())? {
+                directive_num += 1;
+            } else if d.contains("nextln") {
+                return Err(format!("not a directive: `{d}`"));
             }
         }
-        builder.finish()
+
+        if directive_num == 0 {
+            return Err("no directives".to_string());
+        }
+        Ok(builder.finish())
     }
 }
 
+fn dump_func(parsed_module: &ParsedModule, func_ref: FuncRef) -> String {
+    let func = &parsed_module.module.funcs[func_ref];
+    FuncWriter::new(func_ref, func, Some(&parsed_module.debug))
+        .dump_string()
+        .unwrap()
+}
+
 #[derive(Debug)]
 pub struct FileCheckResult {
     path: PathBuf,
     result: Result<(), String>,
+    /// The diff from the lines the directives expect to the actual output, if the check failed.
+    diff: Option<String>,
 }
 
 impl FileCheckResult {
     fn new(path: PathBuf, result: Result<(), String>) -> Self {
-        Self { path, result }
+        Self {
+            path,
+            result,
+            diff: None,
+        }
     }
 
-    fn print_result(&self, stdout: &mut StandardStream) -> io::Result<()> {
+    /// Returns the name of the test, e.g., `sccp::simple_sccp::simple_fold`.
+    fn name(&self) -> String {
         let path = self.path.strip_prefix(FIXTURE_ROOT).unwrap();
-        write!(
-            stdout,
-            "test {} ...",
-            path.to_string_lossy()
-                .replace('/', "::")
-                .replace(".sntn", "")
-        )?;
+        path.to_string_lossy()
+            .replace('/', "::")
+            .replace(".sntn", "")
+    }
+
+    fn print_result(&self, stdout: &mut StandardStream) -> io::Result<()> {
+        write!(stdout, "test {} ...", self.name())?;
         match &self.result {
             Ok(()) => {
                 stdout.set_color(ColorSpec::new().set_fg(Color::Green.into()))?;
@@ -223,6 +481,9 @@ impl FileCheckResult {
                 writeln!(stdout, " FAILED")?;
                 stdout.reset()?;
                 writeln!(stdout, "{}", err)?;
+                if let Some(diff) = self.diff.as_deref().filter(|diff| !diff.is_empty()) {
+                    writeln!(stdout, "diff of expected and actual lines:\n{diff}")?;
+                }
             }
         }
         Ok(())
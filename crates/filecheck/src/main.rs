@@ -1,26 +1,121 @@
-use sonatina_filecheck::{
-    adce::AdceTransform, gvn::GvnTransform, insn_simplify::InsnSimplifyTransform,
-    licm::LicmTransformer, sccp::SccpTransform, FileCheckRunner,
-};
+//! `sonatina-filecheck` runs every fixture through its pass pipeline and checks the output.
+//!
+//! With `--coverage`, a summary of the insns and types exercised by the fixtures of each transform
+//! is printed. With `--min-coverage PERCENT`, the run fails if the fixtures of a transform cover
+//! fewer insns than `PERCENT`. With `--update`, the check directives of the fixtures are rewritten
+//! to match the actual output, so that a change to the output can be reviewed as a diff of them.
+//!
+//! With `--json`, the results are printed as JSON for CI, and the coverage summary goes to stderr.
 
-fn main() {
-    let mut runner = FileCheckRunner::new(SccpTransform::default());
-    runner.run();
+use std::{io, num::NonZeroUsize, process};
 
-    runner.attach_transformer(AdceTransform::default());
-    runner.run();
+use sonatina_filecheck::FileCheckRunner;
 
-    runner.attach_transformer(InsnSimplifyTransform::default());
-    runner.run();
+const USAGE: &str = "\
+Usage: sonatina-filecheck [OPTIONS]
 
-    runner.attach_transformer(GvnTransform::default());
-    runner.run();
+Options:
+      --coverage                Print the insns and types covered by the fixtures of each transform
+      --min-coverage <PERCENT>  Fail if the fixtures of a transform cover fewer insns than PERCENT
+      --update                  Rewrite the check directives to match the actual output
+  -j, --jobs <N>                Number of threads fixtures are checked on [default: 1]
+      --json                    Print the results as JSON
+  -h, --help                    Print this message";
 
-    runner.attach_transformer(LicmTransformer::default());
+struct Args {
+    coverage: bool,
+    min_coverage: Option<f64>,
+    update: bool,
+    jobs: NonZeroUsize,
+    json: bool,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut coverage = false;
+        let mut min_coverage = None;
+        let mut update = false;
+        let mut jobs = NonZeroUsize::MIN;
+        let mut json = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--coverage" => coverage = true,
+                "--min-coverage" => {
+                    let percent = args.next().ok_or("missing value for `--min-coverage`")?;
+                    min_coverage = Some(
+                        percent
+                            .parse()
+                            .map_err(|_| format!("invalid coverage `{percent}`"))?,
+                    );
+                }
+                "--update" => update = true,
+                "-j" | "--jobs" => {
+                    let n = args.next().ok_or("missing value for `--jobs`")?;
+                    jobs = n
+                        .parse()
+                        .map_err(|_| format!("invalid number of jobs `{n}`"))?;
+                }
+                "--json" => json = true,
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+
+        Ok(Self {
+            coverage,
+            min_coverage,
+            update,
+            jobs,
+            json,
+        })
+    }
+}
+
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    let mut runner = FileCheckRunner::new();
+    runner.set_threads(args.jobs);
+    if args.update {
+        runner.enable_update();
+    }
     runner.run();
 
-    runner.print_results();
+    if args.json {
+        runner.write_json(io::stdout()).unwrap();
+    } else {
+        runner.print_results();
+    }
+    if args.coverage || args.min_coverage.is_some() {
+        if args.json {
+            runner.coverage().write_summary(io::stderr()).unwrap();
+        } else {
+            runner.coverage().write_summary(io::stdout()).unwrap();
+        }
+    }
     if !runner.is_ok() {
-        std::process::exit(101);
+        process::exit(101);
+    }
+
+    if let Some(threshold) = args.min_coverage {
+        let below: Vec<_> = runner.coverage().below(threshold).collect();
+        if !below.is_empty() {
+            eprintln!(
+                "error: insn coverage is below {threshold}% for {}",
+                below.join(", ")
+            );
+            process::exit(1);
+        }
     }
 }
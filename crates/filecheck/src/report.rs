@@ -0,0 +1,55 @@
+//! This module contains the report of a failed function, which shows the lines the directives
+//! expect next to the actual output.
+
+/// Returns a line diff from `expected` to `actual`, where removed lines are prefixed with `-` and
+/// added ones with `+`. Output before the first and after the last expected line is left out, and
+/// whitespace is ignored.
+///
+/// Patterns that use variables or regexes are compared as they're written, so they show up as
+/// changed even if they match.
+pub(crate) fn diff(expected: &[String], actual: &str) -> String {
+    let actual: Vec<_> = actual.lines().collect();
+    let (n, m) = (expected.len(), actual.len());
+
+    // `lcs[i][j]` is the length of the longest common subsequence of `expected[i..]` and
+    // `actual[j..]`.
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if same(&expected[i], actual[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && same(&expected[i], actual[j]) {
+            lines.push(format!("  {}", actual[j]));
+            (i, j) = (i + 1, j + 1);
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("- {}", expected[i].trim()));
+            i += 1;
+        }
+    }
+
+    let start = lines
+        .iter()
+        .position(|line| !line.starts_with('+'))
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|line| !line.starts_with('+'))
+        .map_or(start, |idx| idx + 1);
+    lines[start..end].join("\n")
+}
+
+fn same(pattern: &str, line: &str) -> bool {
+    pattern.split_whitespace().eq(line.split_whitespace())
+}
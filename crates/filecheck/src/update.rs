@@ -0,0 +1,65 @@
+//! This module rewrites the directives of a fixture to match the actual output of its functions,
+//! which keeps fixtures maintainable when the output changes across the board, e.g., when the IR
+//! printer changes.
+use std::collections::BTreeMap;
+
+use crate::directive;
+
+/// Returns `source` with the directives above each function in `outputs`, which maps function
+/// names to their output, replaced by directives that match the output line by line. Other
+/// comments above the functions are kept.
+pub(crate) fn rewrite(source: &str, outputs: &BTreeMap<String, String>) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for line in source.lines() {
+        if let Some(output) = func_name(line).and_then(|name| outputs.get(name)) {
+            let mut comments = Vec::new();
+            while lines.last().is_some_and(|line| is_func_comment(line)) {
+                comments.push(lines.pop().unwrap());
+            }
+            comments.reverse();
+            lines.extend(
+                comments
+                    .into_iter()
+                    .filter(|comment| !directive::is_directive(comment)),
+            );
+            lines.extend(directives(output));
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut rewritten = lines.join("\n");
+    if source.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    rewritten
+}
+
+/// Returns the directives that match the body of a function in `output`.
+fn directives(output: &str) -> Vec<String> {
+    let mut body: Vec<_> = output.lines().skip(1).collect();
+    while body
+        .last()
+        .is_some_and(|line| line.trim().is_empty() || line.trim() == "}")
+    {
+        body.pop();
+    }
+
+    body.iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            let kind = if idx == 0 { "check" } else { "nextln" };
+            format!("# {kind}: {line}")
+        })
+        .collect()
+}
+
+/// Returns the name of the function defined on `line`, if any.
+fn func_name(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("func ")?;
+    let name = &rest[rest.find('%')? + 1..];
+    Some(&name[..name.find('(')?])
+}
+
+fn is_func_comment(line: &str) -> bool {
+    line.starts_with('#') && !line.starts_with("#!")
+}
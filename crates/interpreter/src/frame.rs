@@ -2,13 +2,12 @@ use cranelift_entity::{packed_option::PackedOption, SecondaryMap};
 
 use sonatina_ir::{module::ModuleCtx, DataFlowGraph, Type, Value, I256};
 
-use crate::{types, EvalValue, ProgramCounter};
+use crate::{types, EvalValue, Memory, ProgramCounter};
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Frame {
     pub ret_addr: PackedOption<ProgramCounter>,
     local_values: SecondaryMap<Value, EvalValue>, // 256-bit register
-    alloca_region: Vec<u8>,                       // big endian
 }
 
 impl Frame {
@@ -26,53 +25,55 @@ impl Frame {
         }
     }
 
-    pub fn load(&mut self, v: Value, dfg: &DataFlowGraph) -> I256 {
+    /// Returns the literal of `v`. The literal of a global value is the address of the global
+    /// variable in `memory`.
+    pub fn load(&mut self, v: Value, dfg: &DataFlowGraph, memory: &Memory) -> I256 {
         if !self.is_assigned(v) {
-            if let Some(gv) = dfg.value_gv(v) {
-                dfg.ctx.with_gv_store(|s| {
-                    if !s.is_const(gv) {
-                        todo!()
-                    }
-                })
-            }
-            let i256 = dfg.value_imm(v).unwrap().as_i256();
+            let i256 = match dfg.value_gv(v) {
+                Some(gv) => memory.gv_addr(gv).into(),
+                None => dfg.value_imm(v).unwrap().as_i256(),
+            };
             self.local_values[v] = EvalValue::from_i256(i256);
         }
         self.local_values[v].i256()
     }
 
+    /// Assign `literal` to `v`. Values defined in a loop are reassigned in every iteration.
     pub fn map(&mut self, literal: I256, v: Value) {
-        debug_assert!(!self.is_assigned(v));
         self.local_values[v] = EvalValue::from_i256(literal)
     }
 
-    pub fn alloca(&mut self, ctx: &ModuleCtx, ty: Type, v: Value) {
+    pub fn alloca(&mut self, ctx: &ModuleCtx, ty: Type, v: Value, memory: &mut Memory) {
         debug_assert!(!self.is_assigned(v));
 
-        let addr = self.alloca_region.len();
-
-        let size = types::size_of_ty_data(ctx, ty);
-        self.alloca_region.resize(addr + size, 0);
+        let addr = memory.alloca(types::size_of_ty_data(ctx, ty));
         self.local_values[v] = EvalValue::from_usize(addr);
     }
 
-    pub fn ldr(&mut self, ctx: &ModuleCtx, addr: I256, v: Value, ty: Type) {
-        let addr = addr.to_u256().as_usize();
-        debug_assert!(addr < self.alloca_region.len());
-
-        let size = types::size_of_ty_data(ctx, ty);
-        let literal_b = &self.alloca_region[addr..addr + size];
-        let Some(data) = EvalValue::deserialize(ctx, ty, literal_b) else {
-            return;
-        };
-        self.map(data.i256(), v);
+    /// Loads the value of `ty` at `addr` into `v`. Returns `None` if `addr` is invalid.
+    pub fn ldr(
+        &mut self,
+        ctx: &ModuleCtx,
+        addr: I256,
+        v: Value,
+        ty: Type,
+        memory: &Memory,
+    ) -> Option<()> {
+        let data = memory.load(ctx, addr, ty)?;
+        self.map(data, v);
+        Some(())
     }
 
-    pub fn str(&mut self, ctx: &ModuleCtx, addr: I256, data: I256, ty: Type) {
-        let addr = addr.to_u256().as_usize();
-        let size = types::size_of_ty_data(ctx, ty);
-        let reg_value = EvalValue::from_i256(data);
-        reg_value.serialize(ctx, ty, &mut self.alloca_region[addr..size]);
+    /// Stores `data` as a value of `ty` at `addr`. Returns `None` if `addr` is invalid.
+    pub fn str(
+        &mut self,
+        ctx: &ModuleCtx,
+        addr: I256,
+        data: I256,
+        ty: Type,
+        memory: &mut Memory,
+    ) -> Option<()> {
+        memory.store(ctx, addr, data, ty)
     }
 
     pub fn is_assigned(&self, v: Value) -> bool {
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use sonatina_ir::{module::FuncRef, Block};
+
+/// Gas consumed by a concrete execution, measured with the cost table of the target ISA.
+///
+/// The gas of a function or a block is exclusive, i.e., gas consumed in callees is attributed to
+/// the callees.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GasReport {
+    total: u64,
+    per_func: BTreeMap<FuncRef, u64>,
+    per_block: BTreeMap<(FuncRef, Block), u64>,
+}
+
+impl GasReport {
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn func_gas(&self, func: FuncRef) -> u64 {
+        self.per_func.get(&func).copied().unwrap_or_default()
+    }
+
+    pub fn block_gas(&self, func: FuncRef, block: Block) -> u64 {
+        self.per_block
+            .get(&(func, block))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Iterates over functions that consumed gas in the order of `FuncRef`.
+    pub fn iter_funcs(&self) -> impl Iterator<Item = (FuncRef, u64)> + '_ {
+        self.per_func.iter().map(|(func, gas)| (*func, *gas))
+    }
+
+    /// Iterates over blocks that consumed gas in the order of `FuncRef` then `Block`.
+    pub fn iter_blocks(&self) -> impl Iterator<Item = (FuncRef, Block, u64)> + '_ {
+        self.per_block
+            .iter()
+            .map(|((func, block), gas)| (*func, *block, *gas))
+    }
+
+    pub(crate) fn charge(&mut self, func: FuncRef, block: Block, gas: u64) {
+        self.total += gas;
+        *self.per_func.entry(func).or_default() += gas;
+        *self.per_block.entry((func, block)).or_default() += gas;
+    }
+}
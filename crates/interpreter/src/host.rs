@@ -0,0 +1,182 @@
+//! This module contains the interface between an execution and the EVM it runs in, which lets
+//! contract logic be tested without a node.
+//!
+//! Storage insns go through [`EvmHost::sload`] and [`EvmHost::sstore`], with narrow keys and
+//! values zero-extended to words. A call to an external function is served by the host if the
+//! function is named after a Yul builtin it implements, and takes the same arguments as the
+//! builtin, e.g.,
+//!
+//! ```text
+//! declare external %keccak256(*i8, i256) -> i256;
+//! declare external %log1(*i8, i256, i256);
+//! ```
+use std::collections::BTreeMap;
+
+use sonatina_ir::{I256, U256};
+use tiny_keccak::{Hasher, Keccak};
+
+use crate::{EvalError, Memory, ProgramCounter};
+
+pub trait EvmHost {
+    fn sload(&mut self, key: I256) -> I256;
+
+    fn sstore(&mut self, key: I256, value: I256);
+
+    fn balance(&mut self, address: U256) -> U256;
+
+    fn call(&mut self, input: CallInput) -> CallOutput;
+
+    fn log(&mut self, log: Log);
+
+    fn block(&self) -> BlockContext;
+
+    fn keccak256(&mut self, data: &[u8]) -> U256 {
+        let mut hash = [0; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(data);
+        hasher.finalize(&mut hash);
+        U256::from_big_endian(&hash)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallInput {
+    pub callee: U256,
+    pub value: U256,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOutput {
+    pub success: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    pub topics: Vec<U256>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockContext {
+    pub number: U256,
+    pub timestamp: U256,
+    pub coinbase: U256,
+    pub chain_id: U256,
+    pub base_fee: U256,
+    pub gas_limit: U256,
+}
+
+/// A host that keeps the world in memory. Calls succeed with the output set for the callee, or
+/// with no output, and are recorded along with logs.
+#[derive(Debug, Clone, Default)]
+pub struct MockHost {
+    pub storage: BTreeMap<I256, I256>,
+    pub balances: BTreeMap<U256, U256>,
+    pub call_outputs: BTreeMap<U256, CallOutput>,
+    pub calls: Vec<CallInput>,
+    pub logs: Vec<Log>,
+    pub block: BlockContext,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EvmHost for MockHost {
+    fn sload(&mut self, key: I256) -> I256 {
+        self.storage.get(&key).copied().unwrap_or_else(I256::zero)
+    }
+
+    fn sstore(&mut self, key: I256, value: I256) {
+        self.storage.insert(key, value);
+    }
+
+    fn balance(&mut self, address: U256) -> U256 {
+        self.balances.get(&address).copied().unwrap_or_default()
+    }
+
+    fn call(&mut self, input: CallInput) -> CallOutput {
+        let output = self
+            .call_outputs
+            .get(&input.callee)
+            .cloned()
+            .unwrap_or(CallOutput {
+                success: true,
+                data: Vec::new(),
+            });
+        self.calls.push(input);
+        output
+    }
+
+    fn log(&mut self, log: Log) {
+        self.logs.push(log);
+    }
+
+    fn block(&self) -> BlockContext {
+        self.block
+    }
+}
+
+/// Runs the builtin `name` with `args` on `host`, and returns its result, which is zero for
+/// builtins without one. Returns `Ok(None)` if there is no such builtin, and fails if the builtin
+/// accesses memory out of range for the insn at `pc`.
+pub(crate) fn call_builtin(
+    host: &mut impl EvmHost,
+    memory: &mut Memory,
+    name: &str,
+    args: &[I256],
+    pc: ProgramCounter,
+) -> Result<Option<I256>, EvalError> {
+    let invalid_address = || EvalError::InvalidAddress(pc);
+    let args: Vec<U256> = args.iter().map(|arg| arg.to_u256()).collect();
+    let result = match (name, args.as_slice()) {
+        ("balance", [address]) => host.balance(*address),
+        ("keccak256", [offset, size]) => {
+            let data = memory.read(*offset, *size).ok_or_else(invalid_address)?;
+            host.keccak256(data)
+        }
+        ("call", [_gas, callee, value, in_offset, in_size, out_offset, out_size]) => {
+            let input = CallInput {
+                callee: *callee,
+                value: *value,
+                data: memory
+                    .read(*in_offset, *in_size)
+                    .ok_or_else(invalid_address)?
+                    .to_vec(),
+            };
+            let output = host.call(input);
+            let size = usize::try_from(*out_size).map_or(output.data.len(), |out_size| {
+                output.data.len().min(out_size)
+            });
+            memory
+                .write(*out_offset, &output.data[..size])
+                .ok_or_else(invalid_address)?;
+            U256::from(output.success as u8)
+        }
+        ("log0" | "log1" | "log2" | "log3" | "log4", [offset, size, topics @ ..])
+            if name[3..] == topics.len().to_string() =>
+        {
+            let log = Log {
+                topics: topics.to_vec(),
+                data: memory
+                    .read(*offset, *size)
+                    .ok_or_else(invalid_address)?
+                    .to_vec(),
+            };
+            host.log(log);
+            U256::zero()
+        }
+        ("number", []) => host.block().number,
+        ("timestamp", []) => host.block().timestamp,
+        ("coinbase", []) => host.block().coinbase,
+        ("chainid", []) => host.block().chain_id,
+        ("basefee", []) => host.block().base_fee,
+        ("gaslimit", []) => host.block().gas_limit,
+        _ => return Ok(None),
+    };
+    Ok(Some(result.into()))
+}
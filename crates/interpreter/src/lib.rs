@@ -1,10 +1,16 @@
 pub mod frame;
+pub mod gas;
+pub mod host;
+pub mod memory;
 pub mod pc;
 pub mod state;
 pub mod types;
 pub mod value;
 
 pub use frame::Frame;
+pub use gas::GasReport;
+pub use host::{EvmHost, MockHost};
+pub use memory::Memory;
 pub use pc::ProgramCounter;
-pub use state::State;
-pub use value::{EvalResult, EvalValue};
+pub use state::{Snapshot, State};
+pub use value::{EvalError, EvalResult, EvalValue};
@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+use cranelift_entity::SecondaryMap;
+use sonatina_ir::{
+    global_variable::ConstantValue, module::ModuleCtx, types::CompoundTypeData, GlobalVariable,
+    Type, I256, U256,
+};
+
+use crate::{types, EvalValue};
+
+/// The size of the reserved region at address 0, so that no object is at the null address, as
+/// the optimizer assumes when it folds comparisons of addresses with null.
+pub(crate) const NULL_REGION_SIZE: usize = 32;
+
+/// The memory of an execution, which is shared by all frames so that pointers stay valid across
+/// calls.
+///
+/// Global variables are laid out after the null region in the order they are declared, followed
+/// by the allocas of the frames. A call opens a new region for the allocas of the callee, which is
+/// freed when the callee returns.
+#[derive(Clone, Default)]
+pub struct Memory {
+    bytes: Vec<u8>, // big endian
+    gv_addrs: SecondaryMap<GlobalVariable, usize>,
+    frame_bases: Vec<usize>,
+}
+
+impl Memory {
+    /// Returns the memory with the global variables of `ctx` laid out and initialized.
+    pub fn new(ctx: &ModuleCtx) -> Self {
+        let mut memory = Self {
+            bytes: vec![0; NULL_REGION_SIZE],
+            ..Self::default()
+        };
+        let gvs: Vec<_> = ctx.with_gv_store(|s| {
+            s.all_gvs()
+                .map(|gv| (gv, s.ty(gv), s.init_data(gv).cloned()))
+                .collect()
+        });
+
+        for (gv, ty, data) in gvs {
+            let addr = memory.alloca(types::size_of_ty_data(ctx, ty));
+            memory.gv_addrs[gv] = addr;
+            if let Some(data) = data {
+                memory.write_const(ctx, addr, ty, &data);
+            }
+        }
+        memory
+    }
+
+    pub fn gv_addr(&self, gv: GlobalVariable) -> usize {
+        self.gv_addrs[gv]
+    }
+
+    /// Allocates `size` zeroed bytes in the region of the innermost frame and returns their
+    /// address.
+    pub fn alloca(&mut self, size: usize) -> usize {
+        let addr = self.bytes.len();
+        self.bytes.resize(addr + size, 0);
+        addr
+    }
+
+    pub fn push_frame(&mut self) {
+        self.frame_bases.push(self.bytes.len());
+    }
+
+    /// Frees the allocas of the innermost frame.
+    pub fn pop_frame(&mut self) {
+        let base = self.frame_bases.pop().unwrap();
+        self.bytes.truncate(base);
+    }
+
+    /// Returns the value of `ty` at `addr`, or `None` if `addr` isn't the address of one.
+    pub fn load(&self, ctx: &ModuleCtx, addr: I256, ty: Type) -> Option<I256> {
+        let size = types::size_of_ty_data(ctx, ty);
+        let range = self.range(addr.to_u256(), size.into())?;
+        EvalValue::deserialize(ctx, ty, &self.bytes[range]).map(|data| data.i256())
+    }
+
+    /// Stores `data` as a value of `ty` at `addr`. Returns `None` without changing memory if
+    /// `addr` isn't the address of a value of `ty`.
+    pub fn store(&mut self, ctx: &ModuleCtx, addr: I256, data: I256, ty: Type) -> Option<()> {
+        let size = types::size_of_ty_data(ctx, ty);
+        let range = self.range(addr.to_u256(), size.into())?;
+        EvalValue::from_i256(data).serialize(ctx, ty, &mut self.bytes[range]);
+        Some(())
+    }
+
+    /// Returns the `size` bytes at `addr`, or `None` if any of them is out of memory.
+    pub fn read(&self, addr: U256, size: U256) -> Option<&[u8]> {
+        let range = self.range(addr, size)?;
+        Some(&self.bytes[range])
+    }
+
+    /// Writes `bytes` at `addr`. Returns `None` without changing memory if any of them is out of
+    /// memory.
+    pub fn write(&mut self, addr: U256, bytes: &[u8]) -> Option<()> {
+        let range = self.range(addr, bytes.len().into())?;
+        self.bytes[range].copy_from_slice(bytes);
+        Some(())
+    }
+
+    /// Sets the `size` bytes at `addr` to `byte`. Returns `None` without changing memory if any
+    /// of them is out of memory.
+    pub fn fill(&mut self, addr: U256, size: U256, byte: u8) -> Option<()> {
+        let range = self.range(addr, size)?;
+        self.bytes[range].fill(byte);
+        Some(())
+    }
+
+    /// Returns the range of the `size` bytes at `addr` if they are all allocated, i.e., they are
+    /// neither in the null region nor freed. An empty range is valid anywhere.
+    fn range(&self, addr: U256, size: U256) -> Option<Range<usize>> {
+        let size = usize::try_from(size).ok()?;
+        if size == 0 {
+            return Some(0..0);
+        }
+
+        let addr = usize::try_from(addr).ok()?;
+        let end = addr.checked_add(size)?;
+        (addr >= NULL_REGION_SIZE && end <= self.bytes.len()).then_some(addr..end)
+    }
+
+    fn write_const(&mut self, ctx: &ModuleCtx, addr: usize, ty: Type, data: &ConstantValue) {
+        let fields: Vec<Type> = match (data, ty) {
+            (ConstantValue::Immediate(imm), _) => {
+                let size = types::size_of_ty_data(ctx, ty);
+                EvalValue::from_i256(imm.as_i256()).serialize(
+                    ctx,
+                    ty,
+                    &mut self.bytes[addr..addr + size],
+                );
+                return;
+            }
+            (_, Type::Compound(cmpd_ty)) => {
+                ctx.with_ty_store(|s| match s.resolve_compound(cmpd_ty) {
+                    CompoundTypeData::Array { elem, len } => vec![*elem; *len],
+                    CompoundTypeData::Struct(data) => data.fields.clone(),
+                    _ => unreachable!(),
+                })
+            }
+            _ => unreachable!(),
+        };
+
+        let (ConstantValue::Array(elems) | ConstantValue::Struct(elems)) = data else {
+            unreachable!()
+        };
+        let mut offset = addr;
+        for (elem, elem_ty) in elems.iter().zip(fields) {
+            self.write_const(ctx, offset, elem_ty, elem);
+            offset += types::size_of_ty_data(ctx, elem_ty);
+        }
+    }
+}
@@ -1,40 +1,263 @@
-use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Not, Sub};
+use std::{
+    collections::BTreeMap,
+    ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr},
+    sync::Arc,
+};
 
 use sonatina_ir::{
-    insn::{BinaryOp, CastOp, UnaryOp},
+    insn::{BinaryOp, CastOp, MemOp, UnaryOp},
     module::FuncRef,
-    Block, DataLocationKind, Immediate, InsnData, Module, Value,
+    profile::Profile,
+    Block, DataLocationKind, Immediate, InsnData, Layout, Module, Type, Value, I256, U256,
+};
+
+use crate::{
+    host::{self, EvmHost, MockHost},
+    types, EvalError, EvalResult, Frame, GasReport, Memory, ProgramCounter,
 };
 
-use crate::{types, EvalResult, Frame, ProgramCounter};
+/// The most slots a storage `memcpy`, `memmove` or `memset` may span, so that a huge length fails
+/// instead of exhausting memory or time.
+const MAX_STORAGE_SLOTS: usize = 1 << 16;
 
-pub struct State {
-    module: Module,
+/// An execution of a function, which runs in the EVM `H` provides.
+pub struct State<H = MockHost> {
+    module: Arc<Module>,
     frames: Vec<Frame>,
+    memory: Memory,
     pc: ProgramCounter,
     prev_block: Option<Block>,
+    host: H,
+    gas: Option<GasReport>,
+    profile: Option<Profile>,
+}
+
+/// A copy of everything an execution mutates, i.e., frames, memory, the host, the program counter,
+/// consumed gas and the profile.
+#[derive(Clone)]
+pub struct Snapshot<H = MockHost> {
+    frames: Vec<Frame>,
+    memory: Memory,
+    pc: ProgramCounter,
+    prev_block: Option<Block>,
+    host: H,
+    gas: Option<GasReport>,
+    profile: Option<Profile>,
 }
 
 impl State {
-    pub fn new(module: Module, entry_func: FuncRef, args: &[Value]) -> Self {
+    /// Starts an execution of `entry_func` with `args`, which are values in the function, e.g.,
+    /// immediates.
+    pub fn new(module: impl Into<Arc<Module>>, entry_func: FuncRef, args: &[Value]) -> Self {
+        let module = module.into();
+        let memory = Memory::new(&module.ctx);
+
+        let func = &module.funcs[entry_func];
+        let mut entry_frame = Frame::new();
+        debug_assert!(func.arg_values.len() == args.len());
+        let arg_literals: Vec<_> = args
+            .iter()
+            .map(|arg| entry_frame.load(*arg, &func.dfg, &memory))
+            .collect();
+
+        Self::with_literals(module, memory, entry_func, arg_literals)
+    }
+
+    /// Starts an execution of `entry_func` with immediate arguments, e.g., to evaluate a call
+    /// whose arguments are constants.
+    pub fn with_imm_args(
+        module: impl Into<Arc<Module>>,
+        entry_func: FuncRef,
+        args: &[Immediate],
+    ) -> Self {
+        let module = module.into();
+        let memory = Memory::new(&module.ctx);
+        let arg_literals = args.iter().map(|arg| arg.as_i256()).collect();
+
+        Self::with_literals(module, memory, entry_func, arg_literals)
+    }
+
+    fn with_literals(
+        module: Arc<Module>,
+        memory: Memory,
+        entry_func: FuncRef,
+        arg_literals: Vec<I256>,
+    ) -> Self {
         let func = &module.funcs[entry_func];
         let pc = ProgramCounter::new(entry_func, &func.layout);
 
         let mut entry_frame = Frame::new();
-        debug_assert!(func.arg_values.len() == args.len());
-        for arg in args {
-            entry_frame.load(*arg, &func.dfg);
-        }
+        debug_assert!(func.arg_values.len() == arg_literals.len());
+        entry_frame.load_args(&func.arg_values, arg_literals.into_iter());
         let frames = vec![entry_frame];
 
         Self {
             module,
             frames,
+            memory,
             pc,
             prev_block: None,
+            host: MockHost::new(),
+            gas: None,
+            profile: None,
+        }
+    }
+
+    pub fn storage(&self) -> &BTreeMap<I256, I256> {
+        &self.host.storage
+    }
+}
+
+impl<H> State<H> {
+    /// Replaces the host the execution runs in, e.g., to set up the world it starts in.
+    pub fn with_host<H2: EvmHost>(self, host: H2) -> State<H2> {
+        State {
+            module: self.module,
+            frames: self.frames,
+            memory: self.memory,
+            pc: self.pc,
+            prev_block: self.prev_block,
+            host,
+            gas: self.gas,
+            profile: self.profile,
+        }
+    }
+
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    pub fn host_mut(&mut self) -> &mut H {
+        &mut self.host
+    }
+
+    pub fn pc(&self) -> ProgramCounter {
+        self.pc
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+}
+
+impl<H: Clone> State<H> {
+    /// Takes a snapshot of the execution, which [`Self::restore`] rewinds to.
+    pub fn snapshot(&self) -> Snapshot<H> {
+        Snapshot {
+            frames: self.frames.clone(),
+            memory: self.memory.clone(),
+            pc: self.pc,
+            prev_block: self.prev_block,
+            host: self.host.clone(),
+            gas: self.gas.clone(),
+            profile: self.profile.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot<H>) {
+        let Snapshot {
+            frames,
+            memory,
+            pc,
+            prev_block,
+            host,
+            gas,
+            profile,
+        } = snapshot;
+        self.frames = frames;
+        self.memory = memory;
+        self.pc = pc;
+        self.prev_block = prev_block;
+        self.host = host;
+        self.gas = gas;
+        self.profile = profile;
+    }
+
+    /// Returns an independent copy of the execution that shares the module with `self`.
+    pub fn fork(&self) -> Self {
+        let Snapshot {
+            frames,
+            memory,
+            pc,
+            prev_block,
+            host,
+            gas,
+            profile,
+        } = self.snapshot();
+        Self {
+            module: self.module.clone(),
+            frames,
+            memory,
+            pc,
+            prev_block,
+            host,
+            gas,
+            profile,
+        }
+    }
+}
+
+impl<H: EvmHost> State<H> {
+    /// Returns the destinations of the insn to be executed next, or an empty list if it's not a
+    /// branch.
+    pub fn branch_dests(&self) -> Vec<Block> {
+        let dfg = &self.module.funcs[self.pc.func_ref].dfg;
+        dfg.analyze_branch(self.pc.insn).iter_dests().collect()
+    }
+
+    /// Executes the branch insn to be executed next as if it jumped to `dest`, regardless of its
+    /// condition. This allows exploring every outcome of a branch, e.g., with forks of the state.
+    ///
+    /// # Panics
+    /// Panics if the next insn is not a branch to `dest`.
+    pub fn force_branch(&mut self, dest: Block) {
+        assert!(
+            self.branch_dests().contains(&dest),
+            "the next insn doesn't branch to `{dest}`"
+        );
+
+        let func = &self.module.funcs[self.pc.func_ref];
+        let block = func.layout.insn_block(self.pc.insn);
+        if let Some(gas) = &mut self.gas {
+            let cost = self
+                .module
+                .ctx
+                .isa
+                .cost_table()
+                .insn_cost(func.dfg.insn_data(self.pc.insn));
+            gas.charge(self.pc.func_ref, block, cost);
+        }
+        if let Some(profile) = &mut self.profile {
+            record_insn(profile, self.pc, &func.layout, Some(dest));
         }
+
+        self.prev_block = Some(block);
+        self.pc.branch_to(dest, &func.layout);
+    }
+
+    /// Measure gas consumed by the following steps with the cost table of the target ISA.
+    pub fn enable_gas_metering(&mut self) {
+        self.gas.get_or_insert_with(GasReport::default);
+    }
+
+    /// Returns the gas consumed so far, or `None` if gas metering is disabled.
+    pub fn gas_report(&self) -> Option<&GasReport> {
+        self.gas.as_ref()
+    }
+
+    /// Count the blocks and edges the following steps run, e.g., to attach them as branch
+    /// weights with [`Profile::apply`].
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
     }
 
+    /// Returns the counts so far, or `None` if profiling is disabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// # Panics
+    /// Panics if the execution fails, see [`Self::try_step`].
     pub fn run(mut self) -> EvalResult {
         loop {
             if let Some(arg) = self.step() {
@@ -43,41 +266,92 @@ impl State {
         }
     }
 
+    /// Run with gas metering enabled, and returns the result along with the consumed gas.
+    pub fn run_metered(mut self) -> (EvalResult, GasReport) {
+        self.enable_gas_metering();
+        loop {
+            if let Some(arg) = self.step() {
+                return (arg, self.gas.unwrap());
+            }
+        }
+    }
+
+    /// Run with profiling enabled, and returns the result along with the profile.
+    pub fn run_profiled(mut self) -> (EvalResult, Profile) {
+        self.enable_profiling();
+        loop {
+            if let Some(arg) = self.step() {
+                return (arg, self.profile.unwrap());
+            }
+        }
+    }
+
+    /// Runs at most `fuel` steps, which bounds the evaluation of code that may not terminate,
+    /// e.g., when it's evaluated at compile time.
+    pub fn eval(mut self, fuel: u64) -> Result<EvalResult, EvalError> {
+        for _ in 0..fuel {
+            if let Some(result) = self.try_step()? {
+                return Ok(result);
+            }
+        }
+        Err(EvalError::OutOfFuel)
+    }
+
+    /// # Panics
+    /// Panics if the execution fails, see [`Self::try_step`].
     pub fn step(&mut self) -> Option<EvalResult> {
+        self.try_step().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Executes the next insn, and returns the result of the entry function once it returns. The
+    /// state is left unchanged if the insn fails.
+    pub fn try_step(&mut self) -> Result<Option<EvalResult>, EvalError> {
         let frame = self.frames.last_mut().unwrap();
-        let insn = self.pc.insn;
+        let memory = &mut self.memory;
+        let ProgramCounter { func_ref, insn } = self.pc;
         let ctx = &self.module.ctx;
-        let func = &self.module.funcs[self.pc.func_ref];
+        let func = &self.module.funcs[func_ref];
 
         let dfg = &func.dfg;
         let layout = &func.layout;
 
         let insn_data = dfg.insn_data(insn);
+        let gas_cost = ctx.isa.cost_table().insn_cost(insn_data);
+        let block = layout.insn_block(insn);
 
         use InsnData::*;
-        match insn_data {
+        let result = match insn_data {
             Unary { code, args } => {
-                let arg = frame.load(args[0], dfg);
+                let arg = to_imm(frame.load(args[0], dfg, memory), dfg.value_ty(args[0]));
                 use UnaryOp::*;
                 let result = match code {
                     Not => arg.not(),
-                    Neg => arg.neg(),
+                    Neg => -arg,
+                    Clz => arg.clz(),
+                    Ctz => arg.ctz(),
+                    Popcount => arg.popcount(),
+                    Bswap => arg.bswap(),
                 };
 
                 let v = dfg.insn_result(insn).unwrap();
-                frame.map(result, v);
+                frame.map(result.as_i256(), v);
 
                 self.pc.next_insn(layout);
                 None
             }
             Binary { code, args } => {
-                let lhs: Immediate = frame.load(args[0], dfg).into();
-                let rhs: Immediate = frame.load(args[1], dfg).into();
+                let ty = dfg.value_ty(args[0]);
+                let lhs = to_imm(frame.load(args[0], dfg, memory), ty);
+                let rhs = to_imm(frame.load(args[1], dfg, memory), ty);
                 use BinaryOp::*;
+                if matches!(code, Udiv | Sdiv) && rhs.is_zero() {
+                    return Err(EvalError::DivisionByZero(self.pc));
+                }
+
                 let result = match code {
-                    Add => lhs.add(rhs),
-                    Sub => lhs.sub(rhs),
-                    Mul => lhs.mul(rhs),
+                    Add => lhs + rhs,
+                    Sub => lhs - rhs,
+                    Mul => lhs * rhs,
                     Udiv => lhs.udiv(rhs),
                     Sdiv => lhs.sdiv(rhs),
                     Lt => lhs.lt(rhs),
@@ -93,6 +367,11 @@ impl State {
                     And => lhs.bitand(rhs),
                     Or => lhs.bitor(rhs),
                     Xor => lhs.bitxor(rhs),
+                    Shl => lhs.shl(rhs),
+                    Shr => lhs.shr(rhs),
+                    Sar => lhs.sar(rhs),
+                    Rotl => lhs.rotl(rhs),
+                    Rotr => lhs.rotr(rhs),
                 }
                 .as_i256();
 
@@ -102,16 +381,33 @@ impl State {
                 self.pc.next_insn(layout);
                 None
             }
-            Cast { code, args, .. } => {
-                let arg = frame.load(args[0], dfg);
+            OverflowBinary { code, args } => {
+                let ty = dfg.value_ty(args[0]);
+                let lhs = to_imm(frame.load(args[0], dfg, memory), ty);
+                let rhs = to_imm(frame.load(args[1], dfg, memory), ty);
+                let (result, overflow) = lhs.overflowing_op(*code, rhs);
+
+                let results = dfg.insn_results(insn);
+                frame.map(result.as_i256(), results[0]);
+                frame.map(Immediate::from(overflow).as_i256(), results[1]);
+
+                self.pc.next_insn(layout);
+                None
+            }
+            Cast { code, args, ty } => {
+                let from = dfg.value_ty(args[0]);
+                let arg = frame.load(args[0], dfg, memory);
                 use CastOp::*;
                 let result = match code {
-                    Zext => arg.neg(),
-                    Sext | Trunc | BitCast => arg,
+                    // Literals are kept sign-extended, so only the zero extension changes the
+                    // bits above the width of the argument.
+                    Zext if from.is_integral() => I256::from_u256(to_imm(arg, from).as_unsigned()),
+                    Zext | Sext | BitCast => arg,
+                    Trunc => to_imm(arg, *ty).as_i256(),
                 };
 
                 let v = dfg.insn_result(insn).unwrap();
-                frame.map(result, v);
+                frame.map(to_imm(result, *ty).as_i256(), v);
 
                 self.pc.next_insn(layout);
                 None
@@ -120,12 +416,20 @@ impl State {
                 use DataLocationKind::*;
                 match loc {
                     Memory => {
-                        let addr = frame.load(args[0], dfg);
+                        let addr = frame.load(args[0], dfg, memory);
                         let v = dfg.insn_result(insn).unwrap();
                         let ty = dfg.insn_result_ty(insn).unwrap();
-                        frame.ldr(ctx, addr, v, ty);
+                        frame
+                            .ldr(ctx, addr, v, ty, memory)
+                            .ok_or(EvalError::InvalidAddress(self.pc))?;
+                    }
+                    Storage => {
+                        let key = frame.load(args[0], dfg, memory);
+                        let key = to_word(key, dfg.value_ty(args[0]));
+                        let v = dfg.insn_result(insn).unwrap();
+                        let data = self.host.sload(key);
+                        frame.map(to_imm(data, dfg.value_ty(v)).as_i256(), v);
                     }
-                    Storage => todo!(),
                 }
 
                 self.pc.next_insn(layout);
@@ -135,46 +439,146 @@ impl State {
                 use DataLocationKind::*;
                 match loc {
                     Memory => {
-                        let addr = frame.load(args[0], dfg);
-                        let data = frame.load(args[1], dfg);
+                        let addr = frame.load(args[0], dfg, memory);
+                        let data = frame.load(args[1], dfg, memory);
                         let ty = dfg.value_ty(args[1]);
-                        frame.str(ctx, addr, data, ty);
+                        frame
+                            .str(ctx, addr, data, ty, memory)
+                            .ok_or(EvalError::InvalidAddress(self.pc))?;
+                    }
+                    Storage => {
+                        let key = frame.load(args[0], dfg, memory);
+                        let data = frame.load(args[1], dfg, memory);
+                        let key = to_word(key, dfg.value_ty(args[0]));
+                        let data = to_word(data, dfg.value_ty(args[1]));
+                        self.host.sstore(key, data);
+                    }
+                }
+
+                self.pc.next_insn(layout);
+                None
+            }
+            Mem { code, args, loc } => {
+                let dst = frame.load(args[0], dfg, memory);
+                let src = frame.load(args[1], dfg, memory);
+                let len = frame.load(args[2], dfg, memory).to_u256();
+                let pc = self.pc;
+                let storage_slots = || {
+                    usize::try_from(len)
+                        .ok()
+                        .filter(|&slots| slots <= MAX_STORAGE_SLOTS)
+                        .ok_or(EvalError::StorageRangeTooLarge(pc))
+                };
+
+                use DataLocationKind::*;
+                match (code, loc) {
+                    (MemOp::Memcpy | MemOp::Memmove, Memory) => {
+                        let bytes = memory
+                            .read(src.to_u256(), len)
+                            .ok_or(EvalError::InvalidAddress(self.pc))?
+                            .to_vec();
+                        memory
+                            .write(dst.to_u256(), &bytes)
+                            .ok_or(EvalError::InvalidAddress(self.pc))?;
+                    }
+                    (MemOp::Memset, Memory) => {
+                        let byte = src.trunc_to_i8() as u8;
+                        memory
+                            .fill(dst.to_u256(), len, byte)
+                            .ok_or(EvalError::InvalidAddress(self.pc))?;
+                    }
+                    (MemOp::Memcpy | MemOp::Memmove, Storage) => {
+                        let slots = storage_slots()?;
+                        let dst = to_word(dst, dfg.value_ty(args[0])).to_u256();
+                        let src = to_word(src, dfg.value_ty(args[1])).to_u256();
+                        let slot = |base: U256, idx: usize| {
+                            I256::from_u256(base.overflowing_add(U256::from(idx)).0)
+                        };
+                        // All slots are read before any is written, so overlapping ranges are
+                        // moved as a whole.
+                        let words: Vec<_> =
+                            (0..slots).map(|i| self.host.sload(slot(src, i))).collect();
+                        for (i, word) in words.into_iter().enumerate() {
+                            self.host.sstore(slot(dst, i), word);
+                        }
+                    }
+                    (MemOp::Memset, Storage) => {
+                        let slots = storage_slots()?;
+                        let dst = to_word(dst, dfg.value_ty(args[0])).to_u256();
+                        let byte = U256::from(src.trunc_to_i8() as u8);
+                        let word = (0..32).fold(U256::zero(), |word, _| word << 8 | byte);
+                        for i in 0..slots {
+                            let key = I256::from_u256(dst.overflowing_add(U256::from(i)).0);
+                            self.host.sstore(key, I256::from_u256(word));
+                        }
                     }
-                    Storage => todo!(),
                 }
 
                 self.pc.next_insn(layout);
                 None
             }
             Call { func, args, .. } => {
-                let arg_literals = args.iter().map(|arg| frame.load(*arg, dfg));
+                let callee = &self.module.funcs[*func];
+                let arg_literals = args.iter().map(|arg| frame.load(*arg, dfg, memory));
+
+                if callee.layout.entry_block().is_none() {
+                    // An external function, which the host may implement.
+                    let arg_literals: Vec<_> = arg_literals.collect();
+                    let name = callee.sig.name();
+                    let result =
+                        host::call_builtin(&mut self.host, memory, name, &arg_literals, self.pc)?
+                            .ok_or(EvalError::UndefinedFunc(*func))?;
+                    if let Some(v) = dfg.insn_result(insn) {
+                        frame.map(to_imm(result, dfg.value_ty(v)).as_i256(), v);
+                    }
 
-                // Function prologue
+                    self.pc.next_insn(layout);
+                } else {
+                    // Function prologue
 
-                let ret_addr = self.pc;
+                    let ret_addr = self.pc;
 
-                let callee = &self.module.funcs[*func];
-                let mut new_frame = Frame::new();
-                debug_assert!(callee.arg_values.len() == args.len());
-                new_frame.load_args(&callee.arg_values, arg_literals);
-                new_frame.set_ret_addr(ret_addr);
-                self.frames.push(new_frame);
+                    let mut new_frame = Frame::new();
+                    debug_assert!(callee.arg_values.len() == args.len());
+                    new_frame.load_args(&callee.arg_values, arg_literals);
+                    new_frame.set_ret_addr(ret_addr);
+                    self.frames.push(new_frame);
+                    memory.push_frame();
 
-                self.pc.call(*func, &callee.layout);
+                    self.pc.call(*func, &callee.layout);
+                }
+                None
+            }
+            Intrinsic {
+                intrinsic, args, ..
+            } => {
+                let arg_literals: Vec<_> = args
+                    .iter()
+                    .map(|arg| frame.load(*arg, dfg, memory))
+                    .collect();
+                let name = self.module.ctx.isa.with_intrinsics(|intrinsics| {
+                    intrinsics.intrinsic_data(*intrinsic).name.clone()
+                });
+                let result =
+                    host::call_builtin(&mut self.host, memory, &name, &arg_literals, self.pc)?
+                        .ok_or(EvalError::UndefinedIntrinsic(*intrinsic))?;
+                if let Some(v) = dfg.insn_result(insn) {
+                    frame.map(to_imm(result, dfg.value_ty(v)).as_i256(), v);
+                }
+
+                self.pc.next_insn(layout);
                 None
             }
             Jump { dests, .. } => {
-                let block = layout.insn_block(insn);
                 self.prev_block = Some(block);
 
                 self.pc.branch_to(dests[0], layout);
                 None
             }
             Branch { args, dests } => {
-                let arg = frame.load(args[0], dfg);
-                let idx = arg.not().to_u256().as_usize();
+                let arg = frame.load(args[0], dfg, memory);
+                let idx = if arg.trunc_to_i1() { 0 } else { 1 };
 
-                let block = layout.insn_block(insn);
                 self.prev_block = Some(block);
                 self.pc.branch_to(dests[idx], layout);
                 None
@@ -184,26 +588,23 @@ impl State {
                 default,
                 table,
             } => {
-                let block = layout.insn_block(insn);
-                self.prev_block = Some(block);
+                let cond = frame.load(args[0], dfg, memory);
+                let dest = args[1..]
+                    .iter()
+                    .position(|arg| frame.load(*arg, dfg, memory) == cond)
+                    .map(|idx| table[idx])
+                    .or(*default);
+                let Some(dest) = dest else {
+                    return Err(EvalError::NoBranchDest(self.pc));
+                };
 
-                let cond = args[0];
-                for (idx, arg) in args[1..].iter().enumerate() {
-                    let cond = frame.load(cond, dfg);
-                    let arg = frame.load(*arg, dfg);
-                    if cond == arg {
-                        self.pc.branch_to(table[idx], layout);
-                        return None;
-                    }
-                }
-                if let Some(block) = *default {
-                    self.pc.branch_to(block, layout);
-                }
+                self.prev_block = Some(block);
+                self.pc.branch_to(dest, layout);
                 None
             }
             Alloca { ty } => {
                 let v = dfg.insn_result(insn).unwrap();
-                frame.alloca(ctx, *ty, v);
+                frame.alloca(ctx, *ty, v, memory);
 
                 self.pc.next_insn(layout);
                 None
@@ -215,11 +616,12 @@ impl State {
                     Some(caller_frame) => {
                         // Function epilogue
 
+                        memory.pop_frame();
                         self.pc.resume_frame_at(frame.ret_addr.unwrap());
 
                         let caller = &self.module.funcs[self.pc.func_ref];
                         if let Some(arg) = *args {
-                            let arg_literal = frame.load(arg, dfg);
+                            let arg_literal = frame.load(arg, dfg, memory);
                             let v = caller.dfg.insn_result(self.pc.insn).unwrap();
                             caller_frame.map(arg_literal, v);
                         }
@@ -227,18 +629,35 @@ impl State {
                         self.pc.next_insn(&caller.layout);
                         None
                     }
-                    None => {
-                        let Some(arg) = *args else {
-                            return Some(EvalResult::Void);
-                        };
-                        let arg_literal = frame.load(arg, dfg);
-                        let ty = dfg.value_ty(arg);
-                        Some(EvalResult::from_i256(ctx, arg_literal, ty))
-                    }
+                    None => match *args {
+                        Some(arg) => {
+                            let arg_literal = frame.load(arg, dfg, memory);
+                            let ty = dfg.value_ty(arg);
+                            Some(EvalResult::from_i256(ctx, arg_literal, ty))
+                        }
+                        None => Some(EvalResult::Void),
+                    },
                 }
             }
+            Revert { selector, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| frame.load(*arg, dfg, memory))
+                    .collect();
+                return Err(EvalError::Revert {
+                    selector: *selector,
+                    args,
+                });
+            }
+            Trap { reason } => {
+                return Err(EvalError::Trap {
+                    pc: self.pc,
+                    reason: *reason,
+                })
+            }
+            Unreachable => return Err(EvalError::Unreachable(self.pc)),
             Gep { args } => {
-                let mut arg_literals = args.iter().map(|arg| frame.load(*arg, dfg));
+                let mut arg_literals = args.iter().map(|arg| frame.load(*arg, dfg, memory));
                 let base_addr = arg_literals.next().unwrap();
                 let ty = dfg.value_ty(args[0]);
                 debug_assert!(ctx.with_ty_store(|s| s.is_ptr(ty)));
@@ -255,7 +674,7 @@ impl State {
                 let prev_block = self.prev_block.unwrap();
                 for (v, block) in values.iter().zip(blocks.iter()) {
                     if prev_block == *block {
-                        let lit = frame.load(*v, dfg);
+                        let lit = frame.load(*v, dfg, memory);
                         let v = dfg.insn_result(insn).unwrap();
                         frame.map(lit, v);
                         break;
@@ -264,13 +683,54 @@ impl State {
                 self.pc.next_insn(layout);
                 None
             }
+        };
+
+        if let Some(gas) = &mut self.gas {
+            gas.charge(func_ref, block, gas_cost);
         }
+        if let Some(profile) = &mut self.profile {
+            let dest = insn_data
+                .is_branch()
+                .then(|| layout.insn_block(self.pc.insn));
+            record_insn(profile, ProgramCounter { func_ref, insn }, layout, dest);
+        }
+        Ok(result)
+    }
+}
+
+/// Counts the block of `pc` if its insn is the first one, and the edge to `dest` if it branches.
+fn record_insn(profile: &mut Profile, pc: ProgramCounter, layout: &Layout, dest: Option<Block>) {
+    let block = layout.insn_block(pc.insn);
+    if layout.first_insn_of(block) == Some(pc.insn) {
+        profile.add_block_count(pc.func_ref, block, 1);
+    }
+    if let Some(dest) = dest {
+        profile.add_edge_count(pc.func_ref, block, dest, 1);
+    }
+}
+
+/// Returns `literal` as a storage word, in which a narrow integer is zero-extended as it is on the
+/// EVM.
+fn to_word(literal: I256, ty: Type) -> I256 {
+    I256::from_u256(to_imm(literal, ty).as_unsigned())
+}
+
+/// Returns `literal` as an immediate of `ty`, so that arithmetic wraps at the width of `ty`.
+/// Pointers are 256-bit.
+fn to_imm(literal: I256, ty: Type) -> Immediate {
+    if ty.is_integral() {
+        Immediate::from_i256(literal, ty)
+    } else {
+        Immediate::I256(literal)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use sonatina_ir::{insn::TrapReason, U256};
+
     use super::*;
+    use crate::memory::NULL_REGION_SIZE;
 
     fn parse_module(input: &str) -> Module {
         match sonatina_parser::parse_module(input) {
@@ -291,6 +751,149 @@ mod test {
         State::new(module, func_ref, &[])
     }
 
+    #[test]
+    fn gas_metering() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %callee(v0.i32) -> i32 {
+            block0:
+                v1.i32 = mul v0 3.i32;
+                return v1;
+        }
+
+        func private %test() -> i32 {
+            block0:
+                v0.i32 = call %callee 1.i32;
+                v1.i32 = add v0 2.i32;
+                return v1;
+        }
+        ";
+
+        let module = parse_module(input);
+        let mut funcs = module.iter_functions();
+        let (callee, test) = (funcs.next().unwrap(), funcs.next().unwrap());
+        let block = module.funcs[test].layout.entry_block().unwrap();
+        let state = State::new(module, test, &[]);
+
+        let (result, gas) = state.run_metered();
+        assert_eq!(result.into_i32(), 5i32);
+
+        // `call` + `add` + `return`.
+        assert_eq!(gas.func_gas(test), 15 + 3 + 11);
+        assert_eq!(gas.block_gas(test, block), 15 + 3 + 11);
+        // `mul` + `return`.
+        assert_eq!(gas.func_gas(callee), 5 + 11);
+        assert_eq!(gas.total(), 45);
+    }
+
+    #[test]
+    fn profile() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i64 {
+            block0:
+                jump block1;
+            block1:
+                v0.i64 = phi (0.i64 block0) (v1 block1);
+                v1.i64 = add v0 1.i64;
+                v2.i1 = lt v1 10.i64;
+                br v2 block1 block2;
+            block2:
+                return v1;
+        }
+        ";
+
+        let mut module = parse_module(input);
+        let func_ref = module.iter_functions().next().unwrap();
+        let blocks: Vec<_> = module.funcs[func_ref].layout.iter_block().collect();
+        let (b1, b2) = (blocks[1], blocks[2]);
+        // The profile is measured on a copy of the module, as a frontend would before compiling.
+        let state = State::new(parse_module(input), func_ref, &[]);
+
+        let (result, profile) = state.run_profiled();
+        assert_eq!(result.into_i64(), 10i64);
+        assert_eq!(profile.block_count(func_ref, b1), Some(10));
+        assert_eq!(profile.edge_count(func_ref, b1, b1), Some(9));
+        assert_eq!(profile.edge_count(func_ref, b1, b2), Some(1));
+
+        assert_eq!(profile.apply(&mut module), Ok(1));
+        let func = &module.funcs[func_ref];
+        let branch = func.layout.last_insn_of(b1).unwrap();
+        assert_eq!(func.dfg.branch_weights(branch).unwrap().weights(), [9, 1]);
+    }
+
+    #[test]
+    fn no_object_at_null() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        gv private %counter: i256;
+
+        func private %test() -> i1 {
+            block0:
+                v0.*i256 = bitcast 0.i256;
+                v1.i1 = eq %counter v0;
+                v2.*i32 = alloca i32;
+                v3.*i32 = bitcast 0.i256;
+                v4.i1 = eq v2 v3;
+                v5.i1 = or v1 v4;
+                return v5;
+        }
+        ";
+
+        // The optimizer folds these comparisons to `false`.
+        let state = parse_module_make_state(input);
+        assert!(!state.run().into_bool());
+    }
+
+    #[test]
+    fn fork_branch() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i64 {
+            block0:
+                v0.*i64 = bitcast 0.i256;
+                store @storage v0 10.i64;
+                br 1.i1 block1 block2;
+            block1:
+                store @storage v0 20.i64;
+                v1.i64 = load @storage v0;
+                return v1;
+            block2:
+                v2.i64 = load @storage v0;
+                v3.i64 = add v2 1.i64;
+                return v3;
+        }
+        ";
+
+        let mut state = parse_module_make_state(input);
+        state.step();
+        state.step();
+        let snapshot = state.snapshot();
+        let dests = state.branch_dests();
+        assert_eq!(dests.len(), 2);
+
+        let mut fork = state.fork();
+        fork.force_branch(dests[1]);
+        assert_eq!(fork.run().into_i64(), 11i64);
+
+        let result = loop {
+            if let Some(result) = state.step() {
+                break result;
+            }
+        };
+        assert_eq!(result.into_i64(), 20i64);
+        assert_eq!(state.storage()[&I256::zero()], I256::from(20i64));
+
+        state.restore(snapshot);
+        assert_eq!(state.storage()[&I256::zero()], I256::from(10i64));
+        state.force_branch(dests[1]);
+        assert_eq!(state.run().into_i64(), 11i64);
+    }
+
     #[test]
     fn unary() {
         let input = "
@@ -311,6 +914,31 @@ mod test {
         assert_eq!(result.into_i32(), 1i32);
     }
 
+    #[test]
+    fn bit_manipulation() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i32 {
+            block0:
+                v0.i32 = bswap 0x0001f000.i32;
+                v1.i32 = clz v0;
+                v2.i32 = ctz v0;
+                v3.i32 = popcount v0;
+                v4.i32 = mul v1 v2;
+                v5.i32 = add v4 v3;
+                return v5;
+        }
+        ";
+
+        let state = parse_module_make_state(input);
+
+        let result = state.run();
+
+        // `v0` is `0x00f00100`.
+        assert_eq!(result.into_i32(), 8 * 8 + 5);
+    }
+
     #[test]
     fn binary_arithmetic() {
         let input = "
@@ -392,6 +1020,34 @@ mod test {
         assert_eq!(data.into_i32(), 1i32);
     }
 
+    #[test]
+    fn mem_intrinsics() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i32 {
+            block0:
+                v0.*[i32; 3] = alloca [i32; 3];
+                v1.*i32 = gep v0 0.i256;
+                v2.*i32 = gep v0 1.i256;
+                v3.*i32 = gep v0 2.i256;
+                memset @memory v0 1.i8 12.i256;
+                store @memory v2 2.i32;
+                memmove @memory v3 v2 4.i256;
+                v4.i32 = load @memory v1;
+                v5.i32 = load @memory v3;
+                v6.i32 = add v4 v5;
+                return v6;
+        }
+        ";
+
+        let state = parse_module_make_state(input);
+
+        let data = state.run();
+
+        assert_eq!(data.into_i32(), 0x01010101 + 2);
+    }
+
     #[test]
     fn call() {
         let input = "
@@ -530,7 +1186,7 @@ mod test {
 
         let elem_ptr = state.run();
 
-        assert_eq!(elem_ptr.into_usize(), 12usize);
+        assert_eq!(elem_ptr.into_usize(), NULL_REGION_SIZE + 12);
     }
 
     #[test]
@@ -571,7 +1227,7 @@ mod test {
 
         let elem_ptr = state.run();
 
-        assert_eq!(elem_ptr.into_usize(), 16usize);
+        assert_eq!(elem_ptr.into_usize(), NULL_REGION_SIZE + 16);
     }
 
     #[test]
@@ -593,6 +1249,362 @@ mod test {
 
         let elem_ptr = state.run();
 
-        assert_eq!(elem_ptr.into_usize(), 11usize);
+        assert_eq!(elem_ptr.into_usize(), NULL_REGION_SIZE + 11);
+    }
+
+    #[test]
+    fn arithmetic_wraps_at_width() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i8 {
+            block0:
+                v0.i8 = add 127.i8 1.i8;
+                v1.i8 = sdiv v0 2.i8;
+                v2.i8 = udiv -2.i8 2.i8;
+                v3.i8 = add v1 v2;
+                return v3;
+        }
+        ";
+
+        let state = parse_module_make_state(input);
+
+        assert_eq!(state.run().into_i8(), 63i8);
+    }
+
+    #[test]
+    fn overflow_checked_arithmetic() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %test() -> i8 {
+            block0:
+                v0.i8, v1.i1 = saddo 127.i8 1.i8;
+                v2.i8, v3.i1 = umulo 5.i8 6.i8;
+                v4.i8 = zext v1;
+                v5.i8 = zext v3;
+                v6.i8 = add v0 v4;
+                v7.i8 = add v6 v2;
+                v8.i8 = add v7 v5;
+                return v8;
+        }
+        ";
+
+        let state = parse_module_make_state(input);
+
+        assert_eq!(state.run().into_i8(), -97i8);
+    }
+
+    #[test]
+    fn memory_across_calls() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        gv public const %table: [i32; 2] = [7, 11];
+
+        func private %write(v0.*i32, v1.i32) -> void {
+            block0:
+                v2.*i32 = alloca i32;
+                store @memory v0 v1;
+                return;
+        }
+
+        func private %test() -> i32 {
+            block0:
+                v0.*i32 = alloca i32;
+                call %write v0 5.i32;
+                v1.*[i32; 2] = bitcast %table;
+                v2.*i32 = gep v1 1.i8;
+                v3.i32 = load @memory v2;
+                v4.i32 = load @memory v0;
+                v5.i32 = add v3 v4;
+                return v5;
+        }
+        ";
+
+        let module = parse_module(input);
+        let func_ref = module.iter_functions().nth(1).unwrap();
+        let state = State::new(module, func_ref, &[]);
+
+        assert_eq!(state.run().into_i32(), 16i32);
+    }
+
+    #[test]
+    fn eval() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        declare external %ext(i32) -> i32;
+
+        func private %div(v0.i32, v1.i32) -> i32 {
+            block0:
+                v2.i32 = udiv v0 v1;
+                return v2;
+        }
+
+        func private %spin() -> void {
+            block0:
+                jump block0;
+        }
+
+        func private %call_ext() -> i32 {
+            block0:
+                v0.i32 = call %ext 1.i32;
+                return v0;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        let funcs: Vec<_> = module.iter_functions().collect();
+        let (ext, div, spin, call_ext) = (funcs[0], funcs[1], funcs[2], funcs[3]);
+
+        let args = [Immediate::I32(12), Immediate::I32(4)];
+        let state = State::with_imm_args(module.clone(), div, &args);
+        assert_eq!(state.eval(10), Ok(EvalResult::I32(3)));
+
+        let args = [Immediate::I32(12), Immediate::I32(0)];
+        let state = State::with_imm_args(module.clone(), div, &args);
+        assert!(matches!(state.eval(10), Err(EvalError::DivisionByZero(_))));
+
+        let state = State::with_imm_args(module.clone(), spin, &[]);
+        assert_eq!(state.eval(100), Err(EvalError::OutOfFuel));
+
+        let state = State::with_imm_args(module, call_ext, &[]);
+        assert_eq!(state.eval(10), Err(EvalError::UndefinedFunc(ext)));
+    }
+
+    #[test]
+    fn host() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        declare external %keccak256(*i8, i256) -> i256;
+        declare external %log1(*i8, i256, i256);
+        declare external %number() -> i256;
+
+        func public %test() -> i256 {
+            block0:
+                v0.*i256 = alloca i256;
+                v1.*i8 = bitcast v0;
+                v2.i256 = call %keccak256 v1 0.i256;
+                v3.i256 = call %number;
+                store @storage v3 v2;
+                call %log1 v1 32.i256 v3;
+                return v2;
+        }
+        ";
+
+        let module = parse_module(input);
+        let func_ref = module.iter_functions().nth(3).unwrap();
+        let host = MockHost {
+            block: host::BlockContext {
+                number: U256::from(7u64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut state = State::new(module, func_ref, &[]).with_host(host);
+
+        let result = loop {
+            if let Some(result) = state.step() {
+                break result;
+            }
+        };
+        let empty_hash = I256::from_u256(
+            U256::from_str_radix(
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+                16,
+            )
+            .unwrap(),
+        );
+        assert_eq!(result.into_i256(), empty_hash);
+        assert_eq!(state.host().storage[&I256::from(7i64)], empty_hash);
+        assert_eq!(
+            state.host().logs,
+            vec![host::Log {
+                topics: vec![U256::from(7u64)],
+                data: vec![0; 32],
+            }]
+        );
+    }
+
+    #[test]
+    fn intrinsics() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        declare intrinsic %trace(i256);
+
+        func public %test() -> i256 {
+            block0:
+                v0.i256 = intrinsic %number;
+                v1.i256 = intrinsic %timestamp;
+                v2.i256 = add v0 v1;
+                return v2;
+        }
+
+        func public %trace() {
+            block0:
+                intrinsic %trace 1.i256;
+                return;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        let funcs: Vec<_> = module.iter_functions().collect();
+        let host = MockHost {
+            block: host::BlockContext {
+                number: U256::from(7u64),
+                timestamp: U256::from(100u64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let state = State::new(module.clone(), funcs[0], &[]).with_host(host);
+        assert_eq!(state.eval(10), Ok(EvalResult::I256(I256::from(107i64))));
+
+        let trace = module
+            .ctx
+            .isa
+            .with_intrinsics(|intrinsics| intrinsics.intrinsic_by_name("trace"))
+            .unwrap();
+        let state = State::new(module, funcs[1], &[]);
+        assert_eq!(state.eval(10), Err(EvalError::UndefinedIntrinsic(trace)));
+    }
+
+    #[test]
+    fn revert_and_trap() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func public %check(v0.i1, v1.i256) {
+            block0:
+                br v0 block1 block2;
+
+            block1:
+                revert 0xcafebabe v1 7.i8;
+
+            block2:
+                trap overflow;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let args = [Immediate::I1(true), Immediate::I256(I256::from(42i64))];
+        let state = State::with_imm_args(module.clone(), func_ref, &args);
+        assert_eq!(
+            state.eval(10),
+            Err(EvalError::Revert {
+                selector: [0xca, 0xfe, 0xba, 0xbe],
+                args: vec![I256::from(42i64), I256::from(7i64)],
+            })
+        );
+
+        let args = [Immediate::I1(false), Immediate::I256(I256::zero())];
+        let state = State::with_imm_args(module, func_ref, &args);
+        assert!(matches!(
+            state.eval(10),
+            Err(EvalError::Trap {
+                reason: TrapReason::Overflow,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unreachable() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func public %pick(v0.i1) -> i8 {
+            block0:
+                br v0 block1 block2;
+
+            block1:
+                return 1.i8;
+
+            block2:
+                unreachable;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let state = State::with_imm_args(module.clone(), func_ref, &[Immediate::I1(true)]);
+        assert_eq!(state.eval(10), Ok(EvalResult::I8(1)));
+
+        let state = State::with_imm_args(module, func_ref, &[Immediate::I1(false)]);
+        assert!(matches!(state.eval(10), Err(EvalError::Unreachable(_))));
+    }
+
+    #[test]
+    fn invalid_address() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %load_null() -> i32 {
+            block0:
+                v0.*i32 = bitcast 0.i256;
+                v1.i32 = load @memory v0;
+                return v1;
+        }
+
+        func private %store_far() -> void {
+            block0:
+                v0.*i32 = bitcast -1.i256;
+                store @memory v0 1.i32;
+                return;
+        }
+
+        func private %copy_past_end() -> void {
+            block0:
+                v0.*i8 = alloca i8;
+                memcpy @memory v0 v0 64.i256;
+                return;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        for func_ref in module.iter_functions() {
+            let state = State::with_imm_args(module.clone(), func_ref, &[]);
+            assert!(matches!(
+                state.eval(10),
+                Err(EvalError::InvalidAddress(pc)) if pc.func_ref == func_ref
+            ));
+        }
+    }
+
+    #[test]
+    fn storage_range_too_large() {
+        let input = "
+        target = \"evm-ethereum-london\"
+
+        func private %fill_all() -> void {
+            block0:
+                v0.*i256 = bitcast 0.i256;
+                memset @storage v0 1.i8 -1.i256;
+                return;
+        }
+
+        func private %copy_many() -> void {
+            block0:
+                v0.*i256 = bitcast 0.i256;
+                v1.*i256 = bitcast 1.i256;
+                memcpy @storage v0 v1 65537.i256;
+                return;
+        }
+        ";
+
+        let module = Arc::new(parse_module(input));
+        for func_ref in module.iter_functions() {
+            let state = State::with_imm_args(module.clone(), func_ref, &[]);
+            assert!(matches!(
+                state.eval(10),
+                Err(EvalError::StorageRangeTooLarge(pc)) if pc.func_ref == func_ref
+            ));
+        }
     }
 }
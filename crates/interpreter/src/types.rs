@@ -23,6 +23,7 @@ pub fn size_of_ty_data(ctx: &ModuleCtx, ty: Type) -> usize {
                 Struct(data) => data.fields.iter().fold(0usize, |acc, field_ty| {
                     acc + size_of_ty_data(ctx, *field_ty)
                 }),
+                Bytes => mem::size_of::<usize>() + size_of_ty_data(ctx, Type::I256),
             })
         }
         Type::Void => mem::size_of::<()>(),
@@ -62,6 +63,13 @@ pub fn gep(
                 }
                 cmpd_ty = to_cmpd_ty(data.fields[index]);
             }
+            CompoundTypeData::Bytes => {
+                for idx in 0..index {
+                    let field_ty = ctx.with_ty_store(|s| s.bytes_field(idx));
+                    offset += size_of_ty_data(ctx, field_ty);
+                }
+                cmpd_ty = None;
+            }
             _ => unreachable!(),
         }
     }
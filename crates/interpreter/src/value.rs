@@ -1,7 +1,13 @@
-use std::mem;
+use std::{fmt, mem};
 
 use byteorder::{BigEndian, WriteBytesExt};
-use sonatina_ir::{module::ModuleCtx, Type, I256, U256};
+use sonatina_ir::{
+    insn::{DisplaySelector, TrapReason},
+    module::{FuncRef, ModuleCtx},
+    Intrinsic, Type, I256, U256,
+};
+
+use crate::ProgramCounter;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 pub enum EvalValue {
@@ -75,6 +81,7 @@ impl EvalValue {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EvalResult {
     I1(bool),
     I8(i8),
@@ -168,3 +175,86 @@ impl EvalResult {
         usize
     }
 }
+
+/// An execution that can't go on, e.g., because its behavior is undefined or it reverted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The insn at the program counter divides by zero.
+    DivisionByZero(ProgramCounter),
+    /// The `br_table` at the program counter matches none of its destinations and has no default.
+    NoBranchDest(ProgramCounter),
+    /// The function is called but has no body, and the host doesn't implement it.
+    UndefinedFunc(FuncRef),
+    /// The intrinsic is called but the host doesn't implement it.
+    UndefinedIntrinsic(Intrinsic),
+    /// The execution didn't finish within the given number of steps.
+    OutOfFuel,
+    /// A `revert` was reached with the error `selector(args)`.
+    Revert { selector: [u8; 4], args: Vec<I256> },
+    /// The `trap` at the program counter was reached.
+    Trap {
+        pc: ProgramCounter,
+        reason: TrapReason,
+    },
+    /// The `unreachable` at the program counter was reached, i.e., the function assumes
+    /// something that doesn't hold for the args.
+    Unreachable(ProgramCounter),
+    /// The insn at the program counter accesses memory that isn't allocated, e.g., through a
+    /// null or dangling pointer.
+    InvalidAddress(ProgramCounter),
+    /// The storage `memcpy`, `memmove` or `memset` at the program counter spans more slots than
+    /// the interpreter supports.
+    StorageRangeTooLarge(ProgramCounter),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DivisionByZero(pc) => {
+                write!(f, "division by zero at {:?} in {:?}", pc.insn, pc.func_ref)
+            }
+            Self::NoBranchDest(pc) => write!(
+                f,
+                "`br_table` at {:?} in {:?} has no destination for its value",
+                pc.insn, pc.func_ref
+            ),
+            Self::UndefinedFunc(func_ref) => write!(f, "{func_ref:?} has no body"),
+            Self::UndefinedIntrinsic(intrinsic) => {
+                write!(f, "{intrinsic:?} is not implemented by the host")
+            }
+            Self::OutOfFuel => write!(f, "the execution ran out of fuel"),
+            Self::Revert { selector, args } => {
+                write!(
+                    f,
+                    "the execution reverted with `{}(",
+                    DisplaySelector(*selector)
+                )?;
+                for (idx, arg) in args.iter().enumerate() {
+                    let delim = if idx == 0 { "" } else { ", " };
+                    write!(f, "{delim}{arg}")?;
+                }
+                write!(f, ")`")
+            }
+            Self::Trap { pc, reason } => {
+                write!(f, "`trap {reason}` at {:?} in {:?}", pc.insn, pc.func_ref)
+            }
+            Self::Unreachable(pc) => {
+                write!(
+                    f,
+                    "`unreachable` reached at {:?} in {:?}",
+                    pc.insn, pc.func_ref
+                )
+            }
+            Self::InvalidAddress(pc) => {
+                write!(f, "invalid address at {:?} in {:?}", pc.insn, pc.func_ref)
+            }
+            Self::StorageRangeTooLarge(pc) => write!(
+                f,
+                "storage range too large at {:?} in {:?}",
+                pc.insn, pc.func_ref
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
@@ -0,0 +1,142 @@
+//! Golden end-to-end tests.
+//!
+//! Each fixture is optimized with the [default pipeline](PassManager::default_pipeline), then
+//! executed in the interpreter with gas metering. A fixture specifies the run in its header
+//! comments:
+//!
+//! ```text
+//! # run: %func 1.i32 2.i32
+//! # result: 3.i32
+//! # gas budget: 42
+//! ```
+//!
+//! The test fails if the result differs, or if the measured gas exceeds the budget. Run with
+//! `SONATINA_BLESS=1` to record the measured gas as the new budget.
+
+use std::fs;
+
+use dir_test::{dir_test, Fixture};
+use sonatina_codegen::pass_manager::PassManager;
+use sonatina_interpreter::{EvalResult, State};
+use sonatina_ir::Immediate;
+
+const BUDGET_DIRECTIVE: &str = "# gas budget:";
+
+#[dir_test(
+    dir: "$CARGO_MANIFEST_DIR/test_files/golden",
+    glob: "*.sntn"
+)]
+fn golden(fixture: Fixture<&str>) {
+    let spec = RunSpec::parse(fixture.content());
+    let mut module = match sonatina_parser::parse_module(fixture.content()) {
+        Ok(pm) => pm.module,
+        Err(errs) => {
+            for err in errs {
+                eprintln!(
+                    "{}",
+                    err.print_to_string(fixture.path(), fixture.content(), true)
+                );
+            }
+            panic!("parsing failed");
+        }
+    };
+
+    PassManager::default_pipeline().run(&mut module);
+
+    let func_ref = module
+        .iter_functions()
+        .find(|func_ref| module.funcs[*func_ref].sig.name() == spec.func)
+        .unwrap_or_else(|| panic!("function `{}` is not found", spec.func));
+    let func = &mut module.funcs[func_ref];
+    let args: Vec<_> = spec
+        .args
+        .iter()
+        .map(|arg| func.dfg.make_imm_value(parse_imm(arg)))
+        .collect();
+
+    let (result, gas) = State::new(module, func_ref, &args).run_metered();
+    assert_eq!(display_result(&result), spec.result, "{}", fixture.path());
+
+    if std::env::var_os("SONATINA_BLESS").is_some() {
+        let content = fixture
+            .content()
+            .lines()
+            .map(|line| {
+                if line.starts_with(BUDGET_DIRECTIVE) {
+                    format!("{BUDGET_DIRECTIVE} {}", gas.total())
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(fixture.path(), content + "\n").unwrap();
+        return;
+    }
+
+    assert!(
+        gas.total() <= spec.gas_budget,
+        "{}: consumed {} gas, exceeding the budget of {}",
+        fixture.path(),
+        gas.total(),
+        spec.gas_budget
+    );
+}
+
+struct RunSpec {
+    func: String,
+    args: Vec<String>,
+    result: String,
+    gas_budget: u64,
+}
+
+impl RunSpec {
+    fn parse(content: &str) -> Self {
+        let directive = |name: &str| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(str::trim)
+                .unwrap_or_else(|| panic!("`{name}` directive is missing"))
+        };
+
+        let mut run = directive("# run:").split_whitespace();
+        let func = run.next().unwrap().trim_start_matches('%').to_string();
+        let args = run.map(str::to_string).collect();
+
+        Self {
+            func,
+            args,
+            result: directive("# result:").to_string(),
+            gas_budget: directive(BUDGET_DIRECTIVE).parse().unwrap(),
+        }
+    }
+}
+
+/// Parse an immediate written as `<number>.<type>`, e.g., `1.i32`.
+fn parse_imm(imm: &str) -> Immediate {
+    let (number, ty) = imm.rsplit_once('.').unwrap();
+    match ty {
+        "i1" => Immediate::I1(number == "1"),
+        "i8" => Immediate::I8(number.parse().unwrap()),
+        "i16" => Immediate::I16(number.parse().unwrap()),
+        "i32" => Immediate::I32(number.parse().unwrap()),
+        "i64" => Immediate::I64(number.parse().unwrap()),
+        "i128" => Immediate::I128(number.parse().unwrap()),
+        _ => panic!("unsupported immediate `{imm}`"),
+    }
+}
+
+fn display_result(result: &EvalResult) -> String {
+    match result {
+        EvalResult::I1(val) => format!("{}.i1", u8::from(*val)),
+        EvalResult::I8(val) => format!("{val}.i8"),
+        EvalResult::I16(val) => format!("{val}.i16"),
+        EvalResult::I32(val) => format!("{val}.i32"),
+        EvalResult::I64(val) => format!("{val}.i64"),
+        EvalResult::I128(val) => format!("{val}.i128"),
+        EvalResult::I256(val) => format!("{val:?}.i256"),
+        EvalResult::Void => "void".to_string(),
+        EvalResult::Addr(addr) => format!("{addr}.ptr"),
+    }
+}
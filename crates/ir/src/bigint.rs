@@ -206,6 +206,27 @@ impl hash::Hash for I256 {
     }
 }
 
+/// Serialized as its two's complement bit pattern.
+#[cfg(feature = "serde")]
+impl serde::Serialize for I256 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_u256().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for I256 {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        U256::deserialize(deserializer).map(Self::from_u256)
+    }
+}
+
 impl cmp::PartialEq for I256 {
     fn eq(&self, rhs: &Self) -> bool {
         self.is_negative == rhs.is_negative && self.abs == rhs.abs
@@ -0,0 +1,162 @@
+//! This module contains [`BlockParams`], the block-parameter view of the phis of a function.
+//!
+//! In the block-parameter form of SSA, a block takes params instead of beginning with phis, and
+//! each branch passes args to the params of its destination, e.g.,
+//!
+//! ```text
+//! block1(v1.i64):
+//!     v2.i64 = add v1 1.i64;
+//!     jump block1(v2);
+//! ```
+//!
+//! is the same as a `v1.i64 = phi (v0 block0) (v2 block1)` at the top of `block1`. Functions keep
+//! phis, so that passes see a single representation; this view is computed from the phis for the
+//! [writer](crate::ir_writer::FuncWriter::with_block_params), and the parser turns the params of
+//! a block back into phis.
+use std::fmt;
+
+use cranelift_entity::SecondaryMap;
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+use crate::{Block, ControlFlowGraph, Function, Insn, Value};
+
+#[derive(Debug, Default)]
+pub struct BlockParams {
+    /// The phis of each block, in order. The params of a block are their results.
+    phis: SecondaryMap<Block, SmallVec<[Insn; 4]>>,
+    params: SecondaryMap<Block, SmallVec<[Value; 4]>>,
+    /// The args of each edge, keyed by its source and its destination.
+    args: FxHashMap<(Block, Block), SmallVec<[Value; 4]>>,
+}
+
+impl BlockParams {
+    /// Converts the phis of `func` to block params. Fails if a phi has no arg from a predecessor
+    /// of its block, or an arg from a block that isn't one, since neither can be written as args
+    /// of the branches.
+    pub fn from_phis(func: &Function, cfg: &ControlFlowGraph) -> Result<Self, BlockParamsError> {
+        let mut this = Self::default();
+        for block in func.layout.iter_block() {
+            let phis: SmallVec<[Insn; 4]> = func.phis_of(block).collect();
+            if phis.is_empty() {
+                continue;
+            }
+
+            for &phi in &phis {
+                if let Some(&from) = func
+                    .dfg
+                    .phi_blocks(phi)
+                    .iter()
+                    .find(|from| !cfg.preds_of(block).any(|pred| pred == *from))
+                {
+                    return Err(BlockParamsError::StrayArg { phi, from });
+                }
+            }
+
+            for &pred in cfg.preds_of(block) {
+                let args = phis
+                    .iter()
+                    .map(|&phi| {
+                        func.dfg
+                            .phi_arg_from(phi, pred)
+                            .ok_or(BlockParamsError::MissingArg { phi, pred })
+                    })
+                    .collect::<Result<_, _>>()?;
+                this.args.insert((pred, block), args);
+            }
+
+            this.params[block] = phis
+                .iter()
+                .map(|&phi| func.dfg.insn_result(phi).unwrap())
+                .collect();
+            this.phis[block] = phis;
+        }
+
+        Ok(this)
+    }
+
+    /// Returns the params of `block`.
+    pub fn params(&self, block: Block) -> &[Value] {
+        &self.params[block]
+    }
+
+    /// Returns the args that the branch of `from` passes to the params of `to`.
+    pub fn args(&self, from: Block, to: Block) -> &[Value] {
+        self.args.get(&(from, to)).map_or(&[], |args| args)
+    }
+
+    /// Returns `true` if `insn` is a phi that is written as a param of its block.
+    pub fn is_param_phi(&self, block: Block, insn: Insn) -> bool {
+        self.phis[block].contains(&insn)
+    }
+}
+
+/// A phi can't be written as a block param.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockParamsError {
+    /// The phi has no arg from `pred`, a predecessor of its block.
+    MissingArg { phi: Insn, pred: Block },
+    /// The phi has an arg from `from`, which isn't a predecessor of its block.
+    StrayArg { phi: Insn, from: Block },
+}
+
+impl fmt::Display for BlockParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingArg { phi, pred } => {
+                write!(f, "phi {phi:?} has no arg from the predecessor {pred:?}")
+            }
+            Self::StrayArg { phi, from } => {
+                write!(
+                    f,
+                    "phi {phi:?} has an arg from {from:?}, which isn't a predecessor"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlockParamsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::test_util::*, Type};
+
+    #[test]
+    fn loop_counter() {
+        let mut builder = test_func_builder(&[Type::I64], Type::I64);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let arg = builder.args()[0];
+
+        builder.switch_to_block(b0);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        let i = builder.phi(Type::I64, &[(arg, b0)]);
+        let one = builder.make_imm_value(1i64);
+        let next = builder.add(i, one);
+        builder.append_phi_arg(i, next, b1);
+        let cond = builder.eq(next, one);
+        builder.br(cond, b2, b1);
+
+        builder.switch_to_block(b2);
+        builder.ret(Some(i));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &module.funcs[func_ref];
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+
+        let params = BlockParams::from_phis(func, &cfg).unwrap();
+        assert_eq!(params.params(b1), [i]);
+        assert_eq!(params.args(b0, b1), [arg]);
+        assert_eq!(params.args(b1, b1), [next]);
+        assert!(params.params(b2).is_empty());
+        assert!(params.args(b1, b2).is_empty());
+    }
+}
@@ -2,9 +2,10 @@ use smallvec::SmallVec;
 
 use crate::{
     func_cursor::{CursorLocation, FuncCursor},
-    insn::{BinaryOp, CastOp, DataLocationKind, InsnData, UnaryOp},
-    module::FuncRef,
-    Block, Function, GlobalVariable, Immediate, Type, Value,
+    insn::{BinaryOp, CastOp, DataLocationKind, InsnData, MemOp, OverflowOp, TrapReason, UnaryOp},
+    module::{FuncRef, WidthPolicy},
+    types::{BYTES_LEN_FIELD, BYTES_PTR_FIELD},
+    Block, BranchWeights, Function, GlobalVariable, Immediate, Intrinsic, SourceLoc, Type, Value,
 };
 
 use super::{
@@ -18,6 +19,7 @@ pub struct FunctionBuilder<C> {
     func_ref: FuncRef,
     pub cursor: C,
     ssa_builder: SsaBuilder,
+    loc: Option<SourceLoc>,
 }
 
 macro_rules! impl_binary_insn {
@@ -28,6 +30,14 @@ macro_rules! impl_binary_insn {
     };
 }
 
+macro_rules! impl_overflow_insn {
+    ($name:ident, $code:path) => {
+        pub fn $name(&mut self, lhs: Value, rhs: Value) -> (Value, Value) {
+            self.overflow_binary_op($code, lhs, rhs)
+        }
+    };
+}
+
 macro_rules! impl_cast_insn {
     ($name:ident, $code:path) => {
         pub fn $name(&mut self, lhs: Value, ty: Type) -> Value {
@@ -48,6 +58,7 @@ where
             func_ref,
             cursor,
             ssa_builder: SsaBuilder::new(),
+            loc: None,
         }
     }
 
@@ -88,6 +99,11 @@ where
         self.cursor.set_location(CursorLocation::BlockBottom(block));
     }
 
+    /// Set the source location attached to insns built after this call.
+    pub fn set_loc(&mut self, loc: Option<SourceLoc>) {
+        self.loc = loc;
+    }
+
     pub fn make_imm_value<Imm>(&mut self, imm: Imm) -> Value
     where
         Imm: Into<Immediate>,
@@ -108,6 +124,10 @@ where
         self.module_builder.declare_array_type(elem, len)
     }
 
+    pub fn bytes_type(&mut self) -> Type {
+        self.module_builder.bytes_type()
+    }
+
     pub fn declare_struct_type(&mut self, name: &str, fields: &[Type], packed: bool) -> Type {
         self.module_builder
             .declare_struct_type(name, fields, packed)
@@ -129,7 +149,24 @@ where
         self.unary_op(UnaryOp::Neg, lhs)
     }
 
+    pub fn clz(&mut self, lhs: Value) -> Value {
+        self.unary_op(UnaryOp::Clz, lhs)
+    }
+
+    pub fn ctz(&mut self, lhs: Value) -> Value {
+        self.unary_op(UnaryOp::Ctz, lhs)
+    }
+
+    pub fn popcount(&mut self, lhs: Value) -> Value {
+        self.unary_op(UnaryOp::Popcount, lhs)
+    }
+
+    pub fn bswap(&mut self, lhs: Value) -> Value {
+        self.unary_op(UnaryOp::Bswap, lhs)
+    }
+
     pub fn binary_op(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        let (lhs, rhs) = self.unify_widths(op, lhs, rhs);
         let insn_data = InsnData::Binary {
             code: op,
             args: [lhs, rhs],
@@ -154,6 +191,26 @@ where
     impl_binary_insn!(ne, BinaryOp::Ne);
     impl_binary_insn!(and, BinaryOp::And);
     impl_binary_insn!(or, BinaryOp::Or);
+    impl_binary_insn!(xor, BinaryOp::Xor);
+    impl_binary_insn!(shl, BinaryOp::Shl);
+    impl_binary_insn!(shr, BinaryOp::Shr);
+    impl_binary_insn!(sar, BinaryOp::Sar);
+    impl_binary_insn!(rotl, BinaryOp::Rotl);
+    impl_binary_insn!(rotr, BinaryOp::Rotr);
+
+    /// Inserts a binary op with an overflow check, and returns its wrapped result and its overflow
+    /// flag. Unlike [`Self::binary_op`], the operands must have the same type.
+    pub fn overflow_binary_op(&mut self, op: OverflowOp, lhs: Value, rhs: Value) -> (Value, Value) {
+        let results = self.insert_insn_with_results(InsnData::overflow_binary(op, lhs, rhs));
+        (results[0], results[1])
+    }
+
+    impl_overflow_insn!(uaddo, OverflowOp::Uaddo);
+    impl_overflow_insn!(saddo, OverflowOp::Saddo);
+    impl_overflow_insn!(usubo, OverflowOp::Usubo);
+    impl_overflow_insn!(ssubo, OverflowOp::Ssubo);
+    impl_overflow_insn!(umulo, OverflowOp::Umulo);
+    impl_overflow_insn!(smulo, OverflowOp::Smulo);
 
     pub fn cast_op(&mut self, op: CastOp, value: Value, ty: Type) -> Value {
         let insn_data = InsnData::Cast {
@@ -164,6 +221,46 @@ where
         self.insert_insn(insn_data).unwrap()
     }
 
+    /// Makes integer operands of `op` the same width as the [`WidthPolicy`] of the module
+    /// dictates. The operands of shifts and rotates are left as they are, since the amount may be
+    /// of any width.
+    ///
+    /// # Panics
+    /// Panics if the operands have different widths and the policy is [`WidthPolicy::Strict`].
+    pub fn unify_widths(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> (Value, Value) {
+        let lhs_ty = self.func.dfg.value_ty(lhs);
+        let rhs_ty = self.func.dfg.value_ty(rhs);
+        if lhs_ty == rhs_ty || op.is_shift() || !lhs_ty.is_integral() || !rhs_ty.is_integral() {
+            return (lhs, rhs);
+        }
+
+        match self.module_builder.ctx.width_policy {
+            WidthPolicy::Strict => panic!(
+                "operands of `{op}` have different widths: `{}` and `{}`",
+                lhs_ty.to_string(&self.func.dfg),
+                rhs_ty.to_string(&self.func.dfg)
+            ),
+            WidthPolicy::Widen if lhs_ty < rhs_ty => {
+                (self.extend(lhs, rhs_ty, op.is_signed()), rhs)
+            }
+            WidthPolicy::Widen => (lhs, self.extend(rhs, lhs_ty, op.is_signed())),
+        }
+    }
+
+    /// Extends an integer `value` to `ty`. Immediates are extended in place.
+    fn extend(&mut self, value: Value, ty: Type, signed: bool) -> Value {
+        if let Some(imm) = self.func.dfg.value_imm(value) {
+            let imm = if signed { imm.sext(ty) } else { imm.zext(ty) };
+            return self.make_imm_value(imm);
+        }
+
+        if signed {
+            self.sext(value, ty)
+        } else {
+            self.zext(value, ty)
+        }
+    }
+
     impl_cast_insn!(sext, CastOp::Sext);
     impl_cast_insn!(zext, CastOp::Zext);
     impl_cast_insn!(trunc, CastOp::Trunc);
@@ -182,6 +279,25 @@ where
         self.insert_insn(insn_data);
     }
 
+    pub fn mem_op(&mut self, op: MemOp, loc: DataLocationKind, dst: Value, src: Value, len: Value) {
+        self.insert_insn(InsnData::mem(op, dst, src, len, loc));
+    }
+
+    /// Copies `len` bytes, or slots in storage, from `src` to `dst`. The ranges must not overlap.
+    pub fn memcpy(&mut self, loc: DataLocationKind, dst: Value, src: Value, len: Value) {
+        self.mem_op(MemOp::Memcpy, loc, dst, src, len)
+    }
+
+    /// Copies `len` bytes, or slots in storage, from `src` to `dst`. The ranges may overlap.
+    pub fn memmove(&mut self, loc: DataLocationKind, dst: Value, src: Value, len: Value) {
+        self.mem_op(MemOp::Memmove, loc, dst, src, len)
+    }
+
+    /// Fills `len` bytes, or slots in storage, at `dst` with the `i8` value `byte`.
+    pub fn memset(&mut self, loc: DataLocationKind, dst: Value, byte: Value, len: Value) {
+        self.mem_op(MemOp::Memset, loc, dst, byte, len)
+    }
+
     /// Build memory load instruction.
     pub fn memory_load(&mut self, addr: Value) -> Value {
         self.load(DataLocationKind::Memory, addr)
@@ -273,21 +389,102 @@ where
             func,
             args: args.into(),
             ret_ty: sig.ret_ty(),
+            is_tail: false,
         };
         self.func.callees.insert(func, sig);
         self.insert_insn(insn_data)
     }
 
+    pub fn intrinsic(&mut self, intrinsic: Intrinsic, args: &[Value]) -> Option<Value> {
+        let ret_ty = self
+            .module_builder
+            .ctx
+            .isa
+            .with_intrinsics(|r| r.intrinsic_data(intrinsic).ret_ty);
+        let insn_data = InsnData::Intrinsic {
+            intrinsic,
+            args: args.into(),
+            ret_ty,
+        };
+        self.insert_insn(insn_data)
+    }
+
     pub fn ret(&mut self, args: Option<Value>) {
         let insn_data = InsnData::Return { args };
         self.insert_insn(insn_data);
     }
 
+    pub fn revert(&mut self, selector: [u8; 4], args: &[Value]) {
+        let insn_data = InsnData::Revert {
+            selector,
+            args: args.into(),
+        };
+        self.insert_insn(insn_data);
+    }
+
+    pub fn trap(&mut self, reason: TrapReason) {
+        let insn_data = InsnData::Trap { reason };
+        self.insert_insn(insn_data);
+    }
+
+    pub fn unreachable(&mut self) {
+        self.insert_insn(InsnData::Unreachable);
+    }
+
+    /// Attaches `weights` to the branch that ends the current block, e.g., `&[1, 99]` for a `br`
+    /// whose `else` destination is hot, see [`BranchWeights`].
+    ///
+    /// # Panics
+    /// Panics if the current block doesn't end in a branch with as many destinations as `weights`.
+    pub fn set_branch_weights(&mut self, weights: &[u32]) {
+        let block = self.cursor.block(&self.func).unwrap();
+        let branch = self.func.layout.last_insn_of(block).unwrap();
+        self.func
+            .dfg
+            .set_branch_weights(branch, Some(BranchWeights::new(weights)));
+    }
+
     pub fn gep(&mut self, args: &[Value]) -> Option<Value> {
         let insn_data = InsnData::Gep { args: args.into() };
         self.insert_insn(insn_data)
     }
 
+    /// Build a `bytes` descriptor on the stack frame from a pointer to the first byte and a
+    /// length, and returns the pointer to the descriptor.
+    pub fn make_bytes(&mut self, data: Value, len: Value) -> Value {
+        let bytes_ty = self.bytes_type();
+        let desc = self.alloca(bytes_ty);
+
+        let ptr_addr = self.bytes_field_addr(desc, BYTES_PTR_FIELD);
+        self.memory_store(ptr_addr, data);
+        let len_addr = self.bytes_field_addr(desc, BYTES_LEN_FIELD);
+        self.memory_store(len_addr, len);
+
+        desc
+    }
+
+    /// Load the pointer to the first byte from the `bytes` descriptor pointed by `desc`.
+    pub fn bytes_ptr(&mut self, desc: Value) -> Value {
+        let addr = self.bytes_field_addr(desc, BYTES_PTR_FIELD);
+        self.memory_load(addr)
+    }
+
+    /// Load the length in bytes from the `bytes` descriptor pointed by `desc`.
+    pub fn bytes_len(&mut self, desc: Value) -> Value {
+        let addr = self.bytes_field_addr(desc, BYTES_LEN_FIELD);
+        self.memory_load(addr)
+    }
+
+    fn bytes_field_addr(&mut self, desc: Value, field: usize) -> Value {
+        debug_assert!(self.module_builder.ctx.with_ty_store(|s| {
+            let ty = self.func.dfg.value_ty(desc);
+            s.deref(ty).is_some_and(|pointee| s.is_bytes(pointee))
+        }));
+
+        let field = self.make_imm_value(field as i32);
+        self.gep(&[desc, field]).unwrap()
+    }
+
     pub fn phi(&mut self, ty: Type, args: &[(Value, Block)]) -> Value {
         let insn_data = InsnData::Phi {
             values: args.iter().map(|(val, _)| *val).collect(),
@@ -347,6 +544,10 @@ where
         &self.func.arg_values
     }
 
+    pub fn word_type(&self) -> Type {
+        self.module_builder.word_type()
+    }
+
     pub fn address_type(&self) -> Type {
         self.module_builder.ctx.isa.type_provider().address_type()
     }
@@ -360,19 +561,22 @@ where
     }
 
     fn insert_insn(&mut self, insn_data: InsnData) -> Option<Value> {
+        self.insert_insn_with_results(insn_data).first().copied()
+    }
+
+    fn insert_insn_with_results(&mut self, insn_data: InsnData) -> SmallVec<[Value; 2]> {
         let insn = self.cursor.insert_insn_data(&mut self.func, insn_data);
-        let result = self.cursor.make_result(&mut self.func, insn);
-        if let Some(result) = result {
-            self.cursor.attach_result(&mut self.func, insn, result);
-        }
+        self.func.dfg.set_insn_loc(insn, self.loc);
+        let results = self.cursor.make_and_attach_results(&mut self.func, insn);
         self.cursor.set_location(CursorLocation::At(insn));
-        result
+        results
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{super::test_util::*, *};
+    use crate::{func_cursor::InsnInserter, module::ModuleCtx, Linkage, Signature};
 
     #[test]
     fn entry_block() {
@@ -398,6 +602,106 @@ mod tests {
         v3.i8 = sub v2 1.i8;
         return;
 
+}
+"
+        );
+    }
+
+    #[test]
+    fn overflow_checked_add() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I1);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg0 = builder.args()[0];
+        let (sum, overflow) = builder.uaddo(arg0, arg0);
+        builder.ret(Some(overflow));
+
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let dfg = &module.funcs[func_ref].dfg;
+        let insn = dfg.value_insn(sum).unwrap();
+        assert_eq!(dfg.insn_results(insn), &[sum, overflow]);
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i32) -> i1 {
+    block0:
+        v1.i32, v2.i1 = uaddo v0 v0;
+        return v2;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn shift_amount_width_strict() {
+        let mut builder = test_func_builder(&[Type::I64, Type::I8], Type::I64);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let args = builder.args();
+        let (arg0, arg1) = (args[0], args[1]);
+        let v2 = builder.shl(arg0, arg1);
+        builder.ret(Some(v2));
+
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i64, v1.i8) -> i64 {
+    block0:
+        v2.i64 = shl v0 v1;
+        return v2;
+
+}
+"
+        );
+    }
+
+    #[test]
+    fn shift_amount_width_widen() {
+        let mut ctx = ModuleCtx::new(build_test_isa());
+        ctx.width_policy = WidthPolicy::Widen;
+        let mut mb = ModuleBuilder::new(ctx);
+        let sig = Signature::new(
+            "test_func",
+            Linkage::Public,
+            &[Type::I8, Type::I64],
+            Type::I8,
+        );
+        let func_ref = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(func_ref);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let args = builder.args();
+        let (arg0, arg1) = (args[0], args[1]);
+        let v2 = builder.sar(arg0, arg1);
+        let v3 = builder.make_imm_value(1i64);
+        let v3 = builder.rotl(v2, v3);
+        let v4 = builder.add(v3, arg1);
+        let v5 = builder.trunc(v4, Type::I8);
+        builder.ret(Some(v5));
+
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i8, v1.i64) -> i8 {
+    block0:
+        v2.i8 = sar v0 v1;
+        v4.i8 = rotl v2 1.i64;
+        v5.i64 = zext v4;
+        v6.i64 = add v5 v1;
+        v7.i8 = trunc v6;
+        return v7;
+
 }
 "
         );
@@ -505,6 +809,40 @@ mod tests {
         v4.i64 = add v3 v0;
         return;
 
+}
+"
+        );
+    }
+
+    #[test]
+    fn bytes_descriptor() {
+        let mut builder = test_func_builder(&[Type::I256], Type::I256);
+
+        let entry_block = builder.append_block();
+        builder.switch_to_block(entry_block);
+        let len = builder.args()[0];
+        let data = builder.alloca(Type::I8);
+        let desc = builder.make_bytes(data, len);
+        let len = builder.bytes_len(desc);
+        builder.ret(Some(len));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i256) -> i256 {
+    block0:
+        v1.*i8 = alloca i8;
+        v2.*bytes = alloca bytes;
+        v4.**i8 = gep v2 0.i32;
+        store @memory v4 v1;
+        v6.*i256 = gep v2 1.i32;
+        store @memory v6 v0;
+        v7.*i256 = gep v2 1.i32;
+        v8.i256 = load @memory v7;
+        return v8;
+
 }
 "
         );
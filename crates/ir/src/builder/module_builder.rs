@@ -1,10 +1,14 @@
 use cranelift_entity::PrimaryMap;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{
+    contract::Contracts,
     func_cursor::{CursorLocation, FuncCursor},
+    intrinsic::IntrinsicError,
     module::{FuncRef, ModuleCtx},
-    Function, GlobalVariable, GlobalVariableData, Module, Signature, Type,
+    static_init::StaticInits,
+    Function, GlobalVariable, GlobalVariableData, Intrinsic, IntrinsicData, Linkage, Module,
+    Signature, Type,
 };
 
 use super::FunctionBuilder;
@@ -15,6 +19,10 @@ pub struct ModuleBuilder {
 
     pub ctx: ModuleCtx,
 
+    pub static_inits: StaticInits,
+
+    pub contracts: Contracts,
+
     /// Map function name -> FuncRef to avoid duplicated declaration.
     declared_funcs: FxHashMap<String, FuncRef>,
 }
@@ -24,6 +32,8 @@ impl ModuleBuilder {
         Self {
             funcs: PrimaryMap::default(),
             ctx,
+            static_inits: StaticInits::default(),
+            contracts: Contracts::default(),
             declared_funcs: FxHashMap::default(),
         }
     }
@@ -40,6 +50,28 @@ impl ModuleBuilder {
         }
     }
 
+    /// Declares a function that is defined outside of the module, e.g., in a library linked to
+    /// the module later. The function has [`Linkage::External`] and no body, but can be called
+    /// like any other function.
+    pub fn declare_external_function(
+        &mut self,
+        name: &str,
+        args: &[Type],
+        ret_ty: Type,
+    ) -> FuncRef {
+        self.declare_function(Signature::new(name, Linkage::External, args, ret_ty))
+    }
+
+    /// Registers `func` as a static initializer that runs after the initializers in `deps`.
+    pub fn add_static_init(&mut self, func: FuncRef, deps: &[FuncRef]) {
+        self.static_inits.register(func, deps);
+    }
+
+    /// Defines the contract `name`, whose external functions are `entries`.
+    pub fn define_contract(&mut self, name: &str, entries: &[FuncRef]) {
+        self.contracts.define(name, entries);
+    }
+
     pub fn sig(&self, func: FuncRef) -> &Signature {
         &self.funcs[func].sig
     }
@@ -69,6 +101,15 @@ impl ModuleBuilder {
         self.ctx.with_ty_store_mut(|s| s.make_ptr(ty))
     }
 
+    pub fn bytes_type(&mut self) -> Type {
+        self.ctx.with_ty_store_mut(|s| s.make_bytes())
+    }
+
+    /// Returns the integer type `word` resolves to on the target.
+    pub fn word_type(&self) -> Type {
+        self.ctx.isa.type_provider().word_type()
+    }
+
     pub fn get_func_ref(&self, name: &str) -> Option<FuncRef> {
         self.declared_funcs.get(name).copied()
     }
@@ -77,6 +118,17 @@ impl ModuleBuilder {
         &self.funcs[func].sig
     }
 
+    /// Registers an intrinsic of the frontend with the target. Declaring an intrinsic that is
+    /// already registered returns it, as long as the signatures are the same.
+    pub fn declare_intrinsic(&mut self, data: IntrinsicData) -> Result<Intrinsic, IntrinsicError> {
+        self.ctx.isa.with_intrinsics_mut(|r| r.register(data))
+    }
+
+    /// Returns the intrinsic `name` of the target, or one declared by the frontend.
+    pub fn get_intrinsic(&self, name: &str) -> Option<Intrinsic> {
+        self.ctx.isa.with_intrinsics(|r| r.intrinsic_by_name(name))
+    }
+
     pub fn build_function<C>(self, func: FuncRef) -> FunctionBuilder<C>
     where
         C: FuncCursor,
@@ -89,6 +141,9 @@ impl ModuleBuilder {
         Module {
             funcs: self.funcs,
             ctx: self.ctx,
+            static_inits: self.static_inits,
+            contracts: self.contracts,
+            removed_funcs: FxHashSet::default(),
         }
     }
 }
@@ -25,6 +25,10 @@ pub(super) struct SsaBuilder {
 
     /// Records trivial phis.
     trivial_phis: SparseSet<Insn>,
+
+    /// Maps the results of removed trivial phis to the values that replaced them, since blocks may
+    /// still record them as the definitions of variables.
+    aliases: SecondaryMap<Value, PackedOption<Value>>,
 }
 
 impl SsaBuilder {
@@ -33,6 +37,7 @@ impl SsaBuilder {
             blocks: SecondaryMap::default(),
             vars: PrimaryMap::default(),
             trivial_phis: SparseSet::new(),
+            aliases: SecondaryMap::default(),
         }
     }
     pub(super) fn declare_var(&mut self, ty: Type) -> Variable {
@@ -44,11 +49,12 @@ impl SsaBuilder {
     }
 
     pub(super) fn use_var(&mut self, func: &mut Function, var: Variable, block: Block) -> Value {
-        if let Some(value) = self.blocks[block].use_var_local(var) {
+        let value = if let Some(value) = self.blocks[block].use_var_local(var) {
             value
         } else {
             self.use_var_recursive(func, var, block)
-        }
+        };
+        self.resolve_alias(value)
     }
 
     pub(super) fn var_ty(&mut self, var: Variable) -> Type {
@@ -116,31 +122,51 @@ impl SsaBuilder {
         self.remove_trivial_phi(func, phi);
     }
 
+    /// Removes `phi` if all its arguments are the same value, ignoring references to the phi
+    /// itself, and then its phi users that become trivial by the removal.
     fn remove_trivial_phi(&mut self, func: &mut Function, phi: Insn) {
         let phi_value = func.dfg.insn_result(phi).unwrap();
-        let phi_args = func.dfg.insn_args(phi);
-        if phi_args.is_empty() {
-            panic!("variable is undefined or used in unreachable block");
-        }
-
-        let first = phi_args[0];
-
-        if phi_args.iter().any(|arg| *arg != first) {
-            return;
+        let mut same = None;
+        for &arg in func.dfg.insn_args(phi) {
+            if Some(arg) == same || arg == phi_value {
+                continue;
+            }
+            if same.is_some() {
+                return;
+            }
+            same = Some(arg);
         }
+        let Some(same) = same else {
+            panic!("variable is undefined or used in unreachable block");
+        };
 
-        func.dfg.change_to_alias(phi_value, first);
+        let users: Vec<_> = func
+            .dfg
+            .users(phi_value)
+            .copied()
+            .filter(|user| *user != phi)
+            .collect();
+        func.dfg.change_to_alias(phi_value, same);
+        self.aliases[phi_value] = same.into();
         self.trivial_phis.insert(phi);
         InsnInserter::at_location(CursorLocation::At(phi)).remove_insn(func);
 
-        for i in 0..func.dfg.users_num(phi_value) {
-            let user = func.dfg.user(phi_value, i);
+        for user in users {
             if func.dfg.is_phi(user) && !self.trivial_phis.contains_key(user) {
                 self.remove_trivial_phi(func, user);
             }
         }
     }
 
+    /// Returns the value `value` stands for, which differs from `value` if it's the result of a
+    /// removed trivial phi.
+    fn resolve_alias(&self, mut value: Value) -> Value {
+        while let Some(alias) = self.aliases[value].expand() {
+            value = alias;
+        }
+        value
+    }
+
     fn prepend_phi(&mut self, func: &mut Function, var: Variable, block: Block) -> (Insn, Value) {
         let ty = self.var_ty(var);
         let insn_data = InsnData::Phi {
@@ -452,6 +478,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn use_var_loop_invariant() {
+        let mut builder = test_func_builder(&[], Type::Void);
+
+        let var = builder.declare_var(Type::I32);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+
+        builder.switch_to_block(b0);
+        let value = builder.make_imm_value(1i32);
+        builder.def_var(var, value);
+        builder.jump(b1);
+        builder.seal_block();
+
+        builder.switch_to_block(b1);
+        builder.br(value, b2, b3);
+
+        // The phi placed in the unsealed header only refers to itself from the latch.
+        builder.switch_to_block(b2);
+        builder.seal_block();
+        let val = builder.use_var(var);
+        builder.add(val, val);
+        builder.jump(b1);
+
+        builder.switch_to_block(b1);
+        builder.seal_block();
+
+        builder.switch_to_block(b3);
+        builder.seal_block();
+        let val = builder.use_var(var);
+        builder.add(val, val);
+        builder.ret(None);
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let dumped = dump_func(&module, func_ref);
+        assert!(!dumped.contains("phi"));
+        assert_eq!(dumped.matches("add 1.i32 1.i32").count(), 2);
+    }
+
     #[test]
     fn use_var_global_complex() {
         let mut builder = test_func_builder(&[], Type::Void);
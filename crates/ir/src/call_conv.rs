@@ -0,0 +1,69 @@
+use std::{fmt, str::FromStr};
+
+use crate::Linkage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// Calling convention of functions, which decides how backends call a function and how it's
+/// reached from the outside of the contract.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CallConv {
+    #[default]
+    /// The function is called by jumping to it from the code of the same contract.
+    Internal,
+
+    /// The function is an external function of the contract: the dispatcher calls it for the
+    /// selector of its canonical signature, ABI-decoding its arguments from calldata and
+    /// ABI-encoding its result as the returned data.
+    SolidityExternal,
+
+    /// The function runs when the selector in calldata matches no external function. It takes no
+    /// arguments and returns nothing.
+    Fallback,
+
+    /// The function is an external function of another contract, which is called by a message
+    /// call with ABI-encoded arguments, so it runs on the storage of that contract.
+    Contract,
+}
+
+impl CallConv {
+    /// Returns the calling convention a function with `linkage` has unless it's set explicitly:
+    /// public functions are external functions of the contract, and other functions are internal.
+    pub fn default_for(linkage: Linkage) -> Self {
+        match linkage {
+            Linkage::Public => Self::SolidityExternal,
+            Linkage::Private | Linkage::External => Self::Internal,
+        }
+    }
+
+    /// Returns `true` if a call to a function with the convention may reuse the frame of the
+    /// caller, i.e., the function is lowered as a function of the contract. A message call needs
+    /// its own frame, and a fallback is never called from IR.
+    pub fn allows_tail_call(self) -> bool {
+        matches!(self, Self::Internal | Self::SolidityExternal)
+    }
+}
+
+impl fmt::Display for CallConv {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Internal => write!(f, "internal"),
+            Self::SolidityExternal => write!(f, "solidity_external"),
+            Self::Fallback => write!(f, "fallback"),
+            Self::Contract => write!(f, "contract"),
+        }
+    }
+}
+
+impl FromStr for CallConv {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "internal" => Ok(Self::Internal),
+            "solidity_external" => Ok(Self::SolidityExternal),
+            "fallback" => Ok(Self::Fallback),
+            "contract" => Ok(Self::Contract),
+            _ => Err(()),
+        }
+    }
+}
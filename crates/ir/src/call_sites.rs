@@ -0,0 +1,169 @@
+//! This module contains [`CallSiteIndex`], a reverse index from functions to the call insns that
+//! call them.
+//!
+//! Module passes that look up the callers of a function, e.g., the inliner and dead function
+//! elimination, compute the index once and [update](CallSiteIndex::update) it for each function
+//! they change, instead of scanning every function body again.
+use std::collections::BTreeSet;
+
+use rustc_hash::FxHashMap;
+
+use crate::{module::FuncRef, Function, Insn, InsnData, Module};
+
+/// A call insn and the function it's in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CallSite {
+    pub caller: FuncRef,
+    pub insn: Insn,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallSiteIndex {
+    /// The call sites of each callee.
+    sites: FxHashMap<FuncRef, BTreeSet<CallSite>>,
+    /// The calls in each caller in layout order, with their callees.
+    calls: FxHashMap<FuncRef, Vec<(Insn, FuncRef)>>,
+}
+
+impl CallSiteIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compute(&mut self, module: &Module) {
+        self.clear();
+        for func_ref in module.iter_functions() {
+            self.index(func_ref, &module.funcs[func_ref]);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sites.clear();
+        self.calls.clear();
+    }
+
+    /// Re-indexes the calls in `func_ref`, which must be called after the calls in the function
+    /// change.
+    pub fn update(&mut self, module: &Module, func_ref: FuncRef) {
+        self.remove_function(func_ref);
+        self.index(func_ref, &module.funcs[func_ref]);
+    }
+
+    /// Removes the calls in `func_ref` from the index, e.g., once the function is removed from
+    /// the module.
+    pub fn remove_function(&mut self, func_ref: FuncRef) {
+        for (insn, callee) in self.calls.remove(&func_ref).unwrap_or_default() {
+            let sites = self.sites.get_mut(&callee).unwrap();
+            sites.remove(&CallSite {
+                caller: func_ref,
+                insn,
+            });
+            if sites.is_empty() {
+                self.sites.remove(&callee);
+            }
+        }
+    }
+
+    /// Returns the call sites of `callee` ordered by caller and insn.
+    pub fn call_sites(&self, callee: FuncRef) -> impl Iterator<Item = CallSite> + '_ {
+        self.sites.get(&callee).into_iter().flatten().copied()
+    }
+
+    pub fn call_sites_num(&self, callee: FuncRef) -> usize {
+        self.sites.get(&callee).map_or(0, BTreeSet::len)
+    }
+
+    pub fn is_called(&self, callee: FuncRef) -> bool {
+        self.sites.contains_key(&callee)
+    }
+
+    /// Returns the calls in `caller` in layout order, with their callees.
+    pub fn calls_in(&self, caller: FuncRef) -> &[(Insn, FuncRef)] {
+        self.calls.get(&caller).map_or(&[], Vec::as_slice)
+    }
+
+    fn index(&mut self, func_ref: FuncRef, func: &Function) {
+        let calls: Vec<_> = func
+            .layout
+            .iter_block()
+            .flat_map(|block| func.layout.iter_insn(block))
+            .filter_map(|insn| match func.dfg.insn_data(insn) {
+                InsnData::Call { func, .. } => Some((insn, *func)),
+                _ => None,
+            })
+            .collect();
+        if calls.is_empty() {
+            return;
+        }
+
+        for &(insn, callee) in &calls {
+            self.sites.entry(callee).or_default().insert(CallSite {
+                caller: func_ref,
+                insn,
+            });
+        }
+        self.calls.insert(func_ref, calls);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        builder::{test_util::build_test_isa, ModuleBuilder},
+        func_cursor::InsnInserter,
+        module::ModuleCtx,
+        Linkage, Signature, Type,
+    };
+
+    #[test]
+    fn index_and_update() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let sig = Signature::new("callee", Linkage::Private, &[], Type::I32);
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let one = builder.make_imm_value(1i32);
+        builder.ret(Some(one));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("caller", Linkage::Public, &[], Type::I32);
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v0 = builder.call(callee, &[]).unwrap();
+        let v1 = builder.call(callee, &[]).unwrap();
+        let v2 = builder.add(v0, v1);
+        builder.ret(Some(v2));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        let mut index = CallSiteIndex::new();
+        index.compute(&module);
+        assert_eq!(index.call_sites_num(callee), 2);
+        assert!(!index.is_called(caller));
+        let first = index.calls_in(caller)[0].0;
+        assert_eq!(
+            index.call_sites(callee).next(),
+            Some(CallSite {
+                caller,
+                insn: first,
+            })
+        );
+
+        let func = &mut module.funcs[caller];
+        func.dfg.change_to_alias(v0, v1);
+        func.remove_insn(first).unwrap();
+        index.update(&module, caller);
+        assert_eq!(index.call_sites_num(callee), 1);
+        assert_eq!(index.calls_in(caller).len(), 1);
+
+        index.remove_function(caller);
+        assert!(!index.is_called(callee));
+        assert!(index.calls_in(caller).is_empty());
+    }
+}
@@ -77,7 +77,7 @@ impl ControlFlowGraph {
     }
 
     fn analyze_insn(&mut self, func: &Function, insn: Insn) {
-        if func.dfg.is_return(insn) {
+        if func.dfg.is_exit(insn) {
             let exit = func.layout.insn_block(insn);
             self.exits.push(exit);
         }
@@ -0,0 +1,301 @@
+//! This module contains [`FunctionCloner`], which copies the body of a function into another
+//! function, or into a new one, and records how the blocks, insns and values of the source map to
+//! the copies.
+//!
+//! Transforms that duplicate code, e.g., inlining, outlining, specialization and loop unrolling,
+//! clone with it and then patch the copy through the recorded maps.
+use rustc_hash::FxHashMap;
+
+use crate::{Block, Function, Immediate, Insn, InsnData, Signature, Value, ValueData};
+
+/// Returns a copy of `func` that contains only the blocks and insns in its layout, and the values
+/// they refer to.
+pub fn clone_function(func: &Function) -> Function {
+    FunctionCloner::new(func).clone_with_sig(func.sig.clone())
+}
+
+pub struct FunctionCloner<'a> {
+    src: &'a Function,
+    values: FxHashMap<Value, Value>,
+    imms: FxHashMap<Value, Immediate>,
+    blocks: FxHashMap<Block, Block>,
+    insns: FxHashMap<Insn, Insn>,
+}
+
+impl<'a> FunctionCloner<'a> {
+    pub fn new(src: &'a Function) -> Self {
+        Self {
+            src,
+            values: FxHashMap::default(),
+            imms: FxHashMap::default(),
+            blocks: FxHashMap::default(),
+            insns: FxHashMap::default(),
+        }
+    }
+
+    /// Makes the copy use `to` in place of `from`. Every argument of the source function must be
+    /// mapped before cloning into an existing function.
+    pub fn map_value(&mut self, from: Value, to: Value) -> &mut Self {
+        self.values.insert(from, to);
+        self
+    }
+
+    /// Makes the copy use `imm` in place of `from`, e.g., to specialize the copy for an argument.
+    pub fn map_imm(&mut self, from: Value, imm: Immediate) -> &mut Self {
+        self.imms.insert(from, imm);
+        self
+    }
+
+    /// Clones the body of the source function into `dest`. The copied blocks are laid out in the
+    /// source order after `after`, or at the end of `dest` if it's `None`.
+    ///
+    /// The copied insns keep their source locations and branch weights, and the callees of the
+    /// source function are added to the callees of `dest`.
+    pub fn clone_into(&mut self, dest: &mut Function, after: Option<Block>) {
+        let src = self.src;
+        let blocks: Vec<_> = src.layout.iter_block().collect();
+        self.clone_blocks_into(dest, &blocks, after);
+
+        for (func_ref, sig) in &src.callees {
+            dest.callees.insert(*func_ref, sig.clone());
+        }
+    }
+
+    /// Clones `blocks` of the source function into `dest` in the given order, like
+    /// [`Self::clone_into`]. Values the blocks use from other blocks must be mapped, and their
+    /// branches and phis may only refer to `blocks`. Only the callees the copied insns call are
+    /// added to the callees of `dest`.
+    pub fn clone_blocks_into(
+        &mut self,
+        dest: &mut Function,
+        blocks: &[Block],
+        after: Option<Block>,
+    ) {
+        let src = self.src;
+
+        let mut insert_after = after;
+        for &block in blocks {
+            let new_block = dest.dfg.make_block();
+            match insert_after {
+                Some(after) => dest.layout.insert_block_after(new_block, after),
+                None => dest.layout.append_block(new_block),
+            }
+            self.blocks.insert(block, new_block);
+            insert_after = Some(new_block);
+        }
+
+        // Create insns with placeholder data first so that results are available to forward
+        // references, e.g., phi args defined in later blocks.
+        let mut insns = Vec::new();
+        for &block in blocks {
+            let new_block = self.blocks[&block];
+            for insn in src.layout.iter_insn(block) {
+                let new_insn = dest.dfg.make_insn(InsnData::jump(new_block));
+                dest.layout.append_insn(new_insn, new_block);
+                dest.dfg.set_insn_loc(new_insn, src.dfg.insn_loc(insn));
+                for &result in src.dfg.insn_results(insn) {
+                    let ty = src.dfg.value_ty(result);
+                    let new_result = dest.dfg.make_value(ValueData::Insn { insn: new_insn, ty });
+                    dest.dfg.attach_result(new_insn, new_result);
+                    self.values.insert(result, new_result);
+                }
+                self.insns.insert(insn, new_insn);
+                insns.push((insn, new_insn));
+            }
+        }
+
+        for (insn, new_insn) in insns {
+            let mut data = src.dfg.insn_data(insn).clone();
+            for arg in data.args_mut() {
+                *arg = self.clone_value(dest, *arg);
+            }
+            remap_blocks(&mut data, &self.blocks);
+            if let InsnData::Call { func, .. } = &data {
+                dest.callees.insert(*func, src.callees[func].clone());
+            }
+            dest.dfg.replace_insn(new_insn, data);
+            dest.dfg
+                .set_branch_weights(new_insn, src.dfg.branch_weights(insn).cloned());
+        }
+    }
+
+    /// Clones the source function into a new function with `sig`. Arguments of the source
+    /// function that aren't mapped are mapped to the arguments of the new function in order.
+    pub fn clone_with_sig(&mut self, sig: Signature) -> Function {
+        let src = self.src;
+        let mut dest = Function::new(&src.dfg.ctx, sig.with_attrs(src.sig.attrs()));
+
+        let mut new_args = dest.arg_values.iter().copied();
+        for arg in &src.arg_values {
+            if !self.values.contains_key(arg) && !self.imms.contains_key(arg) {
+                let new_arg = new_args
+                    .next()
+                    .expect("the new signature has fewer arguments than unmapped ones");
+                self.values.insert(*arg, new_arg);
+            }
+        }
+
+        self.clone_into(&mut dest, None);
+        dest
+    }
+
+    /// Returns the copy of `value`, if it's been copied or mapped.
+    pub fn value(&self, value: Value) -> Option<Value> {
+        self.values.get(&value).copied()
+    }
+
+    pub fn block(&self, block: Block) -> Option<Block> {
+        self.blocks.get(&block).copied()
+    }
+
+    pub fn insn(&self, insn: Insn) -> Option<Insn> {
+        self.insns.get(&insn).copied()
+    }
+
+    pub fn value_map(&self) -> &FxHashMap<Value, Value> {
+        &self.values
+    }
+
+    pub fn block_map(&self) -> &FxHashMap<Block, Block> {
+        &self.blocks
+    }
+
+    pub fn insn_map(&self) -> &FxHashMap<Insn, Insn> {
+        &self.insns
+    }
+
+    fn clone_value(&mut self, dest: &mut Function, value: Value) -> Value {
+        if let Some(&mapped) = self.values.get(&value) {
+            return mapped;
+        }
+
+        if let Some(imm) = self.imms.get(&value) {
+            let mapped = dest.dfg.make_imm_value(*imm);
+            self.values.insert(value, mapped);
+            return mapped;
+        }
+
+        let mapped = match self.src.dfg.value_data(value) {
+            ValueData::Immediate { imm, .. } => dest.dfg.make_imm_value(*imm),
+            ValueData::Global { gv, .. } => dest.dfg.make_global_value(*gv),
+            ValueData::Arg { idx, .. } => panic!("argument {idx} isn't mapped"),
+            ValueData::Insn { .. } => unreachable!("results are mapped before they are used"),
+        };
+        self.values.insert(value, mapped);
+        mapped
+    }
+}
+
+fn remap_blocks(data: &mut InsnData, block_map: &FxHashMap<Block, Block>) {
+    match data {
+        InsnData::Jump { dests } => {
+            dests[0] = block_map[&dests[0]];
+        }
+
+        InsnData::Branch { dests, .. } => {
+            for dest in dests {
+                *dest = block_map[dest];
+            }
+        }
+
+        InsnData::BrTable { default, table, .. } => {
+            if let Some(default) = default {
+                *default = block_map[default];
+            }
+            for dest in table {
+                *dest = block_map[dest];
+            }
+        }
+
+        InsnData::Phi { blocks, .. } => {
+            for block in blocks {
+                *block = block_map[block];
+            }
+        }
+
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::test_util::*, module::FuncRef, Linkage, Module, Type};
+
+    fn build_module() -> (Module, FuncRef) {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let (cond, x) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(cond, b1, b2);
+
+        builder.switch_to_block(b1);
+        let v2 = builder.add(x, x);
+        builder.jump(b3);
+
+        builder.switch_to_block(b2);
+        let v3 = builder.mul(x, x);
+        builder.jump(b3);
+
+        builder.switch_to_block(b3);
+        let v4 = builder.phi(Type::I32, &[(v2, b1), (v3, b2)]);
+        builder.ret(Some(v4));
+
+        builder.seal_all();
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        (module, func_ref)
+    }
+
+    #[test]
+    fn clone_whole_function() {
+        let (mut module, func_ref) = build_module();
+        let expected = dump_func(&module, func_ref);
+
+        module.funcs[func_ref] = clone_function(&module.funcs[func_ref]);
+        assert_eq!(dump_func(&module, func_ref), expected);
+    }
+
+    #[test]
+    fn clone_with_mapped_arg() {
+        let (mut module, func_ref) = build_module();
+        let func = &module.funcs[func_ref];
+
+        let mut cloner = FunctionCloner::new(func);
+        cloner.map_imm(func.arg_values[0], Immediate::I1(true));
+
+        let sig = Signature::new("test_func", Linkage::Public, &[Type::I32], Type::I32);
+        let cloned = cloner.clone_with_sig(sig);
+        let entry = func.layout.entry_block().unwrap();
+        assert_eq!(cloner.block(entry), cloned.layout.entry_block());
+        assert_eq!(cloner.value(func.arg_values[1]), Some(cloned.arg_values[0]));
+
+        module.funcs[func_ref] = cloned;
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i32) -> i32 {
+    block0:
+        br 1.i1 block1 block2;
+
+    block1:
+        v1.i32 = add v0 v0;
+        jump block3;
+
+    block2:
+        v2.i32 = mul v0 v0;
+        jump block3;
+
+    block3:
+        v3.i32 = phi (v1 block1) (v2 block2);
+        return v3;
+
+}
+"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! This module contains contracts, i.e., the deployable artifacts a module is compiled into.
+//!
+//! A module that defines no contracts is compiled into a single contract whose entries are its
+//! public functions. A frontend for a language with several contracts per file defines each of
+//! them with its own entries instead. The contracts share the other functions of the module, and
+//! each contract only contains the functions reachable from its entries.
+use crate::module::FuncRef;
+
+#[derive(Debug, Clone, Default)]
+pub struct Contracts {
+    contracts: Vec<Contract>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contract {
+    pub name: String,
+    /// The external functions of the contract.
+    pub entries: Vec<FuncRef>,
+}
+
+impl Contracts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines the contract `name` with `entries`. Defining a contract again adds to its entries.
+    pub fn define(&mut self, name: &str, entries: &[FuncRef]) {
+        match self.contracts.iter_mut().find(|c| c.name == name) {
+            Some(contract) => {
+                for entry in entries {
+                    if !contract.entries.contains(entry) {
+                        contract.entries.push(*entry);
+                    }
+                }
+            }
+            None => self.contracts.push(Contract {
+                name: name.to_string(),
+                entries: entries.to_vec(),
+            }),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Contract> {
+        self.contracts.iter().find(|c| c.name == name)
+    }
+
+    /// Returns the contracts in the order they were defined.
+    pub fn iter(&self) -> impl Iterator<Item = &Contract> {
+        self.contracts.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+}
@@ -1,13 +1,15 @@
 //! This module contains Sonatine IR data flow graph.
 use std::collections::BTreeSet;
 
-use cranelift_entity::{entity_impl, packed_option::PackedOption, PrimaryMap, SecondaryMap};
+use cranelift_entity::{entity_impl, PrimaryMap, SecondaryMap};
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
 
-use crate::{global_variable::ConstantValue, module::ModuleCtx, GlobalVariable};
+use crate::{
+    global_variable::ConstantValue, module::ModuleCtx, GlobalVariable, Intrinsic, SourceLoc,
+};
 
-use super::{BranchInfo, Immediate, Insn, InsnData, Type, Value, ValueData};
+use super::{BranchInfo, BranchWeights, Immediate, Insn, InsnData, Type, Value, ValueData};
 
 #[derive(Debug, Clone)]
 pub struct DataFlowGraph {
@@ -17,10 +19,12 @@ pub struct DataFlowGraph {
     #[doc(hidden)]
     pub values: PrimaryMap<Value, ValueData>,
     insns: PrimaryMap<Insn, InsnData>,
-    insn_results: SecondaryMap<Insn, PackedOption<Value>>,
+    insn_results: SecondaryMap<Insn, SmallVec<[Value; 1]>>,
     #[doc(hidden)]
     pub immediates: FxHashMap<Immediate, Value>,
     users: SecondaryMap<Value, BTreeSet<Insn>>,
+    insn_locs: SecondaryMap<Insn, Option<SourceLoc>>,
+    branch_weights: SecondaryMap<Insn, Option<BranchWeights>>,
 }
 
 impl DataFlowGraph {
@@ -33,6 +37,8 @@ impl DataFlowGraph {
             insn_results: SecondaryMap::default(),
             immediates: FxHashMap::default(),
             users: SecondaryMap::default(),
+            insn_locs: SecondaryMap::default(),
+            branch_weights: SecondaryMap::default(),
         }
     }
 
@@ -68,40 +74,104 @@ impl DataFlowGraph {
 
     pub fn make_global_value(&mut self, gv: GlobalVariable) -> Value {
         let gv_ty = self.ctx.with_gv_store(|s| s.ty(gv));
-        let ty = self.ctx.with_ty_store_mut(|s| s.make_ptr(gv_ty));
+        let ty = self.ctx.make_ptr(gv_ty);
         let value_data = ValueData::Global { gv, ty };
         self.make_value(value_data)
     }
 
-    pub fn replace_insn(&mut self, insn: Insn, insn_data: InsnData) {
+    /// Replaces the data of `insn` in place and returns the old data.
+    ///
+    /// The result values of `insn`, its position in the layout and its source location are kept, so
+    /// a 1-for-1 rewrite doesn't need to rewrite users of the results. The types of the results are
+    /// updated to the result types of `insn_data`.
+    pub fn replace_insn(&mut self, insn: Insn, insn_data: InsnData) -> InsnData {
         for i in 0..self.insn_args_num(insn) {
             let arg = self.insn_arg(insn, i);
             self.remove_user(arg, insn);
         }
-        self.insns[insn] = insn_data;
+        let old_data = std::mem::replace(&mut self.insns[insn], insn_data);
         self.attach_user(insn);
+        if self.branch_weights[insn]
+            .as_ref()
+            .is_some_and(|weights| self.analyze_branch(insn).dests_num() != weights.weights().len())
+        {
+            self.branch_weights[insn] = None;
+        }
+
+        if !self.insn_results[insn].is_empty() {
+            let tys = self.insns[insn].result_types(self);
+            assert!(
+                tys.len() >= self.insn_results[insn].len(),
+                "an insn can't be replaced with an insn with fewer results"
+            );
+            for (result, ty) in self.insn_results[insn].iter().zip(tys) {
+                self.values[*result] = ValueData::Insn { insn, ty };
+            }
+        }
+
+        old_data
     }
 
     pub fn change_to_alias(&mut self, value: Value, alias: Value) {
-        let mut users = std::mem::take(&mut self.users[value]);
+        self.replace_uses(value, alias);
+    }
+
+    /// Rewrites every use of `old` to `new`. Only the users of `old` are visited, so the cost
+    /// doesn't depend on the size of the function.
+    pub fn replace_uses(&mut self, old: Value, new: Value) {
+        if old == new {
+            return;
+        }
+
+        let mut users = std::mem::take(&mut self.users[old]);
         for insn in &users {
             for arg in self.insns[*insn].args_mut() {
-                if *arg == value {
-                    *arg = alias;
+                if *arg == old {
+                    *arg = new;
                 }
             }
         }
-        self.users[alias].append(&mut users);
+        self.users[new].append(&mut users);
+    }
+
+    /// Rewrites the uses of `old` in `insn` to `new`, and leaves the other users of `old` as is.
+    pub fn replace_uses_in(&mut self, insn: Insn, old: Value, new: Value) {
+        if old == new || !self.users[old].remove(&insn) {
+            return;
+        }
+
+        for arg in self.insns[insn].args_mut() {
+            if *arg == old {
+                *arg = new;
+            }
+        }
+        self.users[new].insert(insn);
     }
 
+    /// Returns the data of the first result of `insn`.
     pub fn make_result(&mut self, insn: Insn) -> Option<ValueData> {
         let ty = self.insns[insn].result_type(self)?;
         Some(ValueData::Insn { insn, ty })
     }
 
+    /// Returns the data of all results of `insn` in order.
+    pub fn make_results(&mut self, insn: Insn) -> SmallVec<[ValueData; 2]> {
+        self.insns[insn]
+            .result_types(self)
+            .into_iter()
+            .map(|ty| ValueData::Insn { insn, ty })
+            .collect()
+    }
+
+    /// Attaches `value` as the next result of `insn`.
     pub fn attach_result(&mut self, insn: Insn, value: Value) {
-        debug_assert!(self.insn_results[insn].is_none());
-        self.insn_results[insn] = value.into();
+        debug_assert!(!self.insn_results[insn].contains(&value));
+        self.insn_results[insn].push(value);
+    }
+
+    /// Detaches the results from `insn` and returns them.
+    pub fn detach_results(&mut self, insn: Insn) -> SmallVec<[Value; 1]> {
+        std::mem::take(&mut self.insn_results[insn])
     }
 
     pub fn make_arg_value(&mut self, ty: Type, idx: usize) -> ValueData {
@@ -116,12 +186,68 @@ impl DataFlowGraph {
         &self.values[value]
     }
 
+    /// Returns the source location that `insn` originates from.
+    pub fn insn_loc(&self, insn: Insn) -> Option<SourceLoc> {
+        self.insn_locs[insn]
+    }
+
+    pub fn set_insn_loc(&mut self, insn: Insn, loc: Option<SourceLoc>) {
+        self.insn_locs[insn] = loc;
+    }
+
+    /// Returns the weights of the destinations of the branch `insn`, if a frontend or a profile
+    /// attached them.
+    pub fn branch_weights(&self, insn: Insn) -> Option<&BranchWeights> {
+        self.branch_weights[insn].as_ref()
+    }
+
+    /// # Panics
+    /// Panics if `insn` isn't a branch with as many destinations as `weights` has weights.
+    pub fn set_branch_weights(&mut self, insn: Insn, weights: Option<BranchWeights>) {
+        if let Some(weights) = &weights {
+            assert_eq!(
+                self.analyze_branch(insn).dests_num(),
+                weights.weights().len(),
+                "branch weights must match the destinations"
+            );
+        }
+        self.branch_weights[insn] = weights;
+    }
+
+    /// Returns the weight of the edge from the branch `insn` to `dest`, i.e., the sum of the
+    /// weights of the destinations that are `dest`.
+    pub fn edge_weight(&self, insn: Insn, dest: Block) -> Option<u64> {
+        let weights = self.branch_weights(insn)?;
+        let weight = self
+            .analyze_branch(insn)
+            .iter_dests()
+            .zip(weights.weights())
+            .filter(|(block, _)| *block == dest)
+            .map(|(_, &weight)| u64::from(weight))
+            .sum();
+        Some(weight)
+    }
+
+    /// Returns `true` if `insn` may have a side effect. Unlike [`InsnData::has_side_effect`], a
+    /// pure intrinsic has none.
     pub fn has_side_effect(&self, insn: Insn) -> bool {
-        self.insns[insn].has_side_effect()
+        match self.insns[insn] {
+            InsnData::Intrinsic { intrinsic, .. } => self.is_effectful_intrinsic(intrinsic),
+            ref data => data.has_side_effect(),
+        }
     }
 
     pub fn may_trap(&self, insn: Insn) -> bool {
-        self.insns[insn].may_trap()
+        match self.insns[insn] {
+            InsnData::Intrinsic { intrinsic, .. } => self.is_effectful_intrinsic(intrinsic),
+            ref data => data.may_trap(),
+        }
+    }
+
+    fn is_effectful_intrinsic(&self, intrinsic: Intrinsic) -> bool {
+        self.ctx
+            .isa
+            .with_intrinsics(|r| r.intrinsic_data(intrinsic).has_side_effect)
     }
 
     pub fn attach_user(&mut self, insn: Insn) {
@@ -167,6 +293,7 @@ impl DataFlowGraph {
         }
     }
 
+    /// Returns the type of the first result of `insn`.
     pub fn insn_result_ty(&self, insn: Insn) -> Option<Type> {
         self.insn_result(insn).map(|value| self.value_ty(value))
     }
@@ -217,6 +344,17 @@ impl DataFlowGraph {
         removed
     }
 
+    /// Renames the incoming block `from` of the phi `insn` to `to`, see
+    /// [`InsnData::rename_phi_block`].
+    pub fn rename_phi_block(&mut self, insn: Insn, from: Block, to: Block) {
+        self.insns[insn].rename_phi_block(from, to)
+    }
+
+    /// Returns the arg of the phi `insn` that flows through `from`, if any.
+    pub fn phi_arg_from(&self, insn: Insn, from: Block) -> Option<Value> {
+        self.insns[insn].phi_arg_from(from)
+    }
+
     pub fn insn_args(&self, insn: Insn) -> &[Value] {
         self.insn_data(insn).args()
     }
@@ -241,15 +379,33 @@ impl DataFlowGraph {
         old_arg
     }
 
+    /// Returns the first result of `insn`, which is the only result of most insns.
     pub fn insn_result(&self, insn: Insn) -> Option<Value> {
-        self.insn_results[insn].expand()
+        self.insn_results[insn].first().copied()
+    }
+
+    /// Returns all results of `insn` in order.
+    pub fn insn_results(&self, insn: Insn) -> &[Value] {
+        &self.insn_results[insn]
     }
 
     pub fn analyze_branch(&self, insn: Insn) -> BranchInfo {
         self.insns[insn].analyze_branch()
     }
 
+    /// Removes `dest` from the destinations of the branch `insn`. The weights of the remaining
+    /// destinations are kept, unless only one is left.
     pub fn remove_branch_dest(&mut self, insn: Insn, dest: Block) {
+        let mut old_weights: SmallVec<[_; 8]> = match self.branch_weights[insn].take() {
+            Some(weights) => self
+                .analyze_branch(insn)
+                .iter_dests()
+                .zip(weights.weights().iter().copied())
+                .map(Some)
+                .collect(),
+            None => SmallVec::new(),
+        };
+
         let this = &mut self.insns[insn];
         match this {
             InsnData::Jump { .. } => panic!("can't remove destination from `Jump` insn"),
@@ -301,6 +457,22 @@ impl DataFlowGraph {
 
             _ => panic!("not a branch"),
         }
+
+        let branch_info = self.insns[insn].analyze_branch();
+        if !old_weights.is_empty() && branch_info.dests_num() > 1 {
+            // Each remaining destination takes the weight of the first old one with its block.
+            let weights: SmallVec<[u32; 8]> = branch_info
+                .iter_dests()
+                .map(|block| {
+                    let pos = old_weights
+                        .iter()
+                        .position(|old| old.is_some_and(|(b, _)| b == block))
+                        .unwrap();
+                    old_weights[pos].take().unwrap().1
+                })
+                .collect();
+            self.branch_weights[insn] = Some(BranchWeights::new(&weights));
+        }
     }
 
     pub fn rewrite_branch_dest(&mut self, insn: Insn, from: Block, to: Block) {
@@ -315,6 +487,11 @@ impl DataFlowGraph {
         self.insns[insn].is_return()
     }
 
+    /// Returns `true` if `insn` ends the execution of the function, see [`InsnData::is_exit`].
+    pub fn is_exit(&self, insn: Insn) -> bool {
+        self.insns[insn].is_exit()
+    }
+
     pub fn is_branch(&self, insn: Insn) -> bool {
         self.insns[insn].is_branch()
     }
@@ -338,6 +515,7 @@ pub enum ValueDef {
 
 /// An opaque reference to [`BlockData`]
 #[derive(Clone, PartialEq, Eq, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block(pub u32);
 entity_impl!(Block, "block");
 
@@ -352,3 +530,108 @@ impl BlockData {
         Self::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{builder::test_util::*, insn::BinaryOp};
+
+    #[test]
+    fn replace_insn_keeps_result() {
+        let mut builder = test_func_builder(&[Type::I32, Type::I32], Type::I64);
+        let file = builder
+            .module_builder
+            .ctx
+            .with_source_file_store_mut(|s| s.make_file("main.fe"));
+        let loc = SourceLoc::new(file, 0, 4);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (arg0, arg1) = (builder.args()[0], builder.args()[1]);
+        builder.set_loc(Some(loc));
+        let v2 = builder.add(arg0, arg0);
+        builder.set_loc(None);
+        let v3 = builder.sext(v2, Type::I64);
+        builder.ret(Some(v3));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let dfg = &mut module.funcs[func_ref].dfg;
+
+        let insn = dfg.value_insn(v2).unwrap();
+        let user = dfg.value_insn(v3).unwrap();
+        let old_data = dfg.replace_insn(insn, InsnData::binary(BinaryOp::Mul, arg1, arg1));
+
+        assert_eq!(old_data, InsnData::binary(BinaryOp::Add, arg0, arg0));
+        assert_eq!(dfg.insn_result(insn), Some(v2));
+        assert_eq!(dfg.value_ty(v2), Type::I32);
+        assert_eq!(dfg.insn_loc(insn), Some(loc));
+        assert_eq!(dfg.users(arg0).count(), 0);
+        assert_eq!(dfg.users(arg1).copied().collect::<Vec<_>>(), vec![insn]);
+        assert_eq!(dfg.users(v2).copied().collect::<Vec<_>>(), vec![user]);
+    }
+
+    #[test]
+    fn replace_uses() {
+        let mut builder = test_func_builder(&[Type::I32, Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let (arg0, arg1) = (builder.args()[0], builder.args()[1]);
+        let v2 = builder.add(arg0, arg0);
+        let v3 = builder.mul(v2, arg0);
+        builder.ret(Some(v3));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let dfg = &mut module.funcs[func_ref].dfg;
+        let add = dfg.value_insn(v2).unwrap();
+        let mul = dfg.value_insn(v3).unwrap();
+
+        dfg.replace_uses_in(mul, arg0, arg1);
+        assert_eq!(dfg.insn_args(mul), &[v2, arg1]);
+        assert_eq!(dfg.insn_args(add), &[arg0, arg0]);
+        assert_eq!(dfg.users(arg0).copied().collect::<Vec<_>>(), vec![add]);
+
+        dfg.replace_uses(arg0, arg1);
+        assert_eq!(dfg.insn_args(add), &[arg1, arg1]);
+        assert_eq!(dfg.users(arg0).count(), 0);
+        assert_eq!(dfg.users(arg1).copied().collect::<Vec<_>>(), vec![add, mul]);
+    }
+
+    #[test]
+    fn remove_branch_dest_keeps_weights() {
+        let mut builder = test_func_builder(&[Type::I8], Type::Void);
+        let blocks: Vec<_> = (0..4).map(|_| builder.append_block()).collect();
+        let arg = builder.args()[0];
+
+        builder.switch_to_block(blocks[0]);
+        let (one, two) = (builder.make_imm_value(1i8), builder.make_imm_value(2i8));
+        builder.br_table(arg, Some(blocks[1]), &[(one, blocks[2]), (two, blocks[3])]);
+        builder.set_branch_weights(&[1, 10, 100]);
+        for &block in &blocks[1..] {
+            builder.switch_to_block(block);
+            builder.ret(None);
+        }
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let br_table = func.layout.last_insn_of(blocks[0]).unwrap();
+        let dfg = &mut func.dfg;
+        assert_eq!(dfg.edge_weight(br_table, blocks[2]), Some(10));
+
+        dfg.remove_branch_dest(br_table, blocks[2]);
+        assert_eq!(dfg.branch_weights(br_table).unwrap().weights(), [1, 100]);
+        assert_eq!(dfg.edge_weight(br_table, blocks[3]), Some(100));
+
+        // A jump has no weights.
+        dfg.remove_branch_dest(br_table, blocks[1]);
+        assert!(matches!(dfg.insn_data(br_table), InsnData::Jump { .. }));
+        assert_eq!(dfg.branch_weights(br_table), None);
+    }
+}
@@ -1,3 +1,5 @@
+use smallvec::SmallVec;
+
 use super::{Block, Function, Insn, InsnData, Value};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
@@ -33,6 +35,17 @@ pub trait FuncCursor {
         }
     }
 
+    /// Inserts `insn` before the cursor location, i.e., before the insn the cursor points to or at
+    /// the top or bottom of the block.
+    fn insert_insn_before(&mut self, func: &mut Function, insn: Insn) {
+        match self.loc() {
+            CursorLocation::At(at) => func.layout.insert_insn_before(insn, at),
+            CursorLocation::BlockTop(block) => func.layout.prepend_insn(insn, block),
+            CursorLocation::BlockBottom(block) => func.layout.append_insn(insn, block),
+            CursorLocation::NoWhere => panic!("cursor loc points to `NoWhere`"),
+        }
+    }
+
     fn append_insn(&mut self, func: &mut Function, insn: Insn) {
         let current_block = self.expect_block(func);
         func.layout.append_insn(insn, current_block);
@@ -49,6 +62,12 @@ pub trait FuncCursor {
         insn
     }
 
+    fn insert_insn_data_before(&mut self, func: &mut Function, data: InsnData) -> Insn {
+        let insn = func.dfg.make_insn(data);
+        self.insert_insn_before(func, insn);
+        insn
+    }
+
     fn append_insn_data(&mut self, func: &mut Function, data: InsnData) -> Insn {
         let insn = func.dfg.make_insn(data);
         self.append_insn(func, insn);
@@ -66,6 +85,17 @@ pub trait FuncCursor {
         func.dfg.replace_insn(insn, insn_data);
     }
 
+    /// Replaces all uses of the result of the insn the cursor points to with `value`, and removes
+    /// the insn. The insn must have at most one result.
+    fn replace_with_value(&mut self, func: &mut Function, value: Value) {
+        let insn = self.expect_insn();
+        debug_assert!(func.dfg.insn_results(insn).len() <= 1);
+        if let Some(result) = func.dfg.insn_result(insn) {
+            func.dfg.change_to_alias(result, value);
+        }
+        self.remove_insn(func);
+    }
+
     fn remove_insn(&mut self, func: &mut Function) {
         let insn = self.expect_insn();
         let next_loc = self.next_loc(func);
@@ -88,6 +118,27 @@ pub trait FuncCursor {
         func.dfg.attach_result(insn, value)
     }
 
+    /// Makes the result of `insn` and attaches it, if the insn has one.
+    fn make_and_attach_result(&mut self, func: &mut Function, insn: Insn) -> Option<Value> {
+        let result = self.make_result(func, insn)?;
+        self.attach_result(func, insn, result);
+        Some(result)
+    }
+
+    /// Makes all results of `insn` and attaches them in order.
+    fn make_and_attach_results(&mut self, func: &mut Function, insn: Insn) -> SmallVec<[Value; 2]> {
+        let results: SmallVec<[Value; 2]> = func
+            .dfg
+            .make_results(insn)
+            .into_iter()
+            .map(|value_data| func.dfg.make_value(value_data))
+            .collect();
+        for result in &results {
+            self.attach_result(func, insn, *result);
+        }
+        results
+    }
+
     fn make_block(&mut self, func: &mut Function) -> Block {
         func.dfg.make_block()
     }
@@ -251,3 +302,41 @@ impl FuncCursor for InsnInserter {
         self.loc
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::test_util::*, insn::BinaryOp, Type};
+
+    #[test]
+    fn insert_before_and_replace() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let block = builder.append_block();
+        builder.switch_to_block(block);
+        let arg = builder.args()[0];
+        let sum = builder.add(arg, arg);
+        builder.ret(Some(sum));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+
+        let add = func.layout.first_insn_of(block).unwrap();
+        let mut cursor = InsnInserter::at_location(CursorLocation::At(add));
+        let mul = cursor.insert_insn_data_before(func, InsnData::binary(BinaryOp::Mul, arg, arg));
+        let product = cursor.make_and_attach_result(func, mul).unwrap();
+        cursor.replace_with_value(func, product);
+
+        assert_eq!(
+            dump_func(&module, func_ref),
+            "func public %test_func(v0.i32) -> i32 {
+    block0:
+        v2.i32 = mul v0 v0;
+        return v2;
+
+}
+"
+        );
+    }
+}
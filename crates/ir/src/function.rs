@@ -1,5 +1,5 @@
-use super::{module::FuncRef, DataFlowGraph, Layout, Type, Value};
-use crate::{module::ModuleCtx, types::DisplayType, Linkage};
+use super::{module::FuncRef, Block, DataFlowGraph, Insn, InsnData, Layout, Type, Value};
+use crate::{module::ModuleCtx, types::DisplayType, CallConv, ControlFlowGraph, Linkage};
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
 use std::fmt::{self, Write};
@@ -37,9 +37,261 @@ impl Function {
             callees: FxHashMap::default(),
         }
     }
+
+    /// Returns `true` if `insn` may have a side effect. Unlike
+    /// [`DataFlowGraph::has_side_effect`], a call only has one if the callee may write the state
+    /// or revert, i.e., it isn't declared both `pure` or `view` and `nothrow`, so the call may be
+    /// removed if its result is unused. Tail calls end their block, so they're always kept.
+    pub fn has_side_effect(&self, insn: Insn) -> bool {
+        match self.dfg.insn_data(insn) {
+            InsnData::Call {
+                func,
+                is_tail: false,
+                ..
+            } => self.callees.get(func).is_none_or(|sig| {
+                let attrs = sig.attrs();
+                !(attrs.mutability.is_read_only() && attrs.nothrow)
+            }),
+            _ => self.dfg.has_side_effect(insn),
+        }
+    }
+
+    /// Removes `insn` from the layout and detaches it from its arguments and its results.
+    ///
+    /// Fails without changing anything if a result is still used by other insns, which would be
+    /// left referring to a value without a definition.
+    pub fn remove_insn(&mut self, insn: Insn) -> Result<(), RemoveInsnError> {
+        for &result in self.dfg.insn_results(insn) {
+            let users: Vec<_> = self
+                .dfg
+                .users(result)
+                .copied()
+                .filter(|user| *user != insn)
+                .collect();
+            if !users.is_empty() {
+                return Err(RemoveInsnError {
+                    insn,
+                    result,
+                    users,
+                });
+            }
+        }
+
+        for idx in 0..self.dfg.insn_args_num(insn) {
+            let arg = self.dfg.insn_arg(insn, idx);
+            self.dfg.remove_user(arg, insn);
+        }
+        self.dfg.detach_results(insn);
+        if self.layout.is_insn_inserted(insn) {
+            self.layout.remove_insn(insn);
+        }
+        Ok(())
+    }
+
+    /// Returns the phi insns of `block`, which come before its other insns.
+    pub fn phis_of(&self, block: Block) -> impl Iterator<Item = Insn> + '_ {
+        self.layout
+            .iter_insn(block)
+            .take_while(|insn| self.dfg.is_phi(*insn))
+    }
+
+    /// Appends `(value, pred)` to the phi `insn`, e.g., after an edge from `pred` is added.
+    pub fn add_phi_arg(&mut self, insn: Insn, value: Value, pred: Block) {
+        debug_assert!(self.dfg.phi_arg_from(insn, pred).is_none());
+        self.dfg.append_phi_arg(insn, value, pred);
+    }
+
+    /// Removes the args that flow through `pred` from every phi of `block`, e.g., after the edge
+    /// from `pred` to `block` is removed. Phis without an arg from `pred` are left as they are.
+    pub fn remove_phi_args(&mut self, block: Block, pred: Block) {
+        let phis: SmallVec<[Insn; 4]> = self.phis_of(block).collect();
+        for phi in phis {
+            while self.dfg.phi_arg_from(phi, pred).is_some() {
+                self.dfg.remove_phi_arg(phi, pred);
+            }
+        }
+    }
+
+    /// Renames the incoming block `from` to `to` in every phi of `block`, e.g., after the edge
+    /// from `from` to `block` is split by `to`.
+    pub fn rename_phi_blocks(&mut self, block: Block, from: Block, to: Block) {
+        let phis: SmallVec<[Insn; 4]> = self.phis_of(block).collect();
+        for phi in phis {
+            self.dfg.rename_phi_block(phi, from, to);
+        }
+    }
+
+    /// Checks that the incoming blocks of the phis of each block are the predecessors of the
+    /// block in `cfg`, so that a pass that changes the CFG fails where it forgets to update a phi.
+    /// Does nothing in release builds.
+    ///
+    /// # Panics
+    /// In debug builds, if a phi has an arg from a block that isn't a predecessor, or no arg from
+    /// a predecessor.
+    pub fn debug_check_phis(&self, cfg: &ControlFlowGraph) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let name = self.sig.name();
+        for block in self.layout.iter_block() {
+            for phi in self.phis_of(block) {
+                let phi_blocks = self.dfg.phi_blocks(phi);
+                if let Some(from) = phi_blocks
+                    .iter()
+                    .find(|from| !cfg.preds_of(block).any(|pred| pred == *from))
+                {
+                    panic!("{phi:?} in {block:?} of `{name}` has an arg from {from:?}, not a pred");
+                }
+                if let Some(pred) = cfg.preds_of(block).find(|pred| !phi_blocks.contains(pred)) {
+                    panic!("{phi:?} in {block:?} of `{name}` has no arg from the pred {pred:?}");
+                }
+            }
+        }
+    }
+}
+
+/// An insn couldn't be removed because one of its results is still used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoveInsnError {
+    pub insn: Insn,
+    pub result: Value,
+    pub users: Vec<Insn>,
+}
+
+impl fmt::Display for RemoveInsnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "can't remove {:?}, since its result v{} is used by",
+            self.insn, self.result.0
+        )?;
+        for user in &self.users {
+            write!(f, " {user:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RemoveInsnError {}
+
+/// Attributes of a function. They are kept in its [`Signature`], so that callers see them too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FuncAttrs {
+    /// Optimization passes leave the function untouched.
+    pub optnone: bool,
+    pub inline: InlineHint,
+    /// The function is rarely called, so it's placed away from hot code.
+    pub cold: bool,
+    /// The function never reverts nor traps.
+    pub nothrow: bool,
+    pub mutability: StateMutability,
+}
+
+impl FuncAttrs {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Writes attributes separated by spaces, e.g., `optnone inline(never) cold nothrow view`.
+impl fmt::Display for FuncAttrs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Self {
+            optnone,
+            inline,
+            cold,
+            nothrow,
+            mutability,
+        } = *self;
+        let mut sep = "";
+        if optnone {
+            write!(f, "optnone")?;
+            sep = " ";
+        }
+        if inline != InlineHint::default() {
+            write!(f, "{sep}{inline}")?;
+            sep = " ";
+        }
+        if cold {
+            write!(f, "{sep}cold")?;
+            sep = " ";
+        }
+        if nothrow {
+            write!(f, "{sep}nothrow")?;
+            sep = " ";
+        }
+        if mutability != StateMutability::default() {
+            write!(f, "{sep}{mutability}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether calls to a function should be inlined, as requested by the frontend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InlineHint {
+    /// The inliner decides by the cost of the function.
+    #[default]
+    Auto,
+    /// Calls are inlined whenever possible, regardless of the cost.
+    Always,
+    /// Calls are never inlined.
+    Never,
+}
+
+impl fmt::Display for InlineHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "inline(auto)"),
+            Self::Always => write!(f, "inline(always)"),
+            Self::Never => write!(f, "inline(never)"),
+        }
+    }
+}
+
+/// How a function may access the state of the chain, as declared by the frontend. It's exposed
+/// in the ABI of a contract, but not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StateMutability {
+    /// Neither reads nor writes the state.
+    Pure,
+    /// Reads but doesn't write the state.
+    View,
+    /// Writes the state, and doesn't accept ether.
+    #[default]
+    NonPayable,
+    /// Writes the state, and accepts ether.
+    Payable,
+}
+
+impl StateMutability {
+    /// Returns `true` if the function doesn't write the state.
+    pub fn is_read_only(self) -> bool {
+        matches!(self, Self::Pure | Self::View)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pure => "pure",
+            Self::View => "view",
+            Self::NonPayable => "nonpayable",
+            Self::Payable => "payable",
+        }
+    }
+}
+
+impl fmt::Display for StateMutability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Signature {
     /// Name of the function.
     name: String,
@@ -47,27 +299,75 @@ pub struct Signature {
     /// Linkage of the function.
     linkage: Linkage,
 
+    /// Calling convention of the function.
+    call_conv: CallConv,
+
+    attrs: FuncAttrs,
+
     args: SmallVec<[Type; 8]>,
     ret_ty: Type,
 }
 
 impl Signature {
+    /// Creates a signature with the default calling convention of `linkage`, see
+    /// [`CallConv::default_for`].
     pub fn new(name: &str, linkage: Linkage, args: &[Type], ret_ty: Type) -> Self {
         Self {
             name: name.to_string(),
             linkage,
+            call_conv: CallConv::default_for(linkage),
+            attrs: FuncAttrs::default(),
             args: args.into(),
             ret_ty,
         }
     }
+
+    pub fn with_call_conv(mut self, call_conv: CallConv) -> Self {
+        self.call_conv = call_conv;
+        self
+    }
+
+    pub fn with_attrs(mut self, attrs: FuncAttrs) -> Self {
+        self.attrs = attrs;
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// Sets the name of the function. Use [`Module::rename_function`] to rename a function of a
+    /// module, so that its callers are updated as well.
+    ///
+    /// [`Module::rename_function`]: crate::Module::rename_function
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
     pub fn linkage(&self) -> Linkage {
         self.linkage
     }
 
+    pub fn call_conv(&self) -> CallConv {
+        self.call_conv
+    }
+
+    /// Returns `true` if the calling convention differs from the default of the linkage, so it's
+    /// written in the textual IR.
+    pub fn has_explicit_call_conv(&self) -> bool {
+        self.call_conv != CallConv::default_for(self.linkage)
+    }
+
+    pub fn attrs(&self) -> FuncAttrs {
+        self.attrs
+    }
+
+    /// Returns the attributes to modify. Callers keep a copy of the signature, so attributes
+    /// should be set before the function is called.
+    pub fn attrs_mut(&mut self) -> &mut FuncAttrs {
+        &mut self.attrs
+    }
+
     pub fn args(&self) -> &[Type] {
         &self.args
     }
@@ -99,6 +399,8 @@ impl<'a, 'b> fmt::Display for DisplaySignature<'a, 'b> {
         let Signature {
             name,
             linkage,
+            call_conv,
+            attrs: _,
             args,
             ret_ty,
         } = sig;
@@ -112,6 +414,114 @@ impl<'a, 'b> fmt::Display for DisplaySignature<'a, 'b> {
 
         let ret_ty = DisplayType::new(*ret_ty, dfg);
 
-        write!(f, "func {linkage} %{name}({args_ty}) -> {ret_ty}")
+        if sig.has_explicit_call_conv() {
+            write!(
+                f,
+                "func {linkage} {call_conv} %{name}({args_ty}) -> {ret_ty}"
+            )
+        } else {
+            write!(f, "func {linkage} %{name}({args_ty}) -> {ret_ty}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::test_util::*;
+
+    #[test]
+    fn remove_insn() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg0 = builder.args()[0];
+        let v1 = builder.add(arg0, arg0);
+        let v2 = builder.mul(v1, arg0);
+        builder.ret(Some(arg0));
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let func = &mut module.funcs[func_ref];
+        let add = func.dfg.value_insn(v1).unwrap();
+        let mul = func.dfg.value_insn(v2).unwrap();
+
+        let err = func.remove_insn(add).unwrap_err();
+        assert_eq!(err.users, vec![mul]);
+        assert!(func.layout.is_insn_inserted(add));
+
+        func.remove_insn(mul).unwrap();
+        func.remove_insn(add).unwrap();
+        assert!(!func.layout.is_insn_inserted(add));
+        assert_eq!(func.dfg.insn_result(add), None);
+        assert_eq!(func.dfg.users(arg0).count(), 1);
+    }
+
+    /// Builds a diamond whose join block has a phi of the two args, and returns the function, the
+    /// blocks and the phi.
+    fn diamond() -> (crate::Module, FuncRef, [Block; 4], Insn) {
+        let mut builder = test_func_builder(&[Type::I1, Type::I32, Type::I32], Type::I32);
+        let blocks = [(); 4].map(|_| builder.append_block());
+        let [b0, b1, b2, b3] = blocks;
+        let args = builder.args().to_vec();
+
+        builder.switch_to_block(b0);
+        builder.br(args[0], b1, b2);
+        builder.switch_to_block(b1);
+        builder.jump(b3);
+        builder.switch_to_block(b2);
+        builder.jump(b3);
+        builder.switch_to_block(b3);
+        let phi = builder.phi(Type::I32, &[(args[1], b1), (args[2], b2)]);
+        builder.ret(Some(phi));
+        builder.seal_all();
+
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+        let phi = module.funcs[func_ref].dfg.value_insn(phi).unwrap();
+        (module, func_ref, blocks, phi)
+    }
+
+    #[test]
+    fn edit_phi_args() {
+        let (mut module, func_ref, [_, b1, b2, b3], phi) = diamond();
+        let func = &mut module.funcs[func_ref];
+        let (x, y) = (func.arg_values[1], func.arg_values[2]);
+        assert_eq!(func.phis_of(b3).collect::<Vec<_>>(), [phi]);
+
+        func.remove_phi_args(b3, b2);
+        assert_eq!(func.dfg.phi_arg_from(phi, b2), None);
+        assert_eq!(func.dfg.users(y).count(), 0);
+        func.add_phi_arg(phi, y, b2);
+        assert_eq!(func.dfg.phi_arg_from(phi, b2), Some(y));
+
+        // Split the edge from `b1` with a new block.
+        let b4 = func.dfg.make_block();
+        func.layout.append_block(b4);
+        let jump = func.dfg.make_insn(InsnData::jump(b3));
+        func.layout.append_insn(jump, b4);
+        let branch = func.layout.last_insn_of(b1).unwrap();
+        func.dfg.rewrite_branch_dest(branch, b3, b4);
+        func.rename_phi_blocks(b3, b1, b4);
+        assert_eq!(func.dfg.phi_arg_from(phi, b4), Some(x));
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        func.debug_check_phis(&cfg);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "has no arg from the pred")]
+    fn check_phis_with_missing_arg() {
+        let (mut module, func_ref, [_, _, b2, b3], _) = diamond();
+        let func = &mut module.funcs[func_ref];
+        func.remove_phi_args(b3, b2);
+
+        let mut cfg = ControlFlowGraph::new();
+        cfg.compute(func);
+        func.debug_check_phis(&cfg);
     }
 }
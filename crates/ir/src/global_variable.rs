@@ -1,18 +1,24 @@
 use std::fmt;
 
 use cranelift_entity::PrimaryMap;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 
 use crate::{Immediate, Linkage, Type};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct GlobalVariableStore {
     gv_data: PrimaryMap<GlobalVariable, GlobalVariableData>,
     symbols: FxHashMap<String, GlobalVariable>,
+    /// Additional symbols that refer to global variables.
+    aliases: FxHashMap<String, GlobalVariable>,
+    removed: FxHashSet<GlobalVariable>,
 }
 
 impl GlobalVariableStore {
     pub fn make_gv(&mut self, gv_data: GlobalVariableData) -> GlobalVariable {
+        if self.aliases.contains_key(&gv_data.symbol) {
+            panic!("duplicate global symbol `{}`", gv_data.symbol);
+        }
         match self.symbols.entry(gv_data.symbol.to_string()) {
             std::collections::hash_map::Entry::Occupied(_) => {
                 panic!("duplicate global symbol `{}`", gv_data.symbol);
@@ -29,8 +35,56 @@ impl GlobalVariableStore {
         &self.gv_data[gv]
     }
 
+    /// Makes `alias` another symbol of `gv`, by which [`Self::gv_by_symbol`] finds it as well.
+    pub fn make_alias(&mut self, alias: &str, gv: GlobalVariable) {
+        if self.is_symbol_taken(alias) {
+            panic!("duplicate global symbol `{alias}`");
+        }
+        self.aliases.insert(alias.to_string(), gv);
+    }
+
+    /// Returns the global variable `symbol` refers to, either as its symbol or as an alias.
     pub fn gv_by_symbol(&self, symbol: &str) -> Option<GlobalVariable> {
-        self.symbols.get(symbol).copied()
+        self.symbols
+            .get(symbol)
+            .or_else(|| self.aliases.get(symbol))
+            .copied()
+    }
+
+    pub fn is_alias(&self, symbol: &str) -> bool {
+        self.aliases.contains_key(symbol)
+    }
+
+    /// Returns the aliases and the global variables they refer to.
+    pub fn all_aliases(&self) -> impl Iterator<Item = (&str, GlobalVariable)> {
+        self.aliases.iter().map(|(alias, gv)| (alias.as_str(), *gv))
+    }
+
+    /// Renames the global variable or alias `old` to `new`. Insns refer to global variables by
+    /// [`GlobalVariable`], so they keep referring to the renamed one.
+    pub fn rename_symbol(&mut self, old: &str, new: &str) -> Result<(), SymbolError> {
+        if self.gv_by_symbol(old).is_none() {
+            return Err(SymbolError::Undefined(old.to_string()));
+        }
+        if old == new {
+            return Ok(());
+        }
+        if self.is_symbol_taken(new) {
+            return Err(SymbolError::Duplicate(new.to_string()));
+        }
+
+        if let Some(gv) = self.aliases.remove(old) {
+            self.aliases.insert(new.to_string(), gv);
+        } else {
+            let gv = self.symbols.remove(old).unwrap();
+            self.symbols.insert(new.to_string(), gv);
+            self.gv_data[gv].symbol = new.to_string();
+        }
+        Ok(())
+    }
+
+    fn is_symbol_taken(&self, symbol: &str) -> bool {
+        self.symbols.contains_key(symbol) || self.aliases.contains_key(symbol)
     }
 
     pub fn init_data(&self, gv: GlobalVariable) -> Option<&ConstantValue> {
@@ -45,17 +99,63 @@ impl GlobalVariableStore {
         self.gv_data[gv].ty
     }
 
+    /// Removes `gv` from the store.
+    ///
+    /// `gv` itself is kept as a tombstone, but it's no longer reachable by its symbol or aliases
+    /// nor listed by [`Self::all_gvs`] and [`Self::all_gv_data`].
+    pub fn remove_gv(&mut self, gv: GlobalVariable) {
+        self.symbols.remove(&self.gv_data[gv].symbol);
+        self.aliases.retain(|_, aliased| *aliased != gv);
+        self.removed.insert(gv);
+    }
+
+    /// Replaces the data of `gv`, e.g., to define it in place of a declaration.
+    pub fn replace_gv_data(&mut self, gv: GlobalVariable, gv_data: GlobalVariableData) {
+        self.symbols.remove(&self.gv_data[gv].symbol);
+        self.symbols.insert(gv_data.symbol.clone(), gv);
+        self.gv_data[gv] = gv_data;
+    }
+
+    pub fn is_removed(&self, gv: GlobalVariable) -> bool {
+        self.removed.contains(&gv)
+    }
+
+    pub fn all_gvs(&self) -> impl Iterator<Item = GlobalVariable> + '_ {
+        self.gv_data.keys().filter(|gv| !self.is_removed(*gv))
+    }
+
     pub fn all_gv_data(&self) -> impl Iterator<Item = &GlobalVariableData> {
-        self.gv_data.values()
+        self.all_gvs().map(|gv| &self.gv_data[gv])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolError {
+    /// No global variable or function has the symbol.
+    Undefined(String),
+    /// The symbol is already taken.
+    Duplicate(String),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Undefined(symbol) => write!(f, "`{symbol}` is not defined"),
+            Self::Duplicate(symbol) => write!(f, "`{symbol}` is already defined"),
+        }
     }
 }
 
+impl std::error::Error for SymbolError {}
+
 /// An opaque reference to [`GlobalVariableData`].
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GlobalVariable(pub u32);
 cranelift_entity::entity_impl!(GlobalVariable);
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GlobalVariableData {
     pub symbol: String,
     pub ty: Type,
@@ -93,6 +193,7 @@ impl GlobalVariableData {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConstantValue {
     Immediate(Immediate),
     Array(Vec<ConstantValue>),
@@ -140,3 +241,45 @@ impl fmt::Display for ConstantValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_gv(store: &mut GlobalVariableStore, symbol: &str) -> GlobalVariable {
+        store.make_gv(GlobalVariableData::constant(
+            symbol.to_string(),
+            Type::I32,
+            Linkage::Private,
+            ConstantValue::make_imm(1i32),
+        ))
+    }
+
+    #[test]
+    fn alias_and_rename() {
+        let mut store = GlobalVariableStore::default();
+        let gv = make_gv(&mut store, "one");
+        let other = make_gv(&mut store, "two");
+        store.make_alias("uno", gv);
+        assert_eq!(store.gv_by_symbol("uno"), Some(gv));
+
+        store.rename_symbol("one", "eins").unwrap();
+        assert_eq!(store.gv_data(gv).symbol, "eins");
+        assert_eq!(store.gv_by_symbol("one"), None);
+        assert_eq!(store.gv_by_symbol("eins"), Some(gv));
+        assert_eq!(store.gv_by_symbol("uno"), Some(gv));
+
+        assert_eq!(
+            store.rename_symbol("uno", "two"),
+            Err(SymbolError::Duplicate("two".to_string()))
+        );
+        assert_eq!(
+            store.rename_symbol("one", "un"),
+            Err(SymbolError::Undefined("one".to_string()))
+        );
+
+        store.remove_gv(gv);
+        assert_eq!(store.gv_by_symbol("uno"), None);
+        assert_eq!(store.gv_by_symbol("two"), Some(other));
+    }
+}
@@ -2,26 +2,30 @@ use std::iter;
 
 use dot2::{label::Text, GraphWalk, Id, Labeller, Style};
 
-use crate::{value::DisplayArgValue, Block, ControlFlowGraph, Function, InsnData};
+use crate::{value::DisplayArgValue, Block, ControlFlowGraph, Function, InsnData, Value};
 
-use super::block::BlockNode;
+use super::{block::BlockNode, RenderOptions};
 
 pub(super) const DUMMY_BLOCK: Block = Block(u32::MAX);
 
+/// The fill colors of loop clusters by nesting depth. Loops nested deeper get the last one.
+const LOOP_SHADES: [&str; 4] = ["gray95", "gray88", "gray80", "gray72"];
+
 pub(super) struct FunctionGraph<'a> {
     func: &'a Function,
     cfg: &'a ControlFlowGraph,
+    options: &'a RenderOptions,
 }
 
 impl<'a> FunctionGraph<'a> {
-    pub fn new(func: &'a Function, cfg: &'a ControlFlowGraph) -> Self {
-        Self { func, cfg }
+    pub fn new(func: &'a Function, cfg: &'a ControlFlowGraph, options: &'a RenderOptions) -> Self {
+        Self { func, cfg, options }
     }
 }
 
 impl<'a> FunctionGraph<'a> {
     pub(super) fn blocks(&self) -> Vec<BlockNode<'a>> {
-        let Self { func, cfg } = self;
+        let Self { func, cfg, .. } = self;
         // Dummy block is needed to label the graph with the function signature. Returns a vector
         // with the dummy block as a last element.
         cfg.post_order()
@@ -29,12 +33,25 @@ impl<'a> FunctionGraph<'a> {
             .chain(iter::once(BlockNode::new(func, cfg, DUMMY_BLOCK)))
             .collect()
     }
+
+    /// Returns the block defining `value`, or `None` if it's an immediate or a global.
+    fn def_block(&self, value: Value) -> Option<Block> {
+        let func = self.func;
+        if func.dfg.is_arg(value) {
+            func.layout.entry_block()
+        } else {
+            let insn = func.dfg.value_insn(value)?;
+            func.layout
+                .is_insn_inserted(insn)
+                .then(|| func.layout.insn_block(insn))
+        }
+    }
 }
 
 impl<'a> Labeller<'a> for FunctionGraph<'a> {
     type Node = BlockNode<'a>;
     type Edge = BlockEdge<'a>;
-    type Subgraph = ();
+    type Subgraph = usize;
 
     fn graph_id(&self) -> dot2::Result<Id<'a>> {
         let func = self.func;
@@ -58,10 +75,41 @@ impl<'a> Labeller<'a> for FunctionGraph<'a> {
         if e.from.block == DUMMY_BLOCK {
             Style::Invisible
         } else {
-            Style::None
+            match e.kind {
+                EdgeKind::Cfg => Style::None,
+                EdgeKind::Dom => Style::Dashed,
+                EdgeKind::DefUse(_) => Style::Dotted,
+            }
+        }
+    }
+
+    fn edge_color(&'a self, e: &Self::Edge) -> Option<Text<'a>> {
+        match e.kind {
+            EdgeKind::Cfg => None,
+            EdgeKind::Dom => Some(Text::LabelStr("blue".into())),
+            EdgeKind::DefUse(_) => Some(Text::LabelStr("darkgreen".into())),
         }
     }
 
+    fn subgraph_id(&'a self, s: &Self::Subgraph) -> Option<Id<'a>> {
+        // Graphviz draws a subgraph as a box only if its name starts with `cluster`.
+        Id::new(format!("cluster_loop{s}")).ok()
+    }
+
+    fn subgraph_label(&'a self, s: &Self::Subgraph) -> Text<'a> {
+        let header = self.options.loops[*s].header;
+        Text::LabelStr(format!("loop {header}").into())
+    }
+
+    fn subgraph_style(&'a self, _s: &Self::Subgraph) -> Style {
+        Style::Filled
+    }
+
+    fn subgraph_color(&'a self, s: &Self::Subgraph) -> Option<Text<'a>> {
+        let depth = self.options.loops[*s].depth.min(LOOP_SHADES.len() - 1);
+        Some(Text::LabelStr(LOOP_SHADES[depth].into()))
+    }
+
     fn node_label(&'a self, n: &Self::Node) -> dot2::Result<Text<'a>> {
         Ok(n.label())
     }
@@ -74,14 +122,14 @@ impl<'a> Labeller<'a> for FunctionGraph<'a> {
 impl<'a> GraphWalk<'a> for FunctionGraph<'a> {
     type Node = BlockNode<'a>;
     type Edge = BlockEdge<'a>;
-    type Subgraph = ();
+    type Subgraph = usize;
 
     fn nodes(&self) -> dot2::Nodes<'a, Self::Node> {
         self.blocks().into()
     }
 
     fn edges(&'a self) -> dot2::Edges<'a, Self::Edge> {
-        let Self { func, cfg } = self;
+        let Self { func, cfg, options } = self;
         let mut blocks = self.blocks();
 
         let dummy_block = blocks.pop().unwrap();
@@ -89,6 +137,7 @@ impl<'a> GraphWalk<'a> for FunctionGraph<'a> {
             from: dummy_block,
             to: BlockNode::new(func, cfg, Block(0u32)),
             func,
+            kind: EdgeKind::Cfg,
         }];
         for block in blocks {
             for succ in block.succs() {
@@ -96,10 +145,42 @@ impl<'a> GraphWalk<'a> for FunctionGraph<'a> {
                     from: block,
                     to: succ,
                     func,
+                    kind: EdgeKind::Cfg,
                 };
                 edges.push(edge);
             }
         }
+        for &(block, idom) in &options.dom_edges {
+            edges.push(BlockEdge {
+                from: BlockNode::new(func, cfg, idom),
+                to: BlockNode::new(func, cfg, block),
+                func,
+                kind: EdgeKind::Dom,
+            });
+        }
+
+        for &value in &options.values {
+            let Some(def_block) = self.def_block(value) else {
+                continue;
+            };
+            let mut use_blocks: Vec<_> = func
+                .dfg
+                .users(value)
+                .filter(|insn| func.layout.is_insn_inserted(**insn))
+                .map(|insn| func.layout.insn_block(*insn))
+                .filter(|block| *block != def_block)
+                .collect();
+            use_blocks.sort_unstable();
+            use_blocks.dedup();
+            for use_block in use_blocks {
+                edges.push(BlockEdge {
+                    from: BlockNode::new(func, cfg, def_block),
+                    to: BlockNode::new(func, cfg, use_block),
+                    func,
+                    kind: EdgeKind::DefUse(value),
+                });
+            }
+        }
 
         edges.into()
     }
@@ -111,6 +192,20 @@ impl<'a> GraphWalk<'a> for FunctionGraph<'a> {
     fn target(&self, edge: &Self::Edge) -> Self::Node {
         edge.to
     }
+
+    fn subgraphs(&'a self) -> dot2::Subgraphs<'a, Self::Subgraph> {
+        (0..self.options.loops.len()).collect::<Vec<_>>().into()
+    }
+
+    fn subgraph_nodes(&'a self, s: &Self::Subgraph) -> dot2::Nodes<'a, Self::Node> {
+        let Self { func, cfg, options } = self;
+        options.loops[*s]
+            .blocks
+            .iter()
+            .map(|block| BlockNode::new(func, cfg, *block))
+            .collect::<Vec<_>>()
+            .into()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -118,11 +213,32 @@ pub(super) struct BlockEdge<'a> {
     from: BlockNode<'a>,
     to: BlockNode<'a>,
     func: &'a Function,
+    kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgeKind {
+    /// A control flow edge.
+    Cfg,
+    /// An edge from the immediate dominator of a block.
+    Dom,
+    /// An edge from the block defining a value to a block using it.
+    DefUse(Value),
 }
 
 impl<'a> BlockEdge<'a> {
     fn label(self) -> Text<'static> {
-        let Self { from, to, func } = self;
+        let Self {
+            from,
+            to,
+            func,
+            kind,
+        } = self;
+        match kind {
+            EdgeKind::Cfg => {}
+            EdgeKind::Dom => return Text::LabelStr("".into()),
+            EdgeKind::DefUse(value) => return Text::LabelStr(format!("v{}", value.0).into()),
+        }
         let to = to.block;
         let from = from.block;
         for insn in func.layout.iter_insn(to) {
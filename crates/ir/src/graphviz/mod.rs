@@ -1,6 +1,6 @@
 use std::io;
 
-use crate::{ControlFlowGraph, Function};
+use crate::{Block, ControlFlowGraph, Function, Value};
 
 mod block;
 mod function;
@@ -8,15 +8,68 @@ mod function;
 use function::FunctionGraph;
 
 pub fn render_to<W: io::Write>(func: &Function, output: &mut W) -> io::Result<()> {
+    render_with(func, &RenderOptions::default(), output)
+}
+
+/// Renders the CFG of `func` with the overlays in `options`.
+pub fn render_with<W: io::Write>(
+    func: &Function,
+    options: &RenderOptions,
+    output: &mut W,
+) -> io::Result<()> {
     let mut cfg = ControlFlowGraph::new();
     cfg.compute(func);
-    let func_graph = FunctionGraph::new(func, &cfg);
+    let func_graph = FunctionGraph::new(func, &cfg, options);
     dot2::render(&func_graph, output).map_err(|err| match err {
         dot2::Error::Io(err) => err,
         _ => panic!("invalid graphviz id"),
     })
 }
 
+/// Overlays on the rendered CFG. Dominators and loops are computed by analyses outside of this
+/// crate, so they are given as plain relations between blocks.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Pairs of a block and its immediate dominator, drawn as dashed edges from the dominator.
+    pub dom_edges: Vec<(Block, Block)>,
+    /// Loops, each of which is drawn as a cluster.
+    pub loops: Vec<LoopCluster>,
+    /// Values whose flow is traced by dotted edges from the block defining a value to each other
+    /// block using it.
+    pub values: Vec<Value>,
+}
+
+/// A loop drawn as a cluster, which is shaded darker the deeper it's nested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopCluster {
+    pub header: Block,
+    /// The blocks whose innermost loop is this one.
+    pub blocks: Vec<Block>,
+    /// The number of loops this one is nested in.
+    pub depth: usize,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dom_edge(&mut self, block: Block, idom: Block) -> &mut Self {
+        self.dom_edges.push((block, idom));
+        self
+    }
+
+    pub fn loop_cluster(&mut self, cluster: LoopCluster) -> &mut Self {
+        self.loops.push(cluster);
+        self
+    }
+
+    pub fn trace_value(&mut self, value: Value) -> &mut Self {
+        self.values.push(value);
+        self
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{builder::test_util::test_func_builder, Type};
@@ -73,4 +126,85 @@ mod test {
 ";
         assert_eq!(text, expected);
     }
+
+    #[test]
+    fn test_overlays() {
+        let mut builder = test_func_builder(&[Type::I1], Type::Void);
+
+        let entry_block = builder.append_block();
+        let loop_block = builder.append_block();
+        let exit_block = builder.append_block();
+        let arg0 = builder.args()[0];
+
+        builder.switch_to_block(entry_block);
+        builder.jump(loop_block);
+
+        builder.switch_to_block(loop_block);
+        builder.br(arg0, loop_block, exit_block);
+
+        builder.switch_to_block(exit_block);
+        builder.ret(None);
+
+        builder.seal_all();
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let mut options = RenderOptions::new();
+        options
+            .dom_edge(loop_block, entry_block)
+            .dom_edge(exit_block, loop_block)
+            .loop_cluster(LoopCluster {
+                header: loop_block,
+                blocks: vec![loop_block],
+                depth: 0,
+            });
+
+        let mut text = vec![];
+        render_with(&module.funcs[func_ref], &options, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        assert!(text.contains("subgraph cluster_loop0"));
+        assert!(text.contains("loop block1"));
+        assert!(text.contains("gray95"));
+        assert_eq!(text.matches("dashed").count(), 2);
+    }
+
+    #[test]
+    fn test_def_use_edges() {
+        let mut builder = test_func_builder(&[Type::I64], Type::I64);
+
+        let entry_block = builder.append_block();
+        let then_block = builder.append_block();
+        let merge_block = builder.append_block();
+        let arg0 = builder.args()[0];
+
+        builder.switch_to_block(entry_block);
+        let v1 = builder.add(arg0, arg0);
+        let cond = builder.lt(v1, arg0);
+        builder.br(cond, then_block, merge_block);
+
+        builder.switch_to_block(then_block);
+        let v3 = builder.mul(v1, v1);
+        builder.jump(merge_block);
+
+        builder.switch_to_block(merge_block);
+        let v4 = builder.phi(Type::I64, &[(v3, then_block), (v1, entry_block)]);
+        builder.ret(Some(v4));
+
+        builder.seal_all();
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let mut options = RenderOptions::new();
+        options.trace_value(v1).trace_value(cond);
+
+        let mut text = vec![];
+        render_with(&module.funcs[func_ref], &options, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        // `v1` flows into both other blocks, and `cond` is used only where it's defined.
+        assert!(text.contains("block0 -> block1[label=\"v1\"][style=\"dotted\"]"));
+        assert!(text.contains("block0 -> block2[label=\"v1\"][style=\"dotted\"]"));
+        assert_eq!(text.matches("dotted").count(), 2);
+    }
 }
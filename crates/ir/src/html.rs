@@ -0,0 +1,328 @@
+//! This module renders a function as a standalone HTML page, which is easier to navigate than a
+//! dot file when the function is large.
+//!
+//! The page lists the blocks and insns of the function. A value links to its definition, a
+//! definition lists links to the insns that use the value, and hovering a value highlights all its
+//! occurrences. The CFG is drawn next to the listing by a small embedded script, and a click on a
+//! block in the CFG scrolls to the block in the listing.
+use std::{fmt::Write as _, io};
+
+use cranelift_entity::SecondaryMap;
+
+use crate::{
+    function::DisplaySignature, insn::DisplayInsn, types::DisplayType, Block, ControlFlowGraph,
+    Function, Insn,
+};
+
+const STYLE: &str = r#"
+body { display: flex; margin: 0; font-family: monospace; font-size: 13px; }
+#listing { flex: 1; padding: 12px; overflow: auto; height: 100vh; box-sizing: border-box; }
+#cfg { flex: 1; overflow: auto; height: 100vh; border-left: 1px solid #ccc; }
+.block { margin-bottom: 12px; }
+.block:target, .insn:target { background: #fff3c4; }
+.header { font-weight: bold; }
+.preds { color: #888; font-weight: normal; }
+.insn { padding-left: 2em; white-space: pre; }
+.uses { color: #888; padding-left: 2em; }
+a { color: #1a5fb4; text-decoration: none; }
+.def { font-weight: bold; }
+.hl { background: #ffd166; }
+#cfg rect { fill: #eee; stroke: #555; cursor: pointer; }
+#cfg text { font: 12px monospace; pointer-events: none; }
+#cfg path { fill: none; stroke: #555; marker-end: url(#arrow); }
+"#;
+
+const SCRIPT: &str = r#"
+const W = 80, H = 26, GAP_X = 24, GAP_Y = 48;
+const ns = "http://www.w3.org/2000/svg";
+const svg = document.getElementById("cfg-svg");
+const layers = [];
+for (const node of CFG.nodes) {
+  (layers[node.layer] = layers[node.layer] || []).push(node);
+}
+const pos = {};
+const width = Math.max(...layers.map(l => l.length)) * (W + GAP_X) + 2 * W;
+layers.forEach((layer, y) => layer.forEach((node, x) => {
+  const offset = (width - layer.length * (W + GAP_X)) / 2;
+  pos[node.id] = { x: offset + x * (W + GAP_X), y: GAP_Y / 2 + y * (H + GAP_Y), layer: y };
+}));
+svg.setAttribute("width", width);
+svg.setAttribute("height", layers.length * (H + GAP_Y) + GAP_Y);
+svg.innerHTML = '<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" ' +
+  'markerWidth="6" markerHeight="6" orient="auto"><path d="M0,0 L10,5 L0,10 z"/></marker></defs>';
+for (const [from, to] of CFG.edges) {
+  const a = pos[from], b = pos[to];
+  const path = document.createElementNS(ns, "path");
+  if (b.layer > a.layer) {
+    path.setAttribute("d", `M${a.x + W / 2},${a.y + H} L${b.x + W / 2},${b.y}`);
+  } else {
+    // A back edge goes around the right side of the blocks.
+    const x = Math.max(a.x, b.x) + W + GAP_X / 2;
+    path.setAttribute("d", `M${a.x + W},${a.y + H / 2} C${x},${a.y} ${x},${b.y + H} ${b.x + W},${b.y + H / 2}`);
+  }
+  svg.appendChild(path);
+}
+for (const node of CFG.nodes) {
+  const { x, y } = pos[node.id];
+  const rect = document.createElementNS(ns, "rect");
+  Object.entries({ x, y, width: W, height: H, rx: 4 }).forEach(([k, v]) => rect.setAttribute(k, v));
+  rect.addEventListener("click", () => { location.hash = node.id; });
+  const text = document.createElementNS(ns, "text");
+  text.setAttribute("x", x + 8);
+  text.setAttribute("y", y + H / 2 + 4);
+  text.textContent = node.id;
+  svg.append(rect, text);
+}
+for (const el of document.querySelectorAll("[data-value]")) {
+  const all = () => document.querySelectorAll(`[data-value="${el.dataset.value}"]`);
+  el.addEventListener("mouseenter", () => all().forEach(e => e.classList.add("hl")));
+  el.addEventListener("mouseleave", () => all().forEach(e => e.classList.remove("hl")));
+}
+"#;
+
+/// Writes the page of `func` to `output`.
+pub fn render_html<W: io::Write>(func: &Function, output: &mut W) -> io::Result<()> {
+    let mut cfg = ControlFlowGraph::new();
+    cfg.compute(func);
+
+    let mut listing = String::new();
+    write_listing(func, &cfg, &mut listing).unwrap();
+    let name = escape(func.sig.name());
+
+    write!(
+        output,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{name}</title>\n\
+         <style>{STYLE}</style>\n</head>\n<body>\n<div id=\"listing\">\n{listing}</div>\n\
+         <div id=\"cfg\"><svg id=\"cfg-svg\"></svg></div>\n<script>\nconst CFG = {};\n{SCRIPT}\
+         </script>\n</body>\n</html>\n",
+        cfg_json(&cfg)
+    )
+}
+
+fn write_listing(func: &Function, cfg: &ControlFlowGraph, w: &mut String) -> std::fmt::Result {
+    let sig = DisplaySignature::new(&func.sig, &func.dfg);
+    writeln!(
+        w,
+        "<div class=\"header\">{}</div>",
+        escape(&sig.to_string())
+    )?;
+
+    let mut args = Vec::new();
+    for arg in &func.arg_values {
+        let ty = DisplayType::new(func.dfg.value_ty(*arg), &func.dfg);
+        args.push(format!(
+            "<span class=\"def\" id=\"def-v{0}\" data-value=\"v{0}\">v{0}</span>.{1}",
+            arg.0,
+            escape(&ty.to_string())
+        ));
+    }
+    writeln!(w, "<div>args: {}</div>", args.join(", "))?;
+    for arg in &func.arg_values {
+        write_uses(func, func.dfg.users(*arg), w)?;
+    }
+
+    for block in func.layout.iter_block() {
+        let preds: Vec<_> = cfg
+            .preds_of(block)
+            .map(|pred| format!("<a href=\"#{pred}\">{pred}</a>"))
+            .collect();
+        writeln!(
+            w,
+            "<div class=\"block\" id=\"{block}\"><div class=\"header\">{block}: \
+             <span class=\"preds\">preds: {}</span></div>",
+            preds.join(", ")
+        )?;
+
+        for insn in func.layout.iter_insn(block) {
+            let text = DisplayInsn::new(insn, func).to_string();
+            writeln!(
+                w,
+                "<div class=\"insn\" id=\"insn{}\">{}</div>",
+                insn.0,
+                annotate(&text)
+            )?;
+            for &result in func.dfg.insn_results(insn) {
+                write_uses(func, func.dfg.users(result), w)?;
+            }
+        }
+        writeln!(w, "</div>")?;
+    }
+    Ok(())
+}
+
+/// Writes links to the insns that use a value.
+fn write_uses<'a>(
+    func: &Function,
+    users: impl Iterator<Item = &'a Insn>,
+    w: &mut String,
+) -> std::fmt::Result {
+    let links: Vec<_> = users
+        .filter(|insn| func.layout.is_insn_inserted(**insn))
+        .map(|insn| {
+            let block = func.layout.insn_block(*insn);
+            let label = match func.dfg.insn_result(*insn) {
+                Some(value) => format!("v{} in {block}", value.0),
+                None => block.to_string(),
+            };
+            format!("<a href=\"#insn{}\">{label}</a>", insn.0)
+        })
+        .collect();
+    if !links.is_empty() {
+        writeln!(w, "<div class=\"uses\">uses: {}</div>", links.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Returns `text`, an insn, escaped with its values and blocks turned into links. The value before
+/// ` = ` is the definition.
+fn annotate(text: &str) -> String {
+    let def_end = text.find(" = ");
+    let mut annotated = String::new();
+    let mut chars = text.char_indices().peekable();
+    let mut prev = ' ';
+    while let Some((start, c)) = chars.next() {
+        if !c.is_ascii_alphanumeric() {
+            annotated.push_str(&escape(&c.to_string()));
+            prev = c;
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+        while let Some(&(idx, c)) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            end = idx + c.len_utf8();
+            chars.next();
+        }
+        let word = &text[start..end];
+        // Words after `%` are names of functions and globals, and after `.` are types.
+        let is_ref = prev != '%' && prev != '.';
+        prev = word.chars().last().unwrap();
+
+        if is_ref && is_entity(word, "v") {
+            if def_end.is_some_and(|def_end| start < def_end) {
+                write!(
+                    annotated,
+                    "<span class=\"def\" id=\"def-{word}\" data-value=\"{word}\">{word}</span>"
+                )
+                .unwrap();
+            } else {
+                write!(
+                    annotated,
+                    "<a href=\"#def-{word}\" data-value=\"{word}\">{word}</a>"
+                )
+                .unwrap();
+            }
+        } else if is_ref && is_entity(word, "block") {
+            write!(annotated, "<a href=\"#{word}\">{word}</a>").unwrap();
+        } else {
+            annotated.push_str(word);
+        }
+    }
+    annotated
+}
+
+fn is_entity(word: &str, prefix: &str) -> bool {
+    word.strip_prefix(prefix)
+        .is_some_and(|idx| !idx.is_empty() && idx.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns the CFG as a JS object, where the layer of a block is the length of the longest path
+/// from the entry to it that doesn't take a back edge.
+fn cfg_json(cfg: &ControlFlowGraph) -> String {
+    let rpo: Vec<Block> = {
+        let mut po: Vec<_> = cfg.post_order().collect();
+        po.reverse();
+        po
+    };
+    let mut rpo_idx: SecondaryMap<Block, Option<usize>> = SecondaryMap::new();
+    for (idx, block) in rpo.iter().enumerate() {
+        rpo_idx[*block] = Some(idx);
+    }
+
+    let mut layers: SecondaryMap<Block, usize> = SecondaryMap::new();
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    for (idx, &block) in rpo.iter().enumerate() {
+        layers[block] = cfg
+            .preds_of(block)
+            .filter(|pred| rpo_idx[**pred].is_some_and(|pred_idx| pred_idx < idx))
+            .map(|pred| layers[*pred] + 1)
+            .max()
+            .unwrap_or(0);
+        nodes.push(format!(
+            "{{\"id\":\"{block}\",\"layer\":{}}}",
+            layers[block]
+        ));
+        for succ in cfg.succs_of(block) {
+            edges.push(format!("[\"{block}\",\"{succ}\"]"));
+        }
+    }
+
+    format!(
+        "{{\"nodes\":[{}],\"edges\":[{}]}}",
+        nodes.join(","),
+        edges.join(",")
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::test_util::test_func_builder, Type};
+
+    #[test]
+    fn render() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I64], Type::I64);
+        let entry_block = builder.append_block();
+        let then_block = builder.append_block();
+        let merge_block = builder.append_block();
+        let [cond, arg] = builder.args()[..] else {
+            unreachable!()
+        };
+
+        builder.switch_to_block(entry_block);
+        builder.br(cond, then_block, merge_block);
+
+        builder.switch_to_block(then_block);
+        let v = builder.add(arg, arg);
+        builder.jump(merge_block);
+
+        builder.switch_to_block(merge_block);
+        let phi = builder.phi(Type::I64, &[(v, then_block), (arg, entry_block)]);
+        builder.ret(Some(phi));
+
+        builder.seal_all();
+        let module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        let mut html = vec![];
+        render_html(&module.funcs[func_ref], &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+
+        assert!(html.contains("<div class=\"block\" id=\"block1\">"));
+        assert!(html.contains("id=\"def-v2\" data-value=\"v2\">v2</span>.i64 = add"));
+        assert!(html.contains("<a href=\"#def-v1\" data-value=\"v1\">v1</a>"));
+        assert!(html.contains("<a href=\"#block2\">block2</a>"));
+        assert!(html.contains("uses: <a href=\"#insn"));
+        assert!(html.contains(
+            "{\"id\":\"block0\",\"layer\":0},{\"id\":\"block1\",\"layer\":1},\
+             {\"id\":\"block2\",\"layer\":2}"
+        ));
+    }
+}
@@ -7,6 +7,7 @@ use smallvec::SmallVec;
 
 use crate::{
     function::Function,
+    intrinsic::Intrinsic,
     types::{CompoundTypeData, DisplayType},
     value::{display_arg_values, DisplayArgValue, DisplayResultValue},
 };
@@ -18,6 +19,7 @@ use super::{
 
 /// An opaque reference to [`InsnData`]
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Insn(pub u32);
 cranelift_entity::entity_impl!(Insn);
 
@@ -46,6 +48,7 @@ impl<'a> fmt::Display for DisplayInsn<'a> {
 
 /// An instruction data definition.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InsnData {
     /// Unary instructions.
     Unary { code: UnaryOp, args: [Value; 1] },
@@ -53,6 +56,10 @@ pub enum InsnData {
     /// Binary instructions.
     Binary { code: BinaryOp, args: [Value; 2] },
 
+    /// Binary arithmetic with an overflow check. The insn has two results, the wrapped result of
+    /// the operation and an `i1` flag that is set if the operation overflowed.
+    OverflowBinary { code: OverflowOp, args: [Value; 2] },
+
     /// Cast operations.
     Cast {
         code: CastOp,
@@ -72,11 +79,29 @@ pub enum InsnData {
         loc: DataLocationKind,
     },
 
+    /// Copy or fill a range of memory or storage. The args are the destination, the source or the
+    /// `i8` fill byte, and the length, which counts bytes in memory and slots in storage.
+    Mem {
+        code: MemOp,
+        args: [Value; 3],
+        loc: DataLocationKind,
+    },
+
     /// Call a function in the same contract.
     Call {
         func: FuncRef,
         args: SmallVec<[Value; 8]>,
         ret_ty: Type,
+        /// `true` if the call is immediately followed by a return of its result, so that the
+        /// callee can reuse the frame of the caller.
+        is_tail: bool,
+    },
+
+    /// Call an [intrinsic](crate::intrinsic) of the target.
+    Intrinsic {
+        intrinsic: Intrinsic,
+        args: SmallVec<[Value; 8]>,
+        ret_ty: Type,
     },
 
     /// Unconditional jump instruction.
@@ -98,6 +123,21 @@ pub enum InsnData {
     /// Return.
     Return { args: Option<Value> },
 
+    /// Exit the function by reverting with the error `selector(args)`, which undoes the state
+    /// changes of the call. Backends pass the selector and the args to the caller as ABI-encoded
+    /// revert data.
+    Revert {
+        selector: [u8; 4],
+        args: SmallVec<[Value; 8]>,
+    },
+
+    /// Exit the function by reverting because a check failed, e.g., an arithmetic overflow.
+    Trap { reason: TrapReason },
+
+    /// Mark a path that never executes, e.g., the default of a `br_table` over all the values of an
+    /// enum. Optimizers may assume it's never reached.
+    Unreachable,
+
     /// Get element pointer.
     Gep { args: SmallVec<[Value; 8]> },
 
@@ -111,6 +151,7 @@ pub enum InsnData {
 
 /// Indicates where the data is stored.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DataLocationKind {
     /// Volatile memory.
     Memory,
@@ -157,6 +198,13 @@ impl InsnData {
         }
     }
 
+    pub fn overflow_binary(code: OverflowOp, lhs: Value, rhs: Value) -> Self {
+        Self::OverflowBinary {
+            code,
+            args: [lhs, rhs],
+        }
+    }
+
     pub fn cast(code: CastOp, arg: Value, ty: Type) -> Self {
         Self::Cast {
             code,
@@ -165,6 +213,14 @@ impl InsnData {
         }
     }
 
+    pub fn mem(code: MemOp, dst: Value, src: Value, len: Value, loc: DataLocationKind) -> Self {
+        Self::Mem {
+            code,
+            args: [dst, src, len],
+            loc,
+        }
+    }
+
     pub fn alloca(ty: Type) -> Self {
         Self::Alloca { ty }
     }
@@ -241,7 +297,11 @@ impl InsnData {
 
     pub fn args(&self) -> &[Value] {
         match self {
-            Self::Binary { args, .. } | Self::Store { args, .. } => args,
+            Self::Mem { args, .. } => args,
+
+            Self::Binary { args, .. }
+            | Self::OverflowBinary { args, .. }
+            | Self::Store { args, .. } => args,
 
             Self::Unary { args, .. }
             | Self::Cast { args, .. }
@@ -249,7 +309,9 @@ impl InsnData {
             | Self::Branch { args, .. } => args,
 
             Self::Call { args, .. }
+            | Self::Intrinsic { args, .. }
             | Self::BrTable { args, .. }
+            | Self::Revert { args, .. }
             | Self::Phi { values: args, .. }
             | Self::Gep { args } => args,
 
@@ -261,7 +323,11 @@ impl InsnData {
 
     pub fn args_mut(&mut self) -> &mut [Value] {
         match self {
-            Self::Binary { args, .. } | Self::Store { args, .. } => args,
+            Self::Mem { args, .. } => args,
+
+            Self::Binary { args, .. }
+            | Self::OverflowBinary { args, .. }
+            | Self::Store { args, .. } => args,
 
             Self::Unary { args, .. }
             | Self::Cast { args, .. }
@@ -269,7 +335,9 @@ impl InsnData {
             | Self::Branch { args, .. } => args,
 
             Self::Call { args, .. }
+            | Self::Intrinsic { args, .. }
             | Self::BrTable { args, .. }
+            | Self::Revert { args, .. }
             | Self::Phi { values: args, .. }
             | Self::Gep { args } => args,
 
@@ -312,6 +380,33 @@ impl InsnData {
         values.remove(index)
     }
 
+    /// Renames the incoming block `from` of the phi to `to`, e.g., after the edge from `from` is
+    /// split by `to`.
+    ///
+    /// # Panics
+    /// If `insn` is not a phi insn, then the function panics.
+    pub fn rename_phi_block(&mut self, from: Block, to: Block) {
+        for block in self.phi_blocks_mut() {
+            if *block == from {
+                *block = to;
+            }
+        }
+    }
+
+    /// Returns the phi arg that flows through `from`, if any.
+    ///
+    /// # Panics
+    /// If `insn` is not a phi insn, then the function panics.
+    pub fn phi_arg_from(&self, from: Block) -> Option<Value> {
+        match self {
+            InsnData::Phi { values, blocks, .. } => blocks
+                .iter()
+                .position(|block| *block == from)
+                .map(|idx| values[idx]),
+            _ => panic!("insn is not a phi function"),
+        }
+    }
+
     pub fn phi_blocks(&self) -> &[Block] {
         match self {
             InsnData::Phi { blocks, .. } => blocks,
@@ -334,6 +429,18 @@ impl InsnData {
         matches!(self, InsnData::Return { .. })
     }
 
+    /// Returns `true` if the insn ends the execution of the function, i.e., it's a `return`, a
+    /// `revert`, a `trap` or an `unreachable`.
+    pub fn is_exit(&self) -> bool {
+        matches!(
+            self,
+            InsnData::Return { .. }
+                | InsnData::Revert { .. }
+                | InsnData::Trap { .. }
+                | InsnData::Unreachable
+        )
+    }
+
     pub fn is_branch(&self) -> bool {
         matches!(
             self,
@@ -341,27 +448,92 @@ impl InsnData {
         )
     }
 
+    /// Returns the name of the insn in the textual IR, e.g., `add` or `br_table`.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Self::Unary { code, .. } => code.as_str(),
+            Self::Binary { code, .. } => code.as_str(),
+            Self::OverflowBinary { code, .. } => code.as_str(),
+            Self::Cast { code, .. } => code.as_str(),
+            Self::Load { .. } => "load",
+            Self::Store { .. } => "store",
+            Self::Mem { code, .. } => code.as_str(),
+            Self::Call { .. } => "call",
+            Self::Intrinsic { .. } => "intrinsic",
+            Self::Jump { .. } => "jump",
+            Self::Branch { .. } => "br",
+            Self::BrTable { .. } => "br_table",
+            Self::Alloca { .. } => "alloca",
+            Self::Return { .. } => "return",
+            Self::Revert { .. } => "revert",
+            Self::Trap { .. } => "trap",
+            Self::Unreachable => "unreachable",
+            Self::Gep { .. } => "gep",
+            Self::Phi { .. } => "phi",
+        }
+    }
+
+    /// Returns the [mnemonics](Self::mnemonic) of all insns.
+    pub fn all_mnemonics() -> impl Iterator<Item = &'static str> {
+        let ops = UnaryOp::ALL
+            .iter()
+            .map(|op| op.as_str())
+            .chain(BinaryOp::ALL.iter().map(|op| op.as_str()))
+            .chain(OverflowOp::ALL.iter().map(|op| op.as_str()))
+            .chain(CastOp::ALL.iter().map(|op| op.as_str()))
+            .chain(MemOp::ALL.iter().map(|op| op.as_str()));
+        ops.chain([
+            "load",
+            "store",
+            "call",
+            "intrinsic",
+            "jump",
+            "br",
+            "br_table",
+            "alloca",
+            "return",
+            "revert",
+            "trap",
+            "unreachable",
+            "gep",
+            "phi",
+        ])
+    }
+
+    /// Returns `true` if the insn may have a side effect. An intrinsic is assumed to have one,
+    /// since the registry of the target isn't at hand; see [`DataFlowGraph::has_side_effect`].
     pub fn has_side_effect(&self) -> bool {
         matches!(
             self,
             InsnData::Load { .. }
                 | InsnData::Store { .. }
+                | InsnData::Mem { .. }
                 | InsnData::Call { .. }
+                | InsnData::Intrinsic { .. }
                 | InsnData::Return { .. }
+                | InsnData::Revert { .. }
+                | InsnData::Trap { .. }
+                | InsnData::Unreachable
                 | InsnData::Alloca { .. }
         )
     }
 
     pub fn may_trap(&self) -> bool {
         match self {
-            InsnData::Load { .. } | InsnData::Store { .. } | InsnData::Call { .. } => true,
+            InsnData::Load { .. }
+            | InsnData::Store { .. }
+            | InsnData::Mem { .. }
+            | InsnData::Call { .. }
+            | InsnData::Intrinsic { .. } => true,
             InsnData::Binary { code, .. } => matches!(code, BinaryOp::Udiv | BinaryOp::Sdiv),
             _ => false,
         }
     }
 
+    /// Returns the type of the first result of the insn, or `None` if the insn has no results.
     pub fn result_type(&self, dfg: &DataFlowGraph) -> Option<Type> {
         match self {
+            Self::OverflowBinary { args, .. } => Some(dfg.value_ty(args[0])),
             Self::Unary { args, .. } => Some(dfg.value_ty(args[0])),
             Self::Binary { code, args } => Some(code.result_type(dfg, args)),
             Self::Cast { ty, .. } => Some(*ty),
@@ -372,11 +544,21 @@ impl InsnData {
             }
             Self::Gep { args } => Some(get_gep_result_type(dfg, args[0], &args[1..])),
             Self::Call { ret_ty, .. } => Some(*ret_ty),
+            Self::Intrinsic { ret_ty, .. } => (*ret_ty != Type::Void).then_some(*ret_ty),
             Self::Phi { ty, .. } => Some(*ty),
-            Self::Alloca { ty } => Some(dfg.ctx.with_ty_store_mut(|s| s.make_ptr(*ty))),
+            Self::Alloca { ty } => Some(dfg.ctx.make_ptr(*ty)),
             _ => None,
         }
     }
+
+    /// Returns the types of all results of the insn in order. Most insns have at most one result,
+    /// whose type is the [`Self::result_type`].
+    pub fn result_types(&self, dfg: &DataFlowGraph) -> SmallVec<[Type; 2]> {
+        match self {
+            Self::OverflowBinary { args, .. } => [dfg.value_ty(args[0]), Type::I1].into(),
+            _ => self.result_type(dfg).into_iter().collect(),
+        }
+    }
 }
 
 pub struct DisplayInsnData<'a> {
@@ -407,6 +589,11 @@ impl<'a> fmt::Display for DisplayInsnData<'a> {
                 display_arg_values(f, args, dfg)?;
                 ";".fmt(f)
             }
+            OverflowBinary { code, args } => {
+                write!(f, "{} ", code.as_str())?;
+                display_arg_values(f, args, dfg)?;
+                ";".fmt(f)
+            }
             Cast { code, args, .. } => {
                 write!(f, "{} ", code.as_str())?;
                 display_arg_values(f, args, dfg)?;
@@ -422,14 +609,39 @@ impl<'a> fmt::Display for DisplayInsnData<'a> {
                 display_arg_values(f, args, dfg)?;
                 ";".fmt(f)
             }
+            Mem { code, args, loc } => {
+                write!(f, "{code} {loc} ")?;
+                display_arg_values(f, args, dfg)?;
+                ";".fmt(f)
+            }
             Call {
-                args, func: callee, ..
+                args,
+                func: callee,
+                is_tail,
+                ..
             } => {
                 let callee = DisplayCalleeFuncRef::new(*callee, func);
+                if *is_tail {
+                    "tail ".fmt(f)?;
+                }
                 write!(f, "call %{callee} ")?;
                 display_arg_values(f, args, dfg)?;
                 ";".fmt(f)
             }
+            Intrinsic {
+                intrinsic, args, ..
+            } => {
+                let name = dfg
+                    .ctx
+                    .isa
+                    .with_intrinsics(|r| r.intrinsic_data(*intrinsic).name.clone());
+                write!(f, "intrinsic %{name}")?;
+                for arg in args {
+                    let arg = DisplayArgValue::new(*arg, dfg);
+                    write!(f, " {arg}")?;
+                }
+                ";".fmt(f)
+            }
             Jump { dests } => {
                 let block = dests[0];
                 write!(f, "jump {block};")
@@ -466,6 +678,16 @@ impl<'a> fmt::Display for DisplayInsnData<'a> {
                 }
                 ";".fmt(f)
             }
+            Revert { selector, args } => {
+                write!(f, "revert {}", DisplaySelector(*selector))?;
+                for arg in args {
+                    let arg = DisplayArgValue::new(*arg, dfg);
+                    write!(f, " {arg}")?;
+                }
+                ";".fmt(f)
+            }
+            Trap { reason } => write!(f, "trap {reason};"),
+            Unreachable => "unreachable;".fmt(f),
             Gep { args } => {
                 "gep ".fmt(f)?;
                 display_arg_values(f, args, dfg)?;
@@ -485,16 +707,38 @@ impl<'a> fmt::Display for DisplayInsnData<'a> {
 
 /// Unary operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOp {
     Not,
     Neg,
+    /// Counts the leading zero bits in the width of the type.
+    Clz,
+    /// Counts the trailing zero bits, which is the width of the type for zero.
+    Ctz,
+    /// Counts the one bits.
+    Popcount,
+    /// Reverses the order of the bytes. Types narrower than 16 bits are left as is.
+    Bswap,
 }
 
 impl UnaryOp {
+    pub const ALL: [Self; 6] = [
+        Self::Not,
+        Self::Neg,
+        Self::Clz,
+        Self::Ctz,
+        Self::Popcount,
+        Self::Bswap,
+    ];
+
     pub(super) fn as_str(self) -> &'static str {
         match self {
             Self::Not => "not",
             Self::Neg => "neg",
+            Self::Clz => "clz",
+            Self::Ctz => "ctz",
+            Self::Popcount => "popcount",
+            Self::Bswap => "bswap",
         }
     }
 }
@@ -506,6 +750,10 @@ impl FromStr for UnaryOp {
         match s {
             "not" => Ok(Self::Not),
             "neg" => Ok(Self::Neg),
+            "clz" => Ok(Self::Clz),
+            "ctz" => Ok(Self::Ctz),
+            "popcount" => Ok(Self::Popcount),
+            "bswap" => Ok(Self::Bswap),
             _ => Err(()),
         }
     }
@@ -519,6 +767,7 @@ impl fmt::Display for UnaryOp {
 
 /// Binary operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -538,9 +787,42 @@ pub enum BinaryOp {
     And,
     Or,
     Xor,
+    Shl,
+    Shr,
+    Sar,
+    /// Rotate left by the second operand modulo the bit width.
+    Rotl,
+    /// Rotate right by the second operand modulo the bit width.
+    Rotr,
 }
 
 impl BinaryOp {
+    pub const ALL: [Self; 23] = [
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Udiv,
+        Self::Sdiv,
+        Self::Lt,
+        Self::Gt,
+        Self::Slt,
+        Self::Sgt,
+        Self::Le,
+        Self::Ge,
+        Self::Sle,
+        Self::Sge,
+        Self::Eq,
+        Self::Ne,
+        Self::And,
+        Self::Or,
+        Self::Xor,
+        Self::Shl,
+        Self::Shr,
+        Self::Sar,
+        Self::Rotl,
+        Self::Rotr,
+    ];
+
     pub fn is_commutative(self) -> bool {
         matches!(
             self,
@@ -568,6 +850,11 @@ impl BinaryOp {
             Self::And => "and",
             Self::Or => "or",
             Self::Xor => "xor",
+            Self::Shl => "shl",
+            Self::Shr => "shr",
+            Self::Sar => "sar",
+            Self::Rotl => "rotl",
+            Self::Rotr => "rotr",
         }
     }
 
@@ -579,7 +866,25 @@ impl BinaryOp {
         }
     }
 
-    fn is_cmp(self) -> bool {
+    /// Returns `true` if the op interprets its operands as signed integers.
+    pub fn is_signed(self) -> bool {
+        matches!(
+            self,
+            Self::Sdiv | Self::Slt | Self::Sgt | Self::Sle | Self::Sge | Self::Sar
+        )
+    }
+
+    /// Returns `true` if the op shifts or rotates its first operand by its second one, whose width
+    /// is independent of the first.
+    pub fn is_shift(self) -> bool {
+        matches!(
+            self,
+            Self::Shl | Self::Shr | Self::Sar | Self::Rotl | Self::Rotr
+        )
+    }
+
+    /// Returns `true` if the op is a comparison, whose result is `i1`.
+    pub fn is_cmp(self) -> bool {
         matches!(
             self,
             Self::Eq
@@ -625,12 +930,220 @@ impl FromStr for BinaryOp {
             "and" => Ok(Self::And),
             "or" => Ok(Self::Or),
             "xor" => Ok(Self::Xor),
+            "shl" => Ok(Self::Shl),
+            "shr" => Ok(Self::Shr),
+            "sar" => Ok(Self::Sar),
+            "rotl" => Ok(Self::Rotl),
+            "rotr" => Ok(Self::Rotr),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Binary arithmetic operations with an overflow check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowOp {
+    Uaddo,
+    Saddo,
+    Usubo,
+    Ssubo,
+    Umulo,
+    Smulo,
+}
+
+impl OverflowOp {
+    pub const ALL: [Self; 6] = [
+        Self::Uaddo,
+        Self::Saddo,
+        Self::Usubo,
+        Self::Ssubo,
+        Self::Umulo,
+        Self::Smulo,
+    ];
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Uaddo => "uaddo",
+            Self::Saddo => "saddo",
+            Self::Usubo => "usubo",
+            Self::Ssubo => "ssubo",
+            Self::Umulo => "umulo",
+            Self::Smulo => "smulo",
+        }
+    }
+
+    /// Returns the op without the overflow check, which computes the same wrapped result.
+    pub fn unchecked(self) -> BinaryOp {
+        match self {
+            Self::Uaddo | Self::Saddo => BinaryOp::Add,
+            Self::Usubo | Self::Ssubo => BinaryOp::Sub,
+            Self::Umulo | Self::Smulo => BinaryOp::Mul,
+        }
+    }
+
+    /// Returns `true` if the op checks for signed overflow.
+    pub fn is_signed(self) -> bool {
+        matches!(self, Self::Saddo | Self::Ssubo | Self::Smulo)
+    }
+
+    pub fn is_commutative(self) -> bool {
+        self.unchecked().is_commutative()
+    }
+}
+
+impl fmt::Display for OverflowOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OverflowOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uaddo" => Ok(Self::Uaddo),
+            "saddo" => Ok(Self::Saddo),
+            "usubo" => Ok(Self::Usubo),
+            "ssubo" => Ok(Self::Ssubo),
+            "umulo" => Ok(Self::Umulo),
+            "smulo" => Ok(Self::Smulo),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Operations on a range of memory or storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemOp {
+    /// Copy between ranges that don't overlap.
+    Memcpy,
+    /// Copy between ranges that may overlap.
+    Memmove,
+    /// Fill a range with a byte.
+    Memset,
+}
+
+impl MemOp {
+    pub const ALL: [Self; 3] = [Self::Memcpy, Self::Memmove, Self::Memset];
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Memcpy => "memcpy",
+            Self::Memmove => "memmove",
+            Self::Memset => "memset",
+        }
+    }
+}
+
+impl fmt::Display for MemOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MemOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memcpy" => Ok(Self::Memcpy),
+            "memmove" => Ok(Self::Memmove),
+            "memset" => Ok(Self::Memset),
             _ => Err(()),
         }
     }
 }
 
+/// The reason of a `trap`, i.e., the check that failed. Each reason has the code of the Solidity
+/// panic of the same check, so that callers decode the revert data of a trap as a
+/// `Panic(uint256)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrapReason {
+    /// An assertion failed.
+    Assert,
+    /// An arithmetic operation overflowed.
+    Overflow,
+    /// A division or remainder by zero.
+    DivisionByZero,
+    /// A conversion to an enum got a value out of range.
+    InvalidEnum,
+    /// An element was popped from an empty array.
+    EmptyArrayPop,
+    /// An array was indexed out of bounds.
+    OutOfBounds,
+    /// Too much memory was allocated.
+    OutOfMemory,
+}
+
+impl TrapReason {
+    pub const ALL: [Self; 7] = [
+        Self::Assert,
+        Self::Overflow,
+        Self::DivisionByZero,
+        Self::InvalidEnum,
+        Self::EmptyArrayPop,
+        Self::OutOfBounds,
+        Self::OutOfMemory,
+    ];
+
+    /// Returns the code of the Solidity panic for the reason.
+    pub fn panic_code(self) -> u8 {
+        match self {
+            Self::Assert => 0x01,
+            Self::Overflow => 0x11,
+            Self::DivisionByZero => 0x12,
+            Self::InvalidEnum => 0x21,
+            Self::EmptyArrayPop => 0x31,
+            Self::OutOfBounds => 0x32,
+            Self::OutOfMemory => 0x41,
+        }
+    }
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Assert => "assert",
+            Self::Overflow => "overflow",
+            Self::DivisionByZero => "div_by_zero",
+            Self::InvalidEnum => "invalid_enum",
+            Self::EmptyArrayPop => "empty_array_pop",
+            Self::OutOfBounds => "out_of_bounds",
+            Self::OutOfMemory => "out_of_memory",
+        }
+    }
+}
+
+impl fmt::Display for TrapReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TrapReason {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|reason| reason.as_str() == s)
+            .ok_or(())
+    }
+}
+
+/// Displays an error selector as `0x` followed by 8 hex digits.
+pub struct DisplaySelector(pub [u8; 4]);
+
+impl fmt::Display for DisplaySelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:08x}", u32::from_be_bytes(self.0))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CastOp {
     Sext,
     Zext,
@@ -639,6 +1152,8 @@ pub enum CastOp {
 }
 
 impl CastOp {
+    pub const ALL: [Self; 4] = [Self::Sext, Self::Zext, Self::Trunc, Self::BitCast];
+
     pub(super) fn as_str(self) -> &'static str {
         match self {
             Self::Sext => "sext",
@@ -757,6 +1272,34 @@ impl<'a> Iterator for BranchDestIter<'a> {
     }
 }
 
+/// The relative weights of the destinations of a branch, in the order of
+/// [`BranchInfo::iter_dests`], e.g., `[1, 99]` for a `br` that goes to its second destination 99
+/// times out of 100. Only the ratios matter, so frontends may use counts or probabilities scaled
+/// to integers alike.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BranchWeights(SmallVec<[u32; 2]>);
+
+impl BranchWeights {
+    pub fn new(weights: &[u32]) -> Self {
+        Self(weights.into())
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.0
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().map(|&w| u64::from(w)).sum()
+    }
+
+    /// Returns the probability that the branch goes to its `idx`th destination, or `None` if all
+    /// weights are zero.
+    pub fn probability(&self, idx: usize) -> Option<f64> {
+        let total = self.total();
+        (total != 0).then(|| f64::from(self.0[idx]) / total as f64)
+    }
+}
+
 fn get_gep_result_type(dfg: &DataFlowGraph, base: Value, indices: &[Value]) -> Type {
     let ctx = &dfg.ctx;
     let base_ty = ctx.with_ty_store(|s| {
@@ -778,15 +1321,22 @@ fn get_gep_result_type(dfg: &DataFlowGraph, base: Value, indices: &[Value]) -> T
         result_ty = ctx.with_ty_store(|s| match s.resolve_compound(compound) {
             CompoundTypeData::Array { elem, .. } => *elem,
             CompoundTypeData::Ptr(_) => result_ty,
-            CompoundTypeData::Struct(s) => {
+            CompoundTypeData::Struct(def) => {
+                let index = match dfg.value_data(index) {
+                    ValueData::Immediate { imm, .. } => imm.as_usize(),
+                    _ => unreachable!(),
+                };
+                def.fields[index]
+            }
+            CompoundTypeData::Bytes => {
                 let index = match dfg.value_data(index) {
                     ValueData::Immediate { imm, .. } => imm.as_usize(),
                     _ => unreachable!(),
                 };
-                s.fields[index]
+                s.bytes_field(index)
             }
         });
     }
 
-    ctx.with_ty_store_mut(|s| s.make_ptr(result_ty))
+    ctx.make_ptr(result_ty)
 }
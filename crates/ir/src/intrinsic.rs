@@ -0,0 +1,138 @@
+//! This module contains intrinsics, i.e., target-specific operations that are called like
+//! functions, e.g., reading the number of the current block on the EVM.
+//!
+//! Each [`TargetIsa`](crate::isa::TargetIsa) has a registry of intrinsics, which it fills with the
+//! operations its backend lowers natively. Frontends may register more by name, so that a new
+//! operation doesn't need a new insn; a backend reports intrinsics it can't lower as an error.
+use std::fmt;
+
+use cranelift_entity::PrimaryMap;
+use rustc_hash::FxHashMap;
+
+use crate::Type;
+
+/// An opaque reference to [`IntrinsicData`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Intrinsic(pub u32);
+cranelift_entity::entity_impl!(Intrinsic);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IntrinsicData {
+    pub name: String,
+    pub params: Vec<Type>,
+    /// The type of the result, or [`Type::Void`] if the intrinsic has none.
+    pub ret_ty: Type,
+    /// `false` if the result only depends on the args and on state that doesn't change while the
+    /// contract runs, e.g., the block number, so that the intrinsic may be merged or removed like
+    /// an arithmetic insn.
+    pub has_side_effect: bool,
+}
+
+impl IntrinsicData {
+    /// Makes an intrinsic with side effects.
+    pub fn new(name: &str, params: &[Type], ret_ty: Type) -> Self {
+        Self {
+            name: name.to_string(),
+            params: params.to_vec(),
+            ret_ty,
+            has_side_effect: true,
+        }
+    }
+
+    /// Marks the intrinsic as free of side effects.
+    pub fn pure(mut self) -> Self {
+        self.has_side_effect = false;
+        self
+    }
+}
+
+/// The intrinsics of a target, by name.
+#[derive(Debug, Default)]
+pub struct IntrinsicRegistry {
+    intrinsics: PrimaryMap<Intrinsic, IntrinsicData>,
+    names: FxHashMap<String, Intrinsic>,
+}
+
+impl IntrinsicRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `data`. Registering an intrinsic again returns the existing one, as long as it's
+    /// registered with the same signature.
+    pub fn register(&mut self, data: IntrinsicData) -> Result<Intrinsic, IntrinsicError> {
+        if let Some(&intrinsic) = self.names.get(&data.name) {
+            return if self.intrinsics[intrinsic] == data {
+                Ok(intrinsic)
+            } else {
+                Err(IntrinsicError::SignatureMismatch(data.name))
+            };
+        }
+
+        let name = data.name.clone();
+        let intrinsic = self.intrinsics.push(data);
+        self.names.insert(name, intrinsic);
+        Ok(intrinsic)
+    }
+
+    pub fn intrinsic_by_name(&self, name: &str) -> Option<Intrinsic> {
+        self.names.get(name).copied()
+    }
+
+    pub fn intrinsic_data(&self, intrinsic: Intrinsic) -> &IntrinsicData {
+        &self.intrinsics[intrinsic]
+    }
+
+    pub fn all_intrinsics(&self) -> impl Iterator<Item = (Intrinsic, &IntrinsicData)> {
+        self.intrinsics.iter()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntrinsicError {
+    /// An intrinsic of the name is already registered with another signature.
+    SignatureMismatch(String),
+}
+
+impl fmt::Display for IntrinsicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SignatureMismatch(name) => {
+                write!(f, "intrinsic `{name}` is registered with another signature")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IntrinsicError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let mut registry = IntrinsicRegistry::new();
+        let gas = registry
+            .register(IntrinsicData::new("gas", &[], Type::I256))
+            .unwrap();
+        let number = registry
+            .register(IntrinsicData::new("number", &[], Type::I256).pure())
+            .unwrap();
+        assert_ne!(gas, number);
+        assert_eq!(registry.intrinsic_by_name("number"), Some(number));
+        assert!(!registry.intrinsic_data(number).has_side_effect);
+
+        assert_eq!(
+            registry.register(IntrinsicData::new("gas", &[], Type::I256)),
+            Ok(gas)
+        );
+        assert_eq!(
+            registry.register(IntrinsicData::new("gas", &[Type::I256], Type::I256)),
+            Err(IntrinsicError::SignatureMismatch("gas".to_string()))
+        );
+        assert_eq!(registry.all_intrinsics().count(), 2);
+    }
+}
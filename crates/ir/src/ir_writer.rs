@@ -1,9 +1,11 @@
-use std::io;
+use std::{collections::BTreeSet, io};
 
 use crate::{
-    module::{FuncRef, ModuleCtx},
+    block_params::BlockParams,
+    insn::DisplaySelector,
+    module::{FuncRef, ModuleCtx, WidthPolicy},
     types::{CompoundType, CompoundTypeData, StructData},
-    DataLocationKind, GlobalVariableData, Module,
+    ControlFlowGraph, DataLocationKind, GlobalVariableData, Module,
 };
 
 use super::{Block, Function, Insn, InsnData, Type, Value};
@@ -18,6 +20,7 @@ impl DebugProvider for () {}
 pub struct ModuleWriter<'a> {
     module: &'a Module,
     debug: Option<&'a dyn DebugProvider>,
+    block_params: bool,
 }
 
 impl<'a> ModuleWriter<'a> {}
@@ -27,6 +30,7 @@ impl<'a> ModuleWriter<'a> {
         Self {
             module,
             debug: None,
+            block_params: false,
         }
     }
 
@@ -34,12 +38,23 @@ impl<'a> ModuleWriter<'a> {
         Self {
             module,
             debug: Some(debug),
+            block_params: false,
         }
     }
 
+    /// Writes phis as block params, see [`FuncWriter::with_block_params`]. A function whose phis
+    /// can't be converted, e.g., since a phi lacks an arg from a predecessor, is written with phis.
+    pub fn with_block_params(mut self) -> Self {
+        self.block_params = true;
+        self
+    }
+
     pub fn write(&mut self, mut w: impl io::Write) -> io::Result<()> {
         // Write target.
-        writeln!(w, "target = {}", self.module.ctx.isa.triple())?;
+        writeln!(w, "target = \"{}\"", self.module.ctx.isa.triple())?;
+        if self.module.ctx.width_policy != WidthPolicy::default() {
+            writeln!(w, "width_policy = \"{}\"", self.module.ctx.width_policy)?;
+        }
 
         // Write struct types defined in the module.
         self.module.ctx.with_ty_store(|s| {
@@ -58,9 +73,31 @@ impl<'a> ModuleWriter<'a> {
             io::Result::Ok(())
         })?;
 
-        for func_ref in self.module.funcs.keys() {
+        self.write_intrinsic_declarations(&mut w)?;
+
+        // Functions without a body are written as declarations, which precede all definitions.
+        let (decls, defs): (Vec<_>, Vec<_>) = self
+            .module
+            .iter_functions()
+            .partition(|func_ref| self.module.funcs[*func_ref].layout.entry_block().is_none());
+        for func_ref in decls {
+            let func = &self.module.funcs[func_ref];
+            FuncWriter::new(func_ref, func, self.debug).write_declaration(&mut w)?;
+        }
+        for func_ref in defs {
             let func = &self.module.funcs[func_ref];
+            let block_params = self
+                .block_params
+                .then(|| {
+                    let mut cfg = ControlFlowGraph::new();
+                    cfg.compute(func);
+                    BlockParams::from_phis(func, &cfg).ok()
+                })
+                .flatten();
             let mut func_writer = FuncWriter::new(func_ref, func, self.debug);
+            if let Some(block_params) = &block_params {
+                func_writer = func_writer.with_block_params(block_params);
+            }
             func_writer.write(&mut w)?;
             writeln!(w)?;
         }
@@ -73,6 +110,44 @@ impl<'a> ModuleWriter<'a> {
         self.write(&mut s)?;
         unsafe { Ok(String::from_utf8_unchecked(s)) }
     }
+
+    /// Writes the intrinsics the functions use as declarations, e.g.,
+    /// `declare intrinsic %number() -> i256 pure;`, so that intrinsics registered by a frontend
+    /// are registered again when the module is parsed.
+    fn write_intrinsic_declarations(&self, mut w: impl io::Write) -> io::Result<()> {
+        let mut used = BTreeSet::new();
+        for func_ref in self.module.iter_functions() {
+            let func = &self.module.funcs[func_ref];
+            for block in func.layout.iter_block() {
+                for insn in func.layout.iter_insn(block) {
+                    if let InsnData::Intrinsic { intrinsic, .. } = func.dfg.insn_data(insn) {
+                        used.insert(*intrinsic);
+                    }
+                }
+            }
+        }
+
+        let ctx = &self.module.ctx;
+        ctx.isa.with_intrinsics(|r| {
+            for intrinsic in used {
+                let data = r.intrinsic_data(intrinsic);
+                write!(w, "declare intrinsic %{}(", data.name)?;
+                let mut delim = "";
+                for ty in &data.params {
+                    write!(w, "{delim}")?;
+                    ty.ir_write(ctx, &mut w)?;
+                    delim = ", ";
+                }
+                write!(w, ") -> ")?;
+                data.ret_ty.ir_write(ctx, &mut w)?;
+                if !data.has_side_effect {
+                    write!(w, " pure")?;
+                }
+                writeln!(w, ";")?;
+            }
+            io::Result::Ok(())
+        })
+    }
 }
 
 pub struct FuncWriter<'a> {
@@ -80,6 +155,7 @@ pub struct FuncWriter<'a> {
     func: &'a Function,
     level: u8,
     debug: Option<&'a dyn DebugProvider>,
+    block_params: Option<&'a BlockParams>,
 }
 
 impl<'a> FuncWriter<'a> {
@@ -93,17 +169,24 @@ impl<'a> FuncWriter<'a> {
             func,
             level: 0,
             debug,
+            block_params: None,
         }
     }
 
-    pub fn write(&mut self, mut w: impl io::Write) -> io::Result<()> {
-        // TODO: extern declarations aren't printed correctly
+    /// Writes the phis in `block_params` as params of their blocks, e.g., `block1(v1.i64):`, and
+    /// their args as args of the branches, e.g., `jump block1(v2);`.
+    pub fn with_block_params(mut self, block_params: &'a BlockParams) -> Self {
+        self.block_params = Some(block_params);
+        self
+    }
 
-        w.write_fmt(format_args!(
-            "func {} %{}(",
-            self.func.sig.linkage(),
-            self.func.sig.name()
-        ))?;
+    pub fn write(&mut self, mut w: impl io::Write) -> io::Result<()> {
+        let sig = &self.func.sig;
+        write!(w, "func {} ", sig.linkage())?;
+        if sig.has_explicit_call_conv() {
+            write!(w, "{} ", sig.call_conv())?;
+        }
+        write!(w, "%{}(", sig.name())?;
         self.write_iter_with_delim(
             self.func.arg_values.iter().map(|v| ValueWithTy(*v)),
             ", ",
@@ -111,6 +194,9 @@ impl<'a> FuncWriter<'a> {
         )?;
         write!(w, ") -> ")?;
         self.func.sig.ret_ty().ir_write(self.ctx(), &mut w)?;
+        if !self.func.sig.attrs().is_empty() {
+            write!(w, " {}", self.func.sig.attrs())?;
+        }
 
         writeln!(w, " {{")?;
         self.level += 1;
@@ -127,6 +213,37 @@ impl<'a> FuncWriter<'a> {
         Ok(())
     }
 
+    /// Writes the signature of the function as a declaration, e.g.,
+    /// `declare external %add(i8, i8) -> i8;`.
+    pub fn write_declaration(&mut self, mut w: impl io::Write) -> io::Result<()> {
+        let sig = &self.func.sig;
+        write!(w, "declare {} ", sig.linkage())?;
+        if sig.has_explicit_call_conv() {
+            write!(w, "{} ", sig.call_conv())?;
+        }
+        write!(w, "%{}(", sig.name())?;
+        let mut delim = "";
+        for ty in sig.args() {
+            write!(w, "{delim}")?;
+            ty.ir_write(self.ctx(), &mut w)?;
+            delim = ", ";
+        }
+        write!(w, ") -> ")?;
+        sig.ret_ty().ir_write(self.ctx(), &mut w)?;
+        if !sig.attrs().is_empty() {
+            write!(w, " {}", sig.attrs())?;
+        }
+        writeln!(w, ";")
+    }
+
+    /// Writes `insn` as it appears in the body of the function, without indentation.
+    pub fn write_insn(&mut self, insn: Insn, mut w: impl io::Write) -> io::Result<()> {
+        let level = std::mem::take(&mut self.level);
+        let result = insn.write(self, &mut w);
+        self.level = level;
+        result
+    }
+
     pub fn ctx(&self) -> &ModuleCtx {
         &self.func.dfg.ctx
     }
@@ -144,10 +261,25 @@ impl<'a> FuncWriter<'a> {
     fn write_block_with_insn(&mut self, block: Block, mut w: impl io::Write) -> io::Result<()> {
         self.indent(&mut w)?;
         block.write(self, &mut w)?;
+        let params = self.block_params.map_or(&[][..], |bp| bp.params(block));
+        if !params.is_empty() {
+            write!(w, "(")?;
+            self.write_iter_with_delim(params.iter().map(|v| ValueWithTy(*v)), ", ", &mut w)?;
+            write!(w, ")")?;
+        }
 
         self.enter(&mut w)?;
-        let insns = self.func.layout.iter_insn(block);
-        self.write_iter_with_delim(insns, "\n", &mut w)?;
+        let insns: Vec<_> = self
+            .func
+            .layout
+            .iter_insn(block)
+            .filter(|&insn| {
+                !self
+                    .block_params
+                    .is_some_and(|bp| bp.is_param_phi(block, insn))
+            })
+            .collect();
+        self.write_iter_with_delim(insns.into_iter(), "\n", &mut w)?;
         self.leave();
 
         Ok(())
@@ -157,6 +289,22 @@ impl<'a> FuncWriter<'a> {
         self.write_iter_with_delim(args.iter(), " ", &mut w)
     }
 
+    /// Writes `dest`, a destination of the branch `insn`, followed by the args it passes to the
+    /// params of `dest` if phis are written as block params.
+    fn write_dest(&mut self, insn: Insn, dest: Block, mut w: impl io::Write) -> io::Result<()> {
+        dest.write(self, &mut w)?;
+        let Some(block_params) = self.block_params else {
+            return Ok(());
+        };
+        let args = block_params.args(self.func.layout.insn_block(insn), dest);
+        if !args.is_empty() {
+            write!(w, "(")?;
+            self.write_iter_with_delim(args.iter(), ", ", &mut w)?;
+            write!(w, ")")?;
+        }
+        Ok(())
+    }
+
     fn write_iter_with_delim<T>(
         &mut self,
         iter: impl Iterator<Item = T>,
@@ -206,14 +354,15 @@ trait IrWrite {
 impl IrWrite for Value {
     fn write(&self, writer: &mut FuncWriter, w: &mut impl io::Write) -> io::Result<()> {
         let value = *self;
-        if let Some(imm) = writer.func.dfg.value_imm(value) {
-            write!(w, "{}.", imm)?;
-            let ty = writer.func.dfg.value_ty(value);
-            ty.ir_write(writer.ctx(), w)
-        } else if let Some(gv) = writer.func.dfg.value_gv(value) {
+        // `value_imm` sees through constant globals, so globals are checked first.
+        if let Some(gv) = writer.func.dfg.value_gv(value) {
             writer
                 .ctx()
                 .with_gv_store(|s| write!(w, "%{}", s.gv_data(gv).symbol))
+        } else if let Some(imm) = writer.func.dfg.value_imm(value) {
+            write!(w, "{}.", imm)?;
+            let ty = writer.func.dfg.value_ty(value);
+            ty.ir_write(writer.ctx(), w)
         } else if let Some(name) = writer.value_name(value) {
             write!(w, "{name}")
         } else {
@@ -229,9 +378,9 @@ impl GlobalVariableData {
         self.ty.ir_write(ctx, w)?;
 
         if let Some(data) = &self.data {
-            write!(w, " = {};", data)
+            writeln!(w, " = {};", data)
         } else {
-            write!(w, ";")
+            writeln!(w, ";")
         }
     }
 }
@@ -256,6 +405,13 @@ impl Type {
             Self::Compound(compound) => compound.ir_write(ctx, w),
         }
     }
+
+    /// Returns the type as it's written in the textual IR.
+    pub(crate) fn ir_string(&self, ctx: &ModuleCtx) -> String {
+        let mut buf = Vec::new();
+        self.ir_write(ctx, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
 }
 
 impl CompoundType {
@@ -275,6 +431,7 @@ impl CompoundType {
             CompoundTypeData::Struct(def) => {
                 write!(w, "%{}", def.name)
             }
+            CompoundTypeData::Bytes => write!(w, "bytes"),
         }
     }
 }
@@ -308,11 +465,15 @@ impl IrWrite for Insn {
         use InsnData::*;
 
         writer.indent(&mut *w)?;
-        if let Some(insn_result) = writer.func.dfg.insn_result(*self) {
-            insn_result.write(writer, &mut *w)?;
-            w.write_all(b".")?;
-            let ty = writer.func.dfg.value_ty(insn_result);
-            ty.ir_write(writer.ctx(), &mut *w)?;
+        let func = writer.func;
+        let results = func.dfg.insn_results(*self);
+        if !results.is_empty() {
+            for (i, insn_result) in results.iter().enumerate() {
+                if i > 0 {
+                    w.write_all(b", ")?;
+                }
+                ValueWithTy(*insn_result).write(writer, &mut *w)?;
+            }
             w.write_all(b" = ")?;
         }
 
@@ -330,6 +491,12 @@ impl IrWrite for Insn {
                 writer.write_insn_args(args, &mut *w)?;
             }
 
+            OverflowBinary { code, args } => {
+                write!(w, "{}", code)?;
+                writer.space(&mut *w)?;
+                writer.write_insn_args(args, &mut *w)?;
+            }
+
             Cast { code, args, .. } => {
                 write!(w, "{}", code)?;
                 writer.space(&mut *w)?;
@@ -358,7 +525,27 @@ impl IrWrite for Insn {
                 writer.write_insn_args(args, &mut *w)?;
             }
 
-            Call { func, args, .. } => {
+            Mem { code, args, loc } => {
+                write!(w, "{code}")?;
+                writer.space(&mut *w)?;
+                match loc {
+                    DataLocationKind::Memory => write!(w, "@memory")?,
+                    DataLocationKind::Storage => write!(w, "@storage")?,
+                }
+                writer.space(&mut *w)?;
+                writer.write_insn_args(args, &mut *w)?;
+            }
+
+            Call {
+                func,
+                args,
+                is_tail,
+                ..
+            } => {
+                if *is_tail {
+                    write!(w, "tail")?;
+                    writer.space(&mut *w)?;
+                }
                 write!(w, "call")?;
                 writer.space(&mut *w)?;
                 write!(w, "%{}", writer.func.callees[func].name())?;
@@ -366,18 +553,36 @@ impl IrWrite for Insn {
                 writer.write_insn_args(args, &mut *w)?;
             }
 
+            Intrinsic {
+                intrinsic, args, ..
+            } => {
+                let name = writer
+                    .func
+                    .dfg
+                    .ctx
+                    .isa
+                    .with_intrinsics(|r| r.intrinsic_data(*intrinsic).name.clone());
+                write!(w, "intrinsic %{name}")?;
+                if !args.is_empty() {
+                    writer.space(&mut *w)?;
+                    writer.write_insn_args(args, &mut *w)?;
+                }
+            }
+
             Jump { dests } => {
                 write!(w, "jump")?;
                 writer.space(&mut *w)?;
-                writer.write_iter_with_delim(dests.iter(), " ", &mut *w)?;
+                writer.write_dest(*self, dests[0], &mut *w)?;
             }
 
             Branch { args, dests } => {
                 write!(w, "br")?;
                 writer.space(&mut *w)?;
                 writer.write_insn_args(args, &mut *w)?;
-                writer.space(&mut *w)?;
-                writer.write_iter_with_delim(dests.iter(), " ", &mut *w)?;
+                for dest in dests {
+                    writer.space(&mut *w)?;
+                    writer.write_dest(*self, *dest, &mut *w)?;
+                }
             }
 
             BrTable {
@@ -388,25 +593,18 @@ impl IrWrite for Insn {
                 write!(w, "br_table")?;
                 writer.space(&mut *w)?;
                 args[0].write(writer, &mut *w)?;
-                writer.space(&mut *w)?;
                 if let Some(default) = default {
-                    default.write(writer, &mut *w)?;
-                } else {
-                    write!(w, "undef")?;
+                    writer.space(&mut *w)?;
+                    writer.write_dest(*self, *default, &mut *w)?;
                 }
-                writer.space(&mut *w)?;
 
-                let mut table_args = vec![];
                 for (value, block) in args[1..].iter().zip(table.iter()) {
-                    let mut arg = vec![b'('];
-                    value.write(writer, &mut arg)?;
-                    writer.space(&mut arg)?;
-                    block.write(writer, &mut arg)?;
-                    arg.push(b')');
-                    table_args.push(arg);
+                    write!(w, " (")?;
+                    value.write(writer, &mut *w)?;
+                    writer.space(&mut *w)?;
+                    writer.write_dest(*self, *block, &mut *w)?;
+                    write!(w, ")")?;
                 }
-
-                writer.write_iter_with_delim(table_args.iter(), " ", &mut *w)?;
             }
 
             Alloca { ty } => {
@@ -423,6 +621,18 @@ impl IrWrite for Insn {
                 }
             }
 
+            Revert { selector, args } => {
+                write!(w, "revert {}", DisplaySelector(*selector))?;
+                if !args.is_empty() {
+                    writer.space(&mut *w)?;
+                    writer.write_insn_args(args, &mut *w)?;
+                }
+            }
+
+            Trap { reason } => write!(w, "trap {reason}")?,
+
+            Unreachable => write!(w, "unreachable")?,
+
             Gep { args } => {
                 write!(w, "gep")?;
                 writer.space(&mut *w)?;
@@ -1,12 +1,15 @@
-use crate::Type;
+use crate::{
+    insn::{BinaryOp, CastOp, DataLocationKind, MemOp, OverflowOp, UnaryOp},
+    intrinsic::{IntrinsicData, IntrinsicRegistry},
+    InsnData, Type,
+};
 
-use super::{IsaSpecificTypeProvider, TargetIsa};
+use super::{Endian, IsaSpecificCostTable, IsaSpecificTypeProvider, TargetIsa};
 
 use sonatina_triple::{Architecture, Chain, EvmVersion, TargetTriple, Version};
 
 #[derive(Debug, Clone, Copy)]
 pub struct EvmEth {
-    #[allow(unused)]
     version: EvmVersion,
 }
 
@@ -14,15 +17,48 @@ impl EvmEth {
     pub(super) fn build_isa(triple: TargetTriple) -> TargetIsa {
         debug_assert_eq!(triple.architecture, Architecture::Evm);
         debug_assert_eq!(triple.chain, Chain::Ethereum);
-        let type_provider = match triple.version {
+        let isa = match triple.version {
             Version::EvmVersion(version) => Self { version },
+            _ => unreachable!(),
         };
 
-        TargetIsa::new(triple, Box::new(type_provider))
+        TargetIsa::new(triple, Box::new(isa), Box::new(isa), isa.intrinsics())
+    }
+
+    /// Returns the intrinsics that are lowered to a single opcode. They have the names of the
+    /// builtins of the interpreter, which runs them the same way.
+    fn intrinsics(self) -> IntrinsicRegistry {
+        let mut registry = IntrinsicRegistry::new();
+        let word = Type::I256;
+
+        let mut block_info = vec!["number", "timestamp", "coinbase", "gaslimit"];
+        if self.version >= EvmVersion::Istanbul {
+            block_info.push("chainid");
+        }
+        if self.version >= EvmVersion::London {
+            block_info.push("basefee");
+        }
+        // The block doesn't change while the contract runs, but balances change with calls, and a
+        // hash reads memory.
+        let intrinsics = block_info
+            .into_iter()
+            .map(|name| IntrinsicData::new(name, &[], word).pure())
+            .chain([
+                IntrinsicData::new("balance", &[word], word),
+                IntrinsicData::new("keccak256", &[word, word], word),
+            ]);
+        for data in intrinsics {
+            registry.register(data).unwrap();
+        }
+        registry
     }
 }
 
 impl IsaSpecificTypeProvider for EvmEth {
+    fn word_type(&self) -> Type {
+        Type::I256
+    }
+
     fn pointer_type(&self) -> Type {
         Type::I256
     }
@@ -38,4 +74,227 @@ impl IsaSpecificTypeProvider for EvmEth {
     fn gas_type(&self) -> Type {
         Type::I256
     }
+
+    fn endian(&self) -> Endian {
+        Endian::Big
+    }
+}
+
+/// Gas costs are estimated from the opcode sequences that insns are lowered to, e.g., a branch is
+/// `PUSH` + `JUMPI`. Storage accesses are charged as cold accesses, and `SSTORE` as setting a
+/// zero slot to non-zero, so the cost is an upper bound.
+impl IsaSpecificCostTable for EvmEth {
+    fn insn_cost(&self, insn_data: &InsnData) -> u64 {
+        const PUSH: u64 = 3;
+        const JUMP: u64 = 8;
+        const JUMPI: u64 = 10;
+
+        match insn_data {
+            InsnData::Unary { code, .. } => match code {
+                UnaryOp::Not => 3,
+                // `PUSH 0` + `SUB`.
+                UnaryOp::Neg => PUSH + 3,
+                // Bit manipulation is expanded into shifts and masks, which take fewer steps for
+                // narrower types, so these are the costs for `i256`.
+                UnaryOp::Popcount => 98,
+                UnaryOp::Ctz => 125,
+                UnaryOp::Clz => 200,
+                UnaryOp::Bswap => 165,
+            },
+
+            InsnData::Binary { code, .. } => match code {
+                BinaryOp::Mul | BinaryOp::Udiv | BinaryOp::Sdiv => 5,
+                // Negated comparisons need an extra `ISZERO`.
+                BinaryOp::Le | BinaryOp::Ge | BinaryOp::Sle | BinaryOp::Sge | BinaryOp::Ne => 6,
+                // Two shifts of the operand, by the amount and by its complement, are ORed.
+                BinaryOp::Rotl | BinaryOp::Rotr => 10 * 3,
+                _ => 3,
+            },
+
+            InsnData::OverflowBinary { code, .. } => match code {
+                // `ADD` or `SUB`, then `DUP` + `DUP` + `LT` with an operand for the flag.
+                OverflowOp::Uaddo | OverflowOp::Usubo => 4 * 3,
+                // The flag compares the signs of the operands and the result.
+                OverflowOp::Saddo | OverflowOp::Ssubo => 9 * 3,
+                // The product is divided by an operand and compared with the other one.
+                OverflowOp::Umulo => 5 + 5 + 4 * 3,
+                // `-1 * MIN` overflows without failing the division check.
+                OverflowOp::Smulo => 5 + 5 + 8 * 3,
+            },
+
+            InsnData::Cast { code, .. } => match code {
+                CastOp::Sext => PUSH + 5,
+                CastOp::Zext | CastOp::Trunc => PUSH + 3,
+                CastOp::BitCast => 0,
+            },
+
+            InsnData::Load { loc, .. } => match loc {
+                DataLocationKind::Memory => 3,
+                DataLocationKind::Storage => 2100,
+            },
+
+            InsnData::Store { loc, .. } => match loc {
+                DataLocationKind::Memory => 3,
+                DataLocationKind::Storage => 22100,
+            },
+
+            // A copy within memory calls the identity precompile, which costs `100` for the warm
+            // access plus `18` for a word. The other intrinsics are loops, which are charged for a
+            // single iteration.
+            InsnData::Mem { code, loc, .. } => match (code, loc) {
+                (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Memory) => {
+                    PUSH * 5 + 2 + 100 + 18 + 2
+                }
+                (MemOp::Memset, DataLocationKind::Memory) => 100,
+                (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Storage) => 2100 + 22100 + 100,
+                (MemOp::Memset, DataLocationKind::Storage) => 22100 + 100,
+            },
+
+            // Push the return address and the callee, then `JUMP` + `JUMPDEST`.
+            InsnData::Call { .. } => PUSH * 2 + JUMP + 1,
+
+            // The intrinsics of the target are single opcodes, most of which read the block for
+            // `2`.
+            InsnData::Intrinsic { .. } => 2,
+
+            InsnData::Jump { .. } => PUSH + JUMP,
+
+            InsnData::Branch { .. } => PUSH + JUMPI,
+
+            // Each case is `DUP` + `PUSH` + `EQ` + `PUSH` + `JUMPI`.
+            InsnData::BrTable { table, default, .. } => {
+                let cases = table.len() as u64 * (3 + PUSH + 3 + PUSH + JUMPI);
+                cases + if default.is_some() { PUSH + JUMP } else { 0 }
+            }
+
+            InsnData::Alloca { .. } => 0,
+
+            // `SWAP` + `JUMP` back to the return address.
+            InsnData::Return { .. } => 3 + JUMP,
+
+            // The selector is shifted into place and stored, then each arg is stored after it,
+            // and `PUSH` + `PUSH` + `REVERT` returns the data. A trap reverts with its panic code.
+            InsnData::Revert { args, .. } => {
+                PUSH * 3 + 3 + 3 + args.len() as u64 * (PUSH + 3) + PUSH * 2
+            }
+            InsnData::Trap { .. } => PUSH * 3 + 3 + 3 + PUSH * 2 + 3 + PUSH * 2,
+
+            // `INVALID`, which is never executed.
+            InsnData::Unreachable => 0,
+
+            // `MUL` + `ADD` for each index.
+            InsnData::Gep { args } => (args.len() as u64 - 1) * (5 + 3),
+
+            InsnData::Phi { .. } => 0,
+        }
+    }
+
+    /// Sizes follow the frame-based lowering, where each operand is loaded from its slot in the
+    /// frame and each result is stored to its slot, i.e., `PUSH1` + `MLOAD` + `PUSH1` + `ADD` +
+    /// `MLOAD` or `MSTORE`. Results narrower than a word are masked with a `PUSH4` + `AND`, and
+    /// labels are pushed with a `PUSH2`, which fits any offset in a contract of the size limit.
+    fn insn_size(&self, insn_data: &InsnData) -> u64 {
+        const SLOT: u64 = 7;
+        const MASK: u64 = 6;
+        const POPCOUNT: u64 = 229;
+        const LABEL: u64 = 3;
+        // Restores the frame pointer and the stack pointer of the caller.
+        const EPILOGUE: u64 = 11;
+
+        match insn_data {
+            InsnData::Unary { code, .. } => match code {
+                UnaryOp::Not => SLOT + 1 + MASK + SLOT,
+                UnaryOp::Neg => SLOT + 3 + MASK + SLOT,
+                // Most of the expansions are 32-byte masks.
+                UnaryOp::Popcount => SLOT + POPCOUNT + MASK + SLOT,
+                UnaryOp::Ctz => SLOT + 9 + MASK + POPCOUNT + MASK + SLOT,
+                UnaryOp::Clz => SLOT + 40 + POPCOUNT + 3 + MASK + SLOT,
+                UnaryOp::Bswap => SLOT + 385 + MASK + SLOT,
+            },
+
+            InsnData::Binary { code, .. } => match code {
+                BinaryOp::Le | BinaryOp::Ge | BinaryOp::Sle | BinaryOp::Sge | BinaryOp::Ne => {
+                    SLOT * 2 + 2 + SLOT
+                }
+                BinaryOp::Rotl | BinaryOp::Rotr => SLOT * 2 + 12 + MASK + SLOT,
+                _ => SLOT * 2 + 1 + MASK + SLOT,
+            },
+
+            // Both the result and the flag are stored to their slots.
+            InsnData::OverflowBinary { code, .. } => {
+                let ops = match code {
+                    OverflowOp::Uaddo | OverflowOp::Usubo => 5,
+                    OverflowOp::Saddo | OverflowOp::Ssubo => 12,
+                    OverflowOp::Umulo => 10,
+                    OverflowOp::Smulo => 14,
+                };
+                SLOT * 2 + ops + MASK + SLOT * 2
+            }
+
+            InsnData::Cast { code, .. } => match code {
+                // `PUSH1` + `SIGNEXTEND` before masking.
+                CastOp::Sext => SLOT + 3 + MASK + SLOT,
+                CastOp::Zext | CastOp::Trunc => SLOT + MASK + SLOT,
+                CastOp::BitCast => SLOT + SLOT,
+            },
+
+            InsnData::Load { .. } => SLOT + 1 + SLOT,
+
+            InsnData::Store { .. } => SLOT * 2 + 1,
+
+            // A loop pushes its index, compares it with the length, and jumps out and back with
+            // labels. A `memmove` in storage has a loop for each direction.
+            InsnData::Mem { code, loc, .. } => {
+                const LOOP: u64 = SLOT + LABEL * 2 + 16;
+                match (code, loc) {
+                    (MemOp::Memcpy | MemOp::Memmove, DataLocationKind::Memory) => SLOT * 4 + 5,
+                    (MemOp::Memset, DataLocationKind::Memory) => LOOP + SLOT * 2 + 3,
+                    (MemOp::Memcpy, DataLocationKind::Storage) => LOOP + SLOT * 2 + 6,
+                    (MemOp::Memmove, DataLocationKind::Storage) => {
+                        SLOT * 2 + 1 + LABEL * 2 + 4 + (LOOP + SLOT * 2 + 6) * 2
+                    }
+                    (MemOp::Memset, DataLocationKind::Storage) => LOOP + SLOT * 2 + 38,
+                }
+            }
+
+            // Push the return address, the arguments and the callee, then `JUMP` + `JUMPDEST`.
+            InsnData::Call { args, .. } => LABEL + args.len() as u64 * SLOT + LABEL + 2 + SLOT,
+
+            InsnData::Intrinsic { args, .. } => args.len() as u64 * SLOT + 1 + SLOT,
+
+            InsnData::Jump { .. } => LABEL + 1,
+
+            InsnData::Branch { .. } => SLOT + LABEL + 1 + LABEL + 1,
+
+            // Each case loads the key and the scrutinee, then `EQ` + `PUSH2` + `JUMPI`.
+            InsnData::BrTable { table, default, .. } => {
+                let cases = table.len() as u64 * (SLOT * 2 + 1 + LABEL + 1);
+                cases + if default.is_some() { LABEL + 1 } else { 0 }
+            }
+
+            // The frame pointer plus the offset of the region.
+            InsnData::Alloca { .. } => 6 + SLOT,
+
+            // `SWAP1` + `JUMP` back to the return address.
+            InsnData::Return { args } => args.map_or(0, |_| SLOT + 1) + EPILOGUE + 1,
+
+            // `PUSH4` + `PUSH1` + `SHL` + `PUSH1` + `MSTORE` for the selector, `PUSH1` +
+            // `MSTORE` for each arg, and `PUSH1` + `PUSH1` + `REVERT`.
+            InsnData::Revert { args, .. } => 11 + args.len() as u64 * (SLOT + 3) + 5,
+            InsnData::Trap { .. } => 11 + 2 + 3 + 5,
+
+            InsnData::Unreachable => 1,
+
+            // `PUSH` + `MUL` + `ADD` for each index.
+            InsnData::Gep { args } => SLOT + (args.len() as u64 - 1) * (SLOT + 4) + SLOT,
+
+            InsnData::Phi { .. } => 0,
+        }
+    }
+
+    /// The index is shifted, rebased and bounds-checked, then the entry is copied from the code
+    /// to memory with `CODECOPY` and jumped to.
+    fn jump_table_cost(&self) -> u64 {
+        90
+    }
 }
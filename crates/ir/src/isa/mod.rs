@@ -1,9 +1,12 @@
+use std::sync::{Arc, RwLock};
+
 use dyn_clone::DynClone;
 use sonatina_triple::{Architecture, TargetTriple};
 
-use crate::Type;
+use crate::{intrinsic::IntrinsicRegistry, InsnData, Type, U256};
 
 pub mod evm_eth;
+pub mod riscv32;
 
 pub struct IsaBuilder {
     triple: TargetTriple,
@@ -16,6 +19,7 @@ impl IsaBuilder {
     pub fn build(self) -> TargetIsa {
         match self.triple.architecture {
             Architecture::Evm => evm_eth::EvmEth::build_isa(self.triple),
+            Architecture::Riscv32 => riscv32::Riscv32::build_isa(self.triple),
         }
     }
 }
@@ -24,6 +28,10 @@ impl IsaBuilder {
 pub struct TargetIsa {
     triple: TargetTriple,
     type_provider: Box<dyn IsaSpecificTypeProvider>,
+    cost_table: Box<dyn IsaSpecificCostTable>,
+    /// Shared by the clones of the ISA, so that intrinsics registered while a module is built are
+    /// seen by all of its functions.
+    intrinsics: Arc<RwLock<IntrinsicRegistry>>,
 }
 
 impl TargetIsa {
@@ -31,23 +39,205 @@ impl TargetIsa {
         self.type_provider.as_ref()
     }
 
+    pub fn cost_table(&self) -> &dyn IsaSpecificCostTable {
+        self.cost_table.as_ref()
+    }
+
     pub fn triple(&self) -> &TargetTriple {
         &self.triple
     }
 
-    fn new(triple: TargetTriple, type_provider: Box<dyn IsaSpecificTypeProvider>) -> Self {
+    pub fn type_layout(&self) -> TypeLayout {
+        let pointer_size = TypeLayout::int_size(self.type_provider.pointer_type());
+        TypeLayout::new(self.type_provider.endian(), pointer_size)
+    }
+
+    pub fn with_intrinsics<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&IntrinsicRegistry) -> R,
+    {
+        f(&self.intrinsics.read().unwrap())
+    }
+
+    /// Gives mutable access to the intrinsics of the target, e.g., to register the intrinsics of a
+    /// frontend.
+    pub fn with_intrinsics_mut<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut IntrinsicRegistry) -> R,
+    {
+        f(&mut self.intrinsics.write().unwrap())
+    }
+
+    fn new(
+        triple: TargetTriple,
+        type_provider: Box<dyn IsaSpecificTypeProvider>,
+        cost_table: Box<dyn IsaSpecificCostTable>,
+        intrinsics: IntrinsicRegistry,
+    ) -> Self {
         Self {
             triple,
             type_provider,
+            cost_table,
+            intrinsics: Arc::new(RwLock::new(intrinsics)),
         }
     }
 }
 
-pub trait IsaSpecificTypeProvider: std::fmt::Debug + DynClone {
+pub trait IsaSpecificTypeProvider: std::fmt::Debug + DynClone + Send + Sync {
+    /// Returns the integer type of the native word of the target, which frontends use for sizes
+    /// and indices, e.g., `i256` on the EVM.
+    fn word_type(&self) -> Type;
     fn pointer_type(&self) -> Type;
     fn address_type(&self) -> Type;
     fn balance_type(&self) -> Type;
     fn gas_type(&self) -> Type;
+    /// Returns the byte order of scalars in memory.
+    fn endian(&self) -> Endian;
 }
 
 dyn_clone::clone_trait_object!(IsaSpecificTypeProvider);
+
+/// The cost model of a target, which heuristics of optimizations and lowerings weigh their
+/// choices with instead of counting insns.
+pub trait IsaSpecificCostTable: std::fmt::Debug + DynClone + Send + Sync {
+    /// Returns the static cost of executing the insn on the target, e.g., gas on the EVM.
+    /// Dynamic costs such as memory expansion are not included.
+    fn insn_cost(&self, insn_data: &InsnData) -> u64;
+
+    /// Returns the estimated number of bytes of code the insn is lowered to.
+    fn insn_size(&self, insn_data: &InsnData) -> u64;
+
+    /// Returns the cost of jumping to the destination of a switch through a jump table, i.e.,
+    /// computing the index and loading the entry, excluding the comparisons made after.
+    fn jump_table_cost(&self) -> u64;
+}
+
+dyn_clone::clone_trait_object!(IsaSpecificCostTable);
+
+/// The byte order of scalars in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    /// The most significant byte comes first, e.g., on the EVM.
+    Big,
+    /// The least significant byte comes first, e.g., in Wasm linear memory.
+    Little,
+}
+
+impl Endian {
+    /// Returns the offset of the `significance`-th least significant byte in the memory of a
+    /// `size`-byte scalar.
+    pub fn byte_offset_in_word(self, size: usize, significance: usize) -> usize {
+        debug_assert!(significance < size);
+        match self {
+            Self::Big => size - 1 - significance,
+            Self::Little => significance,
+        }
+    }
+}
+
+/// The layout of scalars in the memory of a target, which byte-level accesses, e.g., bitfields and
+/// ABI encoding, go through instead of assuming a byte order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub endian: Endian,
+    /// The number of bytes of a pointer.
+    pub pointer_size: usize,
+}
+
+impl TypeLayout {
+    pub fn new(endian: Endian, pointer_size: usize) -> Self {
+        Self {
+            endian,
+            pointer_size,
+        }
+    }
+
+    /// Returns the number of bytes a scalar of `ty` occupies in memory. Compound types are
+    /// pointers, the only compound scalars.
+    pub fn scalar_size(&self, ty: Type) -> usize {
+        match ty {
+            Type::Compound(_) => self.pointer_size,
+            _ => Self::int_size(ty),
+        }
+    }
+
+    /// Returns the offset of the `significance`-th least significant byte in the memory of a
+    /// scalar of `ty`.
+    pub fn byte_offset_in_word(&self, ty: Type, significance: usize) -> usize {
+        self.endian
+            .byte_offset_in_word(self.scalar_size(ty), significance)
+    }
+
+    /// Returns the number of bits to shift a scalar of `ty` right by to move the byte at offset
+    /// `idx` of its memory to the least significant byte.
+    pub fn extract_byte(&self, ty: Type, idx: usize) -> usize {
+        // The byte offset is its own inverse, so it maps an offset back to a significance, too.
+        self.byte_offset_in_word(ty, idx) * 8
+    }
+
+    /// Returns the memory image of the low `scalar_size(ty)` bytes of `value`.
+    pub fn to_bytes(&self, value: U256, ty: Type) -> Vec<u8> {
+        let size = self.scalar_size(ty);
+        let mut bytes = vec![0; size];
+        for (significance, byte) in (0..size.min(32)).map(|i| (i, value.byte(i))) {
+            bytes[self.endian.byte_offset_in_word(size, significance)] = byte;
+        }
+        bytes
+    }
+
+    /// Returns the scalar of `ty` whose memory image is `bytes`, zero-extended.
+    pub fn from_bytes(&self, bytes: &[u8], ty: Type) -> U256 {
+        let size = self.scalar_size(ty);
+        debug_assert_eq!(bytes.len(), size);
+        let mut value = U256::zero();
+        for significance in (0..size.min(32)).rev() {
+            value = (value << 8)
+                | U256::from(bytes[self.endian.byte_offset_in_word(size, significance)]);
+        }
+        value
+    }
+
+    fn int_size(ty: Type) -> usize {
+        match ty {
+            Type::I1 | Type::I8 => 1,
+            Type::I16 => 2,
+            Type::I32 => 4,
+            Type::I64 => 8,
+            Type::I128 => 16,
+            Type::I256 => 32,
+            Type::Void => 0,
+            Type::Compound(_) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endian_bytes() {
+        let value = U256::from(0x1122_3344u64);
+        let big = TypeLayout::new(Endian::Big, 32);
+        let little = TypeLayout::new(Endian::Little, 4);
+
+        assert_eq!(big.to_bytes(value, Type::I32), vec![0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(
+            little.to_bytes(value, Type::I32),
+            vec![0x44, 0x33, 0x22, 0x11]
+        );
+        assert_eq!(big.to_bytes(value, Type::I16), vec![0x33, 0x44]);
+        assert_eq!(big.from_bytes(&[0x11, 0x22, 0x33, 0x44], Type::I32), value);
+        assert_eq!(
+            little.from_bytes(&[0x44, 0x33, 0x22, 0x11], Type::I32),
+            value
+        );
+
+        assert_eq!(big.byte_offset_in_word(Type::I32, 0), 3);
+        assert_eq!(little.byte_offset_in_word(Type::I32, 0), 0);
+        // The byte at offset 1 is `0x22` on big-endian and `0x33` on little-endian targets.
+        assert_eq!((value >> big.extract_byte(Type::I32, 1)).byte(0), 0x22);
+        assert_eq!((value >> little.extract_byte(Type::I32, 1)).byte(0), 0x33);
+        assert_eq!(big.scalar_size(Type::I256), 32);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    insn::{BinaryOp, CastOp, DataLocationKind, MemOp, OverflowOp, UnaryOp},
+    intrinsic::IntrinsicRegistry,
+    InsnData, Type,
+};
+
+use super::{Endian, IsaSpecificCostTable, IsaSpecificTypeProvider, TargetIsa};
+
+use sonatina_triple::{Architecture, TargetTriple, Version};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Riscv32;
+
+impl Riscv32 {
+    pub(super) fn build_isa(triple: TargetTriple) -> TargetIsa {
+        debug_assert_eq!(triple.architecture, Architecture::Riscv32);
+        debug_assert!(matches!(triple.version, Version::RiscvExtensions(_)));
+        let isa = Self;
+
+        TargetIsa::new(
+            triple,
+            Box::new(isa),
+            Box::new(isa),
+            IntrinsicRegistry::new(),
+        )
+    }
+}
+
+impl IsaSpecificTypeProvider for Riscv32 {
+    fn word_type(&self) -> Type {
+        Type::I32
+    }
+
+    fn pointer_type(&self) -> Type {
+        Type::I32
+    }
+
+    fn address_type(&self) -> Type {
+        Type::I32
+    }
+
+    fn balance_type(&self) -> Type {
+        Type::I64
+    }
+
+    fn gas_type(&self) -> Type {
+        Type::I64
+    }
+
+    fn endian(&self) -> Endian {
+        Endian::Little
+    }
+}
+
+/// Costs are the number of machine insns that insns are lowered to, which is what zkVMs charge
+/// cycles for, assuming operands are in registers. Values narrower than 32 bits need extra insns
+/// to stay zero-extended, which is not included.
+impl IsaSpecificCostTable for Riscv32 {
+    fn insn_cost(&self, insn_data: &InsnData) -> u64 {
+        match insn_data {
+            InsnData::Unary { code, .. } => match code {
+                UnaryOp::Not | UnaryOp::Neg => 1,
+                // RV32IM has no bit manipulation insns, so they are expanded into shifts and masks.
+                UnaryOp::Clz => 30,
+                UnaryOp::Ctz | UnaryOp::Popcount => 20,
+                UnaryOp::Bswap => 12,
+            },
+
+            InsnData::Binary { code, .. } => match code {
+                // Equality and negated comparisons need a second insn to produce `0` or `1`.
+                BinaryOp::Le
+                | BinaryOp::Ge
+                | BinaryOp::Sle
+                | BinaryOp::Sge
+                | BinaryOp::Eq
+                | BinaryOp::Ne => 2,
+                // RV32IM has no rotate insns, so a rotate is two shifts and an `OR`.
+                BinaryOp::Rotl | BinaryOp::Rotr => 6,
+                _ => 1,
+            },
+
+            InsnData::OverflowBinary { code, .. } => match code {
+                // The flag is an unsigned comparison of the result with an operand.
+                OverflowOp::Uaddo | OverflowOp::Usubo => 2,
+                // The flag compares the signs of the operands and the result.
+                OverflowOp::Saddo | OverflowOp::Ssubo => 4,
+                // `MULHU` is checked for a non-zero high half.
+                OverflowOp::Umulo => 3,
+                // `MULH` is compared with the sign of the low half.
+                OverflowOp::Smulo => 5,
+            },
+
+            InsnData::Cast { code, .. } => match code {
+                // A shift left and an arithmetic shift right.
+                CastOp::Sext => 2,
+                CastOp::Zext | CastOp::Trunc | CastOp::BitCast => 1,
+            },
+
+            InsnData::Load { loc, .. } | InsnData::Store { loc, .. } => match loc {
+                DataLocationKind::Memory => 1,
+                // zkVMs have no storage; it's accessed through a host call.
+                DataLocationKind::Storage => 100,
+            },
+
+            // A loop over bytes, which is charged for copying its operands and a single
+            // iteration. A `memmove` compares the addresses to pick the direction.
+            InsnData::Mem { code, .. } => match code {
+                MemOp::Memcpy => 11,
+                MemOp::Memmove => 14,
+                MemOp::Memset => 9,
+            },
+
+            // Arguments are moved to argument registers, and the callee saves the registers it
+            // uses.
+            InsnData::Call { args, .. } => args.len() as u64 + 4,
+
+            // The target has no intrinsics of its own, so those of frontends are charged like
+            // calls.
+            InsnData::Intrinsic { args, .. } => args.len() as u64 + 4,
+
+            InsnData::Jump { .. } => 1,
+
+            // An inverted branch over a jump, which reaches the whole code.
+            InsnData::Branch { .. } => 2,
+
+            // Each case loads the key and branches.
+            InsnData::BrTable { table, default, .. } => {
+                table.len() as u64 * 3 + u64::from(default.is_some())
+            }
+
+            InsnData::Alloca { .. } => 1,
+
+            InsnData::Return { .. } => 3,
+
+            // All halt with `ebreak`, since there is no caller to pass revert data to.
+            InsnData::Revert { .. } | InsnData::Trap { .. } | InsnData::Unreachable => 1,
+
+            // `MUL` + `ADD` for each index.
+            InsnData::Gep { args } => (args.len() as u64 - 1) * 2,
+
+            InsnData::Phi { .. } => 0,
+        }
+    }
+
+    /// Every machine insn takes 4 bytes.
+    fn insn_size(&self, insn_data: &InsnData) -> u64 {
+        match insn_data {
+            // Storage is accessed through a host call, which takes a few insns to set up.
+            InsnData::Load {
+                loc: DataLocationKind::Storage,
+                ..
+            }
+            | InsnData::Store {
+                loc: DataLocationKind::Storage,
+                ..
+            } => 4 * 4,
+            // A `memmove` has a loop for each direction.
+            InsnData::Mem {
+                code: MemOp::Memmove,
+                ..
+            } => 4 * 24,
+            _ => 4 * self.insn_cost(insn_data),
+        }
+    }
+
+    /// A bounds check, scaling the index, adding the base of the table, loading the entry and
+    /// `jalr`.
+    fn jump_table_cost(&self) -> u64 {
+        8
+    }
+}
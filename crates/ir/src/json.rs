@@ -0,0 +1,355 @@
+//! This module contains the JSON export of modules, for visualizers and analysis scripts that
+//! shouldn't have to parse the textual IR.
+//!
+//! The schema is versioned and doesn't follow the in-memory layout of the IR, so it stays stable as
+//! the IR changes. A module is exported in the following format.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "target": "evm-ethereum-london",
+//!   "structs": [{ "name": "pair", "fields": ["i32", "*i8"], "packed": false }],
+//!   "globals": [{
+//!     "symbol": "x", "type": "i32", "linkage": "private", "const": true, "init": "1"
+//!   }],
+//!   "functions": [{
+//!     "id": 0, "name": "f", "linkage": "public", "call_conv": "solidity_external", "args": [0],
+//!     "ret": "i32",
+//!     "values": [
+//!       { "id": 0, "kind": "arg", "type": "i32", "index": 0 },
+//!       { "id": 1, "kind": "imm", "type": "i32", "imm": "1" },
+//!       { "id": 2, "kind": "insn", "type": "i32", "insn": 0 }
+//!     ],
+//!     "blocks": [{
+//!       "id": 0,
+//!       "insns": [
+//!         { "id": 0, "op": "add", "args": [0, 1], "result": 2 },
+//!         { "id": 1, "op": "return", "args": [2], "result": null }
+//!       ]
+//!     }]
+//!   }]
+//! }
+//! ```
+//!
+//! Types are written as in the textual IR, and immediates as decimal strings since they may not
+//! fit in a JSON number. `args` and `result` of an insn are ids in `values`. Some insns have more
+//! members:
+//! - `call`: `callee`, the name of the called function, and `tail`.
+//! - `intrinsic`: `intrinsic`, the name of the intrinsic.
+//! - `load` and `store`: `loc`, either `"memory"` or `"storage"`.
+//! - `jump` and `br`: `dests`, the ids of the destination blocks.
+//! - `br_table`: `default`, a block id or `null`, and `table`, the block ids of the cases.
+//! - `phi`: `blocks`, the incoming block of each of `args`.
+//! - `alloca` and casts: `type`, the allocated type or the type cast to.
+//! - `revert`: `selector`, the error selector as a hex string.
+//! - `trap`: `reason`, e.g., `"overflow"`.
+//!
+//! `result` is the first result of an insn. Insns with more results, e.g., `uaddo`, also have
+//! `results`, the ids of all of them in order.
+//!
+//! A value of kind `global` has a `symbol` member instead of `imm`, `index` or `insn`. Functions
+//! that are only declared have no `blocks`.
+use std::{fmt, io};
+
+use crate::{
+    insn::DisplaySelector, module::ModuleCtx, Block, DataLocationKind, Function,
+    GlobalVariableData, InsnData, Module, Value, ValueData,
+};
+
+/// The version of the schema, which is bumped on incompatible changes.
+pub const JSON_VERSION: u32 = 1;
+
+impl Module {
+    pub fn write_json(&self, mut w: impl io::Write) -> io::Result<()> {
+        let ctx = &self.ctx;
+        write!(
+            w,
+            "{{\"version\":{JSON_VERSION},\"target\":{},\"structs\":[",
+            JsonStr(&ctx.isa.triple().to_string())
+        )?;
+
+        let structs: Vec<_> = ctx.with_ty_store(|s| s.all_struct_data().cloned().collect());
+        for (i, data) in structs.iter().enumerate() {
+            write!(
+                w,
+                "{}{{\"name\":{},\"fields\":[",
+                delim(i),
+                JsonStr(&data.name)
+            )?;
+            for (j, field) in data.fields.iter().enumerate() {
+                write!(w, "{}{}", delim(j), JsonStr(&field.ir_string(ctx)))?;
+            }
+            write!(w, "],\"packed\":{}}}", data.packed)?;
+        }
+
+        write!(w, "],\"globals\":[")?;
+        let globals: Vec<_> = ctx.with_gv_store(|s| s.all_gv_data().cloned().collect());
+        for (i, gv) in globals.iter().enumerate() {
+            write!(w, "{}", delim(i))?;
+            write_global(ctx, gv, &mut w)?;
+        }
+
+        write!(w, "],\"functions\":[")?;
+        for (i, func_ref) in self.iter_functions().enumerate() {
+            write!(w, "{}{{\"id\":{},", delim(i), func_ref.as_u32())?;
+            write_function(&self.funcs[func_ref], &mut w)?;
+            write!(w, "}}")?;
+        }
+        write!(w, "]}}")
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut buf = Vec::new();
+        self.write_json(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+fn write_global(
+    ctx: &ModuleCtx,
+    gv: &GlobalVariableData,
+    w: &mut impl io::Write,
+) -> io::Result<()> {
+    write!(
+        w,
+        "{{\"symbol\":{},\"type\":{},\"linkage\":\"{}\",\"const\":{},\"init\":",
+        JsonStr(&gv.symbol),
+        JsonStr(&gv.ty.ir_string(ctx)),
+        gv.linkage,
+        gv.is_const
+    )?;
+    match &gv.data {
+        Some(data) => write!(w, "{}}}", JsonStr(&data.to_string())),
+        None => write!(w, "null}}"),
+    }
+}
+
+/// Writes the members of a function object other than `id`.
+fn write_function(func: &Function, w: &mut impl io::Write) -> io::Result<()> {
+    let ctx = &func.dfg.ctx;
+    let sig = &func.sig;
+    write!(
+        w,
+        "\"name\":{},\"linkage\":\"{}\",\"call_conv\":\"{}\",\"args\":[",
+        JsonStr(sig.name()),
+        sig.linkage(),
+        sig.call_conv()
+    )?;
+    for (i, arg) in func.arg_values.iter().enumerate() {
+        write!(w, "{}{}", delim(i), arg.as_u32())?;
+    }
+    write!(w, "],\"ret\":{}", JsonStr(&sig.ret_ty().ir_string(ctx)))?;
+    if func.layout.entry_block().is_none() {
+        return Ok(());
+    }
+
+    write!(w, ",\"values\":[")?;
+    for (i, (value, data)) in func.dfg.values.iter().enumerate() {
+        let (kind, ty) = match data {
+            ValueData::Insn { ty, .. } => ("insn", ty),
+            ValueData::Arg { ty, .. } => ("arg", ty),
+            ValueData::Immediate { ty, .. } => ("imm", ty),
+            ValueData::Global { ty, .. } => ("global", ty),
+        };
+        write!(
+            w,
+            "{}{{\"id\":{},\"kind\":\"{kind}\",\"type\":{},",
+            delim(i),
+            value.as_u32(),
+            JsonStr(&ty.ir_string(ctx))
+        )?;
+        match data {
+            ValueData::Insn { insn, .. } => write!(w, "\"insn\":{}}}", insn.as_u32())?,
+            ValueData::Arg { idx, .. } => write!(w, "\"index\":{idx}}}")?,
+            ValueData::Immediate { imm, .. } => write!(w, "\"imm\":\"{imm}\"}}")?,
+            ValueData::Global { gv, .. } => {
+                let symbol = ctx.with_gv_store(|s| s.gv_data(*gv).symbol.clone());
+                write!(w, "\"symbol\":{}}}", JsonStr(&symbol))?
+            }
+        }
+    }
+
+    write!(w, "],\"blocks\":[")?;
+    for (i, block) in func.layout.iter_block().enumerate() {
+        write!(w, "{}{{\"id\":{},\"insns\":[", delim(i), block.as_u32())?;
+        for (j, insn) in func.layout.iter_insn(block).enumerate() {
+            let data = func.dfg.insn_data(insn);
+            write!(
+                w,
+                "{}{{\"id\":{},\"op\":\"{}\",\"args\":{}",
+                delim(j),
+                insn.as_u32(),
+                data.mnemonic(),
+                JsonValues(data.args())
+            )?;
+            write_insn_members(func, data, w)?;
+            let results = func.dfg.insn_results(insn);
+            if results.len() > 1 {
+                write!(w, ",\"results\":{}", JsonValues(results))?;
+            }
+            match func.dfg.insn_result(insn) {
+                Some(result) => write!(w, ",\"result\":{}}}", result.as_u32())?,
+                None => write!(w, ",\"result\":null}}")?,
+            }
+        }
+        write!(w, "]}}")?;
+    }
+    write!(w, "]")
+}
+
+/// Writes the members specific to the kind of the insn.
+fn write_insn_members(func: &Function, data: &InsnData, w: &mut impl io::Write) -> io::Result<()> {
+    let ctx = &func.dfg.ctx;
+    match data {
+        InsnData::Cast { ty, .. } | InsnData::Alloca { ty } => {
+            write!(w, ",\"type\":{}", JsonStr(&ty.ir_string(ctx)))
+        }
+        InsnData::Load { loc, .. } | InsnData::Store { loc, .. } | InsnData::Mem { loc, .. } => {
+            let loc = match loc {
+                DataLocationKind::Memory => "memory",
+                DataLocationKind::Storage => "storage",
+            };
+            write!(w, ",\"loc\":\"{loc}\"")
+        }
+        InsnData::Call {
+            func: callee,
+            is_tail,
+            ..
+        } => write!(
+            w,
+            ",\"callee\":{},\"tail\":{is_tail}",
+            JsonStr(func.callees[callee].name())
+        ),
+        InsnData::Intrinsic { intrinsic, .. } => {
+            let name = ctx
+                .isa
+                .with_intrinsics(|r| r.intrinsic_data(*intrinsic).name.clone());
+            write!(w, ",\"intrinsic\":{}", JsonStr(&name))
+        }
+        InsnData::Jump { dests } => write!(w, ",\"dests\":{}", JsonBlocks(dests)),
+        InsnData::Branch { dests, .. } => write!(w, ",\"dests\":{}", JsonBlocks(dests)),
+        InsnData::BrTable { default, table, .. } => {
+            match default {
+                Some(block) => write!(w, ",\"default\":{}", block.as_u32())?,
+                None => write!(w, ",\"default\":null")?,
+            }
+            write!(w, ",\"table\":{}", JsonBlocks(table))
+        }
+        InsnData::Phi { blocks, .. } => write!(w, ",\"blocks\":{}", JsonBlocks(blocks)),
+        InsnData::Revert { selector, .. } => {
+            write!(w, ",\"selector\":\"{}\"", DisplaySelector(*selector))
+        }
+        InsnData::Trap { reason } => write!(w, ",\"reason\":\"{reason}\""),
+        InsnData::Unary { .. }
+        | InsnData::Binary { .. }
+        | InsnData::OverflowBinary { .. }
+        | InsnData::Return { .. }
+        | InsnData::Unreachable
+        | InsnData::Gep { .. } => Ok(()),
+    }
+}
+
+/// Returns the separator that precedes the `idx`th element of a JSON array or object.
+pub fn delim(idx: usize) -> &'static str {
+    if idx == 0 {
+        ""
+    } else {
+        ","
+    }
+}
+
+/// Writes an array of value ids.
+struct JsonValues<'a>(&'a [Value]);
+
+impl fmt::Display for JsonValues<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, value) in self.0.iter().enumerate() {
+            write!(f, "{}{}", delim(i), value.as_u32())?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Writes an array of block ids.
+struct JsonBlocks<'a>(&'a [Block]);
+
+impl fmt::Display for JsonBlocks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, block) in self.0.iter().enumerate() {
+            write!(f, "{}{}", delim(i), block.as_u32())?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Writes a string as a JSON string literal.
+pub struct JsonStr<'a>(pub &'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{builder::test_util::*, Type};
+
+    #[test]
+    fn export_function() {
+        let mut builder = test_func_builder(&[Type::I32], Type::I32);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let imm = builder.make_imm_value(-1i32);
+        let v = builder.add(arg, imm);
+        builder.jump(b1);
+        builder.switch_to_block(b1);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let module = builder.finish().build();
+
+        let json: serde_json::Value = serde_json::from_str(&module.to_json()).unwrap();
+        assert_eq!(json["version"], JSON_VERSION);
+        assert_eq!(json["target"], "evm-ethereum-london");
+
+        let func = &json["functions"][0];
+        assert_eq!(func["name"], "test_func");
+        assert_eq!(func["call_conv"], "solidity_external");
+        assert_eq!(func["args"], serde_json::json!([arg.as_u32()]));
+        assert_eq!(func["ret"], "i32");
+
+        let imm = &func["values"][imm.as_u32() as usize];
+        assert_eq!(imm["kind"], "imm");
+        assert_eq!(imm["imm"], "-1");
+
+        let blocks = func["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        let add = &blocks[0]["insns"][0];
+        assert_eq!(add["op"], "add");
+        assert_eq!(add["result"], v.as_u32());
+        assert_eq!(
+            blocks[0]["insns"][1]["dests"],
+            serde_json::json!([b1.as_u32()])
+        );
+        assert_eq!(blocks[1]["insns"][0]["op"], "return");
+        assert_eq!(blocks[1]["insns"][0]["result"], serde_json::Value::Null);
+    }
+}
@@ -184,6 +184,20 @@ impl Layout {
         self.blocks[block] = BlockNode::default();
     }
 
+    /// Moves `block` along with its insns after `after`.
+    pub fn move_block_after(&mut self, block: Block, after: Block) {
+        debug_assert_ne!(block, after);
+        let BlockNode {
+            first_insn,
+            last_insn,
+            ..
+        } = self.blocks[block];
+        self.remove_block(block);
+        self.insert_block_after(block, after);
+        self.blocks[block].first_insn = first_insn;
+        self.blocks[block].last_insn = last_insn;
+    }
+
     pub fn append_insn(&mut self, insn: Insn, block: Block) {
         debug_assert!(self.is_block_inserted(block));
         debug_assert!(!self.is_insn_inserted(insn));
@@ -460,6 +474,36 @@ mod tests {
         assert_eq!(layout.last_block, None);
     }
 
+    #[test]
+    fn test_block_move() {
+        let mut layout = Layout::new();
+        let ctx = ModuleCtx::new(build_test_isa());
+        let mut dfg = DataFlowGraph::new(ctx);
+
+        // block1 -> block2 -> block3.
+        let b1 = dfg.make_block();
+        let b2 = dfg.make_block();
+        let b3 = dfg.make_block();
+        layout.append_block(b1);
+        layout.append_block(b2);
+        layout.append_block(b3);
+        let insn = dfg.make_insn(InsnData::jump(b3));
+        layout.append_insn(insn, b2);
+
+        // block1 -> block3 -> block2.
+        layout.move_block_after(b2, b3);
+        assert_eq!(layout.last_block, Some(b2));
+        assert_eq!(layout.next_block_of(b1), Some(b3));
+        assert_eq!(layout.next_block_of(b3), Some(b2));
+        assert_eq!(layout.first_insn_of(b2), Some(insn));
+        assert_eq!(layout.insn_block(insn), b2);
+
+        // block3 -> block1 -> block2.
+        layout.move_block_after(b1, b3);
+        assert_eq!(layout.entry_block, Some(b3));
+        assert_eq!(layout.iter_block().collect::<Vec<_>>(), [b3, b1, b2]);
+    }
+
     #[test]
     fn test_insn_insertion() {
         let mut layout = Layout::new();
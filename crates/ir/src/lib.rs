@@ -1,16 +1,30 @@
+pub mod block_params;
 pub mod builder;
+pub mod call_conv;
+pub mod call_sites;
 pub mod cfg;
+pub mod cloner;
+pub mod contract;
 pub mod dfg;
 pub mod func_cursor;
 pub mod function;
 pub mod global_variable;
 pub mod graphviz;
+pub mod html;
 pub mod insn;
+pub mod intrinsic;
 pub mod ir_writer;
 pub mod isa;
+pub mod json;
 pub mod layout;
 pub mod linkage;
+pub mod linker;
 pub mod module;
+pub mod profile;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod source_loc;
+pub mod static_init;
 pub mod types;
 pub mod value;
 
@@ -18,14 +32,18 @@ mod bigint;
 
 pub use bigint::{I256, U256};
 pub use builder::Variable;
+pub use call_conv::CallConv;
 pub use cfg::ControlFlowGraph;
 pub use dfg::{Block, BlockData, DataFlowGraph};
-pub use function::{Function, Signature};
+pub use function::{FuncAttrs, Function, InlineHint, RemoveInsnError, Signature, StateMutability};
 pub use global_variable::{GlobalVariable, GlobalVariableData};
-pub use graphviz::render_to;
-pub use insn::{BranchInfo, DataLocationKind, Insn, InsnData};
+pub use graphviz::{render_to, render_with};
+pub use html::render_html;
+pub use insn::{BranchInfo, BranchWeights, DataLocationKind, Insn, InsnData};
+pub use intrinsic::{Intrinsic, IntrinsicData};
 pub use layout::Layout;
 pub use linkage::Linkage;
 pub use module::Module;
+pub use source_loc::SourceLoc;
 pub use types::Type;
 pub use value::{Immediate, Value, ValueData};
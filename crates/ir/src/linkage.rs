@@ -2,6 +2,7 @@ use std::{fmt, str::FromStr};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 /// Linkage of symbols.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Linkage {
     /// The symbol is defined in the module, and can be used from the outside of the module.
     Public,
@@ -0,0 +1,674 @@
+//! This module contains the linker, which merges a module into another one, so that a library can
+//! be compiled separately and linked into the modules that use it.
+//!
+//! Functions and global variables are resolved by name. A declaration, i.e., a symbol with
+//! [`Linkage::External`], resolves to the public definition of the same name in the other module,
+//! and declarations of the same name in both modules are merged. Private symbols never resolve
+//! anything: a private symbol whose name is taken by the other module is renamed. Two public
+//! definitions of the same name are an error, as are resolved symbols whose types differ.
+//!
+//! Aliases of global variables are carried over as they are, so an alias whose name is taken by
+//! the other module is an error as well. Struct types are identified by name, so structs of the
+//! same name must have the same fields, and so must intrinsics of the same name have the same
+//! signature.
+use std::fmt;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::{
+    module::{FuncRef, ModuleCtx},
+    source_loc::{InlinedAt, InlinedAtData, SourceFile},
+    types::{CompoundType, CompoundTypeData, StructData},
+    Function, GlobalVariable, GlobalVariableData, InsnData, Intrinsic, IntrinsicData, Linkage,
+    Module, Signature, SourceLoc, Type, ValueData,
+};
+
+/// Merges `src` into `dest`. The functions, global variables, static initializers and contracts of
+/// `src` are added to `dest`, and refer to the entities of `dest` afterwards.
+///
+/// On failure, `dest` keeps its functions and global variables, but types of `src` may have been
+/// added to it.
+pub fn link(dest: &mut Module, src: Module) -> Result<(), LinkError> {
+    if dest.ctx.isa.triple() != src.ctx.isa.triple() {
+        return Err(LinkError::TargetMismatch);
+    }
+
+    let types = link_types(&dest.ctx, &src.ctx)?;
+    let mut names = SymbolNames::default();
+
+    let gv_plan = dest.ctx.with_gv_store(|dest_store| {
+        src.ctx.with_gv_store(|src_store| {
+            names.extend(dest_store.all_gv_data().map(|data| data.symbol.as_str()));
+            names.extend(src_store.all_gv_data().map(|data| data.symbol.as_str()));
+            names.extend(dest_store.all_aliases().map(|(alias, _)| alias));
+            names.extend(src_store.all_aliases().map(|(alias, _)| alias));
+
+            let plan = src_store
+                .all_gvs()
+                .map(|gv| {
+                    let data = src_store.gv_data(gv);
+                    let existing = dest_store
+                        .gv_by_symbol(&data.symbol)
+                        .map(|dest_gv| (dest_gv, dest_store.gv_data(dest_gv)))
+                        .map(|(dest_gv, dest_data)| {
+                            let same_ty = dest_data.ty == types.map(data.ty);
+                            (dest_gv, dest_data.linkage, same_ty)
+                        });
+                    let resolution = resolve(&data.symbol, data.linkage, existing, &mut names)?;
+                    Ok((gv, resolution))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if let Some((alias, _)) = src_store
+                .all_aliases()
+                .find(|(alias, _)| dest_store.gv_by_symbol(alias).is_some())
+            {
+                return Err(LinkError::DuplicateSymbol(alias.to_string()));
+            }
+            Ok(plan)
+        })
+    })?;
+
+    names.extend(dest.funcs.values().map(|func| func.sig.name()));
+    names.extend(src.funcs.values().map(|func| func.sig.name()));
+    let func_plan = src
+        .iter_functions()
+        .map(|func_ref| {
+            let sig = &src.funcs[func_ref].sig;
+            let existing = dest
+                .iter_functions()
+                .map(|dest_ref| (dest_ref, &dest.funcs[dest_ref]))
+                .find(|(_, dest_func)| dest_func.sig.name() == sig.name())
+                .map(|(dest_ref, dest_func)| {
+                    let dest_sig = &dest_func.sig;
+                    let same_ty = dest_sig.ret_ty() == types.map(sig.ret_ty())
+                        && dest_sig.args().len() == sig.args().len()
+                        && dest_sig
+                            .args()
+                            .iter()
+                            .zip(sig.args())
+                            .all(|(dest_ty, ty)| *dest_ty == types.map(*ty));
+                    (dest_ref, dest_sig.linkage(), same_ty)
+                });
+            let resolution = resolve(sig.name(), sig.linkage(), existing, &mut names)?;
+            Ok((func_ref, resolution))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let intrinsics = link_intrinsics(&dest.ctx, &src.ctx, &types)?;
+    let gvs = link_gvs(&dest.ctx, &src.ctx, &types, gv_plan);
+    let locs = LocMap::new(&dest.ctx, &src.ctx);
+
+    let mut func_map = FxHashMap::default();
+    let mut moved = Vec::new();
+    for (func_ref, resolution) in func_plan {
+        let src_sig = &src.funcs[func_ref].sig;
+        let dest_ref = match resolution {
+            Resolution::Add(name) => {
+                let sig = map_sig(src_sig, &name, |ty| types.map(ty));
+                let dest_ref = dest.funcs.push(Function::new(&dest.ctx, sig));
+                moved.push((func_ref, dest_ref));
+                dest_ref
+            }
+            Resolution::AddRenaming(dest_ref, new_name) => {
+                let dest_sig = &mut dest.funcs[dest_ref].sig;
+                *dest_sig = map_sig(dest_sig, &new_name, |ty| ty);
+                let sig = map_sig(src_sig, src_sig.name(), |ty| types.map(ty));
+                let dest_ref = dest.funcs.push(Function::new(&dest.ctx, sig));
+                moved.push((func_ref, dest_ref));
+                dest_ref
+            }
+            Resolution::Define(dest_ref) => {
+                moved.push((func_ref, dest_ref));
+                dest_ref
+            }
+            Resolution::Use(dest_ref) => dest_ref,
+        };
+        func_map.insert(func_ref, dest_ref);
+    }
+
+    let mut src_funcs = src.funcs;
+    for (func_ref, dest_ref) in moved {
+        let func = std::mem::replace(
+            &mut src_funcs[func_ref],
+            Function::new(&src.ctx, Signature::default()),
+        );
+        let name = dest.funcs[dest_ref].sig.name().to_string();
+        let maps = EntityMaps {
+            types: &types,
+            gvs: &gvs,
+            intrinsics: &intrinsics,
+            funcs: &func_map,
+            locs: &locs,
+        };
+        dest.funcs[dest_ref] = maps.map_func(func, &name, &dest.ctx);
+    }
+
+    // Refresh the signatures callers keep, which changed if the callee was renamed or defined.
+    let sigs: FxHashMap<_, _> = dest
+        .funcs
+        .iter()
+        .map(|(func_ref, func)| (func_ref, func.sig.clone()))
+        .collect();
+    for func in dest.funcs.values_mut() {
+        for (callee, sig) in func.callees.iter_mut() {
+            *sig = sigs[callee].clone();
+        }
+    }
+
+    for init in src.static_inits.iter() {
+        let deps: Vec<_> = init.deps.iter().map(|dep| func_map[dep]).collect();
+        dest.static_inits.register(func_map[&init.func], &deps);
+    }
+    for contract in src.contracts.iter() {
+        let entries: Vec<_> = contract
+            .entries
+            .iter()
+            .map(|entry| func_map[entry])
+            .collect();
+        dest.contracts.define(&contract.name, &entries);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// The modules are compiled for different targets.
+    TargetMismatch,
+    /// Both modules define the public symbol.
+    DuplicateSymbol(String),
+    /// The symbol has different types in the modules.
+    SymbolTypeMismatch(String),
+    /// The struct has different fields in the modules.
+    StructMismatch(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TargetMismatch => write!(f, "modules are compiled for different targets"),
+            Self::DuplicateSymbol(name) => write!(f, "`{name}` is defined in both modules"),
+            Self::SymbolTypeMismatch(name) => {
+                write!(f, "`{name}` has different types in the modules")
+            }
+            Self::StructMismatch(name) => {
+                write!(f, "struct `{name}` has different fields in the modules")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// How a symbol of the source module is merged into the destination module.
+#[derive(Debug)]
+enum Resolution<T> {
+    /// The symbol is added under the name.
+    Add(String),
+    /// The private symbol `T` of the destination, or its alias of the same name, is renamed to the
+    /// name, and the symbol is added under its own name.
+    AddRenaming(T, String),
+    /// The symbol resolves to `T` of the destination.
+    Use(T),
+    /// The symbol defines the declaration `T` of the destination.
+    Define(T),
+}
+
+/// Resolves the symbol `name` against `existing`, the symbol of the same name in the destination
+/// with its linkage, and whether it has the same type.
+fn resolve<T>(
+    name: &str,
+    linkage: Linkage,
+    existing: Option<(T, Linkage, bool)>,
+    names: &mut SymbolNames,
+) -> Result<Resolution<T>, LinkError> {
+    let Some((dest, dest_linkage, same_ty)) = existing else {
+        return Ok(Resolution::Add(name.to_string()));
+    };
+
+    match (dest_linkage, linkage) {
+        (_, Linkage::Private) => Ok(Resolution::Add(names.fresh(name))),
+        (Linkage::Private, _) => Ok(Resolution::AddRenaming(dest, names.fresh(name))),
+        _ if !same_ty => Err(LinkError::SymbolTypeMismatch(name.to_string())),
+        (Linkage::External, Linkage::Public) => Ok(Resolution::Define(dest)),
+        (_, Linkage::External) => Ok(Resolution::Use(dest)),
+        (Linkage::Public, Linkage::Public) => Err(LinkError::DuplicateSymbol(name.to_string())),
+    }
+}
+
+/// The names taken in either module, to rename private symbols to.
+#[derive(Default)]
+struct SymbolNames(FxHashSet<String>);
+
+impl SymbolNames {
+    fn extend<'a>(&mut self, names: impl Iterator<Item = &'a str>) {
+        self.0.extend(names.map(str::to_string));
+    }
+
+    fn fresh(&mut self, name: &str) -> String {
+        let fresh = (0..)
+            .map(|idx| format!("{name}__{idx}"))
+            .find(|fresh| !self.0.contains(fresh))
+            .unwrap();
+        self.0.insert(fresh.clone());
+        fresh
+    }
+}
+
+/// Maps the compound types of the source module to the types of the destination module.
+struct TypeMap(FxHashMap<CompoundType, CompoundType>);
+
+impl TypeMap {
+    fn map(&self, ty: Type) -> Type {
+        match ty {
+            Type::Compound(cmpd) => Type::Compound(self.0[&cmpd]),
+            _ => ty,
+        }
+    }
+}
+
+fn link_types(dest: &ModuleCtx, src: &ModuleCtx) -> Result<TypeMap, LinkError> {
+    let compounds: Vec<_> = src.with_ty_store(|s| {
+        s.all_compounds()
+            .map(|(cmpd, data)| (cmpd, data.clone()))
+            .collect()
+    });
+
+    // A compound type can only refer to types made before it, so they're already mapped.
+    let mut types = TypeMap(FxHashMap::default());
+    for (cmpd, data) in compounds {
+        let data = match data {
+            CompoundTypeData::Array { elem, len } => CompoundTypeData::Array {
+                elem: types.map(elem),
+                len,
+            },
+            CompoundTypeData::Ptr(ty) => CompoundTypeData::Ptr(types.map(ty)),
+            CompoundTypeData::Struct(def) => CompoundTypeData::Struct(StructData {
+                fields: def.fields.iter().map(|ty| types.map(*ty)).collect(),
+                ..def
+            }),
+            CompoundTypeData::Bytes => CompoundTypeData::Bytes,
+        };
+
+        let ty = dest.with_ty_store_mut(|s| match data {
+            CompoundTypeData::Struct(def) => match s.struct_type_by_name(&def.name) {
+                Some(ty) if s.struct_def(ty) == Some(&def) => Ok(ty),
+                Some(_) => Err(LinkError::StructMismatch(def.name)),
+                None => Ok(s.make_struct(&def.name, &def.fields, def.packed)),
+            },
+            data => Ok(Type::Compound(s.make_compound(data))),
+        })?;
+        let Type::Compound(new_cmpd) = ty else {
+            unreachable!();
+        };
+        types.0.insert(cmpd, new_cmpd);
+    }
+
+    Ok(types)
+}
+
+/// Registers the intrinsics of `src` with `dest`. Nothing is registered if an intrinsic has a
+/// different signature in `dest`.
+fn link_intrinsics(
+    dest: &ModuleCtx,
+    src: &ModuleCtx,
+    types: &TypeMap,
+) -> Result<FxHashMap<Intrinsic, Intrinsic>, LinkError> {
+    let intrinsics: Vec<_> = src.isa.with_intrinsics(|r| {
+        r.all_intrinsics()
+            .map(|(intrinsic, data)| {
+                let data = IntrinsicData {
+                    params: data.params.iter().map(|ty| types.map(*ty)).collect(),
+                    ret_ty: types.map(data.ret_ty),
+                    ..data.clone()
+                };
+                (intrinsic, data)
+            })
+            .collect()
+    });
+
+    dest.isa.with_intrinsics_mut(|r| {
+        if let Some((_, data)) = intrinsics.iter().find(|(_, data)| {
+            r.intrinsic_by_name(&data.name)
+                .is_some_and(|intrinsic| r.intrinsic_data(intrinsic) != data)
+        }) {
+            return Err(LinkError::SymbolTypeMismatch(data.name.clone()));
+        }
+
+        Ok(intrinsics
+            .into_iter()
+            .map(|(intrinsic, data)| (intrinsic, r.register(data).unwrap()))
+            .collect())
+    })
+}
+
+fn link_gvs(
+    dest: &ModuleCtx,
+    src: &ModuleCtx,
+    types: &TypeMap,
+    plan: Vec<(GlobalVariable, Resolution<GlobalVariable>)>,
+) -> FxHashMap<GlobalVariable, GlobalVariable> {
+    let mut gvs = FxHashMap::default();
+    for (gv, resolution) in plan {
+        let data = src.with_gv_store(|s| s.gv_data(gv).clone());
+        let data = GlobalVariableData {
+            ty: types.map(data.ty),
+            ..data
+        };
+        let new_gv = dest.with_gv_store_mut(|s| match resolution {
+            Resolution::Add(symbol) => s.make_gv(GlobalVariableData { symbol, ..data }),
+            Resolution::AddRenaming(_, symbol) => {
+                s.rename_symbol(&data.symbol, &symbol)
+                    .expect("fresh names are not taken");
+                s.make_gv(data)
+            }
+            Resolution::Define(dest_gv) => {
+                s.replace_gv_data(dest_gv, data);
+                dest_gv
+            }
+            Resolution::Use(dest_gv) => dest_gv,
+        });
+        gvs.insert(gv, new_gv);
+    }
+
+    let aliases: Vec<_> = src.with_gv_store(|s| {
+        s.all_aliases()
+            .map(|(alias, gv)| (alias.to_string(), gv))
+            .collect()
+    });
+    dest.with_gv_store_mut(|s| {
+        for (alias, gv) in aliases {
+            s.make_alias(&alias, gvs[&gv]);
+        }
+    });
+    gvs
+}
+
+/// Maps the source files and inlined call sites of the source module to the ones of the
+/// destination module.
+struct LocMap {
+    files: FxHashMap<SourceFile, SourceFile>,
+    inlined_ats: FxHashMap<InlinedAt, InlinedAt>,
+}
+
+impl LocMap {
+    fn new(dest: &ModuleCtx, src: &ModuleCtx) -> Self {
+        let mut map = Self {
+            files: FxHashMap::default(),
+            inlined_ats: FxHashMap::default(),
+        };
+
+        let (files, inlined_ats): (Vec<_>, Vec<_>) = src.with_source_file_store(|s| {
+            (
+                s.all_files()
+                    .map(|(file, path)| (file, path.to_string()))
+                    .collect(),
+                s.all_inlined_ats()
+                    .map(|(inlined_at, data)| (inlined_at, data.clone()))
+                    .collect(),
+            )
+        });
+
+        dest.with_source_file_store_mut(|s| {
+            for (file, path) in files {
+                map.files.insert(file, s.make_file(&path));
+            }
+            // Parents of an inlined call site are made before it.
+            for (inlined_at, data) in inlined_ats {
+                let data = InlinedAtData {
+                    call_site: data.call_site.map(|loc| map.map(loc)),
+                    parent: data.parent.map(|parent| map.inlined_ats[&parent]),
+                    ..data
+                };
+                map.inlined_ats.insert(inlined_at, s.make_inlined_at(data));
+            }
+        });
+
+        map
+    }
+
+    fn map(&self, loc: SourceLoc) -> SourceLoc {
+        SourceLoc {
+            file: self.files[&loc.file],
+            inlined_at: loc
+                .inlined_at
+                .map(|inlined_at| self.inlined_ats[&inlined_at]),
+            ..loc
+        }
+    }
+}
+
+struct EntityMaps<'a> {
+    types: &'a TypeMap,
+    gvs: &'a FxHashMap<GlobalVariable, GlobalVariable>,
+    intrinsics: &'a FxHashMap<Intrinsic, Intrinsic>,
+    funcs: &'a FxHashMap<FuncRef, FuncRef>,
+    locs: &'a LocMap,
+}
+
+impl EntityMaps<'_> {
+    /// Rewrites `func` of the source module to refer to the entities of the destination module.
+    fn map_func(&self, mut func: Function, name: &str, ctx: &ModuleCtx) -> Function {
+        func.sig = map_sig(&func.sig, name, |ty| self.types.map(ty));
+        func.dfg.ctx = ctx.clone();
+
+        for value in func.dfg.values.values_mut() {
+            *value = match *value {
+                ValueData::Insn { insn, ty } => ValueData::Insn {
+                    insn,
+                    ty: self.types.map(ty),
+                },
+                ValueData::Arg { ty, idx } => ValueData::Arg {
+                    ty: self.types.map(ty),
+                    idx,
+                },
+                ValueData::Immediate { .. } => continue,
+                ValueData::Global { gv, ty } => ValueData::Global {
+                    gv: self.gvs[&gv],
+                    ty: self.types.map(ty),
+                },
+            };
+        }
+
+        let insns: Vec<_> = func
+            .layout
+            .iter_block()
+            .flat_map(|block| func.layout.iter_insn(block))
+            .collect();
+        for insn in insns {
+            let mut data = func.dfg.insn_data(insn).clone();
+            match &mut data {
+                InsnData::Cast { ty, .. }
+                | InsnData::Alloca { ty }
+                | InsnData::Phi { ty, .. }
+                | InsnData::Call { ret_ty: ty, .. }
+                | InsnData::Intrinsic { ret_ty: ty, .. } => *ty = self.types.map(*ty),
+                _ => {}
+            }
+            match &mut data {
+                InsnData::Call { func: callee, .. } => *callee = self.funcs[callee],
+                InsnData::Intrinsic { intrinsic, .. } => *intrinsic = self.intrinsics[intrinsic],
+                _ => {}
+            }
+            func.dfg.replace_insn(insn, data);
+
+            let loc = func.dfg.insn_loc(insn);
+            func.dfg
+                .set_insn_loc(insn, loc.map(|loc| self.locs.map(loc)));
+        }
+
+        // The signatures of the callees are refreshed once all functions are linked.
+        func.callees = func
+            .callees
+            .into_iter()
+            .map(|(callee, sig)| (self.funcs[&callee], sig))
+            .collect();
+
+        func
+    }
+}
+
+fn map_sig(sig: &Signature, name: &str, map_ty: impl Fn(Type) -> Type) -> Signature {
+    let args: Vec<_> = sig.args().iter().map(|ty| map_ty(*ty)).collect();
+    Signature::new(name, sig.linkage(), &args, map_ty(sig.ret_ty()))
+        .with_call_conv(sig.call_conv())
+        .with_attrs(sig.attrs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        builder::{test_util::*, ModuleBuilder},
+        func_cursor::InsnInserter,
+        global_variable::ConstantValue,
+        DataLocationKind,
+    };
+
+    /// Builds a library with a public `%add_one(v0.i32) -> i32` that loads the increment from a
+    /// global aliased as `uno`, and a private `%helper`.
+    fn build_lib() -> Module {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        mb.declare_struct_type("pair", &[Type::I32, Type::I32], false);
+        let gv = mb.make_global(GlobalVariableData::constant(
+            "one".to_string(),
+            Type::I32,
+            Linkage::Public,
+            ConstantValue::make_imm(1i32),
+        ));
+        mb.ctx.with_gv_store_mut(|s| s.make_alias("uno", gv));
+
+        let helper =
+            mb.declare_function(Signature::new("helper", Linkage::Private, &[], Type::I32));
+        let mut builder = mb.build_function::<InsnInserter>(helper);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let ptr = builder.make_global_value(gv);
+        let one = builder.load(DataLocationKind::Memory, ptr);
+        builder.ret(Some(one));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("add_one", Linkage::Public, &[Type::I32], Type::I32);
+        let add_one = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(add_one);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let one = builder.call(helper, &[]).unwrap();
+        let v = builder.add(x, one);
+        builder.ret(Some(v));
+        builder.seal_all();
+
+        builder.finish().build()
+    }
+
+    /// Builds a module whose public `%main(v0.i32) -> i32` calls the declared `%add_one` and a
+    /// private `%helper` of its own.
+    fn build_main(add_one_args: &[Type]) -> (Module, FuncRef) {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        mb.declare_struct_type("pair", &[Type::I32, Type::I32], false);
+        let add_one = mb.declare_external_function("add_one", add_one_args, Type::I32);
+
+        let helper =
+            mb.declare_function(Signature::new("helper", Linkage::Private, &[], Type::I32));
+        let mut builder = mb.build_function::<InsnInserter>(helper);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let two = builder.make_imm_value(2i32);
+        builder.ret(Some(two));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("main", Linkage::Public, &[Type::I32], Type::I32);
+        let main = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(main);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let x = builder.args()[0];
+        let v = builder.call(helper, &[]).unwrap();
+        let args: Vec<_> = add_one_args.iter().map(|_| x).collect();
+        let r = builder.call(add_one, &args).unwrap();
+        let v = builder.add(v, r);
+        builder.ret(Some(v));
+        builder.seal_all();
+
+        (builder.finish().build(), main)
+    }
+
+    #[test]
+    fn link_library() {
+        let (mut module, main) = build_main(&[Type::I32]);
+        module.link(build_lib()).unwrap();
+
+        // The declaration of `%add_one` is replaced with the definition, and the private
+        // `%helper` of the library is added under a fresh name.
+        assert_eq!(module.funcs.len(), 4);
+        let add_one = module
+            .iter_functions()
+            .find(|func_ref| module.funcs[*func_ref].sig.name() == "add_one")
+            .unwrap();
+        assert_eq!(module.funcs[add_one].sig.linkage(), Linkage::Public);
+        assert_eq!(
+            module.funcs[main].callees[&add_one].linkage(),
+            Linkage::Public
+        );
+        let dumped = dump_func(&module, add_one);
+        assert!(dumped.contains("call %helper__0"));
+        assert!(dump_func(&module, main).contains("call %helper "));
+
+        let gv = module.ctx.with_gv_store(|s| s.gv_by_symbol("one")).unwrap();
+        assert_eq!(module.ctx.with_gv_store(|s| s.ty(gv)), Type::I32);
+        assert_eq!(
+            module.ctx.with_gv_store(|s| s.gv_by_symbol("uno")),
+            Some(gv)
+        );
+        assert_eq!(module.ctx.with_ty_store(|s| s.all_struct_data().count()), 1);
+    }
+
+    #[test]
+    fn link_errors() {
+        let (mut module, _) = build_main(&[Type::I32, Type::I32]);
+        assert_eq!(
+            module.link(build_lib()),
+            Err(LinkError::SymbolTypeMismatch("add_one".to_string()))
+        );
+
+        let mut module = build_lib();
+        assert_eq!(
+            module.link(build_lib()),
+            Err(LinkError::DuplicateSymbol("one".to_string()))
+        );
+
+        let (mut module, _) = build_main(&[Type::I32]);
+        module
+            .ctx
+            .with_ty_store_mut(|s| s.make_struct("pair2", &[], false));
+        let lib = build_lib();
+        lib.ctx
+            .with_ty_store_mut(|s| s.make_struct("pair2", &[Type::I8], false));
+        assert_eq!(
+            module.link(lib),
+            Err(LinkError::StructMismatch("pair2".to_string()))
+        );
+
+        let (mut module, _) = build_main(&[Type::I32]);
+        let hash = |params: &[Type]| IntrinsicData::new("hash", params, Type::I32);
+        module
+            .ctx
+            .isa
+            .with_intrinsics_mut(|r| r.register(hash(&[Type::I32])))
+            .unwrap();
+        let lib = build_lib();
+        lib.ctx
+            .isa
+            .with_intrinsics_mut(|r| r.register(hash(&[])))
+            .unwrap();
+        assert_eq!(
+            module.link(lib),
+            Err(LinkError::SymbolTypeMismatch("hash".to_string()))
+        );
+    }
+}
@@ -1,13 +1,26 @@
 use std::{
     fmt,
+    str::FromStr,
     sync::{Arc, RwLock},
 };
 
+use arc_swap::ArcSwapOption;
 use cranelift_entity::{entity_impl, PrimaryMap};
 
-use crate::Function;
+use rustc_hash::FxHashSet;
 
-use crate::{global_variable::GlobalVariableStore, isa::TargetIsa, types::TypeStore};
+use crate::{Function, InsnData};
+
+use crate::{
+    contract::Contracts,
+    global_variable::{GlobalVariableStore, SymbolError},
+    isa::TargetIsa,
+    linker::{self, LinkError},
+    source_loc::SourceFileStore,
+    static_init::{InitError, StaticInits},
+    types::{CompoundTypeData, TypeStore},
+    Type,
+};
 
 use super::Linkage;
 
@@ -17,6 +30,16 @@ pub struct Module {
     pub funcs: PrimaryMap<FuncRef, Function>,
 
     pub ctx: ModuleCtx,
+
+    /// Functions that initialize global variables before any other function runs.
+    pub static_inits: StaticInits,
+
+    /// The contracts the module is compiled into.
+    pub contracts: Contracts,
+
+    /// Functions removed by [`Self::remove_function`]. Their `FuncRef`s stay in [`Self::funcs`]
+    /// as tombstones, so that the other `FuncRef`s remain valid.
+    pub removed_funcs: FxHashSet<FuncRef>,
 }
 
 impl Module {
@@ -25,33 +48,193 @@ impl Module {
         Self {
             funcs: PrimaryMap::default(),
             ctx: ModuleCtx::new(isa),
+            static_inits: StaticInits::default(),
+            contracts: Contracts::default(),
+            removed_funcs: FxHashSet::default(),
         }
     }
 
-    /// Returns `func_ref` in the module.
+    /// Returns `func_ref` of the functions in the module, except the removed ones.
     pub fn iter_functions(&self) -> impl Iterator<Item = FuncRef> {
-        self.funcs.keys()
+        let funcs: Vec<_> = self
+            .funcs
+            .keys()
+            .filter(|func_ref| !self.is_removed(*func_ref))
+            .collect();
+        funcs.into_iter()
+    }
+
+    pub fn is_removed(&self, func_ref: FuncRef) -> bool {
+        self.removed_funcs.contains(&func_ref)
+    }
+
+    /// Removes the function from the module. Its body is dropped, and its `FuncRef` is kept as a
+    /// tombstone that [`Self::iter_functions`] skips.
+    ///
+    /// Fails without changing anything if the function is still called by another function, or
+    /// it's a static initializer or an entry of a contract.
+    pub fn remove_function(&mut self, func_ref: FuncRef) -> Result<(), RemoveFunctionError> {
+        if self.static_inits.iter().any(|init| init.func == func_ref) {
+            return Err(RemoveFunctionError::StaticInit(func_ref));
+        }
+        if let Some(contract) = self
+            .contracts
+            .iter()
+            .find(|contract| contract.entries.contains(&func_ref))
+        {
+            return Err(RemoveFunctionError::ContractEntry {
+                func: func_ref,
+                contract: contract.name.clone(),
+            });
+        }
+        let callers: Vec<_> = self
+            .iter_functions()
+            .filter(|caller| *caller != func_ref && self.calls(*caller, func_ref))
+            .collect();
+        if !callers.is_empty() {
+            return Err(RemoveFunctionError::Called {
+                func: func_ref,
+                callers,
+            });
+        }
+
+        let sig = self.funcs[func_ref].sig.clone();
+        self.funcs[func_ref] = Function::new(&self.ctx, sig);
+        for func in self.funcs.values_mut() {
+            func.callees.remove(&func_ref);
+        }
+        self.removed_funcs.insert(func_ref);
+        Ok(())
+    }
+
+    /// Replaces the function with `func`, e.g., with an optimized copy of its body, and returns
+    /// the old function. The signature callers keep of the function is replaced as well.
+    ///
+    /// # Panics
+    /// Panics if the function is removed, or `func` takes or returns different types.
+    pub fn replace_function(&mut self, func_ref: FuncRef, func: Function) -> Function {
+        assert!(!self.is_removed(func_ref), "{func_ref:?} is removed");
+        let old_sig = &self.funcs[func_ref].sig;
+        assert!(
+            old_sig.args() == func.sig.args() && old_sig.ret_ty() == func.sig.ret_ty(),
+            "the new function of {func_ref:?} has a different signature"
+        );
+
+        let sig = func.sig.clone();
+        for caller in self.funcs.values_mut() {
+            if let Some(callee_sig) = caller.callees.get_mut(&func_ref) {
+                *callee_sig = sig.clone();
+            }
+        }
+        std::mem::replace(&mut self.funcs[func_ref], func)
+    }
+
+    /// Returns `true` if `caller` has a call to `callee` in its layout.
+    fn calls(&self, caller: FuncRef, callee: FuncRef) -> bool {
+        let func = &self.funcs[caller];
+        func.layout.iter_block().any(|block| {
+            func.layout.iter_insn(block).any(|insn| {
+                matches!(
+                    func.dfg.insn_data(insn),
+                    InsnData::Call { func: target, .. } if *target == callee
+                )
+            })
+        })
     }
 
     /// Returns `true` if the function has external linkage.
     pub fn is_external(&self, func_ref: FuncRef) -> bool {
         self.funcs[func_ref].sig.linkage() == Linkage::External
     }
+
+    /// Returns the static initializers in the order they run.
+    pub fn static_init_order(&self) -> Result<Vec<FuncRef>, InitError> {
+        self.static_inits.order(&self.funcs)
+    }
+
+    /// Renames the function to `new`. The signature callers keep of the function is renamed as
+    /// well, so that their calls refer to the new name.
+    pub fn rename_function(&mut self, func_ref: FuncRef, new: &str) -> Result<(), SymbolError> {
+        if self
+            .funcs
+            .iter()
+            .any(|(other, func)| other != func_ref && func.sig.name() == new)
+        {
+            return Err(SymbolError::Duplicate(new.to_string()));
+        }
+
+        self.funcs[func_ref].sig.set_name(new);
+        for func in self.funcs.values_mut() {
+            if let Some(sig) = func.callees.get_mut(&func_ref) {
+                sig.set_name(new);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges `other` into the module, e.g., a separately compiled library. See
+    /// [`linker`](crate::linker) for how the symbols of the modules are resolved.
+    pub fn link(&mut self, other: Module) -> Result<(), LinkError> {
+        linker::link(self, other)
+    }
+}
+
+/// A function couldn't be removed because it's still used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoveFunctionError {
+    /// The function is called by `callers`.
+    Called {
+        func: FuncRef,
+        callers: Vec<FuncRef>,
+    },
+    /// The function is a static initializer.
+    StaticInit(FuncRef),
+    /// The function is an entry of `contract`.
+    ContractEntry { func: FuncRef, contract: String },
+}
+
+impl fmt::Display for RemoveFunctionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Called { func, callers } => {
+                write!(f, "can't remove {func:?}, since it's called by")?;
+                for caller in callers {
+                    write!(f, " {caller:?}")?;
+                }
+                Ok(())
+            }
+            Self::StaticInit(func) => {
+                write!(f, "can't remove {func:?}, since it's a static initializer")
+            }
+            Self::ContractEntry { func, contract } => write!(
+                f,
+                "can't remove {func:?}, since it's an entry of contract `{contract}`"
+            ),
+        }
+    }
 }
 
+impl std::error::Error for RemoveFunctionError {}
+
 #[derive(Debug, Clone)]
 pub struct ModuleCtx {
     pub isa: TargetIsa,
-    type_store: Arc<RwLock<TypeStore>>,
+    /// How binary operands of different integer widths are treated. This is read by the builder
+    /// of the module, so it should be set before functions are built.
+    pub width_policy: WidthPolicy,
+    type_store: Arc<SharedTypeStore>,
     gv_store: Arc<RwLock<GlobalVariableStore>>,
+    source_file_store: Arc<RwLock<SourceFileStore>>,
 }
 
 impl ModuleCtx {
     pub fn new(isa: TargetIsa) -> Self {
         Self {
             isa,
-            type_store: Arc::new(RwLock::new(TypeStore::default())),
+            width_policy: WidthPolicy::default(),
+            type_store: Arc::new(SharedTypeStore::default()),
             gv_store: Arc::new(RwLock::new(GlobalVariableStore::default())),
+            source_file_store: Arc::new(RwLock::new(SourceFileStore::default())),
         }
     }
 
@@ -59,14 +242,83 @@ impl ModuleCtx {
     where
         F: FnOnce(&TypeStore) -> R,
     {
-        f(&self.type_store.read().unwrap())
+        let store = &self.type_store;
+        if let Some(frozen) = &*store.frozen.load() {
+            return f(frozen);
+        }
+
+        let building = store.building.read().unwrap();
+        // The store might have been frozen while waiting for the lock.
+        match &*store.frozen.load() {
+            Some(frozen) => f(frozen),
+            None => f(&building),
+        }
     }
 
+    /// Gives mutable access to the type store. Once the store is
+    /// [frozen](Self::freeze_types), this copies the store, so types should be made before
+    /// freezing.
     pub fn with_ty_store_mut<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&mut TypeStore) -> R,
     {
-        f(&mut self.type_store.write().unwrap())
+        let store = &self.type_store;
+        let mut building = store.building.write().unwrap();
+        match store.frozen.load_full() {
+            Some(frozen) => {
+                let mut new = TypeStore::clone(&frozen);
+                let result = f(&mut new);
+                store.frozen.store(Some(Arc::new(new)));
+                result
+            }
+            None => f(&mut building),
+        }
+    }
+
+    /// Freezes the type store, after which reading it takes no lock. This is meant to be called
+    /// once a module is built, before functions are compiled in parallel.
+    pub fn freeze_types(&self) {
+        let store = &self.type_store;
+        let mut building = store.building.write().unwrap();
+        if store.frozen.load().is_none() {
+            store
+                .frozen
+                .store(Some(Arc::new(std::mem::take(&mut *building))));
+        }
+    }
+
+    /// Returns `true` if the type store is [frozen](Self::freeze_types).
+    pub fn is_types_frozen(&self) -> bool {
+        self.type_store.frozen.load().is_some()
+    }
+
+    /// Returns a copy of the context whose stores are copies too, unlike [`Clone::clone`] which
+    /// shares them. This keeps a snapshot of the global variables while a module is rewritten.
+    pub fn deep_clone(&self) -> Self {
+        let type_store = SharedTypeStore {
+            building: RwLock::new(self.with_ty_store(TypeStore::clone)),
+            frozen: ArcSwapOption::empty(),
+        };
+        Self {
+            isa: self.isa.clone(),
+            width_policy: self.width_policy,
+            type_store: Arc::new(type_store),
+            gv_store: Arc::new(RwLock::new(self.with_gv_store(GlobalVariableStore::clone))),
+            source_file_store: Arc::new(RwLock::new(
+                self.with_source_file_store(SourceFileStore::clone),
+            )),
+        }
+    }
+
+    /// Makes the pointer type to `ty`. Unlike [`TypeStore::make_ptr`], this only takes the
+    /// write lock of the type store if the type doesn't exist yet, so that functions compiled in
+    /// parallel don't contend for it.
+    pub fn make_ptr(&self, ty: Type) -> Type {
+        let data = CompoundTypeData::Ptr(ty);
+        if let Some(cmpd) = self.with_ty_store(|s| s.lookup_compound(&data)) {
+            return Type::Compound(cmpd);
+        }
+        self.with_ty_store_mut(|s| Type::Compound(s.make_compound(data)))
     }
 
     pub fn with_gv_store<F, R>(&self, f: F) -> R
@@ -82,9 +334,74 @@ impl ModuleCtx {
     {
         f(&mut self.gv_store.write().unwrap())
     }
+
+    pub fn with_source_file_store<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&SourceFileStore) -> R,
+    {
+        f(&self.source_file_store.read().unwrap())
+    }
+
+    pub fn with_source_file_store_mut<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut SourceFileStore) -> R,
+    {
+        f(&mut self.source_file_store.write().unwrap())
+    }
+}
+
+/// The policy for binary insns whose integer operands have different widths, e.g., `add` of an
+/// `i8` and an `i32`. The amount of a shift or a rotate may have any width under either policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WidthPolicy {
+    /// Operands must have the same width.
+    #[default]
+    Strict,
+    /// The narrower operand is extended to the width of the other one, with `sext` for signed
+    /// insns, e.g., `slt` and `sdiv`, and with `zext` otherwise.
+    Widen,
+}
+
+impl WidthPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Widen => "widen",
+        }
+    }
+}
+
+impl fmt::Display for WidthPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WidthPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "widen" => Ok(Self::Widen),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The type store shared by all clones of a [`ModuleCtx`].
+///
+/// The store lives behind a lock while the module is built. Freezing moves it into an atomically
+/// swapped snapshot; a type made after that replaces the snapshot with an updated copy, so readers
+/// never wait.
+#[derive(Debug, Default)]
+struct SharedTypeStore {
+    building: RwLock<TypeStore>,
+    frozen: ArcSwapOption<TypeStore>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FuncRef(u32);
 entity_impl!(FuncRef);
 
@@ -106,3 +423,106 @@ impl<'a> fmt::Display for DisplayCalleeFuncRef<'a> {
         write!(f, "{}", sig.name())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        builder::{test_util::build_test_isa, ModuleBuilder},
+        func_cursor::InsnInserter,
+        global_variable::ConstantValue,
+        GlobalVariableData, Signature,
+    };
+
+    #[test]
+    fn freeze_types() {
+        let ctx = ModuleCtx::new(build_test_isa());
+        let ptr = ctx.make_ptr(Type::I8);
+
+        let clone = ctx.clone();
+        clone.freeze_types();
+        assert!(ctx.is_types_frozen());
+        assert_eq!(ctx.make_ptr(Type::I8), ptr);
+
+        // Types can still be made after freezing, and are visible to all clones.
+        let array = ctx.with_ty_store_mut(|s| s.make_array(Type::I8, 2));
+        assert_eq!(
+            clone.with_ty_store(|s| s.array_def(array)),
+            Some((Type::I8, 2))
+        );
+        assert_eq!(clone.with_ty_store(|s| s.deref(ptr)), Some(Type::I8));
+    }
+
+    #[test]
+    fn deep_clone() {
+        let ctx = ModuleCtx::new(build_test_isa());
+        let data = GlobalVariableData::constant(
+            "g".to_string(),
+            Type::I32,
+            Linkage::Private,
+            ConstantValue::make_imm(1i32),
+        );
+        let gv = ctx.with_gv_store_mut(|s| s.make_gv(data.clone()));
+
+        let snapshot = ctx.deep_clone();
+        let replaced = GlobalVariableData {
+            data: Some(ConstantValue::make_imm(2i32)),
+            ..data.clone()
+        };
+        ctx.with_gv_store_mut(|s| s.replace_gv_data(gv, replaced));
+        ctx.make_ptr(Type::I8);
+
+        assert_eq!(snapshot.with_gv_store(|s| s.gv_data(gv).clone()), data);
+        let ptr = CompoundTypeData::Ptr(Type::I8);
+        assert_eq!(snapshot.with_ty_store(|s| s.lookup_compound(&ptr)), None);
+    }
+
+    #[test]
+    fn remove_and_replace_function() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let sig = Signature::new("callee", Linkage::Private, &[], Type::I32);
+        let callee = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(callee);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let one = builder.make_imm_value(1i32);
+        builder.ret(Some(one));
+        builder.seal_all();
+        let mut mb = builder.finish();
+
+        let sig = Signature::new("caller", Linkage::Public, &[], Type::I32);
+        let caller = mb.declare_function(sig);
+        let mut builder = mb.build_function::<InsnInserter>(caller);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let v = builder.call(callee, &[]).unwrap();
+        builder.ret(Some(v));
+        builder.seal_all();
+        let mut module = builder.finish().build();
+
+        assert_eq!(
+            module.remove_function(callee),
+            Err(RemoveFunctionError::Called {
+                func: callee,
+                callers: vec![caller],
+            })
+        );
+
+        // Replace the caller with a function that returns the immediate by itself.
+        let sig = Signature::new("caller", Linkage::Public, &[], Type::I32);
+        let mut func = Function::new(&module.ctx, sig);
+        let b0 = func.dfg.make_block();
+        func.layout.append_block(b0);
+        let one = func.dfg.make_imm_value(1i32);
+        let ret = func.dfg.make_insn(InsnData::Return { args: Some(one) });
+        func.layout.append_insn(ret, b0);
+        let old = module.replace_function(caller, func);
+        assert_eq!(old.callees.len(), 1);
+
+        module.remove_function(callee).unwrap();
+        assert!(module.is_removed(callee));
+        assert_eq!(module.iter_functions().collect::<Vec<_>>(), vec![caller]);
+        assert!(module.funcs[callee].layout.entry_block().is_none());
+    }
+}
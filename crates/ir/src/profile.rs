@@ -0,0 +1,311 @@
+//! This module contains [`Profile`], the execution counts of blocks and edges measured by running
+//! a module, e.g., by the interpreter or by replaying transaction traces.
+//!
+//! [`Profile::apply`] turns the counts into the [`BranchWeights`] of each branch, which
+//! optimizations read to find hot paths. A branch whose edges are all counted gets their counts
+//! as weights. Otherwise, the count of an edge is derived from block counts where it's determined
+//! by them: an edge into a block that has no other predecessor is taken as often as the block
+//! runs, and the last uncounted edge of a branch is taken whenever the others aren't.
+use std::{collections::BTreeMap, fmt};
+
+use smallvec::SmallVec;
+
+use crate::{module::FuncRef, Block, BranchWeights, ControlFlowGraph, Function, Module};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    blocks: BTreeMap<(FuncRef, Block), u64>,
+    edges: BTreeMap<(FuncRef, Block, Block), u64>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `count` runs of `block` of `func`.
+    pub fn add_block_count(&mut self, func: FuncRef, block: Block, count: u64) {
+        let total = self.blocks.entry((func, block)).or_default();
+        *total = total.saturating_add(count);
+    }
+
+    /// Adds `count` branches from `from` to `to` in `func`.
+    pub fn add_edge_count(&mut self, func: FuncRef, from: Block, to: Block, count: u64) {
+        let total = self.edges.entry((func, from, to)).or_default();
+        *total = total.saturating_add(count);
+    }
+
+    /// Adds the counts of `other`, e.g., to combine the profiles of several runs.
+    pub fn merge(&mut self, other: &Profile) {
+        for (&(func, block), &count) in &other.blocks {
+            self.add_block_count(func, block, count);
+        }
+        for (&(func, from, to), &count) in &other.edges {
+            self.add_edge_count(func, from, to, count);
+        }
+    }
+
+    pub fn block_count(&self, func: FuncRef, block: Block) -> Option<u64> {
+        self.blocks.get(&(func, block)).copied()
+    }
+
+    pub fn edge_count(&self, func: FuncRef, from: Block, to: Block) -> Option<u64> {
+        self.edges.get(&(func, from, to)).copied()
+    }
+
+    /// Returns `true` if the profile has counts of `func`.
+    pub fn has_func(&self, func: FuncRef) -> bool {
+        let blocks = self
+            .blocks
+            .range((func, Block(0))..=(func, Block(u32::MAX)))
+            .next();
+        let edges = self
+            .edges
+            .range((func, Block(0), Block(0))..=(func, Block(u32::MAX), Block(u32::MAX)))
+            .next();
+        blocks.is_some() || edges.is_some()
+    }
+
+    /// Attaches branch weights derived from the counts to the branches of every profiled
+    /// function of `module`, and returns the number of branches that got weights. Branches whose
+    /// edges can't be counted keep their weights.
+    ///
+    /// Fails without changing `module` if the profile refers to a function, a block or an edge
+    /// that isn't in it, i.e., it was measured on another version of the module.
+    pub fn apply(&self, module: &mut Module) -> Result<usize, ProfileError> {
+        self.verify(module)?;
+
+        let mut applied = 0;
+        let mut cfg = ControlFlowGraph::new();
+        for func_ref in module.iter_functions() {
+            if !self.has_func(func_ref) {
+                continue;
+            }
+
+            let func = &mut module.funcs[func_ref];
+            cfg.compute(func);
+            let blocks: Vec<_> = func.layout.iter_block().collect();
+            for block in blocks {
+                let Some(branch) = func.layout.last_insn_of(block) else {
+                    continue;
+                };
+                if func.dfg.analyze_branch(branch).dests_num() < 2 {
+                    continue;
+                }
+
+                if let Some(weights) = self.branch_weights(func_ref, func, &cfg, block) {
+                    func.dfg.set_branch_weights(branch, Some(weights));
+                    applied += 1;
+                }
+            }
+        }
+
+        Ok(applied)
+    }
+
+    fn verify(&self, module: &Module) -> Result<(), ProfileError> {
+        let is_defined = |func: FuncRef| module.funcs.is_valid(func) && !module.is_removed(func);
+        let is_inserted = |func: FuncRef, block: Block| {
+            let layout = &module.funcs[func].layout;
+            module.funcs[func].dfg.blocks.is_valid(block) && layout.is_block_inserted(block)
+        };
+
+        for &(func, block) in self.blocks.keys() {
+            if !is_defined(func) {
+                return Err(ProfileError::UnknownFunc(func));
+            }
+            if !is_inserted(func, block) {
+                return Err(ProfileError::UnknownBlock { func, block });
+            }
+        }
+
+        for &(func, from, to) in self.edges.keys() {
+            if !is_defined(func) {
+                return Err(ProfileError::UnknownFunc(func));
+            }
+            let is_edge = is_inserted(func, from)
+                && is_inserted(func, to)
+                && module.funcs[func]
+                    .layout
+                    .last_insn_of(from)
+                    .is_some_and(|branch| {
+                        module.funcs[func]
+                            .dfg
+                            .analyze_branch(branch)
+                            .iter_dests()
+                            .any(|dest| dest == to)
+                    });
+            if !is_edge {
+                return Err(ProfileError::UnknownEdge { func, from, to });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the weights of the branch of `block`, or `None` if the count of one of its edges
+    /// is unknown.
+    fn branch_weights(
+        &self,
+        func_ref: FuncRef,
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        block: Block,
+    ) -> Option<BranchWeights> {
+        let branch = func.layout.last_insn_of(block)?;
+        let dests: SmallVec<[Block; 4]> = func.dfg.analyze_branch(branch).iter_dests().collect();
+
+        let mut succs: SmallVec<[Block; 4]> = SmallVec::new();
+        for &dest in &dests {
+            if !succs.contains(&dest) {
+                succs.push(dest);
+            }
+        }
+
+        let mut counts: SmallVec<[Option<u64>; 4]> = succs
+            .iter()
+            .map(|&succ| {
+                self.edge_count(func_ref, block, succ).or_else(|| {
+                    self.block_count(func_ref, succ)
+                        .filter(|_| cfg.pred_num_of(succ) == 1)
+                })
+            })
+            .collect();
+
+        let unknown: SmallVec<[usize; 4]> = (0..counts.len())
+            .filter(|&idx| counts[idx].is_none())
+            .collect();
+        if let [idx] = unknown[..] {
+            let block_count = self.block_count(func_ref, block)?;
+            let known: u64 = counts.iter().flatten().sum();
+            counts[idx] = Some(block_count.saturating_sub(known));
+        }
+        let counts: SmallVec<[u64; 4]> = counts.into_iter().collect::<Option<_>>()?;
+
+        // The count of an edge goes to the first destination it is, so that the weight of the
+        // edge is its count, see `DataFlowGraph::edge_weight`.
+        let mut counted: SmallVec<[Block; 4]> = SmallVec::new();
+        let weights: SmallVec<[u64; 4]> = dests
+            .iter()
+            .map(|&dest| {
+                if counted.contains(&dest) {
+                    return 0;
+                }
+                counted.push(dest);
+                counts[succs.iter().position(|&succ| succ == dest).unwrap()]
+            })
+            .collect();
+        Some(scale_weights(&weights))
+    }
+}
+
+/// Scales `counts` down to fit in `u32`, keeping nonzero counts nonzero, since a weight of zero
+/// marks an edge as never taken.
+fn scale_weights(counts: &[u64]) -> BranchWeights {
+    let max = counts.iter().copied().max().unwrap_or_default();
+    let scale = max.div_ceil(u64::from(u32::MAX)).max(1);
+    let weights: SmallVec<[u32; 4]> = counts
+        .iter()
+        .map(|&count| match count {
+            0 => 0,
+            count => (count / scale).max(1) as u32,
+        })
+        .collect();
+    BranchWeights::new(&weights)
+}
+
+/// A profile doesn't match the module it's applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileError {
+    /// The function isn't defined in the module.
+    UnknownFunc(FuncRef),
+    /// The block isn't in the function.
+    UnknownBlock { func: FuncRef, block: Block },
+    /// The function has no branch from `from` to `to`.
+    UnknownEdge {
+        func: FuncRef,
+        from: Block,
+        to: Block,
+    },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownFunc(func) => {
+                write!(f, "profiled function {func:?} isn't defined in the module")
+            }
+            Self::UnknownBlock { func, block } => {
+                write!(f, "profiled block {block} isn't in function {func:?}")
+            }
+            Self::UnknownEdge { func, from, to } => {
+                write!(f, "profiled edge {from} -> {to} isn't in function {func:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::test_util::*, Type};
+
+    #[test]
+    fn apply_counts() {
+        let mut builder = test_func_builder(&[Type::I1, Type::I1], Type::Void);
+        let b0 = builder.append_block();
+        let b1 = builder.append_block();
+        let b2 = builder.append_block();
+        let b3 = builder.append_block();
+        let (c0, c1) = (builder.args()[0], builder.args()[1]);
+
+        builder.switch_to_block(b0);
+        builder.br(c0, b1, b2);
+
+        builder.switch_to_block(b1);
+        builder.br(c1, b2, b3);
+
+        builder.switch_to_block(b2);
+        builder.ret(None);
+
+        builder.switch_to_block(b3);
+        builder.ret(None);
+        builder.seal_all();
+
+        let mut module = builder.finish().build();
+        let func_ref = module.iter_functions().next().unwrap();
+
+        // `b0 -> b1` is determined by the count of `b1`, which has no other predecessor, and
+        // `b0 -> b2` by the count of `b0`. `b1 -> b2` is counted directly.
+        let mut profile = Profile::new();
+        profile.add_block_count(func_ref, b0, 100);
+        profile.add_block_count(func_ref, b1, 10);
+        profile.add_edge_count(func_ref, b1, b2, 7);
+        profile.add_edge_count(func_ref, b1, b3, 3);
+        assert_eq!(profile.apply(&mut module), Ok(2));
+
+        let func = &module.funcs[func_ref];
+        let branch_of = |block| func.layout.last_insn_of(block).unwrap();
+        assert_eq!(
+            func.dfg.branch_weights(branch_of(b0)),
+            Some(&BranchWeights::new(&[10, 90]))
+        );
+        assert_eq!(
+            func.dfg.branch_weights(branch_of(b1)),
+            Some(&BranchWeights::new(&[7, 3]))
+        );
+
+        let mut stale = Profile::new();
+        stale.add_edge_count(func_ref, b0, b3, 1);
+        assert_eq!(
+            stale.apply(&mut module),
+            Err(ProfileError::UnknownEdge {
+                func: func_ref,
+                from: b0,
+                to: b3
+            })
+        );
+    }
+}
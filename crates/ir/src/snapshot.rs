@@ -0,0 +1,138 @@
+//! This module contains serializable snapshots of modules, so that build tools can dump IR as
+//! JSON, MessagePack or any other format `serde` supports, and inspect it.
+//!
+//! IR entities refer to each other by index, e.g., a [`Type::Compound`] is an index into the type
+//! store of the module, so a snapshot keeps the tables the indices point into along with the
+//! functions.
+//!
+//! [`Type::Compound`]: crate::Type::Compound
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    module::FuncRef,
+    types::{CompoundType, CompoundTypeData},
+    Block, Function, GlobalVariable, GlobalVariableData, Insn, InsnData, Intrinsic, IntrinsicData,
+    Module, Signature, Value, ValueData,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleSnapshot {
+    /// The target triple, e.g., `evm-ethereum-london`.
+    pub target: String,
+    pub compound_types: Vec<(CompoundType, CompoundTypeData)>,
+    pub globals: Vec<(GlobalVariable, GlobalVariableData)>,
+    /// The intrinsics of the target, including those registered by the frontend.
+    pub intrinsics: Vec<(Intrinsic, IntrinsicData)>,
+    pub funcs: Vec<(FuncRef, FunctionSnapshot)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionSnapshot {
+    pub sig: Signature,
+    pub arg_values: Vec<Value>,
+    /// The data of the values of the function, indexed by [`Value`].
+    pub values: Vec<ValueData>,
+    /// The blocks in layout order, empty for a declaration.
+    pub blocks: Vec<BlockSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockSnapshot {
+    pub block: Block,
+    pub insns: Vec<InsnSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsnSnapshot {
+    pub insn: Insn,
+    pub data: InsnData,
+    /// The results of the insn, empty if it has none.
+    pub results: Vec<Value>,
+}
+
+impl ModuleSnapshot {
+    pub fn new(module: &Module) -> Self {
+        let ctx = &module.ctx;
+        Self {
+            target: ctx.isa.triple().to_string(),
+            compound_types: ctx.with_ty_store(|s| {
+                s.all_compounds()
+                    .map(|(cmpd, data)| (cmpd, data.clone()))
+                    .collect()
+            }),
+            globals: ctx
+                .with_gv_store(|s| s.all_gvs().map(|gv| (gv, s.gv_data(gv).clone())).collect()),
+            intrinsics: ctx.isa.with_intrinsics(|r| {
+                r.all_intrinsics()
+                    .map(|(intrinsic, data)| (intrinsic, data.clone()))
+                    .collect()
+            }),
+            funcs: module
+                .iter_functions()
+                .map(|func_ref| (func_ref, FunctionSnapshot::new(&module.funcs[func_ref])))
+                .collect(),
+        }
+    }
+}
+
+impl FunctionSnapshot {
+    pub fn new(func: &Function) -> Self {
+        let dfg = &func.dfg;
+        let blocks = func
+            .layout
+            .iter_block()
+            .map(|block| BlockSnapshot {
+                block,
+                insns: func
+                    .layout
+                    .iter_insn(block)
+                    .map(|insn| InsnSnapshot {
+                        insn,
+                        data: dfg.insn_data(insn).clone(),
+                        results: dfg.insn_results(insn).to_vec(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            sig: func.sig.clone(),
+            arg_values: func.arg_values.to_vec(),
+            values: dfg.values.values().cloned().collect(),
+            blocks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{builder::test_util::*, Type};
+
+    #[test]
+    fn json_roundtrip() {
+        let mut builder = test_func_builder(&[Type::I64], Type::I64);
+        let b0 = builder.append_block();
+        builder.switch_to_block(b0);
+        let arg = builder.args()[0];
+        let imm = builder.make_imm_value(-1i64);
+        let v = builder.add(arg, imm);
+        builder.ret(Some(v));
+        builder.seal_all();
+        let module = builder.finish().build();
+
+        let snapshot = ModuleSnapshot::new(&module);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: ModuleSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.target, "evm-ethereum-london");
+
+        let (_, func) = &parsed.funcs[0];
+        assert_eq!(func.sig.name(), "test_func");
+        assert_eq!(func.blocks[0].insns.len(), 2);
+        assert!(matches!(
+            func.values[imm.as_u32() as usize],
+            ValueData::Immediate { imm, .. } if imm.is_all_one()
+        ));
+    }
+}
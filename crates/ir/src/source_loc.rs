@@ -0,0 +1,143 @@
+//! This module contains source location definitions that instructions can carry for debugging.
+use cranelift_entity::PrimaryMap;
+use rustc_hash::FxHashMap;
+
+/// A byte range in a source file that an instruction originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceLoc {
+    pub file: SourceFile,
+    pub start: u32,
+    pub end: u32,
+    /// The call site that the instruction was inlined at, if any.
+    pub inlined_at: Option<InlinedAt>,
+}
+
+impl SourceLoc {
+    pub fn new(file: SourceFile, start: u32, end: u32) -> Self {
+        debug_assert!(start <= end);
+        Self {
+            file,
+            start,
+            end,
+            inlined_at: None,
+        }
+    }
+}
+
+/// An opaque reference to a source file path registered in [`SourceFileStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceFile(u32);
+cranelift_entity::entity_impl!(SourceFile);
+
+/// An opaque reference to an inlined call site registered in [`SourceFileStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InlinedAt(u32);
+cranelift_entity::entity_impl!(InlinedAt);
+
+/// A call site that `callee` was inlined at.
+///
+/// `parent` is the call site that the call itself was inlined at, so following `parent` gives the
+/// chain of call sites from the innermost to the outermost.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InlinedAtData {
+    /// The name of the inlined function.
+    pub callee: String,
+    /// The location of the call, or `None` if the call has no location.
+    /// The location never has `inlined_at` on its own, see `parent` instead.
+    pub call_site: Option<SourceLoc>,
+    pub parent: Option<InlinedAt>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceFileStore {
+    files: PrimaryMap<SourceFile, String>,
+    rev_files: FxHashMap<String, SourceFile>,
+    inlined_ats: PrimaryMap<InlinedAt, InlinedAtData>,
+    rev_inlined_ats: FxHashMap<InlinedAtData, InlinedAt>,
+}
+
+impl SourceFileStore {
+    /// Returns the file for `path`, registering it if it's not registered yet.
+    pub fn make_file(&mut self, path: &str) -> SourceFile {
+        if let Some(file) = self.rev_files.get(path) {
+            return *file;
+        }
+
+        let file = self.files.push(path.to_string());
+        self.rev_files.insert(path.to_string(), file);
+        file
+    }
+
+    pub fn path(&self, file: SourceFile) -> &str {
+        &self.files[file]
+    }
+
+    pub fn all_files(&self) -> impl Iterator<Item = (SourceFile, &str)> {
+        self.files.iter().map(|(file, path)| (file, path.as_str()))
+    }
+
+    pub fn make_inlined_at(&mut self, data: InlinedAtData) -> InlinedAt {
+        debug_assert!(data.call_site.is_none_or(|loc| loc.inlined_at.is_none()));
+        if let Some(inlined_at) = self.rev_inlined_ats.get(&data) {
+            return *inlined_at;
+        }
+
+        let inlined_at = self.inlined_ats.push(data.clone());
+        self.rev_inlined_ats.insert(data, inlined_at);
+        inlined_at
+    }
+
+    pub fn inlined_at_data(&self, inlined_at: InlinedAt) -> &InlinedAtData {
+        &self.inlined_ats[inlined_at]
+    }
+
+    pub fn all_inlined_ats(&self) -> impl Iterator<Item = (InlinedAt, &InlinedAtData)> {
+        self.inlined_ats.iter()
+    }
+
+    /// Removes all files and inlined call sites. Locations that refer to them become dangling, so
+    /// they must be removed first.
+    pub fn clear(&mut self) {
+        self.files.clear();
+        self.rev_files.clear();
+        self.inlined_ats.clear();
+        self.rev_inlined_ats.clear();
+    }
+
+    /// Returns `loc` of an instruction in `callee` that is inlined at a call located at
+    /// `call_site`. The new call site is appended to the outermost end of the inlined-at chain of
+    /// `loc`.
+    pub fn inline_loc(
+        &mut self,
+        loc: SourceLoc,
+        callee: &str,
+        call_site: Option<SourceLoc>,
+    ) -> SourceLoc {
+        let outermost = self.make_inlined_at(InlinedAtData {
+            callee: callee.to_string(),
+            call_site: call_site.map(|loc| SourceLoc {
+                inlined_at: None,
+                ..loc
+            }),
+            parent: call_site.and_then(|loc| loc.inlined_at),
+        });
+
+        SourceLoc {
+            inlined_at: Some(self.append_inlined_at(loc.inlined_at, outermost)),
+            ..loc
+        }
+    }
+
+    fn append_inlined_at(&mut self, chain: Option<InlinedAt>, outermost: InlinedAt) -> InlinedAt {
+        let Some(inlined_at) = chain else {
+            return outermost;
+        };
+
+        let data = self.inlined_ats[inlined_at].clone();
+        let parent = self.append_inlined_at(data.parent, outermost);
+        self.make_inlined_at(InlinedAtData {
+            parent: Some(parent),
+            ..data
+        })
+    }
+}
@@ -0,0 +1,167 @@
+//! This module contains static initializers, i.e., functions that initialize global variables
+//! whose initial values are not constants.
+//!
+//! An initializer takes no arguments and returns nothing. It can depend on other initializers,
+//! e.g., because it reads the globals they initialize; initializers run after the ones they depend
+//! on, and otherwise in the order they were registered.
+use std::fmt;
+
+use cranelift_entity::{PrimaryMap, SecondaryMap};
+
+use crate::{module::FuncRef, Function, Type};
+
+#[derive(Debug, Clone, Default)]
+pub struct StaticInits {
+    inits: Vec<StaticInit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticInit {
+    pub func: FuncRef,
+    /// Initializers that must run before `func`. Functions that are not registered as
+    /// initializers impose no order.
+    pub deps: Vec<FuncRef>,
+}
+
+impl StaticInits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `func` as an initializer that runs after `deps`. Registering a function again
+    /// adds to its dependencies.
+    pub fn register(&mut self, func: FuncRef, deps: &[FuncRef]) {
+        match self.inits.iter_mut().find(|init| init.func == func) {
+            Some(init) => init.deps.extend_from_slice(deps),
+            None => self.inits.push(StaticInit {
+                func,
+                deps: deps.to_vec(),
+            }),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StaticInit> {
+        self.inits.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inits.is_empty()
+    }
+
+    /// Returns the initializers in the order they run.
+    pub fn order(&self, funcs: &PrimaryMap<FuncRef, Function>) -> Result<Vec<FuncRef>, InitError> {
+        for init in &self.inits {
+            let sig = &funcs[init.func].sig;
+            if !sig.args().is_empty() || sig.ret_ty() != Type::Void {
+                return Err(InitError::InvalidSignature(init.func));
+            }
+        }
+
+        let mut marks = SecondaryMap::<FuncRef, Mark>::default();
+        let mut order = Vec::with_capacity(self.inits.len());
+        let mut path = Vec::new();
+        for init in &self.inits {
+            self.visit(init.func, &mut marks, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        func: FuncRef,
+        marks: &mut SecondaryMap<FuncRef, Mark>,
+        path: &mut Vec<FuncRef>,
+        order: &mut Vec<FuncRef>,
+    ) -> Result<(), InitError> {
+        match marks[func] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                let start = path.iter().position(|f| *f == func).unwrap();
+                return Err(InitError::Cycle(path[start..].to_vec()));
+            }
+            Mark::Unvisited => {}
+        }
+
+        let Some(init) = self.inits.iter().find(|init| init.func == func) else {
+            return Ok(());
+        };
+        marks[func] = Mark::Visiting;
+        path.push(func);
+        for &dep in &init.deps {
+            self.visit(dep, marks, path, order)?;
+        }
+        path.pop();
+        marks[func] = Mark::Done;
+        order.push(func);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitError {
+    /// The initializers depend on each other in a cycle, listed in dependency order.
+    Cycle(Vec<FuncRef>),
+    /// The initializer takes arguments or returns a value.
+    InvalidSignature(FuncRef),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cycle(cycle) => {
+                write!(f, "static initializers depend on each other:")?;
+                for func in cycle {
+                    write!(f, " {func:?}")?;
+                }
+                Ok(())
+            }
+            Self::InvalidSignature(func) => write!(
+                f,
+                "static initializer {func:?} must take no arguments and return nothing"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Mark {
+    #[default]
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        builder::{test_util::build_test_isa, ModuleBuilder},
+        module::ModuleCtx,
+        Linkage, Signature,
+    };
+
+    fn declare(mb: &mut ModuleBuilder, name: &str) -> FuncRef {
+        mb.declare_function(Signature::new(name, Linkage::Private, &[], Type::Void))
+    }
+
+    #[test]
+    fn init_order() {
+        let mut mb = ModuleBuilder::new(ModuleCtx::new(build_test_isa()));
+        let a = declare(&mut mb, "a");
+        let b = declare(&mut mb, "b");
+        let c = declare(&mut mb, "c");
+        let not_init = declare(&mut mb, "not_init");
+
+        let mut inits = StaticInits::new();
+        inits.register(a, &[c]);
+        inits.register(b, &[not_init]);
+        inits.register(c, &[]);
+        assert_eq!(inits.order(&mb.funcs), Ok(vec![c, a, b]));
+
+        inits.register(c, &[b, a]);
+        assert_eq!(inits.order(&mb.funcs), Err(InitError::Cycle(vec![a, c])));
+    }
+}
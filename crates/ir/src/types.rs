@@ -7,7 +7,7 @@ use rustc_hash::FxHashMap;
 
 use crate::DataFlowGraph;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct TypeStore {
     compounds: PrimaryMap<CompoundType, CompoundTypeData>,
     rev_types: FxHashMap<CompoundTypeData, CompoundType>,
@@ -25,6 +25,16 @@ impl TypeStore {
         Type::Compound(ty)
     }
 
+    /// Make the `bytes` type, a descriptor of a byte string laid out as `{*i8, i256}`: a pointer
+    /// to the first byte and the length in bytes. The descriptor can be indexed by `gep` like a
+    /// struct.
+    pub fn make_bytes(&mut self) -> Type {
+        // Make sure the data pointer type exists so that `bytes_field` works on a shared store.
+        self.make_ptr(Type::I8);
+        let ty = self.make_compound(CompoundTypeData::Bytes);
+        Type::Compound(ty)
+    }
+
     pub fn make_struct(&mut self, name: &str, fields: &[Type], packed: bool) -> Type {
         let compound_data = CompoundTypeData::Struct(StructData {
             name: name.to_string(),
@@ -61,6 +71,19 @@ impl TypeStore {
         }
     }
 
+    /// Returns the type of the `idx`-th field of the `bytes` descriptor, i.e., `*i8` for
+    /// [`BYTES_PTR_FIELD`] and `i256` for [`BYTES_LEN_FIELD`].
+    ///
+    /// # Panics
+    /// Panics if `idx` is out of range.
+    pub fn bytes_field(&self, idx: usize) -> Type {
+        match idx {
+            BYTES_PTR_FIELD => Type::Compound(self.rev_types[&CompoundTypeData::Ptr(Type::I8)]),
+            BYTES_LEN_FIELD => Type::I256,
+            _ => panic!("`bytes` has no field {idx}"),
+        }
+    }
+
     pub fn struct_type_by_name(&self, name: &str) -> Option<Type> {
         self.struct_types.get(name).map(|ty| Type::Compound(*ty))
     }
@@ -105,6 +128,18 @@ impl TypeStore {
         }
     }
 
+    pub fn is_bytes(&self, ty: Type) -> bool {
+        match ty {
+            Type::Compound(compound) => self.compounds[compound].is_bytes(),
+            _ => false,
+        }
+    }
+
+    /// Returns the compound type of `data` if it's already interned.
+    pub fn lookup_compound(&self, data: &CompoundTypeData) -> Option<CompoundType> {
+        self.rev_types.get(data).copied()
+    }
+
     pub fn make_compound(&mut self, data: CompoundTypeData) -> CompoundType {
         if let Some(compound) = self.rev_types.get(&data) {
             *compound
@@ -118,10 +153,15 @@ impl TypeStore {
     pub fn resolve_compound(&self, compound: CompoundType) -> &CompoundTypeData {
         &self.compounds[compound]
     }
+
+    pub fn all_compounds(&self) -> impl Iterator<Item = (CompoundType, &CompoundTypeData)> {
+        self.compounds.iter()
+    }
 }
 
 /// Sonatina IR types definition.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     I1,
     I8,
@@ -137,6 +177,7 @@ pub enum Type {
 
 /// An opaque reference to [`CompoundTypeData`].
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompoundType(u32);
 cranelift_entity::entity_impl!(CompoundType);
 
@@ -166,6 +207,7 @@ impl<'a> fmt::Display for DisplayCompoundType<'a> {
                         write!(f, "{{{name}}}")
                     }
                 }
+                Bytes => write!(f, "bytes"),
             })
     }
 }
@@ -202,13 +244,25 @@ impl<'a> fmt::Display for DisplayType<'a> {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompoundTypeData {
-    Array { elem: Type, len: usize },
+    Array {
+        elem: Type,
+        len: usize,
+    },
+    /// A memory address. Address `0` is null, where no `alloca` or global variable is placed.
     Ptr(Type),
     Struct(StructData),
+    Bytes,
 }
 
+/// Index of the data pointer field of the `bytes` descriptor.
+pub const BYTES_PTR_FIELD: usize = 0;
+/// Index of the length field of the `bytes` descriptor.
+pub const BYTES_LEN_FIELD: usize = 1;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StructData {
     pub name: String,
     pub fields: Vec<Type>,
@@ -223,6 +277,10 @@ impl CompoundTypeData {
     pub fn is_ptr(&self) -> bool {
         matches!(self, Self::Ptr(_))
     }
+
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, Self::Bytes)
+    }
 }
 
 impl Type {
@@ -2,12 +2,17 @@
 
 use std::{fmt, ops};
 
-use crate::{types::DisplayType, DataFlowGraph, GlobalVariable};
+use crate::{
+    insn::{BinaryOp, OverflowOp},
+    types::DisplayType,
+    DataFlowGraph, GlobalVariable,
+};
 
 use super::{Insn, Type, I256, U256};
 
 /// An opaque reference to [`ValueData`].
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Value(pub u32);
 cranelift_entity::entity_impl!(Value);
 
@@ -25,12 +30,19 @@ impl<'a> DisplayResultValue<'a> {
 impl<'a> fmt::Display for DisplayResultValue<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let Self { insn, dfg } = *self;
-        if let Some(value) = dfg.insn_result(insn) {
-            let ty = dfg.insn_result_ty(insn).unwrap();
-            let ty = DisplayType::new(ty, dfg);
-            return write!(f, "v{}.{ty} = ", value.0);
+        let results = dfg.insn_results(insn);
+        if results.is_empty() {
+            return Ok(());
         }
-        Ok(())
+
+        for (i, value) in results.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let ty = DisplayType::new(dfg.value_ty(*value), dfg);
+            write!(f, "v{}.{ty}", value.0)?;
+        }
+        f.write_str(" = ")
     }
 }
 
@@ -74,6 +86,7 @@ pub fn display_arg_values(
 
 /// An value data definition.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ValueData {
     /// The value is defined by an instruction.
     Insn { insn: Insn, ty: Type },
@@ -89,6 +102,7 @@ pub enum ValueData {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Immediate {
     I1(bool),
     I8(i8),
@@ -112,8 +126,41 @@ impl Immediate {
         }
     }
 
+    /// Returns the wrapped result of `code` and whether the operation overflowed.
+    pub fn overflowing_op(self, code: OverflowOp, rhs: Self) -> (Self, bool) {
+        let lhs = self;
+        let res = match code.unchecked() {
+            BinaryOp::Add => lhs + rhs,
+            BinaryOp::Sub => lhs - rhs,
+            _ => lhs * rhs,
+        };
+
+        let overflow = match code {
+            OverflowOp::Uaddo => res.as_unsigned() < lhs.as_unsigned(),
+            OverflowOp::Usubo => lhs.as_unsigned() < rhs.as_unsigned(),
+            OverflowOp::Saddo => {
+                lhs.is_negative() == rhs.is_negative() && res.is_negative() != lhs.is_negative()
+            }
+            OverflowOp::Ssubo => {
+                lhs.is_negative() != rhs.is_negative() && res.is_negative() != lhs.is_negative()
+            }
+            // The product wrapped if dividing it by `lhs` doesn't give `rhs` back.
+            OverflowOp::Umulo => {
+                !lhs.is_zero() && res.as_unsigned() / lhs.as_unsigned() != rhs.as_unsigned()
+            }
+            // The division can't tell `-1 * MIN` from `MIN`, so it's checked separately.
+            OverflowOp::Smulo => {
+                !lhs.is_zero()
+                    && (res.sdiv(lhs) != rhs || (lhs.is_all_one() && rhs.is_signed_min()))
+            }
+        };
+        (res, overflow)
+    }
+
     pub fn udiv(self, rhs: Self) -> Self {
-        self.apply_binop(rhs, |lhs, rhs| (lhs.to_u256() / rhs.to_u256()).into())
+        debug_assert_eq!(self.ty(), rhs.ty());
+        let quot = self.as_unsigned() / rhs.as_unsigned();
+        Self::from_i256(I256::from_u256(quot), self.ty())
     }
 
     pub fn sdiv(self, rhs: Self) -> Self {
@@ -152,6 +199,69 @@ impl Immediate {
         self.apply_binop_raw(rhs, |lhs, rhs| (lhs >= rhs).into())
     }
 
+    /// Arithmetic shift right. Vacated bits are filled with the sign bit.
+    pub fn sar(self, rhs: Self) -> Self {
+        let ty = self.ty();
+        if !self.is_negative() {
+            return self >> rhs;
+        }
+
+        match self.shift_amount(rhs) {
+            Some(_) => (self >> rhs) | !(Self::all_one(ty) >> rhs),
+            None => Self::all_one(ty),
+        }
+    }
+
+    /// Rotate left. The rotate amount is taken modulo the bit width of the type.
+    pub fn rotl(self, rhs: Self) -> Self {
+        self.rotate_left(self.rotate_amount(rhs))
+    }
+
+    /// Rotate right. The rotate amount is taken modulo the bit width of the type.
+    pub fn rotr(self, rhs: Self) -> Self {
+        let bits = self.bit_width();
+        self.rotate_left((bits - self.rotate_amount(rhs)) % bits)
+    }
+
+    /// Counts the leading zero bits in the width of the type.
+    pub fn clz(self) -> Self {
+        let leading_zeros = self.as_unsigned().leading_zeros() as usize - (256 - self.bit_width());
+        self.count(leading_zeros)
+    }
+
+    /// Counts the trailing zero bits. Zero has as many as the width of the type.
+    pub fn ctz(self) -> Self {
+        let trailing_zeros = (self.as_unsigned().trailing_zeros() as usize).min(self.bit_width());
+        self.count(trailing_zeros)
+    }
+
+    pub fn popcount(self) -> Self {
+        let ones = self
+            .as_unsigned()
+            .0
+            .iter()
+            .map(|word| word.count_ones())
+            .sum::<u32>();
+        self.count(ones as usize)
+    }
+
+    /// Reverses the order of the bytes. `I1` has no bytes to reverse and is returned as is.
+    pub fn bswap(self) -> Self {
+        let val = self.as_unsigned();
+        let swapped = (0..self.bit_width() / 8).fold(U256::zero(), |acc, idx| {
+            (acc << 8) | ((val >> (idx * 8)) & U256::from(0xff))
+        });
+        match self {
+            Self::I1(_) => self,
+            _ => Self::from_i256(I256::from_u256(swapped), self.ty()),
+        }
+    }
+
+    /// Returns `num` as an immediate of the type of `self`.
+    fn count(self, num: usize) -> Self {
+        Self::from_i256(I256::from_u256(U256::from(num)), self.ty())
+    }
+
     pub fn sext(self, ty: Type) -> Self {
         debug_assert!(self.ty() < ty);
         Self::from_i256(self.as_i256(), ty)
@@ -224,6 +334,11 @@ impl Immediate {
         self == Self::all_one(self.ty())
     }
 
+    /// Returns `true` if the immediate is the minimum signed value of its type.
+    pub fn is_signed_min(self) -> bool {
+        self.as_unsigned() == U256::one() << (self.bit_width() - 1)
+    }
+
     pub fn is_two(self) -> bool {
         self.apply_unop_raw(|val| val == I256::one().overflowing_add(I256::one()).0)
     }
@@ -262,6 +377,53 @@ impl Immediate {
         }
     }
 
+    /// Returns the bit pattern of the immediate zero-extended to 256 bits.
+    pub fn as_unsigned(self) -> U256 {
+        let bits = self.bit_width();
+        let val = self.as_i256().to_u256();
+        if bits == 256 {
+            val
+        } else {
+            val & ((U256::one() << bits) - U256::one())
+        }
+    }
+
+    /// Returns the shift amount specified by `rhs`, or `None` if it's out of the bit width of
+    /// `self`.
+    fn shift_amount(self, rhs: Self) -> Option<usize> {
+        let amount = rhs.as_unsigned();
+        if amount >= U256::from(self.bit_width() as u64) {
+            None
+        } else {
+            Some(amount.as_usize())
+        }
+    }
+
+    fn rotate_amount(self, rhs: Self) -> usize {
+        (rhs.as_unsigned() % U256::from(self.bit_width())).as_usize()
+    }
+
+    fn rotate_left(self, amount: usize) -> Self {
+        if amount == 0 {
+            return self;
+        }
+        let val = self.as_unsigned();
+        let rotated = (val << amount) | (val >> (self.bit_width() - amount));
+        Self::from_i256(I256::from_u256(rotated), self.ty())
+    }
+
+    pub fn bit_width(self) -> usize {
+        match self {
+            Self::I1(..) => 1,
+            Self::I8(..) => 8,
+            Self::I16(..) => 16,
+            Self::I32(..) => 32,
+            Self::I64(..) => 64,
+            Self::I128(..) => 128,
+            Self::I256(..) => 256,
+        }
+    }
+
     fn apply_binop<F>(self, rhs: Self, f: F) -> Self
     where
         F: FnOnce(I256, I256) -> I256,
@@ -347,6 +509,34 @@ impl ops::BitOr for Immediate {
     }
 }
 
+/// Shift left. Shifting by the bit width of the type or more yields zero.
+impl ops::Shl for Immediate {
+    type Output = Self;
+
+    fn shl(self, rhs: Self) -> Self {
+        match self.shift_amount(rhs) {
+            Some(amount) => {
+                Self::from_i256(I256::from_u256(self.as_unsigned() << amount), self.ty())
+            }
+            None => Self::zero(self.ty()),
+        }
+    }
+}
+
+/// Logical shift right. Shifting by the bit width of the type or more yields zero.
+impl ops::Shr for Immediate {
+    type Output = Self;
+
+    fn shr(self, rhs: Self) -> Self {
+        match self.shift_amount(rhs) {
+            Some(amount) => {
+                Self::from_i256(I256::from_u256(self.as_unsigned() >> amount), self.ty())
+            }
+            None => Self::zero(self.ty()),
+        }
+    }
+}
+
 impl ops::Not for Immediate {
     type Output = Self;
 
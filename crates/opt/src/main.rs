@@ -0,0 +1,331 @@
+//! `sonatina-opt` reads a module in the textual IR format, runs a pass pipeline on it, and prints
+//! the resulting module.
+//!
+//! ```text
+//! sonatina-opt input.sntn --passes inline,sccp,adce --emit dot -o out.dot
+//! ```
+//!
+//! With `--emit html`, a page per function is written to the directory given by `-o`, where values
+//! link to their definitions and uses and the CFG is drawn next to the listing.
+//!
+//! With `--dot-overlays domtree,loops`, the dominator tree and the loops are drawn over the CFGs
+//! emitted as dot.
+//!
+//! With `--dump-cfg-after sccp,adce`, the CFG of each function is written as a dot file to the
+//! `--dump-dir` after each run of the given passes, or of every pass with `all`.
+//!
+//! With `--stats`, the time each pass took and what it changed are printed to stderr. With
+//! `--opt-bisect-limit N`, only the first `N` pass invocations run, and every invocation is printed
+//! to stderr so that a miscompilation can be bisected to a single pass invocation.
+
+use std::{
+    fs,
+    io::{self, Write},
+    num::NonZeroUsize,
+    path::Path,
+    process,
+};
+
+use sonatina_codegen::{
+    domtree::DomTree,
+    graphviz,
+    loop_analysis::LoopTree,
+    pass_manager::{Pass, PassManager},
+};
+use sonatina_ir::{
+    graphviz::RenderOptions, ir_writer::ModuleWriter, ControlFlowGraph, Function, Module,
+};
+
+const USAGE: &str = "\
+Usage: sonatina-opt [OPTIONS] <INPUT>
+
+Options:
+  -p, --passes <PASSES>  Comma separated list of passes to run [default: the default pipeline]
+  -j, --jobs <N>         Number of threads function passes run on [default: 1]
+      --emit <KIND>      Output kind, `ir`, `dot` or `html` [default: ir]
+      --dot-overlays <OVERLAYS>
+                         Comma separated list of `domtree` and `loops` to draw over dot output
+      --stats <FORMAT>   Print pass statistics to stderr, as a `table` or `json`
+      --opt-bisect-limit <N>
+                         Run only the first N pass invocations
+      --dump-cfg-after <PASSES>
+                         Dump CFGs as dot files after each run of the passes, or `all`
+      --dump-dir <DIR>   Directory CFGs are dumped to [default: cfg-dump]
+  -o, --output <FILE>    Write the output to FILE instead of stdout, or to the directory FILE
+                         with `--emit html`
+      --list-passes      Print the available passes
+  -h, --help             Print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Ir,
+    Dot,
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct DotOverlays {
+    domtree: bool,
+    loops: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatsFormat {
+    Table,
+    Json,
+}
+
+struct Args {
+    input: String,
+    passes: Option<String>,
+    jobs: NonZeroUsize,
+    emit: Emit,
+    dot_overlays: DotOverlays,
+    stats: Option<StatsFormat>,
+    bisect_limit: Option<usize>,
+    dump_cfg_after: Vec<Pass>,
+    dump_dir: String,
+    output: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut input = None;
+        let mut passes = None;
+        let mut jobs = NonZeroUsize::MIN;
+        let mut emit = Emit::Ir;
+        let mut dot_overlays = DotOverlays::default();
+        let mut stats = None;
+        let mut bisect_limit = None;
+        let mut dump_cfg_after = Vec::new();
+        let mut dump_dir = "cfg-dump".to_string();
+        let mut output = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            // A long option takes its value either as the next argument or after `=`.
+            let (arg, mut inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = |name: &str| {
+                inline_value
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("missing value for `{name}`"))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                }
+                "--list-passes" => {
+                    for pass in Pass::ALL {
+                        println!("{pass}");
+                    }
+                    process::exit(0);
+                }
+                "-p" | "--passes" => passes = Some(value(&arg)?),
+                "-j" | "--jobs" => {
+                    let n = value(&arg)?;
+                    jobs = n
+                        .parse()
+                        .map_err(|_| format!("invalid number of jobs `{n}`"))?;
+                }
+                "-o" | "--output" => output = Some(value(&arg)?),
+                "--emit" => {
+                    emit = match value(&arg)?.as_str() {
+                        "ir" => Emit::Ir,
+                        "dot" => Emit::Dot,
+                        "html" => Emit::Html,
+                        kind => return Err(format!("unknown output kind `{kind}`")),
+                    }
+                }
+                "--dot-overlays" => {
+                    for overlay in value(&arg)?.split(',').map(str::trim) {
+                        match overlay {
+                            "domtree" => dot_overlays.domtree = true,
+                            "loops" => dot_overlays.loops = true,
+                            _ => return Err(format!("unknown dot overlay `{overlay}`")),
+                        }
+                    }
+                }
+                "--stats" => {
+                    stats = match value(&arg)?.as_str() {
+                        "table" => Some(StatsFormat::Table),
+                        "json" => Some(StatsFormat::Json),
+                        format => return Err(format!("unknown stats format `{format}`")),
+                    }
+                }
+                "--opt-bisect-limit" => {
+                    let limit = value(&arg)?;
+                    bisect_limit = Some(
+                        limit
+                            .parse()
+                            .map_err(|_| format!("invalid bisect limit `{limit}`"))?,
+                    );
+                }
+                "--dump-cfg-after" => {
+                    let passes = value(&arg)?;
+                    dump_cfg_after = if passes == "all" {
+                        Pass::ALL.to_vec()
+                    } else {
+                        passes
+                            .split(',')
+                            .map(|pass| pass.trim().parse().map_err(|err| format!("{err}")))
+                            .collect::<Result<_, _>>()?
+                    };
+                }
+                "--dump-dir" => dump_dir = value(&arg)?,
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ if input.is_none() => input = Some(arg),
+                _ => return Err(format!("unexpected argument `{arg}`")),
+            }
+        }
+
+        if emit == Emit::Html && output.is_none() {
+            return Err("`--emit html` requires an output directory".to_string());
+        }
+
+        Ok(Self {
+            input: input.ok_or("no input file is given")?,
+            passes,
+            jobs,
+            emit,
+            dot_overlays,
+            stats,
+            bisect_limit,
+            dump_cfg_after,
+            dump_dir,
+            output,
+        })
+    }
+}
+
+fn main() {
+    let args = Args::parse().unwrap_or_else(|err| {
+        eprintln!("error: {err}\n\n{USAGE}");
+        process::exit(2);
+    });
+
+    if let Err(err) = run(&args) {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let mut pass_manager = match &args.passes {
+        Some(passes) => PassManager::parse_pipeline(passes).map_err(|err| err.to_string())?,
+        None => PassManager::default_pipeline(),
+    };
+    pass_manager.set_threads(args.jobs);
+
+    let input = fs::read_to_string(&args.input)
+        .map_err(|err| format!("failed to read `{}`: {err}", args.input))?;
+    let mut parsed = match sonatina_parser::parse_module(&input) {
+        Ok(parsed) => parsed,
+        Err(errs) => {
+            for err in &errs {
+                eprintln!("{}", err.print_to_string(&args.input, &input, true));
+            }
+            return Err(format!("failed to parse `{}`", args.input));
+        }
+    };
+
+    if args.stats.is_some() {
+        pass_manager.enable_stats();
+    }
+    if let Some(limit) = args.bisect_limit {
+        pass_manager.set_bisect_limit(limit);
+    }
+    if !args.dump_cfg_after.is_empty() {
+        pass_manager.dump_cfg_after(&args.dump_cfg_after, &args.dump_dir);
+    }
+    pass_manager.run(&mut parsed.module);
+    for invocation in pass_manager.invocations() {
+        eprintln!("{invocation}");
+    }
+    if let (Some(format), Some(stats)) = (args.stats, pass_manager.stats()) {
+        let mut stderr = io::stderr();
+        match format {
+            StatsFormat::Table => stats.write_table(&mut stderr),
+            StatsFormat::Json => stats.write_json(&mut stderr).and_then(|_| writeln!(stderr)),
+        }
+        .map_err(|err| err.to_string())?;
+    }
+
+    if args.emit == Emit::Html {
+        return write_html(&parsed.module, args.output.as_deref().unwrap());
+    }
+
+    let mut buf = Vec::new();
+    match args.emit {
+        // Value names are debug info, so they are dropped along with the rest of it.
+        Emit::Ir if pass_manager.passes().contains(&Pass::Strip) => {
+            ModuleWriter::new(&parsed.module).write(&mut buf)
+        }
+        Emit::Ir => {
+            ModuleWriter::with_debug_provider(&parsed.module, &parsed.debug).write(&mut buf)
+        }
+        Emit::Html => unreachable!(),
+        Emit::Dot => parsed
+            .module
+            .iter_functions()
+            .filter(|func_ref| !parsed.module.is_external(*func_ref))
+            .try_for_each(|func_ref| {
+                let func = &parsed.module.funcs[func_ref];
+                let options = render_options(func, args.dot_overlays);
+                sonatina_ir::render_with(func, &options, &mut buf)
+            }),
+    }
+    .map_err(|err| err.to_string())?;
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, buf).map_err(|err| format!("failed to write `{path}`: {err}"))
+        }
+        None => io::stdout().write_all(&buf).map_err(|err| err.to_string()),
+    }
+}
+
+/// Writes a page per function with a body to `dir/<name>.html`.
+fn write_html(module: &Module, dir: &str) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|err| format!("failed to create `{dir}`: {err}"))?;
+    for func_ref in module.iter_functions() {
+        if module.is_external(func_ref) {
+            continue;
+        }
+        let func = &module.funcs[func_ref];
+        let path = Path::new(dir).join(format!("{}.html", func.sig.name()));
+        let mut buf = Vec::new();
+        sonatina_ir::render_html(func, &mut buf).map_err(|err| err.to_string())?;
+        fs::write(&path, buf)
+            .map_err(|err| format!("failed to write `{}`: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn render_options(func: &Function, overlays: DotOverlays) -> RenderOptions {
+    let mut options = RenderOptions::new();
+    if overlays == DotOverlays::default() {
+        return options;
+    }
+
+    let mut cfg = ControlFlowGraph::new();
+    let mut domtree = DomTree::new();
+    cfg.compute(func);
+    domtree.compute(&cfg);
+    if overlays.domtree {
+        graphviz::add_domtree(&mut options, &domtree);
+    }
+    if overlays.loops {
+        let mut lpt = LoopTree::new();
+        lpt.compute(&cfg, &domtree);
+        graphviz::add_loops(&mut options, func, &lpt);
+    }
+    options
+}
@@ -6,14 +6,15 @@ use crate::{
 use either::Either;
 use hex::FromHex;
 pub use ir::{
-    insn::{BinaryOp, CastOp, UnaryOp},
-    DataLocationKind, Immediate, Linkage,
+    insn::{BinaryOp, CastOp, MemOp, OverflowOp, TrapReason, UnaryOp},
+    module::WidthPolicy,
+    CallConv, DataLocationKind, Immediate, Linkage,
 };
 use ir::{I256, U256};
 use pest::Parser as _;
 use smol_str::SmolStr;
 pub use sonatina_triple::{InvalidTriple, TargetTriple};
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 // `Span`s aren't printed in the Debug output because the pest
 // code locations differ on windows vs *nix, which breaks the ast tests.
@@ -41,7 +42,10 @@ pub fn parse(input: &str) -> Result<Module, Vec<Error>> {
 #[derive(Debug)]
 pub struct Module {
     pub target: Option<TargetTriple>,
+    pub width_policy: WidthPolicy,
+    pub globals: Vec<Global>,
     pub declared_functions: Vec<FuncDeclaration>,
+    pub declared_intrinsics: Vec<IntrinsicDeclaration>,
     pub struct_types: Vec<Struct>,
     pub functions: Vec<Func>,
     pub comments: Vec<String>,
@@ -50,6 +54,7 @@ pub struct Module {
 impl FromSyntax<Error> for Module {
     fn from_syntax(node: &mut Node<Error>) -> Self {
         let target = node.single(Rule::target_triple);
+        let width_policy = node.parse_str_opt(Rule::width_policy).unwrap_or_default();
 
         let module_comments = node.map_while(|p| {
             if p.as_rule() == Rule::COMMENT && p.as_str().starts_with("#!") {
@@ -60,7 +65,9 @@ impl FromSyntax<Error> for Module {
         });
 
         let mut struct_types = vec![];
+        let mut globals = vec![];
         let mut declared_functions = vec![];
+        let mut declared_intrinsics = vec![];
         let mut functions = vec![];
 
         loop {
@@ -74,8 +81,12 @@ impl FromSyntax<Error> for Module {
 
             if let Some(struct_) = node.single_opt(Rule::struct_declaration) {
                 struct_types.push(struct_);
+            } else if let Some(global) = node.single_opt(Rule::gv_declaration) {
+                globals.push(global);
             } else if let Some(func) = node.single_opt(Rule::function_declaration) {
                 declared_functions.push(func);
+            } else if let Some(intrinsic) = node.single_opt(Rule::intrinsic_declaration) {
+                declared_intrinsics.push(intrinsic);
             } else {
                 match node.single_opt::<Func>(Rule::function) {
                     Some(mut func) => {
@@ -88,7 +99,10 @@ impl FromSyntax<Error> for Module {
         }
         Module {
             target,
+            width_policy,
+            globals,
             declared_functions,
+            declared_intrinsics,
             struct_types,
             functions,
             comments: module_comments,
@@ -117,9 +131,11 @@ impl FromSyntax<Error> for SmolStr {
 #[derive(Debug)]
 pub struct FuncDeclaration {
     pub linkage: Linkage,
+    pub call_conv: Option<CallConv>,
     pub name: FunctionName,
     pub params: Vec<Type>,
     pub ret_type: Option<Type>,
+    pub attrs: Vec<SmolStr>,
 }
 
 impl FromSyntax<Error> for FuncDeclaration {
@@ -130,16 +146,106 @@ impl FromSyntax<Error> for FuncDeclaration {
 
         FuncDeclaration {
             linkage,
+            call_conv: node.parse_str_opt(Rule::function_call_conv),
             name: node.single(Rule::function_identifier),
             params: node.descend_into(Rule::function_param_type_list, |n| n.multi(Rule::type_name)),
             ret_type: node.descend_into_opt(Rule::function_ret_type, |n| n.single(Rule::type_name)),
+            attrs: node.multi(Rule::function_attr),
+        }
+    }
+}
+
+/// A declaration of an intrinsic, e.g., `declare intrinsic %number() -> i256 pure;`.
+#[derive(Debug)]
+pub struct IntrinsicDeclaration {
+    pub name: Spanned<FunctionName>,
+    pub params: Vec<Type>,
+    pub ret_type: Option<Type>,
+    pub is_pure: bool,
+}
+
+impl FromSyntax<Error> for IntrinsicDeclaration {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        IntrinsicDeclaration {
+            name: node.single(Rule::function_identifier),
+            params: node.descend_into(Rule::function_param_type_list, |n| n.multi(Rule::type_name)),
+            ret_type: node.descend_into_opt(Rule::function_ret_type, |n| n.single(Rule::type_name)),
+            is_pure: node.get_opt(Rule::intrinsic_pure).is_some(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Global {
+    pub linkage: Linkage,
+    pub is_const: bool,
+    pub name: Spanned<GlobalName>,
+    pub ty: Type,
+    pub init: Option<GlobalInit>,
+}
+
+impl FromSyntax<Error> for Global {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        let linkage = node
+            .parse_str_opt(Rule::gv_linkage)
+            .unwrap_or(Linkage::Private);
+
+        Global {
+            linkage,
+            is_const: node.get_opt(Rule::gv_const).is_some(),
+            name: node.single(Rule::global_identifier),
+            ty: node.single(Rule::type_name),
+            init: node.single_opt(Rule::gv_initializer),
+        }
+    }
+}
+
+/// Doesn't include `%` prefix.
+#[derive(Debug)]
+pub struct GlobalName(pub SmolStr);
+
+impl FromSyntax<Error> for GlobalName {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        GlobalName(node.parse_str(Rule::global_name))
+    }
+}
+
+/// The initial value of a global variable.
+#[derive(Dbg)]
+pub struct GlobalInit {
+    pub kind: GlobalInitKind,
+    #[debug(skip)]
+    pub span: Span,
+}
+
+/// Numbers are typed by the type of the global variable, so they are kept as text until the type
+/// is resolved.
+#[derive(Debug)]
+pub enum GlobalInitKind {
+    Number(SmolStr),
+    Array(Vec<GlobalInit>),
+    Struct(Vec<GlobalInit>),
+}
+
+impl FromSyntax<Error> for GlobalInit {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        node.descend();
+        let kind = match node.rule {
+            Rule::gv_number => GlobalInitKind::Number(node.txt.into()),
+            Rule::gv_array => GlobalInitKind::Array(node.multi(Rule::gv_initializer)),
+            Rule::gv_struct => GlobalInitKind::Struct(node.multi(Rule::gv_initializer)),
+            _ => unreachable!(),
+        };
+        GlobalInit {
+            kind,
+            span: node.span,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Struct {
-    pub name: StructName,
+    pub name: Spanned<StructName>,
     pub fields: Vec<Type>,
     pub packed: bool,
 }
@@ -180,8 +286,13 @@ pub struct Func {
 
 impl FromSyntax<Error> for Func {
     fn from_syntax(node: &mut Node<Error>) -> Self {
+        // The attrs follow the signature in the grammar, so that it doesn't span the whitespace
+        // before them when there are none.
+        let mut signature: FuncSignature = node.single(Rule::function_signature);
+        signature.attrs = node.multi(Rule::function_attr);
+
         Func {
-            signature: node.single(Rule::function_signature),
+            signature,
             blocks: node.multi(Rule::block),
             comments: vec![],
         }
@@ -191,9 +302,11 @@ impl FromSyntax<Error> for Func {
 #[derive(Debug)]
 pub struct FuncSignature {
     pub linkage: Linkage,
+    pub call_conv: Option<CallConv>,
     pub name: FunctionName,
     pub params: Vec<ValueDeclaration>,
     pub ret_type: Option<Type>,
+    pub attrs: Vec<SmolStr>,
 }
 
 impl FromSyntax<Error> for FuncSignature {
@@ -204,9 +317,11 @@ impl FromSyntax<Error> for FuncSignature {
 
         FuncSignature {
             linkage,
+            call_conv: node.parse_str_opt(Rule::function_call_conv),
             name: node.single(Rule::function_identifier),
             params: node.descend_into(Rule::function_params, |n| n.multi(Rule::value_declaration)),
             ret_type: node.descend_into_opt(Rule::function_ret_type, |n| n.single(Rule::type_name)),
+            attrs: vec![],
         }
     }
 }
@@ -224,6 +339,8 @@ impl FromSyntax<Error> for FunctionName {
 #[derive(Debug)]
 pub struct Block {
     pub id: BlockId,
+    /// The params of the block, e.g., `block1(v1.i64):`, which are lowered to phis.
+    pub params: Vec<ValueDeclaration>,
     pub stmts: Vec<Stmt>,
 }
 
@@ -236,6 +353,9 @@ impl FromSyntax<Error> for Block {
     fn from_syntax(node: &mut Node<Error>) -> Self {
         Self {
             id: node.single(Rule::block_ident),
+            params: node
+                .descend_into_opt(Rule::block_params, |n| n.multi(Rule::value_declaration))
+                .unwrap_or_default(),
             stmts: node.multi(Rule::stmt),
         }
     }
@@ -261,6 +381,44 @@ impl FromSyntax<Error> for BlockId {
     }
 }
 
+/// A destination of a branch, with the args it passes to the params of the block, e.g.,
+/// `block1(v2, 1.i64)`.
+pub struct Dest {
+    pub block: BlockId,
+    pub args: Vec<Value>,
+    pub span: Span,
+}
+
+impl Dest {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        Self::from_syntax_opt(node).unwrap()
+    }
+
+    /// Takes a block ident and the block args that immediately follow it.
+    fn from_syntax_opt(node: &mut Node<Error>) -> Option<Self> {
+        let block: BlockId = node.single_opt(Rule::block_ident)?;
+        let (args, end) = node
+            .descend_into_next_opt(Rule::block_args, |n| (n.multi(Rule::value), n.span.1))
+            .unwrap_or((vec![], block.span.1));
+        let span = Span(block.span.0, end);
+        Some(Self { block, args, span })
+    }
+}
+
+// A dest without args is written like a plain `BlockId`.
+impl fmt::Debug for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.args.is_empty() {
+            fmt::Debug::fmt(&self.block, f)
+        } else {
+            f.debug_struct("Dest")
+                .field("block", &self.block)
+                .field("args", &self.args)
+                .finish()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Stmt {
     pub kind: StmtKind,
@@ -270,27 +428,52 @@ impl FromSyntax<Error> for Stmt {
     fn from_syntax(node: &mut Node<Error>) -> Self {
         node.descend();
         let kind = match node.rule {
-            Rule::define_stmt => StmtKind::Define(
-                node.single(Rule::value_declaration),
-                node.single(Rule::expr),
-            ),
+            Rule::define_stmt => {
+                StmtKind::Define(node.multi(Rule::value_declaration), node.single(Rule::expr))
+            }
             Rule::store_stmt => StmtKind::Store(
                 node.parse_str(Rule::location),
                 node.single(Rule::value),
                 node.single(Rule::value),
             ),
+            Rule::mem_stmt => StmtKind::Mem(
+                node.parse_str(Rule::mem_op),
+                node.parse_str(Rule::location),
+                [
+                    node.single(Rule::value),
+                    node.single(Rule::value),
+                    node.single(Rule::value),
+                ],
+            ),
             Rule::return_stmt => StmtKind::Return(node.single_opt(Rule::value)),
-            Rule::jump_stmt => StmtKind::Jump(node.single(Rule::block_ident)),
+            Rule::revert_stmt => {
+                let selector = node.get(Rule::error_selector);
+                StmtKind::Revert(
+                    <[u8; 4]>::from_hex(&selector.as_str()[2..]).unwrap(),
+                    node.multi(Rule::value),
+                )
+            }
+            Rule::trap_stmt => StmtKind::Trap(node.parse_str(Rule::trap_reason)),
+            Rule::unreachable_stmt => StmtKind::Unreachable,
+            Rule::jump_stmt => StmtKind::Jump(Dest::from_syntax(node)),
             Rule::br_stmt => StmtKind::Branch(
                 node.single(Rule::value),
-                node.single(Rule::block_ident),
-                node.single(Rule::block_ident),
+                Dest::from_syntax(node),
+                Dest::from_syntax(node),
             ),
             Rule::br_table_stmt => StmtKind::BranchTable(
                 node.single(Rule::value),
-                node.single_opt(Rule::block_ident),
+                Dest::from_syntax_opt(node),
                 node.multi(Rule::br_table_case),
             ),
+            Rule::call_stmt => {
+                node.descend();
+                StmtKind::Call(Call::from_syntax(node))
+            }
+            Rule::intrinsic_stmt => {
+                node.descend();
+                StmtKind::Intrinsic(IntrinsicCall::from_syntax(node))
+            }
             _ => unreachable!(),
         };
         Stmt { kind }
@@ -299,13 +482,21 @@ impl FromSyntax<Error> for Stmt {
 
 #[derive(Debug)]
 pub enum StmtKind {
-    Define(ValueDeclaration, Expr),
+    /// Defines the results of an insn, e.g., `v1.i32, v2.i1 = uaddo v0 v0`.
+    Define(Vec<ValueDeclaration>, Expr),
     Store(DataLocationKind, Value, Value),
+    /// A memory intrinsic, e.g., `memcpy @memory v0 v1 32.i256`.
+    Mem(MemOp, DataLocationKind, [Value; 3]),
     Return(Option<Value>),
-    Jump(BlockId),
-    Branch(Value, BlockId, BlockId),
-    BranchTable(Value, Option<BlockId>, Vec<(Value, BlockId)>),
+    /// A revert with an error selector and args, e.g., `revert 0x08c379a0 v0`.
+    Revert([u8; 4], Vec<Value>),
+    Trap(TrapReason),
+    Unreachable,
+    Jump(Dest),
+    Branch(Value, Dest, Dest),
+    BranchTable(Value, Option<Dest>, Vec<(Value, Dest)>),
     Call(Call),
+    Intrinsic(IntrinsicCall),
 }
 
 impl FromSyntax<Error> for (Value, BlockId) {
@@ -314,6 +505,12 @@ impl FromSyntax<Error> for (Value, BlockId) {
     }
 }
 
+impl FromSyntax<Error> for (Value, Dest) {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        (node.single(Rule::value), Dest::from_syntax(node))
+    }
+}
+
 #[derive(Dbg)]
 pub struct Type {
     pub kind: TypeKind,
@@ -327,6 +524,9 @@ pub enum TypeKind {
     Ptr(Box<Type>),
     Array(Box<Type>, usize),
     Struct(SmolStr),
+    Bytes,
+    /// The native word of the target, resolved to an integer type when the module is built.
+    Word,
     Void,
     Error,
 }
@@ -347,7 +547,9 @@ impl FromSyntax<Error> for Type {
                 };
                 TypeKind::Array(Box::new(node.single(Rule::type_name)), size)
             }
+            Rule::word_type => TypeKind::Word,
             Rule::void_type => TypeKind::Void,
+            Rule::bytes_type => TypeKind::Bytes,
             Rule::struct_identifier => TypeKind::Struct(node.parse_str(Rule::struct_name)),
             _ => unreachable!(),
         };
@@ -386,11 +588,13 @@ impl From<IntType> for ir::Type {
 #[derive(Debug)]
 pub enum Expr {
     Binary(BinaryOp, Value, Value),
+    OverflowBinary(OverflowOp, Value, Value),
     Unary(UnaryOp, Value),
     Cast(CastOp, Value),
     Load(DataLocationKind, Value),
     Alloca(Type),
     Call(Call),
+    Intrinsic(IntrinsicCall),
     Gep(Vec<Value>),
     Phi(Vec<(Value, BlockId)>),
 }
@@ -404,12 +608,15 @@ impl FromSyntax<Error> for Expr {
                 node.single(Rule::value),
                 node.single(Rule::value),
             ),
+            Rule::ovf_expr => Expr::OverflowBinary(
+                node.parse_str(Rule::ovf_op),
+                node.single(Rule::value),
+                node.single(Rule::value),
+            ),
             Rule::una_expr => Expr::Unary(node.parse_str(Rule::una_op), node.single(Rule::value)),
             Rule::alloca_expr => Expr::Alloca(node.single(Rule::type_name)),
-            Rule::call_expr => Expr::Call(Call(
-                node.single(Rule::function_identifier),
-                node.multi(Rule::value),
-            )),
+            Rule::call_expr => Expr::Call(Call::from_syntax(node)),
+            Rule::intrinsic_expr => Expr::Intrinsic(IntrinsicCall::from_syntax(node)),
             Rule::cast_expr => Expr::Cast(node.parse_str(Rule::cast_op), node.single(Rule::value)),
 
             Rule::gep_expr => Expr::Gep(node.multi(Rule::value)),
@@ -420,8 +627,33 @@ impl FromSyntax<Error> for Expr {
     }
 }
 
+/// A call to a function. The last field is `true` for a call marked with `tail`.
 #[derive(Debug)]
-pub struct Call(pub Spanned<FunctionName>, pub Vec<Value>);
+pub struct Call(pub Spanned<FunctionName>, pub Vec<Value>, pub bool);
+
+impl FromSyntax<Error> for Call {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        let is_tail = node.get_opt(Rule::tail_marker).is_some();
+        Call(
+            node.single(Rule::function_identifier),
+            node.multi(Rule::value),
+            is_tail,
+        )
+    }
+}
+
+/// A call to an intrinsic, e.g., `intrinsic %balance v0`.
+#[derive(Debug)]
+pub struct IntrinsicCall(pub Spanned<FunctionName>, pub Vec<Value>);
+
+impl FromSyntax<Error> for IntrinsicCall {
+    fn from_syntax(node: &mut Node<Error>) -> Self {
+        IntrinsicCall(
+            node.single(Rule::function_identifier),
+            node.multi(Rule::value),
+        )
+    }
+}
 
 #[derive(Dbg)]
 pub struct ValueName {
@@ -459,6 +691,8 @@ pub struct Value {
 pub enum ValueKind {
     Immediate(Immediate),
     Named(ValueName),
+    /// The address of a global variable. Doesn't include `%` prefix.
+    Global(SmolStr),
     Error,
 }
 
@@ -493,6 +727,7 @@ impl FromSyntax<Error> for Value {
         node.descend();
         let kind = match node.rule {
             Rule::value_name => ValueKind::Named(ValueName::from_syntax(node)),
+            Rule::global_identifier => ValueKind::Global(node.parse_str(Rule::global_name)),
             Rule::imm_number => {
                 let ty: IntType = node.parse_str(Rule::primitive_type);
                 node.descend();
@@ -2,6 +2,7 @@ use std::io;
 
 use crate::{syntax::Rule, Span};
 use annotate_snippets::{Level, Renderer, Snippet};
+use pest::error::{ErrorVariant, InputLocation};
 use smol_str::SmolStr;
 use sonatina_triple::InvalidTriple;
 
@@ -12,18 +13,94 @@ pub enum Error {
     InvalidTarget(InvalidTriple, Span),
     SyntaxError(pest::error::Error<Rule>),
     Undefined(UndefinedKind, Span),
-    DuplicateValueName(SmolStr, Span),
+    DuplicateValueName {
+        name: SmolStr,
+        span: Span,
+        /// The span of the first definition.
+        first: Span,
+    },
+    DuplicateGlobalName {
+        name: SmolStr,
+        span: Span,
+        first: Span,
+    },
+    DuplicateStructName {
+        name: SmolStr,
+        span: Span,
+        first: Span,
+    },
+    /// The initializer of a global variable doesn't have the shape of its type, e.g., an array
+    /// initializes a struct.
+    InvalidInitializer(Span),
     TypeMismatch {
         specified: SmolStr,
         inferred: SmolStr,
         span: Span,
     },
+    /// The number of values defined by a stmt differs from the number of results of its insn.
+    ResultCountMismatch {
+        declared: usize,
+        results: usize,
+        span: Span,
+    },
+    /// A branch passes a different number of args to a block than the block has params.
+    BlockArgCountMismatch {
+        block: ir::Block,
+        params: usize,
+        args: usize,
+        span: Span,
+    },
+    /// Operands of a binary insn have different widths in a module with the strict width policy.
+    WidthMismatch {
+        lhs: SmolStr,
+        rhs: SmolStr,
+        span: Span,
+    },
+    /// A declared intrinsic is already registered with the target with another signature.
+    IntrinsicMismatch {
+        name: SmolStr,
+        span: Span,
+    },
+}
+
+/// The structured form of an [`Error`], for tools that render diagnostics themselves, e.g., with
+/// `ariadne` or `codespan-reporting`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// The span the error is reported at.
+    pub span: Span,
+    /// What was expected at `span`, e.g., the tokens a syntax error allows or the declared type of
+    /// a value.
+    pub expected: Vec<String>,
+    /// What was found at `span` instead, if it's not the text of the span itself.
+    pub found: Option<String>,
+    /// Other places in the input the error refers to, e.g., the first definition of a duplicate
+    /// name.
+    pub labels: Vec<Label>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum UndefinedKind {
     Block(ir::Block),
     Func(SmolStr),
+    Intrinsic(SmolStr),
+    Global(SmolStr),
     Type(SmolStr),
     Value(SmolStr),
 }
@@ -35,54 +112,152 @@ impl Error {
             Error::InvalidTarget(_, span) => *span,
             Error::Undefined(_, span) => *span,
 
-            Error::DuplicateValueName(_, span) => *span,
+            Error::DuplicateValueName { span, .. } => *span,
+            Error::DuplicateGlobalName { span, .. } => *span,
+            Error::DuplicateStructName { span, .. } => *span,
+            Error::InvalidInitializer(span) => *span,
             Error::SyntaxError(err) => match err.location {
-                pest::error::InputLocation::Pos(p) => Span(p as u32, p as u32),
-                pest::error::InputLocation::Span((s, e)) => Span(s as u32, e as u32),
+                InputLocation::Pos(p) => Span(p as u32, p as u32),
+                InputLocation::Span((s, e)) => Span(s as u32, e as u32),
             },
             Error::TypeMismatch { span, .. } => *span,
+            Error::ResultCountMismatch { span, .. } => *span,
+            Error::BlockArgCountMismatch { span, .. } => *span,
+            Error::WidthMismatch { span, .. } => *span,
+            Error::IntrinsicMismatch { span, .. } => *span,
         }
     }
 
-    pub fn print(
-        &self,
-        mut w: impl io::Write,
-        path: &str,
-        content: &str,
-        colors: bool,
-    ) -> io::Result<()> {
-        let label = match self {
+    /// Returns the structured form of the error. `content` is the parsed input.
+    pub fn diagnostic(&self, content: &str) -> Diagnostic {
+        let mut expected = vec![];
+        let mut found = None;
+        let mut labels = vec![];
+
+        let message = match self {
             Error::NumberOutOfBounds(_) => "number out of bounds".into(),
             Error::InvalidTarget(err, _) => err.to_string(),
-            Error::SyntaxError(err) => err.to_string(),
+            Error::SyntaxError(err) => match &err.variant {
+                ErrorVariant::ParsingError { positives, .. } => {
+                    expected = positives.iter().map(|rule| format!("{rule:?}")).collect();
+                    let pos = self.span().0 as usize;
+                    found = Some(match content[pos..].split_whitespace().next() {
+                        Some(token) => format!("`{token}`"),
+                        None => "end of input".to_string(),
+                    });
+                    "syntax error".into()
+                }
+                ErrorVariant::CustomError { message } => message.clone(),
+            },
             Error::Undefined(kind, _) => match kind {
                 UndefinedKind::Block(id) => format!("undefined block: `block{}`", id.0),
                 UndefinedKind::Func(name) => format!("undefined function: `%{name}`"),
+                UndefinedKind::Intrinsic(name) => format!("undefined intrinsic: `%{name}`"),
+                UndefinedKind::Global(name) => format!("undefined global variable: `%{name}`"),
                 UndefinedKind::Type(name) => format!("undefined type: `%{name}`"),
                 UndefinedKind::Value(name) => format!("undefined value: `{name}`"),
             },
-            Error::DuplicateValueName(name, _) => format!("value name `{name}` is already defined"),
+            Error::DuplicateValueName { name, first, .. } => {
+                labels.push(Label::new(*first, format!("`{name}` first defined here")));
+                format!("value name `{name}` is already defined")
+            }
+            Error::DuplicateGlobalName { name, first, .. } => {
+                labels.push(Label::new(*first, format!("`%{name}` first defined here")));
+                format!("global variable `%{name}` is already defined")
+            }
+            Error::DuplicateStructName { name, first, .. } => {
+                labels.push(Label::new(
+                    *first,
+                    format!("struct `%{name}` first defined here"),
+                ));
+                format!("struct `%{name}` is already defined")
+            }
+            Error::InvalidInitializer(_) => {
+                "initializer doesn't match the type of the global variable".into()
+            }
             Error::TypeMismatch {
                 specified,
                 inferred,
                 ..
-            } => format!(
-                "type mismatch: value declared as `{specified}`, but inferred type is `{inferred}`",
-            ),
+            } => {
+                expected.push(format!("`{specified}`"));
+                found = Some(format!("`{inferred}`"));
+                format!(
+                    "type mismatch: value declared as `{specified}`, but inferred type is \
+                     `{inferred}`",
+                )
+            }
+            Error::ResultCountMismatch {
+                declared, results, ..
+            } => {
+                expected.push(format!("{results} values"));
+                found = Some(format!("{declared} values"));
+                format!("{declared} values are defined, but the insn has {results} results")
+            }
+            Error::BlockArgCountMismatch {
+                block,
+                params,
+                args,
+                ..
+            } => {
+                expected.push(format!("{params} args"));
+                found = Some(format!("{args} args"));
+                format!(
+                    "`block{}` has {params} params, but {args} args are passed",
+                    block.0
+                )
+            }
+            Error::WidthMismatch { lhs, rhs, .. } => {
+                format!("width mismatch: operands have types `{lhs}` and `{rhs}`")
+            }
+            Error::IntrinsicMismatch { name, .. } => {
+                format!("intrinsic `%{name}` is already registered with another signature")
+            }
         };
-        let snippet = Level::Error.title("parse error").snippet(
-            Snippet::source(content)
-                .line_start(0)
-                .origin(path)
-                .fold(true)
-                .annotation(Level::Error.span(self.span().as_range()).label(&label)),
-        );
+
+        Diagnostic {
+            message,
+            span: self.span(),
+            expected,
+            found,
+            labels,
+        }
+    }
+
+    pub fn print(
+        &self,
+        mut w: impl io::Write,
+        path: &str,
+        content: &str,
+        colors: bool,
+    ) -> io::Result<()> {
+        let diag = self.diagnostic(content);
+        let label = match self {
+            // pest's message already says what was expected.
+            Error::SyntaxError(err) => err.to_string(),
+            _ => diag.message.clone(),
+        };
+
+        let mut snippet = Snippet::source(content)
+            .line_start(0)
+            .origin(path)
+            .fold(true)
+            .annotation(Level::Error.span(diag.span.as_range()).label(&label));
+        for secondary in &diag.labels {
+            snippet = snippet.annotation(
+                Level::Info
+                    .span(secondary.span.as_range())
+                    .label(&secondary.message),
+            );
+        }
+        let message = Level::Error.title("parse error").snippet(snippet);
+
         let rend = if colors {
             Renderer::styled()
         } else {
             Renderer::plain()
         };
-        let disp = rend.render(snippet);
+        let disp = rend.render(message);
         write!(w, "{}", disp)
     }
 
@@ -4,10 +4,12 @@ use ir::{
     self,
     builder::{FunctionBuilder, ModuleBuilder},
     func_cursor::{CursorLocation, FuncCursor, InsnInserter},
+    global_variable::ConstantValue,
     ir_writer::DebugProvider,
     isa::IsaBuilder,
-    module::{FuncRef, ModuleCtx},
-    InsnData, Module, Signature,
+    module::{FuncRef, ModuleCtx, WidthPolicy},
+    FuncAttrs, GlobalVariableData, Immediate, InlineHint, InsnData, Intrinsic, IntrinsicData,
+    Module, Signature, StateMutability, I256, U256,
 };
 use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
 use smallvec::SmallVec;
@@ -18,7 +20,8 @@ use syntax::Spanned;
 pub mod ast;
 mod error;
 pub mod syntax;
-pub use error::{Error, UndefinedKind};
+pub mod yul;
+pub use error::{Diagnostic, Error, Label, UndefinedKind};
 pub use syntax::Span;
 
 type Bimap<K, V> = bimap::BiHashMap<K, V, BuildHasherDefault<FxHasher>>;
@@ -32,17 +35,58 @@ pub fn parse_module(input: &str) -> Result<ParsedModule, Vec<Error>> {
     let ast = ast::parse(input)?;
 
     let isa = IsaBuilder::new(ast.target.unwrap()).build();
-    let mut builder = ModuleBuilder::new(ModuleCtx::new(isa));
+    let mut ctx = ModuleCtx::new(isa);
+    ctx.width_policy = ast.width_policy;
+    let mut builder = ModuleBuilder::new(ctx);
 
     let mut ctx = BuildCtx::default();
 
+    let mut struct_spans = FxHashMap::<SmolStr, Span>::default();
     for st in ast.struct_types {
+        let name = &st.name.inner.0;
+        if let Some(&first) = struct_spans.get(name) {
+            ctx.errors.push(Error::DuplicateStructName {
+                name: name.clone(),
+                span: st.name.span,
+                first,
+            });
+            continue;
+        }
+        struct_spans.insert(name.clone(), st.name.span);
+
         let fields = st
             .fields
             .iter()
             .map(|t| ctx.type_(&mut builder, t))
             .collect::<Vec<_>>();
-        builder.declare_struct_type(&st.name.0, &fields, false);
+        builder.declare_struct_type(name, &fields, st.packed);
+    }
+
+    let mut global_spans = FxHashMap::<SmolStr, Span>::default();
+    for gv in ast.globals {
+        let ty = ctx.type_(&mut builder, &gv.ty);
+        let data = gv
+            .init
+            .as_ref()
+            .and_then(|init| ctx.const_value(&builder, ty, init));
+
+        let name = &gv.name.inner.0;
+        if let Some(&first) = global_spans.get(name) {
+            ctx.errors.push(Error::DuplicateGlobalName {
+                name: name.clone(),
+                span: gv.name.span,
+                first,
+            });
+            continue;
+        }
+        global_spans.insert(name.clone(), gv.name.span);
+        builder.make_global(GlobalVariableData::new(
+            name.to_string(),
+            ty,
+            gv.linkage,
+            gv.is_const,
+            data,
+        ));
     }
 
     for func in ast.declared_functions {
@@ -57,10 +101,39 @@ pub fn parse_module(input: &str) -> Result<ParsedModule, Vec<Error>> {
             .map(|t| ctx.type_(&mut builder, t))
             .unwrap_or(ir::Type::Void);
 
-        let sig = Signature::new(&func.name.0, func.linkage, &params, ret_ty);
+        let mut sig = Signature::new(&func.name.0, func.linkage, &params, ret_ty)
+            .with_attrs(func_attrs(&func.attrs));
+        if let Some(call_conv) = func.call_conv {
+            sig = sig.with_call_conv(call_conv);
+        }
         builder.declare_function(sig);
     }
 
+    for intrinsic in ast.declared_intrinsics {
+        let params = intrinsic
+            .params
+            .iter()
+            .map(|t| ctx.type_(&mut builder, t))
+            .collect::<Vec<_>>();
+        let ret_ty = intrinsic
+            .ret_type
+            .as_ref()
+            .map(|t| ctx.type_(&mut builder, t))
+            .unwrap_or(ir::Type::Void);
+
+        let name = &intrinsic.name.inner.0;
+        let mut data = IntrinsicData::new(name, &params, ret_ty);
+        if intrinsic.is_pure {
+            data = data.pure();
+        }
+        if builder.declare_intrinsic(data).is_err() {
+            ctx.errors.push(Error::IntrinsicMismatch {
+                name: name.clone(),
+                span: intrinsic.name.span,
+            });
+        }
+    }
+
     for func in ast.functions.iter() {
         let sig = &func.signature;
         let args = sig
@@ -74,7 +147,12 @@ pub fn parse_module(input: &str) -> Result<ParsedModule, Vec<Error>> {
             .as_ref()
             .map(|t| ctx.type_(&mut builder, t))
             .unwrap_or(ir::Type::Void);
-        let sig = Signature::new(&sig.name.0, sig.linkage, &args, ret_ty);
+        let call_conv = sig.call_conv;
+        let mut sig = Signature::new(&sig.name.0, sig.linkage, &args, ret_ty)
+            .with_attrs(func_attrs(&sig.attrs));
+        if let Some(call_conv) = call_conv {
+            sig = sig.with_call_conv(call_conv);
+        }
 
         builder.declare_function(sig);
     }
@@ -116,12 +194,22 @@ impl DebugProvider for DebugInfo {
     }
 }
 
+/// An edge whose destination may have params, with the args the branch passes to them.
+struct Edge {
+    from: ir::Block,
+    dest: ir::Block,
+    args: SmallVec<[ir::Value; 4]>,
+    span: Span,
+}
+
 #[derive(Default)]
 struct BuildCtx {
     errors: Vec<Error>,
     blocks: FxHashSet<ir::Block>,
     value_names: FxHashMap<FuncRef, Bimap<ir::Value, SmolStr>>,
     func_value_names: Bimap<ir::Value, SmolStr>,
+    /// The spans where the values of the current function are first defined.
+    func_value_spans: FxHashMap<SmolStr, Span>,
 }
 
 impl BuildCtx {
@@ -138,10 +226,17 @@ impl BuildCtx {
             self.name_value(value, name);
         }
 
+        for ValueDeclaration(name, ty) in func.blocks.iter().flat_map(|b| b.params.iter()) {
+            let ty = self.type_(&mut fb.module_builder, ty);
+            self.declare_value(&mut fb.func, name, ty);
+        }
+
         for stmt in func.blocks.iter().flat_map(|b| b.stmts.iter()) {
-            if let StmtKind::Define(ValueDeclaration(name, ty), _) = &stmt.kind {
-                let ty = self.type_(&mut fb.module_builder, ty);
-                self.declare_value(&mut fb.func, name, ty);
+            if let StmtKind::Define(decls, _) = &stmt.kind {
+                for ValueDeclaration(name, ty) in decls {
+                    let ty = self.type_(&mut fb.module_builder, ty);
+                    self.declare_value(&mut fb.func, name, ty);
+                }
             }
         }
 
@@ -154,26 +249,57 @@ impl BuildCtx {
             }
         }
 
+        // Block params are lowered to phis, whose args are appended once all branches are built.
+        let mut param_phis: FxHashMap<ir::Block, Vec<ir::Insn>> = FxHashMap::default();
+        let mut edges = vec![];
+
         for block in &func.blocks {
             let block_id = ir::Block(block.id());
             fb.cursor.append_block(&mut fb.func, block_id);
             fb.cursor.set_location(CursorLocation::BlockTop(block_id));
 
+            for ValueDeclaration(name, ty) in &block.params {
+                let ty = self.type_(&mut fb.module_builder, ty);
+                let insn = fb.cursor.insert_insn_data(&mut fb.func, InsnData::phi(ty));
+                let value = *self.func_value_names.get_by_right(&name.string).unwrap();
+                fb.func.dfg.values[value] = ir::ValueData::Insn { insn, ty };
+                fb.cursor.attach_result(&mut fb.func, insn, value);
+                fb.cursor.set_location(CursorLocation::At(insn));
+                param_phis.entry(block_id).or_default().push(insn);
+            }
+
             for stmt in &block.stmts {
                 match &stmt.kind {
-                    ast::StmtKind::Define(ValueDeclaration(name, type_), expr) => {
-                        let ty = self.type_(&mut fb.module_builder, type_);
+                    ast::StmtKind::Define(decls, expr) => {
+                        let tys: Vec<_> = decls
+                            .iter()
+                            .map(|ValueDeclaration(_, type_)| {
+                                self.type_(&mut fb.module_builder, type_)
+                            })
+                            .collect();
+                        let ty = tys[0];
                         let err_count = self.errors.len();
 
                         let insn_data = match expr {
-                            ast::Expr::Binary(op, lhs, rhs) => {
-                                let lhs = self.value(&mut fb, lhs);
-                                let rhs = self.value(&mut fb, rhs);
+                            ast::Expr::Binary(op, lhs_ast, rhs_ast) => {
+                                let lhs = self.value(&mut fb, lhs_ast);
+                                let rhs = self.value(&mut fb, rhs_ast);
+                                let (lhs, rhs) = if self.errors.len() == err_count {
+                                    let span = Span(lhs_ast.span.0, rhs_ast.span.1);
+                                    self.unify_widths(&mut fb, *op, lhs, rhs, span)
+                                } else {
+                                    (lhs, rhs)
+                                };
                                 InsnData::Binary {
                                     code: *op,
                                     args: [lhs, rhs],
                                 }
                             }
+                            ast::Expr::OverflowBinary(op, lhs, rhs) => {
+                                let lhs = self.value(&mut fb, lhs);
+                                let rhs = self.value(&mut fb, rhs);
+                                InsnData::overflow_binary(*op, lhs, rhs)
+                            }
                             ast::Expr::Unary(op, val) => {
                                 let val = self.value(&mut fb, val);
                                 InsnData::Unary {
@@ -200,7 +326,7 @@ impl BuildCtx {
                                 let ty = self.type_(&mut fb.module_builder, ty);
                                 InsnData::Alloca { ty }
                             }
-                            ast::Expr::Call(ast::Call(name, args)) => {
+                            ast::Expr::Call(ast::Call(name, args, is_tail)) => {
                                 let func = self.func_ref(&mut fb.module_builder, name);
 
                                 let args: smallvec::SmallVec<[ir::Value; 8]> =
@@ -210,8 +336,14 @@ impl BuildCtx {
                                 let ret_ty = sig.ret_ty();
                                 fb.func.callees.insert(func, sig);
 
-                                InsnData::Call { func, args, ret_ty }
+                                InsnData::Call {
+                                    func,
+                                    args,
+                                    ret_ty,
+                                    is_tail: *is_tail,
+                                }
                             }
+                            ast::Expr::Intrinsic(call) => self.intrinsic_call(&mut fb, call),
                             ast::Expr::Gep(vals) => {
                                 let args: SmallVec<[ir::Value; 8]> =
                                     vals.iter().map(|val| self.value(&mut fb, val)).collect();
@@ -227,21 +359,38 @@ impl BuildCtx {
                             },
                         };
 
-                        // Report declared type mismatch if no error has been reported for this stmt
-                        let inferred_ty = insn_data.result_type(&fb.func.dfg).unwrap();
-                        if self.errors.len() == err_count && ty != inferred_ty {
-                            self.errors.push(Error::TypeMismatch {
-                                specified: ty.to_string(&fb.func.dfg).into(),
-                                inferred: inferred_ty.to_string(&fb.func.dfg).into(),
-                                span: type_.span,
+                        let inferred_tys = insn_data.result_types(&fb.func.dfg);
+                        if self.errors.len() == err_count && inferred_tys.len() != decls.len() {
+                            let (first, last) = (&decls[0], &decls[decls.len() - 1]);
+                            self.errors.push(Error::ResultCountMismatch {
+                                declared: decls.len(),
+                                results: inferred_tys.len(),
+                                span: Span(first.0.span.0, last.1.span.1),
                             });
                         }
 
+                        // Report declared type mismatch if no error has been reported for this stmt
+                        for (decl, (ty, inferred_ty)) in
+                            decls.iter().zip(tys.iter().zip(&inferred_tys))
+                        {
+                            if self.errors.len() == err_count && ty != inferred_ty {
+                                self.errors.push(Error::TypeMismatch {
+                                    specified: ty.to_string(&fb.func.dfg).into(),
+                                    inferred: inferred_ty.to_string(&fb.func.dfg).into(),
+                                    span: decl.1.span,
+                                });
+                            }
+                        }
+
                         // xxx cleanup
-                        let value = *self.func_value_names.get_by_right(&name.string).unwrap();
                         let insn = fb.cursor.insert_insn_data(&mut fb.func, insn_data);
-                        fb.func.dfg.values[value] = ir::ValueData::Insn { insn, ty };
-                        fb.cursor.attach_result(&mut fb.func, insn, value);
+                        for (ValueDeclaration(name, _), ty) in
+                            decls.iter().zip(tys).take(inferred_tys.len())
+                        {
+                            let value = *self.func_value_names.get_by_right(&name.string).unwrap();
+                            fb.func.dfg.values[value] = ir::ValueData::Insn { insn, ty };
+                            fb.cursor.attach_result(&mut fb.func, insn, value);
+                        }
                         fb.cursor.set_location(CursorLocation::At(insn));
                     }
                     ast::StmtKind::Store(loc, addr, val) => {
@@ -253,52 +402,135 @@ impl BuildCtx {
                             ir::DataLocationKind::Storage => fb.storage_store(addr, val),
                         }
                     }
+                    ast::StmtKind::Mem(op, loc, [dst, src, len]) => {
+                        let dst = self.value(&mut fb, dst);
+                        let src = self.value(&mut fb, src);
+                        let len = self.value(&mut fb, len);
+                        fb.mem_op(*op, *loc, dst, src, len);
+                    }
                     ast::StmtKind::Return(val) => {
                         let val = val.as_ref().map(|v| self.value(&mut fb, v));
                         fb.ret(val);
                     }
-                    ast::StmtKind::Jump(block_id) => {
-                        let block_id = self.block(block_id);
-                        fb.jump(block_id);
+                    ast::StmtKind::Revert(selector, args) => {
+                        let args: Vec<_> =
+                            args.iter().map(|val| self.value(&mut fb, val)).collect();
+                        fb.revert(*selector, &args);
+                    }
+                    ast::StmtKind::Trap(reason) => fb.trap(*reason),
+                    ast::StmtKind::Unreachable => fb.unreachable(),
+                    ast::StmtKind::Jump(dest) => {
+                        let dest = self.dest(&mut fb, block_id, dest, &mut edges);
+                        fb.jump(dest);
                     }
-                    ast::StmtKind::Branch(cond, true_block, false_block) => {
+                    ast::StmtKind::Branch(cond, true_dest, false_dest) => {
                         let cond = self.value(&mut fb, cond);
-                        let true_block = self.block(true_block);
-                        let false_block = self.block(false_block);
+                        let true_block = self.dest(&mut fb, block_id, true_dest, &mut edges);
+                        let false_block = self.dest(&mut fb, block_id, false_dest, &mut edges);
                         fb.br(cond, true_block, false_block);
                     }
-                    ast::StmtKind::BranchTable(index, default_block, table) => {
+                    ast::StmtKind::BranchTable(index, default_dest, table) => {
                         let index = self.value(&mut fb, index);
-                        let default_block = default_block.as_ref().map(|b| self.block(b));
+                        let default_block = default_dest
+                            .as_ref()
+                            .map(|dest| self.dest(&mut fb, block_id, dest, &mut edges));
 
                         let table = table
                             .iter()
-                            .map(|(val, block)| {
-                                let block = self.block(block);
+                            .map(|(val, dest)| {
+                                let block = self.dest(&mut fb, block_id, dest, &mut edges);
                                 (self.value(&mut fb, val), block)
                             })
                             .collect::<Vec<_>>();
                         fb.br_table(index, default_block, &table);
                     }
-                    ast::StmtKind::Call(ast::Call(name, args)) => {
-                        let func_ref = self.func_ref(&mut fb.module_builder, name);
+                    ast::StmtKind::Intrinsic(call) => {
+                        let insn_data = self.intrinsic_call(&mut fb, call);
 
-                        let args = args
-                            .iter()
-                            .map(|val| self.value(&mut fb, val))
-                            .collect::<Vec<_>>();
-                        fb.call(func_ref, &args).unwrap();
+                        // The result of the intrinsic is unused, so it's not attached.
+                        let insn = fb.cursor.insert_insn_data(&mut fb.func, insn_data);
+                        fb.cursor.set_location(CursorLocation::At(insn));
+                    }
+                    ast::StmtKind::Call(ast::Call(name, args, is_tail)) => {
+                        let func = self.func_ref(&mut fb.module_builder, name);
+
+                        let args: SmallVec<[ir::Value; 8]> =
+                            args.iter().map(|val| self.value(&mut fb, val)).collect();
+
+                        let sig = fb.module_builder.get_sig(func).clone();
+                        let ret_ty = sig.ret_ty();
+                        fb.func.callees.insert(func, sig);
+
+                        // The result of the call is unused, so it's not attached.
+                        let insn = fb.cursor.insert_insn_data(
+                            &mut fb.func,
+                            InsnData::Call {
+                                func,
+                                args,
+                                ret_ty,
+                                is_tail: *is_tail,
+                            },
+                        );
+                        fb.cursor.set_location(CursorLocation::At(insn));
                     }
                 }
             }
         }
 
+        for edge in edges {
+            let phis = param_phis.get(&edge.dest).map_or(&[][..], |phis| phis);
+            if phis.len() != edge.args.len() {
+                if self.blocks.contains(&edge.dest) {
+                    self.errors.push(Error::BlockArgCountMismatch {
+                        block: edge.dest,
+                        params: phis.len(),
+                        args: edge.args.len(),
+                        span: edge.span,
+                    });
+                }
+                continue;
+            }
+            for (&phi, &arg) in phis.iter().zip(&edge.args) {
+                fb.func.dfg.append_phi_arg(phi, arg, edge.from);
+            }
+        }
+
         let names = std::mem::take(&mut self.func_value_names);
         self.value_names.insert(func_ref, names);
+        self.func_value_spans.clear();
         fb.seal_all();
         fb.finish()
     }
 
+    /// Makes the operands of a binary insn the same width, or reports an error if the width
+    /// policy of the module is strict.
+    fn unify_widths(
+        &mut self,
+        fb: &mut FunctionBuilder<InsnInserter>,
+        op: ir::insn::BinaryOp,
+        lhs: ir::Value,
+        rhs: ir::Value,
+        span: Span,
+    ) -> (ir::Value, ir::Value) {
+        let lhs_ty = fb.func.dfg.value_ty(lhs);
+        let rhs_ty = fb.func.dfg.value_ty(rhs);
+        if fb.module_builder.ctx.width_policy == WidthPolicy::Strict
+            && !op.is_shift()
+            && lhs_ty != rhs_ty
+            && lhs_ty.is_integral()
+            && rhs_ty.is_integral()
+        {
+            self.errors.push(Error::WidthMismatch {
+                lhs: lhs_ty.to_string(&fb.func.dfg).into(),
+                rhs: rhs_ty.to_string(&fb.func.dfg).into(),
+                span,
+            });
+            return (lhs, rhs);
+        }
+
+        fb.unify_widths(op, lhs, rhs)
+    }
+
     fn func_ref(&mut self, mb: &mut ModuleBuilder, name: &Spanned<ast::FunctionName>) -> FuncRef {
         mb.get_func_ref(&name.inner.0).unwrap_or_else(|| {
             self.errors.push(Error::Undefined(
@@ -309,6 +541,59 @@ impl BuildCtx {
         })
     }
 
+    fn intrinsic_call(
+        &mut self,
+        fb: &mut FunctionBuilder<InsnInserter>,
+        call: &ast::IntrinsicCall,
+    ) -> InsnData {
+        let ast::IntrinsicCall(name, args) = call;
+        let args = args.iter().map(|val| self.value(fb, val)).collect();
+
+        let mb = &fb.module_builder;
+        let (intrinsic, ret_ty) = match mb.get_intrinsic(&name.inner.0) {
+            Some(intrinsic) => {
+                let ret_ty = mb
+                    .ctx
+                    .isa
+                    .with_intrinsics(|r| r.intrinsic_data(intrinsic).ret_ty);
+                (intrinsic, ret_ty)
+            }
+            None => {
+                self.errors.push(Error::Undefined(
+                    UndefinedKind::Intrinsic(name.inner.0.clone()),
+                    name.span,
+                ));
+                (Intrinsic::from_u32(0), ir::Type::Void)
+            }
+        };
+
+        InsnData::Intrinsic {
+            intrinsic,
+            args,
+            ret_ty,
+        }
+    }
+
+    /// Resolves the block of `dest`, a destination of the branch of `from`, and records the args
+    /// it passes to the params of the block.
+    fn dest(
+        &mut self,
+        fb: &mut FunctionBuilder<InsnInserter>,
+        from: ir::Block,
+        dest: &ast::Dest,
+        edges: &mut Vec<Edge>,
+    ) -> ir::Block {
+        let block = self.block(&dest.block);
+        let args = dest.args.iter().map(|arg| self.value(fb, arg)).collect();
+        edges.push(Edge {
+            from,
+            dest: block,
+            args,
+            span: dest.span,
+        });
+        block
+    }
+
     fn block(&mut self, b: &ast::BlockId) -> ir::Block {
         let block = ir::Block(b.id.unwrap());
         if !self.blocks.contains(&block) {
@@ -330,19 +615,30 @@ impl BuildCtx {
             .insert_no_overwrite(value, name.string.clone())
             .is_err()
         {
-            self.errors
-                .push(Error::DuplicateValueName(name.string.clone(), name.span));
+            self.duplicate_value_name(name);
+        } else {
+            self.func_value_spans.insert(name.string.clone(), name.span);
         }
     }
 
     fn name_value(&mut self, value: ir::Value, name: &ast::ValueName) {
         if self.func_value_names.contains_right(&name.string) {
-            self.errors
-                .push(Error::DuplicateValueName(name.string.clone(), name.span));
+            self.duplicate_value_name(name);
+        } else {
+            self.func_value_spans.insert(name.string.clone(), name.span);
         }
         self.func_value_names.insert(value, name.string.clone());
     }
 
+    fn duplicate_value_name(&mut self, name: &ast::ValueName) {
+        let first = self.func_value_spans[&name.string];
+        self.errors.push(Error::DuplicateValueName {
+            name: name.string.clone(),
+            span: name.span,
+            first,
+        });
+    }
+
     fn value(&mut self, fb: &mut FunctionBuilder<InsnInserter>, val: &ast::Value) -> ir::Value {
         match &val.kind {
             ast::ValueKind::Immediate(imm) => fb.make_imm_value(*imm),
@@ -357,10 +653,76 @@ impl BuildCtx {
                     ));
                     ir::Value(0)
                 }),
+            ast::ValueKind::Global(name) => match fb.module_builder.global_by_name(name) {
+                Some(gv) => fb.make_global_value(gv),
+                None => {
+                    self.errors.push(Error::Undefined(
+                        UndefinedKind::Global(name.clone()),
+                        val.span,
+                    ));
+                    ir::Value(0)
+                }
+            },
             ast::ValueKind::Error => unreachable!(),
         }
     }
 
+    /// Converts the initializer of a global variable of type `ty`.
+    fn const_value(
+        &mut self,
+        mb: &ModuleBuilder,
+        ty: ir::Type,
+        init: &ast::GlobalInit,
+    ) -> Option<ConstantValue> {
+        let value = match &init.kind {
+            ast::GlobalInitKind::Number(txt) if ty.is_integral() => {
+                let Some(imm) = parse_int(txt, ty) else {
+                    self.errors.push(Error::NumberOutOfBounds(init.span));
+                    return None;
+                };
+                ConstantValue::Immediate(imm)
+            }
+
+            ast::GlobalInitKind::Array(elems) => match mb.ctx.with_ty_store(|s| s.array_def(ty)) {
+                Some((elem_ty, len)) if len == elems.len() => ConstantValue::Array(
+                    elems
+                        .iter()
+                        .map(|elem| self.const_value(mb, elem_ty, elem))
+                        .collect::<Option<_>>()?,
+                ),
+                _ => {
+                    self.errors.push(Error::InvalidInitializer(init.span));
+                    return None;
+                }
+            },
+
+            ast::GlobalInitKind::Struct(fields) => {
+                let field_tys = mb
+                    .ctx
+                    .with_ty_store(|s| s.struct_def(ty).map(|def| def.fields.clone()));
+                match field_tys {
+                    Some(field_tys) if field_tys.len() == fields.len() => ConstantValue::Struct(
+                        fields
+                            .iter()
+                            .zip(field_tys)
+                            .map(|(field, ty)| self.const_value(mb, ty, field))
+                            .collect::<Option<_>>()?,
+                    ),
+                    _ => {
+                        self.errors.push(Error::InvalidInitializer(init.span));
+                        return None;
+                    }
+                }
+            }
+
+            ast::GlobalInitKind::Number(_) => {
+                self.errors.push(Error::InvalidInitializer(init.span));
+                return None;
+            }
+        };
+        Some(value)
+    }
+
     fn type_(&mut self, mb: &mut ModuleBuilder, t: &ast::Type) -> ir::Type {
         match &t.kind {
             ast::TypeKind::Int(i) => (*i).into(),
@@ -373,6 +735,8 @@ impl BuildCtx {
                 mb.declare_array_type(elem, *n)
             }
             ast::TypeKind::Void => ir::Type::Void,
+            ast::TypeKind::Bytes => mb.bytes_type(),
+            ast::TypeKind::Word => mb.word_type(),
             ast::TypeKind::Struct(name) => mb.get_struct_type(name).unwrap_or_else(|| {
                 self.errors
                     .push(Error::Undefined(UndefinedKind::Type(name.clone()), t.span));
@@ -382,3 +746,40 @@ impl BuildCtx {
         }
     }
 }
+
+/// Parses a decimal integer of type `ty`, which may be written either signed or unsigned.
+fn parse_int(txt: &str, ty: ir::Type) -> Option<Immediate> {
+    let (is_negative, digits) = match txt.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, txt),
+    };
+    let abs: I256 = U256::from_dec_str(digits).ok()?.into();
+    let val = if is_negative {
+        I256::zero().overflowing_sub(abs).0
+    } else {
+        abs
+    };
+
+    let imm = Immediate::from_i256(val, ty);
+    (imm.as_i256() == val || imm.zext(ir::Type::I256).as_i256() == val).then_some(imm)
+}
+
+/// Returns the attributes written after a function signature.
+fn func_attrs(attrs: &[SmolStr]) -> FuncAttrs {
+    let mut func_attrs = FuncAttrs::default();
+    for attr in attrs {
+        match attr.as_str() {
+            "optnone" => func_attrs.optnone = true,
+            "inline(always)" => func_attrs.inline = InlineHint::Always,
+            "inline(never)" => func_attrs.inline = InlineHint::Never,
+            "cold" => func_attrs.cold = true,
+            "nothrow" => func_attrs.nothrow = true,
+            "pure" => func_attrs.mutability = StateMutability::Pure,
+            "view" => func_attrs.mutability = StateMutability::View,
+            "nonpayable" => func_attrs.mutability = StateMutability::NonPayable,
+            "payable" => func_attrs.mutability = StateMutability::Payable,
+            _ => unreachable!(),
+        }
+    }
+    func_attrs
+}
@@ -141,6 +141,19 @@ impl<'i, E> Node<'i, E> {
         Some(self.with_child(p, f))
     }
 
+    /// Like [`Self::descend_into_opt`], but only descends if the next pair matches `rule`, e.g., to
+    /// take the optional args that follow a block ident, rather than those of a later one.
+    pub fn descend_into_next_opt<F, T>(&mut self, rule: Rule, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        if self.pairs.first()?.as_ref().unwrap().as_rule() != rule {
+            return None;
+        }
+        let p = self.pairs.remove(0).unwrap();
+        Some(self.with_child(p, f))
+    }
+
     pub fn single<T: FromSyntax<E>>(&mut self, rule: Rule) -> T {
         self.single_opt(rule).unwrap()
     }
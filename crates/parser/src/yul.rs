@@ -0,0 +1,802 @@
+//! This module contains an importer of Yul, so that existing Yul code can be migrated to Sonatina
+//! one function at a time.
+//!
+//! A practical subset of Yul is supported: function definitions, variables, `if`, `switch`, `for`
+//! with `break` and `continue`, `leave`, and the arithmetic, comparison, bitwise, memory and
+//! storage builtins, each of which maps to a single IR insn. Every Yul value is an `i256`, and
+//! every Yul function becomes a public function whose name has `.` and `$` replaced by `_`.
+//!
+//! The following is not supported:
+//! * statements other than function definitions at the top level of the code,
+//! * sub-objects and data of an object,
+//! * functions and declarations with more than one return value,
+//! * string literals and builtins that have no IR insn, e.g., `calldataload` or `keccak256`.
+use std::fmt;
+
+use ir::{
+    builder::{FunctionBuilder, ModuleBuilder, Variable},
+    func_cursor::InsnInserter,
+    insn::BinaryOp,
+    module::{FuncRef, ModuleCtx},
+    Block, Linkage, Module, Signature, Type, Value, U256,
+};
+use pest::{
+    iterators::{Pair, Pairs},
+    Parser as _,
+};
+use rustc_hash::FxHashMap;
+use smol_str::SmolStr;
+
+use crate::Span;
+
+#[derive(pest_derive::Parser)]
+#[grammar = "yul.pest"]
+pub struct YulParser;
+
+/// Parses the Yul code in `input`, which is either a block or an object, and builds a module of
+/// its functions.
+pub fn parse_yul(input: &str, ctx: ModuleCtx) -> Result<Module, Vec<YulError>> {
+    let root = YulParser::parse(Rule::yul, input)
+        .map_err(|err| vec![YulError::SyntaxError(Box::new(err))])?
+        .next()
+        .unwrap();
+
+    let mut errors = vec![];
+    let code = root.into_inner().next().unwrap();
+    let code = match code.as_rule() {
+        Rule::object => {
+            let mut inner = code.into_inner();
+            let _name = inner.next().unwrap();
+            let code = inner.next().unwrap().into_inner().next().unwrap();
+            for rest in inner {
+                errors.push(YulError::Unsupported(
+                    "sub-objects and data".into(),
+                    span_of(&rest),
+                ));
+            }
+            code
+        }
+        _ => code,
+    };
+
+    let mut defs = vec![];
+    for stmt in code.clone().into_inner() {
+        if stmt.as_rule() != Rule::function_definition {
+            errors.push(YulError::Unsupported(
+                "top-level statements".into(),
+                span_of(&stmt),
+            ));
+        }
+    }
+    collect_functions(code.into_inner(), &mut defs);
+
+    let mut mb = ModuleBuilder::new(ctx);
+    let mut symbols = FxHashMap::default();
+    let mut declared = vec![];
+    for def in defs {
+        let mut inner = def.clone().into_inner();
+        let name = inner.next().unwrap();
+        let mut params = vec![];
+        let mut rets = vec![];
+        let mut body = None;
+        for pair in inner {
+            match pair.as_rule() {
+                Rule::identifier => params.push(pair),
+                Rule::returns => rets.extend(pair.into_inner()),
+                Rule::block => body = Some(pair),
+                _ => {}
+            }
+        }
+        let params = untyped(params);
+        let rets = untyped(rets);
+
+        if rets.len() > 1 {
+            errors.push(YulError::Unsupported(
+                "multiple return values".into(),
+                span_of(&def),
+            ));
+            continue;
+        }
+
+        let symbol = name.as_str().replace(['.', '$'], "_");
+        if let Some(&first) = symbols.get(&symbol) {
+            errors.push(YulError::DuplicateName {
+                name: name.as_str().into(),
+                span: span_of(&name),
+                first,
+            });
+            continue;
+        }
+        symbols.insert(symbol.clone(), span_of(&name));
+
+        let ret_ty = if rets.is_empty() {
+            Type::Void
+        } else {
+            Type::I256
+        };
+        let sig = Signature::new(
+            &symbol,
+            Linkage::Public,
+            &vec![Type::I256; params.len()],
+            ret_ty,
+        );
+        let func_ref = mb.declare_function(sig);
+        declared.push(FuncDef {
+            name: name.as_str(),
+            func_ref,
+            params,
+            ret: rets.into_iter().next(),
+            body: body.unwrap(),
+        });
+    }
+
+    let callees: FxHashMap<_, _> = declared
+        .iter()
+        .map(|def| {
+            (
+                def.name,
+                (def.func_ref, def.params.len(), def.ret.is_some()),
+            )
+        })
+        .collect();
+    for def in declared {
+        let mut lowerer = FuncLowerer::new(mb, &def, &callees, &mut errors);
+        lowerer.lower_body(def.body);
+        mb = lowerer.finish();
+    }
+
+    if errors.is_empty() {
+        Ok(mb.build())
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug)]
+pub enum YulError {
+    SyntaxError(Box<pest::error::Error<Rule>>),
+    /// A variable or function that is not defined.
+    Undefined(SmolStr, Span),
+    DuplicateName {
+        name: SmolStr,
+        span: Span,
+        first: Span,
+    },
+    /// A construct or builtin outside of the supported subset.
+    Unsupported(SmolStr, Span),
+    ArgNum {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An expression doesn't produce the number of values its context expects.
+    ValueNum {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    NumberOutOfBounds(Span),
+    /// `break` or `continue` outside of a loop.
+    Misplaced(SmolStr, Span),
+}
+
+impl YulError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::SyntaxError(err) => match err.location {
+                pest::error::InputLocation::Pos(p) => Span(p as u32, p as u32),
+                pest::error::InputLocation::Span((s, e)) => Span(s as u32, e as u32),
+            },
+            Self::Undefined(_, span)
+            | Self::Unsupported(_, span)
+            | Self::NumberOutOfBounds(span)
+            | Self::Misplaced(_, span) => *span,
+            Self::DuplicateName { span, .. }
+            | Self::ArgNum { span, .. }
+            | Self::ValueNum { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for YulError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SyntaxError(err) => write!(f, "{err}"),
+            Self::Undefined(name, _) => write!(f, "undefined identifier: `{name}`"),
+            Self::DuplicateName { name, .. } => write!(f, "`{name}` is already defined"),
+            Self::Unsupported(what, _) => write!(f, "unsupported: {what}"),
+            Self::ArgNum {
+                expected, found, ..
+            } => write!(f, "expected {expected} arguments, but found {found}"),
+            Self::ValueNum {
+                expected, found, ..
+            } => write!(f, "expected {expected} values, but found {found}"),
+            Self::NumberOutOfBounds(_) => write!(f, "number out of bounds"),
+            Self::Misplaced(keyword, _) => write!(f, "`{keyword}` is not allowed here"),
+        }
+    }
+}
+
+impl std::error::Error for YulError {}
+
+struct FuncDef<'i> {
+    name: &'i str,
+    func_ref: FuncRef,
+    params: Vec<Pair<'i, Rule>>,
+    ret: Option<Pair<'i, Rule>>,
+    body: Pair<'i, Rule>,
+}
+
+/// The function, the number of arguments and whether it returns a value.
+type Callees<'i> = FxHashMap<&'i str, (FuncRef, usize, bool)>;
+
+struct Loop {
+    /// The block of the post statements, made when the first edge to it is.
+    post: Option<Block>,
+    exit: Block,
+}
+
+struct FuncLowerer<'i, 'a> {
+    builder: FunctionBuilder<InsnInserter>,
+    callees: &'a Callees<'i>,
+    errors: &'a mut Vec<YulError>,
+    scopes: Vec<FxHashMap<&'i str, (Variable, Span)>>,
+    loops: Vec<Loop>,
+    ret: Option<Variable>,
+    block: Block,
+    /// `true` if the current block has a terminator, so that the rest of the Yul block is dead.
+    terminated: bool,
+    word_ptr: Type,
+    byte_ptr: Type,
+}
+
+impl<'i, 'a> FuncLowerer<'i, 'a> {
+    fn new(
+        mb: ModuleBuilder,
+        def: &FuncDef<'i>,
+        callees: &'a Callees<'i>,
+        errors: &'a mut Vec<YulError>,
+    ) -> Self {
+        let mut builder = mb.build_function::<InsnInserter>(def.func_ref);
+        let word_ptr = builder.ptr_type(Type::I256);
+        let byte_ptr = builder.ptr_type(Type::I8);
+        let block = builder.append_block();
+        builder.switch_to_block(block);
+
+        let mut lowerer = Self {
+            builder,
+            callees,
+            errors,
+            scopes: vec![FxHashMap::default()],
+            loops: vec![],
+            ret: None,
+            block,
+            terminated: false,
+            word_ptr,
+            byte_ptr,
+        };
+        for (i, param) in def.params.iter().enumerate() {
+            let value = lowerer.builder.args()[i];
+            lowerer.define(param, value);
+        }
+        if let Some(ret) = &def.ret {
+            let zero = lowerer.zero();
+            lowerer.ret = Some(lowerer.define(ret, zero));
+        }
+        lowerer
+    }
+
+    fn lower_body(&mut self, body: Pair<'i, Rule>) {
+        self.lower_block(body);
+        if !self.terminated {
+            self.ret();
+        }
+    }
+
+    fn finish(mut self) -> ModuleBuilder {
+        self.builder.seal_all();
+        self.builder.finish()
+    }
+
+    fn lower_block(&mut self, block: Pair<'i, Rule>) {
+        self.scopes.push(FxHashMap::default());
+        self.lower_stmts(block.into_inner());
+        self.scopes.pop();
+    }
+
+    fn lower_stmts(&mut self, stmts: Pairs<'i, Rule>) {
+        for stmt in stmts {
+            if self.terminated {
+                break;
+            }
+            self.lower_stmt(stmt);
+        }
+    }
+
+    fn lower_stmt(&mut self, stmt: Pair<'i, Rule>) {
+        let span = span_of(&stmt);
+        match stmt.as_rule() {
+            // Functions are lowered on their own.
+            Rule::function_definition => {}
+
+            Rule::variable_declaration => {
+                let mut names = vec![];
+                let mut init = None;
+                for pair in stmt.into_inner() {
+                    match pair.as_rule() {
+                        Rule::identifier => names.push(pair),
+                        _ => init = Some(pair),
+                    }
+                }
+                let names = untyped(names);
+                if names.len() > 1 {
+                    self.errors
+                        .push(YulError::Unsupported("multiple variables".into(), span));
+                    return;
+                }
+                let value = match init {
+                    Some(init) => self.lower_value(init),
+                    None => self.zero(),
+                };
+                self.define(&names[0], value);
+            }
+
+            Rule::assignment => {
+                let mut pairs: Vec<_> = stmt.into_inner().collect();
+                let expr = pairs.pop().unwrap();
+                if pairs.len() > 1 {
+                    self.errors
+                        .push(YulError::Unsupported("multiple variables".into(), span));
+                    return;
+                }
+                let value = self.lower_value(expr);
+                if let Some(var) = self.lookup(&pairs[0]) {
+                    self.builder.def_var(var, value);
+                }
+            }
+
+            Rule::if_stmt => {
+                let mut inner = stmt.into_inner();
+                let cond = self.lower_cond(inner.next().unwrap());
+                let then = self.builder.append_block();
+                let merge = self.builder.append_block();
+                self.builder.br(cond, then, merge);
+
+                self.switch_to(then);
+                self.lower_block(inner.next().unwrap());
+                if !self.terminated {
+                    self.builder.jump(merge);
+                }
+                self.switch_to(merge);
+            }
+
+            Rule::switch_stmt => self.lower_switch(stmt),
+            Rule::for_loop => self.lower_for(stmt),
+
+            Rule::break_stmt => match self.loops.last() {
+                Some(lp) => {
+                    let exit = lp.exit;
+                    self.builder.jump(exit);
+                    self.terminated = true;
+                }
+                None => self.errors.push(YulError::Misplaced("break".into(), span)),
+            },
+
+            Rule::continue_stmt => {
+                if self.loops.is_empty() {
+                    self.errors
+                        .push(YulError::Misplaced("continue".into(), span));
+                    return;
+                }
+                let post = self.loop_post();
+                self.builder.jump(post);
+                self.terminated = true;
+            }
+
+            Rule::leave_stmt => self.ret(),
+
+            Rule::block => self.lower_block(stmt),
+
+            Rule::expression => {
+                if self.lower_expr(stmt).is_some() {
+                    self.errors.push(YulError::ValueNum {
+                        expected: 0,
+                        found: 1,
+                        span,
+                    });
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn lower_switch(&mut self, stmt: Pair<'i, Rule>) {
+        let mut inner = stmt.into_inner();
+        let cond = self.lower_value(inner.next().unwrap());
+
+        let mut table = vec![];
+        let mut bodies = vec![];
+        let mut default = None;
+        for case in inner {
+            let block = self.builder.append_block();
+            match case.as_rule() {
+                Rule::case => {
+                    let mut case = case.into_inner();
+                    let key = self.lower_literal(case.next().unwrap());
+                    table.push((key, block));
+                    bodies.push((block, case.next().unwrap()));
+                }
+                _ => {
+                    default = Some(block);
+                    bodies.push((block, case.into_inner().next().unwrap()));
+                }
+            }
+        }
+
+        // Without a default case, a value that matches no case falls through to the merge block.
+        let mut merge = match default {
+            Some(_) => None,
+            None => Some(self.builder.append_block()),
+        };
+        if table.is_empty() {
+            self.builder.jump(default.unwrap());
+        } else {
+            self.builder.br_table(cond, default.or(merge), &table);
+        }
+
+        let mut open_ends = vec![];
+        for (block, body) in bodies {
+            self.switch_to(block);
+            self.lower_block(body);
+            if !self.terminated {
+                open_ends.push(self.block);
+            }
+        }
+
+        if merge.is_none() && !open_ends.is_empty() {
+            merge = Some(self.builder.append_block());
+        }
+        match merge {
+            Some(merge) => {
+                for end in open_ends {
+                    self.switch_to(end);
+                    self.builder.jump(merge);
+                }
+                self.switch_to(merge);
+            }
+            // Every case leaves the switch, so the code after it is dead.
+            None => self.terminated = true,
+        }
+    }
+
+    fn lower_for(&mut self, stmt: Pair<'i, Rule>) {
+        let mut inner = stmt.into_inner();
+        let (init, cond, post, body) = (
+            inner.next().unwrap(),
+            inner.next().unwrap(),
+            inner.next().unwrap(),
+            inner.next().unwrap(),
+        );
+
+        // Variables of the init block are visible in the rest of the loop.
+        self.scopes.push(FxHashMap::default());
+        self.lower_stmts(init.into_inner());
+        if self.terminated {
+            self.scopes.pop();
+            return;
+        }
+
+        let header = self.builder.append_block();
+        self.builder.jump(header);
+        self.switch_to(header);
+        let cond = self.lower_cond(cond);
+        let body_block = self.builder.append_block();
+        let exit = self.builder.append_block();
+        self.builder.br(cond, body_block, exit);
+
+        self.loops.push(Loop { post: None, exit });
+        self.switch_to(body_block);
+        self.lower_block(body);
+        if !self.terminated {
+            let post = self.loop_post();
+            self.builder.jump(post);
+        }
+
+        if let Some(post_block) = self.loops.pop().unwrap().post {
+            self.switch_to(post_block);
+            self.lower_block(post);
+            if !self.terminated {
+                self.builder.jump(header);
+            }
+        }
+
+        self.switch_to(exit);
+        self.scopes.pop();
+    }
+
+    /// Returns the block of the post statements of the innermost loop.
+    fn loop_post(&mut self) -> Block {
+        if let Some(post) = self.loops.last().unwrap().post {
+            return post;
+        }
+        let post = self.builder.append_block();
+        self.loops.last_mut().unwrap().post = Some(post);
+        post
+    }
+
+    /// Lowers a condition, which is true if the expression is non-zero.
+    fn lower_cond(&mut self, expr: Pair<'i, Rule>) -> Value {
+        let value = self.lower_value(expr);
+        let zero = self.zero();
+        self.builder.ne(value, zero)
+    }
+
+    /// Lowers an expression that must produce a value.
+    fn lower_value(&mut self, expr: Pair<'i, Rule>) -> Value {
+        let span = span_of(&expr);
+        match self.lower_expr(expr) {
+            Some(value) => value,
+            None => {
+                self.errors.push(YulError::ValueNum {
+                    expected: 1,
+                    found: 0,
+                    span,
+                });
+                self.zero()
+            }
+        }
+    }
+
+    fn lower_expr(&mut self, expr: Pair<'i, Rule>) -> Option<Value> {
+        let expr = expr.into_inner().next().unwrap();
+        match expr.as_rule() {
+            Rule::literal => Some(self.lower_literal(expr)),
+            Rule::identifier => Some(match self.lookup(&expr) {
+                Some(var) => self.builder.use_var(var),
+                None => self.zero(),
+            }),
+            Rule::function_call => self.lower_call(expr),
+            _ => unreachable!(),
+        }
+    }
+
+    fn lower_call(&mut self, call: Pair<'i, Rule>) -> Option<Value> {
+        let span = span_of(&call);
+        let mut inner = call.into_inner();
+        let name = inner.next().unwrap();
+        let exprs: Vec<_> = inner.collect();
+
+        // Yul evaluates arguments from right to left.
+        let mut args: Vec<_> = exprs
+            .into_iter()
+            .rev()
+            .map(|expr| self.lower_value(expr))
+            .collect();
+        args.reverse();
+
+        if let Some(&(func_ref, arg_num, has_ret)) = self.callees.get(name.as_str()) {
+            if !self.check_arg_num(arg_num, args.len(), span) {
+                return has_ret.then(|| self.zero());
+            }
+            return self.builder.call(func_ref, &args);
+        }
+
+        let Some(arg_num) = builtin_arg_num(name.as_str()) else {
+            self.errors
+                .push(YulError::Undefined(name.as_str().into(), span_of(&name)));
+            return Some(self.zero());
+        };
+        if !self.check_arg_num(arg_num, args.len(), span) {
+            return Some(self.zero());
+        }
+
+        let b = &mut self.builder;
+        let value = match name.as_str() {
+            "add" => b.add(args[0], args[1]),
+            "sub" => b.sub(args[0], args[1]),
+            "mul" => b.mul(args[0], args[1]),
+            "div" => b.udiv(args[0], args[1]),
+            "sdiv" => b.sdiv(args[0], args[1]),
+            "and" => b.and(args[0], args[1]),
+            "or" => b.or(args[0], args[1]),
+            "xor" => b.xor(args[0], args[1]),
+            "not" => b.not(args[0]),
+            // Yul takes the shift amount first.
+            "shl" => b.shl(args[1], args[0]),
+            "shr" => b.shr(args[1], args[0]),
+            "sar" => b.sar(args[1], args[0]),
+
+            "lt" | "gt" | "slt" | "sgt" | "eq" => {
+                let op = match name.as_str() {
+                    "lt" => BinaryOp::Lt,
+                    "gt" => BinaryOp::Gt,
+                    "slt" => BinaryOp::Slt,
+                    "sgt" => BinaryOp::Sgt,
+                    _ => BinaryOp::Eq,
+                };
+                let cmp = b.binary_op(op, args[0], args[1]);
+                b.zext(cmp, Type::I256)
+            }
+            "iszero" => {
+                let zero = b.make_imm_value(U256::zero());
+                let cmp = b.eq(args[0], zero);
+                b.zext(cmp, Type::I256)
+            }
+
+            "mload" => {
+                let addr = b.bitcast(args[0], self.word_ptr);
+                b.memory_load(addr)
+            }
+            "sload" => {
+                let addr = b.bitcast(args[0], self.word_ptr);
+                b.storage_load(addr)
+            }
+            "mstore" | "mstore8" | "sstore" => {
+                match name.as_str() {
+                    "mstore" => {
+                        let addr = b.bitcast(args[0], self.word_ptr);
+                        b.memory_store(addr, args[1]);
+                    }
+                    "mstore8" => {
+                        let addr = b.bitcast(args[0], self.byte_ptr);
+                        let byte = b.trunc(args[1], Type::I8);
+                        b.memory_store(addr, byte);
+                    }
+                    _ => {
+                        let addr = b.bitcast(args[0], self.word_ptr);
+                        b.storage_store(addr, args[1]);
+                    }
+                }
+                return None;
+            }
+            // The argument is evaluated for its side effects only.
+            "pop" => return None,
+            _ => unreachable!(),
+        };
+        Some(value)
+    }
+
+    fn lower_literal(&mut self, literal: Pair<'i, Rule>) -> Value {
+        let lit = literal.into_inner().next().unwrap();
+        let span = span_of(&lit);
+        let value = match lit.as_rule() {
+            Rule::hex_number => U256::from_str_radix(&lit.as_str()[2..], 16).ok(),
+            Rule::decimal_number => U256::from_dec_str(lit.as_str()).ok(),
+            Rule::bool_literal => Some(U256::from((lit.as_str() == "true") as u8)),
+            _ => {
+                self.errors
+                    .push(YulError::Unsupported("string literals".into(), span));
+                return self.zero();
+            }
+        };
+        match value {
+            Some(value) => self.builder.make_imm_value(value),
+            None => {
+                self.errors.push(YulError::NumberOutOfBounds(span));
+                self.zero()
+            }
+        }
+    }
+
+    fn check_arg_num(&mut self, expected: usize, found: usize, span: Span) -> bool {
+        if expected != found {
+            self.errors.push(YulError::ArgNum {
+                expected,
+                found,
+                span,
+            });
+        }
+        expected == found
+    }
+
+    /// Declares a variable for `name` in the innermost scope and defines it as `value`.
+    fn define(&mut self, name: &Pair<'i, Rule>, value: Value) -> Variable {
+        let span = span_of(name);
+        let var = self.builder.declare_var(Type::I256);
+        self.builder.def_var(var, value);
+
+        // Yul doesn't allow shadowing.
+        if let Some((_, first)) = self.scopes.iter().find_map(|s| s.get(name.as_str())) {
+            self.errors.push(YulError::DuplicateName {
+                name: name.as_str().into(),
+                span,
+                first: *first,
+            });
+        } else {
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert(name.as_str(), (var, span));
+        }
+        var
+    }
+
+    fn lookup(&mut self, name: &Pair<'i, Rule>) -> Option<Variable> {
+        let var = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(name.as_str()))
+            .map(|(var, _)| *var);
+        if var.is_none() {
+            self.errors
+                .push(YulError::Undefined(name.as_str().into(), span_of(name)));
+        }
+        var
+    }
+
+    fn ret(&mut self) {
+        let Some(ret) = self.ret else {
+            self.builder.ret(None);
+            self.terminated = true;
+            return;
+        };
+        let value = self.builder.use_var(ret);
+        self.builder.ret(Some(value));
+        self.terminated = true;
+    }
+
+    fn switch_to(&mut self, block: Block) {
+        self.builder.switch_to_block(block);
+        self.block = block;
+        self.terminated = false;
+    }
+
+    fn zero(&mut self) -> Value {
+        self.builder.make_imm_value(U256::zero())
+    }
+}
+
+/// Returns the number of arguments of a supported builtin.
+fn builtin_arg_num(name: &str) -> Option<usize> {
+    match name {
+        "add" | "sub" | "mul" | "div" | "sdiv" | "and" | "or" | "xor" | "shl" | "shr" | "sar"
+        | "lt" | "gt" | "slt" | "sgt" | "eq" | "mstore" | "mstore8" | "sstore" => Some(2),
+        "not" | "iszero" | "mload" | "sload" | "pop" => Some(1),
+        _ => None,
+    }
+}
+
+/// Collects the function definitions in `stmts` and the blocks nested in them.
+fn collect_functions<'i>(stmts: Pairs<'i, Rule>, defs: &mut Vec<Pair<'i, Rule>>) {
+    for stmt in stmts {
+        match stmt.as_rule() {
+            Rule::function_definition => {
+                defs.push(stmt.clone());
+                collect_functions(stmt.into_inner(), defs);
+            }
+            Rule::block
+            | Rule::if_stmt
+            | Rule::switch_stmt
+            | Rule::case
+            | Rule::default
+            | Rule::for_loop => collect_functions(stmt.into_inner(), defs),
+            _ => {}
+        }
+    }
+}
+
+/// Drops the types of typed identifiers, which follow the names, e.g., `u256` in `x:u256`.
+fn untyped(idents: Vec<Pair<Rule>>) -> Vec<Pair<Rule>> {
+    let mut names = vec![];
+    let mut prev_end = None;
+    for ident in idents {
+        let span = ident.as_span();
+        let is_type = prev_end.is_some_and(|end| {
+            ident.get_input()[end..span.start()]
+                .trim_start()
+                .starts_with(':')
+        });
+        prev_end = Some(span.end());
+        if !is_type {
+            names.push(ident);
+        }
+    }
+    names
+}
+
+fn span_of(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    Span::from_range(span.start()..span.end())
+}
@@ -1,6 +1,6 @@
 use dir_test::{dir_test, Fixture};
 use indenter::indented;
-use ir::ir_writer::ModuleWriter;
+use ir::{ir_writer::ModuleWriter, StateMutability};
 use pest::{iterators::Pairs, Parser as _};
 use sonatina_parser::{
     ast, parse_module,
@@ -53,6 +53,57 @@ fn test_module_ir(fixture: Fixture<&str>) {
     snap_test!(w.dump_string().unwrap(), fixture.path(), Some("ir"));
 }
 
+#[dir_test(
+    dir: "$CARGO_MANIFEST_DIR/test_files/syntax/module",
+    glob: "*.sntn"
+)]
+fn test_module_roundtrip(fixture: Fixture<&str>) {
+    assert_roundtrip(fixture.path(), fixture.content());
+}
+
+#[dir_test(
+    dir: "$CARGO_MANIFEST_DIR/test_files/roundtrip",
+    glob: "*.sntn"
+)]
+fn test_roundtrip(fixture: Fixture<&str>) {
+    assert_roundtrip(fixture.path(), fixture.content());
+}
+
+#[dir_test(
+    dir: "$CARGO_MANIFEST_DIR/../filecheck/fixtures",
+    glob: "**/*.sntn"
+)]
+fn test_filecheck_fixture_roundtrip(fixture: Fixture<&str>) {
+    assert_roundtrip(fixture.path(), fixture.content());
+}
+
+/// Checks that the written module parses back into a module that is written the same way, both
+/// with phis and with block params.
+fn assert_roundtrip(path: &str, content: &str) {
+    let write = |content: &str, block_params: bool| {
+        let module = parse_module(content).unwrap_or_else(|errs| {
+            for err in errs {
+                eprintln!("{}", err.print_to_string(path, content, false));
+            }
+            panic!("failed to parse `{path}`");
+        });
+        let mut w = ModuleWriter::with_debug_provider(&module.module, &module.debug);
+        if block_params {
+            w = w.with_block_params();
+        }
+        w.dump_string().unwrap()
+    };
+
+    let written = write(content, false);
+    assert_eq!(write(&written, false), written);
+
+    // Lowered block params take their args in edge order, so the phis they're lowered to are
+    // only compared after being written as block params again.
+    let written = write(content, true);
+    assert_eq!(write(&written, true), written);
+    assert_eq!(write(&write(&written, false), true), written);
+}
+
 fn test_rule(rule: Rule, fixture: Fixture<&str>) {
     match Parser::parse(rule, fixture.content()) {
         Ok(r) => {
@@ -86,3 +137,176 @@ impl<'i> fmt::Debug for PairsWrapper<'i> {
         Ok(())
     }
 }
+
+#[test]
+fn test_word_type() {
+    let input = r#"target = "evm-ethereum-london"
+
+func public %size(v0.*word) -> word {
+    block0:
+        v1.word = load @memory v0;
+        return v1;
+}
+"#;
+    let module = parse_module(input).unwrap();
+    let mut w = ModuleWriter::with_debug_provider(&module.module, &module.debug);
+    let ir = w.dump_string().unwrap();
+    assert!(ir.contains("func public %size(v0.*i256) -> i256"), "{ir}");
+    assert!(ir.contains("v1.i256 = load @memory v0;"), "{ir}");
+}
+
+#[test]
+fn test_func_attrs() {
+    let input = r#"target = "evm-ethereum-london"
+
+func private %keep() -> i8 optnone {
+    block0:
+        return 1.i8;
+}
+
+func public %get() -> i8 optnone nothrow view {
+    block0:
+        return 1.i8;
+}
+"#;
+    let module = parse_module(input).unwrap();
+    let funcs: Vec<_> = module.module.iter_functions().collect();
+    let attrs = module.module.funcs[funcs[0]].sig.attrs();
+    assert!(attrs.optnone);
+    assert!(!attrs.nothrow);
+    assert_eq!(attrs.mutability, StateMutability::NonPayable);
+    let attrs = module.module.funcs[funcs[1]].sig.attrs();
+    assert!(attrs.nothrow);
+    assert_eq!(attrs.mutability, StateMutability::View);
+
+    let mut w = ModuleWriter::with_debug_provider(&module.module, &module.debug);
+    let ir = w.dump_string().unwrap();
+    assert!(ir.contains("func private %keep() -> i8 optnone {"), "{ir}");
+    assert!(
+        ir.contains("func public %get() -> i8 optnone nothrow view {"),
+        "{ir}"
+    );
+}
+
+#[test]
+fn test_width_policy() {
+    let func = r#"
+func public %mixed(v0.i8, v1.i32) -> i1 {
+    block0:
+        v2.i32 = add v0 v1;
+        v3.i1 = slt v1 -1.i8;
+        v4.i8 = shl v0 v1;
+        v5.i32 = sar v1 3.i8;
+        return v3;
+}
+"#;
+
+    let strict = format!("target = \"evm-ethereum-london\"\n{func}");
+    let errs = parse_module(&strict).err().unwrap();
+    // The shifts aren't mismatches, since their amount may have any width.
+    assert_eq!(errs.len(), 2);
+    assert!(matches!(errs[0], Error::WidthMismatch { .. }));
+
+    let widen = format!("target = \"evm-ethereum-london\"\nwidth_policy = \"widen\"\n{func}");
+    let module = parse_module(&widen).unwrap();
+    let mut w = ModuleWriter::with_debug_provider(&module.module, &module.debug);
+    let ir = w.dump_string().unwrap();
+    assert!(ir.contains("width_policy = \"widen\""), "{ir}");
+    assert!(ir.contains("i32 = zext v0;"), "{ir}");
+    assert!(ir.contains("v3.i1 = slt v1 -1.i32;"), "{ir}");
+    assert!(ir.contains("v4.i8 = shl v0 v1;"), "{ir}");
+    assert!(ir.contains("v5.i32 = sar v1 3.i8;"), "{ir}");
+}
+
+#[test]
+fn test_block_params() {
+    let input = r#"target = "evm-ethereum-london"
+
+func public %count(v0.i64) -> i64 {
+    block0:
+        jump block1(v0, 0.i64);
+
+    block1(v1.i64, v2.i64):
+        v3.i64 = add v2 1.i64;
+        v4.i1 = eq v3 v1;
+        br v4 block2 block1(v1, v3);
+
+    block2:
+        return v3;
+}
+"#;
+    let module = parse_module(input).unwrap();
+    let mut w = ModuleWriter::with_debug_provider(&module.module, &module.debug);
+    let ir = w.dump_string().unwrap();
+    assert!(ir.contains("v1.i64 = phi (v0 block0) (v1 block1);"), "{ir}");
+    assert!(
+        ir.contains("v2.i64 = phi (0.i64 block0) (v3 block1);"),
+        "{ir}"
+    );
+    assert!(ir.contains("br v4 block2 block1;"), "{ir}");
+
+    let mut w =
+        ModuleWriter::with_debug_provider(&module.module, &module.debug).with_block_params();
+    let ir = w.dump_string().unwrap();
+    assert!(ir.contains("jump block1(v0, 0.i64);"), "{ir}");
+    assert!(ir.contains("block1(v1.i64, v2.i64):"), "{ir}");
+    assert!(ir.contains("br v4 block2 block1(v1, v3);"), "{ir}");
+    assert!(!ir.contains("phi"), "{ir}");
+
+    let input = r#"target = "evm-ethereum-london"
+
+func public %f(v0.i8) -> i8 {
+    block0:
+        jump block1(v0, v0);
+
+    block1(v1.i8):
+        return v1;
+}
+"#;
+    let errs = parse_module(input).err().unwrap();
+    assert_eq!(errs.len(), 1);
+    let diag = errs[0].diagnostic(input);
+    assert_eq!(diag.message, "`block1` has 1 params, but 2 args are passed");
+    assert_eq!(&input[diag.span.as_range()], "block1(v0, v0)");
+}
+
+#[test]
+fn test_diagnostics() {
+    let input = r#"target = "evm-ethereum-london"
+
+type %s = {i8, i8};
+type %s = {i32};
+
+func public %f(v0.i8) -> i8 {
+    block0:
+        v0.i8 = add v0 1.i8;
+        return v0;
+}
+"#;
+    let errs = parse_module(input).err().unwrap();
+    assert_eq!(errs.len(), 2);
+
+    let diag = errs[0].diagnostic(input);
+    assert_eq!(diag.message, "struct `%s` is already defined");
+    assert_eq!(&input[diag.span.as_range()], "%s");
+    assert_eq!(diag.labels.len(), 1);
+    assert_eq!(diag.labels[0].message, "struct `%s` first defined here");
+    assert!(diag.labels[0].span.0 < diag.span.0);
+
+    let diag = errs[1].diagnostic(input);
+    assert_eq!(diag.message, "value name `v0` is already defined");
+    assert_eq!(&input[diag.labels[0].span.as_range()], "v0");
+    assert!(input[diag.labels[0].span.1 as usize..].starts_with(".i8) -> i8"));
+
+    let input = r#"target = "evm-ethereum-london"
+
+func public %f() -> i8 {
+    block0:
+        return 1.i8
+}
+"#;
+    let errs = parse_module(input).err().unwrap();
+    let diag = errs[0].diagnostic(input);
+    assert_eq!(diag.found.as_deref(), Some("`}`"));
+    assert!(!diag.expected.is_empty());
+}
@@ -0,0 +1,76 @@
+use ir::{ir_writer::ModuleWriter, isa::IsaBuilder, module::ModuleCtx};
+use sonatina_parser::{
+    parse_module,
+    yul::{parse_yul, YulError},
+};
+use sonatina_triple::TargetTriple;
+
+fn ctx() -> ModuleCtx {
+    let triple = TargetTriple::parse("evm-ethereum-london").unwrap();
+    ModuleCtx::new(IsaBuilder::new(triple).build())
+}
+
+#[test]
+fn import_yul_object() {
+    let input = r#"
+object "Counter" {
+    code {
+        function sum_to(n) -> total {
+            for { let i := 0 } lt(i, n) { i := add(i, 1) } {
+                if iszero(and(i, 1)) { continue }
+                total := add(total, i)
+            }
+        }
+
+        function classify(x) -> r {
+            switch x
+            case 0 { r := 10 }
+            case 0x2a { leave }
+            default { r := shl(4, x) }
+        }
+
+        function bump(slot) {
+            let v := sload(slot)
+            sstore(slot, add(v, classify(v)))
+            mstore(0x40, sum_to(v))
+        }
+    }
+}
+"#;
+    let module = parse_yul(input, ctx()).unwrap();
+    let names: Vec<_> = module
+        .iter_functions()
+        .map(|func| module.funcs[func].sig.name().to_string())
+        .collect();
+    assert_eq!(names, ["sum_to", "classify", "bump"]);
+
+    // The imported module is valid IR that survives a roundtrip through the text format.
+    let written = ModuleWriter::new(&module).dump_string().unwrap();
+    let parsed = parse_module(&written).unwrap();
+    let rewritten = ModuleWriter::with_debug_provider(&parsed.module, &parsed.debug)
+        .dump_string()
+        .unwrap();
+    assert_eq!(rewritten, written);
+}
+
+#[test]
+fn import_yul_errors() {
+    let input = r#"
+{
+    function f(a) -> b {
+        b := calldataload(a)
+        break
+    }
+    function f() {}
+}
+"#;
+    let errors = parse_yul(input, ctx()).unwrap_err();
+    assert!(matches!(
+        errors.as_slice(),
+        [
+            YulError::DuplicateName { .. },
+            YulError::Undefined(name, _),
+            YulError::Misplaced(..),
+        ] if name == "calldataload"
+    ));
+}
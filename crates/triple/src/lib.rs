@@ -7,6 +7,8 @@ pub struct TargetTriple {
     pub architecture: Architecture,
     pub chain: Chain,
     pub version: Version,
+    /// Features enabled on top of those implied by `version`, in the order they are given.
+    pub features: Vec<Feature>,
 }
 
 impl TargetTriple {
@@ -15,10 +17,32 @@ impl TargetTriple {
             architecture,
             chain,
             version,
+            features: Vec::new(),
         }
     }
+
+    /// Enables `feature` in addition to those implied by the version.
+    pub fn with_feature(mut self, feature: Feature) -> Self {
+        if !self.features.contains(&feature) {
+            self.features.push(feature);
+        }
+        self
+    }
+
+    /// Returns `true` if `feature` is enabled explicitly or implied by the version.
+    pub fn has_feature(&self, feature: Feature) -> bool {
+        self.features.contains(&feature) || self.version.implies(feature)
+    }
+
+    /// Parses a triple of the form `architecture-chain-version`, where the version may be
+    /// followed by features to enable, e.g., `evm-ethereum-london+const-pool`.
     pub fn parse(s: &str) -> Result<Self, InvalidTriple> {
-        let mut triple = s.split('-');
+        // Features may contain `-` themselves, so they're split off first.
+        let (triple, features) = match s.split_once('+') {
+            Some((triple, features)) => (triple, Some(features)),
+            None => (s, None),
+        };
+        let mut triple = triple.split('-');
 
         let arch = Architecture::parse(
             triple
@@ -38,29 +62,86 @@ impl TargetTriple {
                 .ok_or_else(|| InvalidTriple::InvalidFormat(s.to_string()))?,
         )?;
 
-        if triple.next().is_none() {
-            Ok(Self::new(arch, chain, version))
-        } else {
-            Err(InvalidTriple::InvalidFormat(s.to_string()))
+        if triple.next().is_some() {
+            return Err(InvalidTriple::InvalidFormat(s.to_string()));
         }
+
+        let mut target = Self::new(arch, chain, version);
+        for feature in features
+            .into_iter()
+            .flat_map(|features| features.split('+'))
+        {
+            let feature = Feature::parse(feature)?;
+            if !feature.is_available_on(arch) {
+                return Err(InvalidTriple::InvalidCombination);
+            }
+            target = target.with_feature(feature);
+        }
+        Ok(target)
     }
 }
 
 impl Display for TargetTriple {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}-{}-{}", self.architecture, self.chain, self.version)
+        write!(f, "{}-{}-{}", self.architecture, self.chain, self.version)?;
+        for feature in &self.features {
+            write!(f, "+{feature}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An optional feature of a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// The `PUSH0` opcode of EIP-3855. Implied by Shanghai and later EVM versions.
+    Push0,
+    /// The `MCOPY` opcode of EIP-5656, which copies memory. Implied by Cancun and later EVM
+    /// versions.
+    Mcopy,
+    /// Shares large constants that are used repeatedly by loading them from a pool in the data
+    /// section instead of pushing them at every use.
+    ConstPool,
+}
+
+impl Feature {
+    fn parse(s: &str) -> Result<Self, InvalidTriple> {
+        match s {
+            "push0" => Ok(Self::Push0),
+            "const-pool" => Ok(Self::ConstPool),
+            "mcopy" => Ok(Self::Mcopy),
+            _ => Err(InvalidTriple::FeatureNotSupported(s.to_string())),
+        }
+    }
+
+    fn is_available_on(self, arch: Architecture) -> bool {
+        match self {
+            Self::Push0 | Self::ConstPool | Self::Mcopy => arch == Architecture::Evm,
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Push0 => write!(f, "push0"),
+            Self::ConstPool => write!(f, "const-pool"),
+            Self::Mcopy => write!(f, "mcopy"),
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Architecture {
     Evm,
+    Riscv32,
 }
 
 impl Architecture {
     fn parse(s: &str) -> Result<Self, InvalidTriple> {
         match s {
             "evm" => Ok(Self::Evm),
+            "riscv32" => Ok(Self::Riscv32),
             _ => Err(InvalidTriple::ArchitectureNotSupported),
         }
     }
@@ -70,6 +151,7 @@ impl Display for Architecture {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Evm => write!(f, "evm"),
+            Self::Riscv32 => write!(f, "riscv32"),
         }
     }
 }
@@ -77,12 +159,15 @@ impl Display for Architecture {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Chain {
     Ethereum,
+    /// A zkVM that proves the execution of RISC-V programs.
+    ZkVm,
 }
 
 impl Chain {
     fn parse(s: &str) -> Result<Self, InvalidTriple> {
         match s {
             "ethereum" => Ok(Chain::Ethereum),
+            "zkvm" => Ok(Chain::ZkVm),
             _ => Err(InvalidTriple::ChainNotSupported),
         }
     }
@@ -92,6 +177,7 @@ impl Display for Chain {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Chain::Ethereum => write!(f, "ethereum"),
+            Chain::ZkVm => write!(f, "zkvm"),
         }
     }
 }
@@ -99,6 +185,7 @@ impl Display for Chain {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
     EvmVersion(EvmVersion),
+    RiscvExtensions(RiscvExtensions),
 }
 
 impl Version {
@@ -112,10 +199,29 @@ impl Version {
                     "constantinople" => EvmVersion::Constantinople,
                     "istanbul" => EvmVersion::Istanbul,
                     "london" => EvmVersion::London,
+                    "shanghai" => EvmVersion::Shanghai,
+                    "cancun" => EvmVersion::Cancun,
                     _ => return Err(InvalidTriple::VersionNotSupported),
                 };
                 Ok(Self::EvmVersion(evm_version))
             }
+            (Architecture::Riscv32, Chain::ZkVm) => {
+                let extensions = match s {
+                    "rv32im" => RiscvExtensions::Rv32im,
+                    _ => return Err(InvalidTriple::VersionNotSupported),
+                };
+                Ok(Self::RiscvExtensions(extensions))
+            }
+            _ => Err(InvalidTriple::InvalidCombination),
+        }
+    }
+
+    /// Returns `true` if every target of this version has `feature`.
+    pub fn implies(self, feature: Feature) -> bool {
+        match (self, feature) {
+            (Self::EvmVersion(version), Feature::Push0) => version >= EvmVersion::Shanghai,
+            (Self::EvmVersion(version), Feature::Mcopy) => version >= EvmVersion::Cancun,
+            _ => false,
         }
     }
 }
@@ -124,11 +230,13 @@ impl Display for Version {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::EvmVersion(evm_version) => write!(f, "{}", evm_version),
+            Self::RiscvExtensions(extensions) => write!(f, "{}", extensions),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// EVM hard forks, in the order they were activated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EvmVersion {
     Frontier,
     Homestead,
@@ -136,7 +244,17 @@ pub enum EvmVersion {
     Constantinople,
     Istanbul,
     London,
+    Shanghai,
+    Cancun,
+}
+
+/// The extensions of the base integer ISA a RISC-V target implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiscvExtensions {
+    /// The 32-bit base integer ISA with the multiplication and division extension.
+    Rv32im,
 }
+
 #[derive(Debug, Clone, Error)]
 pub enum InvalidTriple {
     #[error("the format of triple must be `architecture-chain-version: but got `{0}`")]
@@ -151,6 +269,9 @@ pub enum InvalidTriple {
     #[error("given version is not supported")]
     VersionNotSupported,
 
+    #[error("feature `{0}` is not supported")]
+    FeatureNotSupported(String),
+
     #[error("given triple consists of invalid combination")]
     InvalidCombination,
 }
@@ -164,6 +285,16 @@ impl Display for EvmVersion {
             Self::Constantinople => write!(f, "constantinople"),
             Self::Istanbul => write!(f, "istanbul"),
             Self::London => write!(f, "london"),
+            Self::Shanghai => write!(f, "shanghai"),
+            Self::Cancun => write!(f, "cancun"),
+        }
+    }
+}
+
+impl Display for RiscvExtensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rv32im => write!(f, "rv32im"),
         }
     }
 }
@@ -181,4 +312,48 @@ mod tests {
         assert_eq!(triple.chain, Chain::Ethereum);
         assert_eq!(triple.version, Version::EvmVersion(EvmVersion::Istanbul));
     }
+
+    #[test]
+    fn riscv() {
+        let target = "riscv32-zkvm-rv32im";
+        let triple = TargetTriple::parse(target).unwrap();
+
+        assert_eq!(triple.architecture, Architecture::Riscv32);
+        assert_eq!(triple.chain, Chain::ZkVm);
+        assert_eq!(
+            triple.version,
+            Version::RiscvExtensions(RiscvExtensions::Rv32im)
+        );
+        assert_eq!(triple.to_string(), target);
+        assert!(matches!(
+            TargetTriple::parse("evm-zkvm-rv32im"),
+            Err(InvalidTriple::InvalidCombination)
+        ));
+    }
+
+    #[test]
+    fn features() {
+        let london = TargetTriple::parse("evm-ethereum-london+const-pool").unwrap();
+        assert!(london.has_feature(Feature::ConstPool));
+        assert!(!london.has_feature(Feature::Push0));
+        assert_eq!(london.to_string(), "evm-ethereum-london+const-pool");
+
+        let shanghai = TargetTriple::parse("evm-ethereum-shanghai").unwrap();
+        assert!(shanghai.has_feature(Feature::Push0));
+        assert!(!shanghai.has_feature(Feature::ConstPool));
+        assert!(!shanghai.has_feature(Feature::Mcopy));
+
+        let cancun = TargetTriple::parse("evm-ethereum-cancun").unwrap();
+        assert!(cancun.has_feature(Feature::Push0));
+        assert!(cancun.has_feature(Feature::Mcopy));
+
+        assert!(matches!(
+            TargetTriple::parse("evm-ethereum-london+simd"),
+            Err(InvalidTriple::FeatureNotSupported(_))
+        ));
+        assert!(matches!(
+            TargetTriple::parse("riscv32-zkvm-rv32im+push0"),
+            Err(InvalidTriple::InvalidCombination)
+        ));
+    }
 }